@@ -0,0 +1,44 @@
+#!/bin/sh
+# Checks that the library, its examples and the C FFI cross-compile for Android using the NDK.
+#
+# Usage: check_ndk_build.sh [API level]
+#
+# `ANDROID_NDK_HOME` must point to the NDK, which provides both the compiler and the kernel headers
+# the bindings are generated from. The Rust standard library must be installed for each target,
+# e.g.:
+#
+#   rustup target add aarch64-linux-android armv7-linux-androideabi x86_64-linux-android
+set -eu
+
+api=${1:-30}
+
+if [ -z "${ANDROID_NDK_HOME:-}" ]; then
+    echo "ANDROID_NDK_HOME must point to the Android NDK" >&2
+    exit 1
+fi
+
+case "$(uname -s)" in
+Darwin) host_tag=darwin-x86_64 ;;
+*) host_tag=linux-x86_64 ;;
+esac
+ndk_bin="$ANDROID_NDK_HOME/toolchains/llvm/prebuilt/$host_tag/bin"
+manifest="$(dirname "$(realpath "$0")")/../Cargo.toml"
+
+# Rust target, and prefix of the matching NDK compiler.
+for entry in \
+    aarch64-linux-android:aarch64-linux-android \
+    armv7-linux-androideabi:armv7a-linux-androideabi \
+    x86_64-linux-android:x86_64-linux-android; do
+    target=${entry%%:*}
+    clang="$ndk_bin/${entry##*:}$api-clang"
+    cc_var="CC_$(echo "$target" | tr '-' '_')"
+    linker_var="CARGO_TARGET_$(echo "$target" | tr 'a-z-' 'A-Z_')_LINKER"
+
+    echo "Checking $target..."
+    env "$cc_var=$clang" "$linker_var=$clang" \
+        cargo check --quiet --manifest-path "$manifest" --target "$target" \
+        -p v4l2r --lib --examples
+    env "$cc_var=$clang" "$linker_var=$clang" \
+        cargo check --quiet --manifest-path "$manifest" --target "$target" \
+        -p v4l2r-ffi --no-default-features --features android
+done
@@ -129,6 +129,13 @@ void on_event(void *ptr, struct v4l2r_decoder_event *event) {
     printf("Drain completed!\n");
     drain_completed = true;
     break;
+  case DecoderError: {
+    char msg[256];
+
+    v4l2r_last_error_msg(msg, sizeof(msg));
+    fprintf(stderr, "Decoder error %d: %s\n", event->decoder_error, msg);
+    break;
+  }
   }
 }
 
@@ -0,0 +1,207 @@
+#include <fcntl.h>
+#include <linux/dma-heap.h>
+#include <linux/videodev2.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/ioctl.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+#include "v4l2r.h"
+
+#define NUM_FRAMES 20
+// Number of frames we may hold at the same time in addition to the ones
+// needed by the decoder.
+#define NUM_EXTRA_FRAMES 2
+
+static const char *input_file_path = "../c_fwht_decode/sample.fwht";
+static size_t input_frame_sizes[NUM_FRAMES] = {
+    39504, 5822, 42410, 5822, 42106, 5822, 41802, 7646, 40606, 8468,
+    42640, 6644, 42928, 6644, 42624, 8468, 40540, 8846, 43002, 8846,
+};
+
+static const char *device_path = "/dev/video1";
+
+static volatile bool drain_completed = false;
+static volatile int num_frames = 0;
+static volatile bool frames_ok = true;
+
+// Allocate a DMABUF of `size` bytes to pass encoded data to the decoder.
+static int allocate_dmabuf(size_t size) {
+  struct dma_heap_allocation_data allocation_data;
+  int dma_device;
+  int ret;
+
+  dma_device = open("/dev/dma_heap/system", O_RDWR | O_CLOEXEC);
+  if (dma_device < 0) {
+    perror("error opening DMA heap device /dev/dma_heap/system");
+    return -1;
+  }
+
+  memset(&allocation_data, 0, sizeof(allocation_data));
+  allocation_data.len = size;
+  allocation_data.fd_flags = O_CLOEXEC | O_RDWR;
+  ret = ioctl(dma_device, DMA_HEAP_IOCTL_ALLOC, &allocation_data);
+  close(dma_device);
+  if (ret < 0) {
+    perror("error while allocating DMA memory");
+    return -1;
+  }
+
+  return allocation_data.fd;
+}
+
+// FNV-1a hash of `size` bytes of `data`.
+static uint32_t checksum(const uint8_t *data, size_t size, uint32_t hash) {
+  size_t i;
+
+  for (i = 0; i < size; i++) {
+    hash ^= data[i];
+    hash *= 16777619u;
+  }
+
+  return hash;
+}
+
+static void on_input_done(void *ptr, const struct v4l2_buffer *buffer) {
+  printf("Input buffer %d done\n", buffer->index);
+}
+
+static void on_frame_exported(const struct v4l2r_frame *frame) {
+  uint32_t hash = 2166136261u;
+  size_t i;
+
+  for (i = 0; i < frame->num_planes; i++) {
+    // The FD remains owned by the library: we can map it, but must not close
+    // it. A client wishing to use it after releasing the frame would need to
+    // dup() it first.
+    size_t map_size = frame->offsets[i] + frame->sizes[i];
+    uint8_t *mapping =
+        mmap(NULL, map_size, PROT_READ, MAP_SHARED, frame->fds[i], 0);
+    if (mapping == MAP_FAILED) {
+      perror("Error while mapping frame");
+      frames_ok = false;
+      break;
+    }
+    hash = checksum(mapping + frame->offsets[i], frame->sizes[i], hash);
+    munmap(mapping, map_size);
+  }
+
+  printf("Frame %d exported, %.4s %ux%u (visible %ux%u), %zu plane(s), "
+         "stride %u, checksum %08x\n",
+         frame->bitstream_id, (const char *)&frame->fourcc, frame->width,
+         frame->height, frame->visible_rect.width, frame->visible_rect.height,
+         frame->num_planes, frame->strides[0], hash);
+
+  if (frame->bitstream_id != num_frames) {
+    fprintf(stderr, "Unexpected frame %d, expected %d\n", frame->bitstream_id,
+            num_frames);
+    frames_ok = false;
+  }
+  num_frames++;
+
+  // Give the frame back to the decoder. Its FDs are invalid from now on.
+  v4l2r_frame_release(frame);
+}
+
+static void on_event(void *ptr, struct v4l2r_decoder_event *event) {
+  switch (event->tag) {
+  case FormatChanged:
+    printf("New CAPTURE format, min frames: %d\n",
+           event->format_changed.min_num_frames);
+    break;
+  case FrameExported:
+    on_frame_exported(event->frame_exported);
+    break;
+  case EndOfStream:
+    printf("Drain completed!\n");
+    drain_completed = true;
+    break;
+  case DecoderError: {
+    char msg[256];
+
+    v4l2r_last_error_msg(msg, sizeof(msg));
+    fprintf(stderr, "Decoder error %d: %s\n", event->decoder_error, msg);
+    frames_ok = false;
+    break;
+  }
+  default:
+    fprintf(stderr, "Unexpected event %d\n", event->tag);
+    frames_ok = false;
+    break;
+  }
+}
+
+int main(int argc, char **argv) {
+  struct v4l2_format output_format;
+  size_t output_buffer_size;
+  int output_dmabuf;
+  FILE *input_file;
+  int i;
+  int ret;
+
+  if (argc > 1)
+    device_path = argv[1];
+
+  input_file = fopen(input_file_path, "r");
+  if (!input_file) {
+    perror("Cannot open input file");
+    return 1;
+  }
+
+  v4l2r_init();
+
+  struct v4l2r_decoder *decoder = v4l2r_decoder_new_exported(
+      device_path, V4L2_PIX_FMT_FWHT, 1, 0, 0, NUM_EXTRA_FRAMES, on_input_done,
+      on_event, NULL);
+  if (!decoder) {
+    fprintf(stderr, "Cannot create decoder for %s\n", device_path);
+    return 1;
+  }
+
+  ret = v4l2r_decoder_get_input_format(decoder, &output_format);
+  if (ret < 0)
+    return 1;
+  output_buffer_size = output_format.fmt.pix_mp.plane_fmt[0].sizeimage;
+  output_dmabuf = allocate_dmabuf(output_buffer_size);
+  if (output_dmabuf < 0)
+    return 1;
+
+  for (i = 0; i < NUM_FRAMES; i++) {
+    size_t frame_bytes_used = input_frame_sizes[i];
+    void *mapping = mmap(NULL, output_buffer_size, PROT_READ | PROT_WRITE,
+                         MAP_SHARED, output_dmabuf, 0);
+    if (mapping == MAP_FAILED) {
+      perror("Error while mapping");
+      return 1;
+    }
+    if (fread(mapping, frame_bytes_used, 1, input_file) != 1) {
+      perror("Error reading file");
+      return 1;
+    }
+    munmap(mapping, output_buffer_size);
+
+    ret = v4l2r_decoder_decode(decoder, i, output_dmabuf, frame_bytes_used);
+    if (ret < 0)
+      return 1;
+  }
+
+  v4l2r_decoder_drain(decoder, false);
+  while (!drain_completed)
+    usleep(10000);
+
+  v4l2r_decoder_destroy(decoder);
+  close(output_dmabuf);
+  fclose(input_file);
+
+  if (num_frames != NUM_FRAMES || !frames_ok) {
+    fprintf(stderr, "Expected %d valid frames, got %d\n", NUM_FRAMES,
+            num_frames);
+    return 1;
+  }
+  printf("Decoding complete\n");
+
+  return 0;
+}
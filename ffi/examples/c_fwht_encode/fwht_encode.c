@@ -0,0 +1,114 @@
+#include <linux/videodev2.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "v4l2r.h"
+
+#define WIDTH 640
+#define HEIGHT 480
+#define NUM_FRAMES 20
+// Frame after which a key frame is requested.
+#define KEYFRAME_FRAME 10
+
+static const char *device_path = "/dev/video0";
+
+static size_t num_chunks = 0;
+static size_t total_size = 0;
+static bool timestamps_ok = true;
+
+static void on_chunk(void *ptr, const struct v4l2r_encoder_chunk *chunk) {
+  printf("Chunk %zu encoded, size: %zu, timestamp: %lu, keyframe: %d\n",
+         num_chunks, chunk->size, (unsigned long)chunk->timestamp,
+         chunk->keyframe);
+
+  // Timestamps are frame numbers, and FWHT produces one chunk per frame.
+  if (chunk->timestamp != num_chunks) {
+    fprintf(stderr, "Unexpected timestamp %lu for chunk %zu\n",
+            (unsigned long)chunk->timestamp, num_chunks);
+    timestamps_ok = false;
+  }
+
+  num_chunks++;
+  total_size += chunk->size;
+}
+
+// Fill `frame` with a moving RGB gradient.
+static void generate_frame(uint8_t *frame, int index) {
+  int x, y;
+
+  for (y = 0; y < HEIGHT; y++) {
+    for (x = 0; x < WIDTH; x++) {
+      uint8_t *pixel = &frame[(y * WIDTH + x) * 3];
+      pixel[0] = (x + index * 8) & 0xff;
+      pixel[1] = (y + index * 4) & 0xff;
+      pixel[2] = (x + y) & 0xff;
+    }
+  }
+}
+
+int main(int argc, char **argv) {
+  const size_t frame_size = WIDTH * HEIGHT * 3;
+  const uint8_t *planes[1];
+  size_t plane_sizes[1];
+  uint8_t *frame;
+  int ret;
+  int i;
+
+  if (argc > 1)
+    device_path = argv[1];
+
+  frame = malloc(frame_size);
+  if (!frame) {
+    perror("Cannot allocate frame");
+    return 1;
+  }
+
+  v4l2r_init();
+
+  struct v4l2r_encoder *encoder =
+      v4l2r_encoder_new(device_path, V4L2_PIX_FMT_RGB24, WIDTH, HEIGHT,
+                        V4L2_PIX_FMT_FWHT, 2, 2, on_chunk, NULL);
+  if (!encoder) {
+    fprintf(stderr, "Cannot create encoder for %s\n", device_path);
+    return 1;
+  }
+
+  if (v4l2r_encoder_set_bitrate(encoder, 1000000) < 0)
+    printf("Encoder does not support setting the bitrate\n");
+
+  planes[0] = frame;
+  plane_sizes[0] = frame_size;
+  for (i = 0; i < NUM_FRAMES; i++) {
+    if (i == KEYFRAME_FRAME && v4l2r_encoder_force_keyframe(encoder) < 0)
+      printf("Encoder does not support forcing key frames\n");
+
+    generate_frame(frame, i);
+    ret = v4l2r_encoder_encode(encoder, planes, plane_sizes, 1, i);
+    if (ret < 0) {
+      fprintf(stderr, "Error while encoding frame %d\n", i);
+      return 1;
+    }
+  }
+
+  ret = v4l2r_encoder_drain(encoder);
+  if (ret < 0) {
+    fprintf(stderr, "Error while draining encoder\n");
+    return 1;
+  }
+
+  // All the chunks must have been produced by the time drain returns.
+  if (num_chunks != NUM_FRAMES || !timestamps_ok) {
+    fprintf(stderr, "Expected %d chunks with matching timestamps, got %zu\n",
+            NUM_FRAMES, num_chunks);
+    return 1;
+  }
+
+  v4l2r_encoder_destroy(encoder);
+  free(frame);
+  printf("Encoding complete, %zu bytes produced\n", total_size);
+
+  return 0;
+}
@@ -0,0 +1,125 @@
+#include <linux/videodev2.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "v4l2r.h"
+
+static const char *queue_name(uint32_t queue) {
+  switch (queue) {
+  case V4L2_BUF_TYPE_VIDEO_CAPTURE:
+    return "Video Capture";
+  case V4L2_BUF_TYPE_VIDEO_OUTPUT:
+    return "Video Output";
+  case V4L2_BUF_TYPE_VIDEO_CAPTURE_MPLANE:
+    return "Video Capture Multiplanar";
+  case V4L2_BUF_TYPE_VIDEO_OUTPUT_MPLANE:
+    return "Video Output Multiplanar";
+  default:
+    return "Unknown";
+  }
+}
+
+static void print_fourcc(uint32_t fourcc) {
+  printf("'%c%c%c%c'", fourcc & 0xff, (fourcc >> 8) & 0xff,
+         (fourcc >> 16) & 0xff, (fourcc >> 24) & 0xff);
+}
+
+static void list_frame_sizes(const char *path, uint32_t pixelformat) {
+  struct v4l2r_frame_size *sizes;
+  int num_sizes;
+  int i;
+
+  // Query the number of frame sizes first, then allocate the array to fill.
+  num_sizes = v4l2r_enum_frame_sizes(path, pixelformat, NULL, 0);
+  if (num_sizes <= 0)
+    return;
+
+  sizes = calloc(num_sizes, sizeof(*sizes));
+  if (!sizes)
+    return;
+
+  num_sizes = v4l2r_enum_frame_sizes(path, pixelformat, sizes, num_sizes);
+  for (i = 0; i < num_sizes; i++) {
+    const struct v4l2r_frame_size *size = &sizes[i];
+
+    if (size->type_ == V4L2_FRMSIZE_TYPE_DISCRETE)
+      printf("\t\tSize: Discrete %ux%u\n", size->min_width, size->min_height);
+    else
+      printf("\t\tSize: %s %ux%u - %ux%u with step %u/%u\n",
+             size->type_ == V4L2_FRMSIZE_TYPE_CONTINUOUS ? "Continuous"
+                                                         : "Stepwise",
+             size->min_width, size->min_height, size->max_width,
+             size->max_height, size->step_width, size->step_height);
+  }
+
+  free(sizes);
+}
+
+static void list_formats(const char *path, uint32_t queue) {
+  struct v4l2r_fmtdesc formats[64];
+  int num_formats;
+  int i;
+
+  num_formats = v4l2r_enum_formats(path, queue, formats, 64);
+  if (num_formats < 0) {
+    fprintf(stderr, "Cannot enumerate formats of %s\n", path);
+    return;
+  }
+  if (num_formats > 64)
+    num_formats = 64;
+
+  printf("\tType: %s\n\n", queue_name(queue));
+  for (i = 0; i < num_formats; i++) {
+    printf("\t[%d]: ", i);
+    print_fourcc(formats[i].pixelformat);
+    printf(" (%s%s)\n", formats[i].description,
+           formats[i].flags & V4L2_FMT_FLAG_COMPRESSED ? ", compressed" : "");
+    list_frame_sizes(path, formats[i].pixelformat);
+  }
+  printf("\n");
+}
+
+int main(void) {
+  struct v4l2r_device_info *devices;
+  int num_devices;
+  int i;
+
+  v4l2r_init();
+
+  num_devices = v4l2r_list_devices(NULL, 0);
+  if (num_devices <= 0) {
+    printf("No video device found\n");
+    return 0;
+  }
+
+  devices = calloc(num_devices, sizeof(*devices));
+  if (!devices) {
+    perror("Cannot allocate devices");
+    return 1;
+  }
+
+  // Devices may have disappeared since the first call.
+  num_devices = v4l2r_list_devices(devices, num_devices);
+  for (i = 0; i < num_devices; i++) {
+    const struct v4l2r_device_info *device = &devices[i];
+    uint32_t caps = device->capabilities;
+
+    printf("%s: %s (%s, %s)\n", device->path, device->card, device->driver,
+           device->bus_info);
+    printf("ioctl: VIDIOC_ENUM_FMT\n");
+
+    if (caps & (V4L2_CAP_VIDEO_CAPTURE | V4L2_CAP_VIDEO_M2M))
+      list_formats(device->path, V4L2_BUF_TYPE_VIDEO_CAPTURE);
+    if (caps & (V4L2_CAP_VIDEO_OUTPUT | V4L2_CAP_VIDEO_M2M))
+      list_formats(device->path, V4L2_BUF_TYPE_VIDEO_OUTPUT);
+    if (caps & (V4L2_CAP_VIDEO_CAPTURE_MPLANE | V4L2_CAP_VIDEO_M2M_MPLANE))
+      list_formats(device->path, V4L2_BUF_TYPE_VIDEO_CAPTURE_MPLANE);
+    if (caps & (V4L2_CAP_VIDEO_OUTPUT_MPLANE | V4L2_CAP_VIDEO_M2M_MPLANE))
+      list_formats(device->path, V4L2_BUF_TYPE_VIDEO_OUTPUT_MPLANE);
+  }
+
+  free(devices);
+
+  return 0;
+}
@@ -0,0 +1,94 @@
+#include <errno.h>
+#include <fcntl.h>
+#include <linux/videodev2.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <unistd.h>
+
+#include "v4l2r.h"
+
+static const char *device_path = "/dev/video0";
+
+#define CHECK(cond)                                                            \
+  do {                                                                         \
+    if (!(cond)) {                                                             \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond); \
+      return 1;                                                                \
+    }                                                                          \
+  } while (0)
+
+static int run(int fd) {
+  struct v4l2r_control_info info;
+  struct v4l2r_control_value values[2] = {0};
+  size_t error_idx;
+  char msg[256];
+  int32_t value;
+
+  // vivid exposes a brightness control ranging from 0 to 255.
+  CHECK(v4l2r_control_query(fd, V4L2_CID_BRIGHTNESS, &info) == 0);
+  printf("%s: type %u, range [%ld, %ld], step %lu, default %ld\n", info.name,
+         info.type_, (long)info.minimum, (long)info.maximum,
+         (unsigned long)info.step, (long)info.default_value);
+  CHECK(info.type_ == V4L2_CTRL_TYPE_INTEGER);
+  CHECK(info.minimum == 0 && info.maximum == 255);
+
+  // Single get and set.
+  CHECK(v4l2r_control_set(fd, V4L2_CID_BRIGHTNESS, 200) == 0);
+  CHECK(v4l2r_control_get(fd, V4L2_CID_BRIGHTNESS, &value) == 0);
+  CHECK(value == 200);
+
+  // Batch set of brightness and contrast.
+  values[0].id = V4L2_CID_BRIGHTNESS;
+  values[0].value = 100;
+  values[1].id = V4L2_CID_CONTRAST;
+  values[1].value = 150;
+  CHECK(v4l2r_control_set_batch(fd, values, 2, &error_idx) == 0);
+  CHECK(v4l2r_control_get(fd, V4L2_CID_BRIGHTNESS, &value) == 0);
+  CHECK(value == 100);
+  CHECK(v4l2r_control_get(fd, V4L2_CID_CONTRAST, &value) == 0);
+  CHECK(value == 150);
+
+  // A batch containing an unknown control fails and reports its index.
+  values[0].value = 50;
+  values[1].id = V4L2_CID_PRIVATE_BASE + 0xfff;
+  CHECK(v4l2r_control_set_batch(fd, values, 2, &error_idx) ==
+        V4L2R_ERROR_ERRNO);
+  CHECK(error_idx == 1);
+  // The reason of the failure is available until the next error.
+  CHECK(v4l2r_last_error_code() == V4L2R_ERROR_ERRNO);
+  CHECK(v4l2r_last_errno() == EINVAL);
+  CHECK(v4l2r_last_error_msg(NULL, 0) > 0);
+  v4l2r_last_error_msg(msg, sizeof(msg));
+  printf("Expected error: %s\n", msg);
+  // The valid control of the failed batch has not been applied.
+  CHECK(v4l2r_control_get(fd, V4L2_CID_BRIGHTNESS, &value) == 0);
+  CHECK(value == 100);
+
+  // Restore the default value.
+  CHECK(v4l2r_control_set(fd, V4L2_CID_BRIGHTNESS, info.default_value) == 0);
+
+  return 0;
+}
+
+int main(int argc, char **argv) {
+  int fd;
+  int ret;
+
+  if (argc > 1)
+    device_path = argv[1];
+
+  v4l2r_init();
+
+  fd = open(device_path, O_RDWR);
+  if (fd < 0) {
+    perror("Cannot open device");
+    return 1;
+  }
+
+  ret = run(fd);
+  close(fd);
+  if (ret == 0)
+    printf("All control tests passed\n");
+
+  return ret;
+}
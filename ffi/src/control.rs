@@ -0,0 +1,342 @@
+//! Module for querying and setting the controls of a V4L2 device.
+//!
+//! All the functions of this module take the file descriptor of an opened
+//! V4L2 device node, which remains owned by the caller. They return
+//! `V4L2R_SUCCESS` on success, or the code of the error that occurred.
+//!
+//! Only scalar controls are supported for now. [`v4l2r_control_value`] carries
+//! a payload size and pointer that are reserved for compound controls, and
+//! must be set to 0 and NULL respectively until these are supported.
+#![allow(non_camel_case_types)]
+
+use std::{
+    os::{
+        fd::BorrowedFd,
+        raw::{c_char, c_int, c_void},
+    },
+    ptr, slice,
+};
+use v4l2r::{
+    bindings::{self, v4l2_ext_control, v4l2_ext_control__bindgen_ty_1, v4l2_query_ext_ctrl},
+    ioctl::{self, CtrlId, CtrlWhich, QueryCtrlFlags},
+};
+
+use crate::error::{report, report_errno, report_msg, v4l2r_error_code};
+
+/// Description of a control, as returned by [`v4l2r_control_query`].
+#[repr(C)]
+pub struct v4l2r_control_info {
+    id: u32,
+    /// One of the `V4L2_CTRL_TYPE_*` values.
+    type_: u32,
+    /// NUL-terminated name of the control.
+    name: [c_char; 32],
+    minimum: i64,
+    maximum: i64,
+    step: u64,
+    default_value: i64,
+    /// `V4L2_CTRL_FLAG_*` flags of the control.
+    flags: u32,
+    /// Size of a single element of the control's payload, in bytes.
+    elem_size: u32,
+    /// Number of elements of the control's payload.
+    elems: u32,
+}
+
+/// Value of a control, as passed to [`v4l2r_control_set_batch`].
+#[repr(C)]
+pub struct v4l2r_control_value {
+    /// CID of the control.
+    id: u32,
+    /// Size of the payload pointed to by `ptr`. Must be 0 for now.
+    size: u32,
+    /// Value of the control. 32-bit controls use the lower 32 bits.
+    value: i64,
+    /// Payload of compound controls. Must be NULL for now.
+    ptr: *mut c_void,
+}
+
+/// Returns the description of control `id` of `fd`, reporting an error if the
+/// control cannot be queried.
+fn query(fd: &BorrowedFd, id: u32) -> Result<v4l2_query_ext_ctrl, v4l2r_error_code> {
+    let ctrl_id = CtrlId::new(id).map_err(|e| {
+        report(
+            v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+            format!("Invalid control ID {:#x}", id),
+            &e,
+        )
+    })?;
+
+    ioctl::query_ext_ctrl(fd, ctrl_id, QueryCtrlFlags::empty()).map_err(|e| {
+        report_errno(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            format!("Failed to query control {:#x}", id),
+            e,
+        )
+    })
+}
+
+/// Returns a `v4l2_ext_control` setting the scalar control described by
+/// `qctrl` to `value`.
+fn scalar_control(qctrl: &v4l2_query_ext_ctrl, value: i64) -> v4l2_ext_control {
+    let value = if qctrl.type_ == bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER64 {
+        v4l2_ext_control__bindgen_ty_1 { value64: value }
+    } else {
+        v4l2_ext_control__bindgen_ty_1 {
+            value: value as i32,
+        }
+    };
+
+    v4l2_ext_control {
+        id: qctrl.id,
+        __bindgen_anon_1: value,
+        ..Default::default()
+    }
+}
+
+fn v4l2r_control_get_safe(fd: BorrowedFd, id: u32) -> Result<i64, v4l2r_error_code> {
+    let qctrl = query(&fd, id)?;
+    if qctrl.flags & bindings::V4L2_CTRL_FLAG_HAS_PAYLOAD != 0 {
+        return Err(report_msg(
+            v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+            format!("Control {:#x} is not a scalar control", id),
+        ));
+    }
+
+    let mut controls = [scalar_control(&qctrl, 0)];
+    if let Err(e) = ioctl::g_ext_ctrls(&fd, CtrlWhich::Current, &mut controls[..]) {
+        return Err(report_errno(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            format!("Failed to get control {:#x}", id),
+            e,
+        ));
+    }
+
+    // SAFETY: the control is scalar, so its value is stored in `value` or
+    // `value64` depending on its type.
+    let value = unsafe {
+        if qctrl.type_ == bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER64 {
+            controls[0].__bindgen_anon_1.value64
+        } else {
+            controls[0].__bindgen_anon_1.value as i64
+        }
+    };
+
+    Ok(value)
+}
+
+fn v4l2r_control_set_batch_safe(
+    fd: BorrowedFd,
+    values: &[v4l2r_control_value],
+    error_idx: &mut usize,
+) -> v4l2r_error_code {
+    let mut controls = Vec::with_capacity(values.len());
+    for (i, value) in values.iter().enumerate() {
+        if value.size != 0 || !value.ptr.is_null() {
+            *error_idx = i;
+            return report_msg(
+                v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+                format!("Compound control {:#x} is not supported", value.id),
+            );
+        }
+
+        match query(&fd, value.id) {
+            Ok(qctrl) => controls.push(scalar_control(&qctrl, value.value)),
+            Err(code) => {
+                *error_idx = i;
+                return code;
+            }
+        }
+    }
+
+    match ioctl::s_ext_ctrls(&fd, CtrlWhich::Current, &mut controls[..]) {
+        Ok(()) => v4l2r_error_code::V4L2R_SUCCESS,
+        Err(e) => {
+            *error_idx = e.error_idx as usize;
+            report_errno(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Failed to set controls",
+                e,
+            )
+        }
+    }
+}
+
+/// Query the description of control `id` of `fd` into `info`.
+///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes:
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if `id` is not a valid control ID.
+/// * `V4L2R_ERROR_ERRNO` if the control cannot be queried, e.g. because the
+///   device does not support it (`EINVAL`).
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+///
+/// # Safety
+///
+/// `info` must point to a valid `v4l2r_control_info`.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_control_query(
+    fd: c_int,
+    id: u32,
+    info: *mut v4l2r_control_info,
+) -> v4l2r_error_code {
+    let fd = BorrowedFd::borrow_raw(fd);
+    let qctrl = match query(&fd, id) {
+        Ok(qctrl) => qctrl,
+        Err(code) => return code,
+    };
+
+    let mut name = qctrl.name;
+    // Make sure the name is NUL-terminated.
+    if let Some(last) = name.last_mut() {
+        *last = 0;
+    }
+
+    *info = v4l2r_control_info {
+        id: qctrl.id,
+        type_: qctrl.type_,
+        name,
+        minimum: qctrl.minimum,
+        maximum: qctrl.maximum,
+        step: qctrl.step,
+        default_value: qctrl.default_value,
+        flags: qctrl.flags,
+        elem_size: qctrl.elem_size,
+        elems: qctrl.elems,
+    };
+
+    v4l2r_error_code::V4L2R_SUCCESS
+}
+
+/// Get the current value of the 32-bit control `id` of `fd` into `value`.
+///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes:
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if `id` is not a valid control ID, not a
+///   scalar control, or if its value does not fit in 32 bits (use
+///   [`v4l2r_control_get64`] for such controls).
+/// * `V4L2R_ERROR_ERRNO` if the control cannot be queried or read.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+///
+/// # Safety
+///
+/// `value` must point to a valid `int32_t`.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_control_get(
+    fd: c_int,
+    id: u32,
+    value: *mut i32,
+) -> v4l2r_error_code {
+    match v4l2r_control_get_safe(BorrowedFd::borrow_raw(fd), id) {
+        Ok(v) => match i32::try_from(v) {
+            Ok(v) => {
+                *value = v;
+                v4l2r_error_code::V4L2R_SUCCESS
+            }
+            Err(_) => report_msg(
+                v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+                format!("Value {} of control {:#x} does not fit in 32 bits", v, id),
+            ),
+        },
+        Err(code) => code,
+    }
+}
+
+/// Get the current value of the 64-bit control `id` of `fd` into `value`.
+///
+/// Returns the same codes as [`v4l2r_control_get`].
+///
+/// # Safety
+///
+/// `value` must point to a valid `int64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_control_get64(
+    fd: c_int,
+    id: u32,
+    value: *mut i64,
+) -> v4l2r_error_code {
+    match v4l2r_control_get_safe(BorrowedFd::borrow_raw(fd), id) {
+        Ok(v) => {
+            *value = v;
+            v4l2r_error_code::V4L2R_SUCCESS
+        }
+        Err(code) => code,
+    }
+}
+
+/// Set the 32-bit control `id` of `fd` to `value`.
+///
+/// Returns the same codes as [`v4l2r_control_set_batch`].
+///
+/// # Safety
+///
+/// `fd` must be a valid file descriptor.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_control_set(fd: c_int, id: u32, value: i32) -> v4l2r_error_code {
+    v4l2r_control_set64(fd, id, value as i64)
+}
+
+/// Set the 64-bit control `id` of `fd` to `value`.
+///
+/// Returns the same codes as [`v4l2r_control_set_batch`].
+///
+/// # Safety
+///
+/// `fd` must be a valid file descriptor.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_control_set64(fd: c_int, id: u32, value: i64) -> v4l2r_error_code {
+    let control = v4l2r_control_value {
+        id,
+        size: 0,
+        value,
+        ptr: ptr::null_mut(),
+    };
+    let mut error_idx = 0;
+
+    v4l2r_control_set_batch_safe(
+        BorrowedFd::borrow_raw(fd),
+        slice::from_ref(&control),
+        &mut error_idx,
+    )
+}
+
+/// Atomically set the `num_controls` controls of the `controls` array.
+///
+/// On error, the index of the control that caused the failure is written into
+/// `error_idx` if it is not NULL. As with `VIDIOC_S_EXT_CTRLS`, an index equal
+/// to `num_controls` means that the error could not be attributed to a
+/// specific control, and that none of the controls have been set.
+///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes:
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if a control ID is not valid, or a control
+///   is a compound control.
+/// * `V4L2R_ERROR_ERRNO` if a control cannot be queried, or the controls cannot
+///   be set, e.g. because a value is out of range (`ERANGE`).
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+///
+/// # Safety
+///
+/// `controls` must point to a valid array of at least `num_controls`
+/// elements. `error_idx` must either be NULL or point to a valid `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_control_set_batch(
+    fd: c_int,
+    controls: *const v4l2r_control_value,
+    num_controls: usize,
+    error_idx: *mut usize,
+) -> v4l2r_error_code {
+    let controls: &[v4l2r_control_value] = if num_controls == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(controls, num_controls)
+    };
+    let mut idx = 0;
+
+    let ret = v4l2r_control_set_batch_safe(BorrowedFd::borrow_raw(fd), controls, &mut idx);
+    if ret != v4l2r_error_code::V4L2R_SUCCESS && !error_idx.is_null() {
+        *error_idx = idx;
+    }
+
+    ret
+}
@@ -10,32 +10,48 @@
 //! frame being decoded, or a change in the output format (due to e.g. a dynamic
 //! resolution change). The output format is initially undefined and a format
 //! change event will be produced before any frame can be decoded.
+//!
+//! Decoders created using [`v4l2r_decoder_new`] decode into frames provided by
+//! the client. Alternatively, decoders created using
+//! [`v4l2r_decoder_new_exported`] allocate their own frames and pass them to
+//! the client as DMABUFs, which the client must give back once it is done with
+//! them (see the [`frame`](crate::frame) module).
 #![allow(non_camel_case_types)]
 
 use log::{debug, error, info, warn};
 use nix::sys::time::{TimeVal, TimeValLike};
 use std::{
-    ffi::CStr,
     mem::MaybeUninit,
     os::raw::{c_char, c_int, c_uint, c_void},
     path::Path,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 use v4l2r::{
     bindings,
     decoder::{
-        stateful::{Decoder, Decoding, DrainError},
-        CompletedInputBuffer, DecoderEvent, DecoderEventCallback, FormatChangedCallback,
-        FormatChangedReply, InputDoneCallback,
+        stateful::{Decoder, Decoding, DrainError, ReadyToDecode},
+        CompletedInputBuffer, DecodeSetupError, DecoderEvent, DecoderEventCallback,
+        FormatChangedCallback, FormatChangedReply, InputDoneCallback,
+    },
+    device::queue::{
+        direction::Capture, dqbuf::DqBuffer, handles_provider::MmapProvider, FormatBuilder,
+        OutputQueueable,
     },
-    device::queue::{direction::Capture, dqbuf::DqBuffer, FormatBuilder, OutputQueueable},
-    memory::DmaBufHandle,
-    PixelFormat, PlaneLayout, Rect,
+    error::AsErrno,
+    memory::{DmaBufHandle, MemoryType},
+    watchdog::Stalled,
+    Format, PixelFormat, PlaneLayout, Rect,
 };
 
-use crate::memory::{
-    v4l2r_video_frame, v4l2r_video_frame_provider, v4l2r_video_frame_provider_queue_frame,
-    DmaBufFd, VideoFrameMemoryType,
+use crate::{
+    c_path,
+    error::{report, report_errno, report_msg, report_msg_errno, v4l2r_error_code},
+    frame::{into_client_frame, v4l2r_frame, ExportedBuffers},
+    memory::{
+        v4l2r_video_frame, v4l2r_video_frame_provider, v4l2r_video_frame_provider_queue_frame,
+        DmaBufFd, VideoFrameMemoryType,
+    },
+    SendablePtr,
 };
 
 type DynCbDecoder = Decoder<
@@ -48,9 +64,39 @@ type DynCbDecoder = Decoder<
     >,
 >;
 
+type ExportedFramesDecoder = Decoder<
+    Decoding<
+        Vec<DmaBufHandle<DmaBufFd>>,
+        MmapProvider,
+        Box<dyn InputDoneCallback<Vec<DmaBufHandle<DmaBufFd>>>>,
+        Box<dyn DecoderEventCallback<MmapProvider>>,
+        Box<dyn FormatChangedCallback<MmapProvider>>,
+    >,
+>;
+
+/// How decoded frames are passed to the client.
+enum DecoderMode {
+    /// Frames are decoded into DMABUFs provided by the client through a video
+    /// frame provider.
+    ClientFrames(DynCbDecoder),
+    /// Frames are decoded into MMAP buffers exported as DMABUFs.
+    ExportedFrames(ExportedFramesDecoder),
+}
+
+/// Evaluate `$body` with `$decoder` bound to the decoder of `$mode`, whichever
+/// its mode is.
+macro_rules! with_decoder {
+    ($mode:expr, $decoder:ident => $body:expr) => {
+        match $mode {
+            DecoderMode::ClientFrames($decoder) => $body,
+            DecoderMode::ExportedFrames($decoder) => $body,
+        }
+    };
+}
+
 /// A V4L2 decoder instance.
 pub struct v4l2r_decoder {
-    decoder: DynCbDecoder,
+    decoder: DecoderMode,
     // Reference to the video frame provider for our callbacks.
     provider: Option<Arc<v4l2r_video_frame_provider>>,
     // Keep the size of input buffers at hand.
@@ -93,7 +139,8 @@ pub struct v4l2r_decoder_format_changed_event {
     /// Visible rectangle for decoded frames produced after this event.
     visible_rect: bindings::v4l2_rect,
     /// Pointer to the video frame provider the client must use to provide
-    /// frames to decode into.
+    /// frames to decode into, or NULL if the decoder has been created using
+    /// [`v4l2r_decoder_new_exported`] and allocates its own frames.
     ///
     /// When the client receives this event, it must stop using the previous
     /// video frame provider (if any) as soon as possible and destroy it using
@@ -128,12 +175,70 @@ pub enum v4l2r_decoder_event {
     FrameDecoded(v4l2r_decoder_frame_decoded_event),
     FormatChanged(v4l2r_decoder_format_changed_event),
     EndOfStream,
+    /// A frame has been decoded by a decoder created using
+    /// [`v4l2r_decoder_new_exported`]. The client owns the frame until it
+    /// passes it to [`v4l2r_frame_release`].
+    ///
+    /// [`v4l2r_frame_release`]: crate::frame::v4l2r_frame_release
+    FrameExported(*const v4l2r_frame),
+    /// The decoder has failed while processing the stream. The decoder keeps
+    /// running, but is unlikely to produce any further frame and should be
+    /// destroyed.
+    ///
+    /// The error is also recorded as the last error of the thread calling the
+    /// events callback, so its message can be retrieved using
+    /// [`v4l2r_last_error_msg`] from within the callback. The possible codes
+    /// are:
+    ///
+    /// - `V4L2R_ERROR_UNSUPPORTED_FORMAT` if the decoder does not support the
+    ///   codec or the resolution of the stream, or could not find the
+    ///   resolution of the stream in the buffers it has been given,
+    /// - `V4L2R_ERROR_ERRNO` if the driver failed to set up the decoding,
+    /// - `V4L2R_ERROR_INTERNAL` if the driver stopped making progress while
+    ///   it still had OUTPUT buffers to process,
+    /// - `V4L2R_ERROR_DEVICE_LOST`.
+    ///
+    /// [`v4l2r_last_error_msg`]: crate::error::v4l2r_last_error_msg
+    DecoderError(v4l2r_error_code),
 }
 
 /// Events callback. This callback is guaranteed to always be called from the
 /// same thread, i.e. events are completely sequential.
 pub type v4l2r_decoder_event_cb = extern "C" fn(*mut c_void, *mut v4l2r_decoder_event);
 
+/// Record `error`, reported by the decoder through its events callback, as the
+/// last error of the calling thread and return its code.
+fn report_setup_error(error: &DecodeSetupError) -> v4l2r_error_code {
+    let code = match error {
+        // Drivers that cannot find the resolution in the stream most likely do
+        // not support it.
+        DecodeSetupError::UnsupportedCodec(_)
+        | DecodeSetupError::UnsupportedResolution { .. }
+        | DecodeSetupError::NoSourceChange { .. } => {
+            v4l2r_error_code::V4L2R_ERROR_UNSUPPORTED_FORMAT
+        }
+        DecodeSetupError::DriverError { .. } => v4l2r_error_code::V4L2R_ERROR_ERRNO,
+    };
+
+    report_msg_errno(
+        code,
+        error.errno(),
+        format_args!("decoder setup failed: {}", error),
+    )
+}
+
+/// Record `stalled`, reported by the decoder through its events callback, as
+/// the last error of the calling thread and return its code.
+fn report_stalled(stalled: &Stalled) -> v4l2r_error_code {
+    report_msg(
+        v4l2r_error_code::V4L2R_ERROR_INTERNAL,
+        format_args!(
+            "decoder stalled with {} OUTPUT buffers pending",
+            stalled.pending_output
+        ),
+    )
+}
+
 fn set_capture_format_cb(
     f: FormatBuilder,
     desired_pixel_format: Option<PixelFormat>,
@@ -201,7 +306,7 @@ fn frame_decoded_cb(
 
     // Immediately recycle empty frames. We will pass the corresponding
     // event to the client.
-    if *v4l2_data.get_first_plane().bytesused == 0 {
+    if v4l2_data.plane_bytesused(0) == Some(0) {
         debug!(
             "Immediately recycling zero-sized frame {} {}",
             frame.id,
@@ -230,36 +335,29 @@ fn frame_decoded_cb(
     }
 }
 
-// A void pointer that can be sent across threads. This is usually not allowed
-// by Rust, but is necessary for us to call back into the V4L2RustDecoder.
-struct SendablePtr<T>(*mut T);
-impl<T> Clone for SendablePtr<T> {
-    fn clone(&self) -> Self {
-        *self
+/// Returns the pixel format requested for decoded frames, if any.
+fn desired_pixel_format(output_format_fourcc: u32) -> Option<PixelFormat> {
+    match output_format_fourcc {
+        0 => None,
+        fourcc => Some(PixelFormat::from(fourcc)),
     }
 }
-impl<T> Copy for SendablePtr<T> {}
-unsafe impl<T> Send for SendablePtr<T> {}
-unsafe impl<T> Sync for SendablePtr<T> {}
 
-#[allow(clippy::too_many_arguments)]
-fn v4l2r_decoder_new_safe(
+/// Open the decoder at `path`, set its input format and allocate its input
+/// buffers, reporting an error if any of these steps fails.
+fn open_decoder(
     path: &Path,
     input_format_fourcc: u32,
     num_input_buffers: usize,
     input_buffer_size: usize,
-    output_format_fourcc: u32,
-    input_done_cb: v4l2r_decoder_input_done_cb,
-    event_cb: v4l2r_decoder_event_cb,
-    cb_data: *mut c_void,
-) -> *mut v4l2r_decoder {
-    let decoder = match Decoder::open(path) {
-        Ok(decoder) => decoder,
-        Err(e) => {
-            error!("failed to open decoder {}: {:#?}", path.display(), e);
-            return std::ptr::null_mut();
-        }
-    };
+) -> Result<Decoder<ReadyToDecode<Vec<DmaBufHandle<DmaBufFd>>>>, v4l2r_error_code> {
+    let decoder = Decoder::open(path).map_err(|e| {
+        report(
+            v4l2r_error_code::V4L2R_ERROR_DEVICE_NOT_FOUND,
+            format!("Failed to open decoder {}", path.display()),
+            &e,
+        )
+    })?;
 
     info!(
         "Opened decoder {} with format {}, {} input buffers of size {}",
@@ -294,29 +392,74 @@ fn v4l2r_decoder_new_safe(
         );
         Ok(())
     };
-    let decoder = match decoder.set_output_format(format_builder) {
-        Ok(decoder) => decoder,
-        Err(e) => {
-            error!("Error while setting output format: {}", e);
-            return std::ptr::null_mut();
-        }
-    };
+    let decoder = decoder.set_output_format(format_builder).map_err(|e| {
+        report(
+            v4l2r_error_code::V4L2R_ERROR_UNSUPPORTED_FORMAT,
+            "Error while setting output format",
+            &*e,
+        )
+    })?;
 
-    let output_format = match output_format_fourcc {
-        0 => None,
-        fourcc => Some(PixelFormat::from(fourcc)),
-    };
+    decoder
+        .allocate_output_buffers::<Vec<DmaBufHandle<DmaBufFd>>>(num_input_buffers)
+        .map_err(|e| {
+            report(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Error while allocating OUTPUT buffers",
+                &e,
+            )
+        })
+}
 
-    let cb_data = SendablePtr(cb_data);
+/// Returns the callback passing completed input buffers to `input_done_cb`.
+fn input_done_callback(
+    input_done_cb: v4l2r_decoder_input_done_cb,
+    cb_data: SendablePtr<c_void>,
+) -> Box<dyn InputDoneCallback<Vec<DmaBufHandle<DmaBufFd>>>> {
+    Box::new(
+        move |buf: CompletedInputBuffer<Vec<DmaBufHandle<DmaBufFd>>>| {
+            // Make Rust 2021 happy.
+            let cb_data = cb_data;
 
-    let decoder =
-        match decoder.allocate_output_buffers::<Vec<DmaBufHandle<DmaBufFd>>>(num_input_buffers) {
-            Ok(decoder) => decoder,
-            Err(e) => {
-                error!("Error while allocating OUTPUT buffers: {}", e);
-                return std::ptr::null_mut();
+            match buf {
+                CompletedInputBuffer::Dequeued(mut dqbuf) => {
+                    debug!("Input buffer {} done", dqbuf.data.index());
+                    // TODO check return value?
+                    input_done_cb(cb_data.0, dqbuf.data.as_mut_ptr() as *const _);
+                }
+                // Just drop canceled buffers for now - the client will remove
+                // them on its side as well.
+                // TODO add a status parameter to the callback and invoke it?
+                // that way the client does not need to clear its own list...
+                CompletedInputBuffer::Canceled(_) => (),
             }
-        };
+        },
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn v4l2r_decoder_new_safe(
+    path: &Path,
+    input_format_fourcc: u32,
+    num_input_buffers: usize,
+    input_buffer_size: usize,
+    output_format_fourcc: u32,
+    input_done_cb: v4l2r_decoder_input_done_cb,
+    event_cb: v4l2r_decoder_event_cb,
+    cb_data: *mut c_void,
+) -> *mut v4l2r_decoder {
+    let decoder = match open_decoder(
+        path,
+        input_format_fourcc,
+        num_input_buffers,
+        input_buffer_size,
+    ) {
+        Ok(decoder) => decoder,
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    let output_format = desired_pixel_format(output_format_fourcc);
+    let cb_data = SendablePtr(cb_data);
 
     // Reserve memory on the heap for our decoder and take a pointer that we
     // can use in our callbacks.
@@ -337,26 +480,21 @@ fn v4l2r_decoder_new_safe(
                 frame_decoded_cb(decoder, dqbuf, event_cb, cb_data.0)
             }
             DecoderEvent::EndOfStream => event_cb(cb_data.0, &mut v4l2r_decoder_event::EndOfStream),
+            DecoderEvent::SetupError(e) => event_cb(
+                cb_data.0,
+                &mut v4l2r_decoder_event::DecoderError(report_setup_error(&e)),
+            ),
+            DecoderEvent::FrameDropStarted => warn!("Decoder started dropping frames"),
+            DecoderEvent::FrameDropStopped => info!("Decoder stopped dropping frames"),
+            DecoderEvent::Stalled(stalled) => event_cb(
+                cb_data.0,
+                &mut v4l2r_decoder_event::DecoderError(report_stalled(&stalled)),
+            ),
         };
     };
 
     let res = decoder.start(
-        Box::new(
-            move |buf: CompletedInputBuffer<Vec<DmaBufHandle<DmaBufFd>>>| {
-                match buf {
-                    CompletedInputBuffer::Dequeued(mut dqbuf) => {
-                        debug!("Input buffer {} done", dqbuf.data.index());
-                        // TODO check return value?
-                        input_done_cb(cb_data.0, dqbuf.data.as_mut_ptr() as *const _);
-                    }
-                    // Just drop canceled buffers for now - the client will remove
-                    // them on its side as well.
-                    // TODO add a status parameter to the callback and invoke it?
-                    // that way the client does not need to clear its own list...
-                    CompletedInputBuffer::Canceled(_) => (),
-                }
-            },
-        ) as Box<dyn InputDoneCallback<Vec<DmaBufHandle<DmaBufFd>>>>,
+        input_done_callback(input_done_cb, cb_data),
         Box::new(event_handler) as Box<dyn DecoderEventCallback<Arc<v4l2r_video_frame_provider>>>,
         Box::new(
             move |f: FormatBuilder,
@@ -387,7 +525,11 @@ fn v4l2r_decoder_new_safe(
     let decoder = match res {
         Ok(decoder) => decoder,
         Err(e) => {
-            error!("Cannot start decoder: {}", e);
+            report(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Cannot start decoder",
+                &e,
+            );
             return std::ptr::null_mut();
         }
     };
@@ -395,7 +537,7 @@ fn v4l2r_decoder_new_safe(
     let input_format: v4l2r::Format = decoder.get_output_format().unwrap();
 
     let decoder = v4l2r_decoder {
-        decoder,
+        decoder: DecoderMode::ClientFrames(decoder),
         provider: None,
         input_buf_size: input_format.plane_fmt[0].sizeimage as u64,
     };
@@ -416,38 +558,205 @@ fn v4l2r_decoder_new_safe(
     Box::into_raw(decoder_box)
 }
 
+/// Callback invoked when the CAPTURE format of a decoder created using
+/// [`v4l2r_decoder_new_exported`] changes.
+#[allow(clippy::too_many_arguments)]
+fn set_exported_capture_format_cb(
+    f: FormatBuilder,
+    desired_pixel_format: Option<PixelFormat>,
+    visible_rect: Rect,
+    min_num_buffers: usize,
+    num_extra_frames: usize,
+    exported_buffers: &Mutex<ExportedBuffers>,
+    event_cb: v4l2r_decoder_event_cb,
+    cb_data: *mut c_void,
+) -> anyhow::Result<FormatChangedReply<MmapProvider>> {
+    let mut v4l2_format: bindings::v4l2_format = match desired_pixel_format {
+        Some(format) => f.set_pixelformat(format).apply()?,
+        None => f.apply()?,
+    };
+    let format = Format::try_from(v4l2_format)?;
+
+    exported_buffers
+        .lock()
+        .unwrap()
+        .set_format(format.clone(), visible_rect);
+
+    // TODO check return value.
+    event_cb(
+        cb_data,
+        &mut v4l2r_decoder_event::FormatChanged(v4l2r_decoder_format_changed_event {
+            new_format: &mut v4l2_format,
+            visible_rect: visible_rect.into(),
+            new_provider: std::ptr::null(),
+            min_num_frames: min_num_buffers as c_uint,
+        }),
+    );
+
+    Ok(FormatChangedReply {
+        provider: MmapProvider::new(&format),
+        mem_type: MemoryType::Mmap,
+        num_buffers: (min_num_buffers + num_extra_frames).min(bindings::VIDEO_MAX_FRAME as usize),
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn v4l2r_decoder_new_exported_safe(
+    path: &Path,
+    input_format_fourcc: u32,
+    num_input_buffers: usize,
+    input_buffer_size: usize,
+    output_format_fourcc: u32,
+    num_extra_frames: usize,
+    input_done_cb: v4l2r_decoder_input_done_cb,
+    event_cb: v4l2r_decoder_event_cb,
+    cb_data: *mut c_void,
+) -> *mut v4l2r_decoder {
+    let decoder = match open_decoder(
+        path,
+        input_format_fourcc,
+        num_input_buffers,
+        input_buffer_size,
+    ) {
+        Ok(decoder) => decoder,
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    let output_format = desired_pixel_format(output_format_fourcc);
+    let cb_data = SendablePtr(cb_data);
+    let exported_buffers = Arc::new(Mutex::new(ExportedBuffers::new(Arc::clone(
+        decoder.device(),
+    ))));
+
+    let event_handler = {
+        let exported_buffers = Arc::clone(&exported_buffers);
+
+        move |event: DecoderEvent<MmapProvider>| {
+            // Make Rust 2021 happy.
+            let cb_data = cb_data;
+
+            match event {
+                DecoderEvent::FrameDecoded(dqbuf) => {
+                    // Empty frames are recycled on the spot by dropping them.
+                    if dqbuf.data.plane_bytesused(0) == Some(0) {
+                        debug!("Recycling zero-sized frame {}", dqbuf.data.index());
+                        return;
+                    }
+
+                    let frame = exported_buffers.lock().unwrap().export(dqbuf);
+                    if let Some(frame) = frame {
+                        event_cb(
+                            cb_data.0,
+                            &mut v4l2r_decoder_event::FrameExported(into_client_frame(frame)),
+                        );
+                    }
+                }
+                DecoderEvent::EndOfStream => {
+                    event_cb(cb_data.0, &mut v4l2r_decoder_event::EndOfStream)
+                }
+                DecoderEvent::SetupError(e) => event_cb(
+                    cb_data.0,
+                    &mut v4l2r_decoder_event::DecoderError(report_setup_error(&e)),
+                ),
+                DecoderEvent::FrameDropStarted => warn!("Decoder started dropping frames"),
+                DecoderEvent::FrameDropStopped => info!("Decoder stopped dropping frames"),
+                DecoderEvent::Stalled(stalled) => event_cb(
+                    cb_data.0,
+                    &mut v4l2r_decoder_event::DecoderError(report_stalled(&stalled)),
+                ),
+            };
+        }
+    };
+
+    let res = decoder.start(
+        input_done_callback(input_done_cb, cb_data),
+        Box::new(event_handler) as Box<dyn DecoderEventCallback<MmapProvider>>,
+        Box::new(
+            move |f: FormatBuilder,
+                  visible_rect: Rect,
+                  min_num_buffers: usize|
+                  -> anyhow::Result<FormatChangedReply<MmapProvider>> {
+                // Make Rust 2021 happy.
+                let cb_data = cb_data;
+
+                set_exported_capture_format_cb(
+                    f,
+                    output_format,
+                    visible_rect,
+                    min_num_buffers,
+                    num_extra_frames,
+                    &exported_buffers,
+                    event_cb,
+                    cb_data.0,
+                )
+            },
+        ) as Box<dyn FormatChangedCallback<MmapProvider>>,
+    );
+
+    let decoder = match res {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            report(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Cannot start decoder",
+                &e,
+            );
+            return std::ptr::null_mut();
+        }
+    };
+
+    let input_format: v4l2r::Format = decoder.get_output_format().unwrap();
+
+    let decoder = Box::new(v4l2r_decoder {
+        decoder: DecoderMode::ExportedFrames(decoder),
+        provider: None,
+        input_buf_size: input_format.plane_fmt[0].sizeimage as u64,
+    });
+
+    info!("Decoder {:p}: successfully started", decoder.as_ref());
+
+    Box::into_raw(decoder)
+}
+
 fn v4l2r_decoder_decode_safe(
     decoder: &mut v4l2r_decoder,
     bitstream_id: i32,
     fd: c_int,
     bytes_used: usize,
 ) -> c_int {
-    let v4l2_buffer = match decoder.decoder.get_buffer() {
-        Ok(buffer) => buffer,
-        Err(e) => {
-            error!("Error obtaining V4L2 buffer: {}", e);
-            return -1;
-        }
-    };
-    let v4l2_buffer_id = v4l2_buffer.index();
-
-    match v4l2_buffer
-        .set_timestamp(TimeVal::seconds(bitstream_id as i64))
-        .queue_with_handles(
-            vec![DmaBufHandle::from(DmaBufFd::new(
-                fd,
-                decoder.input_buf_size,
-            ))],
-            &[bytes_used],
-        ) {
-        Ok(()) => (),
-        Err(e) => {
-            error!("Error while queueing buffer: {}", e);
-            return -1;
-        }
-    };
+    let input_buf_size = decoder.input_buf_size;
+
+    with_decoder!(&mut decoder.decoder, decoder => {
+        let v4l2_buffer = match decoder.get_buffer() {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                return report(
+                    v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                    "Error obtaining V4L2 buffer",
+                    &e,
+                ) as c_int;
+            }
+        };
+        let v4l2_buffer_id = v4l2_buffer.index();
+
+        match v4l2_buffer
+            .set_timestamp(TimeVal::seconds(bitstream_id as i64))
+            .queue_with_handles(
+                vec![DmaBufHandle::from(DmaBufFd::new(fd, input_buf_size))],
+                &[bytes_used],
+            ) {
+            Ok(()) => (),
+            Err(e) => {
+                return report(
+                    v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                    "Error while queueing buffer",
+                    &e.error,
+                ) as c_int;
+            }
+        };
 
-    v4l2_buffer_id as c_int
+        v4l2_buffer_id as c_int
+    })
 }
 
 /// Create a new decoder for a given encoded format.
@@ -478,6 +787,22 @@ fn v4l2r_decoder_decode_safe(
 /// * `cb_data` is a pointer that will always be passed as the first parameter
 ///   of the `input_done_cb` and `events_cb`.
 ///
+/// Returns NULL if the decoder could not be created, in which case the last
+/// error code is one of the following:
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if `path` is NULL or not valid UTF-8.
+/// * `V4L2R_ERROR_DEVICE_NOT_FOUND` if there is no device at `path`, or it is
+///   not a stateful decoder.
+/// * `V4L2R_ERROR_UNSUPPORTED_FORMAT` if the decoder does not support
+///   `input_format_fourcc`.
+/// * `V4L2R_ERROR_ERRNO` if the device cannot be opened or set up.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+/// * `V4L2R_ERROR_INTERNAL` if the decoder thread cannot be started.
+///
+/// Errors the driver only reports once decoding has started, e.g. if it
+/// rejects the input format or does not support the resolution of the stream,
+/// are reported through a `DecoderError` event instead.
+///
 /// # Safety
 /// The passed `path` must be a valid, zero-terminated C string containining the
 /// path to the device. Expect a crash if passing an invalid string.
@@ -492,9 +817,10 @@ pub unsafe extern "C" fn v4l2r_decoder_new(
     event_cb: v4l2r_decoder_event_cb,
     cb_data: *mut c_void,
 ) -> *mut v4l2r_decoder {
-    let cstr = CStr::from_ptr(path);
-    let rstr = cstr.to_str().unwrap();
-    let path = Path::new(&rstr);
+    let path = match c_path(path) {
+        Ok(path) => path,
+        Err(_) => return std::ptr::null_mut(),
+    };
 
     v4l2r_decoder_new_safe(
         path,
@@ -508,6 +834,58 @@ pub unsafe extern "C" fn v4l2r_decoder_new(
     )
 }
 
+/// Create a new decoder for a given encoded format, which allocates its own
+/// frames and passes them to the client as DMABUFs.
+///
+/// The parameters are the same as for [`v4l2r_decoder_new`], with the
+/// addition of `num_extra_frames`, the number of frames the client may hold
+/// at the same time without stalling the decoder, which is added to the
+/// number of frames required by the decoder.
+///
+/// Decoded frames are passed to the client through the
+/// [`v4l2r_decoder_event::FrameExported`] event, and must be given back using
+/// [`v4l2r_frame_release`] once the client is done with them. The
+/// [`v4l2r_decoder_event::FormatChanged`] event is still produced, but its
+/// `new_provider` member is NULL as the client does not need to provide frames.
+///
+/// Returns NULL if the decoder could not be created, in which case the last
+/// error code is one of those listed for [`v4l2r_decoder_new`].
+///
+/// [`v4l2r_frame_release`]: crate::frame::v4l2r_frame_release
+///
+/// # Safety
+/// The passed `path` must be a valid, zero-terminated C string containining the
+/// path to the device. Expect a crash if passing an invalid string.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_decoder_new_exported(
+    path: *const c_char,
+    input_format_fourcc: u32,
+    num_input_buffers: usize,
+    input_buffer_size: usize,
+    output_format_fourcc: u32,
+    num_extra_frames: usize,
+    input_done_cb: v4l2r_decoder_input_done_cb,
+    event_cb: v4l2r_decoder_event_cb,
+    cb_data: *mut c_void,
+) -> *mut v4l2r_decoder {
+    let path = match c_path(path) {
+        Ok(path) => path,
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    v4l2r_decoder_new_exported_safe(
+        path,
+        input_format_fourcc,
+        num_input_buffers,
+        input_buffer_size,
+        output_format_fourcc,
+        num_extra_frames,
+        input_done_cb,
+        event_cb,
+        cb_data,
+    )
+}
+
 /// Stop and destroy a decoder.
 ///
 /// Stop `decoder` and destroy it. This function DOES take ownership of
@@ -516,24 +894,36 @@ pub unsafe extern "C" fn v4l2r_decoder_new(
 /// It is guaranteed that none of the callbacks passed to [`v4l2r_decoder_new`]
 /// will be called after this function has returned.
 ///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes. `decoder` is
+/// destroyed even if an error is returned.
+///
+/// * `V4L2R_ERROR_ERRNO` if the OUTPUT queue cannot be stopped.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+/// * `V4L2R_ERROR_INTERNAL` if the decoder thread did not stop properly.
+///
 /// # Safety
 ///
 /// `decoder` must be a valid pointer to a decoder returned by
 /// `v4l2r_decoder_new`. Passing a NULL or invalid pointer will cause a crash.
 /// `decoder` must not be used again after this function is called.
 #[no_mangle]
-pub unsafe extern "C" fn v4l2r_decoder_destroy(decoder: *mut v4l2r_decoder) {
+pub unsafe extern "C" fn v4l2r_decoder_destroy(decoder: *mut v4l2r_decoder) -> v4l2r_error_code {
     info!("Decoder {:p}: destroying", decoder);
 
     if decoder.is_null() {
         warn!("Trying to destroy a NULL decoder");
-        return;
+        return v4l2r_error_code::V4L2R_SUCCESS;
     }
 
     let decoder = Box::from_raw(decoder);
-    match decoder.decoder.stop() {
-        Ok(_) => (),
-        Err(e) => error!("Error while stopping decoder: {}", e),
+    let res = with_decoder!(decoder.decoder, decoder => decoder.stop().map(|_| ()));
+    match res {
+        Ok(()) => v4l2r_error_code::V4L2R_SUCCESS,
+        Err(e) => report(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            "Error while stopping decoder",
+            &e,
+        ),
     }
 }
 
@@ -543,8 +933,11 @@ pub unsafe extern "C" fn v4l2r_decoder_destroy(decoder: *mut v4l2r_decoder) {
 /// This function can be called at any time since a decoder always have a valid
 /// input format.
 ///
-/// Returns 0 in case of success, -1 if an error occured, in which case `format`
-/// is not overwritten.
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes, in which case
+/// `format` is not overwritten:
+///
+/// * `V4L2R_ERROR_ERRNO` if the format cannot be obtained.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
 ///
 /// # Safety
 ///
@@ -554,22 +947,25 @@ pub unsafe extern "C" fn v4l2r_decoder_destroy(decoder: *mut v4l2r_decoder) {
 pub unsafe extern "C" fn v4l2r_decoder_get_input_format(
     decoder: *const v4l2r_decoder,
     format: *mut bindings::v4l2_format,
-) -> c_int {
+) -> v4l2r_error_code {
     assert!(!decoder.is_null());
     assert!(!format.is_null());
 
     let decoder = &*decoder;
     let format = &mut *format;
 
-    *format = match decoder.decoder.get_output_format() {
+    *format = match with_decoder!(&decoder.decoder, decoder => decoder.get_output_format()) {
         Ok(format) => format,
         Err(e) => {
-            error!("Error while getting output format: {}", e);
-            return -1;
+            return report_errno(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Error while getting output format",
+                e,
+            )
         }
     };
 
-    0
+    v4l2r_error_code::V4L2R_SUCCESS
 }
 
 /// Decode the encoded data referenced by `fd`.
@@ -586,7 +982,13 @@ pub unsafe extern "C" fn v4l2r_decoder_get_input_format(
 /// the same index will be passed as argument to the *input done callback* when
 /// this is the case.
 ///
-/// In case of error, -1 is returned.
+/// In case of error, one of the following negative error codes is returned:
+///
+/// * `V4L2R_ERROR_ERRNO` if no V4L2 buffer can be obtained, or `fd` cannot be
+///   queued, e.g. because it is not a valid DMABUF.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+/// * `V4L2R_ERROR_INTERNAL` if no V4L2 buffer can be obtained for another
+///   reason, e.g. because the decoder is paused.
 ///
 /// # Safety
 ///
@@ -629,20 +1031,27 @@ pub unsafe extern "C" fn v4l2r_decoder_decode(
 /// [`v4l2r_decoder_frame_decoded_event`]. That way the client can recycle its
 /// input buffers and the decoding process does not get stuck.
 ///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes:
+///
+/// * `V4L2R_ERROR_ERRNO` if completed input buffers cannot be dequeued.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+///
 /// # Safety
 ///
 /// `decoder` must be a valid pointer to a decoder returned by
 /// [`v4l2r_decoder_new`]. Passing a NULL or invalid pointer will cause a crash.
 #[no_mangle]
-pub unsafe extern "C" fn v4l2r_decoder_kick(decoder: *const v4l2r_decoder) {
+pub unsafe extern "C" fn v4l2r_decoder_kick(decoder: *const v4l2r_decoder) -> v4l2r_error_code {
     assert!(!decoder.is_null());
     let decoder = &*decoder;
 
-    match decoder.decoder.kick() {
-        Ok(()) => (),
-        Err(e) => {
-            error!("Error while kicking decoder: {}", e);
-        }
+    match with_decoder!(&decoder.decoder, decoder => decoder.kick()) {
+        Ok(()) => v4l2r_error_code::V4L2R_SUCCESS,
+        Err(e) => report(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            "Error while kicking decoder",
+            &e,
+        ),
     }
 }
 
@@ -658,10 +1067,23 @@ pub enum v4l2r_decoder_drain_response {
     /// Drain cannot be done at the moment because not enough input buffers
     /// have been processed to know the output format.
     TRY_AGAIN,
-    /// An error has occurred.
+    /// An error has occurred. Its code and description are available through
+    /// [`v4l2r_last_error_code`] and [`v4l2r_last_error_msg`].
+    ///
+    /// [`v4l2r_last_error_code`]: crate::error::v4l2r_last_error_code
+    /// [`v4l2r_last_error_msg`]: crate::error::v4l2r_last_error_msg
     ERROR,
 }
 
+/// Drain the decoder, i.e. produce the frames of all the input buffers queued
+/// so far. If `blocking` is true, wait until the drain is completed.
+///
+/// If `ERROR` is returned, the last error code is one of the following:
+///
+/// * `V4L2R_ERROR_ERRNO` if the drain command cannot be sent to the device.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+/// * `V4L2R_ERROR_INTERNAL` if the decoder thread has stopped.
+///
 /// # Safety
 ///
 /// `decoder` must be a valid pointer to a decoder returned by
@@ -674,30 +1096,44 @@ pub unsafe extern "C" fn v4l2r_decoder_drain(
     assert!(!decoder.is_null());
     let decoder = &*decoder;
 
-    match decoder.decoder.drain(blocking) {
+    match with_decoder!(&decoder.decoder, decoder => decoder.drain(blocking)) {
         Ok(true) => v4l2r_decoder_drain_response::DRAIN_COMPLETED,
         Ok(false) => v4l2r_decoder_drain_response::DRAIN_STARTED,
         Err(DrainError::TryAgain) => v4l2r_decoder_drain_response::TRY_AGAIN,
         Err(e) => {
-            error!("Error while draining decoder: {}", e);
+            report(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Error while draining decoder",
+                &e,
+            );
             v4l2r_decoder_drain_response::ERROR
         }
     }
 }
 
+/// Flush the decoder, i.e. try to cancel all pending work.
+///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes:
+///
+/// * `V4L2R_ERROR_ERRNO` if the OUTPUT queue cannot be stopped.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+/// * `V4L2R_ERROR_INTERNAL` if the decoder thread has stopped.
+///
 /// # Safety
 ///
 /// `decoder` must be a valid pointer to a decoder returned by
 /// [`v4l2r_decoder_new`]. Passing a NULL or invalid pointer will cause a crash.
 #[no_mangle]
-pub unsafe extern "C" fn v4l2r_decoder_flush(decoder: *const v4l2r_decoder) {
+pub unsafe extern "C" fn v4l2r_decoder_flush(decoder: *const v4l2r_decoder) -> v4l2r_error_code {
     assert!(!decoder.is_null());
     let decoder = &*decoder;
 
-    match decoder.decoder.flush() {
-        Ok(()) => (),
-        Err(e) => {
-            error!("Error while flushing decoder: {:#?}", e);
-        }
+    match with_decoder!(&decoder.decoder, decoder => decoder.flush()) {
+        Ok(()) => v4l2r_error_code::V4L2R_SUCCESS,
+        Err(e) => report(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            "Error while flushing decoder",
+            &e,
+        ),
     }
 }
@@ -0,0 +1,305 @@
+//! Module for discovering V4L2 devices and the formats they support.
+//!
+//! All the functions of this module fill arrays provided by the caller and
+//! return the total number of available entries, which may be larger than the
+//! size of the array, or a negative `v4l2r_error_code` on error. Calling them
+//! with a NULL array and a size of 0 is a valid way to query the number of
+//! entries before allocating the array.
+//!
+//! Strings are copied into fixed-size arrays of the returned structures and
+//! are always NUL-terminated. No memory owned by the library is ever handed
+//! to the client.
+#![allow(non_camel_case_types)]
+
+use log::warn;
+use std::{
+    os::raw::{c_char, c_int},
+    path::PathBuf,
+    slice,
+};
+use v4l2r::{
+    bindings::{self, v4l2_frmsizeenum},
+    device::{Device, DeviceConfig},
+    ioctl::{self, FormatIterator, FrmSizeTypes},
+    PixelFormat, QueueType,
+};
+
+use crate::{
+    c_path, copy_str,
+    error::{report, report_msg, v4l2r_error_code},
+};
+
+/// Description of a video device.
+#[repr(C)]
+pub struct v4l2r_device_info {
+    /// Path to the device node, e.g. `/dev/video0`.
+    path: [c_char; 64],
+    /// Name of the driver.
+    driver: [c_char; 16],
+    /// Name of the device.
+    card: [c_char; 32],
+    /// Location of the device in the system.
+    bus_info: [c_char; 32],
+    /// `V4L2_CAP_*` capabilities of the device node.
+    capabilities: u32,
+}
+
+/// Description of a pixel format supported by a queue.
+#[repr(C)]
+pub struct v4l2r_fmtdesc {
+    /// Fourcc code of the format.
+    pixelformat: u32,
+    /// `V4L2_FMT_FLAG_*` flags of the format.
+    flags: u32,
+    /// Human-readable description of the format.
+    description: [c_char; 32],
+}
+
+/// Frame size supported by a pixel format.
+///
+/// For `V4L2_FRMSIZE_TYPE_DISCRETE` sizes, the minimum and maximum dimensions
+/// are equal and the steps are 0.
+#[repr(C)]
+pub struct v4l2r_frame_size {
+    /// One of the `V4L2_FRMSIZE_TYPE_*` values.
+    type_: u32,
+    min_width: u32,
+    max_width: u32,
+    step_width: u32,
+    min_height: u32,
+    max_height: u32,
+    step_height: u32,
+}
+
+/// Copy as many `entries` as fit into the `max_entries` long array pointed to
+/// by `array`, and return the total number of entries.
+///
+/// # Safety
+///
+/// `array` must either be NULL, or point to a valid array of at least
+/// `max_entries` elements.
+unsafe fn fill_array<T>(entries: Vec<T>, array: *mut T, max_entries: usize) -> c_int {
+    let total = entries.len();
+
+    if !array.is_null() {
+        let array = slice::from_raw_parts_mut(array, max_entries);
+        for (dst, entry) in array.iter_mut().zip(entries) {
+            *dst = entry;
+        }
+    }
+
+    total as c_int
+}
+
+/// Open the device at `path`, reporting an error if this fails.
+///
+/// # Safety
+///
+/// `path` must either be NULL, or a valid pointer to a NUL-terminated C
+/// string.
+unsafe fn open_device(path: *const c_char) -> Result<Device, v4l2r_error_code> {
+    let path = c_path(path)?;
+
+    Device::open(path, DeviceConfig::new()).map_err(|e| {
+        report(
+            v4l2r_error_code::V4L2R_ERROR_DEVICE_NOT_FOUND,
+            format!("Failed to open device {}", path.display()),
+            &e,
+        )
+    })
+}
+
+/// Returns the paths of all the `/dev/videoN` nodes, sorted by `N`.
+fn video_nodes() -> Result<Vec<PathBuf>, v4l2r_error_code> {
+    let entries = std::fs::read_dir("/dev").map_err(|e| {
+        report(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            "Failed to list /dev",
+            &e,
+        )
+    })?;
+
+    let mut nodes = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let index = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("video")?
+                .parse::<u32>()
+                .ok()?;
+            Some((index, entry.path()))
+        })
+        .collect::<Vec<_>>();
+    nodes.sort_by_key(|(index, _)| *index);
+
+    Ok(nodes.into_iter().map(|(_, path)| path).collect())
+}
+
+/// List the video devices of the system.
+///
+/// Up to `max_devices` devices are written into `devices`. Nodes that cannot
+/// be opened or queried are skipped.
+///
+/// Returns the total number of devices found, or `V4L2R_ERROR_ERRNO` if the
+/// device nodes cannot be listed.
+///
+/// # Safety
+///
+/// `devices` must either be NULL, or point to a valid array of at least
+/// `max_devices` elements.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_list_devices(
+    devices: *mut v4l2r_device_info,
+    max_devices: usize,
+) -> c_int {
+    let nodes = match video_nodes() {
+        Ok(nodes) => nodes,
+        Err(code) => return code as c_int,
+    };
+
+    let infos = nodes
+        .into_iter()
+        .filter_map(|path| {
+            let device = match Device::open(&path, DeviceConfig::new()) {
+                Ok(device) => device,
+                Err(e) => {
+                    warn!("Skipping {}: {:#}", path.display(), e);
+                    return None;
+                }
+            };
+            let caps = device.caps();
+
+            let mut info = v4l2r_device_info {
+                path: [0; 64],
+                driver: [0; 16],
+                card: [0; 32],
+                bus_info: [0; 32],
+                capabilities: caps.device_caps().bits(),
+            };
+            copy_str(&mut info.path, &path.to_string_lossy());
+            copy_str(&mut info.driver, &caps.driver);
+            copy_str(&mut info.card, &caps.card);
+            copy_str(&mut info.bus_info, &caps.bus_info);
+
+            Some(info)
+        })
+        .collect::<Vec<_>>();
+
+    fill_array(infos, devices, max_devices)
+}
+
+/// Enumerate the pixel formats supported by `queue` of the device at `path`.
+///
+/// `queue` is one of the `V4L2_BUF_TYPE_*` values. Up to `max_formats`
+/// formats are written into `formats`.
+///
+/// Returns the total number of formats supported by the queue, or one of the
+/// following error codes:
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if `path` or `queue` is invalid.
+/// * `V4L2R_ERROR_DEVICE_NOT_FOUND` if there is no device at `path`.
+/// * `V4L2R_ERROR_ERRNO` if the device cannot be opened or queried.
+///
+/// # Safety
+///
+/// `path` must be a valid pointer to a NUL-terminated C string. `formats` must
+/// either be NULL, or point to a valid array of at least `max_formats`
+/// elements.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_enum_formats(
+    path: *const c_char,
+    queue: u32,
+    formats: *mut v4l2r_fmtdesc,
+    max_formats: usize,
+) -> c_int {
+    let queue = match QueueType::n(queue) {
+        Some(queue) => queue,
+        None => {
+            return report_msg(
+                v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+                format!("Invalid queue type {}", queue),
+            ) as c_int
+        }
+    };
+    let device = match open_device(path) {
+        Ok(device) => device,
+        Err(code) => return code as c_int,
+    };
+
+    let descs = FormatIterator::new(&device, queue)
+        .map(|fmtdesc| {
+            let mut desc = v4l2r_fmtdesc {
+                pixelformat: fmtdesc.pixelformat.into(),
+                flags: fmtdesc.flags.bits(),
+                description: [0; 32],
+            };
+            copy_str(&mut desc.description, &fmtdesc.description);
+            desc
+        })
+        .collect::<Vec<_>>();
+
+    fill_array(descs, formats, max_formats)
+}
+
+/// Enumerate the frame sizes supported by `pixelformat` on the device at
+/// `path`.
+///
+/// Up to `max_sizes` frame sizes are written into `sizes`.
+///
+/// Returns the total number of frame sizes supported by the format, or one of
+/// the following error codes:
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if `path` is invalid.
+/// * `V4L2R_ERROR_DEVICE_NOT_FOUND` if there is no device at `path`.
+/// * `V4L2R_ERROR_ERRNO` if the device cannot be opened.
+///
+/// # Safety
+///
+/// `path` must be a valid pointer to a NUL-terminated C string. `sizes` must
+/// either be NULL, or point to a valid array of at least `max_sizes` elements.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_enum_frame_sizes(
+    path: *const c_char,
+    pixelformat: u32,
+    sizes: *mut v4l2r_frame_size,
+    max_sizes: usize,
+) -> c_int {
+    let device = match open_device(path) {
+        Ok(device) => device,
+        Err(code) => return code as c_int,
+    };
+    let pixelformat = PixelFormat::from(pixelformat);
+
+    // EINVAL signals the end of the enumeration.
+    let frame_sizes = (0..)
+        .map_while(|index| {
+            ioctl::enum_frame_sizes::<v4l2_frmsizeenum>(&device, index, pixelformat).ok()
+        })
+        .filter_map(|frmsize| {
+            let size = match frmsize.size()? {
+                FrmSizeTypes::Discrete(size) => v4l2r_frame_size {
+                    type_: bindings::v4l2_frmsizetypes_V4L2_FRMSIZE_TYPE_DISCRETE,
+                    min_width: size.width,
+                    max_width: size.width,
+                    step_width: 0,
+                    min_height: size.height,
+                    max_height: size.height,
+                    step_height: 0,
+                },
+                FrmSizeTypes::StepWise(size) => v4l2r_frame_size {
+                    type_: frmsize.type_,
+                    min_width: size.min_width,
+                    max_width: size.max_width,
+                    step_width: size.step_width,
+                    min_height: size.min_height,
+                    max_height: size.max_height,
+                    step_height: size.step_height,
+                },
+            };
+            Some(size)
+        })
+        .collect::<Vec<_>>();
+
+    fill_array(frame_sizes, sizes, max_sizes)
+}
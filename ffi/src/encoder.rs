@@ -0,0 +1,637 @@
+//! Module for creating and controlling V4L2 encoders.
+//!
+//! Encoders are created using [`v4l2r_encoder_new`] and remain active until
+//! being given to [`v4l2r_encoder_destroy`]. They expect to be fed raw frames in
+//! the format specified at creation time using [`v4l2r_encoder_encode`], which
+//! copies the frame data into the encoder's own buffers.
+//!
+//! Encoded data is passed to the client using a chunk callback that is invoked
+//! on a dedicated thread, one chunk at a time.
+#![allow(non_camel_case_types)]
+
+use log::{debug, error, info, warn};
+use nix::sys::time::{TimeVal, TimeValLike};
+use std::{
+    io,
+    os::raw::{c_char, c_void},
+    path::Path,
+    slice,
+};
+use v4l2r::{
+    bindings,
+    device::queue::{
+        direction::Capture, dqbuf::DqBuffer, handles_provider::MmapProvider, FormatBuilder,
+    },
+    encoder::{CompletedOutputBuffer, Encoder, Encoding, ReadyToEncode},
+    ioctl::BufferFlags,
+    memory::MmapHandle,
+    Format, PixelFormat,
+};
+
+use crate::{
+    c_path,
+    error::{report, report_errno, report_msg, v4l2r_error_code},
+    SendablePtr,
+};
+
+type InputDoneCb = Box<dyn Fn(CompletedOutputBuffer<Vec<MmapHandle>>)>;
+type OutputReadyCb = Box<dyn FnMut(DqBuffer<Capture, Vec<MmapHandle>>) + Send>;
+
+type DynCbEncoder = Encoder<Encoding<Vec<MmapHandle>, MmapProvider, InputDoneCb, OutputReadyCb>>;
+
+/// A V4L2 encoder instance.
+pub struct v4l2r_encoder {
+    // Only `None` if restarting the encoder after a drain has failed.
+    encoder: Option<DynCbEncoder>,
+    chunk_cb: v4l2r_encoder_chunk_cb,
+    cb_data: SendablePtr<c_void>,
+}
+
+/// A chunk of encoded data produced by the encoder.
+#[repr(C)]
+pub struct v4l2r_encoder_chunk {
+    /// Pointer to the encoded data. Only valid for the duration of the
+    /// callback.
+    data: *const u8,
+    /// Size of the encoded data in bytes.
+    size: usize,
+    /// Timestamp of the frame this chunk has been encoded from, as passed to
+    /// [`v4l2r_encoder_encode`].
+    timestamp: u64,
+    /// Whether this chunk contains a key frame.
+    keyframe: bool,
+}
+
+/// Chunk callback. This callback is guaranteed to always be called from the
+/// same thread, i.e. chunks are produced sequentially and in stream order.
+///
+/// The first argument is the `cb_data` pointer given to
+/// [`v4l2r_encoder_new`].
+pub type v4l2r_encoder_chunk_cb = extern "C" fn(*mut c_void, *const v4l2r_encoder_chunk);
+
+fn timestamp_to_us(timestamp: bindings::timeval) -> u64 {
+    (timestamp.tv_sec as u64)
+        .wrapping_mul(1_000_000)
+        .wrapping_add(timestamp.tv_usec as u64)
+}
+
+fn output_ready_cb(
+    chunk_cb: v4l2r_encoder_chunk_cb,
+    cb_data: SendablePtr<c_void>,
+) -> OutputReadyCb {
+    Box::new(move |dqbuf: DqBuffer<Capture, Vec<MmapHandle>>| {
+        // Make Rust 2021 happy.
+        let cb_data = cb_data;
+
+        let bytes_used = dqbuf.data.plane_bytesused(0).unwrap_or(0) as usize;
+        let mapping = match dqbuf.get_plane_mapping(0) {
+            Some(mapping) => mapping,
+            None => {
+                error!(
+                    "Cannot map encoded CAPTURE buffer {}, dropping it",
+                    dqbuf.data.index()
+                );
+                return;
+            }
+        };
+        let data = &mapping.as_ref()[..bytes_used.min(mapping.len())];
+        let chunk = v4l2r_encoder_chunk {
+            data: data.as_ptr(),
+            size: data.len(),
+            timestamp: timestamp_to_us(dqbuf.data.timestamp()),
+            keyframe: dqbuf.data.flags().contains(BufferFlags::KEYFRAME),
+        };
+        debug!(
+            "Encoded chunk of {} bytes from V4L2 buffer {} (timestamp: {}, keyframe: {})",
+            chunk.size,
+            dqbuf.data.index(),
+            chunk.timestamp,
+            chunk.keyframe
+        );
+
+        chunk_cb(cb_data.0, &chunk);
+    })
+}
+
+fn start_encoder(
+    encoder: Encoder<ReadyToEncode<Vec<MmapHandle>, MmapProvider>>,
+    chunk_cb: v4l2r_encoder_chunk_cb,
+    cb_data: SendablePtr<c_void>,
+) -> io::Result<DynCbEncoder> {
+    encoder.start(
+        // Input frames are copied into MMAP buffers, so there is nothing to
+        // return to the client.
+        Box::new(|_: CompletedOutputBuffer<Vec<MmapHandle>>| ()) as InputDoneCb,
+        output_ready_cb(chunk_cb, cb_data),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn v4l2r_encoder_new_safe(
+    path: &Path,
+    input_format_fourcc: u32,
+    width: u32,
+    height: u32,
+    output_format_fourcc: u32,
+    num_input_buffers: usize,
+    num_output_buffers: usize,
+    chunk_cb: v4l2r_encoder_chunk_cb,
+    cb_data: *mut c_void,
+) -> *mut v4l2r_encoder {
+    let encoder = match Encoder::open(path) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            report(
+                v4l2r_error_code::V4L2R_ERROR_DEVICE_NOT_FOUND,
+                format!("Failed to open encoder {}", path.display()),
+                &e,
+            );
+            return std::ptr::null_mut();
+        }
+    };
+
+    let input_format = PixelFormat::from(input_format_fourcc);
+    let output_format = PixelFormat::from(output_format_fourcc);
+    info!(
+        "Opened encoder {} for {} {}x{} frames to {}",
+        path.display(),
+        input_format,
+        width,
+        height,
+        output_format
+    );
+
+    let encoder = match encoder.set_capture_format(|f: FormatBuilder| {
+        let format: Format = f.set_pixelformat(output_format).apply()?;
+        if format.pixelformat != output_format {
+            return Err(anyhow::anyhow!(
+                "Unrecognized CAPTURE format {:?}",
+                output_format
+            ));
+        }
+        Ok(())
+    }) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            report(
+                v4l2r_error_code::V4L2R_ERROR_UNSUPPORTED_FORMAT,
+                "Error while setting capture format",
+                &*e,
+            );
+            return std::ptr::null_mut();
+        }
+    };
+
+    let encoder = match encoder.set_output_format(|f: FormatBuilder| {
+        let format: Format = f
+            .set_pixelformat(input_format)
+            .set_size(width as usize, height as usize)
+            .apply()?;
+        if format.pixelformat != input_format {
+            return Err(anyhow::anyhow!(
+                "Unrecognized OUTPUT format {:?}",
+                input_format
+            ));
+        }
+        if format.width != width || format.height != height {
+            return Err(anyhow::anyhow!(
+                "Unsupported frame size {}x{}",
+                width,
+                height
+            ));
+        }
+        Ok(())
+    }) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            report(
+                v4l2r_error_code::V4L2R_ERROR_UNSUPPORTED_FORMAT,
+                "Error while setting output format",
+                &*e,
+            );
+            return std::ptr::null_mut();
+        }
+    };
+
+    let capture_format = match encoder.get_capture_format() {
+        Ok(format) => format,
+        Err(e) => {
+            report_errno(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Error while getting capture format",
+                e,
+            );
+            return std::ptr::null_mut();
+        }
+    };
+
+    let encoder = match encoder
+        .allocate_output_buffers::<Vec<MmapHandle>>(num_input_buffers)
+        .and_then(|encoder| {
+            encoder.allocate_capture_buffers(num_output_buffers, MmapProvider::new(&capture_format))
+        }) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            report(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Error while allocating buffers",
+                &e,
+            );
+            return std::ptr::null_mut();
+        }
+    };
+
+    let cb_data = SendablePtr(cb_data);
+    let encoder = match start_encoder(encoder, chunk_cb, cb_data) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            report(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Cannot start encoder",
+                &e,
+            );
+            return std::ptr::null_mut();
+        }
+    };
+
+    let encoder = Box::new(v4l2r_encoder {
+        encoder: Some(encoder),
+        chunk_cb,
+        cb_data,
+    });
+
+    info!("Encoder {:p}: successfully started", encoder.as_ref());
+
+    Box::into_raw(encoder)
+}
+
+fn v4l2r_encoder_encode_safe(
+    encoder: &mut v4l2r_encoder,
+    planes: &[&[u8]],
+    timestamp: u64,
+) -> v4l2r_error_code {
+    let encoder = match encoder.encoder.as_mut() {
+        Some(encoder) => encoder,
+        None => {
+            return report_msg(
+                v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+                "Encoder is not running",
+            )
+        }
+    };
+
+    let v4l2_buffer = match encoder.get_buffer() {
+        Ok(buffer) => buffer,
+        Err(e) => {
+            return report(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Error obtaining V4L2 buffer",
+                &e,
+            )
+        }
+    };
+
+    let num_buffer_planes = v4l2_buffer.num_expected_planes();
+    if num_buffer_planes > 1 && planes.len() != num_buffer_planes {
+        return report_msg(
+            v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+            format!(
+                "Expected {} planes, got {}",
+                num_buffer_planes,
+                planes.len()
+            ),
+        );
+    }
+
+    let mut bytes_used = vec![0usize; num_buffer_planes];
+    for (i, plane) in planes.iter().enumerate() {
+        // Formats with a single memory plane receive all the planes one after
+        // the other.
+        let buffer_plane = if num_buffer_planes == 1 { 0 } else { i };
+        let offset = bytes_used[buffer_plane];
+
+        let mut mapping = match v4l2_buffer.get_plane_mapping(buffer_plane) {
+            Some(mapping) => mapping,
+            None => {
+                return report_msg(
+                    v4l2r_error_code::V4L2R_ERROR_INTERNAL,
+                    format!("Cannot map plane {} of the input buffer", buffer_plane),
+                )
+            }
+        };
+        match mapping.get_mut(offset..offset + plane.len()) {
+            Some(dst) => dst.copy_from_slice(plane),
+            None => {
+                return report_msg(
+                    v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+                    format!("Plane {} does not fit into the input buffer", i),
+                )
+            }
+        }
+        bytes_used[buffer_plane] += plane.len();
+    }
+
+    match v4l2_buffer
+        .set_timestamp(TimeVal::microseconds(timestamp as i64))
+        .queue(&bytes_used)
+    {
+        Ok(()) => v4l2r_error_code::V4L2R_SUCCESS,
+        Err(e) => report(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            "Error while queueing buffer",
+            &e,
+        ),
+    }
+}
+
+fn v4l2r_encoder_drain_safe(encoder: &mut v4l2r_encoder) -> v4l2r_error_code {
+    let running = match encoder.encoder.take() {
+        Some(running) => running,
+        None => {
+            return report_msg(
+                v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+                "Encoder is not running",
+            )
+        }
+    };
+
+    // Stopping the encoder waits for all the pending frames to be encoded and
+    // passed to the chunk callback.
+    let ready = match running.stop() {
+        Ok(ready) => ready,
+        Err(e) => {
+            return report(
+                v4l2r_error_code::V4L2R_ERROR_ERRNO,
+                "Error while draining encoder",
+                &e,
+            )
+        }
+    };
+
+    match start_encoder(ready, encoder.chunk_cb, encoder.cb_data) {
+        Ok(running) => {
+            encoder.encoder = Some(running);
+            v4l2r_error_code::V4L2R_SUCCESS
+        }
+        Err(e) => report(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            "Cannot restart encoder after drain",
+            &e,
+        ),
+    }
+}
+
+/// Create a new encoder producing a given encoded format.
+///
+/// * `path` is the path to the V4L2 device that will be used for encoding.
+/// * `input_format_fourcc` is the FOURCC code of the raw frames we will
+///   encode, e.g. "NV12" or "RGB3".
+/// * `width` and `height` are the dimensions of the frames to encode.
+/// * `output_format_fourcc` is the FOURCC code of the encoded format to
+///   produce, e.g. "H264" or "FWHT".
+/// * `num_input_buffers` is the number of buffers the encoder uses to hold
+///   frames waiting to be encoded.
+/// * `num_output_buffers` is the number of buffers the encoder uses to hold
+///   encoded data.
+/// * `chunk_cb` is a pointer to a function to be called every time encoded
+///   data is available. See [`v4l2r_encoder_chunk_cb`] for more details.
+/// * `cb_data` is a pointer that will always be passed as the first parameter
+///   of `chunk_cb`.
+///
+/// Returns NULL if the encoder could not be created, in which case the last
+/// error code is one of the following:
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if `path` is NULL or not valid UTF-8.
+/// * `V4L2R_ERROR_DEVICE_NOT_FOUND` if there is no device at `path`, or it is
+///   not an encoder.
+/// * `V4L2R_ERROR_UNSUPPORTED_FORMAT` if the encoder does not support
+///   `input_format_fourcc`, `output_format_fourcc`, or the frame size.
+/// * `V4L2R_ERROR_ERRNO` if the device cannot be opened or set up.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+/// * `V4L2R_ERROR_INTERNAL` if the encoder cannot be set up for another
+///   reason.
+///
+/// # Safety
+/// The passed `path` must be a valid, zero-terminated C string containining the
+/// path to the device. Expect a crash if passing an invalid string.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_encoder_new(
+    path: *const c_char,
+    input_format_fourcc: u32,
+    width: u32,
+    height: u32,
+    output_format_fourcc: u32,
+    num_input_buffers: usize,
+    num_output_buffers: usize,
+    chunk_cb: v4l2r_encoder_chunk_cb,
+    cb_data: *mut c_void,
+) -> *mut v4l2r_encoder {
+    let path = match c_path(path) {
+        Ok(path) => path,
+        Err(_) => return std::ptr::null_mut(),
+    };
+
+    v4l2r_encoder_new_safe(
+        path,
+        input_format_fourcc,
+        width,
+        height,
+        output_format_fourcc,
+        num_input_buffers,
+        num_output_buffers,
+        chunk_cb,
+        cb_data,
+    )
+}
+
+/// Stop and destroy an encoder.
+///
+/// Stop `encoder` and destroy it. This function DOES take ownership of
+/// `encoder`, which must absolutely not be used after this call. Frames that
+/// have not been encoded yet are passed to the chunk callback before this
+/// function returns.
+///
+/// It is guaranteed that the chunk callback passed to [`v4l2r_encoder_new`]
+/// will not be called after this function has returned.
+///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes. `encoder` is
+/// destroyed even if an error is returned.
+///
+/// * `V4L2R_ERROR_ERRNO` if the encoder cannot be stopped.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+/// * `V4L2R_ERROR_INTERNAL` if the encoder thread did not stop properly.
+///
+/// # Safety
+///
+/// `encoder` must be a valid pointer to an encoder returned by
+/// `v4l2r_encoder_new`. Passing a NULL or invalid pointer will cause a crash.
+/// `encoder` must not be used again after this function is called.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_encoder_destroy(encoder: *mut v4l2r_encoder) -> v4l2r_error_code {
+    info!("Encoder {:p}: destroying", encoder);
+
+    if encoder.is_null() {
+        warn!("Trying to destroy a NULL encoder");
+        return v4l2r_error_code::V4L2R_SUCCESS;
+    }
+
+    let encoder = Box::from_raw(encoder);
+    match encoder.encoder.map(|encoder| encoder.stop()) {
+        None | Some(Ok(_)) => v4l2r_error_code::V4L2R_SUCCESS,
+        Some(Err(e)) => report(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            "Error while stopping encoder",
+            &e,
+        ),
+    }
+}
+
+/// Encode a raw frame.
+///
+/// `planes` is an array of `num_planes` pointers to the data of each plane of
+/// the frame, and `plane_sizes` the array of their respective sizes in bytes.
+/// If the input format uses a single memory plane, the planes are copied one
+/// after the other into it. The data is copied before this function returns,
+/// so the client can reuse its memory immediately.
+///
+/// `timestamp` is an arbitrary value, typically the presentation time of the
+/// frame in microseconds, that will be passed along with the corresponding
+/// encoded chunk.
+///
+/// This function blocks until the encoder has a free input buffer.
+///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes:
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if the planes do not match the input
+///   format, or the encoder has stopped after a failed drain.
+/// * `V4L2R_ERROR_ERRNO` if no input buffer can be obtained or queued.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+/// * `V4L2R_ERROR_INTERNAL` if the input buffer cannot be mapped.
+///
+/// # Safety
+///
+/// `encoder` must be a valid pointer to an encoder returned by
+/// [`v4l2r_encoder_new`]. Passing a NULL or invalid pointer will cause a crash.
+/// `planes` and `plane_sizes` must point to arrays of `num_planes` elements,
+/// and each pointer of `planes` to at least as many bytes as specified in
+/// `plane_sizes`.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_encoder_encode(
+    encoder: *mut v4l2r_encoder,
+    planes: *const *const u8,
+    plane_sizes: *const usize,
+    num_planes: usize,
+    timestamp: u64,
+) -> v4l2r_error_code {
+    debug!("Encoder {:p}: encoding frame {}", encoder, timestamp);
+    assert!(!encoder.is_null());
+    assert!(!planes.is_null());
+    assert!(!plane_sizes.is_null());
+    let encoder = &mut *encoder;
+
+    let planes = slice::from_raw_parts(planes, num_planes)
+        .iter()
+        .zip(slice::from_raw_parts(plane_sizes, num_planes))
+        .map(|(&data, &size)| slice::from_raw_parts(data, size))
+        .collect::<Vec<_>>();
+
+    v4l2r_encoder_encode_safe(encoder, &planes, timestamp)
+}
+
+/// Wait until all the frames submitted so far are encoded.
+///
+/// All the encoded chunks for these frames are passed to the chunk callback
+/// before this function returns, after which the encoder is ready to encode
+/// new frames.
+///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes. If an error
+/// occurred, the encoder cannot be used anymore and must be destroyed.
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if the encoder has stopped after a
+///   previous failed drain.
+/// * `V4L2R_ERROR_ERRNO` if the encoder cannot be stopped or restarted.
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+/// * `V4L2R_ERROR_INTERNAL` if the encoder thread did not stop properly.
+///
+/// # Safety
+///
+/// `encoder` must be a valid pointer to an encoder returned by
+/// [`v4l2r_encoder_new`]. Passing a NULL or invalid pointer will cause a crash.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_encoder_drain(encoder: *mut v4l2r_encoder) -> v4l2r_error_code {
+    assert!(!encoder.is_null());
+    let encoder = &mut *encoder;
+
+    v4l2r_encoder_drain_safe(encoder)
+}
+
+/// Ask the encoder to produce a key frame as soon as possible.
+///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes:
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if the encoder has stopped after a failed
+///   drain.
+/// * `V4L2R_ERROR_ERRNO` if the control cannot be set, e.g. because the
+///   encoder does not support this feature (`EINVAL`).
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+///
+/// # Safety
+///
+/// `encoder` must be a valid pointer to an encoder returned by
+/// [`v4l2r_encoder_new`]. Passing a NULL or invalid pointer will cause a crash.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_encoder_force_keyframe(
+    encoder: *const v4l2r_encoder,
+) -> v4l2r_error_code {
+    assert!(!encoder.is_null());
+    let encoder = &*encoder;
+
+    match encoder.encoder.as_ref().map(|e| e.force_keyframe()) {
+        Some(Ok(())) => v4l2r_error_code::V4L2R_SUCCESS,
+        Some(Err(e)) => report_errno(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            "Error while forcing key frame",
+            e,
+        ),
+        None => report_msg(
+            v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+            "Encoder is not running",
+        ),
+    }
+}
+
+/// Set the target bitrate of the encoder, in bits per second.
+///
+/// Returns `V4L2R_SUCCESS`, or one of the following error codes:
+///
+/// * `V4L2R_ERROR_INVALID_ARGUMENT` if the encoder has stopped after a failed
+///   drain.
+/// * `V4L2R_ERROR_ERRNO` if the control cannot be set, e.g. because the
+///   encoder does not support this feature (`EINVAL`).
+/// * `V4L2R_ERROR_DEVICE_LOST` if the device has been disconnected.
+///
+/// # Safety
+///
+/// `encoder` must be a valid pointer to an encoder returned by
+/// [`v4l2r_encoder_new`]. Passing a NULL or invalid pointer will cause a crash.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_encoder_set_bitrate(
+    encoder: *const v4l2r_encoder,
+    bitrate: u32,
+) -> v4l2r_error_code {
+    assert!(!encoder.is_null());
+    let encoder = &*encoder;
+
+    match encoder.encoder.as_ref().map(|e| e.set_bitrate(bitrate)) {
+        Some(Ok(())) => v4l2r_error_code::V4L2R_SUCCESS,
+        Some(Err(e)) => report_errno(
+            v4l2r_error_code::V4L2R_ERROR_ERRNO,
+            "Error while setting bitrate",
+            e,
+        ),
+        None => report_msg(
+            v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+            "Encoder is not running",
+        ),
+    }
+}
@@ -0,0 +1,239 @@
+//! Module for reporting errors to the client.
+//!
+//! Functions of the C API that can fail return a [`v4l2r_error_code`], or a
+//! negative one if they otherwise return a count or an index. Functions that
+//! return a pointer return NULL on failure.
+//!
+//! Every failure is also recorded as the last error of the calling thread,
+//! along with a message rendering the whole chain of errors that caused it.
+//! This message is retrieved using [`v4l2r_last_error_msg`] and is meant for
+//! logging and debugging, whereas the error code is meant to let the client
+//! decide how to react. Successful calls do not clear the last error.
+//!
+//! Errors occurring on the threads of the library that prevent a decoder from
+//! working are reported through its events callback, as described in
+//! `v4l2r_decoder_event`. Other errors occurring on these threads, e.g. while
+//! processing a format change, are only logged.
+#![allow(non_camel_case_types)]
+
+use log::error;
+use nix::errno::Errno;
+use std::{
+    cell::RefCell,
+    error::Error,
+    fmt::Display,
+    io,
+    os::raw::{c_char, c_int},
+    slice,
+};
+
+use crate::copy_str;
+
+/// Error codes returned by the functions of the C API.
+///
+/// The values of these codes are stable and will not change in future
+/// versions.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms, clippy::enum_variant_names)]
+pub enum v4l2r_error_code {
+    /// The operation succeeded.
+    V4L2R_SUCCESS = 0,
+    /// An argument is invalid, or the object the function has been called on
+    /// is not in a state allowing the operation.
+    V4L2R_ERROR_INVALID_ARGUMENT = -1,
+    /// The device node does not exist, or is not a device of the expected
+    /// kind.
+    V4L2R_ERROR_DEVICE_NOT_FOUND = -2,
+    /// The device does not support the requested format or frame size.
+    V4L2R_ERROR_UNSUPPORTED_FORMAT = -3,
+    /// A drain did not complete in time. Reserved for drain operations with a
+    /// deadline, which none of the current functions are.
+    V4L2R_ERROR_DRAIN_TIMEOUT = -4,
+    /// The device has been disconnected and can only be destroyed.
+    V4L2R_ERROR_DEVICE_LOST = -5,
+    /// A system call failed. The errno it returned is obtained using
+    /// [`v4l2r_last_errno`].
+    V4L2R_ERROR_ERRNO = -6,
+    /// An error that does not fit any other category occurred, e.g. a thread
+    /// of the library has stopped unexpectedly.
+    V4L2R_ERROR_INTERNAL = -7,
+}
+
+use v4l2r_error_code::*;
+
+struct LastError {
+    code: v4l2r_error_code,
+    errno: Option<Errno>,
+    msg: String,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<LastError>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(code: v4l2r_error_code, errno: Option<Errno>, msg: String) -> v4l2r_error_code {
+    error!("{}", msg);
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(LastError { code, errno, msg }));
+
+    code
+}
+
+/// Returns the first errno found in the chain of `err`.
+fn find_errno(err: &(dyn Error + 'static)) -> Option<Errno> {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(errno) = err.downcast_ref::<Errno>() {
+            return Some(*errno);
+        }
+        if let Some(raw) = err
+            .downcast_ref::<io::Error>()
+            .and_then(io::Error::raw_os_error)
+        {
+            return Some(Errno::from_raw(raw));
+        }
+        source = err.source();
+    }
+
+    None
+}
+
+/// Render `err` and its sources as a single string. Sources which message is
+/// already part of the message of the error they caused are skipped.
+fn error_chain(err: &(dyn Error + 'static)) -> String {
+    let mut msg = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        let source_msg = err.to_string();
+        if !msg.ends_with(&source_msg) {
+            msg.push_str(": ");
+            msg.push_str(&source_msg);
+        }
+        source = err.source();
+    }
+
+    msg
+}
+
+/// Record a failure described by `msg` as the last error of the calling
+/// thread, and return `code`.
+pub(crate) fn report_msg(code: v4l2r_error_code, msg: impl Display) -> v4l2r_error_code {
+    set_last_error(code, None, msg.to_string())
+}
+
+/// Same as [`report_msg`], for a failure caused by a system call that returned
+/// `errno`, if any.
+pub(crate) fn report_msg_errno(
+    code: v4l2r_error_code,
+    errno: Option<Errno>,
+    msg: impl Display,
+) -> v4l2r_error_code {
+    set_last_error(classify(code, errno), errno, msg.to_string())
+}
+
+/// Returns the code to report for an error of class `code`, refined using the
+/// `errno` that caused it, if any.
+///
+/// An `ENODEV` errno means that the device is lost, and `V4L2R_ERROR_ERRNO`
+/// becomes `V4L2R_ERROR_INTERNAL` if there is no errno. Errors opening a
+/// device are only reported as `V4L2R_ERROR_DEVICE_NOT_FOUND` if the device
+/// does not exist.
+fn classify(code: v4l2r_error_code, errno: Option<Errno>) -> v4l2r_error_code {
+    match (code, errno) {
+        (V4L2R_ERROR_DEVICE_NOT_FOUND, Some(Errno::ENOENT | Errno::ENODEV | Errno::ENXIO)) => {
+            V4L2R_ERROR_DEVICE_NOT_FOUND
+        }
+        (V4L2R_ERROR_DEVICE_NOT_FOUND, Some(_)) => V4L2R_ERROR_ERRNO,
+        (_, Some(Errno::ENODEV)) => V4L2R_ERROR_DEVICE_LOST,
+        (V4L2R_ERROR_ERRNO, None) => V4L2R_ERROR_INTERNAL,
+        (code, _) => code,
+    }
+}
+
+/// Record `err`, which happened while `context`, as the last error of the
+/// calling thread and return its code, which is `code` refined using the
+/// errno found in the chain of `err`, if any.
+pub(crate) fn report(
+    code: v4l2r_error_code,
+    context: impl Display,
+    err: &(dyn Error + 'static),
+) -> v4l2r_error_code {
+    let errno = find_errno(err);
+
+    set_last_error(
+        classify(code, errno),
+        errno,
+        format!("{}: {}", context, error_chain(err)),
+    )
+}
+
+/// Same as [`report`], for errors that can be converted into the errno that
+/// caused them.
+pub(crate) fn report_errno<E>(
+    code: v4l2r_error_code,
+    context: impl Display,
+    err: E,
+) -> v4l2r_error_code
+where
+    E: Error + Into<Errno> + 'static,
+{
+    let msg = format!("{}: {}", context, error_chain(&err));
+    let errno = err.into();
+
+    set_last_error(classify(code, Some(errno)), Some(errno), msg)
+}
+
+/// Returns the code of the last error that occurred on the calling thread, or
+/// `V4L2R_SUCCESS` if no error occurred yet.
+#[no_mangle]
+pub extern "C" fn v4l2r_last_error_code() -> v4l2r_error_code {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map(|last| last.code)
+            .unwrap_or(V4L2R_SUCCESS)
+    })
+}
+
+/// Returns the errno of the system call that caused the last error of the
+/// calling thread, or 0 if that error was not caused by a failed system call.
+#[no_mangle]
+pub extern "C" fn v4l2r_last_errno() -> c_int {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .and_then(|last| last.errno)
+            .map(|errno| errno as c_int)
+            .unwrap_or(0)
+    })
+}
+
+/// Copy the message describing the last error of the calling thread into
+/// `buf`.
+///
+/// The message is truncated if needed so it fits into the `len` bytes of
+/// `buf`, and is always NUL-terminated. An empty string is written if no error
+/// occurred yet.
+///
+/// Returns the length of the whole message, not including the terminating
+/// NUL. A return value of `len` or more means that the message has been
+/// truncated. Passing a NULL `buf` and a `len` of 0 is a valid way to query
+/// the size of the buffer to allocate.
+///
+/// # Safety
+///
+/// `buf` must either be NULL, or point to a valid array of at least `len`
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_last_error_msg(buf: *mut c_char, len: usize) -> usize {
+    LAST_ERROR.with(|last| {
+        let last = last.borrow();
+        let msg = last.as_ref().map(|last| last.msg.as_str()).unwrap_or("");
+
+        if !buf.is_null() {
+            copy_str(slice::from_raw_parts_mut(buf, len), msg);
+        }
+
+        msg.len()
+    })
+}
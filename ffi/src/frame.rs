@@ -0,0 +1,242 @@
+//! Decoded frames exported as DMABUFs.
+//!
+//! Decoders created using [`v4l2r_decoder_new_exported`] allocate their own
+//! frames and pass them to the client as [`v4l2r_frame`]s through the
+//! [`v4l2r_decoder_event::FrameExported`] event. Each frame remains owned by
+//! the client, and is not reused by the decoder, until it is given back using
+//! [`v4l2r_frame_release`].
+//!
+//! The DMABUF FDs of a frame are owned by the library and are only valid
+//! until the frame is released. A client that needs to keep an FD past that
+//! point (e.g. because it has been imported into a graphics API that does not
+//! take a reference) must `dup` it.
+//!
+//! [`v4l2r_decoder_new_exported`]: crate::decoder::v4l2r_decoder_new_exported
+//! [`v4l2r_decoder_event::FrameExported`]: crate::decoder::v4l2r_decoder_event::FrameExported
+#![allow(non_camel_case_types)]
+
+use log::{error, trace};
+use std::{
+    collections::BTreeMap,
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        raw::c_int,
+    },
+    sync::Arc,
+};
+use v4l2r::{
+    bindings,
+    device::{
+        queue::{direction::Capture, dqbuf::DqBuffer},
+        Device,
+    },
+    ioctl::{self, ExpbufFlags},
+    memory::MmapHandle,
+    Format, QueueType, Rect,
+};
+
+use crate::error::{report_msg, v4l2r_error_code};
+
+/// Maximum number of planes of a [`v4l2r_frame`].
+pub const V4L2R_FRAME_MAX_PLANES: usize = 4;
+
+/// A decoded frame, exported as one DMABUF per plane.
+#[repr(C)]
+pub struct v4l2r_frame {
+    /// Identifier of the input buffer this frame has been decoded from, as
+    /// passed to [`v4l2r_decoder_decode`].
+    ///
+    /// [`v4l2r_decoder_decode`]: crate::decoder::v4l2r_decoder_decode
+    pub bitstream_id: i32,
+    /// FOURCC code of the pixel format of the frame.
+    pub fourcc: u32,
+    /// Coded width of the frame.
+    pub width: u32,
+    /// Coded height of the frame.
+    pub height: u32,
+    /// Part of the frame that is meant to be displayed.
+    pub visible_rect: bindings::v4l2_rect,
+    /// Number of valid entries in `fds`, `offsets`, `strides` and `sizes`.
+    pub num_planes: usize,
+    /// DMABUF FDs of the planes. They remain owned by the library and are only
+    /// valid until the frame is passed to [`v4l2r_frame_release`].
+    pub fds: [c_int; V4L2R_FRAME_MAX_PLANES],
+    /// Offset of the data of each plane within its DMABUF.
+    pub offsets: [u32; V4L2R_FRAME_MAX_PLANES],
+    /// Bytes per line of each plane.
+    pub strides: [u32; V4L2R_FRAME_MAX_PLANES],
+    /// Size of the data of each plane, starting at its offset.
+    pub sizes: [u32; V4L2R_FRAME_MAX_PLANES],
+}
+
+/// A frame passed to the client, along with the resources that must remain
+/// alive until the client releases it.
+#[repr(C)]
+pub(crate) struct ExportedFrame {
+    // Must remain the first member, so a pointer to the `v4l2r_frame` is also a
+    // pointer to the `ExportedFrame`.
+    frame: v4l2r_frame,
+    // FDs referenced by `frame`.
+    _fds: Arc<Vec<OwnedFd>>,
+    // CAPTURE buffer holding the frame, which is recycled as it is dropped.
+    _buffer: DqBuffer<Capture, Vec<MmapHandle>>,
+}
+
+/// DMABUFs exported from the CAPTURE buffers of a decoder, and format of the
+/// frames they contain.
+pub(crate) struct ExportedBuffers {
+    device: Arc<Device>,
+    format: Option<(Format, Rect)>,
+    // Exported FDs of the planes of each CAPTURE buffer, by buffer index.
+    fds: BTreeMap<u32, Arc<Vec<OwnedFd>>>,
+}
+
+impl ExportedBuffers {
+    pub(crate) fn new(device: Arc<Device>) -> Self {
+        Self {
+            device,
+            format: None,
+            fds: Default::default(),
+        }
+    }
+
+    /// Set the format of the frames to come.
+    ///
+    /// The CAPTURE buffers are about to be reallocated, so their exported FDs
+    /// are forgotten. Frames still held by the client keep their own reference
+    /// to them.
+    pub(crate) fn set_format(&mut self, format: Format, visible_rect: Rect) {
+        self.format = Some((format, visible_rect));
+        self.fds.clear();
+    }
+
+    /// Returns the FDs of the planes of CAPTURE buffer `index`, exporting them
+    /// if this is the first time the buffer is used.
+    fn fds(
+        &mut self,
+        index: u32,
+        num_planes: usize,
+    ) -> Result<Arc<Vec<OwnedFd>>, ioctl::ExpbufError> {
+        if let Some(fds) = self.fds.get(&index) {
+            return Ok(Arc::clone(fds));
+        }
+
+        let fds = (0..num_planes)
+            .map(|plane| {
+                ioctl::expbuf::<OwnedFd>(
+                    self.device.as_ref(),
+                    QueueType::VideoCaptureMplane,
+                    index as usize,
+                    plane,
+                    ExpbufFlags::CLOEXEC | ExpbufFlags::RDONLY,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let fds = Arc::new(fds);
+        self.fds.insert(index, Arc::clone(&fds));
+
+        Ok(fds)
+    }
+
+    /// Turn `buffer` into a frame to be passed to the client.
+    ///
+    /// Returns `None`, thus recycling `buffer`, if its planes cannot be
+    /// exported.
+    pub(crate) fn export(
+        &mut self,
+        buffer: DqBuffer<Capture, Vec<MmapHandle>>,
+    ) -> Option<Box<ExportedFrame>> {
+        let (format, visible_rect) = match &self.format {
+            Some((format, visible_rect)) => (format.clone(), *visible_rect),
+            None => {
+                error!("Frame decoded before the format is known");
+                return None;
+            }
+        };
+
+        let index = buffer.data.index();
+        let num_planes = buffer.data.num_planes();
+        if num_planes > V4L2R_FRAME_MAX_PLANES {
+            error!("Frames with {} planes are not supported", num_planes);
+            return None;
+        }
+
+        let fds = match self.fds(index, num_planes) {
+            Ok(fds) => fds,
+            Err(e) => {
+                error!("Failed to export CAPTURE buffer {}: {}", index, e);
+                return None;
+            }
+        };
+
+        let mut frame = v4l2r_frame {
+            bitstream_id: buffer.data.timestamp().tv_sec as i32,
+            fourcc: format.pixelformat.into(),
+            width: format.width,
+            height: format.height,
+            visible_rect: visible_rect.into(),
+            num_planes,
+            fds: [-1; V4L2R_FRAME_MAX_PLANES],
+            offsets: [0; V4L2R_FRAME_MAX_PLANES],
+            strides: [0; V4L2R_FRAME_MAX_PLANES],
+            sizes: [0; V4L2R_FRAME_MAX_PLANES],
+        };
+        for (i, plane) in buffer.data.planes().enumerate() {
+            let offset = plane.data_offset;
+            frame.fds[i] = fds[i].as_raw_fd();
+            frame.offsets[i] = offset;
+            frame.strides[i] = format
+                .plane_fmt
+                .get(i)
+                .map(|plane_fmt| plane_fmt.bytesperline)
+                .unwrap_or(0);
+            frame.sizes[i] = plane.data_range().len() as u32;
+        }
+        trace!("Exported CAPTURE buffer {} with fds {:?}", index, frame.fds);
+
+        Some(Box::new(ExportedFrame {
+            frame,
+            _fds: fds,
+            _buffer: buffer,
+        }))
+    }
+}
+
+/// Return a pointer to the [`v4l2r_frame`] of `frame`, transferring its
+/// ownership to the client.
+pub(crate) fn into_client_frame(frame: Box<ExportedFrame>) -> *const v4l2r_frame {
+    Box::into_raw(frame) as *const v4l2r_frame
+}
+
+/// Release `frame`, allowing the decoder to decode into it again.
+///
+/// The DMABUF FDs of `frame` are invalid after this call. This function can
+/// safely be called from any thread, including after the decoder that
+/// produced `frame` has been destroyed.
+///
+/// Returns `V4L2R_SUCCESS`, or `V4L2R_ERROR_INVALID_ARGUMENT` if `frame` is
+/// NULL.
+///
+/// # Safety
+///
+/// `frame` must be a frame received through a
+/// [`v4l2r_decoder_event::FrameExported`] event, that has not been released
+/// yet. It must not be used after this call.
+///
+/// [`v4l2r_decoder_event::FrameExported`]: crate::decoder::v4l2r_decoder_event::FrameExported
+#[no_mangle]
+pub unsafe extern "C" fn v4l2r_frame_release(frame: *const v4l2r_frame) -> v4l2r_error_code {
+    trace!("Releasing frame {:p}", frame);
+    if frame.is_null() {
+        return report_msg(
+            v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+            "Trying to release a NULL frame",
+        );
+    }
+
+    // SAFETY: `frame` is the first member of an `ExportedFrame` obtained from
+    // `into_client_frame`.
+    drop(Box::from_raw(frame as *mut ExportedFrame));
+
+    v4l2r_error_code::V4L2R_SUCCESS
+}
@@ -2,13 +2,75 @@
 //!
 //! This crate provides a C API that can be used by client programs to make use
 //! of the features exported by this crate. For now it strictly focuses on
-//! stateful decoders.
+//! stateful decoders and encoders, the discovery of the devices and formats
+//! they can be created from, and the controls of these devices.
+//!
+//! Failures are reported using the stable error codes of the
+//! [`error`](crate::error) module, which also keeps a message describing the
+//! last error of each thread.
 
 use log::debug;
+use std::{ffi::CStr, os::raw::c_char, path::Path};
+
+use error::{report, report_msg, v4l2r_error_code};
 
+pub mod control;
 pub mod decoder;
+pub mod device;
+pub mod encoder;
+pub mod error;
+pub mod frame;
 pub mod memory;
 
+// A void pointer that can be sent across threads. This is usually not allowed
+// by Rust, but is necessary for us to call back into the C client.
+struct SendablePtr<T>(*mut T);
+impl<T> Clone for SendablePtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for SendablePtr<T> {}
+unsafe impl<T> Send for SendablePtr<T> {}
+unsafe impl<T> Sync for SendablePtr<T> {}
+
+/// Copy `src` into `dst`, truncating it if needed so it is always
+/// NUL-terminated.
+fn copy_str(dst: &mut [c_char], src: &str) {
+    let len = src.len().min(dst.len().saturating_sub(1));
+    for (d, s) in dst.iter_mut().zip(&src.as_bytes()[..len]) {
+        *d = *s as c_char;
+    }
+    if let Some(d) = dst.get_mut(len) {
+        *d = 0;
+    }
+}
+
+/// Returns the path pointed to by `path`, reporting an error if it is NULL or
+/// not valid UTF-8.
+///
+/// # Safety
+///
+/// `path` must either be NULL, or a valid pointer to a NUL-terminated C
+/// string that outlives the returned path.
+unsafe fn c_path<'a>(path: *const c_char) -> Result<&'a Path, v4l2r_error_code> {
+    if path.is_null() {
+        return Err(report_msg(
+            v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+            "Device path is NULL",
+        ));
+    }
+
+    match CStr::from_ptr(path).to_str() {
+        Ok(path) => Ok(Path::new(path)),
+        Err(e) => Err(report(
+            v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+            "Failed to convert device path to string",
+            &e,
+        )),
+    }
+}
+
 static INIT: std::sync::Once = std::sync::Once::new();
 
 /// Initialize the V4L2R library. This only sets up the proper hooks for
@@ -23,6 +23,8 @@ use v4l2r::{
     memory::{BufferHandles, DmaBufHandle, DmaBufSource, MemoryType, PrimitiveBufferHandles},
 };
 
+use crate::error::{report_msg, v4l2r_error_code};
+
 /// The simplest type used to represent a DMABUF fd. It does not take ownership
 /// of the FD at any time and does not close it ; thus the using code is
 /// responsible for managing the given FD's lifetime.
@@ -191,8 +193,8 @@ impl HandlesProvider for v4l2r_video_frame_provider {
 /// will remain untouched by the decoder until the client passes it to this
 /// function again.
 ///
-/// Returns `true` upon success, `false` if the provided frame had an invalid
-/// index or the decoder thread could not be awakened.
+/// Returns `V4L2R_SUCCESS`, or `V4L2R_ERROR_INVALID_ARGUMENT` if the provided
+/// frame has an invalid index.
 ///
 /// This function can safely be called from any thread.
 ///
@@ -205,14 +207,16 @@ impl HandlesProvider for v4l2r_video_frame_provider {
 pub unsafe extern "C" fn v4l2r_video_frame_provider_queue_frame(
     provider: *const v4l2r_video_frame_provider,
     frame: v4l2r_video_frame,
-) -> bool {
+) -> v4l2r_error_code {
     trace!("Queueing output frame: {:?}", frame);
     assert!(!provider.is_null());
     let provider = &*provider;
 
     if frame.id >= bindings::VIDEO_MAX_FRAME {
-        error!("Invalid frame id {}, aborting queue.", frame.id);
-        return false;
+        return report_msg(
+            v4l2r_error_code::V4L2R_ERROR_INVALID_ARGUMENT,
+            format!("Invalid frame id {}, aborting queue.", frame.id),
+        );
     }
 
     let mut provider = provider.d.lock().unwrap();
@@ -220,7 +224,8 @@ pub unsafe extern "C" fn v4l2r_video_frame_provider_queue_frame(
     if let Some(waker) = provider.waker.take() {
         waker.wake_by_ref();
     }
-    true
+
+    v4l2r_error_code::V4L2R_SUCCESS
 }
 
 /// Delete a video frame provider.
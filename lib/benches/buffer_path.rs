@@ -0,0 +1,402 @@
+//! Benchmarks of the overhead added by v4l2r on the buffer path.
+//!
+//! Each benchmark group compares the v4l2r API (`v4l2r`) with the equivalent raw ioctls issued
+//! directly on the device (`raw`), so the difference between both is the cost of v4l2r's
+//! bookkeeping. The benchmarks require actual devices, which are passed using environment
+//! variables. Groups whose device is not specified or not present are skipped:
+//!
+//! * `V4L2R_BENCH_VIVID`: path to a `vivid` capture node, used for the QBUF/DQBUF round-trip,
+//!   plane copy and control benchmarks.
+//! * `V4L2R_BENCH_VICODEC_DEC`: path to a `vicodec` stateful decoder node, and
+//!   `V4L2R_BENCH_FWHT_STREAM`: path to a FWHT stream, used for the decoder throughput
+//!   benchmark.
+//!
+//! ```text
+//! V4L2R_BENCH_VIVID=/dev/video0 cargo bench -p v4l2r --bench buffer_path
+//! ```
+//!
+//! In order to catch regressions, record a baseline before a change with
+//! `cargo bench --bench buffer_path -- --save-baseline before`, and compare to it afterwards with
+//! `cargo bench --bench buffer_path -- --baseline before`.
+use std::fs::File;
+use std::io::BufReader;
+use std::os::fd::{AsFd, AsRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nix::poll::{PollFd, PollFlags, PollTimeout};
+use v4l2r::bindings::{self, v4l2_ext_control, v4l2_ext_controls};
+use v4l2r::controls::user::{Brightness, Contrast};
+use v4l2r::controls::{AsV4l2ControlSlice, SafeExtControl};
+use v4l2r::decoder::format::fwht::FwhtFrameParser;
+use v4l2r::decoder::stateful::Decoder;
+use v4l2r::decoder::{DecoderEvent, FormatChangedReply};
+use v4l2r::device::queue::direction::Capture;
+use v4l2r::device::queue::handles_provider::MmapProvider;
+use v4l2r::device::queue::{BuffersAllocated, GetFreeCaptureBuffer, Queue, QueueInit};
+use v4l2r::device::{Device, DeviceConfig, Stream, TryDequeue};
+use v4l2r::ioctl::{self, CtrlWhich, UncheckedV4l2Buffer};
+use v4l2r::memory::{MemoryType, MmapHandle};
+use v4l2r::{Format, PixelFormat, PlaneLayout, QueueType};
+
+/// Raw ioctls used as a baseline.
+mod raw {
+    use v4l2r::bindings::{v4l2_buffer, v4l2_ext_controls};
+
+    nix::ioctl_readwrite!(vidioc_qbuf, b'V', 15, v4l2_buffer);
+    nix::ioctl_readwrite!(vidioc_dqbuf, b'V', 17, v4l2_buffer);
+    nix::ioctl_readwrite!(vidioc_g_ext_ctrls, b'V', 71, v4l2_ext_controls);
+    nix::ioctl_readwrite!(vidioc_s_ext_ctrls, b'V', 72, v4l2_ext_controls);
+}
+
+const NUM_BUFFERS: u32 = 4;
+
+/// Returns the path of the device given by the environment variable `var`, or `None` if it is not
+/// set or does not exist.
+fn device_from_env(var: &str) -> Option<PathBuf> {
+    let Some(path) = std::env::var_os(var).map(PathBuf::from) else {
+        eprintln!("{} is not set, skipping", var);
+        return None;
+    };
+    if !path.exists() {
+        eprintln!("{} does not exist, skipping", path.display());
+        return None;
+    }
+
+    Some(path)
+}
+
+fn open_device(path: &Path) -> Arc<Device> {
+    Arc::new(Device::open(path, DeviceConfig::new()).expect("Failed to open device"))
+}
+
+/// Returns the capture queue of `device`, using the multi-planar API if available.
+fn capture_queue(device: &Arc<Device>) -> Queue<Capture, QueueInit> {
+    Queue::get_capture_mplane_queue(Arc::clone(device))
+        .or_else(|_| Queue::get_capture_queue(Arc::clone(device)))
+        .expect("Failed to obtain capture queue")
+}
+
+/// Waits until `fd` has a buffer ready to be dequeued.
+fn wait_ready(fd: &impl AsFd) {
+    let mut fds = [PollFd::new(fd.as_fd(), PollFlags::POLLIN)];
+    let timeout = PollTimeout::try_from(Duration::from_secs(1)).unwrap();
+    match nix::poll::poll(&mut fds, timeout) {
+        Ok(0) => panic!("Timeout while waiting for a buffer"),
+        Ok(_) => (),
+        Err(e) => panic!("Error while waiting for a buffer: {}", e),
+    }
+}
+
+/// Allocates MMAP capture buffers of `width`x`height` on `device` and starts streaming.
+fn start_capture(
+    device: &Arc<Device>,
+    width: usize,
+    height: usize,
+) -> Queue<Capture, BuffersAllocated<Vec<MmapHandle>>> {
+    let mut queue = capture_queue(device);
+    let _: Format = queue
+        .change_format()
+        .expect("Failed to get capture format")
+        .set_size(width, height)
+        .set_pixelformat(PixelFormat::from(b"YUYV"))
+        .apply()
+        .expect("Failed to set capture format");
+    let queue = queue
+        .request_buffers::<Vec<MmapHandle>>(NUM_BUFFERS)
+        .expect("Failed to allocate capture buffers");
+    queue.stream_on().expect("Failed to start capture queue");
+
+    queue
+}
+
+/// Allocates MMAP capture buffers on `device` using raw ioctls and starts streaming, returning
+/// the type of the queue.
+fn start_capture_raw(device: &Device) -> QueueType {
+    let queue = capture_queue_type(device);
+    let _: bindings::v4l2_requestbuffers =
+        ioctl::reqbufs(device, queue, MemoryType::Mmap, NUM_BUFFERS)
+            .expect("Failed to allocate capture buffers");
+    ioctl::streamon(device, queue).expect("Failed to start capture queue");
+
+    queue
+}
+
+fn capture_queue_type(device: &Device) -> QueueType {
+    if device
+        .caps()
+        .device_caps()
+        .contains(ioctl::Capabilities::VIDEO_CAPTURE_MPLANE)
+    {
+        QueueType::VideoCaptureMplane
+    } else {
+        QueueType::VideoCapture
+    }
+}
+
+fn raw_buffer(queue: QueueType, index: u32) -> UncheckedV4l2Buffer {
+    let mut buffer = UncheckedV4l2Buffer::new_for_querybuf(queue, Some(index));
+    buffer.0.memory = MemoryType::Mmap as u32;
+    buffer
+}
+
+/// QBUF followed by DQBUF of a capture buffer. Only the time spent in both calls is measured, not
+/// the time spent waiting for the device to fill the buffer.
+fn bench_qbuf_dqbuf(c: &mut Criterion) {
+    let Some(path) = device_from_env("V4L2R_BENCH_VIVID") else {
+        return;
+    };
+    let mut group = c.benchmark_group("qbuf_dqbuf");
+
+    {
+        let device = open_device(&path);
+        let queue = start_capture(&device, 640, 480);
+        group.bench_function("v4l2r", |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    queue
+                        .try_get_free_buffer()
+                        .expect("No free buffer")
+                        .queue()
+                        .expect("Failed to queue buffer");
+                    elapsed += start.elapsed();
+
+                    wait_ready(&*device);
+
+                    let start = Instant::now();
+                    let dqbuf = queue.try_dequeue().expect("Failed to dequeue buffer");
+                    drop(dqbuf);
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+        queue.stream_off().expect("Failed to stop capture queue");
+    }
+
+    {
+        let device = open_device(&path);
+        let queue = start_capture_raw(&device);
+        let fd = device.as_raw_fd();
+        group.bench_function("raw", |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    let mut buffer = raw_buffer(queue, 0);
+                    let start = Instant::now();
+                    unsafe { raw::vidioc_qbuf(fd, buffer.as_mut()) }
+                        .expect("Failed to queue buffer");
+                    elapsed += start.elapsed();
+
+                    wait_ready(&*device);
+
+                    let mut buffer = raw_buffer(queue, 0);
+                    let start = Instant::now();
+                    unsafe { raw::vidioc_dqbuf(fd, buffer.as_mut()) }
+                        .expect("Failed to dequeue buffer");
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+        ioctl::streamoff(&*device, queue).expect("Failed to stop capture queue");
+    }
+
+    group.finish();
+}
+
+/// Copy of the first plane of a dequeued capture buffer into user memory. v4l2r maps the plane
+/// for every access, whereas the baseline keeps a single mapping around.
+fn bench_plane_copy(c: &mut Criterion) {
+    let Some(path) = device_from_env("V4L2R_BENCH_VIVID") else {
+        return;
+    };
+    let mut group = c.benchmark_group("plane_copy");
+
+    for (name, width, height) in [("1080p", 1920, 1080), ("4k", 3840, 2160)] {
+        let device = open_device(&path);
+        let queue = start_capture(&device, width, height);
+        queue
+            .try_get_free_buffer()
+            .expect("No free buffer")
+            .queue()
+            .expect("Failed to queue buffer");
+        wait_ready(&*device);
+        let dqbuf = queue.try_dequeue().expect("Failed to dequeue buffer");
+        let size = dqbuf.data.plane_bytesused(0).unwrap_or(0) as usize;
+        let mut frame = vec![0u8; size];
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_function(BenchmarkId::new("v4l2r", name), |b| {
+            b.iter(|| {
+                let mapping = dqbuf.get_plane_mapping(0).expect("Failed to map plane");
+                frame.copy_from_slice(&mapping[..size]);
+            })
+        });
+
+        let query: ioctl::QueryBuffer =
+            ioctl::querybuf(&*device, queue.get_type(), dqbuf.data.index() as usize)
+                .expect("Failed to query buffer");
+        let plane = &query.planes[0];
+        let mapping =
+            ioctl::mmap(&*device, plane.mem_offset, plane.length).expect("Failed to map plane");
+        group.bench_function(BenchmarkId::new("raw", name), |b| {
+            b.iter(|| frame.copy_from_slice(&mapping[..size]))
+        });
+
+        drop(mapping);
+        drop(dqbuf);
+        queue.stream_off().expect("Failed to stop capture queue");
+    }
+
+    group.finish();
+}
+
+#[repr(C)]
+struct Controls {
+    brightness: SafeExtControl<Brightness>,
+    contrast: SafeExtControl<Contrast>,
+}
+
+impl AsV4l2ControlSlice for &mut Controls {
+    fn as_v4l2_control_slice(&mut self) -> &mut [v4l2_ext_control] {
+        let ptr = (*self) as *mut Controls as *mut v4l2_ext_control;
+        unsafe { std::slice::from_raw_parts_mut(ptr, 2) }
+    }
+}
+
+/// Setting then getting a batch of two controls.
+fn bench_controls(c: &mut Criterion) {
+    let Some(path) = device_from_env("V4L2R_BENCH_VIVID") else {
+        return;
+    };
+    let device = open_device(&path);
+    let mut group = c.benchmark_group("controls");
+
+    group.bench_function("v4l2r", |b| {
+        b.iter(|| {
+            let mut controls = Controls {
+                brightness: SafeExtControl::<Brightness>::from_value(128),
+                contrast: SafeExtControl::<Contrast>::from_value(128),
+            };
+            ioctl::s_ext_ctrls(&*device, CtrlWhich::Current, &mut controls)
+                .expect("Failed to set controls");
+            ioctl::g_ext_ctrls(&*device, CtrlWhich::Current, &mut controls)
+                .expect("Failed to get controls");
+            controls.brightness.value()
+        })
+    });
+
+    let fd = device.as_raw_fd();
+    group.bench_function("raw", |b| {
+        b.iter(|| {
+            let mut controls = [
+                v4l2_ext_control {
+                    id: bindings::V4L2_CID_BRIGHTNESS,
+                    ..Default::default()
+                },
+                v4l2_ext_control {
+                    id: bindings::V4L2_CID_CONTRAST,
+                    ..Default::default()
+                },
+            ];
+            controls[0].__bindgen_anon_1.value = 128;
+            controls[1].__bindgen_anon_1.value = 128;
+            let mut ext_controls = v4l2_ext_controls {
+                count: controls.len() as u32,
+                controls: controls.as_mut_ptr(),
+                ..Default::default()
+            };
+            ext_controls.__bindgen_anon_1.which = bindings::V4L2_CTRL_WHICH_CUR_VAL;
+            unsafe { raw::vidioc_s_ext_ctrls(fd, &mut ext_controls) }
+                .expect("Failed to set controls");
+            unsafe { raw::vidioc_g_ext_ctrls(fd, &mut ext_controls) }
+                .expect("Failed to get controls");
+            unsafe { controls[0].__bindgen_anon_1.value }
+        })
+    });
+
+    group.finish();
+}
+
+/// Decodes all the `frames` using the decoder at `path`.
+fn decode_stream(path: &Path, frames: &[Vec<u8>]) {
+    let mut decoder = Decoder::open(path)
+        .expect("Failed to open decoder")
+        .set_output_format(|f| {
+            let _: Format = f
+                .set_pixelformat(PixelFormat::from(b"FWHT"))
+                .set_planes_layout(vec![PlaneLayout {
+                    sizeimage: 1024 * 1024,
+                    ..Default::default()
+                }])
+                .apply()?;
+            Ok(())
+        })
+        .expect("Failed to set output format")
+        .allocate_output_buffers::<Vec<MmapHandle>>(NUM_BUFFERS as usize)
+        .expect("Failed to allocate output buffers")
+        .start(
+            |_| (),
+            |_: DecoderEvent<MmapProvider>| (),
+            |f, _, min_num_buffers| {
+                Ok(FormatChangedReply {
+                    provider: MmapProvider::new(f.format()),
+                    mem_type: MemoryType::Mmap,
+                    num_buffers: min_num_buffers,
+                })
+            },
+        )
+        .expect("Failed to start decoder");
+
+    for (i, frame) in frames.iter().enumerate() {
+        let buffer = decoder.get_buffer().expect("Failed to get OUTPUT buffer");
+        let mut mapping = buffer
+            .get_plane_mapping(0)
+            .expect("Failed to map OUTPUT buffer");
+        mapping.as_mut()[..frame.len()].copy_from_slice(frame);
+        drop(mapping);
+        buffer
+            .set_timestamp(nix::sys::time::TimeVal::new(i as i64, 0))
+            .queue(&[frame.len()])
+            .expect("Failed to queue OUTPUT buffer");
+    }
+
+    decoder.drain(true).expect("Failed to drain decoder");
+    decoder.stop().expect("Failed to stop decoder");
+}
+
+/// Decoder throughput, in frames per second, on a FWHT stream.
+fn bench_decoder(c: &mut Criterion) {
+    let Some(path) = device_from_env("V4L2R_BENCH_VICODEC_DEC") else {
+        return;
+    };
+    let Some(stream) = std::env::var_os("V4L2R_BENCH_FWHT_STREAM") else {
+        eprintln!("V4L2R_BENCH_FWHT_STREAM is not set, skipping");
+        return;
+    };
+    let frames: Vec<Vec<u8>> = FwhtFrameParser::new(BufReader::new(
+        File::open(&stream).expect("Failed to open FWHT stream"),
+    ))
+    .expect("No FWHT stream detected")
+    .collect();
+
+    let mut group = c.benchmark_group("decoder");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(frames.len() as u64));
+    group.bench_function("fwht", |b| b.iter(|| decode_stream(&path, &frames)));
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_qbuf_dqbuf,
+    bench_plane_copy,
+    bench_controls,
+    bench_decoder
+);
+criterion_main!(benches);
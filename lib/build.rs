@@ -16,6 +16,9 @@ const WRAPPER_H: &str = "v4l2r_wrapper.h";
 // Fix for https://github.com/rust-lang/rust-bindgen/issues/753
 const FIX753_H: &str = "fix753.h";
 
+/// Environment variables that may point to the Android NDK, in order of preference.
+const ANDROID_NDK_ENVS: &[&str] = &["ANDROID_NDK_HOME", "ANDROID_NDK_ROOT"];
+
 fn print_cc(cc: &Path, args: &[&str]) -> String {
     let out = std::process::Command::new(cc.to_str().expect("utf-8?"))
         .args(args)
@@ -31,11 +34,129 @@ fn print_cc(cc: &Path, args: &[&str]) -> String {
     String::from_utf8(out.stdout).expect("non utf-8?!")
 }
 
-fn main() {
+/// Optional controls, ioctls and flags which availability depends on the kernel headers the bindings
+/// are generated from. Each entry is the `cfg` flag set when the feature is available, along with
+/// the structures or constants it requires.
+const OPTIONAL_CONTROLS: &[(&str, &[&str])] = &[
+    (
+        "v4l2r_has_av1",
+        &[
+            "v4l2_ctrl_av1_sequence",
+            "v4l2_ctrl_av1_tile_group_entry",
+            "v4l2_ctrl_av1_frame",
+            "v4l2_ctrl_av1_film_grain",
+        ],
+    ),
+    (
+        "v4l2r_has_hevc",
+        &[
+            "v4l2_ctrl_hevc_sps",
+            "v4l2_ctrl_hevc_pps",
+            "v4l2_ctrl_hevc_slice_params",
+            "v4l2_ctrl_hevc_decode_params",
+            "v4l2_ctrl_hevc_scaling_matrix",
+        ],
+    ),
+    ("v4l2r_has_vp9", &["v4l2_ctrl_vp9_frame"]),
+    // `VIDIOC_SUBDEV_QUERYCAP`, added in Linux 5.14.
+    ("v4l2r_has_subdev_querycap", &["v4l2_subdev_capability"]),
+    // Explicit synchronization fences, which are not part of mainline Linux but are carried by
+    // some downstream kernels.
+    (
+        "v4l2r_has_fences",
+        &[
+            "V4L2_BUF_FLAG_IN_FENCE",
+            "V4L2_BUF_FLAG_OUT_FENCE",
+            "V4L2_BUF_CAP_SUPPORTS_FENCES",
+        ],
+    ),
+];
+
+/// Returns the path of `program`, looking it up in `PATH` if it is not a path already.
+fn find_program(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return Some(program.to_path_buf());
+    }
+
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+/// Returns the sysroot of the Android NDK.
+///
+/// The NDK is looked up from the [`ANDROID_NDK_ENVS`] environment variables, or else from the
+/// location of the C compiler, which for the NDK is a wrapper located in the `bin` directory next
+/// to the sysroot.
+fn android_sysroot() -> PathBuf {
+    for var in ANDROID_NDK_ENVS {
+        println!("cargo::rerun-if-env-changed={}", var);
+    }
+
+    let host_tag = match env::consts::OS {
+        "macos" => "darwin-x86_64",
+        "windows" => "windows-x86_64",
+        _ => "linux-x86_64",
+    };
+
+    let from_env = ANDROID_NDK_ENVS.iter().find_map(|var| {
+        env::var_os(var).map(|ndk| {
+            Path::new(&ndk)
+                .join("toolchains/llvm/prebuilt")
+                .join(host_tag)
+                .join("sysroot")
+        })
+    });
+
+    let from_cc = || {
+        let cc = cc::Build::new().get_compiler();
+        let cc = find_program(cc.path())?.canonicalize().ok()?;
+        Some(cc.parent()?.parent()?.join("sysroot"))
+    };
+
+    from_env
+        .or_else(from_cc)
+        .filter(|sysroot| sysroot.is_dir())
+        .unwrap_or_else(|| {
+            panic!(
+                "Android NDK not found: set `{}` to the NDK's location",
+                ANDROID_NDK_ENVS[0]
+            )
+        })
+}
+
+/// Returns the name of the directory containing the architecture-specific headers of the NDK for
+/// the current target.
+fn android_multiarch_triple() -> &'static str {
+    match env::var("CARGO_CFG_TARGET_ARCH").as_deref() {
+        Ok("aarch64") => "aarch64-linux-android",
+        Ok("arm") => "arm-linux-androideabi",
+        Ok("x86") => "i686-linux-android",
+        Ok("riscv64") => "riscv64-linux-android",
+        _ => "x86_64-linux-android",
+    }
+}
+
+/// Generates the bindings into `bindings_rs` using bindgen.
+fn generate_bindings(bindings_rs: &Path) {
+    let is_android = env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("android");
+
+    let sysroot = if is_android {
+        android_sysroot().display().to_string()
+    } else {
+        let cc = cc::Build::new().get_compiler();
+        print_cc(cc.path(), &["--print-sysroot"]).trim().to_string()
+    };
+
     let videodev2_h_path = env::var(V4L2R_VIDEODEV_ENV)
         .or_else(|e| {
             if let VarError::NotPresent = e {
-                Ok(DEFAULT_VIDEODEV2_H_PATH.to_string())
+                // The NDK's kernel headers are not installed on the host.
+                Ok(if is_android {
+                    format!("{}/usr/include/linux", sysroot)
+                } else {
+                    DEFAULT_VIDEODEV2_H_PATH.to_string()
+                })
             } else {
                 Err(e)
             }
@@ -44,20 +165,23 @@ fn main() {
 
     let videodev2_h = PathBuf::from(videodev2_h_path.clone()).join("videodev2.h");
 
-    println!("cargo::rerun-if-env-changed={}", V4L2R_VIDEODEV_ENV);
     println!("cargo::rerun-if-changed={}", videodev2_h.display());
     println!("cargo::rerun-if-changed={}", FIX753_H);
     println!("cargo::rerun-if-changed={}", WRAPPER_H);
 
-    let cc = cc::Build::new().get_compiler();
-
-    let cc_sysroot = print_cc(cc.path(), &["--print-sysroot"]).trim().to_string();
-
-    let clang_args = [
+    let mut clang_args = vec![
         format!("-I{}", videodev2_h_path),
-        format!("--sysroot={}", &cc_sysroot),
-        format!("-I{}/usr/include/linux", &cc_sysroot),
+        format!("--sysroot={}", &sysroot),
+        format!("-I{}/usr/include/linux", &sysroot),
     ];
+    // The NDK keeps the architecture-specific headers (e.g. `asm/`) in a per-target directory.
+    if is_android {
+        clang_args.push(format!(
+            "-I{}/usr/include/{}",
+            &sysroot,
+            android_multiarch_triple()
+        ));
+    }
 
     let bindings = v4l2r_bindgen_builder(bindgen::Builder::default())
         .header(WRAPPER_H)
@@ -65,8 +189,35 @@ fn main() {
         .generate()
         .expect("unable to generate bindings");
 
-    let out_path = PathBuf::from(env::var("OUT_DIR").expect("`OUT_DIR` is not set"));
     bindings
-        .write_to_file(out_path.join("bindings.rs"))
+        .write_to_file(bindings_rs)
         .expect("Couldn't write bindings!");
 }
+
+/// Sets the `cfg` flags of [`OPTIONAL_CONTROLS`] which structures or constants are all defined in
+/// `bindings_rs`.
+fn detect_optional_controls(bindings_rs: &Path) {
+    let bindings = std::fs::read_to_string(bindings_rs).expect("Couldn't read bindings!");
+
+    for (cfg, items) in OPTIONAL_CONTROLS {
+        println!("cargo::rustc-check-cfg=cfg({})", cfg);
+
+        if items.iter().all(|item| {
+            bindings.contains(&format!("pub struct {} {{", item))
+                || bindings.contains(&format!("pub const {}:", item))
+        }) {
+            println!("cargo::rustc-cfg={}", cfg);
+        }
+    }
+}
+
+fn main() {
+    println!("cargo::rerun-if-env-changed={}", V4L2R_VIDEODEV_ENV);
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").expect("`OUT_DIR` is not set"));
+    let bindings_rs = out_path.join("bindings.rs");
+
+    generate_bindings(&bindings_rs);
+
+    detect_optional_controls(&bindings_rs);
+}
@@ -6,6 +6,7 @@ use std::sync::Arc;
 use std::{cell::RefCell, collections::VecDeque, time::Instant};
 
 use v4l2r::{
+    bitstream::ivf::{IvfHeader, IvfWriter},
     device::{
         poller::PollError,
         queue::{
@@ -18,7 +19,7 @@ use v4l2r::{
     },
     encoder::*,
     memory::{MmapHandle, UserPtrHandle},
-    Format,
+    Format, PixelFormat,
 };
 use v4l2r_utils::framegen::FrameGenerator;
 
@@ -55,6 +56,14 @@ fn main() {
                 .takes_value(true)
                 .help("Save the encoded stream to a file"),
         )
+        .arg(
+            Arg::with_name("codec")
+                .long("codec")
+                .required(false)
+                .takes_value(true)
+                .default_value("fwht")
+                .help("Codec to encode into (fwht or vp8). VP8 streams are saved as IVF"),
+        )
         .arg(
             Arg::with_name("output_mem")
                 .long("output_mem")
@@ -88,7 +97,13 @@ fn main() {
         })
         .unwrap();
 
-    let mut output_file = matches
+    let codec = match matches.value_of("codec") {
+        Some("fwht") => PixelFormat::FWHT,
+        Some("vp8") => PixelFormat::VP8,
+        _ => panic!("Invalid value for codec"),
+    };
+
+    let output_file = matches
         .value_of("output_file")
         .map(|s| File::create(s).expect("Invalid output file specified."));
 
@@ -112,11 +127,12 @@ fn main() {
     let encoder = Encoder::open(Path::new(&device_path))
         .expect("Failed to open device")
         .set_capture_format(|f| {
-            let format: Format = f.set_pixelformat(b"FWHT").apply()?;
+            let format: Format = f.set_pixelformat(codec).apply()?;
 
             ensure!(
-                format.pixelformat == b"FWHT".into(),
-                "FWHT format not supported"
+                format.pixelformat == codec,
+                "{} format not supported",
+                codec
             );
 
             Ok(())
@@ -156,12 +172,29 @@ fn main() {
         output_format.width, output_format.height, output_format.plane_fmt[0].bytesperline
     );
 
-    let mut frame_gen = FrameGenerator::new(
-        output_format.width as usize,
-        output_format.height as usize,
-        output_format.plane_fmt[0].bytesperline as usize,
-    )
-    .expect("Failed to create frame generator");
+    // VP8 frames are not self-delimiting, so put them into an IVF container.
+    enum OutputFile {
+        Raw(File),
+        Ivf(IvfWriter<File>),
+    }
+    let mut output_file = output_file.map(|file| {
+        if codec == PixelFormat::VP8 {
+            // Timestamps are frame numbers at 30 frames per second.
+            let header = IvfHeader::new(
+                codec,
+                output_format.width as u16,
+                output_format.height as u16,
+                1,
+                30,
+            );
+            OutputFile::Ivf(IvfWriter::new(file, &header).expect("Failed to write IVF header"))
+        } else {
+            OutputFile::Raw(file)
+        }
+    });
+
+    let mut frame_gen =
+        FrameGenerator::for_format(&output_format).expect("Failed to create frame generator");
 
     const NUM_BUFFERS: usize = 2;
 
@@ -214,7 +247,7 @@ fn main() {
     let poll_count_writer = Arc::clone(&poll_count_reader);
     let mut frame_counter = 0usize;
     let output_ready_cb = move |cap_dqbuf: DqBuffer<Capture, Vec<MmapHandle>>| {
-        let bytes_used = *cap_dqbuf.data.get_first_plane().bytesused as usize;
+        let bytes_used = cap_dqbuf.data.plane_bytesused(0).unwrap_or(0) as usize;
         // Ignore zero-sized buffers.
         if bytes_used == 0 {
             return;
@@ -240,9 +273,15 @@ fn main() {
             let mapping = cap_dqbuf
                 .get_plane_mapping(0)
                 .expect("Failed to map capture buffer");
-            output
-                .write_all(mapping.as_ref())
-                .expect("Error while writing output data");
+            let data = mapping.as_ref();
+            match output {
+                OutputFile::Raw(file) => file
+                    .write_all(data)
+                    .expect("Error while writing output data"),
+                OutputFile::Ivf(writer) => writer
+                    .write_frame(frame_counter as u64 - 1, data)
+                    .expect("Error while writing output data"),
+            }
         }
     };
 
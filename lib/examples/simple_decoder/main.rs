@@ -11,19 +11,17 @@ use std::{
 
 use anyhow::ensure;
 use nix::sys::time::{TimeVal, TimeValLike};
+use v4l2r::{
+    bitstream::{h264::AccessUnitSplitter, ivf::IvfReader},
+    decoder::stateful::GetBufferError,
+    PixelFormat,
+};
 use v4l2r::{
     decoder::{format::fwht::FwhtFrameParser, FormatChangedReply},
     device::queue::{handles_provider::MmapProvider, FormatBuilder},
     memory::{MemoryType, MmapHandle},
     PlaneLayout,
 };
-use v4l2r::{
-    decoder::{
-        format::{h264::H264FrameSplitter, StreamSplitter},
-        stateful::GetBufferError,
-    },
-    PixelFormat,
-};
 use v4l2r::{
     decoder::{stateful::Decoder, DecoderEvent},
     device::{
@@ -38,6 +36,8 @@ use clap::{App, Arg};
 enum Codec {
     Fwht,
     H264,
+    /// VP8, VP9 or AV1 stream in an IVF container.
+    Ivf,
 }
 
 fn main() {
@@ -60,7 +60,7 @@ fn main() {
                 .required(false)
                 .takes_value(true)
                 .default_value("fwht")
-                .help("Format of the encoded stream (fwht or h264)"),
+                .help("Format of the encoded stream (fwht, h264 or ivf)"),
         )
         .arg(
             Arg::with_name("output_file")
@@ -83,11 +83,63 @@ fn main() {
     {
         "fwht" => Codec::Fwht,
         "h264" => Codec::H264,
+        "ivf" => Codec::Ivf,
         _ => panic!("Invalid input format specified"),
     };
 
     let stream = BufReader::new(File::open(stream_path).expect("Compressed stream not found"));
 
+    // Pixel format of the stream, and iterator over its frames along with their timestamps.
+    type Frames = Box<dyn Iterator<Item = (TimeVal, Vec<u8>)>>;
+    let (pixel_format, frames): (PixelFormat, Frames) = match codec {
+        // TODO setting the timestamp should not be necessary. This is a requirement of the crosvm
+        // video device.
+        Codec::Fwht => (
+            PixelFormat::FWHT,
+            Box::new(
+                FwhtFrameParser::new(stream)
+                    .unwrap_or_else(|| panic!("No FWHT stream detected in {}", stream_path))
+                    .enumerate()
+                    .map(|(id, frame)| (TimeVal::seconds(id as i64), frame)),
+            ),
+        ),
+        Codec::H264 => (
+            PixelFormat::H264,
+            Box::new(
+                AccessUnitSplitter::from_reader(stream)
+                    .enumerate()
+                    .map(|(id, frame)| (TimeVal::seconds(id as i64), frame)),
+            ),
+        ),
+        Codec::Ivf => {
+            let reader = IvfReader::new(stream)
+                .unwrap_or_else(|e| panic!("Invalid IVF file {}: {}", stream_path, e));
+            let header = *reader.header();
+            println!(
+                "IVF stream: {} {}x{}, time base {}/{}",
+                header.fourcc,
+                header.width,
+                header.height,
+                header.timebase_numerator,
+                header.timebase_denominator
+            );
+
+            (
+                header.fourcc,
+                Box::new(reader.map_while(move |frame| match frame {
+                    Ok(frame) => {
+                        let us = header.timestamp_to_us(frame.timestamp).unwrap_or(0);
+                        Some((TimeVal::microseconds(us as i64), frame.data))
+                    }
+                    Err(e) => {
+                        eprintln!("Error while reading IVF frame: {}", e);
+                        None
+                    }
+                })),
+            )
+        }
+    };
+
     let mut output_file: Option<File> = matches
         .value_of("output_file")
         .map(|path| File::create(path).expect("Invalid output file specified."));
@@ -109,7 +161,7 @@ fn main() {
     let start_time = std::time::Instant::now();
     let mut frame_counter = 0usize;
     let mut output_ready_cb = move |cap_dqbuf: DqBuffer<Capture, Vec<MmapHandle>>| {
-        let bytes_used = *cap_dqbuf.data.get_first_plane().bytesused as usize;
+        let bytes_used = cap_dqbuf.data.plane_bytesused(0).unwrap_or(0) as usize;
         // Ignore zero-sized buffers.
         if bytes_used == 0 {
             return;
@@ -143,6 +195,12 @@ fn main() {
     let decoder_event_cb = move |event: DecoderEvent<MmapProvider>| match event {
         DecoderEvent::FrameDecoded(dqbuf) => output_ready_cb(dqbuf),
         DecoderEvent::EndOfStream => (),
+        DecoderEvent::SetupError(e) => eprintln!("\nDecoder setup failed: {}", e),
+        DecoderEvent::FrameDropStarted | DecoderEvent::FrameDropStopped => (),
+        DecoderEvent::Stalled(stalled) => eprintln!(
+            "\nDecoder stalled with {} buffers pending",
+            stalled.pending_output
+        ),
     };
     let set_capture_format_cb = move |f: FormatBuilder,
                                       visible_rect: Rect,
@@ -168,10 +226,6 @@ fn main() {
     let mut decoder = Decoder::open(Path::new(device_path))
         .expect("Failed to open device")
         .set_output_format(|f| {
-            let pixel_format: PixelFormat = match codec {
-                Codec::Fwht => b"FWHT".into(),
-                Codec::H264 => b"H264".into(),
-            };
             let format: Format = f
                 .set_pixelformat(pixel_format)
                 // 1 MB per decoding unit should be enough for most streams.
@@ -199,18 +253,7 @@ fn main() {
 
     println!("Allocated {} buffers", decoder.num_output_buffers());
 
-    let parser = match codec {
-        Codec::Fwht => Box::new(
-            FwhtFrameParser::new(stream)
-                .unwrap_or_else(|| panic!("No FWHT stream detected in {}", stream_path)),
-        ) as Box<dyn StreamSplitter>,
-        Codec::H264 => Box::new(
-            H264FrameSplitter::new(stream)
-                .unwrap_or_else(|| panic!("No H.264 stream detected in {}", stream_path)),
-        ) as Box<dyn StreamSplitter>,
-    };
-
-    'mainloop: for (bitstream_id, frame) in parser.enumerate() {
+    'mainloop: for (timestamp, frame) in frames {
         // Ctrl-c ?
         if lets_quit.load(Ordering::SeqCst) {
             break;
@@ -231,15 +274,18 @@ fn main() {
         mapping.as_mut()[0..frame.len()].copy_from_slice(&frame);
         drop(mapping);
 
-        // TODO setting the timestamp should not be necessary. This is a requirement of the crosvm
-        // video device.
         v4l2_buffer
-            .set_timestamp(TimeVal::seconds(bitstream_id as i64))
+            .set_timestamp(timestamp)
             .queue(&[frame.len()])
             .expect("Failed to queue input frame");
     }
 
     decoder.drain(true).unwrap();
+    let stats = decoder.stats();
     decoder.stop().unwrap();
     println!();
+    println!(
+        "Decoded {} frames, {} lost by the driver, {} dropped because we could not keep up",
+        stats.frames_out, stats.frames_lost, stats.frames_dropped
+    );
 }
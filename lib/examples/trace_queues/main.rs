@@ -0,0 +1,78 @@
+//! Exercises the queues of a memory-to-memory device while printing the spans and events emitted
+//! by v4l2r's `tracing` instrumentation.
+//!
+//! Run with e.g.
+//!
+//! ```text
+//! RUST_LOG='v4l2r[{queue=VideoCaptureMplane}]=debug' \
+//!     cargo run --features tracing --example trace_queues -- /dev/video0
+//! ```
+//!
+//! to only display what happens on the multi-planar CAPTURE queue, or `RUST_LOG=v4l2r=debug` to
+//! see everything.
+use std::path::Path;
+use std::sync::Arc;
+
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+use v4l2r::device::queue::Queue;
+use v4l2r::device::{AllocatedQueue, Device, DeviceConfig, Stream};
+use v4l2r::memory::MmapHandle;
+
+fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        // Print the duration of each span when it closes.
+        .with_span_events(FmtSpan::CLOSE)
+        .init();
+
+    let device_path = std::env::args()
+        .nth(1)
+        .expect("Usage: trace_queues <device>");
+    let device = Arc::new(
+        Device::open(Path::new(&device_path), DeviceConfig::new()).expect("Failed to open device"),
+    );
+
+    let (output_queue, capture_queue) = if let Ok(output_queue) =
+        Queue::get_output_mplane_queue(Arc::clone(&device))
+    {
+        (
+            output_queue,
+            Queue::get_capture_mplane_queue(Arc::clone(&device))
+                .expect("Failed to obtain capture queue"),
+        )
+    } else {
+        (
+            Queue::get_output_queue(Arc::clone(&device)).expect("Failed to obtain output queue"),
+            Queue::get_capture_queue(Arc::clone(&device)).expect("Failed to obtain capture queue"),
+        )
+    };
+
+    let output_queue = output_queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("Failed to allocate output buffers");
+    let capture_queue = capture_queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("Failed to allocate capture buffers");
+
+    output_queue
+        .stream_on()
+        .expect("Failed to start output queue");
+    capture_queue
+        .stream_on()
+        .expect("Failed to start capture queue");
+
+    output_queue
+        .stream_off()
+        .expect("Failed to stop output queue");
+    capture_queue
+        .stream_off()
+        .expect("Failed to stop capture queue");
+
+    output_queue
+        .free_buffers()
+        .expect("Failed to free output buffers");
+    capture_queue
+        .free_buffers()
+        .expect("Failed to free capture buffers");
+}
@@ -0,0 +1,352 @@
+//! Transcodes a FWHT stream into another FWHT stream using two vicodec instances.
+//!
+//! Decoded frames are never copied: the decoder's `CAPTURE` buffers are exported as DMABUFs and
+//! queued as-is to the `OUTPUT` queue of the encoder. A decoded frame is returned to the decoder
+//! once the encoder has released the buffer it has been queued into.
+//!
+//! The decoder can only free its `CAPTURE` buffers, e.g. to switch to a new resolution, once the
+//! encoder has released all of them. So whenever the decoder signals a `LAST` buffer, the encoder
+//! is drained and stopped before the decoder is allowed to go on.
+use std::{
+    fs::File,
+    io::{BufReader, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::mpsc::{self, Receiver, Sender},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::ensure;
+use clap::{App, Arg};
+use nix::sys::time::{TimeVal, TimeValLike};
+use v4l2r::{
+    decoder::{format::fwht::FwhtFrameParser, stateful::Decoder, DecoderEvent, FormatChangedReply},
+    device::queue::{
+        direction::Capture,
+        dqbuf::{DqBuffer, ExportedDqBuffer},
+        handles_provider::MmapProvider,
+        FormatBuilder, OutputQueueable,
+    },
+    encoder::*,
+    ioctl::timeval_to_nix,
+    memory::{DmaBufHandle, MemoryType, MmapHandle},
+    Format, PixelFormat, PlaneLayout, Rect,
+};
+
+const NUM_DECODER_OUTPUT_BUFFERS: usize = 4;
+const NUM_ENCODER_OUTPUT_BUFFERS: usize = 2;
+const NUM_ENCODER_CAPTURE_BUFFERS: usize = 2;
+
+/// Decoded frame exported as a DMABUF. Dropping it returns the frame to the decoder.
+type DecodedFrame = ExportedDqBuffer<Capture, Vec<MmapHandle>>;
+type EncoderInputHandles = Vec<DmaBufHandle<DecodedFrame>>;
+type EncoderInputDoneCb = fn(CompletedOutputBuffer<EncoderInputHandles>);
+type EncoderOutputReadyCb = Box<dyn FnMut(DqBuffer<Capture, Vec<MmapHandle>>) + Send>;
+type TranscodeEncoder =
+    Encoder<Encoding<EncoderInputHandles, MmapProvider, EncoderInputDoneCb, EncoderOutputReadyCb>>;
+
+/// Events sent from the decoder to the encoder thread, in decoding order.
+enum DecodedEvent {
+    /// The decoder switched to a new format. Subsequent frames use this format.
+    FormatChanged(Format),
+    Frame(DqBuffer<Capture, Vec<MmapHandle>>),
+    /// The decoder has produced its last frame for the current format, either because of a drain
+    /// or a resolution change. The encoder must be drained and stopped, which releases all the
+    /// decoded frames, before acknowledging through the sender.
+    Drain(Sender<()>),
+    EndOfStream,
+}
+
+/// Open the encoder at `device_path` and start it for frames of `format`.
+fn start_encoder(
+    device_path: &Path,
+    format: &Format,
+    output: &Arc<Mutex<File>>,
+    encoded_frames: &Arc<AtomicUsize>,
+) -> anyhow::Result<TranscodeEncoder> {
+    let encoder = Encoder::open(device_path)?
+        .set_capture_format(|f| {
+            let capture_format: Format = f.set_pixelformat(PixelFormat::FWHT).apply()?;
+            ensure!(
+                capture_format.pixelformat == PixelFormat::FWHT,
+                "FWHT format not supported"
+            );
+
+            Ok(())
+        })?
+        .set_output_format(|f| {
+            let adjusted: Format = f.set_from(format).apply()?;
+            // The decoded frames are queued as-is, so their layout must be accepted without
+            // adjustment.
+            ensure!(
+                adjusted.pixelformat == format.pixelformat
+                    && adjusted.width == format.width
+                    && adjusted.height == format.height
+                    && adjusted.plane_fmt.first().map(|p| p.bytesperline)
+                        == format.plane_fmt.first().map(|p| p.bytesperline),
+                "Encoder does not support decoded format {:?}",
+                format
+            );
+
+            Ok(())
+        })?;
+
+    let capture_format = encoder.get_capture_format()?;
+
+    let output = Arc::clone(output);
+    let encoded_frames = Arc::clone(encoded_frames);
+    let output_ready_cb: EncoderOutputReadyCb = Box::new(move |cap_dqbuf| {
+        // Ignore zero-sized buffers.
+        if cap_dqbuf.data.plane_bytesused(0).unwrap_or(0) == 0 {
+            return;
+        }
+
+        let mapping = cap_dqbuf
+            .get_plane_mapping(0)
+            .expect("Failed to map capture buffer");
+        output
+            .lock()
+            .unwrap()
+            .write_all(mapping.as_ref())
+            .expect("Error while writing output data");
+        encoded_frames.fetch_add(1, Ordering::SeqCst);
+    });
+    // Dropping the completed buffer drops its handles, which returns the decoded frame to the
+    // decoder.
+    let input_done_cb: EncoderInputDoneCb = |_| ();
+
+    Ok(encoder
+        .allocate_output_buffers::<EncoderInputHandles>(NUM_ENCODER_OUTPUT_BUFFERS)?
+        .allocate_capture_buffers(
+            NUM_ENCODER_CAPTURE_BUFFERS,
+            MmapProvider::new(&capture_format),
+        )?
+        .start(input_done_cb, output_ready_cb)?)
+}
+
+/// Encode the frames received from `events` until the end of the stream.
+fn run_encoder(
+    device_path: PathBuf,
+    events: Receiver<DecodedEvent>,
+    output: File,
+    encoded_frames: Arc<AtomicUsize>,
+) {
+    let output = Arc::new(Mutex::new(output));
+    let mut encoder: Option<TranscodeEncoder> = None;
+
+    for event in events {
+        match event {
+            DecodedEvent::FormatChanged(format) => {
+                // The encoder of the previous format has been stopped by the preceding drain.
+                assert!(encoder.is_none(), "Format changed without a drain");
+                println!(
+                    "Configuring encoder for {}x{} {}",
+                    format.width, format.height, format.pixelformat
+                );
+                encoder = Some(
+                    start_encoder(&device_path, &format, &output, &encoded_frames)
+                        .expect("Failed to start encoder"),
+                );
+            }
+            DecodedEvent::Frame(dqbuf) => {
+                let encoder = encoder
+                    .as_mut()
+                    .expect("Frame decoded before the format was known");
+                let bytes_used = dqbuf.data.plane_bytesused(0).unwrap_or(0) as usize;
+                let timestamp = timeval_to_nix(dqbuf.data.timestamp());
+                let frame = dqbuf.export(0).expect("Failed to export decoded frame");
+
+                encoder
+                    .get_buffer()
+                    .expect("Failed to get encoder buffer")
+                    .set_timestamp(timestamp)
+                    .queue_with_handles(vec![DmaBufHandle::from(frame)], &[bytes_used])
+                    .expect("Failed to queue frame to encoder");
+            }
+            // Propagate the drain of the decoder to the encoder. Stopping the encoder drains the
+            // pending frames and returns all the decoded frames to the decoder.
+            DecodedEvent::Drain(ack) => {
+                if let Some(encoder) = encoder.take() {
+                    encoder.stop().expect("Failed to stop encoder");
+                }
+                let _ = ack.send(());
+            }
+            DecodedEvent::EndOfStream => break,
+        }
+    }
+
+    if let Some(encoder) = encoder {
+        encoder.stop().expect("Failed to stop encoder");
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("V4L2 FWHT transcoder")
+        .arg(
+            Arg::with_name("stream")
+                .required(true)
+                .help("Path to the FWHT stream to transcode"),
+        )
+        .arg(
+            Arg::with_name("decoder")
+                .required(true)
+                .help("Path to the vicodec decoder device file"),
+        )
+        .arg(
+            Arg::with_name("encoder")
+                .required(true)
+                .help("Path to the vicodec encoder device file"),
+        )
+        .arg(
+            Arg::with_name("output_file")
+                .required(true)
+                .help("Path to save the transcoded FWHT stream to"),
+        )
+        .get_matches();
+
+    let stream_path = matches
+        .value_of("stream")
+        .expect("Stream argument not specified");
+    let decoder_path = matches
+        .value_of("decoder")
+        .expect("Decoder argument not specified");
+    let encoder_path = PathBuf::from(
+        matches
+            .value_of("encoder")
+            .expect("Encoder argument not specified"),
+    );
+    let output_file = File::create(
+        matches
+            .value_of("output_file")
+            .expect("Output file not specified"),
+    )
+    .expect("Invalid output file specified.");
+
+    let stream = BufReader::new(File::open(stream_path).expect("Compressed stream not found"));
+    let frames = FwhtFrameParser::new(stream)
+        .unwrap_or_else(|| panic!("No FWHT stream detected in {}", stream_path));
+
+    let (events_tx, events_rx) = mpsc::channel();
+    let format_tx = events_tx.clone();
+
+    // Both callbacks run on the decoder's CAPTURE thread, so the events reach the encoder in
+    // decoding order.
+    let decoder_event_cb = move |event: DecoderEvent<MmapProvider>| match event {
+        DecoderEvent::FrameDecoded(dqbuf) => {
+            let is_last = dqbuf.data.is_last();
+            // Ignore zero-sized buffers, e.g. the last one of a drain.
+            if dqbuf.data.plane_bytesused(0).unwrap_or(0) > 0 {
+                let _ = events_tx.send(DecodedEvent::Frame(dqbuf));
+            }
+            // The decoder frees its CAPTURE buffers on a resolution change as soon as this
+            // callback returns, so wait until the encoder has released them all.
+            if is_last {
+                let (ack_tx, ack_rx) = mpsc::channel();
+                if events_tx.send(DecodedEvent::Drain(ack_tx)).is_ok() {
+                    let _ = ack_rx.recv();
+                }
+            }
+        }
+        DecoderEvent::EndOfStream => {
+            let _ = events_tx.send(DecodedEvent::EndOfStream);
+        }
+        DecoderEvent::SetupError(e) => eprintln!("Decoder setup failed: {}", e),
+        DecoderEvent::FrameDropStarted | DecoderEvent::FrameDropStopped => (),
+        DecoderEvent::Stalled(stalled) => eprintln!(
+            "Decoder stalled with {} buffers pending",
+            stalled.pending_output
+        ),
+    };
+    let set_capture_format_cb = move |f: FormatBuilder,
+                                      visible_rect: Rect,
+                                      min_num_buffers: usize|
+          -> anyhow::Result<FormatChangedReply<MmapProvider>> {
+        let format = f.format().clone();
+        ensure!(
+            format.plane_fmt.len() == 1,
+            "Only single-plane formats can be transcoded"
+        );
+        println!(
+            "New decoder CAPTURE format: {:?} (visible rect: {})",
+            format, visible_rect
+        );
+
+        format_tx
+            .send(DecodedEvent::FormatChanged(format.clone()))
+            .map_err(|_| anyhow::anyhow!("Encoder thread has exited"))?;
+
+        Ok(FormatChangedReply {
+            provider: MmapProvider::new(&format),
+            mem_type: MemoryType::Mmap,
+            // The encoder holds on to the frames it is processing, so allocate enough buffers for
+            // the decoder to keep going meanwhile.
+            num_buffers: min_num_buffers + NUM_ENCODER_OUTPUT_BUFFERS,
+        })
+    };
+
+    let encoded_frames = Arc::new(AtomicUsize::new(0));
+    let encoder_thread = {
+        let encoded_frames = Arc::clone(&encoded_frames);
+        std::thread::Builder::new()
+            .name("Transcode encoder".into())
+            .spawn(move || run_encoder(encoder_path, events_rx, output_file, encoded_frames))
+            .expect("Failed to spawn encoder thread")
+    };
+
+    let mut decoder = Decoder::open(Path::new(decoder_path))
+        .expect("Failed to open decoder")
+        .set_output_format(|f| {
+            let format: Format = f
+                .set_pixelformat(PixelFormat::FWHT)
+                .set_planes_layout(vec![PlaneLayout {
+                    sizeimage: 1024 * 1024,
+                    ..Default::default()
+                }])
+                .apply()?;
+
+            ensure!(
+                format.pixelformat == PixelFormat::FWHT,
+                "FWHT format not supported by decoder"
+            );
+
+            Ok(())
+        })
+        .expect("Failed to set decoder output format")
+        .allocate_output_buffers::<Vec<MmapHandle>>(NUM_DECODER_OUTPUT_BUFFERS)
+        .expect("Failed to allocate decoder output buffers")
+        .start(|_| (), decoder_event_cb, set_capture_format_cb)
+        .expect("Failed to start decoder");
+
+    let mut input_frames = 0usize;
+    for frame in frames {
+        let v4l2_buffer = decoder.get_buffer().expect("Failed to get decoder buffer");
+        let mut mapping = v4l2_buffer
+            .get_plane_mapping(0)
+            .expect("Failed to get OUTPUT buffer mapping");
+        mapping.as_mut()[0..frame.len()].copy_from_slice(&frame);
+        drop(mapping);
+
+        v4l2_buffer
+            .set_timestamp(TimeVal::seconds(input_frames as i64))
+            .queue(&[frame.len()])
+            .expect("Failed to queue input frame");
+        input_frames += 1;
+    }
+
+    // Draining the decoder sends the end of stream event, which makes the encoder thread drain
+    // the encoder and exit.
+    decoder.drain(true).expect("Failed to drain decoder");
+    // Stopping the decoder drops its callbacks, so the encoder thread also exits if the end of
+    // stream was never signaled.
+    decoder.stop().expect("Failed to stop decoder");
+    encoder_thread.join().expect("Encoder thread panicked");
+
+    let encoded_frames = encoded_frames.load(Ordering::SeqCst);
+    println!("Transcoded {} frames", encoded_frames);
+    assert_eq!(
+        encoded_frames, input_frames,
+        "Number of encoded frames does not match the number of input frames"
+    );
+}
@@ -0,0 +1,458 @@
+//! Captures frames from a V4L2 capture device, and writes them either as a Y4M stream, or as
+//! tightly packed raw frames along with a JSON sidecar describing their format.
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, ensure, Context};
+use clap::{App, Arg};
+use v4l2r::{
+    device::{
+        queue::direction::Capture,
+        queue::{GetFreeCaptureBuffer, Queue, QueueInit},
+        Device, DeviceConfig, Stream, TryDequeue,
+    },
+    format::{color_plane_layouts, ColorPlaneLayout},
+    ioctl::{self, SelectionTarget},
+    memory::MmapHandle,
+    Colorspace, Format, Fraction, PixelFormat, Quantization, Rect,
+};
+
+/// Where to find the samples of a Y4M plane in the color planes of a frame.
+struct Component {
+    /// Index of the color plane containing the samples.
+    color_plane: usize,
+    /// Offset of the first sample of a line, in bytes.
+    start: usize,
+    /// Number of bytes between two samples of a line.
+    step: usize,
+    /// Horizontal subsampling factor of the component.
+    hsub: usize,
+    /// Vertical subsampling factor of the component.
+    vsub: usize,
+}
+
+const fn component(
+    color_plane: usize,
+    start: usize,
+    step: usize,
+    hsub: usize,
+    vsub: usize,
+) -> Component {
+    Component {
+        color_plane,
+        start,
+        step,
+        hsub,
+        vsub,
+    }
+}
+
+const YUV420: &[Component] = &[
+    component(0, 0, 1, 1, 1),
+    component(1, 0, 1, 2, 2),
+    component(2, 0, 1, 2, 2),
+];
+const YVU420: &[Component] = &[
+    component(0, 0, 1, 1, 1),
+    component(2, 0, 1, 2, 2),
+    component(1, 0, 1, 2, 2),
+];
+const NV12: &[Component] = &[
+    component(0, 0, 1, 1, 1),
+    component(1, 0, 2, 2, 2),
+    component(1, 1, 2, 2, 2),
+];
+const NV21: &[Component] = &[
+    component(0, 0, 1, 1, 1),
+    component(1, 1, 2, 2, 2),
+    component(1, 0, 2, 2, 2),
+];
+const YUV422P: &[Component] = &[
+    component(0, 0, 1, 1, 1),
+    component(1, 0, 1, 2, 1),
+    component(2, 0, 1, 2, 1),
+];
+const NV16: &[Component] = &[
+    component(0, 0, 1, 1, 1),
+    component(1, 0, 2, 2, 1),
+    component(1, 1, 2, 2, 1),
+];
+const NV61: &[Component] = &[
+    component(0, 0, 1, 1, 1),
+    component(1, 1, 2, 2, 1),
+    component(1, 0, 2, 2, 1),
+];
+const YUYV: &[Component] = &[
+    component(0, 0, 2, 1, 1),
+    component(0, 1, 4, 2, 1),
+    component(0, 3, 4, 2, 1),
+];
+const YVYU: &[Component] = &[
+    component(0, 0, 2, 1, 1),
+    component(0, 3, 4, 2, 1),
+    component(0, 1, 4, 2, 1),
+];
+const UYVY: &[Component] = &[
+    component(0, 1, 2, 1, 1),
+    component(0, 0, 4, 2, 1),
+    component(0, 2, 4, 2, 1),
+];
+const VYUY: &[Component] = &[
+    component(0, 1, 2, 1, 1),
+    component(0, 2, 4, 2, 1),
+    component(0, 0, 4, 2, 1),
+];
+
+/// Returns the Y, Cb and Cr components of `format`, or `None` if it cannot be written as Y4M.
+fn y4m_components(format: PixelFormat) -> Option<&'static [Component]> {
+    let components = match &format.to_fourcc() {
+        b"YU12" | b"YM12" => YUV420,
+        b"YV12" | b"YM21" => YVU420,
+        b"NV12" | b"NM12" => NV12,
+        b"NV21" | b"NM21" => NV21,
+        b"422P" | b"YM16" => YUV422P,
+        b"NV16" | b"NM16" => NV16,
+        b"NV61" | b"NM61" => NV61,
+        b"YUYV" => YUYV,
+        b"YVYU" => YVYU,
+        b"UYVY" => UYVY,
+        b"VYUY" => VYUY,
+        _ => return None,
+    };
+
+    Some(components)
+}
+
+/// Returns the Y4M header describing frames of `format` cropped to `visible`.
+fn y4m_header(format: &Format, visible: &Rect, interval: Option<Fraction>) -> String {
+    let chroma = match (
+        y4m_components(format.pixelformat).unwrap()[1].vsub,
+        format.colorspace,
+    ) {
+        (1, _) => "422",
+        // JPEG centers the chroma samples, while the video standards co-site them horizontally
+        // with the luma ones.
+        (_, Colorspace::Jpeg) => "420jpeg",
+        (_, _) => "420mpeg2",
+    };
+    // Y4M uses the frame rate, which is the inverse of the frame interval.
+    let rate = interval
+        .filter(|interval| interval.numerator != 0 && interval.denominator != 0)
+        .map(|interval| interval.recip().simplified())
+        .unwrap_or(Fraction::new(30, 1));
+    let range = match format.resolved_quantization() {
+        Quantization::FullRange => "FULL",
+        _ => "LIMITED",
+    };
+
+    format!(
+        "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C{} XCOLORRANGE={}\n",
+        visible.width, visible.height, rate.numerator, rate.denominator, chroma, range
+    )
+}
+
+/// Returns the JSON sidecar describing raw frames of `format` cropped to `visible`, with the
+/// size of each of their color planes in `planes`.
+fn raw_sidecar(
+    format: &Format,
+    visible: &Rect,
+    interval: Option<Fraction>,
+    planes: &[(usize, usize)],
+    frames: usize,
+) -> serde_json::Value {
+    serde_json::json!({
+        "pixelformat": format.pixelformat.to_string(),
+        "width": visible.width,
+        "height": visible.height,
+        "coded_width": format.width,
+        "coded_height": format.height,
+        "visible_rect": {
+            "left": visible.left,
+            "top": visible.top,
+            "width": visible.width,
+            "height": visible.height,
+        },
+        "frame_interval": interval.map(|i| format!("{}/{}", i.numerator, i.denominator)),
+        "colorspace": format!("{:?}", format.colorspace),
+        "xfer_func": format!("{:?}", format.resolved_xfer_func()),
+        "ycbcr_enc": format!("{:?}", format.resolved_ycbcr_enc()),
+        "quantization": format!("{:?}", format.resolved_quantization()),
+        "planes": planes
+            .iter()
+            .map(|(line_bytes, lines)| serde_json::json!({
+                "bytesperline": line_bytes,
+                "lines": lines,
+            }))
+            .collect::<Vec<_>>(),
+        "frames": frames,
+    })
+}
+
+/// Writes the samples of `component` within `visible` to `out`, one line after the other.
+fn write_component(
+    out: &mut impl Write,
+    planes: &[&[u8]],
+    layouts: &[ColorPlaneLayout],
+    component: &Component,
+    visible: &Rect,
+) -> std::io::Result<()> {
+    let layout = &layouts[component.color_plane];
+    let plane = &planes[layout.memory_plane][layout.offset..];
+    let first_line = visible.top as usize / component.vsub;
+    let first_sample = component.start + visible.left as usize / component.hsub * component.step;
+    let width = (visible.width as usize).div_ceil(component.hsub);
+    let height = (visible.height as usize).div_ceil(component.vsub);
+
+    let mut line_buf = Vec::with_capacity(width);
+    for line in plane.chunks(layout.stride).skip(first_line).take(height) {
+        line_buf.clear();
+        line_buf.extend(
+            line[first_sample..]
+                .iter()
+                .step_by(component.step)
+                .take(width),
+        );
+        out.write_all(&line_buf)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the color planes of the frame within `visible` to `out`, without their padding.
+fn write_raw(
+    out: &mut impl Write,
+    planes: &[&[u8]],
+    layouts: &[ColorPlaneLayout],
+    visible: &Rect,
+) -> std::io::Result<()> {
+    for layout in layouts {
+        let plane = &planes[layout.memory_plane][layout.offset..];
+        let first_line = visible.top as usize / layout.vsub;
+        let first_byte = visible.left as usize / layout.hsub * layout.bytes_per_pixel;
+        let line_bytes = layout.line_bytes(visible.width as usize);
+
+        for line in plane
+            .chunks(layout.stride)
+            .skip(first_line)
+            .take(layout.lines(visible.height as usize))
+        {
+            out.write_all(&line[first_byte..first_byte + line_bytes])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the CAPTURE queue of `device`, using the multi-planar API if the single-planar one is
+/// not supported.
+fn capture_queue(device: &Arc<Device>) -> anyhow::Result<Queue<Capture, QueueInit>> {
+    Queue::get_capture_queue(Arc::clone(device))
+        .or_else(|_| Queue::get_capture_mplane_queue(Arc::clone(device)))
+        .context("device has no CAPTURE queue")
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let matches = App::new("V4L2 capture")
+        .arg(
+            Arg::with_name("device")
+                .required(true)
+                .help("Path to the capture device file"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .required(true)
+                .help("File to write the captured frames to"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .default_value("YUYV")
+                .help("Pixel format to capture, as a fourcc"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .default_value("640x480")
+                .help("Resolution to capture, as WIDTHxHEIGHT"),
+        )
+        .arg(
+            Arg::with_name("frames")
+                .long("frames")
+                .takes_value(true)
+                .default_value("30")
+                .help("Number of frames to capture"),
+        )
+        .arg(
+            Arg::with_name("fps")
+                .long("fps")
+                .takes_value(true)
+                .help("Frame rate to request from the device"),
+        )
+        .arg(
+            Arg::with_name("raw")
+                .long("raw")
+                .help("Write raw frames and a JSON sidecar instead of Y4M"),
+        )
+        .get_matches();
+
+    let device_path = matches.value_of("device").unwrap();
+    let output_path = Path::new(matches.value_of("output").unwrap());
+    let pixelformat: PixelFormat = matches
+        .value_of("format")
+        .unwrap()
+        .parse()
+        .map_err(|e| anyhow!("invalid pixel format: {}", e))?;
+    let (width, height) = matches
+        .value_of("size")
+        .unwrap()
+        .split_once('x')
+        .and_then(|(w, h)| Some((w.parse::<usize>().ok()?, h.parse::<usize>().ok()?)))
+        .context("invalid size, expected WIDTHxHEIGHT")?;
+    let num_frames: usize = matches
+        .value_of("frames")
+        .unwrap()
+        .parse()
+        .context("invalid number of frames")?;
+    let fps = matches
+        .value_of("fps")
+        .map(|fps| fps.parse::<u32>().context("invalid frame rate"))
+        .transpose()?;
+    let raw = matches.is_present("raw");
+
+    let device = Arc::new(
+        Device::open(Path::new(device_path), DeviceConfig::new())
+            .context("failed to open device")?,
+    );
+    let mut queue = capture_queue(&device)?;
+
+    let format: Format = queue
+        .change_format()?
+        .set_size(width, height)
+        .set_pixelformat(pixelformat)
+        .apply()?;
+    ensure!(
+        format.pixelformat == pixelformat,
+        "device does not support {}, it picked {} instead",
+        pixelformat,
+        format.pixelformat
+    );
+    let layouts = color_plane_layouts(format.pixelformat, format.height, &format.plane_fmt)
+        .ok_or_else(|| anyhow!("{} is not a supported raw format", format.pixelformat))?;
+    let components = if raw {
+        None
+    } else {
+        Some(
+            y4m_components(format.pixelformat)
+                .ok_or_else(|| anyhow!("{} cannot be written as Y4M", format.pixelformat))?,
+        )
+    };
+    println!(
+        "Capturing {} at {}x{}",
+        format.pixelformat, format.width, format.height
+    );
+
+    let queue_type = queue.get_type();
+    if let Some(fps) = fps {
+        let interval = ioctl::s_time_per_frame(&*device, queue_type, Fraction::new(1, fps))
+            .context("failed to set frame interval")?;
+        println!("Frame interval set to {}", interval);
+    }
+    let interval = ioctl::g_time_per_frame(&*device, queue_type).ok();
+
+    // The visible rectangle may be smaller than the coded size, e.g. if the driver requires some
+    // alignment. Drivers that do not support the selection API always fill the whole frame.
+    let frame = Rect::new(0, 0, format.width, format.height);
+    let mut visible = queue
+        .get_selection(SelectionTarget::Compose)
+        .ok()
+        .and_then(|rect| rect.intersection(&frame))
+        .unwrap_or(frame);
+    if components.is_some() {
+        // Start on a chroma sample so all components of the first pixel are present.
+        visible = Rect::new(
+            visible.left & !1,
+            visible.top & !1,
+            visible.width,
+            visible.height,
+        );
+    }
+    println!("Visible rectangle: {}", visible);
+
+    let mut output = BufWriter::new(File::create(output_path).context("failed to create output")?);
+    if components.is_some() {
+        output.write_all(y4m_header(&format, &visible, interval).as_bytes())?;
+    }
+
+    let queue = queue
+        .request_buffers::<Vec<MmapHandle>>(4)
+        .context("failed to allocate buffers")?;
+    queue.stream_on().context("failed to stream on")?;
+
+    let mut written = 0;
+    while written < num_frames {
+        while let Ok(buffer) = queue.try_get_free_buffer() {
+            buffer.queue().context("failed to queue buffer")?;
+        }
+
+        let dqbuf = queue.try_dequeue().context("failed to dequeue buffer")?;
+        if dqbuf.data.has_error() {
+            eprintln!("Skipping corrupted frame {}", dqbuf.data.sequence());
+            continue;
+        }
+
+        let mappings = (0..format.plane_fmt.len())
+            .map(|plane| {
+                dqbuf
+                    .get_plane_mapping(plane)
+                    .ok_or_else(|| anyhow!("failed to map plane {}", plane))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let planes = mappings.iter().map(|m| m.as_ref()).collect::<Vec<_>>();
+
+        match components {
+            Some(components) => {
+                output.write_all(b"FRAME\n")?;
+                for component in components {
+                    write_component(&mut output, &planes, &layouts, component, &visible)?;
+                }
+            }
+            None => write_raw(&mut output, &planes, &layouts, &visible)?,
+        }
+        written += 1;
+    }
+
+    queue.stream_off().context("failed to stream off")?;
+    output.flush()?;
+    println!(
+        "Captured {} frames, {} dropped by the device",
+        written,
+        queue.dropped_frames()
+    );
+
+    if raw {
+        let planes = layouts
+            .iter()
+            .map(|layout| {
+                (
+                    layout.line_bytes(visible.width as usize),
+                    layout.lines(visible.height as usize),
+                )
+            })
+            .collect::<Vec<_>>();
+        let sidecar = raw_sidecar(&format, &visible, interval, &planes, num_frames);
+        let mut sidecar_path = output_path.as_os_str().to_owned();
+        sidecar_path.push(".json");
+        std::fs::write(&sidecar_path, serde_json::to_string_pretty(&sidecar)?)
+            .context("failed to write sidecar")?;
+    }
+
+    Ok(())
+}
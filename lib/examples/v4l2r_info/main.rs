@@ -0,0 +1,551 @@
+//! Prints everything that can be enumerated about a V4L2 device: its capabilities, inputs and
+//! outputs, the formats of each of its queues with their frame sizes and intervals, its controls
+//! and its selection rectangles. Pass `--json` to get the same information in JSON format, e.g. to
+//! attach it to a bug report.
+use std::path::Path;
+
+use anyhow::Context;
+use clap::{App, Arg};
+use serde::Serialize;
+use v4l2r::{
+    bindings,
+    bindings::{v4l2_frmivalenum, v4l2_frmsizeenum, v4l2_input, v4l2_output, v4l2_querymenu},
+    controls::get_control_value,
+    device::{Device, DeviceConfig},
+    ioctl::{
+        self, string_from_cstr, Capability, ControlFlags, ControlIterator, FormatIterator,
+        FrmIvalTypes, FrmSizeTypes, SelectionTarget, SelectionType,
+    },
+    Format, Fraction, PixelFormat, QueueDirection, QueueType, Rect,
+};
+
+/// Queues that can be enumerated with `VIDIOC_ENUM_FMT`.
+const QUEUE_TYPES: &[QueueType] = &[
+    QueueType::VideoCapture,
+    QueueType::VideoCaptureMplane,
+    QueueType::VideoOutput,
+    QueueType::VideoOutputMplane,
+    QueueType::VideoOverlay,
+    QueueType::SdrCapture,
+    QueueType::SdrOutput,
+    QueueType::MetaCapture,
+    QueueType::MetaOutput,
+];
+
+const SELECTION_TARGETS: &[SelectionTarget] = &[
+    SelectionTarget::Crop,
+    SelectionTarget::CropDefault,
+    SelectionTarget::CropBounds,
+    SelectionTarget::NativeSize,
+    SelectionTarget::Compose,
+    SelectionTarget::ComposeDefault,
+    SelectionTarget::ComposeBounds,
+    SelectionTarget::ComposePadded,
+];
+
+#[derive(Serialize)]
+struct DeviceInfo {
+    driver: String,
+    card: String,
+    bus_info: String,
+    version: String,
+    capabilities: Vec<String>,
+    device_caps: Vec<String>,
+    inputs: Vec<IoInfo>,
+    outputs: Vec<IoInfo>,
+    queues: Vec<QueueInfo>,
+    controls: Vec<ControlInfo>,
+}
+
+/// An input or output of the device.
+#[derive(Serialize)]
+struct IoInfo {
+    index: usize,
+    name: String,
+    io_type: u32,
+    status: Vec<String>,
+    current: bool,
+}
+
+#[derive(Serialize)]
+struct QueueInfo {
+    queue: QueueType,
+    current_format: Option<Format>,
+    formats: Vec<FormatInfo>,
+    selections: Vec<SelectionInfo>,
+}
+
+#[derive(Serialize)]
+struct FormatInfo {
+    pixelformat: PixelFormat,
+    description: String,
+    flags: Vec<String>,
+    frame_sizes: Vec<FrameSize>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FrameSize {
+    Discrete {
+        width: u32,
+        height: u32,
+        intervals: Vec<FrameInterval>,
+    },
+    Stepwise {
+        min_width: u32,
+        max_width: u32,
+        step_width: u32,
+        min_height: u32,
+        max_height: u32,
+        step_height: u32,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum FrameInterval {
+    Discrete(Fraction),
+    Stepwise {
+        min: Fraction,
+        max: Fraction,
+        step: Fraction,
+    },
+}
+
+#[derive(Serialize)]
+struct SelectionInfo {
+    target: String,
+    rect: Rect,
+}
+
+#[derive(Serialize)]
+struct ControlInfo {
+    id: u32,
+    name: String,
+    ctrl_type: String,
+    minimum: i64,
+    maximum: i64,
+    step: u64,
+    default: i64,
+    flags: Vec<String>,
+    /// Current value of the control, for controls that have one.
+    value: Option<String>,
+    menu: Vec<MenuItem>,
+}
+
+#[derive(Serialize)]
+struct MenuItem {
+    index: u32,
+    name: String,
+}
+
+/// Returns the names of the flags set in `flags`.
+fn flag_names<B: bitflags::Flags>(flags: B) -> Vec<String> {
+    flags
+        .iter_names()
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// Returns the names of the `V4L2_IN_ST_*` flags set in `status`.
+fn input_status(status: u32) -> Vec<String> {
+    const STATUS: &[(u32, &str)] = &[
+        (bindings::V4L2_IN_ST_NO_POWER, "NO_POWER"),
+        (bindings::V4L2_IN_ST_NO_SIGNAL, "NO_SIGNAL"),
+        (bindings::V4L2_IN_ST_NO_COLOR, "NO_COLOR"),
+        (bindings::V4L2_IN_ST_HFLIP, "HFLIP"),
+        (bindings::V4L2_IN_ST_VFLIP, "VFLIP"),
+        (bindings::V4L2_IN_ST_NO_H_LOCK, "NO_H_LOCK"),
+        (bindings::V4L2_IN_ST_NO_SYNC, "NO_SYNC"),
+    ];
+
+    STATUS
+        .iter()
+        .filter(|(flag, _)| status & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+fn ctrl_type_name(ctrl_type: u32) -> String {
+    match ctrl_type {
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER => "int".into(),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_BOOLEAN => "bool".into(),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_MENU => "menu".into(),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_BUTTON => "button".into(),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER64 => "int64".into(),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_STRING => "string".into(),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_BITMASK => "bitmask".into(),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER_MENU => "intmenu".into(),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_U8 => "u8".into(),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_U16 => "u16".into(),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_U32 => "u32".into(),
+        t => format!("compound(0x{:04x})", t),
+    }
+}
+
+fn inputs(device: &Device) -> Vec<IoInfo> {
+    let current = ioctl::g_input(device).ok();
+    (0..)
+        .map_while(|index| ioctl::enuminput::<v4l2_input>(device, index).ok())
+        .enumerate()
+        .map(|(index, input)| IoInfo {
+            index,
+            name: string_from_cstr(&input.name),
+            io_type: input.type_,
+            status: input_status(input.status),
+            current: current == Some(index),
+        })
+        .collect()
+}
+
+fn outputs(device: &Device) -> Vec<IoInfo> {
+    let current = ioctl::g_output(device).ok();
+    (0..)
+        .map_while(|index| ioctl::enumoutput::<v4l2_output>(device, index).ok())
+        .enumerate()
+        .map(|(index, output)| IoInfo {
+            index,
+            name: string_from_cstr(&output.name),
+            io_type: output.type_,
+            status: vec![],
+            current: current == Some(index),
+        })
+        .collect()
+}
+
+fn frame_intervals(
+    device: &Device,
+    pixelformat: PixelFormat,
+    w: u32,
+    h: u32,
+) -> Vec<FrameInterval> {
+    let mut intervals = Vec::new();
+    for index in 0.. {
+        let Ok(ival) =
+            ioctl::enum_frame_intervals::<v4l2_frmivalenum>(device, index, pixelformat, w, h)
+        else {
+            break;
+        };
+        match ival.intervals() {
+            Some(FrmIvalTypes::Discrete(fraction)) => {
+                intervals.push(FrameInterval::Discrete(fraction))
+            }
+            Some(FrmIvalTypes::StepWise(stepwise)) => {
+                intervals.push(FrameInterval::Stepwise {
+                    min: stepwise.min.into(),
+                    max: stepwise.max.into(),
+                    step: stepwise.step.into(),
+                });
+                // Stepwise intervals are only reported at index 0.
+                break;
+            }
+            None => break,
+        }
+    }
+
+    intervals
+}
+
+fn frame_sizes(device: &Device, pixelformat: PixelFormat) -> Vec<FrameSize> {
+    let mut sizes = Vec::new();
+    for index in 0.. {
+        let Ok(size) = ioctl::enum_frame_sizes::<v4l2_frmsizeenum>(device, index, pixelformat)
+        else {
+            break;
+        };
+        match size.size() {
+            Some(FrmSizeTypes::Discrete(discrete)) => sizes.push(FrameSize::Discrete {
+                width: discrete.width,
+                height: discrete.height,
+                intervals: frame_intervals(device, pixelformat, discrete.width, discrete.height),
+            }),
+            Some(FrmSizeTypes::StepWise(stepwise)) => {
+                sizes.push(FrameSize::Stepwise {
+                    min_width: stepwise.min_width,
+                    max_width: stepwise.max_width,
+                    step_width: stepwise.step_width,
+                    min_height: stepwise.min_height,
+                    max_height: stepwise.max_height,
+                    step_height: stepwise.step_height,
+                });
+                // Stepwise sizes are only reported at index 0.
+                break;
+            }
+            None => break,
+        }
+    }
+
+    sizes
+}
+
+fn selections(device: &Device, queue: QueueType) -> Vec<SelectionInfo> {
+    let selection_type = match queue.direction() {
+        QueueDirection::Capture => SelectionType::Capture,
+        QueueDirection::Output => SelectionType::Output,
+    };
+
+    SELECTION_TARGETS
+        .iter()
+        .filter_map(|&target| {
+            ioctl::g_selection::<Rect>(device, selection_type, target)
+                .ok()
+                .map(|rect| SelectionInfo {
+                    target: format!("{:?}", target),
+                    rect,
+                })
+        })
+        .collect()
+}
+
+fn queues(device: &Device) -> Vec<QueueInfo> {
+    QUEUE_TYPES
+        .iter()
+        .filter_map(|&queue| {
+            let formats = FormatIterator::new(device, queue)
+                .map(|desc| FormatInfo {
+                    pixelformat: desc.pixelformat,
+                    frame_sizes: frame_sizes(device, desc.pixelformat),
+                    flags: flag_names(desc.flags),
+                    description: desc.description,
+                })
+                .collect::<Vec<_>>();
+            if formats.is_empty() {
+                return None;
+            }
+
+            Some(QueueInfo {
+                queue,
+                current_format: ioctl::g_fmt::<Format>(device, queue).ok(),
+                formats,
+                selections: selections(device, queue),
+            })
+        })
+        .collect()
+}
+
+fn menu(device: &Device, id: u32, minimum: i64, maximum: i64, integer: bool) -> Vec<MenuItem> {
+    (minimum.max(0) as u32..=maximum.max(0) as u32)
+        .filter_map(|index| {
+            // Menus can have holes, for which `VIDIOC_QUERYMENU` fails.
+            let item = ioctl::querymenu::<v4l2_querymenu>(device, id, index).ok()?;
+            // SAFETY: `value` is valid for integer menus, and `name` for regular ones.
+            let name = unsafe {
+                if integer {
+                    { item.__bindgen_anon_1.value }.to_string()
+                } else {
+                    string_from_cstr(&item.__bindgen_anon_1.name)
+                }
+            };
+            Some(MenuItem { index, name })
+        })
+        .collect()
+}
+
+fn controls(device: &Device) -> Vec<ControlInfo> {
+    ControlIterator::new(device)
+        .filter(|qctrl| qctrl.type_ != bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_CTRL_CLASS)
+        .map(|qctrl| {
+            let flags = ControlFlags::from_bits_truncate(qctrl.flags);
+            let is_menu = matches!(
+                qctrl.type_,
+                bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_MENU
+                    | bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER_MENU
+            );
+            let value = if flags.contains(ControlFlags::WRITE_ONLY) {
+                None
+            } else {
+                get_control_value(device, qctrl.id)
+                    .ok()
+                    .map(|value| format!("{:?}", value))
+            };
+
+            ControlInfo {
+                id: qctrl.id,
+                name: string_from_cstr(&qctrl.name.map(|c| c as u8)),
+                ctrl_type: ctrl_type_name(qctrl.type_),
+                minimum: qctrl.minimum,
+                maximum: qctrl.maximum,
+                step: qctrl.step,
+                default: qctrl.default_value,
+                flags: flag_names(flags),
+                value,
+                menu: if is_menu {
+                    menu(
+                        device,
+                        qctrl.id,
+                        qctrl.minimum,
+                        qctrl.maximum,
+                        qctrl.type_ == bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER_MENU,
+                    )
+                } else {
+                    vec![]
+                },
+            }
+        })
+        .collect()
+}
+
+fn device_info(device: &Device) -> anyhow::Result<DeviceInfo> {
+    let caps: Capability = ioctl::querycap(device).context("VIDIOC_QUERYCAP failed")?;
+
+    Ok(DeviceInfo {
+        driver: caps.driver.clone(),
+        card: caps.card.clone(),
+        bus_info: caps.bus_info.clone(),
+        version: format!(
+            "{}.{}.{}",
+            (caps.version >> 16) & 0xff,
+            (caps.version >> 8) & 0xff,
+            caps.version & 0xff
+        ),
+        capabilities: flag_names(caps.capabilities),
+        device_caps: flag_names(caps.device_caps()),
+        inputs: inputs(device),
+        outputs: outputs(device),
+        queues: queues(device),
+        controls: controls(device),
+    })
+}
+
+fn print_io(kind: &str, ios: &[IoInfo]) {
+    for io in ios {
+        println!(
+            "{} {}: {}{}{}",
+            kind,
+            io.index,
+            io.name,
+            if io.status.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", io.status.join(", "))
+            },
+            if io.current { " (current)" } else { "" }
+        );
+    }
+}
+
+fn print_frame_interval(interval: &FrameInterval) -> String {
+    match interval {
+        FrameInterval::Discrete(fraction) => format!("{}", fraction),
+        FrameInterval::Stepwise { min, max, step } => format!("{} - {} (step {})", min, max, step),
+    }
+}
+
+fn print_info(info: &DeviceInfo) {
+    println!("Driver:       {}", info.driver);
+    println!("Card:         {}", info.card);
+    println!("Bus info:     {}", info.bus_info);
+    println!("Version:      {}", info.version);
+    println!("Capabilities: {}", info.capabilities.join(" | "));
+    println!("Device caps:  {}", info.device_caps.join(" | "));
+
+    print_io("Input", &info.inputs);
+    print_io("Output", &info.outputs);
+
+    for queue in &info.queues {
+        println!("\nQueue {:?}:", queue.queue);
+        if let Some(format) = &queue.current_format {
+            println!("  Current format: {:?}", format);
+        }
+        for format in &queue.formats {
+            println!(
+                "  {}: {}{}",
+                format.pixelformat,
+                format.description,
+                if format.flags.is_empty() {
+                    String::new()
+                } else {
+                    format!(" ({})", format.flags.join(", "))
+                }
+            );
+            for size in &format.frame_sizes {
+                match size {
+                    FrameSize::Discrete {
+                        width,
+                        height,
+                        intervals,
+                    } => println!(
+                        "    {}x{}: {}",
+                        width,
+                        height,
+                        intervals
+                            .iter()
+                            .map(print_frame_interval)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    FrameSize::Stepwise {
+                        min_width,
+                        max_width,
+                        step_width,
+                        min_height,
+                        max_height,
+                        step_height,
+                    } => println!(
+                        "    {}x{} - {}x{} (step {}x{})",
+                        min_width, min_height, max_width, max_height, step_width, step_height
+                    ),
+                }
+            }
+        }
+        for selection in &queue.selections {
+            println!("  Selection {}: {}", selection.target, selection.rect);
+        }
+    }
+
+    println!("\nControls:");
+    for control in &info.controls {
+        println!(
+            "  {:<32} 0x{:08x} ({}): min={} max={} step={} default={}{}{}",
+            control.name,
+            control.id,
+            control.ctrl_type,
+            control.minimum,
+            control.maximum,
+            control.step,
+            control.default,
+            control
+                .value
+                .as_ref()
+                .map(|value| format!(" value={}", value))
+                .unwrap_or_default(),
+            if control.flags.is_empty() {
+                String::new()
+            } else {
+                format!(" flags={}", control.flags.join(","))
+            }
+        );
+        for item in &control.menu {
+            println!("    {}: {}", item.index, item.name);
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let matches = App::new("V4L2 device information")
+        .arg(
+            Arg::with_name("device")
+                .required(true)
+                .help("Path to the device file"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the information in JSON format"),
+        )
+        .get_matches();
+
+    let device_path = matches.value_of("device").unwrap();
+    let device = Device::open(Path::new(device_path), DeviceConfig::new())
+        .context("failed to open device")?;
+    let info = device_info(&device)?;
+    if matches.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+    } else {
+        print_info(&info);
+    }
+
+    Ok(())
+}
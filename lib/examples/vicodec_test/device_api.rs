@@ -152,12 +152,8 @@ pub fn run<F: FnMut(&[u8])>(
         .expect("Failed to start output_queue");
     capture_queue.stream_on().expect("Failed to start capture");
 
-    let mut frame_gen = FrameGenerator::new(
-        output_format.width as usize,
-        output_format.height as usize,
-        output_format.plane_fmt[0].bytesperline as usize,
-    )
-    .expect("Failed to create frame generator");
+    let mut frame_gen =
+        FrameGenerator::for_format(&output_format).expect("Failed to create frame generator");
 
     let mut cpt = 0usize;
     let mut total_size = 0usize;
@@ -240,7 +236,7 @@ pub fn run<F: FnMut(&[u8])>(
             .try_dequeue()
             .expect("Failed to dequeue capture buffer");
         let cap_index = cap_dqbuf.data.index() as usize;
-        let bytes_used = *cap_dqbuf.data.get_first_plane().bytesused as usize;
+        let bytes_used = cap_dqbuf.data.plane_bytesused(0).unwrap_or(0) as usize;
 
         total_size = total_size.wrapping_add(bytes_used);
         let elapsed = start_time.elapsed();
@@ -172,12 +172,8 @@ pub fn run<F: FnMut(&[u8])>(
     streamon(&fd, output_queue).expect("Failed to start output queue");
     streamon(&fd, capture_queue).expect("Failed to start capture queue");
 
-    let mut frame_gen = FrameGenerator::new(
-        output_format.width as usize,
-        output_format.height as usize,
-        output_format.plane_fmt[0].bytesperline as usize,
-    )
-    .expect("Failed to create frame generator");
+    let mut frame_gen =
+        FrameGenerator::for_format(&output_format).expect("Failed to create frame generator");
 
     let mut cpt = 0usize;
     let mut total_size = 0usize;
@@ -249,7 +245,7 @@ pub fn run<F: FnMut(&[u8])>(
         // The CAPTURE buffer, on the other hand, we want to examine more closely.
         let cap_dqbuf: V4l2Buffer =
             dqbuf(&fd, capture_queue).expect("Failed to dequeue capture buffer");
-        let bytes_used = *cap_dqbuf.get_first_plane().bytesused as usize;
+        let bytes_used = cap_dqbuf.plane_bytesused(0).unwrap_or(0) as usize;
 
         total_size = total_size.wrapping_add(bytes_used);
         let elapsed = start_time.elapsed();
@@ -0,0 +1,425 @@
+//! Helpers for splitting encoded bitstreams into units that can be queued to stateful decoders.
+//!
+//! Stateful decoders expect each OUTPUT buffer to contain a meaningful chunk of the stream
+//! (typically one access unit), whereas encoded files are usually a continuous byte stream. The
+//! [`NalIterator`] and [`NalReader`] types split an Annex-B byte stream (as used by H.264 and HEVC)
+//! into its NAL units, from a byte slice or an [`io::Read`] respectively. Codec-specific modules
+//! can then group these NAL units further, e.g. [`h264::AccessUnitSplitter`].
+//!
+//! Only the start codes are scanned for: the content of the NAL units is not parsed and emulation
+//! prevention bytes are left untouched, which is what decoders expect anyway.
+//!
+//! Streams of codecs that do not use Annex-B are usually stored in a container instead, like the
+//! IVF files supported by the [`ivf`] module. The [`jpeg`] module parses the headers of the
+//! JPEG images making up MJPEG streams.
+
+pub mod h264;
+pub mod ivf;
+pub mod jpeg;
+
+use std::io;
+
+use log::error;
+
+/// Number of bytes following a start code that [`find_start_code`] needs to look at, if
+/// available, to determine the length of that start code.
+const START_CODE_LOOKAHEAD: usize = 4;
+
+/// Returns the offset and length of the first Annex-B start code of `data` at or after `from`.
+///
+/// Both 3-byte (`00 00 01`) and 4-byte (`00 00 00 01`) start codes are recognized. The zero byte
+/// of a 4-byte start code is only considered part of it if the start code is followed by data: a
+/// start code directly followed by another one or by the end of `data` does not start a NAL unit,
+/// so the zero byte preceding it is a trailing zero of the previous NAL unit. Additional zero bytes
+/// preceding a 4-byte start code are never considered part of it.
+fn find_start_code(data: &[u8], from: usize) -> Option<(usize, usize)> {
+    let pos = from + data.get(from..)?.windows(3).position(|w| w == [0, 0, 1])?;
+    let following = &data[pos + 3..];
+    let has_data = !(following.is_empty()
+        || following.starts_with(&[0, 0, 1])
+        || following.starts_with(&[0, 0, 0, 1]));
+
+    if pos > 0 && data[pos - 1] == 0 && has_data {
+        Some((pos - 1, 4))
+    } else {
+        Some((pos, 3))
+    }
+}
+
+/// A NAL unit of an Annex-B stream.
+///
+/// `D` is the storage of the data, typically a `&[u8]` when iterating over a slice with
+/// [`NalIterator`], or a `Vec<u8>` when reading a stream with [`NalReader`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NalUnit<D: AsRef<[u8]>> {
+    data: D,
+    start_code_len: usize,
+}
+
+impl<D: AsRef<[u8]>> NalUnit<D> {
+    /// Returns the whole NAL unit, start code included.
+    ///
+    /// Zero bytes following the NAL unit in the stream (e.g. `trailing_zero_8bits`) are included,
+    /// so the NAL units of a stream can be queued as-is.
+    pub fn data(&self) -> &[u8] {
+        self.data.as_ref()
+    }
+
+    /// Returns the length of the start code at the beginning of [`NalUnit::data`], i.e. 3 or 4.
+    pub fn start_code_len(&self) -> usize {
+        self.start_code_len
+    }
+
+    /// Returns the NAL unit without its start code, i.e. starting with its header.
+    pub fn payload(&self) -> &[u8] {
+        &self.data()[self.start_code_len..]
+    }
+
+    /// Consumes the NAL unit and returns its data, start code included.
+    pub fn into_data(self) -> D {
+        self.data
+    }
+}
+
+/// Iterator over the NAL units of an Annex-B stream contained in a byte slice.
+///
+/// Data preceding the first start code is skipped, and start codes that are not followed by any
+/// data are ignored.
+pub struct NalIterator<'a> {
+    data: &'a [u8],
+    /// Offset and length of the start code of the next NAL unit.
+    next: Option<(usize, usize)>,
+}
+
+impl<'a> NalIterator<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            next: find_start_code(data, 0),
+        }
+    }
+}
+
+impl<'a> Iterator for NalIterator<'a> {
+    type Item = NalUnit<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (start, start_code_len) = self.next?;
+            self.next = find_start_code(self.data, start + start_code_len);
+            let end = self.next.map(|(next, _)| next).unwrap_or(self.data.len());
+
+            if end > start + start_code_len {
+                return Some(NalUnit {
+                    data: &self.data[start..end],
+                    start_code_len,
+                });
+            }
+        }
+    }
+}
+
+/// Iterator over the NAL units of an Annex-B stream read from an [`io::Read`].
+///
+/// This behaves like [`NalIterator`], but reads the stream progressively and returns owned NAL
+/// units. Read errors are logged and end the iteration.
+pub struct NalReader<R: io::Read> {
+    reader: R,
+    /// Data read from `reader` and not returned yet. Once the first start code has been found,
+    /// always starts with the start code of the next NAL unit.
+    buffer: Vec<u8>,
+    /// Length of the start code at the beginning of `buffer`, if one has been found yet.
+    start_code_len: Option<usize>,
+    /// Offset from which to resume the search for the next start code in `buffer`.
+    scan_from: usize,
+    eof: bool,
+}
+
+impl<R: io::Read> NalReader<R> {
+    /// Size of the chunks read from the underlying reader.
+    const READ_SIZE: usize = 0x10000;
+
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::with_capacity(Self::READ_SIZE),
+            start_code_len: None,
+            scan_from: 0,
+            eof: false,
+        }
+    }
+
+    /// Returns whether `buffer` contains enough data after the start code ending at `end` for
+    /// [`find_start_code`] to have determined its length.
+    fn has_lookahead(&self, end: usize) -> bool {
+        self.eof || end + START_CODE_LOOKAHEAD <= self.buffer.len()
+    }
+
+    /// Appends the next chunk of the stream to `buffer`, or sets `eof` if the end of the stream
+    /// has been reached or an error occurred.
+    fn fill_buffer(&mut self) {
+        let len = self.buffer.len();
+        self.buffer.resize(len + Self::READ_SIZE, 0);
+
+        loop {
+            match self.reader.read(&mut self.buffer[len..]) {
+                Ok(read) => {
+                    self.eof = read == 0;
+                    self.buffer.truncate(len + read);
+                    return;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    error!("Error while reading stream: {}", e);
+                    self.eof = true;
+                    self.buffer.truncate(len);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<R: io::Read> Iterator for NalReader<R> {
+    type Item = NalUnit<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.start_code_len {
+                // Skip everything until the first start code.
+                None => match find_start_code(&self.buffer, 0) {
+                    Some((start, start_code_len))
+                        if !self.has_lookahead(start + start_code_len) =>
+                    {
+                        self.fill_buffer();
+                    }
+                    Some((start, start_code_len)) => {
+                        self.buffer.drain(..start);
+                        self.start_code_len = Some(start_code_len);
+                        self.scan_from = start_code_len;
+                    }
+                    None if self.eof => {
+                        self.buffer.clear();
+                        return None;
+                    }
+                    None => {
+                        // Keep the bytes that may be the beginning of a start code.
+                        let garbage = self.buffer.len().saturating_sub(3);
+                        self.buffer.drain(..garbage);
+                        self.fill_buffer();
+                    }
+                },
+                Some(start_code_len) => {
+                    let end = match find_start_code(&self.buffer, self.scan_from) {
+                        Some((end, next_start_code_len))
+                            if !self.has_lookahead(end + next_start_code_len) =>
+                        {
+                            self.fill_buffer();
+                            continue;
+                        }
+                        Some((end, next_start_code_len)) => {
+                            self.start_code_len = Some(next_start_code_len);
+                            self.scan_from = next_start_code_len;
+                            end
+                        }
+                        None if self.eof => {
+                            self.start_code_len = None;
+                            self.scan_from = 0;
+                            self.buffer.len()
+                        }
+                        None => {
+                            // A start code may straddle the current end of the buffer.
+                            self.scan_from =
+                                self.buffer.len().saturating_sub(2).max(start_code_len);
+                            self.fill_buffer();
+                            continue;
+                        }
+                    };
+
+                    let remaining = self.buffer.split_off(end);
+                    let data = std::mem::replace(&mut self.buffer, remaining);
+
+                    if data.len() > start_code_len {
+                        return Some(NalUnit {
+                            data,
+                            start_code_len,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io;
+
+    use super::{NalIterator, NalReader};
+
+    /// xorshift generator, used to produce reproducible pseudo-random streams.
+    pub(crate) struct Rng(u64);
+
+    impl Rng {
+        pub(crate) fn new(seed: u64) -> Self {
+            Self(seed | 1)
+        }
+
+        pub(crate) fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// Returns a random number in `0..max`.
+        pub(crate) fn below(&mut self, max: usize) -> usize {
+            (self.next() % max as u64) as usize
+        }
+
+        /// Returns a random buffer of `len` bytes, biased towards zeroes and ones so start codes
+        /// appear often.
+        pub(crate) fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len)
+                .map(|_| match self.below(4) {
+                    0 | 1 => 0,
+                    2 => 1,
+                    _ => self.next() as u8,
+                })
+                .collect()
+        }
+    }
+
+    /// Reader returning data in small chunks of random size, to exercise the boundary cases of
+    /// `NalReader`.
+    pub(crate) struct ChunkedReader<'a> {
+        data: &'a [u8],
+        rng: Rng,
+    }
+
+    impl<'a> ChunkedReader<'a> {
+        pub(crate) fn new(data: &'a [u8], seed: u64) -> Self {
+            Self {
+                data,
+                rng: Rng::new(seed),
+            }
+        }
+    }
+
+    impl<'a> io::Read for ChunkedReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = (1 + self.rng.below(7)).min(buf.len()).min(self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    fn slice_nals(data: &[u8]) -> Vec<(Vec<u8>, usize)> {
+        NalIterator::new(data)
+            .map(|nal| (nal.data().to_vec(), nal.start_code_len()))
+            .collect()
+    }
+
+    fn reader_nals(data: &[u8], seed: u64) -> Vec<(Vec<u8>, usize)> {
+        NalReader::new(ChunkedReader::new(data, seed))
+            .map(|nal| {
+                let start_code_len = nal.start_code_len();
+                (nal.into_data(), start_code_len)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_nal_iterator() {
+        let stream = [
+            0xff, 0x00, // garbage
+            0x00, 0x00, 0x00, 0x01, 0x67, 0x42, // 4-byte start code
+            0x00, 0x00, 0x01, 0x68, 0xce, 0x00, // 3-byte start code, trailing zero
+            0x00, 0x00, 0x01, // empty NAL unit
+            0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x00, 0x00, // truncated start code
+        ];
+
+        let nals = slice_nals(&stream);
+        assert_eq!(
+            nals,
+            vec![
+                (vec![0x00, 0x00, 0x00, 0x01, 0x67, 0x42], 4),
+                (vec![0x00, 0x00, 0x01, 0x68, 0xce, 0x00], 3),
+                (vec![0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x00, 0x00], 4),
+            ]
+        );
+        assert_eq!(
+            NalIterator::new(&stream).nth(1).unwrap().payload(),
+            [0x68, 0xce, 0x00]
+        );
+
+        for seed in 1..16 {
+            assert_eq!(reader_nals(&stream, seed), nals);
+        }
+
+        assert_eq!(NalIterator::new(&[]).count(), 0);
+        assert_eq!(NalIterator::new(&[0x00, 0x00, 0x01]).count(), 0);
+        assert_eq!(NalIterator::new(&[0x12, 0x00, 0x00, 0x02]).count(), 0);
+        assert_eq!(NalReader::new(&[0x00, 0x00, 0x01][..]).count(), 0);
+
+        // The zero preceding a start code that is followed by data starts the next NAL unit.
+        let stream = [0x00, 0x00, 0x01, 0x68, 0x00, 0x00, 0x00, 0x01, 0x65];
+        let nals = slice_nals(&stream);
+        assert_eq!(
+            nals,
+            vec![
+                (vec![0x00, 0x00, 0x01, 0x68], 3),
+                (vec![0x00, 0x00, 0x00, 0x01, 0x65], 4),
+            ]
+        );
+        for seed in 1..16 {
+            assert_eq!(reader_nals(&stream, seed), nals);
+        }
+        // Unless it is the end of the stream.
+        assert_eq!(
+            slice_nals(&stream[..8]),
+            vec![(vec![0x00, 0x00, 0x01, 0x68, 0x00], 3)]
+        );
+    }
+
+    #[test]
+    fn test_nal_splitting_random() {
+        let mut rng = Rng::new(0x4e41_4c55);
+
+        for seed in 0..200 {
+            let len = rng.below(300);
+            let stream = rng.bytes(len);
+            let nals = slice_nals(&stream);
+
+            // NAL units are non-empty, start with a single start code, and appear in the stream
+            // in order.
+            let mut offset = 0;
+            for (data, start_code_len) in &nals {
+                let (start_code, payload) = data.split_at(*start_code_len);
+                assert!(!payload.is_empty());
+                assert!(start_code.ends_with(&[0, 0, 1]));
+                assert!(!payload.windows(3).any(|w| w == [0, 0, 1]));
+
+                let pos = offset
+                    + stream[offset..]
+                        .windows(data.len())
+                        .position(|w| w == &data[..])
+                        .unwrap();
+                offset = pos + data.len();
+            }
+            // Anything preceding the first start code is garbage, so if there is no NAL unit, only
+            // the part of the stream from the first start code is constrained.
+            if nals.is_empty() {
+                offset = stream
+                    .windows(3)
+                    .position(|w| w == [0, 0, 1])
+                    .unwrap_or(stream.len());
+            }
+            // Only start codes without data can follow the last NAL unit.
+            assert!(stream[offset..].iter().all(|&b| b <= 1));
+
+            assert_eq!(reader_nals(&stream, seed), nals);
+        }
+    }
+}
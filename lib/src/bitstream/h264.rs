@@ -0,0 +1,296 @@
+//! H.264 specific helpers: NAL unit types and grouping of NAL units into access units.
+
+use std::io;
+
+use enumn::N;
+
+use super::{NalIterator, NalReader, NalUnit};
+use crate::decoder::format::StreamSplitter;
+
+/// Type of a H.264 NAL unit, i.e. `nal_unit_type` (table 7-1 of the specification).
+#[repr(u8)]
+#[derive(N, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NalUnitType {
+    Unspecified = 0,
+    Slice = 1,
+    SliceDataA = 2,
+    SliceDataB = 3,
+    SliceDataC = 4,
+    SliceIdr = 5,
+    Sei = 6,
+    Sps = 7,
+    Pps = 8,
+    AccessUnitDelimiter = 9,
+    EndOfSequence = 10,
+    EndOfStream = 11,
+    FillerData = 12,
+    SpsExtension = 13,
+    PrefixNal = 14,
+    SubsetSps = 15,
+    Dps = 16,
+    AuxiliarySlice = 19,
+    SliceExtension = 20,
+    SliceExtensionDepth = 21,
+}
+
+impl NalUnitType {
+    /// Returns whether this NAL unit contains the first slice data partition of a picture, and
+    /// thus the `first_mb_in_slice` syntax element.
+    fn is_first_partition(&self) -> bool {
+        matches!(
+            self,
+            NalUnitType::Slice | NalUnitType::SliceDataA | NalUnitType::SliceIdr
+        )
+    }
+
+    /// Returns whether this NAL unit contains slice data of the primary coded picture.
+    pub fn is_vcl(&self) -> bool {
+        matches!(
+            self,
+            NalUnitType::Slice
+                | NalUnitType::SliceDataA
+                | NalUnitType::SliceDataB
+                | NalUnitType::SliceDataC
+                | NalUnitType::SliceIdr
+        )
+    }
+}
+
+impl<D: AsRef<[u8]>> NalUnit<D> {
+    /// Returns the H.264 type of this NAL unit, or `None` if it is a reserved value.
+    pub fn h264_type(&self) -> Option<NalUnitType> {
+        NalUnitType::n(self.payload()[0] & 0x1f)
+    }
+
+    /// Returns whether this NAL unit is the first slice of a picture, i.e. whether its
+    /// `first_mb_in_slice` is zero. Always `false` if the NAL unit does not contain a slice header.
+    fn h264_is_first_slice(&self) -> bool {
+        match self.h264_type() {
+            // `first_mb_in_slice` is the first syntax element after the header and is coded as
+            // `ue(v)`, so it is zero if and only if the first bit is set.
+            Some(nal_type) if nal_type.is_first_partition() => {
+                matches!(self.payload().get(1), Some(b) if b & 0x80 != 0)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns whether this NAL unit starts a new access unit if it follows an access unit that
+    /// already contains a picture (section 7.4.1.2.3 of the specification).
+    fn h264_starts_access_unit(&self) -> bool {
+        match self.h264_type() {
+            Some(
+                NalUnitType::Sei
+                | NalUnitType::Sps
+                | NalUnitType::Pps
+                | NalUnitType::AccessUnitDelimiter
+                | NalUnitType::PrefixNal
+                | NalUnitType::SubsetSps
+                | NalUnitType::Dps,
+            ) => true,
+            // Reserved types 17 and 18 also start a new access unit.
+            None => matches!(self.payload()[0] & 0x1f, 17 | 18),
+            _ => self.h264_is_first_slice(),
+        }
+    }
+}
+
+/// Groups the NAL units of a H.264 stream into access units, i.e. the data for one picture, which
+/// is what stateful decoders expect in each OUTPUT buffer.
+///
+/// A new access unit is started when an access unit delimiter, a parameter set or a SEI is met
+/// after a picture, or when a slice with a `first_mb_in_slice` of zero is met. This is a heuristic
+/// that works for progressive streams with a single slice group, without parsing slice headers.
+/// NAL units preceding the first picture are returned with it.
+pub struct AccessUnitSplitter<I: Iterator> {
+    nals: I,
+    /// First NAL unit of the next access unit, already read from `nals`.
+    pending: Option<I::Item>,
+}
+
+impl<I, D> AccessUnitSplitter<I>
+where
+    I: Iterator<Item = NalUnit<D>>,
+    D: AsRef<[u8]>,
+{
+    pub fn new(nals: I) -> Self {
+        Self {
+            nals,
+            pending: None,
+        }
+    }
+}
+
+impl<'a> AccessUnitSplitter<NalIterator<'a>> {
+    /// Creates a splitter for the Annex-B stream contained in `data`.
+    pub fn from_slice(data: &'a [u8]) -> Self {
+        Self::new(NalIterator::new(data))
+    }
+}
+
+impl<R: io::Read> AccessUnitSplitter<NalReader<R>> {
+    /// Creates a splitter for the Annex-B stream read from `reader`.
+    pub fn from_reader(reader: R) -> Self {
+        Self::new(NalReader::new(reader))
+    }
+}
+
+impl<I, D> Iterator for AccessUnitSplitter<I>
+where
+    I: Iterator<Item = NalUnit<D>>,
+    D: AsRef<[u8]>,
+{
+    type Item = Vec<u8>;
+
+    /// Returns the data of the next access unit, start codes included.
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = self.pending.take().or_else(|| self.nals.next())?;
+        let mut has_picture = matches!(first.h264_type(), Some(t) if t.is_vcl());
+        let mut access_unit = first.data().to_vec();
+
+        for nal in self.nals.by_ref() {
+            if has_picture && nal.h264_starts_access_unit() {
+                self.pending = Some(nal);
+                break;
+            }
+
+            has_picture |= matches!(nal.h264_type(), Some(t) if t.is_vcl());
+            access_unit.extend_from_slice(nal.data());
+        }
+
+        Some(access_unit)
+    }
+}
+
+impl<I, D> StreamSplitter for AccessUnitSplitter<I>
+where
+    I: Iterator<Item = NalUnit<D>>,
+    D: AsRef<[u8]>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessUnitSplitter, NalUnitType};
+    use crate::bitstream::{
+        tests::{ChunkedReader, Rng},
+        NalIterator,
+    };
+
+    /// Returns a NAL unit of type `nal_type`, with a 4-byte start code and `first_byte` as the
+    /// first byte after the header.
+    fn nal(nal_type: u8, first_byte: u8) -> Vec<u8> {
+        vec![0x00, 0x00, 0x00, 0x01, 0x60 | nal_type, first_byte, 0x42]
+    }
+
+    /// Returns a small stream made of parameter sets and three pictures, the second one split into
+    /// two slices. Also returns the expected access units.
+    fn stream() -> (Vec<u8>, Vec<Vec<u8>>) {
+        let access_units = vec![
+            [nal(9, 0xf0), nal(7, 0x64), nal(8, 0xee), nal(5, 0x88)].concat(),
+            [nal(1, 0x9a), nal(1, 0x42)].concat(),
+            [nal(6, 0x05), nal(1, 0x9a), nal(12, 0xff), nal(10, 0x00)].concat(),
+        ];
+
+        (access_units.concat(), access_units)
+    }
+
+    #[test]
+    fn test_nal_unit_type() {
+        let (stream, _) = stream();
+        let types = NalIterator::new(&stream)
+            .map(|nal| nal.h264_type())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            types,
+            [
+                NalUnitType::AccessUnitDelimiter,
+                NalUnitType::Sps,
+                NalUnitType::Pps,
+                NalUnitType::SliceIdr,
+                NalUnitType::Slice,
+                NalUnitType::Slice,
+                NalUnitType::Sei,
+                NalUnitType::Slice,
+                NalUnitType::FillerData,
+                NalUnitType::EndOfSequence,
+            ]
+            .map(Some)
+        );
+        assert_eq!(NalUnitType::n(17), None);
+    }
+
+    #[test]
+    fn test_access_unit_splitter() {
+        let (stream, access_units) = stream();
+
+        assert_eq!(
+            AccessUnitSplitter::from_slice(&stream).collect::<Vec<_>>(),
+            access_units
+        );
+        assert_eq!(
+            AccessUnitSplitter::from_reader(ChunkedReader::new(&stream, 1)).collect::<Vec<_>>(),
+            access_units
+        );
+
+        // Garbage before the first start code is dropped.
+        let garbage = [&[0x12, 0x34, 0x00][..], &stream[..]].concat();
+        assert_eq!(
+            AccessUnitSplitter::from_slice(&garbage).collect::<Vec<_>>(),
+            access_units
+        );
+
+        // Streams without pictures are returned as a single unit.
+        let headers = [nal(7, 0x64), nal(8, 0xee)].concat();
+        assert_eq!(
+            AccessUnitSplitter::from_slice(&headers).collect::<Vec<_>>(),
+            vec![headers]
+        );
+
+        assert_eq!(AccessUnitSplitter::from_slice(&[]).count(), 0);
+    }
+
+    /// Truncated, corrupted and random streams must never make the splitter panic, and must not
+    /// lose any data past the first start code.
+    #[test]
+    fn test_access_unit_splitter_corrupted() {
+        let (stream, _) = stream();
+        let mut rng = Rng::new(0x4832_3634);
+
+        for seed in 0..500 {
+            let mut data = match seed % 3 {
+                // Truncated stream.
+                0 => stream[..rng.below(stream.len() + 1)].to_vec(),
+                // Corrupted stream.
+                1 => {
+                    let mut data = stream.clone();
+                    for _ in 0..=rng.below(8) {
+                        let pos = rng.below(data.len());
+                        data[pos] = rng.next() as u8;
+                    }
+                    data
+                }
+                // Random data.
+                _ => {
+                    let len = rng.below(200);
+                    rng.bytes(len)
+                }
+            };
+            // Append some trailing garbage.
+            let len = rng.below(4);
+            data.extend(rng.bytes(len));
+
+            let nals = NalIterator::new(&data)
+                .flat_map(|nal| nal.data().to_vec())
+                .collect::<Vec<_>>();
+            let from_slice = AccessUnitSplitter::from_slice(&data).collect::<Vec<_>>();
+            let from_reader = AccessUnitSplitter::from_reader(ChunkedReader::new(&data, seed))
+                .collect::<Vec<_>>();
+
+            assert!(from_slice.iter().all(|access_unit| !access_unit.is_empty()));
+            assert_eq!(from_slice.concat(), nals);
+            assert_eq!(from_reader, from_slice);
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! Reader and writer for the IVF container, commonly used to store VP8, VP9 and AV1 streams.
+//!
+//! An IVF file is made of a 32-byte header, followed by each frame prefixed by a 12-byte header
+//! containing its size and timestamp. All values are little-endian.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use thiserror::Error;
+
+use crate::PixelFormat;
+
+/// Signature at the beginning of every IVF file.
+const IVF_SIGNATURE: [u8; 4] = *b"DKIF";
+/// Size of the file header.
+const IVF_HEADER_SIZE: usize = 32;
+/// Size of the header preceding each frame.
+const IVF_FRAME_HEADER_SIZE: usize = 12;
+/// Offset of the number of frames in the file header.
+const IVF_NUM_FRAMES_OFFSET: u64 = 24;
+
+/// Frames larger than this are rejected by [`IvfReader`], as they most likely denote a corrupted
+/// file.
+pub const IVF_MAX_FRAME_SIZE: u32 = 64 << 20;
+
+#[derive(Debug, Error)]
+pub enum IvfError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid IVF signature {0:x?}")]
+    InvalidSignature([u8; 4]),
+    #[error("unsupported IVF version {0}")]
+    UnsupportedVersion(u16),
+    #[error("invalid IVF header size {0}")]
+    InvalidHeaderSize(u16),
+    #[error("file ends within a header")]
+    TruncatedHeader,
+    #[error("frame of {size} bytes exceeds the maximum of {max} bytes")]
+    FrameTooLarge { size: usize, max: usize },
+    #[error("frame of {declared} bytes is truncated to {read} bytes")]
+    TruncatedFrame { declared: usize, read: usize },
+}
+
+/// Header of an IVF file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IvfHeader {
+    /// Codec of the stream, e.g. [`PixelFormat::VP8`].
+    pub fourcc: PixelFormat,
+    pub width: u16,
+    pub height: u16,
+    /// Denominator of the time base, i.e. the number of timestamp units per second when
+    /// `timebase_numerator` is 1.
+    pub timebase_denominator: u32,
+    pub timebase_numerator: u32,
+    /// Number of frames in the file. Often unreliable, as some writers leave it to zero.
+    pub num_frames: u32,
+}
+
+impl IvfHeader {
+    /// Returns a header for a stream of `fourcc` frames of `width`x`height` pixels, which
+    /// timestamps are expressed in units of `timebase_numerator / timebase_denominator` seconds.
+    pub fn new(
+        fourcc: PixelFormat,
+        width: u16,
+        height: u16,
+        timebase_numerator: u32,
+        timebase_denominator: u32,
+    ) -> Self {
+        Self {
+            fourcc,
+            width,
+            height,
+            timebase_denominator,
+            timebase_numerator,
+            num_frames: 0,
+        }
+    }
+
+    /// Converts `timestamp`, expressed in units of the time base, into microseconds. Returns
+    /// `None` if the time base is invalid or the result overflows.
+    pub fn timestamp_to_us(&self, timestamp: u64) -> Option<u64> {
+        let us = timestamp as u128 * self.timebase_numerator as u128 * 1_000_000
+            / std::num::NonZeroU128::new(self.timebase_denominator as u128)?;
+
+        u64::try_from(us).ok()
+    }
+
+    fn parse(data: &[u8; IVF_HEADER_SIZE]) -> Result<Self, IvfError> {
+        let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+
+        let signature = [data[0], data[1], data[2], data[3]];
+        if signature != IVF_SIGNATURE {
+            return Err(IvfError::InvalidSignature(signature));
+        }
+        match u16_at(4) {
+            0 => (),
+            version => return Err(IvfError::UnsupportedVersion(version)),
+        }
+        match u16_at(6) {
+            size if size as usize == IVF_HEADER_SIZE => (),
+            size => return Err(IvfError::InvalidHeaderSize(size)),
+        }
+
+        Ok(Self {
+            fourcc: PixelFormat::from_u32(u32_at(8)),
+            width: u16_at(12),
+            height: u16_at(14),
+            timebase_denominator: u32_at(16),
+            timebase_numerator: u32_at(20),
+            num_frames: u32_at(24),
+        })
+    }
+
+    fn to_bytes(self) -> [u8; IVF_HEADER_SIZE] {
+        let mut data = [0u8; IVF_HEADER_SIZE];
+
+        data[0..4].copy_from_slice(&IVF_SIGNATURE);
+        // Version 0, followed by the header size.
+        data[6..8].copy_from_slice(&(IVF_HEADER_SIZE as u16).to_le_bytes());
+        data[8..12].copy_from_slice(&self.fourcc.to_u32().to_le_bytes());
+        data[12..14].copy_from_slice(&self.width.to_le_bytes());
+        data[14..16].copy_from_slice(&self.height.to_le_bytes());
+        data[16..20].copy_from_slice(&self.timebase_denominator.to_le_bytes());
+        data[20..24].copy_from_slice(&self.timebase_numerator.to_le_bytes());
+        data[24..28].copy_from_slice(&self.num_frames.to_le_bytes());
+
+        data
+    }
+}
+
+/// A frame read from an IVF file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IvfFrame {
+    /// Presentation timestamp of the frame, in units of the time base of the file.
+    pub timestamp: u64,
+    pub data: Vec<u8>,
+}
+
+/// Fills `buf` from `reader`, returning the number of bytes read. This is only smaller than the
+/// size of `buf` if the end of the stream has been reached.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(len) => read += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(read)
+}
+
+/// Reads the frames of an IVF file.
+///
+/// Iterating over the reader returns the frames of the file until its end, or until an error
+/// occurs, in which case the error is returned and the iteration stops.
+pub struct IvfReader<R: Read> {
+    reader: R,
+    header: IvfHeader,
+    done: bool,
+}
+
+impl<R: Read> IvfReader<R> {
+    /// Reads and validates the IVF header of `reader`.
+    pub fn new(mut reader: R) -> Result<Self, IvfError> {
+        let mut header = [0u8; IVF_HEADER_SIZE];
+        if read_full(&mut reader, &mut header)? < IVF_HEADER_SIZE {
+            return Err(IvfError::TruncatedHeader);
+        }
+
+        Ok(Self {
+            reader,
+            header: IvfHeader::parse(&header)?,
+            done: false,
+        })
+    }
+
+    pub fn header(&self) -> &IvfHeader {
+        &self.header
+    }
+
+    /// Reads the next frame of the file, or returns `None` if the end of the file has been
+    /// reached.
+    pub fn read_frame(&mut self) -> Result<Option<IvfFrame>, IvfError> {
+        let mut header = [0u8; IVF_FRAME_HEADER_SIZE];
+        match read_full(&mut self.reader, &mut header)? {
+            0 => return Ok(None),
+            IVF_FRAME_HEADER_SIZE => (),
+            _ => return Err(IvfError::TruncatedHeader),
+        }
+
+        let size = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&header[4..12]);
+        let timestamp = u64::from_le_bytes(timestamp);
+
+        if size > IVF_MAX_FRAME_SIZE as usize {
+            return Err(IvfError::FrameTooLarge {
+                size,
+                max: IVF_MAX_FRAME_SIZE as usize,
+            });
+        }
+
+        // Do not trust the declared size for the allocation, in case the file is truncated.
+        let mut data = Vec::new();
+        let read = (&mut self.reader)
+            .take(size as u64)
+            .read_to_end(&mut data)?;
+        if read < size {
+            return Err(IvfError::TruncatedFrame {
+                declared: size,
+                read,
+            });
+        }
+
+        Ok(Some(IvfFrame { timestamp, data }))
+    }
+}
+
+impl<R: Read> Iterator for IvfReader<R> {
+    type Item = Result<IvfFrame, IvfError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let res = self.read_frame().transpose();
+        self.done = !matches!(res, Some(Ok(_)));
+
+        res
+    }
+}
+
+/// Writes frames into an IVF file.
+pub struct IvfWriter<W: Write> {
+    writer: W,
+    num_frames: u32,
+}
+
+impl<W: Write> IvfWriter<W> {
+    /// Writes `header` into `writer` and returns a writer ready to receive frames.
+    ///
+    /// The `num_frames` member of `header` is written as-is. Use [`IvfWriter::finish`] to update
+    /// it once all the frames have been written if `writer` is seekable.
+    pub fn new(mut writer: W, header: &IvfHeader) -> Result<Self, IvfError> {
+        writer.write_all(&header.to_bytes())?;
+
+        Ok(Self {
+            writer,
+            num_frames: 0,
+        })
+    }
+
+    /// Appends a frame with data `data` and timestamp `timestamp`, expressed in units of the time
+    /// base of the file.
+    pub fn write_frame(&mut self, timestamp: u64, data: &[u8]) -> Result<(), IvfError> {
+        let size = u32::try_from(data.len()).map_err(|_| IvfError::FrameTooLarge {
+            size: data.len(),
+            max: u32::MAX as usize,
+        })?;
+
+        let mut header = [0u8; IVF_FRAME_HEADER_SIZE];
+        header[0..4].copy_from_slice(&size.to_le_bytes());
+        header[4..12].copy_from_slice(&timestamp.to_le_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)?;
+        self.num_frames = self.num_frames.saturating_add(1);
+
+        Ok(())
+    }
+
+    /// Returns the number of frames written so far.
+    pub fn num_frames(&self) -> u32 {
+        self.num_frames
+    }
+
+    /// Returns the underlying writer, without updating the number of frames in the header.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Seek> IvfWriter<W> {
+    /// Updates the number of frames in the header of the file, and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, IvfError> {
+        let pos = self.writer.stream_position()?;
+        self.writer.seek(SeekFrom::Start(IVF_NUM_FRAMES_OFFSET))?;
+        self.writer.write_all(&self.num_frames.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(pos))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{IvfError, IvfFrame, IvfHeader, IvfReader, IvfWriter, IVF_MAX_FRAME_SIZE};
+    use crate::bitstream::tests::Rng;
+    use crate::PixelFormat;
+
+    fn write_file(frames: &[(u64, &[u8])]) -> Vec<u8> {
+        let header = IvfHeader::new(PixelFormat::VP8, 176, 144, 1, 30);
+        let mut writer = IvfWriter::new(Cursor::new(Vec::new()), &header).unwrap();
+        for (timestamp, data) in frames {
+            writer.write_frame(*timestamp, data).unwrap();
+        }
+        assert_eq!(writer.num_frames(), frames.len() as u32);
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_ivf_roundtrip() {
+        let file = write_file(&[(0, &[0x10, 0x02, 0x00]), (1, &[]), (3, &[0xff; 20])]);
+
+        assert_eq!(file.len(), 32 + 3 * 12 + 23);
+        assert_eq!(&file[0..4], b"DKIF");
+        assert_eq!(&file[8..12], b"VP80");
+        // Frame header: size, then timestamp.
+        assert_eq!(&file[32..44], [3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut reader = IvfReader::new(&file[..]).unwrap();
+        assert_eq!(
+            *reader.header(),
+            IvfHeader {
+                fourcc: PixelFormat::VP8,
+                width: 176,
+                height: 144,
+                timebase_denominator: 30,
+                timebase_numerator: 1,
+                num_frames: 3,
+            }
+        );
+        assert_eq!(reader.header().timestamp_to_us(3), Some(100_000));
+
+        let frames = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            frames,
+            vec![
+                IvfFrame {
+                    timestamp: 0,
+                    data: vec![0x10, 0x02, 0x00],
+                },
+                IvfFrame {
+                    timestamp: 1,
+                    data: vec![],
+                },
+                IvfFrame {
+                    timestamp: 3,
+                    data: vec![0xff; 20],
+                },
+            ]
+        );
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_ivf_invalid_header() {
+        let file = write_file(&[]);
+
+        assert!(matches!(
+            IvfReader::new(&file[..31]),
+            Err(IvfError::TruncatedHeader)
+        ));
+
+        let mut bad = file.clone();
+        bad[0] = b'R';
+        assert!(matches!(
+            IvfReader::new(&bad[..]),
+            Err(IvfError::InvalidSignature(s)) if &s == b"RKIF"
+        ));
+
+        let mut bad = file.clone();
+        bad[4] = 1;
+        assert!(matches!(
+            IvfReader::new(&bad[..]),
+            Err(IvfError::UnsupportedVersion(1))
+        ));
+
+        let mut bad = file;
+        bad[6] = 64;
+        assert!(matches!(
+            IvfReader::new(&bad[..]),
+            Err(IvfError::InvalidHeaderSize(64))
+        ));
+    }
+
+    #[test]
+    fn test_ivf_invalid_frames() {
+        let file = write_file(&[(0, &[1, 2, 3, 4]), (1, &[5, 6, 7, 8])]);
+
+        // Frame larger than the remaining data.
+        let mut reader = IvfReader::new(&file[..file.len() - 1]).unwrap();
+        assert!(matches!(reader.next(), Some(Ok(_))));
+        assert!(matches!(
+            reader.next(),
+            Some(Err(IvfError::TruncatedFrame {
+                declared: 4,
+                read: 3
+            }))
+        ));
+        assert!(reader.next().is_none());
+
+        // File ending within a frame header.
+        let mut reader = IvfReader::new(&file[..32 + 16 + 6]).unwrap();
+        assert!(matches!(reader.next(), Some(Ok(_))));
+        assert!(matches!(
+            reader.next(),
+            Some(Err(IvfError::TruncatedHeader))
+        ));
+
+        // Frame larger than the maximum size.
+        let mut bad = file;
+        bad[32..36].copy_from_slice(&(IVF_MAX_FRAME_SIZE + 1).to_le_bytes());
+        let mut reader = IvfReader::new(&bad[..]).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(IvfError::FrameTooLarge { .. }))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    /// Corrupted files must result in errors, never in panics.
+    #[test]
+    fn test_ivf_corrupted() {
+        let file = write_file(&[(0, &[0x9d; 100]), (1, &[0x01; 50]), (2, &[0x2a; 10])]);
+        let mut rng = Rng::new(0x4956_4600);
+
+        for _ in 0..500 {
+            let mut data = file[..rng.below(file.len() + 1)].to_vec();
+            for _ in 0..rng.below(6) {
+                let pos = rng.below(data.len().max(1));
+                if let Some(b) = data.get_mut(pos) {
+                    *b = rng.next() as u8;
+                }
+            }
+
+            if let Ok(reader) = IvfReader::new(&data[..]) {
+                // Every frame takes at least a frame header, and iteration stops after an error.
+                assert!(reader.count() <= (data.len() - 32) / 12 + 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Parsing of the headers of JPEG images, as found in the frames of MJPEG streams.
+//!
+//! Only the markers preceding the first scan are parsed, which is enough to obtain the dimensions
+//! and chroma subsampling of an image without decoding it.
+
+use thiserror::Error;
+
+use crate::controls::jpeg::JpegChromaSubsampling;
+
+const MARKER_SOI: u8 = 0xd8;
+const MARKER_EOI: u8 = 0xd9;
+const MARKER_SOS: u8 = 0xda;
+const MARKER_DHT: u8 = 0xc4;
+const MARKER_TEM: u8 = 0x01;
+
+/// Returns whether `marker` is one of the start of frame markers, `SOF0` to `SOF15`.
+fn is_sof(marker: u8) -> bool {
+    // `DHT`, `JPG` and `DAC` share the range of the SOF markers.
+    matches!(marker, 0xc0..=0xcf) && !matches!(marker, MARKER_DHT | 0xc8 | 0xcc)
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum JpegHeaderError {
+    #[error("data does not start with a SOI marker")]
+    MissingSoi,
+    #[error("expected a marker at offset {0}")]
+    InvalidMarker(usize),
+    #[error("segment at offset {0} is truncated")]
+    Truncated(usize),
+    #[error("no frame header before the first scan")]
+    MissingFrameHeader,
+    #[error("invalid frame header")]
+    InvalidFrameHeader,
+}
+
+/// Information from the frame header of a JPEG image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JpegHeader {
+    /// Start of frame marker, e.g. `0xc0` for baseline images.
+    pub sof_marker: u8,
+    pub width: u16,
+    pub height: u16,
+    pub num_components: u8,
+    /// Subsampling of the chroma components, or `None` if it cannot be expressed as one of the
+    /// V4L2 subsampling modes.
+    pub subsampling: Option<JpegChromaSubsampling>,
+    /// Whether the image defines its own Huffman tables. Frames of MJPEG streams often omit them
+    /// and expect the decoder to use the default tables of the specification.
+    pub has_huffman_tables: bool,
+}
+
+impl JpegHeader {
+    /// Parses the markers of the JPEG image in `data` up to its first scan.
+    pub fn parse(data: &[u8]) -> Result<Self, JpegHeaderError> {
+        if !data.starts_with(&[0xff, MARKER_SOI]) {
+            return Err(JpegHeaderError::MissingSoi);
+        }
+
+        let mut has_huffman_tables = false;
+        let mut header = None;
+        let mut pos = 2;
+        loop {
+            if data.get(pos) != Some(&0xff) {
+                return Err(JpegHeaderError::InvalidMarker(pos));
+            }
+            let marker_pos = pos;
+            // Any number of fill bytes can precede a marker.
+            while data.get(pos) == Some(&0xff) {
+                pos += 1;
+            }
+            let marker = *data
+                .get(pos)
+                .ok_or(JpegHeaderError::Truncated(marker_pos))?;
+            pos += 1;
+
+            match marker {
+                // Markers without a segment.
+                MARKER_TEM | 0xd0..=0xd7 => continue,
+                MARKER_SOS | MARKER_EOI => break,
+                _ => (),
+            }
+
+            let len = data
+                .get(pos..pos + 2)
+                .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+                .ok_or(JpegHeaderError::Truncated(marker_pos))?;
+            // The length includes its own two bytes.
+            let segment = data
+                .get(pos + 2..pos + len)
+                .filter(|_| len >= 2)
+                .ok_or(JpegHeaderError::Truncated(marker_pos))?;
+            pos += len;
+
+            if marker == MARKER_DHT {
+                has_huffman_tables = true;
+            } else if is_sof(marker) && header.is_none() {
+                header = Some(Self::parse_frame_header(marker, segment)?);
+            }
+        }
+
+        header
+            .map(|header| JpegHeader {
+                has_huffman_tables,
+                ..header
+            })
+            .ok_or(JpegHeaderError::MissingFrameHeader)
+    }
+
+    /// Parses the segment of a SOF marker.
+    fn parse_frame_header(sof_marker: u8, segment: &[u8]) -> Result<Self, JpegHeaderError> {
+        if segment.len() < 6 {
+            return Err(JpegHeaderError::InvalidFrameHeader);
+        }
+        let height = u16::from_be_bytes([segment[1], segment[2]]);
+        let width = u16::from_be_bytes([segment[3], segment[4]]);
+        let num_components = segment[5];
+        let components = segment
+            .get(6..6 + num_components as usize * 3)
+            .ok_or(JpegHeaderError::InvalidFrameHeader)?;
+
+        // A height of zero means that it is defined by a DNL marker after the first scan, which
+        // decoders do not support.
+        if width == 0 || height == 0 || num_components == 0 {
+            return Err(JpegHeaderError::InvalidFrameHeader);
+        }
+
+        // Sampling factors of each component, as (horizontal, vertical).
+        let factors = components
+            .chunks(3)
+            .map(|component| (component[1] >> 4, component[1] & 0xf))
+            .collect::<Vec<_>>();
+        let subsampling = match factors[..] {
+            [_] => Some(JpegChromaSubsampling::Gray),
+            [luma, (1, 1), (1, 1)] => match luma {
+                (1, 1) => Some(JpegChromaSubsampling::Yuv444),
+                (2, 1) => Some(JpegChromaSubsampling::Yuv422),
+                (2, 2) => Some(JpegChromaSubsampling::Yuv420),
+                (4, 1) => Some(JpegChromaSubsampling::Yuv411),
+                (4, 2) => Some(JpegChromaSubsampling::Yuv410),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        Ok(JpegHeader {
+            sof_marker,
+            width,
+            height,
+            num_components,
+            subsampling,
+            has_huffman_tables: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JpegHeader, JpegHeaderError};
+    use crate::bitstream::tests::Rng;
+    use crate::controls::jpeg::JpegChromaSubsampling;
+
+    /// Returns the headers of a 640x480 baseline image with the luma sampling factors `luma`,
+    /// followed by the beginning of its scan.
+    fn image(luma: u8) -> Vec<u8> {
+        [
+            &[0xff, 0xd8][..],
+            // APP0
+            &[0xff, 0xe0, 0x00, 0x06, b'J', b'F', b'I', b'F'],
+            // DQT, truncated to its first values.
+            &[0xff, 0xdb, 0x00, 0x05, 0x00, 0x10, 0x0b],
+            // Fill bytes then SOF0.
+            &[
+                0xff, 0xff, 0xc0, 0x00, 0x11, 0x08, 0x01, 0xe0, 0x02, 0x80, 0x03,
+            ],
+            &[0x01, luma, 0x00, 0x02, 0x11, 0x01, 0x03, 0x11, 0x01],
+            // SOS and entropy-coded data.
+            &[
+                0xff, 0xda, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3f, 0x00, 0xd2, 0xcf,
+            ],
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_jpeg_header() {
+        let header = JpegHeader::parse(&image(0x22)).unwrap();
+        assert_eq!(
+            header,
+            JpegHeader {
+                sof_marker: 0xc0,
+                width: 640,
+                height: 480,
+                num_components: 3,
+                subsampling: Some(JpegChromaSubsampling::Yuv420),
+                has_huffman_tables: false,
+            }
+        );
+
+        for (luma, subsampling) in [
+            (0x11, Some(JpegChromaSubsampling::Yuv444)),
+            (0x21, Some(JpegChromaSubsampling::Yuv422)),
+            (0x41, Some(JpegChromaSubsampling::Yuv411)),
+            (0x12, None),
+        ] {
+            assert_eq!(
+                JpegHeader::parse(&image(luma)).unwrap().subsampling,
+                subsampling
+            );
+        }
+
+        // Huffman tables are detected.
+        let mut with_dht = image(0x22);
+        with_dht.splice(2..2, [0xff, 0xc4, 0x00, 0x03, 0x00]);
+        assert!(JpegHeader::parse(&with_dht).unwrap().has_huffman_tables);
+    }
+
+    #[test]
+    fn test_jpeg_header_invalid() {
+        let image = image(0x22);
+
+        assert_eq!(
+            JpegHeader::parse(&image[1..]),
+            Err(JpegHeaderError::MissingSoi)
+        );
+        assert_eq!(
+            JpegHeader::parse(&image[..20]),
+            Err(JpegHeaderError::Truncated(17))
+        );
+        assert_eq!(
+            JpegHeader::parse(&[0xff, 0xd8, 0x12]),
+            Err(JpegHeaderError::InvalidMarker(2))
+        );
+        assert_eq!(
+            JpegHeader::parse(&[0xff, 0xd8, 0xff, 0xd9]),
+            Err(JpegHeaderError::MissingFrameHeader)
+        );
+
+        // Height defined by a DNL marker.
+        let mut dnl = image.clone();
+        dnl[23..25].copy_from_slice(&[0, 0]);
+        assert_eq!(
+            JpegHeader::parse(&dnl),
+            Err(JpegHeaderError::InvalidFrameHeader)
+        );
+
+        // Corrupted and truncated images must never make the parser panic.
+        let mut rng = Rng::new(0x4a50_4547);
+        for _ in 0..1000 {
+            let mut data = image.clone();
+            for _ in 0..=rng.below(4) {
+                let pos = rng.below(data.len());
+                data[pos] = rng.next() as u8;
+            }
+            data.truncate(rng.below(data.len() + 1));
+            let _ = JpegHeader::parse(&data);
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! Buffer-related utilities that are not tied to a particular queue.
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap};
+
+use crate::ioctl::FrameSyncEvent;
+
+/// Entry of a [`DisplayOrderQueue`]. Ordered so the entry with the smallest display timestamp
+/// (and then the smallest sequence number) is the greatest, as [`BinaryHeap`] is a max-heap.
+struct DisplayOrderEntry<T> {
+    sequence: u32,
+    display_ts: u64,
+    data: T,
+}
+
+impl<T> DisplayOrderEntry<T> {
+    fn key(&self) -> (u64, u32) {
+        (self.display_ts, self.sequence)
+    }
+}
+
+impl<T> PartialEq for DisplayOrderEntry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<T> Eq for DisplayOrderEntry<T> {}
+
+impl<T> PartialOrd for DisplayOrderEntry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for DisplayOrderEntry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key().cmp(&self.key())
+    }
+}
+
+/// Reorders decoded frames from decode order into display order.
+///
+/// Codecs using B-frames (like H.264 or HEVC) produce frames in an order that differs from the
+/// order in which they must be displayed. Frames are pushed into this queue in decode order along
+/// with their V4L2 `sequence` number and a display timestamp, typically the picture order count
+/// (e.g. [`bottom_field_order_cnt`](crate::bindings::v4l2_ctrl_h264_decode_params) for H.264).
+///
+/// Frames are only released once more than `max_reorder_depth` frames are pending, which
+/// guarantees that no frame with a smaller display timestamp can still arrive for a conformant
+/// stream. Remaining frames can be obtained at the end of the stream using
+/// [`DisplayOrderQueue::flush`].
+pub struct DisplayOrderQueue<T> {
+    max_reorder_depth: usize,
+    entries: BinaryHeap<DisplayOrderEntry<T>>,
+}
+
+impl<T> DisplayOrderQueue<T> {
+    /// Create a new queue that keeps up to `max_reorder_depth` frames before releasing them.
+    ///
+    /// For H.264 this is the `max_num_reorder_frames` of the VUI, or the DPB size if the former
+    /// is not present.
+    pub fn new(max_reorder_depth: usize) -> Self {
+        Self {
+            max_reorder_depth,
+            entries: BinaryHeap::with_capacity(max_reorder_depth + 1),
+        }
+    }
+
+    /// Returns the maximum number of frames this queue keeps before releasing them.
+    pub fn max_reorder_depth(&self) -> usize {
+        self.max_reorder_depth
+    }
+
+    /// Returns the number of frames currently pending in the queue.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no frame is pending in the queue.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Push a frame in decode order.
+    pub fn push(&mut self, sequence: u32, display_ts: u64, data: T) {
+        self.entries.push(DisplayOrderEntry {
+            sequence,
+            display_ts,
+            data,
+        });
+    }
+
+    /// Returns the next frame in display order, if the reorder window allows one to be released.
+    pub fn pop_in_order(&mut self) -> Option<T> {
+        if self.entries.len() > self.max_reorder_depth {
+            self.entries.pop().map(|e| e.data)
+        } else {
+            None
+        }
+    }
+
+    /// Returns all the pending frames in display order, leaving the queue empty. This should be
+    /// called at the end of a stream or before a new IDR frame.
+    pub fn flush(&mut self) -> Vec<T> {
+        let mut frames = Vec::with_capacity(self.entries.len());
+        while let Some(entry) = self.entries.pop() {
+            frames.push(entry.data);
+        }
+        frames
+    }
+}
+
+/// Outcome of the correlation of a frame, see [`FrameSyncCorrelator`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameSyncMatch<T> {
+    /// `buffer` has been matched with the frame-sync `event` of its frame.
+    Matched { buffer: T, event: FrameSyncEvent },
+    /// No frame-sync event has been received for `buffer`, which has sequence number `sequence`.
+    NoEvent { sequence: u32, buffer: T },
+    /// No buffer has been received for the `count` frames starting at sequence number `first`.
+    Dropped { first: u32, count: u32 },
+}
+
+/// Matches dequeued capture buffers with the frame-sync events of their frames (see
+/// [`Device::subscribe_frame_sync`](crate::device::Device::subscribe_frame_sync)) using their
+/// sequence numbers, e.g. to correlate them with the buffers of a metadata node.
+///
+/// Buffers and events are obtained separately and can arrive in any relative order, but each of
+/// them is expected in increasing sequence order. Frames are released in sequence order by
+/// [`FrameSyncCorrelator::pop`]. A buffer waits for its event until an event of a later frame
+/// arrives or more than `window` buffers are pending, and frames skipped by the sequence numbers
+/// of the buffers are reported as dropped.
+///
+/// Only one buffer is expected per sequence number, so the fields of a queue using
+/// [`Field::Alternate`](crate::Field::Alternate) need to be paired first, e.g. using
+/// [`FieldPairer`](crate::format::FieldPairer).
+pub struct FrameSyncCorrelator<T> {
+    window: usize,
+    /// Sequence number of the next frame to release, once a first frame has been released.
+    next: Option<u32>,
+    buffers: BTreeMap<u32, T>,
+    events: BTreeMap<u32, FrameSyncEvent>,
+}
+
+impl<T> FrameSyncCorrelator<T> {
+    /// Create a new correlator keeping up to `window` buffers while waiting for their events.
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            next: None,
+            buffers: BTreeMap::new(),
+            events: BTreeMap::new(),
+        }
+    }
+
+    /// Add a frame-sync event. Events of frames that have already been released are ignored.
+    pub fn push_event(&mut self, event: FrameSyncEvent) {
+        if matches!(self.next, Some(next) if event.frame_sequence < next) {
+            return;
+        }
+        self.events.insert(event.frame_sequence, event);
+    }
+
+    /// Add a dequeued buffer, which has sequence number `sequence`.
+    pub fn push_buffer(&mut self, sequence: u32, buffer: T) {
+        self.buffers.insert(sequence, buffer);
+    }
+
+    /// Returns the outcome of the next frame in sequence order, if it is known yet.
+    pub fn pop(&mut self) -> Option<FrameSyncMatch<T>> {
+        self.pop_with_window(self.window)
+    }
+
+    /// Returns the outcome of all the pending frames without waiting for their buffers or events,
+    /// leaving the correlator empty. This should be called at the end of the stream.
+    pub fn flush(&mut self) -> Vec<FrameSyncMatch<T>> {
+        let mut matches = Vec::new();
+        while let Some(m) = self.pop_with_window(0) {
+            matches.push(m);
+        }
+        matches
+    }
+
+    /// Drop all the pending buffers and events, e.g. before restarting the stream, which resets
+    /// the sequence numbers.
+    pub fn reset(&mut self) {
+        self.next = None;
+        self.buffers.clear();
+        self.events.clear();
+    }
+
+    fn pop_with_window(&mut self, window: usize) -> Option<FrameSyncMatch<T>> {
+        let first_buffer = self.buffers.keys().next().copied();
+        let next = match self.next {
+            Some(next) => next,
+            None => first_buffer
+                .into_iter()
+                .chain(self.events.keys().next().copied())
+                .min()?,
+        };
+
+        // This buffer arrived after later frames have been released.
+        if let Some(sequence) = first_buffer.filter(|&sequence| sequence < next) {
+            let buffer = self.buffers.remove(&sequence)?;
+            return Some(FrameSyncMatch::NoEvent { sequence, buffer });
+        }
+
+        let (released, end) = match (self.buffers.remove(&next), self.events.remove(&next)) {
+            (Some(buffer), Some(event)) => (FrameSyncMatch::Matched { buffer, event }, next + 1),
+            (Some(buffer), None) => {
+                // Events arrive in order, so an event of a later frame means this one is lost.
+                if self.events.is_empty() && self.buffers.len() < window {
+                    self.buffers.insert(next, buffer);
+                    return None;
+                }
+                (
+                    FrameSyncMatch::NoEvent {
+                        sequence: next,
+                        buffer,
+                    },
+                    next + 1,
+                )
+            }
+            (None, event) => {
+                // Buffers arrive in order, so the frames before the next one have been dropped.
+                // Without any buffer, give up on the oldest frame once too many events are pending.
+                let end = match (self.buffers.keys().next(), event) {
+                    (Some(&sequence), _) => sequence,
+                    (None, Some(_)) if self.events.len() + 1 > window => next + 1,
+                    (None, None) if self.events.len() > window => *self.events.keys().next()? + 1,
+                    (None, event) => {
+                        if let Some(event) = event {
+                            self.events.insert(next, event);
+                        }
+                        return None;
+                    }
+                };
+                self.events = self.events.split_off(&end);
+                (
+                    FrameSyncMatch::Dropped {
+                        first: next,
+                        count: end - next,
+                    },
+                    end,
+                )
+            }
+        };
+
+        self.next = Some(end);
+        Some(released)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{DisplayOrderQueue, FrameSyncCorrelator, FrameSyncMatch};
+    use crate::ioctl::FrameSyncEvent;
+
+    #[test]
+    fn test_display_order_queue() {
+        // Decode order of a I P B B stream, with POCs 0 6 2 4.
+        let decode_order = [(0, 0, 'I'), (1, 6, 'P'), (2, 2, 'B'), (3, 4, 'b')];
+        let mut queue = DisplayOrderQueue::new(2);
+        assert_eq!(queue.max_reorder_depth(), 2);
+
+        let mut displayed = Vec::new();
+        for (sequence, poc, frame) in decode_order {
+            queue.push(sequence, poc, frame);
+            while let Some(frame) = queue.pop_in_order() {
+                displayed.push(frame);
+            }
+        }
+        assert_eq!(displayed, vec!['I', 'B']);
+        assert_eq!(queue.len(), 2);
+
+        displayed.extend(queue.flush());
+        assert_eq!(displayed, vec!['I', 'B', 'b', 'P']);
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop_in_order(), None);
+    }
+
+    #[test]
+    fn test_display_order_queue_same_ts() {
+        let mut queue = DisplayOrderQueue::new(0);
+        queue.push(1, 0, 1);
+        queue.push(0, 0, 0);
+        assert_eq!(queue.flush(), vec![0, 1]);
+    }
+
+    fn frame_sync(frame_sequence: u32) -> FrameSyncEvent {
+        FrameSyncEvent {
+            frame_sequence,
+            timestamp: Duration::from_millis(frame_sequence as u64 * 33),
+        }
+    }
+
+    fn matched(sequence: u32) -> FrameSyncMatch<u32> {
+        FrameSyncMatch::Matched {
+            buffer: sequence,
+            event: frame_sync(sequence),
+        }
+    }
+
+    #[test]
+    fn test_frame_sync_correlator() {
+        let mut correlator = FrameSyncCorrelator::new(2);
+
+        // Event before its buffer.
+        correlator.push_event(frame_sync(0));
+        assert_eq!(correlator.pop(), None);
+        correlator.push_buffer(0, 0);
+        assert_eq!(correlator.pop(), Some(matched(0)));
+
+        // Buffer before its event.
+        correlator.push_buffer(1, 1);
+        assert_eq!(correlator.pop(), None);
+        correlator.push_event(frame_sync(1));
+        assert_eq!(correlator.pop(), Some(matched(1)));
+
+        // The event of frame 2 is lost, which is known once the one of frame 3 arrives.
+        correlator.push_buffer(2, 2);
+        assert_eq!(correlator.pop(), None);
+        correlator.push_event(frame_sync(3));
+        assert_eq!(
+            correlator.pop(),
+            Some(FrameSyncMatch::NoEvent {
+                sequence: 2,
+                buffer: 2
+            })
+        );
+        assert_eq!(correlator.pop(), None);
+
+        // The buffers of frames 4 and 5 are dropped.
+        correlator.push_event(frame_sync(4));
+        correlator.push_buffer(3, 3);
+        correlator.push_buffer(6, 6);
+        correlator.push_event(frame_sync(5));
+        correlator.push_event(frame_sync(6));
+        assert_eq!(correlator.pop(), Some(matched(3)));
+        assert_eq!(
+            correlator.pop(),
+            Some(FrameSyncMatch::Dropped { first: 4, count: 2 })
+        );
+        assert_eq!(correlator.pop(), Some(matched(6)));
+        assert_eq!(correlator.pop(), None);
+    }
+
+    #[test]
+    fn test_frame_sync_correlator_window() {
+        // Without any event, buffers are released once more than `window` are pending.
+        let mut correlator = FrameSyncCorrelator::new(2);
+        correlator.push_buffer(0, 0);
+        correlator.push_buffer(1, 1);
+        assert_eq!(correlator.pop(), None);
+        correlator.push_buffer(2, 2);
+        assert_eq!(
+            correlator.pop(),
+            Some(FrameSyncMatch::NoEvent {
+                sequence: 0,
+                buffer: 0
+            })
+        );
+        assert_eq!(correlator.pop(), None);
+
+        // Likewise, frames are considered dropped once more than `window` events are pending.
+        correlator.push_event(frame_sync(1));
+        correlator.push_event(frame_sync(2));
+        assert_eq!(correlator.pop(), Some(matched(1)));
+        assert_eq!(correlator.pop(), Some(matched(2)));
+        correlator.push_event(frame_sync(3));
+        correlator.push_event(frame_sync(4));
+        assert_eq!(correlator.pop(), None);
+        correlator.push_event(frame_sync(5));
+        assert_eq!(
+            correlator.pop(),
+            Some(FrameSyncMatch::Dropped { first: 3, count: 1 })
+        );
+        assert_eq!(correlator.pop(), None);
+
+        // Flushing does not wait anymore.
+        correlator.push_buffer(4, 4);
+        assert_eq!(
+            correlator.flush(),
+            vec![matched(4), FrameSyncMatch::Dropped { first: 5, count: 1 }]
+        );
+
+        // Late buffers are released as soon as possible.
+        correlator.push_buffer(1, 1);
+        assert_eq!(
+            correlator.pop(),
+            Some(FrameSyncMatch::NoEvent {
+                sequence: 1,
+                buffer: 1
+            })
+        );
+
+        // Sequence numbers start over after a reset.
+        correlator.reset();
+        correlator.push_event(frame_sync(0));
+        correlator.push_buffer(0, 0);
+        assert_eq!(correlator.pop(), Some(matched(0)));
+    }
+}
@@ -0,0 +1,745 @@
+//! Bring-up of the camera pipelines described by a media controller device, e.g. a raw sensor
+//! followed by a CSI receiver and an ISP, as found on libcamera-style platforms.
+//!
+//! [`Camera::open`] walks the topology of the media device from a sensor to a video node able to
+//! capture the requested pixel format, enables the links between them, and configures the format
+//! of every sub-device along the way, propagating the size from pad to pad. It then returns the
+//! `CAPTURE` queue of the video node, ready to be allocated and streamed, along with the queue of
+//! a metadata node fed by the same sensor if there is one.
+//!
+//! Buffers dequeued from both queues can be delivered together by a [`FrameMatcher`], which
+//! matches them using their sequence numbers.
+//!
+//! If the pipeline cannot be configured, the returned [`CameraError`] names the entity and pad
+//! which rejected the configuration.
+
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+use std::sync::Arc;
+
+use log::warn;
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::{
+    bindings,
+    device::{
+        media::{MediaDevice, MediaDeviceError},
+        queue::{direction::Capture, CreateQueueError, Queue, QueueInit},
+        subdev::SubDevice,
+        Device, DeviceConfig,
+    },
+    error::AsErrno,
+    ioctl::{
+        Capabilities, EntityFunction, FormatIterator, LinkFlags, LinkType, MbusFormat,
+        MediaBusCode, MediaEntity, MediaLink, MediaPad, SubDevWhich, Topology,
+    },
+    Format, PixelFormat, QueueType,
+};
+
+mod pipeline;
+
+pub use pipeline::{Pipeline, PipelineBuilder, PipelineError, PipelineVideoDevice};
+
+/// Media bus codes which video nodes can capture into each pixel format without conversion.
+const MBUS_CODES: &[(&[u8; 4], u32)] = &[
+    (b"RGB3", bindings::MEDIA_BUS_FMT_RGB888_1X24),
+    (b"BGR3", bindings::MEDIA_BUS_FMT_BGR888_1X24),
+    (b"YUYV", bindings::MEDIA_BUS_FMT_YUYV8_1X16),
+    (b"UYVY", bindings::MEDIA_BUS_FMT_UYVY8_1X16),
+    (b"BA81", bindings::MEDIA_BUS_FMT_SBGGR8_1X8),
+    (b"GBRG", bindings::MEDIA_BUS_FMT_SGBRG8_1X8),
+    (b"GRBG", bindings::MEDIA_BUS_FMT_SGRBG8_1X8),
+    (b"RGGB", bindings::MEDIA_BUS_FMT_SRGGB8_1X8),
+    (b"BG10", bindings::MEDIA_BUS_FMT_SBGGR10_1X10),
+    (b"GB10", bindings::MEDIA_BUS_FMT_SGBRG10_1X10),
+    (b"BA10", bindings::MEDIA_BUS_FMT_SGRBG10_1X10),
+    (b"RG10", bindings::MEDIA_BUS_FMT_SRGGB10_1X10),
+    (b"BG12", bindings::MEDIA_BUS_FMT_SBGGR12_1X12),
+    (b"GB12", bindings::MEDIA_BUS_FMT_SGBRG12_1X12),
+    (b"BA12", bindings::MEDIA_BUS_FMT_SGRBG12_1X12),
+    (b"RG12", bindings::MEDIA_BUS_FMT_SRGGB12_1X12),
+];
+
+/// Returns the media bus code that a sub-device must output for a video node to capture it as
+/// `pixel_format`, if it is known.
+fn mbus_code(pixel_format: PixelFormat) -> Option<MediaBusCode> {
+    MBUS_CODES
+        .iter()
+        .find(|(fourcc, _)| PixelFormat::from(*fourcc) == pixel_format)
+        .map(|&(_, code)| code.into())
+}
+
+/// Configuration of a [`Camera`].
+#[derive(Clone, Debug)]
+pub struct CameraConfig {
+    pixel_format: PixelFormat,
+    width: u32,
+    height: u32,
+    metadata: bool,
+}
+
+impl CameraConfig {
+    /// Create a configuration capturing `width`x`height` frames in `pixel_format`.
+    pub fn new(pixel_format: impl Into<PixelFormat>, width: u32, height: u32) -> Self {
+        Self {
+            pixel_format: pixel_format.into(),
+            width,
+            height,
+            metadata: true,
+        }
+    }
+
+    /// Whether to also set up the metadata node fed by the sensor, if there is one. Enabled by
+    /// default.
+    pub fn metadata(mut self, metadata: bool) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CameraError {
+    #[error("error while getting the media topology: {0}")]
+    Topology(Errno),
+    #[error("no camera sensor found in the media topology")]
+    NoSensor,
+    #[error("no video node fed by a sensor can capture {0} frames")]
+    NoVideoNode(PixelFormat),
+    #[error("cannot find the device node of entity {0:?}: {1}")]
+    DeviceNode(String, MediaDeviceError),
+    #[error("cannot open the sub-device of entity {0:?}: {1}")]
+    SubDeviceOpen(String, nix::Error),
+    #[error("failed to set up the link from {from:?} to {to:?}: {error}")]
+    Link {
+        from: String,
+        to: String,
+        error: Errno,
+    },
+    #[error("entity {entity:?} rejected the format of pad {pad}: {error}")]
+    FormatRejected {
+        entity: String,
+        pad: u32,
+        error: Errno,
+    },
+    #[error("entity {entity:?} set format {got:?} on pad {pad} instead of {requested:?}")]
+    FormatMismatch {
+        entity: String,
+        pad: u32,
+        requested: MbusFormat,
+        got: MbusFormat,
+    },
+    #[error("video node {entity:?} rejected its format: {error}")]
+    VideoFormatRejected { entity: String, error: Errno },
+    #[error("video node {entity:?} set format {got:?} instead of {requested:?}")]
+    VideoFormatMismatch {
+        entity: String,
+        requested: Box<Format>,
+        got: Box<Format>,
+    },
+    #[error("cannot obtain the queue of video node {0:?}: {1}")]
+    Queue(String, CreateQueueError),
+}
+
+impl AsErrno for CameraError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            CameraError::Topology(e) => Some(*e),
+            CameraError::DeviceNode(_, e) => e.errno(),
+            CameraError::SubDeviceOpen(_, e) => Some(*e),
+            CameraError::Link { error, .. } => Some(*error),
+            CameraError::FormatRejected { error, .. } => Some(*error),
+            CameraError::VideoFormatRejected { error, .. } => Some(*error),
+            CameraError::Queue(_, e) => e.errno(),
+            CameraError::NoSensor
+            | CameraError::NoVideoNode(_)
+            | CameraError::FormatMismatch { .. }
+            | CameraError::VideoFormatMismatch { .. } => None,
+        }
+    }
+}
+
+/// Returns the name of the entity with ID `id`, for error reporting.
+fn entity_name(topology: &Topology, id: u32) -> String {
+    topology
+        .entity(id)
+        .map(|entity| entity.name.clone())
+        .unwrap_or_else(|| format!("#{}", id))
+}
+
+/// A video node reachable from a sensor.
+struct Node<'a> {
+    sensor: &'a MediaEntity,
+    entity: &'a MediaEntity,
+    /// Data links from the sensor to the node.
+    pipeline: Vec<MediaLink>,
+    device: Arc<Device>,
+}
+
+/// Returns all the video nodes of `topology` reachable from a sensor, closest to their sensor
+/// first. Nodes which cannot be opened are skipped.
+fn find_nodes(topology: &Topology) -> Result<Vec<Node<'_>>, CameraError> {
+    let sensors = topology.entities_by_function(EntityFunction::CamSensor);
+    if sensors.is_empty() {
+        return Err(CameraError::NoSensor);
+    }
+
+    let mut nodes = Vec::new();
+    for sensor in sensors {
+        for entity in topology.entities_by_function(EntityFunction::IoV4l) {
+            let Some(pipeline) = topology.find_pipeline(sensor, entity) else {
+                continue;
+            };
+            let device = MediaDevice::entity_device_path(topology, entity)
+                .map_err(|e| e.to_string())
+                .and_then(|path| {
+                    Device::open(&path, DeviceConfig::new()).map_err(|e| e.to_string())
+                });
+            match device {
+                Ok(device) => nodes.push(Node {
+                    sensor,
+                    entity,
+                    pipeline,
+                    device: Arc::new(device),
+                }),
+                Err(e) => warn!("skipping video node {:?}: {}", entity.name, e),
+            }
+        }
+    }
+    nodes.sort_by_key(|node| node.pipeline.len());
+
+    Ok(nodes)
+}
+
+/// Returns the queue of `node` able to capture `pixel_format`, if any.
+fn capture_queue_type(node: &Node, pixel_format: PixelFormat) -> Option<QueueType> {
+    let caps = node.device.caps().device_caps();
+    let queue = if caps.contains(Capabilities::VIDEO_CAPTURE_MPLANE) {
+        QueueType::VideoCaptureMplane
+    } else if caps.contains(Capabilities::VIDEO_CAPTURE) {
+        QueueType::VideoCapture
+    } else {
+        return None;
+    };
+
+    FormatIterator::new(&*node.device, queue)
+        .any(|fmt| fmt.pixelformat == pixel_format)
+        .then_some(queue)
+}
+
+/// Enables the links of `pipeline`, disabling the other links feeding the same sink pads.
+fn enable_links(
+    media: &MediaDevice,
+    topology: &Topology,
+    pipeline: &[MediaLink],
+) -> Result<(), CameraError> {
+    let link_error = |link: &MediaLink, error| {
+        let pad_entity = |id| topology.pad(id).map(|pad| pad.entity_id).unwrap_or(id);
+        CameraError::Link {
+            from: entity_name(topology, pad_entity(link.source_id)),
+            to: entity_name(topology, pad_entity(link.sink_id)),
+            error,
+        }
+    };
+    let setup_link = |link: &MediaLink, flags| match (
+        topology.pad(link.source_id),
+        topology.pad(link.sink_id),
+    ) {
+        (Some(source), Some(sink)) => media
+            .setup_link(source, sink, flags)
+            .map_err(|error| link_error(link, error)),
+        _ => Err(link_error(link, Errno::EINVAL)),
+    };
+
+    for link in pipeline {
+        let conflicting = topology.links.iter().filter(|other| {
+            other.id != link.id
+                && other.sink_id == link.sink_id
+                && other.link_type() == Some(LinkType::Data)
+                && other.flags.contains(LinkFlags::ENABLED)
+                && !other.flags.contains(LinkFlags::IMMUTABLE)
+        });
+        for other in conflicting {
+            setup_link(other, LinkFlags::empty())?;
+        }
+
+        if !link.flags.contains(LinkFlags::ENABLED) {
+            setup_link(link, LinkFlags::ENABLED)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the sub-device of entity `id`, opening it if needed.
+fn subdev<'a>(
+    subdevs: &'a mut HashMap<u32, SubDevice>,
+    topology: &Topology,
+    id: u32,
+) -> Result<&'a SubDevice, CameraError> {
+    let subdev = match subdevs.entry(id) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let entity = topology.entity(id).ok_or_else(|| {
+                CameraError::DeviceNode(format!("#{}", id), MediaDeviceError::NoSuchEntityId(id))
+            })?;
+            let path = MediaDevice::entity_device_path(topology, entity)
+                .map_err(|e| CameraError::DeviceNode(entity.name.clone(), e))?;
+            let subdev = SubDevice::open(&path)
+                .map_err(|e| CameraError::SubDeviceOpen(entity.name.clone(), e))?;
+            entry.insert(subdev)
+        }
+    };
+
+    Ok(subdev)
+}
+
+/// Configures the formats of the sub-devices of `pipeline` for the video node at its end to
+/// capture `config`, and returns the format it will receive.
+///
+/// Each source pad is set to the requested size, which the sub-device adjusts to what it can
+/// produce from its input, and its format is then propagated to the sink pad it is linked to. The
+/// last source pad is also set to the media bus code of the requested pixel format, if known.
+fn configure_formats(
+    subdevs: &mut HashMap<u32, SubDevice>,
+    topology: &Topology,
+    pipeline: &[MediaLink],
+    config: &CameraConfig,
+) -> Result<MbusFormat, CameraError> {
+    let mut format = MbusFormat::default();
+
+    for (i, link) in pipeline.iter().enumerate() {
+        let (source, sink) = (
+            topology
+                .pad(link.source_id)
+                .expect("pipeline links have pads"),
+            topology
+                .pad(link.sink_id)
+                .expect("pipeline links have pads"),
+        );
+        let last = i == pipeline.len() - 1;
+
+        let entity = entity_name(topology, source.entity_id);
+        let rejected = |error| CameraError::FormatRejected {
+            entity: entity.clone(),
+            pad: source.index,
+            error,
+        };
+        let source_subdev = subdev(subdevs, topology, source.entity_id)?;
+        let mut requested = source_subdev
+            .get_format(source.index, SubDevWhich::Active)
+            .map_err(rejected)?;
+        requested.width = config.width;
+        requested.height = config.height;
+        if last {
+            if let Some(code) = mbus_code(config.pixel_format) {
+                requested.code = code;
+            }
+        }
+        format = source_subdev
+            .set_format(source.index, SubDevWhich::Active, requested)
+            .map_err(rejected)?;
+
+        // The sink of the last link is the video node, which format is set afterwards.
+        if last {
+            break;
+        }
+
+        set_sink_format(subdevs, topology, sink, format)?;
+    }
+
+    Ok(format)
+}
+
+/// Sets `format` on the sink pad `sink`, checking that it is applied as requested.
+fn set_sink_format(
+    subdevs: &mut HashMap<u32, SubDevice>,
+    topology: &Topology,
+    sink: &MediaPad,
+    format: MbusFormat,
+) -> Result<(), CameraError> {
+    let entity = entity_name(topology, sink.entity_id);
+    let got = subdev(subdevs, topology, sink.entity_id)?
+        .set_format(sink.index, SubDevWhich::Active, format)
+        .map_err(|error| CameraError::FormatRejected {
+            entity: entity.clone(),
+            pad: sink.index,
+            error,
+        })?;
+    if (got.code, got.width, got.height) != (format.code, format.width, format.height) {
+        return Err(CameraError::FormatMismatch {
+            entity,
+            pad: sink.index,
+            requested: format,
+            got,
+        });
+    }
+
+    Ok(())
+}
+
+/// Configures the formats of the sub-devices of the metadata `pipeline`, once the formats of the
+/// `video` pipeline have been configured.
+///
+/// The format of the metadata produced by the sensor depends on its image format, so the format
+/// of each source pad is kept as reported by its sub-device and propagated to the sink pad it is
+/// linked to. Links shared with the video pipeline are already configured and are left untouched.
+fn configure_metadata_formats(
+    subdevs: &mut HashMap<u32, SubDevice>,
+    topology: &Topology,
+    pipeline: &[MediaLink],
+    video: &[MediaLink],
+) -> Result<(), CameraError> {
+    // The sink of the last link is the metadata node, which format is set afterwards.
+    let links = pipeline
+        .split_last()
+        .map(|(_, links)| links)
+        .unwrap_or_default();
+    for link in links {
+        if video.iter().any(|video_link| video_link.id == link.id) {
+            continue;
+        }
+        let (source, sink) = (
+            topology
+                .pad(link.source_id)
+                .expect("pipeline links have pads"),
+            topology
+                .pad(link.sink_id)
+                .expect("pipeline links have pads"),
+        );
+
+        let format = subdev(subdevs, topology, source.entity_id)?
+            .get_format(source.index, SubDevWhich::Active)
+            .map_err(|error| CameraError::FormatRejected {
+                entity: entity_name(topology, source.entity_id),
+                pad: source.index,
+                error,
+            })?;
+        set_sink_format(subdevs, topology, sink, format)?;
+    }
+
+    Ok(())
+}
+
+/// Sets the format of the `META_CAPTURE` queue of `node` to the first data format it supports,
+/// and returns the queue along with its format.
+fn configure_metadata_queue(
+    node: &Node,
+) -> Result<(Queue<Capture, QueueInit>, Format), CameraError> {
+    let mut queue = get_queue(node, QueueType::MetaCapture)?;
+    let rejected = |error: Errno| CameraError::VideoFormatRejected {
+        entity: node.entity.name.clone(),
+        error,
+    };
+
+    let mut builder = queue.change_format().map_err(|e| rejected(e.into()))?;
+    // Keep the current data format if the node does not enumerate any.
+    if let Some(fmtdesc) = FormatIterator::new(&*node.device, QueueType::MetaCapture).next() {
+        builder = builder.set_pixelformat(fmtdesc.pixelformat);
+    }
+    let requested = builder.format().clone();
+    let format: Format = builder.apply().map_err(|e| rejected(e.into()))?;
+    if format.pixelformat != requested.pixelformat {
+        return Err(CameraError::VideoFormatMismatch {
+            entity: node.entity.name.clone(),
+            requested: Box::new(requested),
+            got: Box::new(format),
+        });
+    }
+
+    Ok((queue, format))
+}
+
+/// Obtains the `queue` queue of `node`.
+fn get_queue(node: &Node, queue: QueueType) -> Result<Queue<Capture, QueueInit>, CameraError> {
+    let device = Arc::clone(&node.device);
+    match queue {
+        QueueType::VideoCaptureMplane => Queue::get_capture_mplane_queue(device),
+        QueueType::MetaCapture => Queue::get_meta_capture_queue(device),
+        _ => Queue::get_capture_queue(device),
+    }
+    .map_err(|e| CameraError::Queue(node.entity.name.clone(), e))
+}
+
+/// A camera pipeline configured by [`Camera::open`], which queues are ready to be allocated and
+/// streamed.
+pub struct Camera {
+    /// Sensor the frames originate from.
+    pub sensor: MediaEntity,
+    /// Video node the frames are captured from.
+    pub video_node: MediaEntity,
+    /// Data links going from the sensor to the video node.
+    pub pipeline: Vec<MediaLink>,
+    /// Format of the captured frames.
+    pub format: Format,
+    /// `CAPTURE` queue of the video node.
+    pub capture_queue: Queue<Capture, QueueInit>,
+    /// Metadata node fed by the same sensor, if any.
+    pub metadata_node: Option<MediaEntity>,
+    /// Format of the captured metadata, if any.
+    pub metadata_format: Option<Format>,
+    /// `META_CAPTURE` queue of the metadata node, if any.
+    pub metadata_queue: Option<Queue<Capture, QueueInit>>,
+}
+
+impl Camera {
+    /// Sets up the pipeline of `media` going from a sensor to a video node which can capture
+    /// frames as specified by `config`.
+    ///
+    /// The closest video node to a sensor supporting the requested pixel format is selected. If
+    /// metadata is requested and the sensor also feeds a metadata node, the formats of the
+    /// metadata pipeline are propagated from the sensor and the metadata node is set to the first
+    /// data format it supports.
+    pub fn open(media: &MediaDevice, config: CameraConfig) -> Result<Self, CameraError> {
+        let topology = media.get_topology().map_err(CameraError::Topology)?;
+        let nodes = find_nodes(&topology)?;
+
+        let (video, queue) = nodes
+            .iter()
+            .find_map(|node| {
+                capture_queue_type(node, config.pixel_format).map(|queue| (node, queue))
+            })
+            .ok_or(CameraError::NoVideoNode(config.pixel_format))?;
+        let metadata = nodes
+            .iter()
+            .filter(|_| config.metadata)
+            .filter(|node| node.sensor.id == video.sensor.id)
+            .find(|node| {
+                node.device
+                    .caps()
+                    .device_caps()
+                    .contains(Capabilities::META_CAPTURE)
+            });
+
+        enable_links(media, &topology, &video.pipeline)?;
+        if let Some(metadata) = metadata {
+            enable_links(media, &topology, &metadata.pipeline)?;
+        }
+
+        let mut subdevs = HashMap::new();
+        let mbus_format = configure_formats(&mut subdevs, &topology, &video.pipeline, &config)?;
+        if let Some(metadata) = metadata {
+            configure_metadata_formats(
+                &mut subdevs,
+                &topology,
+                &metadata.pipeline,
+                &video.pipeline,
+            )?;
+        }
+
+        let mut capture_queue = get_queue(video, queue)?;
+        let video_format_rejected = |error: Errno| CameraError::VideoFormatRejected {
+            entity: video.entity.name.clone(),
+            error,
+        };
+        let format: Format = capture_queue
+            .change_format()
+            .map_err(|e| video_format_rejected(e.into()))?
+            .set_pixelformat(config.pixel_format)
+            .set_size(mbus_format.width as usize, mbus_format.height as usize)
+            .apply()
+            .map_err(|e| video_format_rejected(e.into()))?;
+        if format.pixelformat != config.pixel_format
+            || (format.width, format.height) != (mbus_format.width, mbus_format.height)
+        {
+            let mut requested = format.clone();
+            requested.pixelformat = config.pixel_format;
+            requested.width = mbus_format.width;
+            requested.height = mbus_format.height;
+            return Err(CameraError::VideoFormatMismatch {
+                entity: video.entity.name.clone(),
+                requested: Box::new(requested),
+                got: Box::new(format),
+            });
+        }
+
+        let (metadata_queue, metadata_format) = match metadata {
+            Some(metadata) => {
+                let (queue, format) = configure_metadata_queue(metadata)?;
+                (Some(queue), Some(format))
+            }
+            None => (None, None),
+        };
+
+        Ok(Camera {
+            sensor: video.sensor.clone(),
+            video_node: video.entity.clone(),
+            pipeline: video.pipeline.clone(),
+            format,
+            capture_queue,
+            metadata_node: metadata.map(|metadata| metadata.entity.clone()),
+            metadata_format,
+            metadata_queue,
+        })
+    }
+}
+
+/// A frame delivered by a [`FrameMatcher`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct CameraFrame<F, M> {
+    /// Sequence number of the frame.
+    pub sequence: u32,
+    pub frame: F,
+    /// Metadata of the frame, or `None` if it has been lost.
+    pub metadata: Option<M>,
+}
+
+/// Delivers the frames of a [`Camera`] along with their metadata to a callback, matching the
+/// buffers dequeued from its capture and metadata queues by sequence number.
+///
+/// Frames and metadata are pushed as they are dequeued. Each of them is expected in increasing
+/// sequence order, but they can arrive in any relative order. A frame is delivered as soon as its
+/// metadata arrives, or without metadata once the metadata of a later frame has arrived or more
+/// than `window` frames are waiting. Metadata which frame has been lost is dropped.
+pub struct FrameMatcher<F, M, C: FnMut(CameraFrame<F, M>)> {
+    window: usize,
+    /// Sequence number of the next frame to deliver, once a first frame has been delivered.
+    next: Option<u32>,
+    frames: BTreeMap<u32, F>,
+    metadata: BTreeMap<u32, M>,
+    callback: C,
+}
+
+impl<F, M, C: FnMut(CameraFrame<F, M>)> FrameMatcher<F, M, C> {
+    /// Create a new matcher keeping up to `window` frames while waiting for their metadata, and
+    /// delivering them to `callback`.
+    pub fn new(window: usize, callback: C) -> Self {
+        Self {
+            window,
+            next: None,
+            frames: BTreeMap::new(),
+            metadata: BTreeMap::new(),
+            callback,
+        }
+    }
+
+    /// Add a dequeued frame, which has sequence number `sequence`.
+    pub fn push_frame(&mut self, sequence: u32, frame: F) {
+        self.frames.insert(sequence, frame);
+        self.deliver(self.window);
+    }
+
+    /// Add dequeued metadata, which has sequence number `sequence`. Metadata of frames that have
+    /// already been delivered is dropped.
+    pub fn push_metadata(&mut self, sequence: u32, metadata: M) {
+        if matches!(self.next, Some(next) if sequence < next) {
+            return;
+        }
+        self.metadata.insert(sequence, metadata);
+        self.deliver(self.window);
+    }
+
+    /// Deliver all the pending frames without waiting for their metadata. This should be called
+    /// at the end of the stream.
+    pub fn flush(&mut self) {
+        self.deliver(0);
+        self.metadata.clear();
+    }
+
+    /// Drop all the pending frames and metadata, e.g. before restarting the stream, which resets
+    /// the sequence numbers.
+    pub fn reset(&mut self) {
+        self.next = None;
+        self.frames.clear();
+        self.metadata.clear();
+    }
+
+    fn deliver(&mut self, window: usize) {
+        while let Some(&sequence) = self.frames.keys().next() {
+            let metadata = self.metadata.remove(&sequence);
+            // Metadata arrives in order, so metadata of a later frame means this one is lost.
+            let lost = self.metadata.keys().next_back() > Some(&sequence);
+            if metadata.is_none() && !lost && self.frames.len() <= window {
+                break;
+            }
+
+            let Some(frame) = self.frames.remove(&sequence) else {
+                break;
+            };
+            // The frames of the metadata still pending before this one have been lost.
+            self.metadata = self.metadata.split_off(&sequence);
+            self.next = Some(sequence.wrapping_add(1));
+            (self.callback)(CameraFrame {
+                sequence,
+                frame,
+                metadata,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn test_mbus_code() {
+        assert_eq!(
+            mbus_code(PixelFormat::from(b"RGB3")),
+            Some(bindings::MEDIA_BUS_FMT_RGB888_1X24.into())
+        );
+        assert_eq!(
+            mbus_code(PixelFormat::from(b"BA81")),
+            Some(bindings::MEDIA_BUS_FMT_SBGGR8_1X8.into())
+        );
+        assert_eq!(mbus_code(PixelFormat::from(b"NV12")), None);
+    }
+
+    #[test]
+    fn test_frame_matcher() {
+        type Frame = CameraFrame<&'static str, &'static str>;
+        fn delivered(receiver: &mpsc::Receiver<Frame>) -> Vec<(u32, &str, Option<&str>)> {
+            receiver
+                .try_iter()
+                .map(|f| (f.sequence, f.frame, f.metadata))
+                .collect()
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let mut matcher = FrameMatcher::new(2, move |frame: Frame| sender.send(frame).unwrap());
+
+        // Frames wait for their metadata, in any relative order.
+        matcher.push_frame(0, "f0");
+        assert!(delivered(&receiver).is_empty());
+        matcher.push_metadata(0, "m0");
+        matcher.push_metadata(1, "m1");
+        assert_eq!(delivered(&receiver), vec![(0, "f0", Some("m0"))]);
+        matcher.push_frame(1, "f1");
+        assert_eq!(delivered(&receiver), vec![(1, "f1", Some("m1"))]);
+
+        // The metadata of frame 2 is lost, which is known once the one of frame 3 arrives.
+        matcher.push_frame(2, "f2");
+        matcher.push_metadata(3, "m3");
+        assert_eq!(delivered(&receiver), vec![(2, "f2", None)]);
+        // Frame 4 is lost, so its metadata is dropped when frame 5 is delivered.
+        matcher.push_metadata(4, "m4");
+        matcher.push_frame(3, "f3");
+        matcher.push_metadata(5, "m5");
+        matcher.push_frame(5, "f5");
+        assert_eq!(
+            delivered(&receiver),
+            vec![(3, "f3", Some("m3")), (5, "f5", Some("m5"))]
+        );
+        // Late metadata is ignored.
+        matcher.push_metadata(2, "m2");
+
+        // Without metadata, frames are delivered once the window is full.
+        matcher.push_frame(6, "f6");
+        matcher.push_frame(7, "f7");
+        assert!(delivered(&receiver).is_empty());
+        matcher.push_frame(8, "f8");
+        assert_eq!(delivered(&receiver), vec![(6, "f6", None)]);
+        matcher.flush();
+        assert_eq!(delivered(&receiver), vec![(7, "f7", None), (8, "f8", None)]);
+
+        // The last sequence number does not overflow.
+        matcher.reset();
+        matcher.push_frame(u32::MAX, "last");
+        matcher.push_metadata(u32::MAX, "m-last");
+        assert_eq!(
+            delivered(&receiver),
+            vec![(u32::MAX, "last", Some("m-last"))]
+        );
+    }
+}
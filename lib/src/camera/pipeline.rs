@@ -0,0 +1,480 @@
+//! Manual configuration of media controller pipelines, for platforms which topology is known in
+//! advance, e.g. the PiSP ISP of the Raspberry Pi 5.
+//!
+//! A [`PipelineBuilder`] records the links to enable and the formats to set on the sub-devices and
+//! video nodes of a media device, and [`PipelineBuilder::build`] applies them in the order they
+//! have been added:
+//!
+//! ```no_run
+//! # use std::path::Path;
+//! #
+//! # use v4l2r::bindings;
+//! # use v4l2r::camera::PipelineBuilder;
+//! # use v4l2r::device::media::MediaDevice;
+//! # use v4l2r::PixelFormat;
+//! #
+//! let media = MediaDevice::open(Path::new("/dev/media0")).unwrap();
+//! let topology = media.get_topology().unwrap();
+//! let sensor = topology.entity_by_name("imx219 10-0010").unwrap().id;
+//! let csi = topology.entity_by_name("csi2").unwrap().id;
+//! let code = bindings::MEDIA_BUS_FMT_SRGGB10_1X10.into();
+//! let format = PixelFormat::from_fourcc(b"pRAA");
+//!
+//! let pipeline = PipelineBuilder::new(media)
+//!     .enable_link(sensor, 0, csi, 0)
+//!     .set_subdev_format(sensor, 0, 1640, 1232, code)
+//!     .set_subdev_format(csi, 0, 1640, 1232, code)
+//!     .set_video_format(Path::new("/dev/video0"), format, 1640, 1232)
+//!     .build()
+//!     .unwrap();
+//! ```
+//!
+//! Contrary to [`Camera::open`](super::Camera::open), no attempt is made to find the pipeline or
+//! to propagate formats: every step is applied as requested, and the first one to fail stops the
+//! configuration.
+
+use std::collections::{hash_map::Entry, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::{
+    device::{
+        media::{MediaDevice, MediaDeviceError},
+        subdev::SubDevice,
+        Device, DeviceConfig, DeviceOpenError,
+    },
+    error::AsErrno,
+    ioctl::{self, Capabilities, LinkFlags, MbusFormat, MediaBusCode, SubDevWhich, Topology},
+    Format, PixelFormat, QueueType,
+};
+
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    #[error("error while getting the media topology: {0}")]
+    Topology(Errno),
+    #[error("no entity with ID {0}")]
+    NoSuchEntity(u32),
+    #[error("entity {entity:?} has no pad {pad}")]
+    NoSuchPad { entity: String, pad: u32 },
+    #[error("no link from pad {from_pad} of {from:?} to pad {to_pad} of {to:?}")]
+    NoSuchLink {
+        from: String,
+        from_pad: u32,
+        to: String,
+        to_pad: u32,
+    },
+    #[error("failed to enable the link from {from:?} to {to:?}: {error}")]
+    Link {
+        from: String,
+        to: String,
+        error: Errno,
+    },
+    #[error("cannot find the device node of entity {0:?}: {1}")]
+    DeviceNode(String, MediaDeviceError),
+    #[error("cannot open the sub-device of entity {0:?}: {1}")]
+    SubDeviceOpen(String, nix::Error),
+    #[error("entity {entity:?} rejected the format of pad {pad}: {error}")]
+    FormatRejected {
+        entity: String,
+        pad: u32,
+        error: Errno,
+    },
+    #[error("entity {entity:?} set format {got:?} on pad {pad} instead of {requested:?}")]
+    FormatMismatch {
+        entity: String,
+        pad: u32,
+        requested: MbusFormat,
+        got: MbusFormat,
+    },
+    #[error("cannot open video node {}: {1}", .0.display())]
+    VideoDeviceOpen(PathBuf, DeviceOpenError),
+    #[error("{} is not a video capture or output node", .0.display())]
+    NotVideoNode(PathBuf),
+    #[error("video node {} rejected its format: {error}", .path.display())]
+    VideoFormatRejected { path: PathBuf, error: Errno },
+    #[error("video node {} set format {got:?} instead of {requested:?}", .path.display())]
+    VideoFormatMismatch {
+        path: PathBuf,
+        requested: Box<Format>,
+        got: Box<Format>,
+    },
+}
+
+impl AsErrno for PipelineError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            PipelineError::Topology(e) => Some(*e),
+            PipelineError::Link { error, .. } => Some(*error),
+            PipelineError::DeviceNode(_, e) => e.errno(),
+            PipelineError::SubDeviceOpen(_, e) => Some(*e),
+            PipelineError::FormatRejected { error, .. } => Some(*error),
+            PipelineError::VideoDeviceOpen(_, e) => e.errno(),
+            PipelineError::VideoFormatRejected { error, .. } => Some(*error),
+            PipelineError::NoSuchEntity(_)
+            | PipelineError::NoSuchPad { .. }
+            | PipelineError::NoSuchLink { .. }
+            | PipelineError::NotVideoNode(_)
+            | PipelineError::FormatMismatch { .. }
+            | PipelineError::VideoFormatMismatch { .. } => None,
+        }
+    }
+}
+
+/// A configuration step of a [`PipelineBuilder`].
+#[derive(Clone, Debug)]
+enum Step {
+    EnableLink {
+        source: u32,
+        source_pad: u32,
+        sink: u32,
+        sink_pad: u32,
+    },
+    SubDevFormat {
+        entity: u32,
+        pad: u32,
+        width: u32,
+        height: u32,
+        code: MediaBusCode,
+    },
+    VideoFormat {
+        path: PathBuf,
+        pixel_format: PixelFormat,
+        width: u32,
+        height: u32,
+    },
+}
+
+/// Returns the name of the entity with ID `id`, or an error if there is no such entity.
+fn entity_name(topology: &Topology, id: u32) -> Result<&str, PipelineError> {
+    topology
+        .entity(id)
+        .map(|entity| entity.name.as_str())
+        .ok_or(PipelineError::NoSuchEntity(id))
+}
+
+/// Returns the queue through which the video node `device` captures or outputs frames.
+fn video_queue_type(device: &Device) -> Option<QueueType> {
+    let caps = device.caps().device_caps();
+
+    [
+        (
+            Capabilities::VIDEO_CAPTURE_MPLANE,
+            QueueType::VideoCaptureMplane,
+        ),
+        (Capabilities::VIDEO_CAPTURE, QueueType::VideoCapture),
+        (
+            Capabilities::VIDEO_OUTPUT_MPLANE,
+            QueueType::VideoOutputMplane,
+        ),
+        (Capabilities::VIDEO_OUTPUT, QueueType::VideoOutput),
+    ]
+    .into_iter()
+    .find(|(cap, _)| caps.contains(*cap))
+    .map(|(_, queue)| queue)
+}
+
+/// Records the steps needed to configure a media controller pipeline, and applies them with
+/// [`PipelineBuilder::build`].
+///
+/// Entities are designated by their ID in the topology of the media device, and pads by their
+/// index within their entity, as used by the sub-device API.
+pub struct PipelineBuilder {
+    media: MediaDevice,
+    steps: Vec<Step>,
+}
+
+impl PipelineBuilder {
+    /// Create a builder configuring the pipeline of `media_device`.
+    pub fn new(media_device: MediaDevice) -> Self {
+        Self {
+            media: media_device,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Enable the data link going from pad `src_pad` of entity `src` to pad `dst_pad` of entity
+    /// `dst`.
+    ///
+    /// Links that are already enabled are left untouched. Other links feeding the same pad are not
+    /// disabled.
+    pub fn enable_link(mut self, src: u32, src_pad: u32, dst: u32, dst_pad: u32) -> Self {
+        self.steps.push(Step::EnableLink {
+            source: src,
+            source_pad: src_pad,
+            sink: dst,
+            sink_pad: dst_pad,
+        });
+        self
+    }
+
+    /// Set the active format of pad `pad` of the sub-device of entity `entity_id` to
+    /// `width`x`height` frames of media bus code `code`.
+    ///
+    /// The other fields of the format, like its colorspace, are kept as reported by the
+    /// sub-device.
+    pub fn set_subdev_format(
+        mut self,
+        entity_id: u32,
+        pad: u32,
+        width: u32,
+        height: u32,
+        code: MediaBusCode,
+    ) -> Self {
+        self.steps.push(Step::SubDevFormat {
+            entity: entity_id,
+            pad,
+            width,
+            height,
+            code,
+        });
+        self
+    }
+
+    /// Set the format of the video node at `path` to `width`x`height` frames of `format`.
+    ///
+    /// The format is set on the capture queue of the node, or on its output queue if it cannot
+    /// capture, e.g. for the input node of an ISP.
+    pub fn set_video_format(
+        mut self,
+        path: &Path,
+        format: PixelFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        self.steps.push(Step::VideoFormat {
+            path: path.to_path_buf(),
+            pixel_format: format,
+            width,
+            height,
+        });
+        self
+    }
+
+    /// Apply the configuration steps in the order they have been added, and return the configured
+    /// pipeline.
+    ///
+    /// Formats which the driver adjusts instead of applying them as requested are reported as
+    /// errors.
+    pub fn build(self) -> Result<Pipeline, PipelineError> {
+        let topology = self.media.get_topology().map_err(PipelineError::Topology)?;
+        let mut subdevs = HashMap::new();
+        let mut video_devices: Vec<(PathBuf, Device, QueueType, Format)> = Vec::new();
+
+        for step in self.steps {
+            match step {
+                Step::EnableLink {
+                    source,
+                    source_pad,
+                    sink,
+                    sink_pad,
+                } => enable_link(&self.media, &topology, source, source_pad, sink, sink_pad)?,
+                Step::SubDevFormat {
+                    entity,
+                    pad,
+                    width,
+                    height,
+                    code,
+                } => set_subdev_format(&mut subdevs, &topology, entity, pad, width, height, code)?,
+                Step::VideoFormat {
+                    path,
+                    pixel_format,
+                    width,
+                    height,
+                } => {
+                    let i = match video_devices.iter().position(|(p, ..)| *p == path) {
+                        Some(i) => i,
+                        None => {
+                            let device = Device::open(&path, DeviceConfig::new())
+                                .map_err(|e| PipelineError::VideoDeviceOpen(path.clone(), e))?;
+                            let queue = video_queue_type(&device)
+                                .ok_or_else(|| PipelineError::NotVideoNode(path.clone()))?;
+                            video_devices.push((path.clone(), device, queue, Default::default()));
+                            video_devices.len() - 1
+                        }
+                    };
+                    let (_, device, queue, format) = &mut video_devices[i];
+                    *format = set_video_format(device, *queue, &path, pixel_format, width, height)?;
+                }
+            }
+        }
+
+        Ok(Pipeline {
+            media: self.media,
+            subdevs,
+            video_devices: video_devices
+                .into_iter()
+                .map(|(path, device, queue, format)| PipelineVideoDevice {
+                    path,
+                    device: Arc::new(device),
+                    queue,
+                    format,
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Enables the link going from pad `source_pad` of entity `source` to pad `sink_pad` of entity
+/// `sink`.
+fn enable_link(
+    media: &MediaDevice,
+    topology: &Topology,
+    source: u32,
+    source_pad: u32,
+    sink: u32,
+    sink_pad: u32,
+) -> Result<(), PipelineError> {
+    let from = entity_name(topology, source)?;
+    let to = entity_name(topology, sink)?;
+    let no_such_pad = |entity: &str, pad| PipelineError::NoSuchPad {
+        entity: entity.to_string(),
+        pad,
+    };
+    let source_pad = topology
+        .entity_pad(source, source_pad)
+        .ok_or_else(|| no_such_pad(from, source_pad))?;
+    let sink_pad = topology
+        .entity_pad(sink, sink_pad)
+        .ok_or_else(|| no_such_pad(to, sink_pad))?;
+
+    let link =
+        topology
+            .data_link(source_pad, sink_pad)
+            .ok_or_else(|| PipelineError::NoSuchLink {
+                from: from.to_string(),
+                from_pad: source_pad.index,
+                to: to.to_string(),
+                to_pad: sink_pad.index,
+            })?;
+    if link.flags.contains(LinkFlags::ENABLED) {
+        return Ok(());
+    }
+
+    media
+        .setup_link(source_pad, sink_pad, LinkFlags::ENABLED)
+        .map_err(|error| PipelineError::Link {
+            from: from.to_string(),
+            to: to.to_string(),
+            error,
+        })
+}
+
+/// Sets the format of pad `pad` of entity `entity`, opening its sub-device if needed.
+fn set_subdev_format(
+    subdevs: &mut HashMap<u32, SubDevice>,
+    topology: &Topology,
+    entity: u32,
+    pad: u32,
+    width: u32,
+    height: u32,
+    code: MediaBusCode,
+) -> Result<(), PipelineError> {
+    let name = entity_name(topology, entity)?;
+    let subdev = match subdevs.entry(entity) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            // `entity_name` succeeded, so the entity exists.
+            let path = MediaDevice::entity_device_path(topology, topology.entity(entity).unwrap())
+                .map_err(|e| PipelineError::DeviceNode(name.to_string(), e))?;
+            let subdev = SubDevice::open(&path)
+                .map_err(|e| PipelineError::SubDeviceOpen(name.to_string(), e))?;
+            entry.insert(subdev)
+        }
+    };
+
+    let rejected = |error| PipelineError::FormatRejected {
+        entity: name.to_string(),
+        pad,
+        error,
+    };
+    let mut requested = subdev
+        .get_format(pad, SubDevWhich::Active)
+        .map_err(rejected)?;
+    requested.width = width;
+    requested.height = height;
+    requested.code = code;
+    let got = subdev
+        .set_format(pad, SubDevWhich::Active, requested)
+        .map_err(rejected)?;
+    if (got.code, got.width, got.height) != (requested.code, width, height) {
+        return Err(PipelineError::FormatMismatch {
+            entity: name.to_string(),
+            pad,
+            requested,
+            got,
+        });
+    }
+
+    Ok(())
+}
+
+/// Sets the format of `queue` of the video node `device`, which is at `path`.
+fn set_video_format(
+    device: &mut Device,
+    queue: QueueType,
+    path: &Path,
+    pixel_format: PixelFormat,
+    width: u32,
+    height: u32,
+) -> Result<Format, PipelineError> {
+    let rejected = |error: Errno| PipelineError::VideoFormatRejected {
+        path: path.to_path_buf(),
+        error,
+    };
+    let mut requested: Format = ioctl::g_fmt(&*device, queue).map_err(|e| rejected(e.into()))?;
+    requested.pixelformat = pixel_format;
+    requested.width = width;
+    requested.height = height;
+    let format: Format =
+        ioctl::s_fmt(device, (queue, &requested)).map_err(|e| rejected(e.into()))?;
+    if format.pixelformat != pixel_format || (format.width, format.height) != (width, height) {
+        return Err(PipelineError::VideoFormatMismatch {
+            path: path.to_path_buf(),
+            requested: Box::new(requested),
+            got: Box::new(format),
+        });
+    }
+
+    Ok(format)
+}
+
+/// A video node configured by a [`PipelineBuilder`].
+pub struct PipelineVideoDevice {
+    /// Path of the node.
+    pub path: PathBuf,
+    /// The opened node, from which the queue can be obtained.
+    pub device: Arc<Device>,
+    /// Queue which format has been set.
+    pub queue: QueueType,
+    /// Format applied to `queue`.
+    pub format: Format,
+}
+
+/// A media controller pipeline configured by [`PipelineBuilder::build`].
+pub struct Pipeline {
+    /// Media device the pipeline belongs to.
+    pub media: MediaDevice,
+    subdevs: HashMap<u32, SubDevice>,
+    video_devices: Vec<PipelineVideoDevice>,
+}
+
+impl Pipeline {
+    /// Returns the sub-device of entity `entity_id`, if one of its formats has been set.
+    pub fn subdev(&self, entity_id: u32) -> Option<&SubDevice> {
+        self.subdevs.get(&entity_id)
+    }
+
+    /// Returns the video node at `path`, if its format has been set.
+    pub fn video_device(&self, path: &Path) -> Option<&PipelineVideoDevice> {
+        self.video_devices.iter().find(|device| device.path == path)
+    }
+
+    /// Returns all the video nodes which format has been set, in the order they were first
+    /// configured.
+    pub fn video_devices(&self) -> &[PipelineVideoDevice] {
+        &self.video_devices
+    }
+}
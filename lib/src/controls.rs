@@ -51,6 +51,26 @@
 //! assert_eq!(controls.contrast.value(), 128);
 //! ```
 //!
+//! Controls can also be created with their default value, which is convenient when they are to be
+//! read:
+//!
+//! ```no_run
+//! # use v4l2r::controls::SafeExtControl;
+//! # use v4l2r::controls::user::Brightness;
+//! # use v4l2r::controls::user::Contrast;
+//! #
+//! #[repr(C)]
+//! struct Controls {
+//!     brightness: SafeExtControl<Brightness>,
+//!     contrast: SafeExtControl<Contrast>,
+//! }
+//!
+//! let controls = Controls {
+//!     brightness: Default::default(),
+//!     contrast: Default::default(),
+//! };
+//! ```
+//!
 //! Due to the use of `repr(C)`, the `Controls` type has the same layout as an array of
 //! `v4l2_ext_control`s and thus can be passed to `s_ext_ctrls` safely.
 //!
@@ -59,16 +79,30 @@
 //! missing.
 
 pub mod codec;
+mod histogram;
+pub mod image_process;
+pub mod jpeg;
 pub mod user;
+mod value;
 
+pub use histogram::ControlHistogram;
+pub use value::{get_control_value, set_control_value, ControlValue, ControlValueError};
+
+use log::error;
 use paste::paste;
 use std::marker::PhantomData;
+use std::os::unix::io::AsRawFd;
+use thiserror::Error;
 
 use crate::bindings;
-// use crate::bindings::v4l2_ctrl_av1_film_grain;
-// use crate::bindings::v4l2_ctrl_av1_frame;
-// use crate::bindings::v4l2_ctrl_av1_sequence;
-// use crate::bindings::v4l2_ctrl_av1_tile_group_entry;
+#[cfg(v4l2r_has_av1)]
+use crate::bindings::v4l2_ctrl_av1_film_grain;
+#[cfg(v4l2r_has_av1)]
+use crate::bindings::v4l2_ctrl_av1_frame;
+#[cfg(v4l2r_has_av1)]
+use crate::bindings::v4l2_ctrl_av1_sequence;
+#[cfg(v4l2r_has_av1)]
+use crate::bindings::v4l2_ctrl_av1_tile_group_entry;
 use crate::bindings::v4l2_ctrl_fwht_params;
 use crate::bindings::v4l2_ctrl_h264_decode_params;
 use crate::bindings::v4l2_ctrl_h264_pps;
@@ -76,16 +110,75 @@ use crate::bindings::v4l2_ctrl_h264_pred_weights;
 use crate::bindings::v4l2_ctrl_h264_scaling_matrix;
 use crate::bindings::v4l2_ctrl_h264_slice_params;
 use crate::bindings::v4l2_ctrl_h264_sps;
-// use crate::bindings::v4l2_ctrl_hevc_decode_params;
-// use crate::bindings::v4l2_ctrl_hevc_pps;
-// use crate::bindings::v4l2_ctrl_hevc_scaling_matrix;
-// use crate::bindings::v4l2_ctrl_hevc_slice_params;
-// use crate::bindings::v4l2_ctrl_hevc_sps;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_ctrl_hevc_decode_params;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_ctrl_hevc_pps;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_ctrl_hevc_scaling_matrix;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_ctrl_hevc_slice_params;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_ctrl_hevc_sps;
+use crate::bindings::v4l2_ctrl_mpeg2_picture;
+use crate::bindings::v4l2_ctrl_mpeg2_quantisation;
 use crate::bindings::v4l2_ctrl_vp8_frame;
-// use crate::bindings::v4l2_ctrl_vp9_frame;
+#[cfg(v4l2r_has_vp9)]
+use crate::bindings::v4l2_ctrl_vp9_frame;
 use crate::bindings::v4l2_ext_control;
 use crate::bindings::v4l2_ext_control__bindgen_ty_1;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1CdefParams;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1FilmGrainFlags;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1FrameRestorationType;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1GlobalMotionParams;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1LoopFilter;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1LoopFilterFlags;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1LoopRestoration;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1LoopRestorationFlags;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1QuantFlags;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1Quantization;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1ScalingPoint;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1Segmentation;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1SegmentationFlags;
+#[cfg(v4l2r_has_av1)]
+use crate::controls::codec::Av1TileInfo;
+use crate::controls::codec::Component;
 use crate::controls::codec::FwhtFlags;
+#[cfg(v4l2r_has_hevc)]
+use crate::controls::codec::HevcDecodeFlags;
+#[cfg(v4l2r_has_hevc)]
+use crate::controls::codec::HevcDpbEntry;
+#[cfg(v4l2r_has_hevc)]
+use crate::controls::codec::HevcSliceFlags;
+#[cfg(v4l2r_has_hevc)]
+use crate::controls::codec::HevcSliceType;
+use crate::controls::codec::Mpeg2PictureFlags;
+use crate::controls::codec::Mpeg2PictureType;
+use crate::controls::codec::MvDirection;
+use crate::controls::codec::SliceRefList;
+use crate::controls::codec::VP8FrameFlags;
+use crate::controls::codec::VP8LoopFilterFlags;
+use crate::controls::codec::VP8SegmentFlags;
+use crate::controls::codec::Vp8LoopFilterType;
+use crate::controls::codec::Vp8PartitionCount;
+use crate::controls::codec::Vp8ReferenceFrame;
+use crate::controls::codec::Vp8SegmentFeatureMode;
+#[cfg(v4l2r_has_hevc)]
+use crate::controls::codec::HEVC_FLAT_SCALING_FACTOR;
+use crate::ioctl;
 
 /// Trait implemented by types that can be passed to the
 /// [`g/s/try_ext_ctrls`](crate::ioctl::g_ext_ctrls) family of functions.
@@ -105,7 +198,84 @@ pub trait ExtControlTrait {
     /// One of `V4L2_CID_*`
     const ID: u32;
     /// Type of the value of this control.
-    type PAYLOAD;
+    ///
+    /// The payload must implement [`ExtControlPayload`] so [`SafeExtControl`] can build a default
+    /// value and compute its size. Implementations outside of this crate using a custom payload
+    /// type therefore need to implement [`ExtControlPayload`] for it as well.
+    type PAYLOAD: ExtControlPayload;
+    /// Value used by the [`Default`] implementation of [`SafeExtControl`]. If `None`, a zeroed
+    /// payload is used.
+    const DEFAULT: Option<Self::PAYLOAD> = None;
+}
+
+/// Trait implemented by the types that can be used as the payload of a control.
+pub trait ExtControlPayload: Sized {
+    /// Size of the payload as passed to the driver in `v4l2_ext_control::size`: 0 for values
+    /// stored in the `v4l2_ext_control` itself, the size of the pointed payload otherwise.
+    const SIZE: usize = 0;
+
+    /// Returns a zero-initialized payload.
+    fn zeroed() -> Self;
+    /// Returns a `v4l2_ext_control` for control `id` containing `self`.
+    fn into_ext_control(self, id: u32) -> v4l2_ext_control;
+}
+
+/// Trait implemented by the payloads of compound controls, which are stored behind the pointer of
+/// their `v4l2_ext_control`.
+///
+/// # Safety
+///
+/// The payload must be a plain C structure for which any bit pattern is valid, and
+/// [`ExtControlPayload::into_ext_control`] must store it behind the `ptr` member of the
+/// `v4l2_ext_control`.
+pub unsafe trait CompoundPayload: ExtControlPayload {}
+
+impl ExtControlPayload for i32 {
+    fn zeroed() -> Self {
+        0
+    }
+
+    fn into_ext_control(self, id: u32) -> v4l2_ext_control {
+        v4l2_ext_control {
+            id,
+            __bindgen_anon_1: v4l2_ext_control__bindgen_ty_1 { value: self },
+            ..Default::default()
+        }
+    }
+}
+
+impl ExtControlPayload for i64 {
+    fn zeroed() -> Self {
+        0
+    }
+
+    fn into_ext_control(self, id: u32) -> v4l2_ext_control {
+        v4l2_ext_control {
+            id,
+            __bindgen_anon_1: v4l2_ext_control__bindgen_ty_1 { value64: self },
+            ..Default::default()
+        }
+    }
+}
+
+/// Unsigned 32-bit controls (e.g. bitmasks) are stored into the `value` member.
+impl ExtControlPayload for u32 {
+    fn zeroed() -> Self {
+        0
+    }
+
+    fn into_ext_control(self, id: u32) -> v4l2_ext_control {
+        (self as i32).into_ext_control(id)
+    }
+}
+
+/// Error returned when building a pointer control from a byte buffer which length does not match
+/// the size of the control's payload.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("payload is {expected} bytes but {actual} bytes were provided")]
+pub struct SizeMismatch {
+    pub expected: usize,
+    pub actual: usize,
 }
 
 /// Memory-safe `v4l2_ext_control`.
@@ -119,13 +289,110 @@ pub trait ExtControlTrait {
 ///
 /// In addition, the value of the control can only be accessed through methods that return the
 /// correct type.
+///
+/// # Thread safety
+///
+/// The payload of pointer controls is only reachable through the raw pointer of the
+/// `v4l2_ext_control`, which prevents `Send` and `Sync` from being derived automatically. That
+/// pointer is allocated by the control, never shared with another control, and freed when the
+/// control is dropped, so the control owns its payload like a `Box` would. All accesses to the
+/// payload go through methods borrowing the control for the duration of the access, so the
+/// payload cannot be aliased mutably.
+///
+/// Thus `SafeExtControl<T>` is `Send` (resp. `Sync`) if `T` and its payload are.
+///
+/// ```
+/// # use v4l2r::controls::{codec::H264Sps, user::Brightness, SafeExtControl};
+/// fn assert_send_sync<T: Send + Sync>() {}
+///
+/// assert_send_sync::<SafeExtControl<Brightness>>();
+/// assert_send_sync::<SafeExtControl<H264Sps>>();
+/// ```
+///
+/// But a control which payload cannot be sent to another thread cannot be either:
+///
+/// ```compile_fail
+/// # use v4l2r::bindings::v4l2_ext_control;
+/// # use v4l2r::controls::{ExtControlPayload, ExtControlTrait, SafeExtControl};
+/// struct NotSendPayload(std::rc::Rc<i32>);
+///
+/// impl ExtControlPayload for NotSendPayload {
+///     fn zeroed() -> Self {
+///         NotSendPayload(Default::default())
+///     }
+///
+///     fn into_ext_control(self, id: u32) -> v4l2_ext_control {
+///         v4l2_ext_control { id, ..Default::default() }
+///     }
+/// }
+///
+/// struct NotSendControl;
+/// impl ExtControlTrait for NotSendControl {
+///     const ID: u32 = 0;
+///     type PAYLOAD = NotSendPayload;
+/// }
+///
+/// fn assert_send<T: Send>() {}
+/// assert_send::<SafeExtControl<NotSendControl>>();
+/// ```
 #[repr(transparent)]
 pub struct SafeExtControl<T: ExtControlTrait>(v4l2_ext_control, PhantomData<T>);
 
+// SAFETY: the control owns its payload, see the thread safety section of `SafeExtControl`.
+unsafe impl<T> Send for SafeExtControl<T>
+where
+    T: ExtControlTrait + Send,
+    T::PAYLOAD: Send,
+{
+}
+
+// SAFETY: shared references to the control only give shared access to its payload, see the thread
+// safety section of `SafeExtControl`.
+unsafe impl<T> Sync for SafeExtControl<T>
+where
+    T: ExtControlTrait + Sync,
+    T::PAYLOAD: Sync,
+{
+}
+
 impl<T: ExtControlTrait> SafeExtControl<T> {
+    /// Create a new control with a zeroed payload.
+    pub fn new_zeroed() -> Self {
+        Self(
+            <T::PAYLOAD as ExtControlPayload>::zeroed().into_ext_control(T::ID),
+            PhantomData,
+        )
+    }
+
     pub fn id(&self) -> u32 {
         self.0.id
     }
+
+    /// Returns the expected value of `v4l2_ext_control::size` for this control, i.e. the
+    /// [`ExtControlPayload::SIZE`] of its payload.
+    ///
+    /// ```
+    /// # use v4l2r::bindings::v4l2_ctrl_h264_sps;
+    /// # use v4l2r::controls::{codec::H264Sps, user::Brightness, SafeExtControl};
+    /// assert_eq!(SafeExtControl::<Brightness>::payload_size(), 0);
+    /// assert_eq!(
+    ///     SafeExtControl::<H264Sps>::payload_size(),
+    ///     std::mem::size_of::<v4l2_ctrl_h264_sps>()
+    /// );
+    /// ```
+    pub const fn payload_size() -> usize {
+        <T::PAYLOAD as ExtControlPayload>::SIZE
+    }
+}
+
+/// Creates a control set to [`ExtControlTrait::DEFAULT`], or to a zeroed payload if the control
+/// does not define a default value.
+impl<T: ExtControlTrait> Default for SafeExtControl<T> {
+    fn default() -> Self {
+        let payload = T::DEFAULT.unwrap_or_else(<T::PAYLOAD as ExtControlPayload>::zeroed);
+
+        Self(payload.into_ext_control(T::ID), PhantomData)
+    }
 }
 
 /// Allows us to pass a `&mut` of a single `SafeExtControl` to `g/s/try_ext_ctrls`.
@@ -135,20 +402,56 @@ impl<T: ExtControlTrait> AsV4l2ControlSlice for &mut SafeExtControl<T> {
     }
 }
 
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait,
+    T::PAYLOAD: CompoundPayload,
+{
+    /// Returns the raw bytes of the payload, as they are passed to the driver.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: the payload is a plain C structure that lives as long as `self`.
+        unsafe {
+            std::slice::from_raw_parts(
+                self.0.__bindgen_anon_1.ptr as *const u8,
+                std::mem::size_of::<T::PAYLOAD>(),
+            )
+        }
+    }
+
+    /// Create a new control which payload is a copy of `bytes`.
+    ///
+    /// `bytes` must be exactly the size of the payload, otherwise [`SizeMismatch`] is returned.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SizeMismatch> {
+        let expected = std::mem::size_of::<T::PAYLOAD>();
+        if bytes.len() != expected {
+            return Err(SizeMismatch {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let mut payload = <T::PAYLOAD as ExtControlPayload>::zeroed();
+        // SAFETY: `CompoundPayload` guarantees that any bit pattern is valid for the payload, and
+        // `bytes` has been checked to be of the same size.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bytes.as_ptr(),
+                &mut payload as *mut T::PAYLOAD as *mut u8,
+                expected,
+            );
+        }
+
+        Ok(Self(payload.into_ext_control(T::ID), PhantomData))
+    }
+}
+
 impl<T> SafeExtControl<T>
 where
     T: ExtControlTrait<PAYLOAD = i32>,
 {
     /// Create a new control from its value.
     pub fn from_value(value: i32) -> Self {
-        Self(
-            v4l2_ext_control {
-                id: T::ID,
-                __bindgen_anon_1: v4l2_ext_control__bindgen_ty_1 { value },
-                ..Default::default()
-            },
-            PhantomData,
-        )
+        Self(value.into_ext_control(T::ID), PhantomData)
     }
 
     /// Returns the value of the control.
@@ -168,14 +471,7 @@ where
 {
     /// Create a new control from its value.
     pub fn from_value64(value64: i64) -> Self {
-        Self(
-            v4l2_ext_control {
-                id: T::ID,
-                __bindgen_anon_1: v4l2_ext_control__bindgen_ty_1 { value64 },
-                ..Default::default()
-            },
-            PhantomData,
-        )
+        Self(value64.into_ext_control(T::ID), PhantomData)
     }
 
     /// Returns the value of the control.
@@ -189,6 +485,35 @@ where
     }
 }
 
+/// Set the single control `T` of `fd` to `value`.
+pub(crate) fn set_control<T: ExtControlTrait<PAYLOAD = i32>>(
+    fd: &impl AsRawFd,
+    value: impl Into<i32>,
+) -> Result<(), ioctl::ExtControlError> {
+    let mut control = SafeExtControl::<T>::from_value(value.into());
+    ioctl::s_ext_ctrls(fd, ioctl::CtrlWhich::Current, &mut control)
+}
+
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait<PAYLOAD = u32>,
+{
+    /// Create a new control from its value.
+    pub fn from_value_u32(value: u32) -> Self {
+        Self(value.into_ext_control(T::ID), PhantomData)
+    }
+
+    /// Returns the value of the control.
+    pub fn value_u32(&self) -> u32 {
+        unsafe { self.0.__bindgen_anon_1.value as u32 }
+    }
+
+    /// Updates the value of the control.
+    pub fn set_value_u32(&mut self, value: u32) {
+        self.0.__bindgen_anon_1.value = value as i32;
+    }
+}
+
 impl<T> SafeExtControl<T>
 where
     T: ExtControlTrait<PAYLOAD = v4l2_ctrl_fwht_params>,
@@ -198,30 +523,524 @@ where
     }
 }
 
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait<PAYLOAD = v4l2_ctrl_vp8_frame>,
+{
+    pub fn frame_flags(&self) -> VP8FrameFlags {
+        VP8FrameFlags::from_bits_truncate(self.vp8_frame().flags as u32)
+    }
+
+    pub fn segment_flags(&self) -> VP8SegmentFlags {
+        VP8SegmentFlags::from_bits_truncate(self.vp8_frame().segment.flags)
+    }
+
+    pub fn loop_filter_flags(&self) -> VP8LoopFilterFlags {
+        VP8LoopFilterFlags::from_bits_truncate(self.vp8_frame().lf.flags)
+    }
+
+    /// Returns the sign bias of `frame`. The last frame never has a sign bias.
+    pub fn reference_frame_sign_bias(&self, frame: Vp8ReferenceFrame) -> bool {
+        let flags = self.frame_flags();
+        match frame {
+            Vp8ReferenceFrame::Last => false,
+            Vp8ReferenceFrame::Golden => flags.contains(VP8FrameFlags::SIGN_BIAS_GOLDEN),
+            Vp8ReferenceFrame::AltRef => flags.contains(VP8FrameFlags::SIGN_BIAS_ALT),
+        }
+    }
+
+    pub fn loop_filter_type(&self) -> Vp8LoopFilterType {
+        if self
+            .loop_filter_flags()
+            .contains(VP8LoopFilterFlags::FILTER_TYPE_SIMPLE)
+        {
+            Vp8LoopFilterType::Simple
+        } else {
+            Vp8LoopFilterType::Normal
+        }
+    }
+
+    /// Returns the number of DCT partitions of the frame, or `None` if `num_dct_parts` does not
+    /// contain a valid value.
+    pub fn partition_count(&self) -> Option<Vp8PartitionCount> {
+        Vp8PartitionCount::n(self.vp8_frame().num_dct_parts)
+    }
+
+    pub fn segment_enabled(&self) -> bool {
+        self.segment_flags().contains(VP8SegmentFlags::ENABLED)
+    }
+
+    pub fn update_mb_segmentation_map(&self) -> bool {
+        self.segment_flags().contains(VP8SegmentFlags::UPDATE_MAP)
+    }
+
+    pub fn segment_feature_mode(&self) -> Vp8SegmentFeatureMode {
+        if self
+            .segment_flags()
+            .contains(VP8SegmentFlags::DELTA_VALUE_MODE)
+        {
+            Vp8SegmentFeatureMode::Delta
+        } else {
+            Vp8SegmentFeatureMode::Absolute
+        }
+    }
+}
+
+#[cfg(v4l2r_has_hevc)]
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait<PAYLOAD = v4l2_ctrl_hevc_decode_params>,
+{
+    pub fn decode_flags(&self) -> HevcDecodeFlags {
+        HevcDecodeFlags::from_bits_truncate(self.hevc_decode_params().flags)
+    }
+
+    /// Returns the picture order count of the current picture.
+    pub fn pic_order_cnt_val(&self) -> i32 {
+        self.hevc_decode_params().pic_order_cnt_val
+    }
+
+    /// Returns the active entries of the DPB.
+    pub fn dpb_slice(&self) -> &[HevcDpbEntry] {
+        let params = self.hevc_decode_params();
+        let len = (params.num_active_dpb_entries as usize).min(params.dpb.len());
+
+        // SAFETY: `HevcDpbEntry` is a transparent wrapper over `v4l2_hevc_dpb_entry`, and `len`
+        // is within the bounds of the `dpb` array.
+        unsafe { std::slice::from_raw_parts(params.dpb.as_ptr() as *const HevcDpbEntry, len) }
+    }
+
+    /// Returns the indices in [`Self::dpb_slice`] of the short-term reference pictures preceding
+    /// the current picture in output order (`RefPicSetStCurrBefore`).
+    pub fn poc_st_curr_before(&self) -> &[u8] {
+        let params = self.hevc_decode_params();
+        let len = (params.num_poc_st_curr_before as usize).min(params.poc_st_curr_before.len());
+        &params.poc_st_curr_before[..len]
+    }
+
+    /// Returns the indices in [`Self::dpb_slice`] of the short-term reference pictures following
+    /// the current picture in output order (`RefPicSetStCurrAfter`).
+    pub fn poc_st_curr_after(&self) -> &[u8] {
+        let params = self.hevc_decode_params();
+        let len = (params.num_poc_st_curr_after as usize).min(params.poc_st_curr_after.len());
+        &params.poc_st_curr_after[..len]
+    }
+
+    /// Returns the indices in [`Self::dpb_slice`] of the long-term reference pictures
+    /// (`RefPicSetLtCurr`).
+    pub fn poc_lt_curr(&self) -> &[u8] {
+        let params = self.hevc_decode_params();
+        let len = (params.num_poc_lt_curr as usize).min(params.poc_lt_curr.len());
+        &params.poc_lt_curr[..len]
+    }
+}
+
+#[cfg(v4l2r_has_hevc)]
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait<PAYLOAD = v4l2_ctrl_hevc_slice_params>,
+{
+    /// Returns the type of the slice, or `None` if `slice_type` does not contain a valid value.
+    pub fn slice_type(&self) -> Option<HevcSliceType> {
+        HevcSliceType::n(self.hevc_slice_params().slice_type)
+    }
+
+    pub fn slice_flags(&self) -> HevcSliceFlags {
+        HevcSliceFlags::from_bits_truncate(self.hevc_slice_params().flags)
+    }
+
+    /// Returns the number of active entries of reference picture list 0, minus 1.
+    pub fn num_ref_idx_l0_active_minus1(&self) -> u8 {
+        self.hevc_slice_params().num_ref_idx_l0_active_minus1
+    }
+
+    /// Returns the number of active entries of reference picture list 1, minus 1. Only relevant
+    /// for B slices.
+    pub fn num_ref_idx_l1_active_minus1(&self) -> u8 {
+        self.hevc_slice_params().num_ref_idx_l1_active_minus1
+    }
+}
+
+#[cfg(v4l2r_has_hevc)]
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait<PAYLOAD = v4l2_ctrl_hevc_scaling_matrix>,
+{
+    /// Returns the 4x4 scaling list for `size_id` and `matrix_id`, as defined in H.265 7.3.4.
+    ///
+    /// Only `size_id` 0 uses 4x4 lists. Returns `None` if `size_id` or `matrix_id` is invalid.
+    pub fn scaling_list_4x4(&self, size_id: u8, matrix_id: u8) -> Option<&[u8; 16]> {
+        match size_id {
+            0 => self
+                .hevc_scaling_matrix()
+                .scaling_list_4x4
+                .get(matrix_id as usize),
+            _ => None,
+        }
+    }
+
+    /// Returns the 8x8 scaling list for `size_id` and `matrix_id`, as defined in H.265 7.3.4.
+    ///
+    /// The 16x16 (`size_id` 2) and 32x32 (`size_id` 3) lists are also stored as 8x8 lists that
+    /// are upsampled by the decoder. Only `matrix_id` 0 and 3 are valid for `size_id` 3. Returns
+    /// `None` if `size_id` or `matrix_id` is invalid.
+    pub fn scaling_list_8x8(&self, size_id: u8, matrix_id: u8) -> Option<&[u8; 64]> {
+        let matrix = self.hevc_scaling_matrix();
+        match size_id {
+            1 => matrix.scaling_list_8x8.get(matrix_id as usize),
+            2 => matrix.scaling_list_16x16.get(matrix_id as usize),
+            3 if matrix_id.is_multiple_of(3) => {
+                matrix.scaling_list_32x32.get(matrix_id as usize / 3)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns whether all the scaling factors are 16, i.e. scaling lists have no effect.
+    pub fn is_flat(&self) -> bool {
+        let matrix = self.hevc_scaling_matrix();
+
+        matrix
+            .scaling_list_4x4
+            .iter()
+            .flatten()
+            .chain(matrix.scaling_list_8x8.iter().flatten())
+            .chain(matrix.scaling_list_16x16.iter().flatten())
+            .chain(matrix.scaling_list_32x32.iter().flatten())
+            .chain(matrix.scaling_list_dc_coef_16x16.iter())
+            .chain(matrix.scaling_list_dc_coef_32x32.iter())
+            .all(|&factor| factor == HEVC_FLAT_SCALING_FACTOR)
+    }
+}
+
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait<PAYLOAD = v4l2_ctrl_h264_slice_params>,
+{
+    fn is_slice_type(&self, types: &[u32]) -> bool {
+        types.contains(&(self.h264_slice_params().slice_type as u32))
+    }
+
+    /// Returns the active part of `ref_pic_list0`, or an empty list if the slice is not a P or
+    /// SP slice.
+    pub fn ref_list_p0(&self) -> SliceRefList<'_> {
+        let params = self.h264_slice_params();
+        SliceRefList::new(
+            &params.ref_pic_list0,
+            params.num_ref_idx_l0_active_minus1,
+            self.is_slice_type(&[
+                bindings::V4L2_H264_SLICE_TYPE_P,
+                bindings::V4L2_H264_SLICE_TYPE_SP,
+            ]),
+        )
+    }
+
+    /// Returns the active part of `ref_pic_list0`, or an empty list if the slice is not a B
+    /// slice.
+    pub fn ref_list_b0(&self) -> SliceRefList<'_> {
+        let params = self.h264_slice_params();
+        SliceRefList::new(
+            &params.ref_pic_list0,
+            params.num_ref_idx_l0_active_minus1,
+            self.is_slice_type(&[bindings::V4L2_H264_SLICE_TYPE_B]),
+        )
+    }
+
+    /// Returns the active part of `ref_pic_list1`, or an empty list if the slice is not a B
+    /// slice.
+    pub fn ref_list_b1(&self) -> SliceRefList<'_> {
+        let params = self.h264_slice_params();
+        SliceRefList::new(
+            &params.ref_pic_list1,
+            params.num_ref_idx_l1_active_minus1,
+            self.is_slice_type(&[bindings::V4L2_H264_SLICE_TYPE_B]),
+        )
+    }
+}
+
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait<PAYLOAD = v4l2_ctrl_mpeg2_picture>,
+{
+    /// Returns the coding type of the picture, or `None` if `picture_coding_type` does not
+    /// contain a valid value.
+    pub fn picture_coding_type(&self) -> Option<Mpeg2PictureType> {
+        Mpeg2PictureType::n(self.mpeg2_picture().picture_coding_type)
+    }
+
+    pub fn picture_flags(&self) -> Mpeg2PictureFlags {
+        Mpeg2PictureFlags::from_bits_truncate(self.mpeg2_picture().flags)
+    }
+
+    /// Returns the `f_code` used to decode the `component` of motion vectors in `direction`.
+    pub fn f_code(&self, direction: MvDirection, component: Component) -> u8 {
+        self.mpeg2_picture().f_code[direction as usize][component as usize]
+    }
+}
+
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait<PAYLOAD = v4l2_ctrl_mpeg2_quantisation>,
+{
+    /// Returns the quantiser matrix for intra blocks, in zigzag scanning order. It applies to both
+    /// luma and chroma unless superseded by [`Self::chroma_intra_quantiser_matrix`].
+    pub fn intra_quantiser_matrix(&self) -> &[u8; 64] {
+        &self.mpeg2_quantisation().intra_quantiser_matrix
+    }
+
+    /// Returns the quantiser matrix for non-intra blocks, in zigzag scanning order. It applies to
+    /// both luma and chroma unless superseded by [`Self::chroma_non_intra_quantiser_matrix`].
+    pub fn non_intra_quantiser_matrix(&self) -> &[u8; 64] {
+        &self.mpeg2_quantisation().non_intra_quantiser_matrix
+    }
+
+    /// Returns the quantiser matrix for chroma intra blocks, in zigzag scanning order. Only
+    /// relevant for non-4:2:0 formats.
+    pub fn chroma_intra_quantiser_matrix(&self) -> &[u8; 64] {
+        &self.mpeg2_quantisation().chroma_intra_quantiser_matrix
+    }
+
+    /// Returns the quantiser matrix for chroma non-intra blocks, in zigzag scanning order. Only
+    /// relevant for non-4:2:0 formats.
+    pub fn chroma_non_intra_quantiser_matrix(&self) -> &[u8; 64] {
+        &self.mpeg2_quantisation().chroma_non_intra_quantiser_matrix
+    }
+}
+
+#[cfg(v4l2r_has_av1)]
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait<PAYLOAD = v4l2_ctrl_av1_frame>,
+{
+    /// Returns the CDEF parameters of the frame.
+    pub fn cdef(&self) -> Av1CdefParams {
+        let cdef = &self.av1_frame().cdef;
+
+        Av1CdefParams {
+            bits: cdef.bits,
+            y_pri_strength: cdef.y_pri_strength,
+            y_sec_strength: cdef.y_sec_strength,
+            uv_pri_strength: cdef.uv_pri_strength,
+            uv_sec_strength: cdef.uv_sec_strength,
+        }
+    }
+
+    /// Returns the CDEF damping value, i.e. `cdef_damping_minus_3 + 3`.
+    pub fn cdef_damping(&self) -> u8 {
+        self.av1_frame().cdef.damping_minus_3.saturating_add(3)
+    }
+
+    /// Returns the number of bits needed to specify which CDEF preset applies to a 64x64 block.
+    pub fn cdef_bits(&self) -> u8 {
+        self.av1_frame().cdef.bits
+    }
+
+    /// Returns the tile grid of the frame.
+    pub fn tile_info(&self) -> Av1TileInfo {
+        let tile_info = &self.av1_frame().tile_info;
+
+        Av1TileInfo {
+            tile_cols: tile_info.tile_cols,
+            tile_rows: tile_info.tile_rows,
+            context_update_tile_id: tile_info.context_update_tile_id as u16,
+            tile_size_bytes: tile_info.tile_size_bytes,
+            mi_col_starts: tile_info.mi_col_starts,
+            mi_row_starts: tile_info.mi_row_starts,
+            width_in_sbs_minus_1: tile_info.width_in_sbs_minus_1,
+            height_in_sbs_minus_1: tile_info.height_in_sbs_minus_1,
+        }
+    }
+
+    /// Returns the loop filter parameters of the frame.
+    pub fn loop_filter(&self) -> Av1LoopFilter {
+        let loop_filter = &self.av1_frame().loop_filter;
+
+        Av1LoopFilter {
+            flags: Av1LoopFilterFlags::from_bits_truncate(loop_filter.flags),
+            level: loop_filter.level,
+            sharpness: loop_filter.sharpness,
+            ref_deltas: loop_filter.ref_deltas,
+            mode_deltas: loop_filter.mode_deltas,
+            delta_lf_res: loop_filter.delta_lf_res,
+        }
+    }
+
+    /// Returns the loop restoration parameters of the frame.
+    pub fn loop_restoration(&self) -> Av1LoopRestoration {
+        let loop_restoration = &self.av1_frame().loop_restoration;
+
+        Av1LoopRestoration {
+            flags: Av1LoopRestorationFlags::from_bits_truncate(loop_restoration.flags),
+            lr_unit_shift: loop_restoration.lr_unit_shift,
+            lr_uv_shift: loop_restoration.lr_uv_shift,
+            frame_restoration_type: loop_restoration
+                .frame_restoration_type
+                .map(|t| Av1FrameRestorationType::n(t).unwrap_or(Av1FrameRestorationType::None)),
+            loop_restoration_size: loop_restoration.loop_restoration_size,
+        }
+    }
+
+    /// Returns the quantization parameters of the frame.
+    pub fn quantization(&self) -> Av1Quantization {
+        let quantization = &self.av1_frame().quantization;
+
+        Av1Quantization {
+            flags: Av1QuantFlags::from_bits_truncate(quantization.flags),
+            base_q_idx: quantization.base_q_idx,
+            delta_q_y_dc: quantization.delta_q_y_dc,
+            delta_q_u_dc: quantization.delta_q_u_dc,
+            delta_q_u_ac: quantization.delta_q_u_ac,
+            delta_q_v_dc: quantization.delta_q_v_dc,
+            delta_q_v_ac: quantization.delta_q_v_ac,
+            qm_y: quantization.qm_y,
+            qm_u: quantization.qm_u,
+            qm_v: quantization.qm_v,
+            delta_q_res: quantization.delta_q_res,
+        }
+    }
+
+    /// Sets the quantization parameters of the frame.
+    pub fn set_quantization(&mut self, params: &Av1Quantization) {
+        let quantization = &mut self.av1_frame_mut().quantization;
+
+        quantization.flags = params.flags.bits();
+        quantization.base_q_idx = params.base_q_idx;
+        quantization.delta_q_y_dc = params.delta_q_y_dc;
+        quantization.delta_q_u_dc = params.delta_q_u_dc;
+        quantization.delta_q_u_ac = params.delta_q_u_ac;
+        quantization.delta_q_v_dc = params.delta_q_v_dc;
+        quantization.delta_q_v_ac = params.delta_q_v_ac;
+        quantization.qm_y = params.qm_y;
+        quantization.qm_u = params.qm_u;
+        quantization.qm_v = params.qm_v;
+        quantization.delta_q_res = params.delta_q_res;
+    }
+
+    /// Returns the segmentation parameters of the frame.
+    pub fn segmentation(&self) -> Av1Segmentation {
+        let segmentation = &self.av1_frame().segmentation;
+
+        Av1Segmentation {
+            flags: Av1SegmentationFlags::from_bits_truncate(segmentation.flags),
+            last_active_seg_id: segmentation.last_active_seg_id,
+            feature_enabled: segmentation.feature_enabled,
+            feature_data: segmentation.feature_data,
+        }
+    }
+
+    /// Sets the segmentation parameters of the frame.
+    pub fn set_segmentation(&mut self, params: &Av1Segmentation) {
+        let segmentation = &mut self.av1_frame_mut().segmentation;
+
+        segmentation.flags = params.flags.bits();
+        segmentation.last_active_seg_id = params.last_active_seg_id;
+        segmentation.feature_enabled = params.feature_enabled;
+        segmentation.feature_data = params.feature_data;
+    }
+
+    /// Returns the global motion parameters of the reference frames.
+    pub fn global_motion_params(&self) -> &Av1GlobalMotionParams {
+        // SAFETY: `Av1GlobalMotionParams` is a transparent wrapper over `v4l2_av1_global_motion`.
+        unsafe { &*(&self.av1_frame().global_motion as *const _ as *const Av1GlobalMotionParams) }
+    }
+}
+
+#[cfg(v4l2r_has_av1)]
+impl<T> SafeExtControl<T>
+where
+    T: ExtControlTrait<PAYLOAD = v4l2_ctrl_av1_film_grain>,
+{
+    pub fn film_grain_flags(&self) -> Av1FilmGrainFlags {
+        Av1FilmGrainFlags::from_bits_truncate(self.av1_film_grain().flags)
+    }
+
+    /// Returns whether film grain should be added to the frame.
+    pub fn apply_grain(&self) -> bool {
+        self.film_grain_flags()
+            .contains(Av1FilmGrainFlags::APPLY_GRAIN)
+    }
+
+    /// Returns whether the chroma scaling is inferred from the luma scaling.
+    pub fn chroma_scaling_from_luma(&self) -> bool {
+        self.film_grain_flags()
+            .contains(Av1FilmGrainFlags::CHROMA_SCALING_FROM_LUMA)
+    }
+
+    /// Returns the shift applied to the scaling function minus 8, i.e. `grain_scaling_minus_8`.
+    pub fn grain_scaling_minus_8(&self) -> u8 {
+        self.av1_film_grain().grain_scaling_minus_8
+    }
+
+    /// Returns the number of auto-regressive coefficients lag.
+    pub fn ar_coeff_lag(&self) -> u8 {
+        self.av1_film_grain().ar_coeff_lag
+    }
+
+    /// Returns the number of points of the luma scaling function.
+    pub fn num_y_points(&self) -> u8 {
+        self.av1_film_grain().num_y_points
+    }
+
+    /// Returns the points of the luma scaling function, in increasing order of value.
+    ///
+    /// The payload stores the values and scalings of the points in separate arrays, so the points
+    /// are returned by value. An out-of-range `num_y_points` is clamped to the size of the arrays.
+    pub fn y_points(&self) -> Vec<Av1ScalingPoint> {
+        let film_grain = self.av1_film_grain();
+
+        film_grain
+            .point_y_value
+            .iter()
+            .zip(film_grain.point_y_scaling.iter())
+            .take(film_grain.num_y_points as usize)
+            .map(|(&value, &scaling)| Av1ScalingPoint { value, scaling })
+            .collect()
+    }
+}
+
+// Controls can be preceded by attributes (typically `#[cfg(...)]`) which are applied to all the
+// items generated for them.
 macro_rules! wrap_single_control {
-    ($ctrl:expr) => {
+    ($(#[$attr:meta])* $ctrl:ident) => {
         paste! {
+            $(#[$attr])*
+            impl ExtControlPayload for [<v4l2_ctrl_ $ctrl>] {
+                const SIZE: usize = std::mem::size_of::<Self>();
+
+                fn zeroed() -> Self {
+                    Default::default()
+                }
+
+                fn into_ext_control(self, id: u32) -> v4l2_ext_control {
+                    let payload = Box::new(self);
+
+                    v4l2_ext_control {
+                        id,
+                        size: Self::SIZE as u32,
+                        __bindgen_anon_1: v4l2_ext_control__bindgen_ty_1 {
+                            [<p_ $ctrl>]: Box::into_raw(payload),
+                        },
+                        ..Default::default()
+                    }
+                }
+            }
+
+            // SAFETY: the payload is a structure of the kernel ABI made of integers, and is
+            // stored behind the pointer of the control.
+            $(#[$attr])*
+            unsafe impl CompoundPayload for [<v4l2_ctrl_ $ctrl>] {}
+
+            $(#[$attr])*
             impl<T> From<[<v4l2_ctrl_ $ctrl>]> for SafeExtControl<T>
             where
                 T: ExtControlTrait<PAYLOAD = [<v4l2_ctrl_ $ctrl>]>,
             {
                 fn from(params: [<v4l2_ctrl_ $ctrl>]) -> Self {
-                    let payload = Box::new(params);
-
-                    Self(
-                        v4l2_ext_control {
-                            id: T::ID,
-                            size: std::mem::size_of::<T::PAYLOAD>() as u32,
-                            __bindgen_anon_1: v4l2_ext_control__bindgen_ty_1 {
-                                [<p_ $ctrl>]: Box::into_raw(payload),
-                            },
-                            ..Default::default()
-                        },
-                        PhantomData,
-                    )
+                    Self(params.into_ext_control(T::ID), PhantomData)
                 }
             }
 
+            $(#[$attr])*
             impl<T> SafeExtControl<T>
             where
                 T: ExtControlTrait<PAYLOAD = [<v4l2_ctrl_ $ctrl>]>,
@@ -239,9 +1058,9 @@ macro_rules! wrap_single_control {
 }
 
 macro_rules! wrap_controls {
-    ($($ctrl:expr),*) => {
+    ($($(#[$attr:meta])* $ctrl:ident),* $(,)?) => {
         $(
-            wrap_single_control!($ctrl);
+            wrap_single_control!($(#[$attr])* $ctrl);
         )*
     };
 }
@@ -250,18 +1069,35 @@ macro_rules! wrap_controls {
 // e.g. `where T: ControlTrait<PAYLOAD = v4l2_ctrl_fwht_params>`, so we need this global
 // implementation.
 macro_rules! wrap_drop {
-    ($($ctrl:expr),*) => {
+    ($($(#[$attr:meta])* $ctrl:ident),* $(,)?) => {
         paste! {
             impl<T: ExtControlTrait> Drop for SafeExtControl<T> {
                 fn drop(&mut self) {
+                    let (id, size) = (self.0.id, self.0.size);
+
+                    // A different size would mean that the payload has been replaced by one of
+                    // another type, which we would free with the wrong layout. Leak it instead.
+                    if size as usize != Self::payload_size() {
+                        if size > 0 {
+                            error!(
+                                "control {:#x} has a payload of {} bytes, expected {}: leaking it",
+                                id,
+                                size,
+                                Self::payload_size()
+                            );
+                        }
+                        return;
+                    }
+
                     // If we have allocated some payload for this control, re-wrap it into its
                     // original container that we immediately drop to free it.
-                    if self.0.size > 0 {
+                    if size > 0 {
                         // SAFETY: all pointers have been obtained using `Box::into_raw` and
                         // haven't been freed since.
                         unsafe {
-                            match self.0.id {
+                            match id {
                             $(
+                                $(#[$attr])*
                                 bindings::[<V4L2_CID_STATELESS_ $ctrl:upper>] => {
                                     let _ = Box::from_raw(self.0.__bindgen_anon_1.[<p_ $ctrl>]);
                                 }
@@ -283,28 +1119,15 @@ macro_rules! wrap_both {
     };
 }
 
-// wrap_both!(
-//     av1_film_grain,
-//     av1_frame,
-//     av1_sequence,
-//     av1_tile_group_entry,
-//     fwht_params,
-//     h264_decode_params,
-//     h264_pred_weights,
-//     h264_pps,
-//     h264_scaling_matrix,
-//     h264_slice_params,
-//     h264_sps,
-//     hevc_decode_params,
-//     hevc_pps,
-//     hevc_scaling_matrix,
-//     hevc_slice_params,
-//     hevc_sps,
-//     vp8_frame,
-//     vp9_frame
-// );
-
 wrap_both!(
+    #[cfg(v4l2r_has_av1)]
+    av1_film_grain,
+    #[cfg(v4l2r_has_av1)]
+    av1_frame,
+    #[cfg(v4l2r_has_av1)]
+    av1_sequence,
+    #[cfg(v4l2r_has_av1)]
+    av1_tile_group_entry,
     fwht_params,
     h264_decode_params,
     h264_pred_weights,
@@ -312,5 +1135,62 @@ wrap_both!(
     h264_scaling_matrix,
     h264_slice_params,
     h264_sps,
-    vp8_frame
+    #[cfg(v4l2r_has_hevc)]
+    hevc_decode_params,
+    #[cfg(v4l2r_has_hevc)]
+    hevc_pps,
+    #[cfg(v4l2r_has_hevc)]
+    hevc_scaling_matrix,
+    #[cfg(v4l2r_has_hevc)]
+    hevc_slice_params,
+    #[cfg(v4l2r_has_hevc)]
+    hevc_sps,
+    mpeg2_picture,
+    mpeg2_quantisation,
+    vp8_frame,
+    #[cfg(v4l2r_has_vp9)]
+    vp9_frame
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controls::codec::H264Sps;
+    use crate::controls::user::Brightness;
+
+    struct DefaultedControl;
+    impl ExtControlTrait for DefaultedControl {
+        const ID: u32 = bindings::V4L2_CID_CONTRAST;
+        type PAYLOAD = i32;
+        const DEFAULT: Option<i32> = Some(42);
+    }
+
+    #[test]
+    fn test_default_uses_declared_default() {
+        let ctrl = SafeExtControl::<DefaultedControl>::default();
+        assert_eq!(ctrl.id(), bindings::V4L2_CID_CONTRAST);
+        assert_eq!(ctrl.value(), 42);
+    }
+
+    #[test]
+    fn test_default_zeroed() {
+        let ctrl = SafeExtControl::<Brightness>::default();
+        assert_eq!(ctrl.id(), bindings::V4L2_CID_BRIGHTNESS);
+        assert_eq!({ ctrl.0.size }, 0);
+        assert_eq!(ctrl.value(), 0);
+    }
+
+    #[test]
+    fn test_default_compound_zeroed() {
+        let ctrl = SafeExtControl::<H264Sps>::default();
+        assert_eq!(
+            ctrl.0.size as usize,
+            SafeExtControl::<H264Sps>::payload_size()
+        );
+        let sps = ctrl.h264_sps();
+        assert_eq!(sps.profile_idc, 0);
+        assert_eq!(sps.level_idc, 0);
+        assert_eq!(sps.pic_width_in_mbs_minus1, 0);
+        assert_eq!(sps.flags, 0);
+    }
+}
@@ -4,6 +4,16 @@ use bitflags::bitflags;
 use enumn::N;
 
 use crate::bindings;
+#[cfg(v4l2r_has_av1)]
+use crate::bindings::v4l2_av1_global_motion;
+#[cfg(v4l2r_has_av1)]
+use crate::bindings::v4l2_ctrl_av1_film_grain;
+#[cfg(v4l2r_has_av1)]
+use crate::bindings::v4l2_ctrl_av1_frame;
+#[cfg(v4l2r_has_av1)]
+use crate::bindings::v4l2_ctrl_av1_sequence;
+#[cfg(v4l2r_has_av1)]
+use crate::bindings::v4l2_ctrl_av1_tile_group_entry;
 use crate::bindings::v4l2_ctrl_fwht_params;
 use crate::bindings::v4l2_ctrl_h264_decode_params;
 use crate::bindings::v4l2_ctrl_h264_pps;
@@ -11,8 +21,24 @@ use crate::bindings::v4l2_ctrl_h264_pred_weights;
 use crate::bindings::v4l2_ctrl_h264_scaling_matrix;
 use crate::bindings::v4l2_ctrl_h264_slice_params;
 use crate::bindings::v4l2_ctrl_h264_sps;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_ctrl_hevc_decode_params;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_ctrl_hevc_pps;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_ctrl_hevc_scaling_matrix;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_ctrl_hevc_slice_params;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_ctrl_hevc_sps;
+use crate::bindings::v4l2_ctrl_mpeg2_picture;
+use crate::bindings::v4l2_ctrl_mpeg2_quantisation;
 use crate::bindings::v4l2_ctrl_vp8_frame;
-// use crate::bindings::v4l2_ctrl_vp9_frame;
+#[cfg(v4l2r_has_vp9)]
+use crate::bindings::v4l2_ctrl_vp9_frame;
+use crate::bindings::v4l2_h264_reference;
+#[cfg(v4l2r_has_hevc)]
+use crate::bindings::v4l2_hevc_dpb_entry;
 use crate::controls::ExtControlTrait;
 
 bitflags! {
@@ -63,18 +89,466 @@ impl ExtControlTrait for H264ScalingMatrix {
     type PAYLOAD = v4l2_ctrl_h264_scaling_matrix;
 }
 
+#[cfg(v4l2r_has_hevc)]
+pub struct HevcSps;
+#[cfg(v4l2r_has_hevc)]
+impl ExtControlTrait for HevcSps {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_HEVC_SPS;
+    type PAYLOAD = v4l2_ctrl_hevc_sps;
+}
+
+#[cfg(v4l2r_has_hevc)]
+pub struct HevcPps;
+#[cfg(v4l2r_has_hevc)]
+impl ExtControlTrait for HevcPps {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_HEVC_PPS;
+    type PAYLOAD = v4l2_ctrl_hevc_pps;
+}
+
+#[cfg(v4l2r_has_hevc)]
+pub struct HevcSliceParams;
+#[cfg(v4l2r_has_hevc)]
+impl ExtControlTrait for HevcSliceParams {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_HEVC_SLICE_PARAMS;
+    type PAYLOAD = v4l2_ctrl_hevc_slice_params;
+}
+
+#[cfg(v4l2r_has_hevc)]
+/// HEVC slice type, as signaled by the `slice_type` syntax element.
+#[repr(u8)]
+#[derive(N, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HevcSliceType {
+    B = bindings::V4L2_HEVC_SLICE_TYPE_B as u8,
+    P = bindings::V4L2_HEVC_SLICE_TYPE_P as u8,
+    I = bindings::V4L2_HEVC_SLICE_TYPE_I as u8,
+}
+
+#[cfg(v4l2r_has_hevc)]
+bitflags! {
+    /// HEVC slice parameters flags.
+    ///
+    /// Whether the picture is a field is given by the `pic_struct` member of the slice parameters,
+    /// and `NO_OUTPUT_OF_PRIOR_PICS` is part of [`HevcDecodeFlags`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct HevcSliceFlags: u64 {
+        const SLICE_SAO_LUMA = bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_SLICE_SAO_LUMA as u64;
+        const SLICE_SAO_CHROMA = bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_SLICE_SAO_CHROMA as u64;
+        const SLICE_TEMPORAL_MVP_ENABLED =
+            bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_SLICE_TEMPORAL_MVP_ENABLED as u64;
+        const MVD_L1_ZERO = bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_MVD_L1_ZERO as u64;
+        const CABAC_INIT = bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_CABAC_INIT as u64;
+        const COLLOCATED_FROM_L0 = bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_COLLOCATED_FROM_L0 as u64;
+        const USE_INTEGER_MV = bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_USE_INTEGER_MV as u64;
+        const SLICE_DEBLOCKING_FILTER_DISABLED =
+            bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_SLICE_DEBLOCKING_FILTER_DISABLED as u64;
+        const SLICE_LOOP_FILTER_ACROSS_SLICES_ENABLED =
+            bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_SLICE_LOOP_FILTER_ACROSS_SLICES_ENABLED as u64;
+        const DEPENDENT_SLICE_SEGMENT =
+            bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_DEPENDENT_SLICE_SEGMENT as u64;
+    }
+}
+
+#[cfg(v4l2r_has_hevc)]
+pub struct HevcDecodeParams;
+#[cfg(v4l2r_has_hevc)]
+impl ExtControlTrait for HevcDecodeParams {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_HEVC_DECODE_PARAMS;
+    type PAYLOAD = v4l2_ctrl_hevc_decode_params;
+}
+
+#[cfg(v4l2r_has_hevc)]
+bitflags! {
+    /// HEVC decode parameters flags.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct HevcDecodeFlags: u64 {
+        const IRAP_PIC = bindings::V4L2_HEVC_DECODE_PARAM_FLAG_IRAP_PIC as u64;
+        const IDR_PIC = bindings::V4L2_HEVC_DECODE_PARAM_FLAG_IDR_PIC as u64;
+        const NO_OUTPUT_OF_PRIOR_PICS =
+            bindings::V4L2_HEVC_DECODE_PARAM_FLAG_NO_OUTPUT_OF_PRIOR as u64;
+    }
+}
+
+#[cfg(v4l2r_has_hevc)]
+bitflags! {
+    /// HEVC DPB entry flags.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct HevcDpbFlags: u8 {
+        const LONG_TERM_REFERENCE = bindings::V4L2_HEVC_DPB_ENTRY_LONG_TERM_REFERENCE as u8;
+    }
+}
+
+#[cfg(v4l2r_has_hevc)]
+/// Safe wrapper over a `v4l2_hevc_dpb_entry`, i.e. a reference picture of the DPB.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug)]
+pub struct HevcDpbEntry(pub v4l2_hevc_dpb_entry);
+
+#[cfg(v4l2r_has_hevc)]
+impl HevcDpbEntry {
+    /// Returns the timestamp of the CAPTURE buffer containing the reference picture, in
+    /// nanoseconds.
+    pub fn timestamp(&self) -> u64 {
+        self.0.timestamp
+    }
+
+    pub fn flags(&self) -> HevcDpbFlags {
+        HevcDpbFlags::from_bits_truncate(self.0.flags)
+    }
+
+    /// Returns the picture order count of the reference picture.
+    pub fn pic_order_cnt_val(&self) -> i32 {
+        self.0.pic_order_cnt_val
+    }
+
+    /// Returns the `pic_struct` of the picture timing SEI message of the reference picture.
+    pub fn field_pic(&self) -> u8 {
+        self.0.field_pic
+    }
+}
+
+#[cfg(v4l2r_has_hevc)]
+pub struct HevcScalingMatrix;
+#[cfg(v4l2r_has_hevc)]
+impl ExtControlTrait for HevcScalingMatrix {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_HEVC_SCALING_MATRIX;
+    type PAYLOAD = v4l2_ctrl_hevc_scaling_matrix;
+}
+
+#[cfg(v4l2r_has_hevc)]
+/// Default HEVC scaling list for intra prediction with `sizeId` 1 to 3, in up-right diagonal scan
+/// order (H.265 Table 7-6).
+const HEVC_DEFAULT_SCALING_LIST_INTRA: [u8; 64] = [
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 17, 16, 17, 16, 17, 18, 17, 18, 18, 17, 18, 21, 19, 20,
+    21, 20, 19, 21, 24, 22, 22, 24, 24, 22, 22, 24, 25, 25, 27, 30, 27, 25, 25, 29, 31, 35, 35, 31,
+    29, 36, 41, 44, 41, 36, 47, 54, 54, 47, 65, 70, 65, 88, 88, 115,
+];
+
+#[cfg(v4l2r_has_hevc)]
+/// Default HEVC scaling list for inter prediction with `sizeId` 1 to 3, in up-right diagonal scan
+/// order (H.265 Table 7-6).
+const HEVC_DEFAULT_SCALING_LIST_INTER: [u8; 64] = [
+    16, 16, 16, 16, 16, 16, 16, 16, 16, 16, 17, 17, 17, 17, 17, 18, 18, 18, 18, 18, 18, 20, 20, 20,
+    20, 20, 20, 20, 24, 24, 24, 24, 24, 24, 24, 24, 25, 25, 25, 25, 25, 25, 25, 28, 28, 28, 28, 28,
+    28, 33, 33, 33, 33, 33, 41, 41, 41, 41, 54, 54, 54, 71, 71, 91,
+];
+
+#[cfg(v4l2r_has_hevc)]
+/// Value of all the entries of a flat HEVC scaling list.
+pub const HEVC_FLAT_SCALING_FACTOR: u8 = 16;
+
+#[cfg(v4l2r_has_hevc)]
+/// Convert the 8x8 scaling list `list` from up-right diagonal scan order (H.265 6.5.3) into the
+/// raster scan order expected by V4L2.
+fn hevc_diagonal_to_raster(list: &[u8; 64]) -> [u8; 64] {
+    let mut raster = [0u8; 64];
+    let mut i = 0;
+
+    // Each diagonal goes from bottom-left to top-right.
+    for diagonal in 0..15 {
+        for x in 0..=diagonal {
+            let y = diagonal - x;
+            if x < 8 && y < 8 {
+                raster[y * 8 + x] = list[i];
+                i += 1;
+            }
+        }
+    }
+
+    raster
+}
+
+#[cfg(v4l2r_has_hevc)]
+impl HevcScalingMatrix {
+    /// Returns the default HEVC scaling matrices (H.265 Tables 7-5 and 7-6), to be used when the
+    /// SPS enables scaling lists without providing them.
+    pub fn from_default_hevc() -> v4l2_ctrl_hevc_scaling_matrix {
+        let intra = hevc_diagonal_to_raster(&HEVC_DEFAULT_SCALING_LIST_INTRA);
+        let inter = hevc_diagonal_to_raster(&HEVC_DEFAULT_SCALING_LIST_INTER);
+        // matrixId 0 to 2 are used for intra prediction, 3 to 5 for inter prediction.
+        let lists = [intra, intra, intra, inter, inter, inter];
+
+        v4l2_ctrl_hevc_scaling_matrix {
+            scaling_list_4x4: [[HEVC_FLAT_SCALING_FACTOR; 16]; 6],
+            scaling_list_8x8: lists,
+            scaling_list_16x16: lists,
+            scaling_list_32x32: [intra, inter],
+            scaling_list_dc_coef_16x16: [HEVC_FLAT_SCALING_FACTOR; 6],
+            scaling_list_dc_coef_32x32: [HEVC_FLAT_SCALING_FACTOR; 2],
+        }
+    }
+}
+
+pub struct Mpeg2Picture;
+impl ExtControlTrait for Mpeg2Picture {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_MPEG2_PICTURE;
+    type PAYLOAD = v4l2_ctrl_mpeg2_picture;
+}
+
+/// MPEG-2 picture coding type, as signaled by the `picture_coding_type` syntax element.
+#[repr(u8)]
+#[derive(N, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mpeg2PictureType {
+    Intra = bindings::V4L2_MPEG2_PIC_CODING_TYPE_I as u8,
+    Predictive = bindings::V4L2_MPEG2_PIC_CODING_TYPE_P as u8,
+    Bidirectional = bindings::V4L2_MPEG2_PIC_CODING_TYPE_B as u8,
+    /// DC intra-coded picture, only allowed in MPEG-1 streams.
+    DCIntra = bindings::V4L2_MPEG2_PIC_CODING_TYPE_D as u8,
+}
+
+bitflags! {
+    /// MPEG-2 picture flags.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Mpeg2PictureFlags: u32 {
+        const TOP_FIELD_FIRST = bindings::V4L2_MPEG2_PIC_FLAG_TOP_FIELD_FIRST;
+        const FRAME_PRED_DCT = bindings::V4L2_MPEG2_PIC_FLAG_FRAME_PRED_DCT;
+        const CONCEALMENT_MV = bindings::V4L2_MPEG2_PIC_FLAG_CONCEALMENT_MV;
+        const Q_SCALE_TYPE = bindings::V4L2_MPEG2_PIC_FLAG_Q_SCALE_TYPE;
+        const INTRA_VLC = bindings::V4L2_MPEG2_PIC_FLAG_INTRA_VLC;
+        const ALT_SCAN = bindings::V4L2_MPEG2_PIC_FLAG_ALT_SCAN;
+        const REPEAT_FIRST = bindings::V4L2_MPEG2_PIC_FLAG_REPEAT_FIRST;
+        const PROGRESSIVE = bindings::V4L2_MPEG2_PIC_FLAG_PROGRESSIVE;
+    }
+}
+
+/// Direction of MPEG-2 motion vectors, i.e. the first index of `f_code`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MvDirection {
+    Forward = 0,
+    Backward = 1,
+}
+
+/// Component of MPEG-2 motion vectors, i.e. the second index of `f_code`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Component {
+    Horizontal = 0,
+    Vertical = 1,
+}
+
+pub struct Mpeg2QuantMatrix;
+impl ExtControlTrait for Mpeg2QuantMatrix {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_MPEG2_QUANTISATION;
+    type PAYLOAD = v4l2_ctrl_mpeg2_quantisation;
+}
+
+/// Default MPEG-2 quantiser matrix for intra blocks, in raster scan order (ISO/IEC 13818-2
+/// 6.3.11).
+const MPEG2_DEFAULT_INTRA_QUANTISER_MATRIX: [u8; 64] = [
+    8, 16, 19, 22, 26, 27, 29, 34, 16, 16, 22, 24, 27, 29, 34, 37, 19, 22, 26, 27, 29, 34, 34, 38,
+    22, 22, 26, 27, 29, 34, 37, 40, 22, 26, 27, 29, 32, 35, 40, 48, 26, 27, 29, 32, 35, 40, 48, 58,
+    26, 27, 29, 34, 38, 46, 56, 69, 27, 29, 35, 38, 46, 56, 69, 83,
+];
+
+/// Value of all the entries of the default MPEG-2 quantiser matrix for non-intra blocks.
+const MPEG2_DEFAULT_NON_INTRA_QUANTISER: u8 = 16;
+
+/// Raster scan position of each coefficient of an 8x8 block in zigzag scanning order (ISO/IEC
+/// 13818-2 Figure 7-2).
+const MPEG2_ZIGZAG_SCAN: [u8; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+impl Mpeg2QuantMatrix {
+    /// Returns the default quantiser matrix for intra blocks (ISO/IEC 13818-2 6.3.11), in the
+    /// zigzag scanning order expected by V4L2.
+    pub fn default_intra() -> [u8; 64] {
+        std::array::from_fn(|i| MPEG2_DEFAULT_INTRA_QUANTISER_MATRIX[MPEG2_ZIGZAG_SCAN[i] as usize])
+    }
+
+    /// Returns the default quantiser matrix for non-intra blocks (ISO/IEC 13818-2 6.3.11), in the
+    /// zigzag scanning order expected by V4L2.
+    pub fn default_non_intra() -> [u8; 64] {
+        [MPEG2_DEFAULT_NON_INTRA_QUANTISER; 64]
+    }
+}
+
 pub struct H264PredWeights;
 impl ExtControlTrait for H264PredWeights {
     const ID: u32 = bindings::V4L2_CID_STATELESS_H264_PRED_WEIGHTS;
     type PAYLOAD = v4l2_ctrl_h264_pred_weights;
 }
 
+impl H264PredWeights {
+    /// Returns the factor by which a sample is scaled when using `weight` with a log2
+    /// denominator of `denom`, i.e. `weight / 2^denom` (ITU-T H.264 8.4.2.3).
+    pub fn weight_scale(denom: u8, weight: i16) -> f32 {
+        weight as f32 / (1u32 << denom.min(31)) as f32
+    }
+}
+
+/// Builder for the payload of the [`H264PredWeights`] control.
+///
+/// The table starts zeroed, and explicit weights are only recorded if the PPS and slice type
+/// require them (the `V4L2_H264_CTRL_PRED_WEIGHTS_REQUIRED` condition of the V4L2 API). Entries
+/// that have not been set explicitly get the default weight of `2^denom` and offset of 0 when the
+/// table is built, as specified by ITU-T H.264 7.4.3.2.
+pub struct H264PredWeightsBuilder {
+    weights: v4l2_ctrl_h264_pred_weights,
+    required: bool,
+    luma_set: [[bool; 32]; 2],
+    chroma_set: [[bool; 32]; 2],
+}
+
+impl H264PredWeightsBuilder {
+    /// Creates a zeroed builder for a slice of type `slice_type` (one of the
+    /// `V4L2_H264_SLICE_TYPE_*` values) referring to `pps`.
+    pub fn new(pps: &v4l2_ctrl_h264_pps, slice_type: u8) -> Self {
+        let slice_type = slice_type as u32;
+        let weighted_pred = pps.flags as u32 & bindings::V4L2_H264_PPS_FLAG_WEIGHTED_PRED != 0;
+        let required = (weighted_pred
+            && (slice_type == bindings::V4L2_H264_SLICE_TYPE_P
+                || slice_type == bindings::V4L2_H264_SLICE_TYPE_SP))
+            || (pps.weighted_bipred_idc == 1 && slice_type == bindings::V4L2_H264_SLICE_TYPE_B);
+
+        Self {
+            weights: Default::default(),
+            required,
+            luma_set: [[false; 32]; 2],
+            chroma_set: [[false; 32]; 2],
+        }
+    }
+
+    /// Returns whether explicit prediction weights are used for this slice.
+    pub fn weights_required(&self) -> bool {
+        self.required
+    }
+
+    pub fn luma_log2_weight_denom(mut self, denom: u16) -> Self {
+        if self.required {
+            self.weights.luma_log2_weight_denom = denom;
+        }
+        self
+    }
+
+    pub fn chroma_log2_weight_denom(mut self, denom: u16) -> Self {
+        if self.required {
+            self.weights.chroma_log2_weight_denom = denom;
+        }
+        self
+    }
+
+    /// Sets the luma weight and offset of reference `ref_idx` in reference picture list `list`
+    /// (0 or 1). Out-of-range indices are ignored.
+    pub fn luma_weight(mut self, list: usize, ref_idx: usize, weight: i16, offset: i16) -> Self {
+        if self.required && list < 2 && ref_idx < 32 {
+            let factors = &mut self.weights.weight_factors[list];
+            factors.luma_weight[ref_idx] = weight;
+            factors.luma_offset[ref_idx] = offset;
+            self.luma_set[list][ref_idx] = true;
+        }
+        self
+    }
+
+    /// Sets the Cb and Cr weights and offsets of reference `ref_idx` in reference picture list
+    /// `list` (0 or 1). Out-of-range indices are ignored.
+    pub fn chroma_weight(
+        mut self,
+        list: usize,
+        ref_idx: usize,
+        weight: [i16; 2],
+        offset: [i16; 2],
+    ) -> Self {
+        if self.required && list < 2 && ref_idx < 32 {
+            let factors = &mut self.weights.weight_factors[list];
+            factors.chroma_weight[ref_idx] = weight;
+            factors.chroma_offset[ref_idx] = offset;
+            self.chroma_set[list][ref_idx] = true;
+        }
+        self
+    }
+
+    /// Builds the payload. If explicit weights are not required, the zeroed table is returned.
+    pub fn build(mut self) -> v4l2_ctrl_h264_pred_weights {
+        if !self.required {
+            return self.weights;
+        }
+
+        let luma_default = 1i16 << self.weights.luma_log2_weight_denom.min(7);
+        let chroma_default = 1i16 << self.weights.chroma_log2_weight_denom.min(7);
+        for (list, factors) in self.weights.weight_factors.iter_mut().enumerate() {
+            for i in 0..32 {
+                if !self.luma_set[list][i] {
+                    factors.luma_weight[i] = luma_default;
+                }
+                if !self.chroma_set[list][i] {
+                    factors.chroma_weight[i] = [chroma_default; 2];
+                }
+            }
+        }
+
+        self.weights
+    }
+
+    /// Returns a table with equal weights for all references, i.e. a denominator of 0, weights
+    /// of 1 and offsets of 0.
+    pub fn unweighted() -> v4l2_ctrl_h264_pred_weights {
+        let mut weights = v4l2_ctrl_h264_pred_weights::default();
+        for factors in weights.weight_factors.iter_mut() {
+            factors.luma_weight = [1; 32];
+            factors.chroma_weight = [[1; 2]; 32];
+        }
+        weights
+    }
+}
+
 pub struct H264SliceParams;
 impl ExtControlTrait for H264SliceParams {
     const ID: u32 = bindings::V4L2_CID_STATELESS_H264_SLICE_PARAMS;
     type PAYLOAD = v4l2_ctrl_h264_slice_params;
 }
 
+/// Active part of one of the reference picture lists of a [`H264SliceParams`] control.
+///
+/// Entries are indices into the `dpb` array of the [`H264DecodeParams`] control of the same
+/// frame. Entries beyond the number of active references are ignored, and entries pointing past
+/// the end of the DPB (a common consequence of stale lists) are reported as invalid.
+#[derive(Clone, Copy, Debug)]
+pub struct SliceRefList<'a> {
+    refs: &'a [v4l2_h264_reference],
+}
+
+impl<'a> SliceRefList<'a> {
+    /// Returns a list made of the first `num_ref_idx_active_minus1 + 1` entries of `refs`, or an
+    /// empty list if `active` is `false`.
+    pub(crate) fn new(
+        refs: &'a [v4l2_h264_reference],
+        num_ref_idx_active_minus1: u8,
+        active: bool,
+    ) -> Self {
+        let len = if active {
+            (num_ref_idx_active_minus1 as usize + 1).min(refs.len())
+        } else {
+            0
+        };
+
+        Self { refs: &refs[..len] }
+    }
+
+    /// Returns the number of active entries in the list.
+    pub fn len(&self) -> u8 {
+        self.refs.len() as u8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.refs.is_empty()
+    }
+
+    /// Returns the DPB index of entry `n`, or `None` if `n` is not an active entry or its index
+    /// is out of the bounds of the DPB.
+    pub fn get(&self, n: usize) -> Option<u8> {
+        self.refs
+            .get(n)
+            .map(|r| r.index)
+            .filter(|&index| (index as u32) < bindings::V4L2_H264_NUM_DPB_ENTRIES)
+    }
+
+    /// Returns whether all the active entries of the list are within the bounds of the DPB.
+    pub fn is_valid(&self) -> bool {
+        (0..self.refs.len()).all(|n| self.get(n).is_some())
+    }
+}
+
 pub struct H264DecodeParams;
 impl ExtControlTrait for H264DecodeParams {
     const ID: u32 = bindings::V4L2_CID_STATELESS_H264_DECODE_PARAMS;
@@ -121,17 +595,497 @@ bitflags! {
     }
 }
 
+/// VP8 reference frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vp8ReferenceFrame {
+    Last,
+    Golden,
+    AltRef,
+}
+
+/// VP8 loop filter type, as signaled by the `filter_type` syntax element.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vp8LoopFilterType {
+    Normal,
+    Simple,
+}
+
+/// Number of DCT coefficients partitions of a VP8 frame.
+#[repr(u8)]
+#[derive(N, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Vp8PartitionCount {
+    One = 1,
+    Two = 2,
+    Four = 4,
+    Eight = 8,
+}
+
+/// How the segment feature data of a VP8 frame is to be interpreted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vp8SegmentFeatureMode {
+    /// Segment values are deltas from the frame-level values.
+    Delta,
+    /// Segment values replace the frame-level values.
+    Absolute,
+}
+
 pub struct Vp8Frame;
 impl ExtControlTrait for Vp8Frame {
     const ID: u32 = bindings::V4L2_CID_STATELESS_VP8_FRAME;
     type PAYLOAD = v4l2_ctrl_vp8_frame;
 }
 
-// pub struct Vp9Frame;
-// impl ExtControlTrait for Vp9Frame {
-//     const ID: u32 = bindings::V4L2_CID_STATELESS_VP9_FRAME;
-//     type PAYLOAD = v4l2_ctrl_vp9_frame;
-// }
+#[cfg(v4l2r_has_vp9)]
+pub struct Vp9Frame;
+#[cfg(v4l2r_has_vp9)]
+impl ExtControlTrait for Vp9Frame {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_VP9_FRAME;
+    type PAYLOAD = v4l2_ctrl_vp9_frame;
+}
+
+#[cfg(v4l2r_has_av1)]
+pub struct Av1Sequence;
+#[cfg(v4l2r_has_av1)]
+impl ExtControlTrait for Av1Sequence {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_AV1_SEQUENCE;
+    type PAYLOAD = v4l2_ctrl_av1_sequence;
+}
+
+#[cfg(v4l2r_has_av1)]
+pub struct Av1TileGroupEntry;
+#[cfg(v4l2r_has_av1)]
+impl ExtControlTrait for Av1TileGroupEntry {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_AV1_TILE_GROUP_ENTRY;
+    type PAYLOAD = v4l2_ctrl_av1_tile_group_entry;
+}
+
+#[cfg(v4l2r_has_av1)]
+pub struct Av1Frame;
+#[cfg(v4l2r_has_av1)]
+impl ExtControlTrait for Av1Frame {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_AV1_FRAME;
+    type PAYLOAD = v4l2_ctrl_av1_frame;
+}
+
+#[cfg(v4l2r_has_av1)]
+pub struct Av1FilmGrain;
+#[cfg(v4l2r_has_av1)]
+impl ExtControlTrait for Av1FilmGrain {
+    const ID: u32 = bindings::V4L2_CID_STATELESS_AV1_FILM_GRAIN;
+    type PAYLOAD = v4l2_ctrl_av1_film_grain;
+}
+
+/// CDEF (Constrained Directional Enhancement Filter) parameters of an AV1 frame, as defined in
+/// AV1 5.9.19.
+///
+/// Each of the `1 << bits` CDEF presets of the frame is made of a primary and a secondary filter
+/// strength for luma and chroma. As in the AV1 specification, secondary strengths are their actual
+/// value, i.e. a coded value of `3` becomes `4`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Av1CdefParams {
+    /// Number of bits needed to specify which CDEF preset applies to a 64x64 block.
+    pub bits: u8,
+    pub y_pri_strength: [u8; 8],
+    pub y_sec_strength: [u8; 8],
+    pub uv_pri_strength: [u8; 8],
+    pub uv_sec_strength: [u8; 8],
+}
+
+impl Av1CdefParams {
+    /// Returns the number of CDEF presets of the frame.
+    pub fn num_presets(&self) -> usize {
+        1 << self.bits.min(3)
+    }
+}
+
+/// Helpers for the combined CDEF strengths of AV1.
+pub struct CdefStrength;
+
+impl CdefStrength {
+    /// Returns the combined strength index of a primary strength `pri` (0 to 15) and secondary
+    /// strength `sec` (0 to 4), i.e. the value of the `cdef_y_strengths` or `cdef_uv_strengths`
+    /// syntax elements of the AV1 reference decoder.
+    ///
+    /// The secondary strength is coded on 2 bits, with `4` being coded as `3`. Out-of-range
+    /// values are clamped.
+    pub fn from_pri_sec(pri: u8, sec: u8) -> u8 {
+        let sec = match sec {
+            0..=2 => sec,
+            _ => 3,
+        };
+
+        (pri.min(15) << 2) | sec
+    }
+}
+
+/// Maximum number of tile columns of an AV1 frame.
+#[cfg(v4l2r_has_av1)]
+pub const AV1_MAX_TILE_COLS: usize = bindings::V4L2_AV1_MAX_TILE_COLS as usize;
+/// Maximum number of tile rows of an AV1 frame.
+#[cfg(v4l2r_has_av1)]
+pub const AV1_MAX_TILE_ROWS: usize = bindings::V4L2_AV1_MAX_TILE_ROWS as usize;
+
+/// Tile grid of an AV1 frame, as defined in AV1 5.9.15.
+#[cfg(v4l2r_has_av1)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Av1TileInfo {
+    pub tile_cols: u8,
+    pub tile_rows: u8,
+    /// Tile to use for the CDF update.
+    pub context_update_tile_id: u16,
+    /// Number of bytes used to code the size of each tile.
+    pub tile_size_bytes: u8,
+    /// Start column of each tile, in units of 4x4 luma samples. The entry at index `tile_cols` is
+    /// the end of the last tile.
+    pub mi_col_starts: [u32; AV1_MAX_TILE_COLS + 1],
+    /// Start row of each tile, in units of 4x4 luma samples. The entry at index `tile_rows` is the
+    /// end of the last tile.
+    pub mi_row_starts: [u32; AV1_MAX_TILE_ROWS + 1],
+    /// Width of each tile column minus 1, in superblocks.
+    pub width_in_sbs_minus_1: [u32; AV1_MAX_TILE_COLS],
+    /// Height of each tile row minus 1, in superblocks.
+    pub height_in_sbs_minus_1: [u32; AV1_MAX_TILE_ROWS],
+}
+
+#[cfg(v4l2r_has_av1)]
+impl Av1TileInfo {
+    /// Returns the number of tiles of the frame.
+    pub fn tile_count(&self) -> u32 {
+        self.tile_cols as u32 * self.tile_rows as u32
+    }
+
+    /// Returns the `(col_start, col_end, row_start, row_end)` bounds of the tile at column `col`
+    /// and row `row`, in superblocks, or `None` if there is no such tile or if its bounds do not
+    /// fit in a `u32`. The end bounds are exclusive.
+    pub fn tile_bounds(&self, col: u8, row: u8) -> Option<(u32, u32, u32, u32)> {
+        if col >= self.tile_cols
+            || row >= self.tile_rows
+            || col as usize >= AV1_MAX_TILE_COLS
+            || row as usize >= AV1_MAX_TILE_ROWS
+        {
+            return None;
+        }
+
+        // Tiles are laid out contiguously, so the start of a tile is the sum of the sizes of the
+        // previous ones.
+        let bounds = |sizes_minus_1: &[u32], index: usize| {
+            let start = sizes_minus_1[..index]
+                .iter()
+                .try_fold(0u32, |start, s| start.checked_add(s.checked_add(1)?))?;
+            let end = start.checked_add(sizes_minus_1[index].checked_add(1)?)?;
+            Some((start, end))
+        };
+        let (col_start, col_end) = bounds(&self.width_in_sbs_minus_1, col as usize)?;
+        let (row_start, row_end) = bounds(&self.height_in_sbs_minus_1, row as usize)?;
+
+        Some((col_start, col_end, row_start, row_end))
+    }
+}
+
+#[cfg(v4l2r_has_av1)]
+bitflags! {
+    /// AV1 film grain flags.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Av1FilmGrainFlags: u8 {
+        const APPLY_GRAIN = bindings::V4L2_AV1_FILM_GRAIN_FLAG_APPLY_GRAIN as u8;
+        const UPDATE_GRAIN = bindings::V4L2_AV1_FILM_GRAIN_FLAG_UPDATE_GRAIN as u8;
+        const CHROMA_SCALING_FROM_LUMA =
+            bindings::V4L2_AV1_FILM_GRAIN_FLAG_CHROMA_SCALING_FROM_LUMA as u8;
+        const OVERLAP = bindings::V4L2_AV1_FILM_GRAIN_FLAG_OVERLAP as u8;
+        const CLIP_TO_RESTRICTED_RANGE =
+            bindings::V4L2_AV1_FILM_GRAIN_FLAG_CLIP_TO_RESTRICTED_RANGE as u8;
+    }
+}
+
+/// A point of the piecewise-linear scaling function of the AV1 film grain synthesis, as defined
+/// in AV1 5.9.30.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Av1ScalingPoint {
+    /// Pixel value at which the point is located.
+    pub value: u8,
+    /// Scaling of the grain at this pixel value.
+    pub scaling: u8,
+}
+
+/// AV1 reference frames, as defined in AV1 6.10.24.
+#[cfg(v4l2r_has_av1)]
+#[repr(u32)]
+#[derive(N, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Av1ReferenceFrame {
+    Last = bindings::v4l2_av1_reference_frame_V4L2_AV1_REF_LAST_FRAME,
+    Last2 = bindings::v4l2_av1_reference_frame_V4L2_AV1_REF_LAST2_FRAME,
+    Last3 = bindings::v4l2_av1_reference_frame_V4L2_AV1_REF_LAST3_FRAME,
+    Golden = bindings::v4l2_av1_reference_frame_V4L2_AV1_REF_GOLDEN_FRAME,
+    BwdRef = bindings::v4l2_av1_reference_frame_V4L2_AV1_REF_BWDREF_FRAME,
+    AltRef2 = bindings::v4l2_av1_reference_frame_V4L2_AV1_REF_ALTREF2_FRAME,
+    AltRef = bindings::v4l2_av1_reference_frame_V4L2_AV1_REF_ALTREF_FRAME,
+}
+
+/// AV1 global motion transformation type, as signaled by the `GmType` syntax element.
+#[cfg(v4l2r_has_av1)]
+#[repr(u32)]
+#[derive(N, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Av1GmType {
+    Identity = bindings::v4l2_av1_warp_model_V4L2_AV1_WARP_MODEL_IDENTITY,
+    Translation = bindings::v4l2_av1_warp_model_V4L2_AV1_WARP_MODEL_TRANSLATION,
+    RotZoom = bindings::v4l2_av1_warp_model_V4L2_AV1_WARP_MODEL_ROTZOOM,
+    Affine = bindings::v4l2_av1_warp_model_V4L2_AV1_WARP_MODEL_AFFINE,
+}
+
+#[cfg(v4l2r_has_av1)]
+bitflags! {
+    /// AV1 global motion flags, as signaled by the `is_global`, `is_rot_zoom` and
+    /// `is_translation` syntax elements.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Av1GlobalMotionFlags: u8 {
+        const IS_GLOBAL = bindings::V4L2_AV1_GLOBAL_MOTION_FLAG_IS_GLOBAL as u8;
+        const IS_ROT_ZOOM = bindings::V4L2_AV1_GLOBAL_MOTION_FLAG_IS_ROT_ZOOM as u8;
+        const IS_TRANSLATION = bindings::V4L2_AV1_GLOBAL_MOTION_FLAG_IS_TRANSLATION as u8;
+    }
+}
+
+/// Precision of the warp model parameters, i.e. `WARPEDMODEL_PREC_BITS` in AV1 3.
+#[cfg(v4l2r_has_av1)]
+const AV1_WARPEDMODEL_PREC_BITS: u32 = 16;
+
+/// Safe wrapper over a `v4l2_av1_global_motion`, i.e. the global motion parameters of each
+/// reference frame of an AV1 frame, as defined in AV1 5.9.24.
+#[cfg(v4l2r_has_av1)]
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug)]
+pub struct Av1GlobalMotionParams(pub v4l2_av1_global_motion);
+
+#[cfg(v4l2r_has_av1)]
+impl Av1GlobalMotionParams {
+    /// Parameters of the identity transformation, which are also the default values of the
+    /// `gm_params` syntax element.
+    pub const IDENTITY: [i32; 6] = [
+        0,
+        0,
+        1 << AV1_WARPEDMODEL_PREC_BITS,
+        0,
+        0,
+        1 << AV1_WARPEDMODEL_PREC_BITS,
+    ];
+
+    pub fn flags(&self, ref_frame: Av1ReferenceFrame) -> Av1GlobalMotionFlags {
+        Av1GlobalMotionFlags::from_bits_truncate(self.0.flags[ref_frame as usize])
+    }
+
+    /// Returns the transformation type used for `ref_frame`. An unknown type is reported as
+    /// [`Av1GmType::Identity`].
+    pub fn motion_type(&self, ref_frame: Av1ReferenceFrame) -> Av1GmType {
+        Av1GmType::n(self.0.type_[ref_frame as usize]).unwrap_or(Av1GmType::Identity)
+    }
+
+    /// Returns the warp model parameters of `ref_frame`, in the order of the `gm_params` syntax
+    /// element.
+    pub fn params(&self, ref_frame: Av1ReferenceFrame) -> &[i32; 6] {
+        &self.0.params[ref_frame as usize]
+    }
+
+    /// Returns whether the motion of `ref_frame` is the identity, either because of its type or
+    /// because its parameters are those of the identity matrix.
+    pub fn is_identity(&self, ref_frame: Av1ReferenceFrame) -> bool {
+        self.motion_type(ref_frame) == Av1GmType::Identity
+            || *self.params(ref_frame) == Self::IDENTITY
+    }
+}
+
+/// Number of reference frames of an AV1 frame, including the intra frame.
+#[cfg(v4l2r_has_av1)]
+pub const AV1_TOTAL_REFS_PER_FRAME: usize = bindings::V4L2_AV1_TOTAL_REFS_PER_FRAME as usize;
+/// Maximum number of planes of an AV1 frame.
+#[cfg(v4l2r_has_av1)]
+pub const AV1_NUM_PLANES_MAX: usize = bindings::V4L2_AV1_NUM_PLANES_MAX as usize;
+
+#[cfg(v4l2r_has_av1)]
+bitflags! {
+    /// AV1 loop filter flags, as signaled by the `loop_filter_delta_enabled`,
+    /// `loop_filter_delta_update`, `delta_lf_present` and `delta_lf_multi` syntax elements.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Av1LoopFilterFlags: u8 {
+        const DELTA_ENABLED = bindings::V4L2_AV1_LOOP_FILTER_FLAG_DELTA_ENABLED as u8;
+        const DELTA_UPDATE = bindings::V4L2_AV1_LOOP_FILTER_FLAG_DELTA_UPDATE as u8;
+        const DELTA_LF_PRESENT = bindings::V4L2_AV1_LOOP_FILTER_FLAG_DELTA_LF_PRESENT as u8;
+        const DELTA_LF_MULTI = bindings::V4L2_AV1_LOOP_FILTER_FLAG_DELTA_LF_MULTI as u8;
+    }
+}
+
+/// Loop filter parameters of an AV1 frame, as defined in AV1 5.9.11.
+#[cfg(v4l2r_has_av1)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Av1LoopFilter {
+    pub flags: Av1LoopFilterFlags,
+    /// Filter strength for the vertical luma edges, horizontal luma edges, U plane and V plane.
+    pub level: [u8; 4],
+    pub sharpness: u8,
+    /// Adjustment of the filter level based on the reference frame. Index 0 is the intra frame,
+    /// the other ones are indexed by [`Av1ReferenceFrame`].
+    pub ref_deltas: [i8; AV1_TOTAL_REFS_PER_FRAME],
+    /// Adjustment of the filter level based on the prediction mode.
+    pub mode_deltas: [i8; 2],
+    /// Left shift to apply to the decoded loop filter delta values.
+    pub delta_lf_res: u8,
+}
+
+#[cfg(v4l2r_has_av1)]
+impl Av1LoopFilter {
+    /// Returns the filter level adjustment of `ref_frame`.
+    pub fn ref_delta(&self, ref_frame: Av1ReferenceFrame) -> i8 {
+        self.ref_deltas[ref_frame as usize]
+    }
+
+    /// Returns the filter level adjustment of the intra frame.
+    pub fn intra_delta(&self) -> i8 {
+        self.ref_deltas[0]
+    }
+}
+
+/// AV1 loop restoration type of a plane, as signaled by the `lr_type` syntax element.
+#[cfg(v4l2r_has_av1)]
+#[repr(u32)]
+#[derive(N, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Av1FrameRestorationType {
+    None = bindings::v4l2_av1_frame_restoration_type_V4L2_AV1_FRAME_RESTORE_NONE,
+    Wiener = bindings::v4l2_av1_frame_restoration_type_V4L2_AV1_FRAME_RESTORE_WIENER,
+    Sgrproj = bindings::v4l2_av1_frame_restoration_type_V4L2_AV1_FRAME_RESTORE_SGRPROJ,
+    Switchable = bindings::v4l2_av1_frame_restoration_type_V4L2_AV1_FRAME_RESTORE_SWITCHABLE,
+}
+
+#[cfg(v4l2r_has_av1)]
+bitflags! {
+    /// AV1 loop restoration flags, as signaled by the `UsesLr` and `usesChromaLr` variables.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Av1LoopRestorationFlags: u8 {
+        const USES_LR = bindings::V4L2_AV1_LOOP_RESTORATION_FLAG_USES_LR as u8;
+        const USES_CHROMA_LR = bindings::V4L2_AV1_LOOP_RESTORATION_FLAG_USES_CHROMA_LR as u8;
+    }
+}
+
+/// Loop restoration parameters of an AV1 frame, as defined in AV1 5.9.20.
+#[cfg(v4l2r_has_av1)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Av1LoopRestoration {
+    pub flags: Av1LoopRestorationFlags,
+    /// Shift applied to the size of the luma loop restoration units.
+    pub lr_unit_shift: u8,
+    /// Shift applied to the size of the chroma loop restoration units.
+    pub lr_uv_shift: u8,
+    /// Restoration type of each plane. An unknown type is reported as
+    /// [`Av1FrameRestorationType::None`].
+    pub frame_restoration_type: [Av1FrameRestorationType; AV1_NUM_PLANES_MAX],
+    /// Size of the loop restoration units of each plane, in samples.
+    pub loop_restoration_size: [u32; AV1_NUM_PLANES_MAX],
+}
+
+#[cfg(v4l2r_has_av1)]
+bitflags! {
+    /// AV1 quantization flags, as signaled by the `diff_uv_delta`, `using_qmatrix` and
+    /// `delta_q_present` syntax elements.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Av1QuantFlags: u8 {
+        const DIFF_UV_DELTA = bindings::V4L2_AV1_QUANTIZATION_FLAG_DIFF_UV_DELTA as u8;
+        const USING_QMATRIX = bindings::V4L2_AV1_QUANTIZATION_FLAG_USING_QMATRIX as u8;
+        const DELTA_Q_PRESENT = bindings::V4L2_AV1_QUANTIZATION_FLAG_DELTA_Q_PRESENT as u8;
+    }
+}
+
+/// Quantization parameters of an AV1 frame, as defined in AV1 5.9.12.
+#[cfg(v4l2r_has_av1)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Av1Quantization {
+    pub flags: Av1QuantFlags,
+    /// Base frame Q index, applying to the AC coefficients of the luma plane.
+    pub base_q_idx: u8,
+    pub delta_q_y_dc: i8,
+    pub delta_q_u_dc: i8,
+    pub delta_q_u_ac: i8,
+    pub delta_q_v_dc: i8,
+    pub delta_q_v_ac: i8,
+    /// Quantizer matrix level of the Y, U and V planes, only relevant if
+    /// [`Av1QuantFlags::USING_QMATRIX`] is set.
+    pub qm_y: u8,
+    pub qm_u: u8,
+    pub qm_v: u8,
+    /// Left shift to apply to the decoded quantizer index delta values.
+    pub delta_q_res: u8,
+}
+
+/// Maximum number of segments of an AV1 frame.
+#[cfg(v4l2r_has_av1)]
+pub const AV1_MAX_SEGMENTS: usize = bindings::V4L2_AV1_MAX_SEGMENTS as usize;
+/// Number of features of an AV1 segment.
+#[cfg(v4l2r_has_av1)]
+pub const AV1_SEG_LVL_MAX: usize = bindings::v4l2_av1_segment_feature_V4L2_AV1_SEG_LVL_MAX as usize;
+
+#[cfg(v4l2r_has_av1)]
+bitflags! {
+    /// AV1 segmentation flags, as signaled by the `segmentation_enabled`,
+    /// `segmentation_update_map`, `segmentation_temporal_update` and `segmentation_update_data`
+    /// syntax elements and the `SegIdPreSkip` variable.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Av1SegmentationFlags: u8 {
+        const ENABLED = bindings::V4L2_AV1_SEGMENTATION_FLAG_ENABLED as u8;
+        const UPDATE_MAP = bindings::V4L2_AV1_SEGMENTATION_FLAG_UPDATE_MAP as u8;
+        const TEMPORAL_UPDATE = bindings::V4L2_AV1_SEGMENTATION_FLAG_TEMPORAL_UPDATE as u8;
+        const UPDATE_DATA = bindings::V4L2_AV1_SEGMENTATION_FLAG_UPDATE_DATA as u8;
+        const SEG_ID_PRE_SKIP = bindings::V4L2_AV1_SEGMENTATION_FLAG_SEG_ID_PRE_SKIP as u8;
+    }
+}
+
+/// AV1 segment features, as defined by the `SEG_LVL_*` constants of AV1 3.
+#[cfg(v4l2r_has_av1)]
+#[repr(u32)]
+#[derive(N, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Av1SegmentFeature {
+    AltQ = bindings::v4l2_av1_segment_feature_V4L2_AV1_SEG_LVL_ALT_Q,
+    AltLfYV = bindings::v4l2_av1_segment_feature_V4L2_AV1_SEG_LVL_ALT_LF_Y_V,
+    AltLfYH = bindings::v4l2_av1_segment_feature_V4L2_AV1_SEG_LVL_ALT_LF_Y_V + 1,
+    AltLfU = bindings::v4l2_av1_segment_feature_V4L2_AV1_SEG_LVL_ALT_LF_Y_V + 2,
+    AltLfV = bindings::v4l2_av1_segment_feature_V4L2_AV1_SEG_LVL_ALT_LF_Y_V + 3,
+    RefFrame = bindings::v4l2_av1_segment_feature_V4L2_AV1_SEG_LVL_REF_FRAME,
+    RefSkip = bindings::v4l2_av1_segment_feature_V4L2_AV1_SEG_LVL_REF_SKIP,
+    RefGlobalMv = bindings::v4l2_av1_segment_feature_V4L2_AV1_SEG_LVL_REF_GLOBALMV,
+}
+
+/// Segmentation parameters of an AV1 frame, as defined in AV1 5.9.14.
+#[cfg(v4l2r_has_av1)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Av1Segmentation {
+    pub flags: Av1SegmentationFlags,
+    /// Highest segment ID with at least one enabled feature.
+    pub last_active_seg_id: u8,
+    /// Bitmask of the enabled features of each segment, indexed by [`Av1SegmentFeature`].
+    pub feature_enabled: [u8; AV1_MAX_SEGMENTS],
+    /// Value of each feature of each segment.
+    pub feature_data: [[i16; AV1_SEG_LVL_MAX]; AV1_MAX_SEGMENTS],
+}
+
+#[cfg(v4l2r_has_av1)]
+impl Av1Segmentation {
+    /// Returns whether segmentation is enabled for the frame.
+    pub fn enabled(&self) -> bool {
+        self.flags.contains(Av1SegmentationFlags::ENABLED)
+    }
+
+    /// Returns whether the segmentation map is updated from the one of the previous frame.
+    pub fn temporal_update(&self) -> bool {
+        self.flags.contains(Av1SegmentationFlags::TEMPORAL_UPDATE)
+    }
+
+    /// Returns the value of `feature` for `segment`, or `None` if the feature is not enabled for
+    /// this segment or `segment` is out of range.
+    pub fn feature(&self, segment: usize, feature: Av1SegmentFeature) -> Option<i16> {
+        let enabled = *self.feature_enabled.get(segment)?;
+
+        if enabled & (1 << feature as u32) != 0 {
+            Some(self.feature_data[segment][feature as usize])
+        } else {
+            None
+        }
+    }
+}
 
 /// Safe wrapper over [`v4l2r::bindings::V4L2_CID_MPEG_VIDEO_HEADER_MODE`]
 #[repr(i32)]
@@ -154,6 +1108,38 @@ impl From<VideoHeaderMode> for i32 {
     }
 }
 
+/// Safe wrapper over [`bindings::V4L2_CID_MPEG_VIDEO_DEC_DISPLAY_DELAY`]
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VideoDecDisplayDelay(pub i32);
+
+impl ExtControlTrait for VideoDecDisplayDelay {
+    const ID: u32 = bindings::V4L2_CID_MPEG_VIDEO_DEC_DISPLAY_DELAY;
+    type PAYLOAD = i32;
+}
+
+impl From<VideoDecDisplayDelay> for i32 {
+    fn from(value: VideoDecDisplayDelay) -> Self {
+        value.0
+    }
+}
+
+/// Safe wrapper over [`bindings::V4L2_CID_MPEG_VIDEO_DEC_DISPLAY_DELAY_ENABLE`]
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VideoDecDisplayDelayEnable(pub bool);
+
+impl ExtControlTrait for VideoDecDisplayDelayEnable {
+    const ID: u32 = bindings::V4L2_CID_MPEG_VIDEO_DEC_DISPLAY_DELAY_ENABLE;
+    type PAYLOAD = i32;
+}
+
+impl From<VideoDecDisplayDelayEnable> for i32 {
+    fn from(value: VideoDecDisplayDelayEnable) -> Self {
+        value.0 as i32
+    }
+}
+
 /// Safe wrapper over [`bindings::V4L2_CID_MPEG_VIDEO_BITRATE`]
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -581,3 +1567,726 @@ impl From<VideoVPXMaxQp> for i32 {
         value.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Component, H264PredWeights, H264PredWeightsBuilder, H264SliceParams, Mpeg2Picture,
+        Mpeg2PictureFlags, Mpeg2PictureType, Mpeg2QuantMatrix, MvDirection,
+    };
+    use crate::bindings::{
+        self, v4l2_ctrl_h264_slice_params, v4l2_ctrl_mpeg2_picture, v4l2_ctrl_mpeg2_quantisation,
+    };
+    use crate::controls::{SafeExtControl, SizeMismatch};
+
+    #[cfg(v4l2r_has_hevc)]
+    #[test]
+    fn test_hevc_diagonal_to_raster() {
+        use super::hevc_diagonal_to_raster;
+
+        let diagonal: [u8; 64] = std::array::from_fn(|i| i as u8);
+        let raster = hevc_diagonal_to_raster(&diagonal);
+
+        // First diagonals: (0, 0), then (0, 1), (1, 0), then (0, 2), (1, 1), (2, 0).
+        assert_eq!(&raster[0..3], &[0, 2, 5]);
+        assert_eq!(&raster[8..10], &[1, 4]);
+        assert_eq!(raster[16], 3);
+        assert_eq!(raster[63], 63);
+    }
+
+    #[cfg(v4l2r_has_hevc)]
+    #[test]
+    fn test_hevc_slice_params() {
+        use super::{HevcSliceFlags, HevcSliceParams, HevcSliceType};
+
+        let mut control = SafeExtControl::<HevcSliceParams>::new_zeroed();
+        assert_eq!(control.slice_type(), Some(HevcSliceType::B));
+
+        let params = control.hevc_slice_params_mut();
+        params.slice_type = bindings::V4L2_HEVC_SLICE_TYPE_P as u8;
+        params.num_ref_idx_l0_active_minus1 = 3;
+        params.num_ref_idx_l1_active_minus1 = 1;
+        params.flags = (bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_CABAC_INIT
+            | bindings::V4L2_HEVC_SLICE_PARAMS_FLAG_DEPENDENT_SLICE_SEGMENT)
+            as u64
+            | 1 << 63;
+
+        assert_eq!(control.slice_type(), Some(HevcSliceType::P));
+        assert_eq!(control.num_ref_idx_l0_active_minus1(), 3);
+        assert_eq!(control.num_ref_idx_l1_active_minus1(), 1);
+        // Unknown flags are dropped.
+        assert_eq!(
+            control.slice_flags(),
+            HevcSliceFlags::CABAC_INIT | HevcSliceFlags::DEPENDENT_SLICE_SEGMENT
+        );
+
+        control.hevc_slice_params_mut().slice_type = 3;
+        assert_eq!(control.slice_type(), None);
+    }
+
+    #[cfg(v4l2r_has_hevc)]
+    #[test]
+    fn test_hevc_default_scaling_matrix() {
+        use super::HevcScalingMatrix;
+
+        let matrix = HevcScalingMatrix::from_default_hevc();
+
+        assert!(matrix.scaling_list_4x4.iter().flatten().all(|&v| v == 16));
+        // The bottom-right coefficients are the most attenuated.
+        assert_eq!(matrix.scaling_list_8x8[0][63], 115);
+        assert_eq!(matrix.scaling_list_8x8[3][63], 91);
+        assert_eq!(matrix.scaling_list_32x32[1][63], 91);
+        // Spot-check the transposition: (x=2, y=1) is at index 8 in diagonal order, (x=6, y=7) at
+        // index 61.
+        assert_eq!(matrix.scaling_list_16x16[0][8 + 2], 16);
+        assert_eq!(matrix.scaling_list_16x16[0][7 * 8 + 6], 88);
+
+        let mut control = SafeExtControl::<HevcScalingMatrix>::from(matrix);
+        assert!(!control.is_flat());
+        assert_eq!(control.scaling_list_8x8(3, 3).unwrap()[63], 91);
+        assert_eq!(control.scaling_list_8x8(3, 1), None);
+        assert_eq!(control.scaling_list_4x4(1, 0), None);
+
+        let flat = control.hevc_scaling_matrix_mut();
+        flat.scaling_list_8x8 = [[16; 64]; 6];
+        flat.scaling_list_16x16 = [[16; 64]; 6];
+        flat.scaling_list_32x32 = [[16; 64]; 2];
+        assert!(control.is_flat());
+    }
+
+    #[test]
+    fn test_h264_pred_weights_builder() {
+        let mut pps = bindings::v4l2_ctrl_h264_pps::default();
+
+        // Without weighted prediction, nothing is recorded.
+        let weights = H264PredWeightsBuilder::new(&pps, bindings::V4L2_H264_SLICE_TYPE_P as u8)
+            .luma_log2_weight_denom(5)
+            .luma_weight(0, 0, 40, 2)
+            .build();
+        assert_eq!(weights.luma_log2_weight_denom, 0);
+        assert_eq!(weights.weight_factors[0].luma_weight[0], 0);
+
+        pps.flags = bindings::V4L2_H264_PPS_FLAG_WEIGHTED_PRED as u16;
+        let builder = H264PredWeightsBuilder::new(&pps, bindings::V4L2_H264_SLICE_TYPE_P as u8);
+        assert!(builder.weights_required());
+        let weights = builder
+            .luma_log2_weight_denom(5)
+            .chroma_log2_weight_denom(2)
+            .luma_weight(0, 0, 40, 2)
+            .chroma_weight(0, 1, [3, 5], [-1, 1])
+            .build();
+        assert_eq!(weights.weight_factors[0].luma_weight[0], 40);
+        assert_eq!(weights.weight_factors[0].luma_offset[0], 2);
+        // Defaults to 2^denom.
+        assert_eq!(weights.weight_factors[0].luma_weight[1], 32);
+        assert_eq!(weights.weight_factors[0].chroma_weight[0], [4, 4]);
+        assert_eq!(weights.weight_factors[0].chroma_weight[1], [3, 5]);
+        assert_eq!(weights.weight_factors[0].chroma_offset[1], [-1, 1]);
+
+        // Weighted prediction only applies to B slices with explicit bi-prediction.
+        assert!(
+            !H264PredWeightsBuilder::new(&pps, bindings::V4L2_H264_SLICE_TYPE_B as u8)
+                .weights_required()
+        );
+        pps.weighted_bipred_idc = 1;
+        assert!(
+            H264PredWeightsBuilder::new(&pps, bindings::V4L2_H264_SLICE_TYPE_B as u8)
+                .weights_required()
+        );
+
+        let unweighted = H264PredWeightsBuilder::unweighted();
+        assert!(unweighted
+            .weight_factors
+            .iter()
+            .all(|f| f.luma_weight == [1; 32] && f.luma_offset == [0; 32]));
+    }
+
+    #[test]
+    fn test_h264_weight_scale() {
+        assert_eq!(H264PredWeights::weight_scale(0, 1), 1.0);
+        assert_eq!(H264PredWeights::weight_scale(5, 40), 1.25);
+        assert_eq!(H264PredWeights::weight_scale(2, -2), -0.5);
+    }
+
+    #[test]
+    fn test_mpeg2_default_quant_matrix() {
+        let intra = Mpeg2QuantMatrix::default_intra();
+
+        // The first coefficients in zigzag order are (0, 0), (1, 0), (0, 1) and (0, 2).
+        assert_eq!(&intra[0..4], &[8, 16, 16, 19]);
+        assert_eq!(intra[63], 83);
+        // Reordering must not lose any coefficient.
+        assert_eq!(intra.iter().map(|&v| v as u32).sum::<u32>(), 2114);
+        assert!(Mpeg2QuantMatrix::default_non_intra()
+            .iter()
+            .all(|&v| v == 16));
+
+        let control = SafeExtControl::<Mpeg2QuantMatrix>::from(v4l2_ctrl_mpeg2_quantisation {
+            intra_quantiser_matrix: intra,
+            non_intra_quantiser_matrix: Mpeg2QuantMatrix::default_non_intra(),
+            chroma_intra_quantiser_matrix: intra,
+            chroma_non_intra_quantiser_matrix: [32; 64],
+        });
+        assert_eq!(control.intra_quantiser_matrix(), &intra);
+        assert_eq!(control.non_intra_quantiser_matrix(), &[16u8; 64]);
+        assert_eq!(control.chroma_intra_quantiser_matrix(), &intra);
+        assert_eq!(control.chroma_non_intra_quantiser_matrix(), &[32u8; 64]);
+    }
+
+    #[test]
+    fn test_h264_slice_ref_lists() {
+        let mut params = v4l2_ctrl_h264_slice_params {
+            slice_type: bindings::V4L2_H264_SLICE_TYPE_B as u8,
+            num_ref_idx_l0_active_minus1: 2,
+            num_ref_idx_l1_active_minus1: 0,
+            ..Default::default()
+        };
+        for (i, r) in params.ref_pic_list0.iter_mut().enumerate() {
+            r.index = i as u8;
+        }
+        params.ref_pic_list0[1].index = bindings::V4L2_H264_NUM_DPB_ENTRIES as u8;
+        params.ref_pic_list1[0].index = 7;
+        let mut control = SafeExtControl::<H264SliceParams>::from(params);
+
+        let l0 = control.ref_list_b0();
+        assert_eq!(l0.len(), 3);
+        assert_eq!(l0.get(0), Some(0));
+        // Stale entry pointing past the end of the DPB.
+        assert_eq!(l0.get(1), None);
+        assert_eq!(l0.get(2), Some(2));
+        // Inactive entry.
+        assert_eq!(l0.get(3), None);
+        assert!(!l0.is_valid());
+
+        let l1 = control.ref_list_b1();
+        assert_eq!(l1.len(), 1);
+        assert_eq!(l1.get(0), Some(7));
+        assert!(l1.is_valid());
+
+        // P lists are only active for P slices.
+        assert!(control.ref_list_p0().is_empty());
+
+        let params = control.h264_slice_params_mut();
+        params.slice_type = bindings::V4L2_H264_SLICE_TYPE_P as u8;
+        params.num_ref_idx_l0_active_minus1 = 255;
+        assert_eq!(
+            control.ref_list_p0().len() as u32,
+            bindings::V4L2_H264_REF_LIST_LEN
+        );
+        assert!(control.ref_list_b0().is_empty());
+        assert!(control.ref_list_b1().is_empty());
+    }
+
+    #[test]
+    fn test_mpeg2_picture() {
+        let mut control = SafeExtControl::<Mpeg2Picture>::from(v4l2_ctrl_mpeg2_picture {
+            flags: bindings::V4L2_MPEG2_PIC_FLAG_TOP_FIELD_FIRST
+                | bindings::V4L2_MPEG2_PIC_FLAG_ALT_SCAN
+                | 0x8000_0000,
+            f_code: [[1, 2], [3, 15]],
+            picture_coding_type: bindings::V4L2_MPEG2_PIC_CODING_TYPE_B as u8,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            control.picture_coding_type(),
+            Some(Mpeg2PictureType::Bidirectional)
+        );
+        assert_eq!(
+            control.picture_flags(),
+            Mpeg2PictureFlags::TOP_FIELD_FIRST | Mpeg2PictureFlags::ALT_SCAN
+        );
+        assert_eq!(
+            control.f_code(MvDirection::Forward, Component::Horizontal),
+            1
+        );
+        assert_eq!(control.f_code(MvDirection::Forward, Component::Vertical), 2);
+        assert_eq!(
+            control.f_code(MvDirection::Backward, Component::Horizontal),
+            3
+        );
+        assert_eq!(
+            control.f_code(MvDirection::Backward, Component::Vertical),
+            15
+        );
+
+        control.mpeg2_picture_mut().picture_coding_type = 0;
+        assert_eq!(control.picture_coding_type(), None);
+    }
+
+    #[cfg(v4l2r_has_hevc)]
+    #[test]
+    fn test_hevc_decode_params() {
+        use super::{HevcDecodeFlags, HevcDecodeParams, HevcDpbFlags};
+
+        let mut control = SafeExtControl::<HevcDecodeParams>::new_zeroed();
+        assert!(control.dpb_slice().is_empty());
+        assert!(control.poc_st_curr_before().is_empty());
+
+        let params = control.hevc_decode_params_mut();
+        params.pic_order_cnt_val = 8;
+        params.flags = bindings::V4L2_HEVC_DECODE_PARAM_FLAG_IRAP_PIC as u64;
+        params.num_active_dpb_entries = 3;
+        for (i, entry) in params.dpb.iter_mut().enumerate() {
+            entry.timestamp = 1000 * i as u64;
+            entry.pic_order_cnt_val = 2 * i as i32;
+        }
+        params.dpb[2].flags = bindings::V4L2_HEVC_DPB_ENTRY_LONG_TERM_REFERENCE as u8;
+        params.num_poc_st_curr_before = 2;
+        params.poc_st_curr_before[..2].copy_from_slice(&[1, 0]);
+        params.num_poc_lt_curr = 1;
+        params.poc_lt_curr[0] = 2;
+
+        assert_eq!(control.decode_flags(), HevcDecodeFlags::IRAP_PIC);
+        assert_eq!(control.pic_order_cnt_val(), 8);
+        let dpb = control.dpb_slice();
+        assert_eq!(dpb.len(), 3);
+        assert_eq!(dpb[1].timestamp(), 1000);
+        assert_eq!(dpb[2].pic_order_cnt_val(), 4);
+        assert_eq!(dpb[0].flags(), HevcDpbFlags::empty());
+        assert_eq!(dpb[2].flags(), HevcDpbFlags::LONG_TERM_REFERENCE);
+        assert_eq!(control.poc_st_curr_before(), &[1, 0]);
+        assert!(control.poc_st_curr_after().is_empty());
+        assert_eq!(control.poc_lt_curr(), &[2]);
+
+        // Counts larger than the arrays are clamped.
+        control.hevc_decode_params_mut().num_active_dpb_entries = 255;
+        assert_eq!(control.dpb_slice().len(), 16);
+    }
+
+    /// When run from `lib/tests/optional_controls.sh`, checks that the optional controls detected
+    /// at build time match those expected from the kernel headers.
+    #[test]
+    fn test_optional_controls_support() {
+        if let Ok(expected) = std::env::var("V4L2R_EXPECT_OPTIONAL_CONTROLS") {
+            let expected = expected.split(',').collect::<Vec<_>>();
+
+            assert_eq!(crate::has_av1_support(), expected.contains(&"av1"));
+            assert_eq!(crate::has_hevc_support(), expected.contains(&"hevc"));
+            assert_eq!(crate::has_vp9_support(), expected.contains(&"vp9"));
+            assert_eq!(crate::has_fence_support(), expected.contains(&"fences"));
+        }
+
+        #[cfg(v4l2r_has_av1)]
+        {
+            use super::{Av1FilmGrain, Av1Frame, Av1Sequence, Av1TileGroupEntry};
+
+            let frame = SafeExtControl::<Av1Frame>::new_zeroed();
+            assert_eq!(frame.id(), bindings::V4L2_CID_STATELESS_AV1_FRAME);
+            assert_eq!(
+                frame.as_bytes().len(),
+                std::mem::size_of::<bindings::v4l2_ctrl_av1_frame>()
+            );
+            let _ = SafeExtControl::<Av1Sequence>::new_zeroed();
+            let _ = SafeExtControl::<Av1TileGroupEntry>::new_zeroed();
+            let _ = SafeExtControl::<Av1FilmGrain>::new_zeroed();
+        }
+
+        #[cfg(v4l2r_has_hevc)]
+        {
+            use super::{HevcPps, HevcSliceParams, HevcSps};
+
+            let sps = SafeExtControl::<HevcSps>::new_zeroed();
+            assert_eq!(sps.id(), bindings::V4L2_CID_STATELESS_HEVC_SPS);
+            let _ = SafeExtControl::<HevcPps>::new_zeroed();
+            let _ = SafeExtControl::<HevcSliceParams>::new_zeroed();
+        }
+
+        #[cfg(v4l2r_has_vp9)]
+        {
+            use super::Vp9Frame;
+
+            let mut frame = SafeExtControl::<Vp9Frame>::new_zeroed();
+            frame.vp9_frame_mut().quant.base_q_idx = 42;
+            let copy = SafeExtControl::<Vp9Frame>::from_bytes(frame.as_bytes()).unwrap();
+            assert_eq!(copy.vp9_frame().quant.base_q_idx, 42);
+        }
+    }
+
+    #[test]
+    fn test_cdef_strength() {
+        use super::CdefStrength;
+
+        assert_eq!(CdefStrength::from_pri_sec(0, 0), 0);
+        assert_eq!(CdefStrength::from_pri_sec(1, 2), 6);
+        // A secondary strength of 4 is coded as 3.
+        assert_eq!(CdefStrength::from_pri_sec(15, 4), 63);
+        assert_eq!(CdefStrength::from_pri_sec(15, 3), 63);
+        assert_eq!(CdefStrength::from_pri_sec(200, 200), 63);
+    }
+
+    #[cfg(v4l2r_has_av1)]
+    #[test]
+    fn test_av1_cdef() {
+        use super::{Av1CdefParams, Av1Frame};
+
+        let mut control = SafeExtControl::<Av1Frame>::new_zeroed();
+        let cdef = &mut control.av1_frame_mut().cdef;
+        cdef.damping_minus_3 = 2;
+        cdef.bits = 1;
+        cdef.y_pri_strength[..2].copy_from_slice(&[3, 15]);
+        cdef.y_sec_strength[..2].copy_from_slice(&[0, 4]);
+        cdef.uv_pri_strength[1] = 7;
+        cdef.uv_sec_strength[1] = 1;
+
+        assert_eq!(control.cdef_damping(), 5);
+        assert_eq!(control.cdef_bits(), 1);
+        let params = control.cdef();
+        assert_eq!(
+            params,
+            Av1CdefParams {
+                bits: 1,
+                y_pri_strength: [3, 15, 0, 0, 0, 0, 0, 0],
+                y_sec_strength: [0, 4, 0, 0, 0, 0, 0, 0],
+                uv_pri_strength: [0, 7, 0, 0, 0, 0, 0, 0],
+                uv_sec_strength: [0, 1, 0, 0, 0, 0, 0, 0],
+            }
+        );
+        assert_eq!(params.num_presets(), 2);
+    }
+
+    #[cfg(v4l2r_has_av1)]
+    #[test]
+    fn test_av1_tile_info() {
+        use super::Av1Frame;
+
+        let mut control = SafeExtControl::<Av1Frame>::new_zeroed();
+        let tile_info = &mut control.av1_frame_mut().tile_info;
+        // 3x2 tiles over a frame of 10x4 superblocks of 16x16 MIs.
+        tile_info.tile_cols = 3;
+        tile_info.tile_rows = 2;
+        tile_info.context_update_tile_id = 4;
+        tile_info.tile_size_bytes = 2;
+        tile_info.width_in_sbs_minus_1[..3].copy_from_slice(&[3, 3, 1]);
+        tile_info.height_in_sbs_minus_1[..2].copy_from_slice(&[1, 1]);
+        tile_info.mi_col_starts[..4].copy_from_slice(&[0, 64, 128, 160]);
+        tile_info.mi_row_starts[..3].copy_from_slice(&[0, 32, 64]);
+
+        let tile_info = control.tile_info();
+        assert_eq!(tile_info.tile_count(), 6);
+        assert_eq!(tile_info.context_update_tile_id, 4);
+        assert_eq!(tile_info.tile_size_bytes, 2);
+        assert_eq!(tile_info.mi_col_starts[3], 160);
+        assert_eq!(tile_info.tile_bounds(0, 0), Some((0, 4, 0, 2)));
+        assert_eq!(tile_info.tile_bounds(1, 1), Some((4, 8, 2, 4)));
+        assert_eq!(tile_info.tile_bounds(2, 0), Some((8, 10, 0, 2)));
+        assert_eq!(tile_info.tile_bounds(3, 0), None);
+        assert_eq!(tile_info.tile_bounds(0, 2), None);
+
+        // Bounds that overflow are rejected.
+        let tile_info = &mut control.av1_frame_mut().tile_info;
+        tile_info.width_in_sbs_minus_1[..3].copy_from_slice(&[3, u32::MAX - 5, 1]);
+        let tile_info = control.tile_info();
+        assert_eq!(tile_info.tile_bounds(1, 0), Some((4, u32::MAX, 0, 2)));
+        assert_eq!(tile_info.tile_bounds(2, 0), None);
+    }
+
+    #[cfg(v4l2r_has_av1)]
+    #[test]
+    fn test_av1_film_grain() {
+        use super::{Av1FilmGrain, Av1ScalingPoint};
+
+        let mut control = SafeExtControl::<Av1FilmGrain>::new_zeroed();
+        assert!(!control.apply_grain());
+        assert!(control.y_points().is_empty());
+
+        let film_grain = control.av1_film_grain_mut();
+        film_grain.flags = (bindings::V4L2_AV1_FILM_GRAIN_FLAG_APPLY_GRAIN
+            | bindings::V4L2_AV1_FILM_GRAIN_FLAG_OVERLAP) as u8;
+        film_grain.grain_scaling_minus_8 = 3;
+        film_grain.ar_coeff_lag = 2;
+        film_grain.num_y_points = 2;
+        film_grain.point_y_value[..3].copy_from_slice(&[16, 128, 255]);
+        film_grain.point_y_scaling[..3].copy_from_slice(&[20, 40, 60]);
+
+        assert!(control.apply_grain());
+        assert!(!control.chroma_scaling_from_luma());
+        assert_eq!(control.grain_scaling_minus_8(), 3);
+        assert_eq!(control.ar_coeff_lag(), 2);
+        assert_eq!(control.num_y_points(), 2);
+        assert_eq!(
+            control.y_points(),
+            vec![
+                Av1ScalingPoint {
+                    value: 16,
+                    scaling: 20
+                },
+                Av1ScalingPoint {
+                    value: 128,
+                    scaling: 40
+                },
+            ]
+        );
+
+        // Out-of-range number of points.
+        control.av1_film_grain_mut().num_y_points = u8::MAX;
+        assert_eq!(
+            control.y_points().len(),
+            control.av1_film_grain().point_y_value.len()
+        );
+    }
+
+    #[cfg(v4l2r_has_av1)]
+    #[test]
+    fn test_av1_loop_filter() {
+        use super::{Av1Frame, Av1LoopFilterFlags, Av1ReferenceFrame};
+
+        let mut control = SafeExtControl::<Av1Frame>::new_zeroed();
+        let loop_filter = &mut control.av1_frame_mut().loop_filter;
+        loop_filter.flags = (bindings::V4L2_AV1_LOOP_FILTER_FLAG_DELTA_ENABLED
+            | bindings::V4L2_AV1_LOOP_FILTER_FLAG_DELTA_LF_MULTI) as u8;
+        loop_filter.level = [10, 12, 4, 5];
+        loop_filter.sharpness = 3;
+        loop_filter.ref_deltas = [1, 0, 0, 0, -1, 0, -1, -1];
+        loop_filter.mode_deltas = [0, 2];
+        loop_filter.delta_lf_res = 1;
+
+        let loop_filter = control.loop_filter();
+        assert_eq!(
+            loop_filter.flags,
+            Av1LoopFilterFlags::DELTA_ENABLED | Av1LoopFilterFlags::DELTA_LF_MULTI
+        );
+        assert_eq!(loop_filter.level, [10, 12, 4, 5]);
+        assert_eq!(loop_filter.sharpness, 3);
+        assert_eq!(loop_filter.intra_delta(), 1);
+        assert_eq!(loop_filter.ref_delta(Av1ReferenceFrame::Last), 0);
+        assert_eq!(loop_filter.ref_delta(Av1ReferenceFrame::Golden), -1);
+        assert_eq!(loop_filter.ref_delta(Av1ReferenceFrame::AltRef), -1);
+        assert_eq!(loop_filter.mode_deltas, [0, 2]);
+        assert_eq!(loop_filter.delta_lf_res, 1);
+    }
+
+    #[cfg(v4l2r_has_av1)]
+    #[test]
+    fn test_av1_loop_restoration() {
+        use super::{Av1Frame, Av1FrameRestorationType, Av1LoopRestorationFlags};
+
+        let mut control = SafeExtControl::<Av1Frame>::new_zeroed();
+        let loop_restoration = &mut control.av1_frame_mut().loop_restoration;
+        loop_restoration.flags = bindings::V4L2_AV1_LOOP_RESTORATION_FLAG_USES_LR as u8;
+        loop_restoration.lr_unit_shift = 1;
+        loop_restoration.lr_uv_shift = 1;
+        loop_restoration.frame_restoration_type = [
+            bindings::v4l2_av1_frame_restoration_type_V4L2_AV1_FRAME_RESTORE_SWITCHABLE,
+            bindings::v4l2_av1_frame_restoration_type_V4L2_AV1_FRAME_RESTORE_WIENER,
+            42,
+        ];
+        loop_restoration.loop_restoration_size = [128, 64, 64];
+
+        let loop_restoration = control.loop_restoration();
+        assert_eq!(loop_restoration.flags, Av1LoopRestorationFlags::USES_LR);
+        assert_eq!(loop_restoration.lr_unit_shift, 1);
+        assert_eq!(loop_restoration.lr_uv_shift, 1);
+        // Unknown types are reported as `None`.
+        assert_eq!(
+            loop_restoration.frame_restoration_type,
+            [
+                Av1FrameRestorationType::Switchable,
+                Av1FrameRestorationType::Wiener,
+                Av1FrameRestorationType::None,
+            ]
+        );
+        assert_eq!(loop_restoration.loop_restoration_size, [128, 64, 64]);
+    }
+
+    #[cfg(v4l2r_has_av1)]
+    #[test]
+    fn test_av1_quantization() {
+        use super::{Av1Frame, Av1QuantFlags, Av1Quantization};
+
+        let mut control = SafeExtControl::<Av1Frame>::new_zeroed();
+        let quantization = &mut control.av1_frame_mut().quantization;
+        quantization.flags = (bindings::V4L2_AV1_QUANTIZATION_FLAG_DIFF_UV_DELTA
+            | bindings::V4L2_AV1_QUANTIZATION_FLAG_DELTA_Q_PRESENT)
+            as u8;
+        quantization.base_q_idx = 120;
+        quantization.delta_q_y_dc = -3;
+        quantization.delta_q_u_ac = 2;
+        quantization.delta_q_v_dc = -1;
+        quantization.delta_q_res = 2;
+
+        let params = control.quantization();
+        assert_eq!(
+            params,
+            Av1Quantization {
+                flags: Av1QuantFlags::DIFF_UV_DELTA | Av1QuantFlags::DELTA_Q_PRESENT,
+                base_q_idx: 120,
+                delta_q_y_dc: -3,
+                delta_q_u_dc: 0,
+                delta_q_u_ac: 2,
+                delta_q_v_dc: -1,
+                delta_q_v_ac: 0,
+                qm_y: 0,
+                qm_u: 0,
+                qm_v: 0,
+                delta_q_res: 2,
+            }
+        );
+
+        let params = Av1Quantization {
+            flags: Av1QuantFlags::USING_QMATRIX,
+            base_q_idx: 255,
+            delta_q_y_dc: 63,
+            delta_q_u_dc: -64,
+            delta_q_u_ac: 1,
+            delta_q_v_dc: -2,
+            delta_q_v_ac: 3,
+            qm_y: 5,
+            qm_u: 6,
+            qm_v: 7,
+            delta_q_res: 3,
+        };
+        let mut control = SafeExtControl::<Av1Frame>::new_zeroed();
+        control.set_quantization(&params);
+        assert_eq!(
+            control.av1_frame().quantization.flags,
+            bindings::V4L2_AV1_QUANTIZATION_FLAG_USING_QMATRIX as u8
+        );
+        assert_eq!(control.quantization(), params);
+    }
+
+    #[cfg(v4l2r_has_av1)]
+    #[test]
+    fn test_av1_segmentation() {
+        use super::{
+            Av1Frame, Av1SegmentFeature, Av1Segmentation, Av1SegmentationFlags, AV1_MAX_SEGMENTS,
+            AV1_SEG_LVL_MAX,
+        };
+
+        let mut control = SafeExtControl::<Av1Frame>::new_zeroed();
+        assert!(!control.segmentation().enabled());
+
+        let segmentation = &mut control.av1_frame_mut().segmentation;
+        segmentation.flags = (bindings::V4L2_AV1_SEGMENTATION_FLAG_ENABLED
+            | bindings::V4L2_AV1_SEGMENTATION_FLAG_TEMPORAL_UPDATE)
+            as u8;
+        segmentation.last_active_seg_id = 2;
+        segmentation.feature_enabled[2] =
+            (1 << Av1SegmentFeature::AltQ as u32) | (1 << Av1SegmentFeature::RefSkip as u32);
+        segmentation.feature_data[2][Av1SegmentFeature::AltQ as usize] = -20;
+        // Data of a disabled feature is ignored.
+        segmentation.feature_data[2][Av1SegmentFeature::AltLfU as usize] = 7;
+
+        let params = control.segmentation();
+        assert!(params.enabled());
+        assert!(params.temporal_update());
+        assert_eq!(params.last_active_seg_id, 2);
+        assert_eq!(params.feature(2, Av1SegmentFeature::AltQ), Some(-20));
+        assert_eq!(params.feature(2, Av1SegmentFeature::RefSkip), Some(0));
+        assert_eq!(params.feature(2, Av1SegmentFeature::AltLfU), None);
+        assert_eq!(params.feature(0, Av1SegmentFeature::AltQ), None);
+        assert_eq!(
+            params.feature(AV1_MAX_SEGMENTS, Av1SegmentFeature::AltQ),
+            None
+        );
+
+        let mut feature_data = [[0; AV1_SEG_LVL_MAX]; AV1_MAX_SEGMENTS];
+        feature_data[7][Av1SegmentFeature::RefFrame as usize] = 4;
+        feature_data[1][Av1SegmentFeature::AltLfYH as usize] = -63;
+        let params = Av1Segmentation {
+            flags: Av1SegmentationFlags::ENABLED
+                | Av1SegmentationFlags::UPDATE_MAP
+                | Av1SegmentationFlags::SEG_ID_PRE_SKIP,
+            last_active_seg_id: 7,
+            feature_enabled: [
+                0,
+                1 << Av1SegmentFeature::AltLfYH as u32,
+                0,
+                0,
+                0,
+                0,
+                0,
+                1 << 5,
+            ],
+            feature_data,
+        };
+        let mut control = SafeExtControl::<Av1Frame>::new_zeroed();
+        control.set_segmentation(&params);
+        assert_eq!(control.av1_frame().segmentation.feature_data[7][5], 4);
+        assert_eq!(control.segmentation(), params);
+        assert_eq!(
+            control
+                .segmentation()
+                .feature(7, Av1SegmentFeature::RefFrame),
+            Some(4)
+        );
+    }
+
+    #[cfg(v4l2r_has_av1)]
+    #[test]
+    fn test_av1_global_motion() {
+        use super::{
+            Av1Frame, Av1GlobalMotionFlags, Av1GlobalMotionParams, Av1GmType, Av1ReferenceFrame,
+        };
+
+        let mut control = SafeExtControl::<Av1Frame>::new_zeroed();
+        // A zeroed payload uses the identity type.
+        assert!(control
+            .global_motion_params()
+            .is_identity(Av1ReferenceFrame::Last));
+
+        let global_motion = &mut control.av1_frame_mut().global_motion;
+        let golden = Av1ReferenceFrame::Golden as usize;
+        global_motion.flags[golden] = (bindings::V4L2_AV1_GLOBAL_MOTION_FLAG_IS_GLOBAL
+            | bindings::V4L2_AV1_GLOBAL_MOTION_FLAG_IS_ROT_ZOOM)
+            as u8;
+        global_motion.type_[golden] = bindings::v4l2_av1_warp_model_V4L2_AV1_WARP_MODEL_ROTZOOM;
+        global_motion.params[golden] = [128, -64, 65600, 20, -20, 65600];
+        let altref = Av1ReferenceFrame::AltRef as usize;
+        global_motion.type_[altref] = bindings::v4l2_av1_warp_model_V4L2_AV1_WARP_MODEL_AFFINE;
+        global_motion.params[altref] = Av1GlobalMotionParams::IDENTITY;
+        global_motion.type_[Av1ReferenceFrame::Last2 as usize] = 42;
+
+        let params = control.global_motion_params();
+        assert_eq!(
+            params.flags(Av1ReferenceFrame::Golden),
+            Av1GlobalMotionFlags::IS_GLOBAL | Av1GlobalMotionFlags::IS_ROT_ZOOM
+        );
+        assert_eq!(
+            params.motion_type(Av1ReferenceFrame::Golden),
+            Av1GmType::RotZoom
+        );
+        assert_eq!(
+            params.params(Av1ReferenceFrame::Golden),
+            &[128, -64, 65600, 20, -20, 65600]
+        );
+        assert!(!params.is_identity(Av1ReferenceFrame::Golden));
+        // Affine type, but identity matrix.
+        assert_eq!(
+            params.motion_type(Av1ReferenceFrame::AltRef),
+            Av1GmType::Affine
+        );
+        assert!(params.is_identity(Av1ReferenceFrame::AltRef));
+        // Unknown types are reported as the identity.
+        assert_eq!(
+            params.motion_type(Av1ReferenceFrame::Last2),
+            Av1GmType::Identity
+        );
+    }
+
+    #[test]
+    fn test_payload_bytes() {
+        let mut control = SafeExtControl::<Mpeg2QuantMatrix>::new_zeroed();
+        control.mpeg2_quantisation_mut().intra_quantiser_matrix = [8; 64];
+        control
+            .mpeg2_quantisation_mut()
+            .chroma_non_intra_quantiser_matrix[63] = 42;
+
+        let bytes = control.as_bytes();
+        assert_eq!(bytes.len(), 256);
+        assert_eq!(&bytes[0..64], &[8u8; 64]);
+        assert_eq!(bytes[255], 42);
+
+        let copy = SafeExtControl::<Mpeg2QuantMatrix>::from_bytes(bytes).unwrap();
+        assert_eq!(copy.as_bytes(), bytes);
+        assert_eq!(copy.intra_quantiser_matrix(), &[8u8; 64]);
+
+        assert_eq!(
+            SafeExtControl::<Mpeg2QuantMatrix>::from_bytes(&bytes[1..]).err(),
+            Some(SizeMismatch {
+                expected: 256,
+                actual: 255
+            })
+        );
+    }
+}
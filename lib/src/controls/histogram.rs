@@ -0,0 +1,177 @@
+//! Statistics over the successive values of an integer control.
+
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::os::unix::io::AsRawFd;
+
+use crate::bindings::v4l2_query_ext_ctrl;
+use crate::controls::ExtControlTrait;
+use crate::ioctl::{self, QueryCtrlError, QueryCtrlFlags};
+
+/// Keeps the last values taken by control `T` and computes statistics over them, e.g. to detect
+/// that an auto-exposure algorithm adjusting it has converged.
+///
+/// Only the last `capacity` values are kept, so memory use does not grow over time and the
+/// statistics reflect the recent behavior of the control.
+#[derive(Clone, Debug)]
+pub struct ControlHistogram<T: ExtControlTrait<PAYLOAD = i32>> {
+    samples: VecDeque<i32>,
+    capacity: usize,
+    minimum: i32,
+    maximum: i32,
+    _control: PhantomData<T>,
+}
+
+impl<T: ExtControlTrait<PAYLOAD = i32>> ControlHistogram<T> {
+    /// Create a histogram keeping up to `capacity` values of a control which can take values
+    /// between `minimum` and `maximum`.
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(capacity: usize, minimum: i32, maximum: i32) -> Self {
+        assert!(capacity > 0, "a histogram needs to keep at least one value");
+
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            minimum,
+            maximum,
+            _control: PhantomData,
+        }
+    }
+
+    /// Create a histogram keeping up to `capacity` values, using the range of the control as
+    /// reported by `fd`.
+    pub fn from_device(fd: &impl AsRawFd, capacity: usize) -> Result<Self, QueryCtrlError> {
+        let (id, _) = ioctl::parse_ctrl_id_and_flags(T::ID);
+        let qctrl: v4l2_query_ext_ctrl = ioctl::query_ext_ctrl(fd, id, QueryCtrlFlags::empty())?;
+
+        Ok(Self::new(
+            capacity,
+            qctrl.minimum.clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+            qctrl.maximum.clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+        ))
+    }
+
+    /// Record `value` as the latest value of the control, discarding the oldest one if the
+    /// histogram is full.
+    pub fn record(&mut self, value: i32) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(value);
+    }
+
+    /// Returns the number of values currently kept.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns whether no value has been recorded yet.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Forget all the recorded values, e.g. after the scene has changed.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Returns the mean of the recorded values, or NaN if there is none.
+    pub fn mean(&self) -> f64 {
+        self.samples.iter().map(|&v| v as f64).sum::<f64>() / self.samples.len() as f64
+    }
+
+    /// Returns the population variance of the recorded values, or NaN if there is none.
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+
+        self.samples
+            .iter()
+            .map(|&v| (v as f64 - mean).powi(2))
+            .sum::<f64>()
+            / self.samples.len() as f64
+    }
+
+    /// Returns the smallest recorded value.
+    ///
+    /// Panics if no value has been recorded.
+    pub fn min(&self) -> i32 {
+        *self.samples.iter().min().expect("no value recorded")
+    }
+
+    /// Returns the largest recorded value.
+    ///
+    /// Panics if no value has been recorded.
+    pub fn max(&self) -> i32 {
+        *self.samples.iter().max().expect("no value recorded")
+    }
+
+    /// Returns the `p`th percentile of the recorded values, `p` being between 0 and 100, using
+    /// the nearest-rank method.
+    ///
+    /// Panics if no value has been recorded.
+    pub fn percentile(&self, p: f64) -> i32 {
+        assert!(!self.samples.is_empty(), "no value recorded");
+
+        let mut sorted = self.samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+
+        sorted[rank.saturating_sub(1)]
+    }
+
+    /// Returns whether the control has stabilized, i.e. whether the variance of its values,
+    /// normalized by the square of the range of the control, is below `threshold`.
+    ///
+    /// This returns `false` until the histogram is full, so a few close values recorded at the
+    /// start are not mistaken for convergence.
+    pub fn is_stable(&self, threshold: f64) -> bool {
+        if self.samples.len() < self.capacity {
+            return false;
+        }
+        let range = (self.maximum as f64 - self.minimum as f64).max(1.0);
+
+        self.variance() / (range * range) < threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controls::user::Brightness;
+
+    #[test]
+    fn test_control_histogram() {
+        let mut histogram = ControlHistogram::<Brightness>::new(4, 0, 255);
+        assert!(histogram.is_empty());
+        assert!(histogram.mean().is_nan());
+        assert!(!histogram.is_stable(1.0));
+
+        for value in [100, 10, 20, 30, 40] {
+            histogram.record(value);
+        }
+        // The first value has been discarded.
+        assert_eq!(histogram.len(), 4);
+        assert_eq!((histogram.min(), histogram.max()), (10, 40));
+        assert_eq!(histogram.mean(), 25.0);
+        assert_eq!(histogram.variance(), 125.0);
+        assert_eq!(histogram.percentile(0.0), 10);
+        assert_eq!(histogram.percentile(50.0), 20);
+        assert_eq!(histogram.percentile(75.0), 30);
+        assert_eq!(histogram.percentile(100.0), 40);
+
+        // 125 / 255^2 is about 0.0019.
+        assert!(histogram.is_stable(0.01));
+        assert!(!histogram.is_stable(0.001));
+
+        histogram.clear();
+        for _ in 0..3 {
+            histogram.record(128);
+        }
+        // Not full yet.
+        assert!(!histogram.is_stable(0.01));
+        histogram.record(128);
+        assert_eq!(histogram.variance(), 0.0);
+        assert!(histogram.is_stable(0.01));
+    }
+}
@@ -0,0 +1,15 @@
+//! Definition of IMAGE_PROC class controls.
+
+use crate::bindings;
+use crate::controls::ExtControlTrait;
+
+/// Test pattern generated by the device, as an index into the menu of patterns it supports.
+///
+/// The meaning of each index is driver-specific: on sensors index 0 usually disables the test
+/// pattern, whereas on `vivid` it selects the 75% color bars. The name of each pattern can be
+/// obtained using [`crate::ioctl::querymenu`].
+pub struct TestPattern;
+impl ExtControlTrait for TestPattern {
+    const ID: u32 = bindings::V4L2_CID_TEST_PATTERN;
+    type PAYLOAD = i32;
+}
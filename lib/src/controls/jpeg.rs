@@ -0,0 +1,48 @@
+//! Definition of JPEG class controls.
+
+use enumn::N;
+
+use crate::bindings;
+use crate::controls::ExtControlTrait;
+
+/// Safe wrapper over [`bindings::V4L2_CID_JPEG_CHROMA_SUBSAMPLING`]
+///
+/// Encoders use this control to select the subsampling of the produced images, while some JPEG
+/// decoders need it to be set to the subsampling of the images they are given.
+#[repr(i32)]
+#[derive(N, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum JpegChromaSubsampling {
+    Yuv444 = bindings::v4l2_jpeg_chroma_subsampling_V4L2_JPEG_CHROMA_SUBSAMPLING_444 as i32,
+    Yuv422 = bindings::v4l2_jpeg_chroma_subsampling_V4L2_JPEG_CHROMA_SUBSAMPLING_422 as i32,
+    Yuv420 = bindings::v4l2_jpeg_chroma_subsampling_V4L2_JPEG_CHROMA_SUBSAMPLING_420 as i32,
+    Yuv411 = bindings::v4l2_jpeg_chroma_subsampling_V4L2_JPEG_CHROMA_SUBSAMPLING_411 as i32,
+    Yuv410 = bindings::v4l2_jpeg_chroma_subsampling_V4L2_JPEG_CHROMA_SUBSAMPLING_410 as i32,
+    Gray = bindings::v4l2_jpeg_chroma_subsampling_V4L2_JPEG_CHROMA_SUBSAMPLING_GRAY as i32,
+}
+
+impl ExtControlTrait for JpegChromaSubsampling {
+    const ID: u32 = bindings::V4L2_CID_JPEG_CHROMA_SUBSAMPLING;
+    type PAYLOAD = i32;
+}
+
+impl From<JpegChromaSubsampling> for i32 {
+    fn from(value: JpegChromaSubsampling) -> Self {
+        value as i32
+    }
+}
+
+/// Safe wrapper over [`bindings::V4L2_CID_JPEG_COMPRESSION_QUALITY`]
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct JpegCompressionQuality(pub i32);
+
+impl ExtControlTrait for JpegCompressionQuality {
+    const ID: u32 = bindings::V4L2_CID_JPEG_COMPRESSION_QUALITY;
+    type PAYLOAD = i32;
+}
+
+impl From<JpegCompressionQuality> for i32 {
+    fn from(value: JpegCompressionQuality) -> Self {
+        value.0
+    }
+}
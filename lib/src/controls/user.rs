@@ -1,5 +1,8 @@
 //! Definition of USER class controls.
 
+use enumn::N;
+use thiserror::Error;
+
 use crate::bindings;
 use crate::controls::ExtControlTrait;
 
@@ -14,3 +17,83 @@ impl ExtControlTrait for Contrast {
     const ID: u32 = bindings::V4L2_CID_CONTRAST;
     type PAYLOAD = i32;
 }
+
+pub struct MinBuffersForCapture;
+impl ExtControlTrait for MinBuffersForCapture {
+    const ID: u32 = bindings::V4L2_CID_MIN_BUFFERS_FOR_CAPTURE;
+    type PAYLOAD = i32;
+}
+
+pub struct MinBuffersForOutput;
+impl ExtControlTrait for MinBuffersForOutput {
+    const ID: u32 = bindings::V4L2_CID_MIN_BUFFERS_FOR_OUTPUT;
+    type PAYLOAD = i32;
+}
+
+pub struct ColorKiller;
+impl ExtControlTrait for ColorKiller {
+    const ID: u32 = bindings::V4L2_CID_COLOR_KILLER;
+    type PAYLOAD = i32;
+}
+
+pub struct ColorEffects;
+impl ExtControlTrait for ColorEffects {
+    const ID: u32 = bindings::V4L2_CID_COLORFX;
+    type PAYLOAD = i32;
+}
+
+/// Menu values of the [`ColorEffects`] control.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, N)]
+#[repr(i32)]
+pub enum ColorEffectsValue {
+    None = bindings::v4l2_colorfx_V4L2_COLORFX_NONE as i32,
+    BlackAndWhite = bindings::v4l2_colorfx_V4L2_COLORFX_BW as i32,
+    Sepia = bindings::v4l2_colorfx_V4L2_COLORFX_SEPIA as i32,
+    Negative = bindings::v4l2_colorfx_V4L2_COLORFX_NEGATIVE as i32,
+    Emboss = bindings::v4l2_colorfx_V4L2_COLORFX_EMBOSS as i32,
+    Sketch = bindings::v4l2_colorfx_V4L2_COLORFX_SKETCH as i32,
+    SkyBlue = bindings::v4l2_colorfx_V4L2_COLORFX_SKY_BLUE as i32,
+    GrassGreen = bindings::v4l2_colorfx_V4L2_COLORFX_GRASS_GREEN as i32,
+    SkinWhiten = bindings::v4l2_colorfx_V4L2_COLORFX_SKIN_WHITEN as i32,
+    Vivid = bindings::v4l2_colorfx_V4L2_COLORFX_VIVID as i32,
+    Aqua = bindings::v4l2_colorfx_V4L2_COLORFX_AQUA as i32,
+    ArtFreeze = bindings::v4l2_colorfx_V4L2_COLORFX_ART_FREEZE as i32,
+    Silhouette = bindings::v4l2_colorfx_V4L2_COLORFX_SILHOUETTE as i32,
+    SolarGlow = bindings::v4l2_colorfx_V4L2_COLORFX_SOLARIZATION as i32,
+    Antique = bindings::v4l2_colorfx_V4L2_COLORFX_ANTIQUE as i32,
+    SetCbCr = bindings::v4l2_colorfx_V4L2_COLORFX_SET_CBCR as i32,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("unknown color effect value {0}")]
+pub struct ColorEffectsValueError(pub i32);
+
+impl TryFrom<i32> for ColorEffectsValue {
+    type Error = ColorEffectsValueError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        Self::n(value).ok_or(ColorEffectsValueError(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_effects_value_try_from() {
+        assert_eq!(ColorEffectsValue::try_from(0), Ok(ColorEffectsValue::None));
+        assert_eq!(
+            ColorEffectsValue::try_from(bindings::v4l2_colorfx_V4L2_COLORFX_SET_CBCR as i32),
+            Ok(ColorEffectsValue::SetCbCr)
+        );
+        // V4L2_COLORFX_SET_RGB is not supported.
+        assert_eq!(
+            ColorEffectsValue::try_from(bindings::v4l2_colorfx_V4L2_COLORFX_SET_RGB as i32),
+            Err(ColorEffectsValueError(
+                bindings::v4l2_colorfx_V4L2_COLORFX_SET_RGB as i32
+            ))
+        );
+        assert!(ColorEffectsValue::try_from(-1).is_err());
+    }
+}
@@ -0,0 +1,304 @@
+//! Dynamically-typed control values, for controls whose type is only known at runtime.
+//!
+//! Tools that configure arbitrary devices typically discover controls through
+//! [`query_ext_ctrl`](crate::ioctl::query_ext_ctrl) and cannot use the statically-typed
+//! [`SafeExtControl`](super::SafeExtControl). [`get_control_value`] and [`set_control_value`]
+//! query the type of the control and exchange its value as a [`ControlValue`].
+use std::os::unix::io::AsRawFd;
+
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::bindings;
+use crate::bindings::v4l2_ext_control;
+use crate::bindings::v4l2_ext_control__bindgen_ty_1;
+use crate::bindings::v4l2_query_ext_ctrl;
+use crate::bindings::v4l2_querymenu;
+use crate::error::AsErrno;
+use crate::ioctl::{
+    bytes_until_nul, g_ext_ctrls, query_ext_ctrl, querymenu, s_ext_ctrls, string_from_cstr, CtrlId,
+    CtrlIdError, CtrlWhich, ExtControlError, QueryCtrlError, QueryCtrlFlags,
+};
+
+/// Value of a control of any type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ControlValue {
+    Integer(i32),
+    Integer64(i64),
+    /// Single-element `U8`, `U16` or `U32` control.
+    UnsignedInteger(u32),
+    Boolean(bool),
+    /// Index of the selected menu item and its name.
+    Menu(u32, String),
+    /// Index of the selected menu item and its value.
+    IntegerMenu(u32, i64),
+    Bitmask(u32),
+    String(String),
+    /// Raw payload of array and compound controls.
+    Compound(Vec<u8>),
+}
+
+#[derive(Debug, Error)]
+pub enum ControlValueError {
+    #[error("invalid control ID: {0}")]
+    InvalidId(#[from] CtrlIdError),
+    #[error("error while querying control: {0}")]
+    QueryCtrl(#[from] QueryCtrlError),
+    #[error("error while accessing control: {0}")]
+    ExtControl(#[from] ExtControlError),
+    #[error("controls of type {0} have no value")]
+    UnsupportedType(u32),
+    #[error("value {value:?} does not match control type {ctrl_type}")]
+    TypeMismatch { value: ControlValue, ctrl_type: u32 },
+}
+
+impl From<ControlValueError> for Errno {
+    fn from(err: ControlValueError) -> Self {
+        match err {
+            ControlValueError::InvalidId(_) => Errno::EINVAL,
+            ControlValueError::QueryCtrl(e) => e.into(),
+            ControlValueError::ExtControl(e) => e.into(),
+            ControlValueError::UnsupportedType(_) => Errno::EINVAL,
+            ControlValueError::TypeMismatch { .. } => Errno::EINVAL,
+        }
+    }
+}
+
+impl AsErrno for ControlValueError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            ControlValueError::InvalidId(_) => None,
+            ControlValueError::QueryCtrl(e) => e.errno(),
+            ControlValueError::ExtControl(e) => e.errno(),
+            ControlValueError::UnsupportedType(_) => None,
+            ControlValueError::TypeMismatch { .. } => None,
+        }
+    }
+}
+
+/// Returns whether the payload of the control described by `qctrl` is passed through a pointer.
+fn has_payload(qctrl: &v4l2_query_ext_ctrl) -> bool {
+    qctrl.flags & bindings::V4L2_CTRL_FLAG_HAS_PAYLOAD != 0
+}
+
+/// Returns whether the control described by `qctrl` holds a single unsigned integer.
+fn is_unsigned_scalar(qctrl: &v4l2_query_ext_ctrl) -> bool {
+    matches!(
+        qctrl.type_,
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_U8
+            | bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_U16
+            | bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_U32
+    ) && qctrl.elems == 1
+        && qctrl.nr_of_dims == 0
+}
+
+/// Returns the size in bytes of the payload of the control described by `qctrl`.
+fn payload_size(qctrl: &v4l2_query_ext_ctrl) -> usize {
+    qctrl.elem_size as usize * qctrl.elems.max(1) as usize
+}
+
+/// Reads the unsigned integer stored at the beginning of `payload`, which is `elem_size` bytes
+/// long.
+fn read_unsigned(payload: &[u8], elem_size: usize) -> u32 {
+    match (elem_size, payload) {
+        (1, [b, ..]) => *b as u32,
+        (2, [b0, b1, ..]) => u16::from_ne_bytes([*b0, *b1]) as u32,
+        (4, [b0, b1, b2, b3, ..]) => u32::from_ne_bytes([*b0, *b1, *b2, *b3]),
+        _ => 0,
+    }
+}
+
+/// Returns a `elem_size` bytes payload containing `value`, truncated to the element size.
+fn write_unsigned(value: u32, elem_size: usize) -> Vec<u8> {
+    match elem_size {
+        1 => vec![value as u8],
+        2 => (value as u16).to_ne_bytes().to_vec(),
+        _ => value.to_ne_bytes().to_vec(),
+    }
+}
+
+/// Returns a zero-terminated `size` bytes payload containing `string`, truncated if needed.
+fn write_string(string: &str, size: usize) -> Vec<u8> {
+    let mut payload = vec![0u8; size];
+    let len = string.len().min(size.saturating_sub(1));
+    payload[..len].copy_from_slice(&string.as_bytes()[..len]);
+    payload
+}
+
+/// Reads the value of a string control from `payload`.
+///
+/// Unlike [`string_from_cstr`], whitespace is preserved since it is part of the control's value.
+fn read_string(payload: &[u8]) -> String {
+    String::from_utf8_lossy(bytes_until_nul(payload)).into_owned()
+}
+
+/// Returns the name (for menus) or value (for integer menus) of item `index` of control `id`.
+fn menu_item(fd: &impl AsRawFd, id: u32, index: u32) -> Option<v4l2_querymenu> {
+    querymenu::<v4l2_querymenu>(fd, id, index).ok()
+}
+
+fn ext_control(id: u32, value: v4l2_ext_control__bindgen_ty_1, size: u32) -> v4l2_ext_control {
+    v4l2_ext_control {
+        id,
+        size,
+        __bindgen_anon_1: value,
+        ..Default::default()
+    }
+}
+
+/// Returns the current value of control `id`, whatever its type.
+pub fn get_control_value(fd: &impl AsRawFd, id: u32) -> Result<ControlValue, ControlValueError> {
+    let qctrl: v4l2_query_ext_ctrl = query_ext_ctrl(fd, CtrlId::new(id)?, QueryCtrlFlags::empty())?;
+
+    if has_payload(&qctrl) {
+        let mut payload = vec![0u8; payload_size(&qctrl)];
+        let mut control = [ext_control(
+            id,
+            v4l2_ext_control__bindgen_ty_1 {
+                ptr: payload.as_mut_ptr() as *mut _,
+            },
+            payload.len() as u32,
+        )];
+        g_ext_ctrls(fd, CtrlWhich::Current, &mut control[..])?;
+
+        return Ok(match qctrl.type_ {
+            bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_STRING => {
+                ControlValue::String(read_string(&payload))
+            }
+            _ if is_unsigned_scalar(&qctrl) => {
+                ControlValue::UnsignedInteger(read_unsigned(&payload, qctrl.elem_size as usize))
+            }
+            _ => ControlValue::Compound(payload),
+        });
+    }
+
+    let mut control = [ext_control(
+        id,
+        v4l2_ext_control__bindgen_ty_1 { value64: 0 },
+        0,
+    )];
+    g_ext_ctrls(fd, CtrlWhich::Current, &mut control[..])?;
+    // SAFETY: the control has no payload, so its value is stored in `value` or `value64`.
+    let (value, value64) = unsafe {
+        (
+            control[0].__bindgen_anon_1.value,
+            control[0].__bindgen_anon_1.value64,
+        )
+    };
+
+    Ok(match qctrl.type_ {
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER => ControlValue::Integer(value),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER64 => ControlValue::Integer64(value64),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_BOOLEAN => ControlValue::Boolean(value != 0),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_BITMASK => ControlValue::Bitmask(value as u32),
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_MENU => {
+            let index = value as u32;
+            // SAFETY: the `name` member is valid for menu controls.
+            let name = menu_item(fd, id, index)
+                .map(|item| string_from_cstr(&unsafe { item.__bindgen_anon_1.name }))
+                .unwrap_or_default();
+            ControlValue::Menu(index, name)
+        }
+        bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER_MENU => {
+            let index = value as u32;
+            // SAFETY: the `value` member is valid for integer menu controls.
+            let item_value = menu_item(fd, id, index)
+                .map(|item| unsafe { item.__bindgen_anon_1.value })
+                .unwrap_or_default();
+            ControlValue::IntegerMenu(index, item_value)
+        }
+        ctrl_type => return Err(ControlValueError::UnsupportedType(ctrl_type)),
+    })
+}
+
+/// Set control `id` to `value`, which must match the type of the control.
+///
+/// Only the index of [`ControlValue::Menu`] and [`ControlValue::IntegerMenu`] values is used.
+/// Strings longer than the maximum length of the control are truncated.
+pub fn set_control_value(
+    fd: &impl AsRawFd,
+    id: u32,
+    value: ControlValue,
+) -> Result<(), ControlValueError> {
+    let qctrl: v4l2_query_ext_ctrl = query_ext_ctrl(fd, CtrlId::new(id)?, QueryCtrlFlags::empty())?;
+
+    let mut payload = match (qctrl.type_, &value) {
+        (_, ControlValue::Compound(payload)) if has_payload(&qctrl) => Some(payload.clone()),
+        (bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_STRING, ControlValue::String(string)) => {
+            Some(write_string(string, payload_size(&qctrl)))
+        }
+        (_, ControlValue::UnsignedInteger(v)) if is_unsigned_scalar(&qctrl) => {
+            Some(write_unsigned(*v, qctrl.elem_size as usize))
+        }
+        _ => None,
+    };
+
+    let union_value = match (qctrl.type_, &value, &mut payload) {
+        (_, _, Some(payload)) => v4l2_ext_control__bindgen_ty_1 {
+            ptr: payload.as_mut_ptr() as *mut _,
+        },
+        (bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER, ControlValue::Integer(v), None) => {
+            v4l2_ext_control__bindgen_ty_1 { value: *v }
+        }
+        (bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER64, ControlValue::Integer64(v), None) => {
+            v4l2_ext_control__bindgen_ty_1 { value64: *v }
+        }
+        (bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_BOOLEAN, ControlValue::Boolean(v), None) => {
+            v4l2_ext_control__bindgen_ty_1 { value: *v as i32 }
+        }
+        (bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_BITMASK, ControlValue::Bitmask(v), None) => {
+            v4l2_ext_control__bindgen_ty_1 { value: *v as i32 }
+        }
+        (bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_MENU, ControlValue::Menu(index, _), None)
+        | (
+            bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER_MENU,
+            ControlValue::IntegerMenu(index, _),
+            None,
+        ) => v4l2_ext_control__bindgen_ty_1 {
+            value: *index as i32,
+        },
+        (ctrl_type, _, None) => {
+            return Err(ControlValueError::TypeMismatch {
+                value: value.clone(),
+                ctrl_type,
+            })
+        }
+    };
+
+    let size = payload.as_ref().map(|p| p.len() as u32).unwrap_or(0);
+    let mut control = [ext_control(id, union_value, size)];
+    s_ext_ctrls(fd, CtrlWhich::Current, &mut control[..])?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string_payload() {
+        let payload = write_string("hello", 8);
+        assert_eq!(payload, b"hello\0\0\0");
+        assert_eq!(read_string(&payload), "hello");
+
+        // Strings are truncated so the terminating zero always fits.
+        let payload = write_string("truncated", 4);
+        assert_eq!(payload, b"tru\0");
+        assert_eq!(read_string(&payload), "tru");
+
+        // Surrounding whitespace is part of the value.
+        let payload = write_string("  padded \t", 16);
+        assert_eq!(read_string(&payload), "  padded \t");
+    }
+
+    #[test]
+    fn test_unsigned_payload() {
+        for elem_size in [1, 2, 4] {
+            let payload = write_unsigned(0x7f, elem_size);
+            assert_eq!(payload.len(), elem_size);
+            assert_eq!(read_unsigned(&payload, elem_size), 0x7f);
+        }
+        assert_eq!(read_unsigned(&write_unsigned(0x1234, 1), 1), 0x34);
+    }
+}
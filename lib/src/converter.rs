@@ -0,0 +1,502 @@
+//! High-level interface for a [V4L2 memory-to-memory
+//! converter](https://www.kernel.org/doc/html/latest/userspace-api/media/v4l/dev-mem2mem.html),
+//! i.e. a device converting raw frames from one format to another such as a scaler, a rotator or
+//! a colorspace conversion engine.
+//!
+//! The frames to convert are queued to the `OUTPUT` queue, and the converted frames are dequeued
+//! from the `CAPTURE` queue. Both queues have their own format, and optionally their own selection
+//! rectangle: the crop rectangle of the `OUTPUT` queue selects the part of the source frames to
+//! convert, and the compose rectangle of the `CAPTURE` queue the part of the destination frames it
+//! is scaled into.
+//!
+//! Source frames can either be copied into `MMAP` buffers using [`Converter::convert`], or passed
+//! as DMABUFs using [`Converter::convert_dmabuf`]. In both cases the converted frame is returned as
+//! a [`DqBuffer`] which can be mapped, or exported using [`DqBuffer::export`] to pass it to
+//! another device without copy. This also makes it possible to chain a decoder to a converter in
+//! order to obtain a format the decoder cannot produce natively.
+use crate::{
+    device::{
+        queue::{
+            direction::{Capture, Direction, Output},
+            dqbuf::DqBuffer,
+            BuffersAllocated, CreateQueueError, GetFreeBufferError, GetFreeCaptureBuffer,
+            GetFreeOutputBuffer, OutputQueueable, Queue, QueueInit, RequestBuffersError,
+        },
+        Device, DeviceConfig, DeviceOpenError, Stream, TryDequeue,
+    },
+    error::AsErrno,
+    ioctl::{
+        Capabilities, DqBufError, DqBufIoctlError, GFmtError, QBufError, SFmtError,
+        SSelectionError, SelectionTarget, StreamOnError, V4l2BufferFromError,
+    },
+    memory::{BufferHandles, DmaBufHandle, DmaBufSource, MmapHandle, PrimitiveBufferHandles},
+    Format, PixelFormat, QueueType, Rect,
+};
+
+use nix::{
+    errno::Errno,
+    poll::{PollFd, PollFlags, PollTimeout},
+};
+use std::{
+    convert::Infallible,
+    os::fd::AsFd,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// A converted frame, as returned by [`Converter::convert`] and [`Converter::convert_dmabuf`].
+/// Dropping it returns the buffer to the converter.
+pub type ConvertedFrame = DqBuffer<Capture, Vec<MmapHandle>>;
+
+/// Configuration of a [`Converter`].
+#[derive(Clone, Debug)]
+pub struct ConverterConfig {
+    src_format: PixelFormat,
+    src_size: (u32, u32),
+    dst_format: PixelFormat,
+    dst_size: (u32, u32),
+    src_rect: Option<Rect>,
+    dst_rect: Option<Rect>,
+    num_buffers: usize,
+    timeout: Duration,
+}
+
+impl ConverterConfig {
+    /// Number of buffers allocated on each queue by default.
+    pub const DEFAULT_NUM_BUFFERS: usize = 2;
+    /// Maximum time to wait for a frame to be converted by default.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Create a configuration converting `src_width`x`src_height` frames in `src_format` into
+    /// `dst_width`x`dst_height` frames in `dst_format`.
+    pub fn new(
+        src_format: impl Into<PixelFormat>,
+        src_width: u32,
+        src_height: u32,
+        dst_format: impl Into<PixelFormat>,
+        dst_width: u32,
+        dst_height: u32,
+    ) -> Self {
+        Self {
+            src_format: src_format.into(),
+            src_size: (src_width, src_height),
+            dst_format: dst_format.into(),
+            dst_size: (dst_width, dst_height),
+            src_rect: None,
+            dst_rect: None,
+            num_buffers: Self::DEFAULT_NUM_BUFFERS,
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Only convert the `rect` area of the source frames, i.e. set the crop rectangle of the
+    /// `OUTPUT` queue.
+    pub fn src_rect(mut self, rect: Rect) -> Self {
+        self.src_rect = Some(rect);
+        self
+    }
+
+    /// Scale the converted frames into the `rect` area of the destination frames, i.e. set the
+    /// compose rectangle of the `CAPTURE` queue.
+    pub fn dst_rect(mut self, rect: Rect) -> Self {
+        self.dst_rect = Some(rect);
+        self
+    }
+
+    /// Number of buffers to allocate on each queue.
+    pub fn num_buffers(mut self, num_buffers: usize) -> Self {
+        self.num_buffers = num_buffers.max(1);
+        self
+    }
+
+    /// Maximum time to wait for a frame to be converted.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConverterOpenError {
+    #[error("error while opening device")]
+    DeviceOpenError(#[from] DeviceOpenError),
+    #[error("specified device is not a memory-to-memory device")]
+    NotAMem2MemDevice,
+    #[error("error while creating queue")]
+    CreateQueueError(#[from] CreateQueueError),
+    #[error("error while obtaining format: {0}")]
+    GFmtError(#[from] GFmtError),
+    #[error("error while setting format: {0}")]
+    SFmtError(#[from] SFmtError),
+    #[error("{queue} queue does not support {width}x{height} {pixelformat} frames")]
+    UnsupportedFormat {
+        queue: QueueType,
+        pixelformat: PixelFormat,
+        width: u32,
+        height: u32,
+    },
+    #[error("error while setting selection rectangle: {0}")]
+    SSelectionError(#[from] SSelectionError),
+    #[error("error while allocating buffers")]
+    RequestBuffersError(#[from] RequestBuffersError),
+    #[error("error while starting streaming: {0}")]
+    StreamOnError(#[from] StreamOnError),
+}
+
+impl AsErrno for ConverterOpenError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            ConverterOpenError::DeviceOpenError(e) => e.errno(),
+            ConverterOpenError::NotAMem2MemDevice => None,
+            ConverterOpenError::CreateQueueError(e) => e.errno(),
+            ConverterOpenError::GFmtError(e) => e.errno(),
+            ConverterOpenError::SFmtError(e) => e.errno(),
+            ConverterOpenError::UnsupportedFormat { .. } => None,
+            ConverterOpenError::SSelectionError(e) => e.errno(),
+            ConverterOpenError::RequestBuffersError(e) => e.errno(),
+            ConverterOpenError::StreamOnError(e) => e.errno(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    #[error("no free buffer to convert the frame")]
+    NoFreeBuffer(#[from] GetFreeBufferError),
+    #[error("failed to map plane {0} of the source buffer")]
+    MapError(usize),
+    #[error("plane {plane} is {size} bytes large but the source buffer only holds {capacity}")]
+    PlaneTooLarge {
+        plane: usize,
+        size: usize,
+        capacity: usize,
+    },
+    #[error("error while queuing buffer: {0}")]
+    QBufError(#[from] QBufError<Infallible>),
+    #[error("error while polling the device: {0}")]
+    PollError(Errno),
+    #[error("error while dequeuing buffer: {0}")]
+    DqBufError(#[from] DqBufError<V4l2BufferFromError>),
+    #[error("frame has not been converted before the timeout expired")]
+    Timeout,
+    #[error("driver reported an error while converting the frame")]
+    ConversionFailed,
+}
+
+impl AsErrno for ConvertError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            ConvertError::NoFreeBuffer(e) => e.errno(),
+            ConvertError::MapError(_) => None,
+            ConvertError::PlaneTooLarge { .. } => None,
+            ConvertError::QBufError(e) => e.errno(),
+            ConvertError::PollError(e) => Some(*e),
+            ConvertError::DqBufError(e) => e.errno(),
+            ConvertError::Timeout => None,
+            ConvertError::ConversionFailed => None,
+        }
+    }
+}
+
+/// Set the format of `queue` to `pixelformat` and `width`x`height`, failing if the driver adjusts
+/// any of them.
+fn set_raw_format<D: Direction>(
+    queue: &mut Queue<D, QueueInit>,
+    pixelformat: PixelFormat,
+    (width, height): (u32, u32),
+) -> Result<Format, ConverterOpenError> {
+    let format: Format = queue
+        .change_format()?
+        .set_pixelformat(pixelformat)
+        .set_size(width as usize, height as usize)
+        .apply()?;
+
+    if format.pixelformat != pixelformat || format.width != width || format.height != height {
+        return Err(ConverterOpenError::UnsupportedFormat {
+            queue: queue.get_type(),
+            pixelformat,
+            width,
+            height,
+        });
+    }
+
+    Ok(format)
+}
+
+/// Dequeue a buffer from `queue` if one is ready.
+fn try_dequeue<D: Direction, P: BufferHandles>(
+    queue: &Queue<D, BuffersAllocated<P>>,
+) -> Result<Option<DqBuffer<D, P>>, ConvertError> {
+    match queue.try_dequeue() {
+        Ok(buffer) => Ok(Some(buffer)),
+        Err(DqBufError::IoctlError(DqBufIoctlError::NotReady)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A memory-to-memory converter processing one frame at a time.
+///
+/// `OP` is the type of the handles of the `OUTPUT` queue: `Vec<MmapHandle>` to copy the source
+/// frames using [`Converter::convert`], or `Vec<DmaBufHandle<T>>` to pass them as DMABUFs using
+/// [`Converter::convert_dmabuf`]. Converted frames are always returned in `MMAP` buffers.
+pub struct Converter<OP: BufferHandles> {
+    device: Arc<Device>,
+    output_queue: Queue<Output, BuffersAllocated<OP>>,
+    capture_queue: Queue<Capture, BuffersAllocated<Vec<MmapHandle>>>,
+    src_format: Format,
+    dst_format: Format,
+    src_rect: Option<Rect>,
+    dst_rect: Option<Rect>,
+    timeout: Duration,
+}
+
+impl<OP: PrimitiveBufferHandles> Converter<OP> {
+    /// Open the device at `path` and configure it according to `config`.
+    pub fn open(path: &Path, config: &ConverterConfig) -> Result<Self, ConverterOpenError> {
+        let device = Arc::new(Device::open(
+            path,
+            DeviceConfig::new().non_blocking_dqbuf(),
+        )?);
+
+        let caps = device.caps().device_caps();
+        let (mut output_queue, mut capture_queue) = if caps.contains(Capabilities::VIDEO_M2M_MPLANE)
+        {
+            (
+                Queue::get_output_mplane_queue(Arc::clone(&device))?,
+                Queue::get_capture_mplane_queue(Arc::clone(&device))?,
+            )
+        } else if caps.contains(Capabilities::VIDEO_M2M) {
+            (
+                Queue::get_output_queue(Arc::clone(&device))?,
+                Queue::get_capture_queue(Arc::clone(&device))?,
+            )
+        } else {
+            return Err(ConverterOpenError::NotAMem2MemDevice);
+        };
+
+        let src_format = set_raw_format(&mut output_queue, config.src_format, config.src_size)?;
+        let dst_format = set_raw_format(&mut capture_queue, config.dst_format, config.dst_size)?;
+
+        // Selection rectangles depend on the format, so set them afterwards.
+        let src_rect = config
+            .src_rect
+            .map(|rect| output_queue.set_selection(SelectionTarget::Crop, rect))
+            .transpose()?;
+        let dst_rect = config
+            .dst_rect
+            .map(|rect| capture_queue.set_selection(SelectionTarget::Compose, rect))
+            .transpose()?;
+
+        let output_queue = output_queue.request_buffers::<OP>(config.num_buffers as u32)?;
+        let capture_queue =
+            capture_queue.request_buffers::<Vec<MmapHandle>>(config.num_buffers as u32)?;
+        output_queue.stream_on()?;
+        capture_queue.stream_on()?;
+
+        Ok(Converter {
+            device,
+            output_queue,
+            capture_queue,
+            src_format,
+            dst_format,
+            src_rect,
+            dst_rect,
+            timeout: config.timeout,
+        })
+    }
+}
+
+impl<OP: BufferHandles> Converter<OP> {
+    /// Returns the device of the converter.
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    /// Returns the format of the source frames.
+    pub fn src_format(&self) -> &Format {
+        &self.src_format
+    }
+
+    /// Returns the format of the converted frames.
+    pub fn dst_format(&self) -> &Format {
+        &self.dst_format
+    }
+
+    /// Returns the crop rectangle applied to the source frames, as adjusted by the driver, if one
+    /// has been configured.
+    pub fn src_rect(&self) -> Option<Rect> {
+        self.src_rect
+    }
+
+    /// Returns the compose rectangle of the converted frames, as adjusted by the driver, if one
+    /// has been configured.
+    pub fn dst_rect(&self) -> Option<Rect> {
+        self.dst_rect
+    }
+
+    /// Queue a `CAPTURE` buffer to receive the next converted frame.
+    fn queue_capture_buffer(&self) -> Result<(), ConvertError> {
+        self.capture_queue.try_get_free_buffer()?.queue()?;
+
+        Ok(())
+    }
+
+    /// Wait until both the source buffer and the converted frame can be dequeued, and return
+    /// them.
+    ///
+    /// If the timeout expires, the buffers remain queued and the converter should not be used
+    /// anymore.
+    fn wait_for_frame(&self) -> Result<(DqBuffer<Output, OP>, ConvertedFrame), ConvertError> {
+        let deadline = Instant::now() + self.timeout;
+        let mut src = None;
+        let mut dst = None;
+
+        loop {
+            if src.is_none() {
+                src = try_dequeue(&self.output_queue)?;
+            }
+            if dst.is_none() {
+                dst = try_dequeue(&self.capture_queue)?;
+            }
+
+            match (src.take(), dst.take()) {
+                (Some(_), Some(dst)) if dst.data.has_error() => {
+                    return Err(ConvertError::ConversionFailed)
+                }
+                (Some(src), Some(dst)) => return Ok((src, dst)),
+                (s, d) => {
+                    src = s;
+                    dst = d;
+                }
+            }
+
+            // Source buffers are signaled as writable once processed, converted frames as
+            // readable.
+            let mut flags = PollFlags::empty();
+            if src.is_none() {
+                flags |= PollFlags::POLLOUT;
+            }
+            if dst.is_none() {
+                flags |= PollFlags::POLLIN;
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut fds = [PollFd::new(self.device.as_fd(), flags)];
+            let poll_timeout = PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX);
+            match nix::poll::poll(&mut fds, poll_timeout) {
+                Ok(0) => return Err(ConvertError::Timeout),
+                Ok(_) => (),
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(ConvertError::PollError(e)),
+            }
+        }
+    }
+}
+
+impl Converter<Vec<MmapHandle>> {
+    /// Copy `planes` into a source buffer and return the converted frame.
+    ///
+    /// `planes` contains the data of each plane of the source frame, in the layout of
+    /// [`Converter::src_format`].
+    pub fn convert(&self, planes: &[&[u8]]) -> Result<ConvertedFrame, ConvertError> {
+        let buffer = self.output_queue.try_get_free_buffer()?;
+
+        for (i, plane) in planes.iter().enumerate() {
+            let mut mapping = buffer
+                .get_plane_mapping(i)
+                .ok_or(ConvertError::MapError(i))?;
+            if plane.len() > mapping.len() {
+                return Err(ConvertError::PlaneTooLarge {
+                    plane: i,
+                    size: plane.len(),
+                    capacity: mapping.len(),
+                });
+            }
+            mapping.as_mut()[..plane.len()].copy_from_slice(plane);
+        }
+
+        let bytes_used: Vec<usize> = planes.iter().map(|plane| plane.len()).collect();
+        buffer.queue(&bytes_used)?;
+        self.queue_capture_buffer()?;
+
+        let (_, frame) = self.wait_for_frame()?;
+
+        Ok(frame)
+    }
+}
+
+impl<T: DmaBufSource + 'static> Converter<Vec<DmaBufHandle<T>>> {
+    /// Convert the frame backed by the DMABUFs `planes`, `bytes_used` bytes of each being used,
+    /// without copying it.
+    ///
+    /// The converted frame is returned along with `planes`, which the converter does not access
+    /// anymore.
+    pub fn convert_dmabuf(
+        &self,
+        planes: Vec<DmaBufHandle<T>>,
+        bytes_used: &[usize],
+    ) -> Result<(ConvertedFrame, Vec<DmaBufHandle<T>>), ConvertError> {
+        self.output_queue
+            .try_get_free_buffer()?
+            .queue_with_handles(planes, bytes_used)
+            .map_err(|e| e.error)?;
+        self.queue_capture_buffer()?;
+
+        let (mut src, frame) = self.wait_for_frame()?;
+        let planes = src.take_handles().unwrap_or_default();
+
+        Ok((frame, planes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConvertError, ConverterConfig, ConverterOpenError};
+    use crate::{error::AsErrno, PixelFormat, QueueType, Rect};
+
+    #[test]
+    fn test_converter_config() {
+        let config = ConverterConfig::new(b"RGB3", 640, 480, b"YUYV", 320, 240);
+        assert_eq!(config.src_format, PixelFormat::from(b"RGB3"));
+        assert_eq!(config.src_size, (640, 480));
+        assert_eq!(config.dst_format, PixelFormat::from(b"YUYV"));
+        assert_eq!(config.dst_size, (320, 240));
+        assert_eq!(config.src_rect, None);
+        assert_eq!(config.dst_rect, None);
+        assert_eq!(config.num_buffers, ConverterConfig::DEFAULT_NUM_BUFFERS);
+
+        let config = config
+            .src_rect(Rect::new(0, 0, 320, 240))
+            .dst_rect(Rect::new(16, 16, 160, 120))
+            .num_buffers(0);
+        assert_eq!(config.src_rect, Some(Rect::new(0, 0, 320, 240)));
+        assert_eq!(config.dst_rect, Some(Rect::new(16, 16, 160, 120)));
+        // At least one buffer is needed on each queue.
+        assert_eq!(config.num_buffers, 1);
+    }
+
+    #[test]
+    fn test_converter_errors() {
+        let error = ConverterOpenError::UnsupportedFormat {
+            queue: QueueType::VideoCapture,
+            pixelformat: PixelFormat::from(b"NV12"),
+            width: 640,
+            height: 480,
+        };
+        assert_eq!(error.errno(), None);
+        assert!(error.to_string().contains("640x480 NV12"));
+
+        let error = ConvertError::PlaneTooLarge {
+            plane: 0,
+            size: 4096,
+            capacity: 1024,
+        };
+        assert_eq!(error.errno(), None);
+        assert_eq!(
+            ConvertError::PollError(nix::errno::Errno::EIO).errno(),
+            Some(nix::errno::Errno::EIO)
+        );
+    }
+}
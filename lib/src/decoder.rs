@@ -7,11 +7,17 @@ use crate::{
         handles_provider::HandlesProvider,
         CanceledBuffer, FormatBuilder,
     },
+    error::AsErrno,
     memory::BufferHandles,
-    Rect,
+    watchdog::Stalled,
+    PixelFormat, Rect,
 };
+use nix::errno::Errno;
+use std::time::Duration;
+use thiserror::Error;
 
 pub mod format;
+pub mod jpeg;
 pub mod stateful;
 
 pub enum CompletedInputBuffer<OP: BufferHandles> {
@@ -27,7 +33,50 @@ where
 {
 }
 
-// TODO: add errors?
+/// Step of the decoder initialization during which a [`DecodeSetupError::DriverError`] occurred.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeSetupStage {
+    /// Setting the format of the OUTPUT queue.
+    OutputFormat,
+    /// Waiting for the initial `SOURCE_CHANGE` event.
+    SourceChange,
+    /// Dequeuing the first decoded frame.
+    FirstFrame,
+}
+
+/// Reason why the decoder could not start decoding a stream.
+///
+/// Drivers report streams exceeding their capabilities in many different ways: rejecting the
+/// OUTPUT format, never signaling the initial resolution, or returning an error buffer as the
+/// first decoded frame. This classifies these failures so clients can e.g. fall back to software
+/// decoding with a meaningful message.
+#[derive(Debug, Error)]
+pub enum DecodeSetupError {
+    #[error("codec {0} is not supported by the decoder")]
+    UnsupportedCodec(PixelFormat),
+    #[error("stream resolution is not supported by the decoder (maximum: {max:?})")]
+    UnsupportedResolution {
+        /// Maximum width and height supported for the OUTPUT format, if the driver reports it.
+        max: Option<(u32, u32)>,
+    },
+    #[error("no source change event received {timeout:?} after the stream started")]
+    NoSourceChange { timeout: Duration },
+    #[error("driver error during {stage:?}: {errno}")]
+    DriverError {
+        errno: Errno,
+        stage: DecodeSetupStage,
+    },
+}
+
+impl AsErrno for DecodeSetupError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            DecodeSetupError::DriverError { errno, .. } => Some(*errno),
+            _ => None,
+        }
+    }
+}
+
 pub enum DecoderEvent<P: HandlesProvider> {
     /// Emitted when a frame is decoded.
     ///
@@ -37,6 +86,10 @@ pub enum DecoderEvent<P: HandlesProvider> {
     /// of them: for instance, when the `V4L2_BUF_FLAG_LAST` is set, the proper
     /// corresponding event (resolution change or end of stream) will be
     /// signaled appropriately.
+    ///
+    /// On a resolution change, the CAPTURE buffers are freed as soon as the callback returns for
+    /// the buffer with the `LAST` flag. Buffers exported to another device must have been
+    /// released by then.
     FrameDecoded(DqBuffer<Capture, P::HandleType>),
     /// Emitted when a previously requested `drain` request completes.
     ///
@@ -44,6 +97,22 @@ pub enum DecoderEvent<P: HandlesProvider> {
     /// corresponding to all the input buffers queued before the `drain` request
     /// have been emitted.
     EndOfStream,
+    /// Emitted when the decoder fails to start decoding the stream.
+    ///
+    /// The decoder keeps running after this event, but is unlikely to produce any frame until it
+    /// is reset with a stream it supports.
+    SetupError(DecodeSetupError),
+    /// Emitted when decoded frames start being dropped because the client does not return them
+    /// fast enough, as configured by a
+    /// [`FrameDropPolicy`](crate::decoder::stateful::FrameDropPolicy).
+    FrameDropStarted,
+    /// Emitted when the client has caught up and decoded frames stop being dropped.
+    FrameDropStopped,
+    /// Emitted when the driver has not made progress for longer than the watchdog timeout set
+    /// with [`Decoder::set_watchdog`](crate::decoder::stateful::Decoder::set_watchdog), which
+    /// usually means it has hung. The client can try to get it going again with
+    /// [`Decoder::recover`](crate::decoder::stateful::Decoder::recover).
+    Stalled(Stalled),
 }
 
 pub trait DecoderEventCallback<P: HandlesProvider>:
@@ -1,26 +1,30 @@
-use super::{PatternSplitter, StreamSplitter};
-use std::io;
+use super::StreamSplitter;
+use crate::bitstream::{h264::AccessUnitSplitter, NalReader};
+use std::io::{self, Read};
 
 static H264_START_CODE: [u8; 4] = [0x0, 0x0, 0x0, 0x1];
 
 /// Splits a H.264 annex B stream into chunks that are all guaranteed to contain a full frame
 /// worth of data.
 ///
-/// This is a pretty naive implementation that is only guaranteed to work with our examples.
-pub struct H264FrameSplitter<S: io::Read>(PatternSplitter<S>);
+/// This is a thin wrapper around [`AccessUnitSplitter`], which should be preferred by new code.
+pub struct H264FrameSplitter<S: io::Read>(
+    AccessUnitSplitter<NalReader<io::Chain<io::Cursor<[u8; 4]>, S>>>,
+);
 
 impl<S: io::Read> H264FrameSplitter<S> {
-    pub fn new(stream: S) -> Option<Self> {
-        Some(Self(PatternSplitter::new(
-            H264_START_CODE.to_vec(),
-            stream,
-        )?))
-    }
+    /// Creates a splitter for `stream`, which must start with a 4-byte start code. Returns `None`
+    /// otherwise.
+    pub fn new(mut stream: S) -> Option<Self> {
+        let mut stream_start = [0u8; 4];
+        stream.read_exact(&mut stream_start).ok()?;
+        if stream_start != H264_START_CODE {
+            return None;
+        }
 
-    fn contains_frame(data: &[u8]) -> bool {
-        data[4..].windows(4).any(|window| {
-            window[0..3] == [0x0, 0x0, 0x1] && (window[3] & 0x1f == 0x5 || window[3] & 0x1f == 0x1)
-        })
+        Some(Self(AccessUnitSplitter::from_reader(
+            io::Cursor::new(stream_start).chain(stream),
+        )))
     }
 }
 
@@ -29,16 +33,34 @@ impl<S: io::Read> Iterator for H264FrameSplitter<S> {
 
     /// Returns the next frame in the stream, header included.
     fn next(&mut self) -> Option<Self::Item> {
-        let mut next_slice = self.0.next()?;
-        while !Self::contains_frame(&next_slice) {
-            match self.0.next() {
-                None => return Some(next_slice),
-                Some(data) => next_slice.extend(data),
-            }
-        }
-
-        Some(next_slice)
+        self.0.next()
     }
 }
 
 impl<S: io::Read> StreamSplitter for H264FrameSplitter<S> {}
+
+#[cfg(test)]
+mod tests {
+    use super::H264FrameSplitter;
+
+    #[test]
+    fn test_h264_frame_splitter() {
+        let sps = [0x00, 0x00, 0x00, 0x01, 0x67, 0x42, 0xc0, 0x1e];
+        let pps = [0x00, 0x00, 0x00, 0x01, 0x68, 0xce, 0x3c, 0x80];
+        let idr = [0x00, 0x00, 0x00, 0x01, 0x65, 0x88, 0x84];
+        let slice = [0x00, 0x00, 0x00, 0x01, 0x41, 0x9a, 0x42];
+        let stream = [&sps[..], &pps[..], &idr[..], &slice[..]].concat();
+
+        // The parameter sets are returned with the first frame.
+        assert_eq!(
+            H264FrameSplitter::new(&stream[..])
+                .unwrap()
+                .collect::<Vec<_>>(),
+            vec![[&sps[..], &pps[..], &idr[..]].concat(), slice.to_vec()]
+        );
+
+        // The stream must start with a 4-byte start code.
+        assert!(H264FrameSplitter::new(&stream[1..]).is_none());
+        assert!(H264FrameSplitter::new(&[0x00, 0x00][..]).is_none());
+    }
+}
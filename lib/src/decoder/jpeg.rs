@@ -0,0 +1,526 @@
+//! Decoding of the MJPEG stream of a camera using a V4L2 JPEG decoder.
+//!
+//! [`MjpegDecoder`] captures MJPEG frames from a camera and queues them into a stateful JPEG
+//! decoder. Decoded frames are delivered through a [`DecoderEventCallback`], like with a regular
+//! [`Decoder`].
+//!
+//! Frames are either copied into the OUTPUT buffers of the decoder, or passed as DMABUFs exported
+//! from the camera to avoid the copy. In the latter case, a camera buffer is only queued again once
+//! the decoder is done reading it.
+//!
+//! The header of each frame is parsed before it is queued, which allows to:
+//!
+//! * skip corrupted frames, which cameras produce from time to time and which can make some
+//!   decoders stall;
+//! * set the `V4L2_CID_JPEG_CHROMA_SUBSAMPLING` control for drivers that need it;
+//! * restart the decoder when the dimensions of the frames change, as the OUTPUT format of JPEG
+//!   decoders must match the dimensions of the images.
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::Infallible,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::ensure;
+use log::{debug, info, warn};
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::{
+    bitstream::jpeg::JpegHeader,
+    controls::{jpeg::JpegChromaSubsampling, SafeExtControl},
+    decoder::{
+        stateful::{
+            Decoder, DecoderOpenError, Decoding, DrainError, GetBufferError, StartDecoderError,
+            StopError,
+        },
+        CompletedInputBuffer, DecodeSetupError, DecoderEvent, DecoderEventCallback,
+        FormatChangedReply,
+    },
+    device::{
+        queue::{
+            direction::Capture,
+            dqbuf::DqBuffer,
+            generic::{GenericBufferHandles, GenericQBuffer, GenericSupportedMemoryType},
+            handles_provider::MmapProvider,
+            BuffersAllocated, CreateQueueError, FormatBuilder, GetFreeCaptureBuffer,
+            OutputQueueable, Queue, RequestBuffersError,
+        },
+        AllocatedQueue, Device, DeviceConfig, DeviceOpenError, Stream, TryDequeue,
+    },
+    error::AsErrno,
+    ioctl::{self, DqBufError, ExpbufFlags, StreamOnError, V4l2BufferFromError},
+    memory::{DmaBufHandle, MemoryType, MmapHandle},
+    Format, PixelFormat, PlaneLayout, Rect,
+};
+
+/// Returns the paths of the stateful decoders accepting JPEG images, sorted by node number.
+pub fn find_jpeg_decoders() -> Vec<PathBuf> {
+    let mut nodes: Vec<(u32, PathBuf)> = match std::fs::read_dir("/dev") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                let index = path
+                    .file_name()?
+                    .to_str()?
+                    .strip_prefix("video")?
+                    .parse()
+                    .ok()?;
+                Some((index, path))
+            })
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+    nodes.sort();
+
+    nodes
+        .into_iter()
+        .map(|(_, path)| path)
+        .filter(|path| match Decoder::open(path) {
+            Ok(decoder) => decoder.check_output_format(PixelFormat::JPEG, 1, 1).is_ok(),
+            Err(_) => false,
+        })
+        .collect()
+}
+
+/// Configuration of a [`MjpegDecoder`].
+#[derive(Clone, Debug)]
+pub struct MjpegDecoderConfig {
+    width: u32,
+    height: u32,
+    capture_formats: Vec<PixelFormat>,
+    num_camera_buffers: usize,
+    num_output_buffers: usize,
+    zero_copy: bool,
+}
+
+impl MjpegDecoderConfig {
+    /// Create a configuration capturing frames of `width`x`height` pixels, decoded into NV12 or
+    /// YUYV.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            capture_formats: vec![PixelFormat::NV12, PixelFormat::YUYV],
+            num_camera_buffers: 4,
+            num_output_buffers: 2,
+            zero_copy: false,
+        }
+    }
+
+    /// Formats to try for the decoded frames, by order of preference. The format selected by the
+    /// decoder is kept if it supports none of them.
+    pub fn capture_formats(mut self, formats: impl IntoIterator<Item = PixelFormat>) -> Self {
+        self.capture_formats = formats.into_iter().collect();
+        self
+    }
+
+    /// Number of buffers to allocate on the camera, and on the OUTPUT queue of the decoder.
+    pub fn num_buffers(mut self, camera: usize, decoder: usize) -> Self {
+        self.num_camera_buffers = camera.max(1);
+        self.num_output_buffers = decoder.max(1);
+        self
+    }
+
+    /// Pass the frames to the decoder as DMABUFs exported from the camera instead of copying them.
+    ///
+    /// The camera buffers are held until the decoder is done with them, so at least two more
+    /// buffers than the decoder has OUTPUT buffers are allocated on the camera.
+    pub fn zero_copy(mut self) -> Self {
+        self.zero_copy = true;
+        self
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MjpegDecoderError {
+    #[error("error while opening camera")]
+    CameraOpenError(#[from] DeviceOpenError),
+    #[error("error while obtaining camera queue")]
+    CameraQueueError(#[from] CreateQueueError),
+    #[error("error while getting camera format")]
+    GFmtError(#[from] ioctl::GFmtError),
+    #[error("error while setting camera format")]
+    SFmtError(#[from] ioctl::SFmtError),
+    #[error("camera cannot produce MJPEG frames (proposed format: {0:?})")]
+    UnsupportedCameraFormat(Format),
+    #[error("error while allocating camera buffers")]
+    CameraBuffersError(#[source] RequestBuffersError),
+    #[error("error while exporting camera buffer")]
+    ExpbufError(#[from] ioctl::ExpbufError),
+    #[error("error while duplicating DMABUF")]
+    DmaBufDupError(#[from] io::Error),
+    #[error("error while starting camera")]
+    StreamOnError(#[from] StreamOnError),
+    #[error("error while dequeuing buffer")]
+    DqBufError(#[from] DqBufError<V4l2BufferFromError>),
+    #[error("error while queuing buffer")]
+    QBufError(#[from] ioctl::QBufError<Infallible>),
+    #[error("cannot map buffer")]
+    MapError,
+    #[error("error while opening decoder")]
+    DecoderOpenError(#[from] DecoderOpenError),
+    #[error("decoder cannot decode the stream")]
+    DecodeSetupError(#[from] DecodeSetupError),
+    #[error("error while setting decoder format")]
+    DecoderFormatError(#[source] anyhow::Error),
+    #[error("error while allocating decoder buffers")]
+    DecoderBuffersError(#[source] RequestBuffersError),
+    #[error("error while starting decoder")]
+    StartDecoderError(#[from] StartDecoderError),
+    #[error("error while obtaining decoder buffer")]
+    GetBufferError(#[from] GetBufferError),
+    #[error("error while draining decoder")]
+    DrainError(#[from] DrainError),
+    #[error("error while stopping decoder")]
+    StopError(#[from] StopError),
+}
+
+impl AsErrno for MjpegDecoderError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            MjpegDecoderError::CameraOpenError(e) => e.errno(),
+            MjpegDecoderError::CameraQueueError(e) => e.errno(),
+            MjpegDecoderError::GFmtError(e) => e.errno(),
+            MjpegDecoderError::SFmtError(e) => e.errno(),
+            MjpegDecoderError::UnsupportedCameraFormat(_) => None,
+            MjpegDecoderError::CameraBuffersError(e) => e.errno(),
+            MjpegDecoderError::ExpbufError(e) => e.errno(),
+            MjpegDecoderError::DmaBufDupError(e) => e.errno(),
+            MjpegDecoderError::StreamOnError(e) => e.errno(),
+            MjpegDecoderError::DqBufError(e) => e.errno(),
+            MjpegDecoderError::QBufError(e) => e.errno(),
+            MjpegDecoderError::MapError => None,
+            MjpegDecoderError::DecoderOpenError(e) => e.errno(),
+            MjpegDecoderError::DecodeSetupError(e) => e.errno(),
+            MjpegDecoderError::DecoderFormatError(_) => None,
+            MjpegDecoderError::DecoderBuffersError(e) => e.errno(),
+            MjpegDecoderError::StartDecoderError(e) => e.errno(),
+            MjpegDecoderError::GetBufferError(e) => e.errno(),
+            MjpegDecoderError::DrainError(e) => e.errno(),
+            MjpegDecoderError::StopError(e) => e.errno(),
+        }
+    }
+}
+
+type CameraBuffer = DqBuffer<Capture, Vec<MmapHandle>>;
+type InputDoneCb = Box<dyn Fn(CompletedInputBuffer<GenericBufferHandles>)>;
+type EventCb = Box<dyn FnMut(DecoderEvent<MmapProvider>) + Send>;
+type FormatChangedCb = Box<
+    dyn Fn(FormatBuilder, Rect, usize) -> anyhow::Result<FormatChangedReply<MmapProvider>> + Send,
+>;
+type JpegDecoder =
+    Decoder<Decoding<GenericBufferHandles, MmapProvider, InputDoneCb, EventCb, FormatChangedCb>>;
+
+/// Decodes the MJPEG stream of a camera using a JPEG decoder.
+///
+/// The decoder is started with the first valid frame, and restarted each time the dimensions of
+/// the frames change. Restarting drains the decoder, so a [`DecoderEvent::EndOfStream`] is emitted
+/// once the frames of the previous dimensions have all been decoded.
+pub struct MjpegDecoder {
+    config: MjpegDecoderConfig,
+    camera: Queue<Capture, BuffersAllocated<Vec<MmapHandle>>>,
+    /// Size of the camera buffers, used as the size of the OUTPUT buffers of the decoder.
+    frame_size: u32,
+    /// DMABUFs exported from the camera buffers, by buffer index. Empty if zero-copy is disabled.
+    dmabufs: Vec<File>,
+    decoder_path: PathBuf,
+    event_cb: Arc<Mutex<EventCb>>,
+    /// Camera buffers being read by the decoder, by index of the OUTPUT buffer they are queued
+    /// into.
+    in_flight: Rc<RefCell<HashMap<usize, CameraBuffer>>>,
+    decoder: Option<JpegDecoder>,
+    /// Header of the last frame queued into `decoder`.
+    header: Option<JpegHeader>,
+    skipped_frames: usize,
+}
+
+impl MjpegDecoder {
+    /// Set up the camera at `camera_path` to produce MJPEG frames and start streaming. The
+    /// decoder at `decoder_path` is opened when the first frame is received.
+    ///
+    /// `event_cb` is called from the decoder thread for each decoded frame.
+    pub fn new<F>(
+        camera_path: &Path,
+        decoder_path: &Path,
+        config: MjpegDecoderConfig,
+        event_cb: F,
+    ) -> Result<Self, MjpegDecoderError>
+    where
+        F: DecoderEventCallback<MmapProvider>,
+    {
+        let event_cb: EventCb = Box::new(event_cb);
+        let device = Arc::new(Device::open(camera_path, DeviceConfig::new())?);
+        let mut camera = Queue::get_capture_queue(Arc::clone(&device))
+            .or_else(|_| Queue::get_capture_mplane_queue(Arc::clone(&device)))?;
+
+        let format: Format = camera
+            .change_format()?
+            .set_pixelformat(PixelFormat::MJPEG)
+            .set_size(config.width as usize, config.height as usize)
+            .apply()?;
+        if format.pixelformat != PixelFormat::MJPEG || format.plane_fmt.is_empty() {
+            return Err(MjpegDecoderError::UnsupportedCameraFormat(format));
+        }
+        debug!("Camera format: {:?}", format);
+
+        let num_camera_buffers = if config.zero_copy {
+            config.num_camera_buffers.max(config.num_output_buffers + 2)
+        } else {
+            config.num_camera_buffers
+        };
+        let camera = camera
+            .request_buffers::<Vec<MmapHandle>>(num_camera_buffers as u32)
+            .map_err(MjpegDecoderError::CameraBuffersError)?;
+
+        let dmabufs = if config.zero_copy {
+            (0..camera.num_buffers())
+                .map(|index| {
+                    ioctl::expbuf(
+                        &*device,
+                        camera.get_type(),
+                        index,
+                        0,
+                        ExpbufFlags::CLOEXEC | ExpbufFlags::RDONLY,
+                    )
+                })
+                .collect::<Result<Vec<File>, _>>()?
+        } else {
+            Vec::new()
+        };
+
+        camera.stream_on()?;
+
+        Ok(Self {
+            config,
+            camera,
+            frame_size: format.plane_fmt[0].sizeimage,
+            dmabufs,
+            decoder_path: decoder_path.to_path_buf(),
+            event_cb: Arc::new(Mutex::new(event_cb)),
+            in_flight: Default::default(),
+            decoder: None,
+            header: None,
+            skipped_frames: 0,
+        })
+    }
+
+    /// Returns the number of frames that have been skipped because they were corrupted.
+    pub fn skipped_frames(&self) -> usize {
+        self.skipped_frames
+    }
+
+    /// Returns the dimensions of the frames currently being decoded, if any.
+    pub fn frame_dimensions(&self) -> Option<(u32, u32)> {
+        self.header
+            .map(|header| (header.width as u32, header.height as u32))
+    }
+
+    /// Wait for the next frame of the camera and queue it into the decoder.
+    ///
+    /// Returns `false` if the frame has been skipped because it is corrupted.
+    pub fn decode_frame(&mut self) -> Result<bool, MjpegDecoderError> {
+        while let Ok(buffer) = self.camera.try_get_free_buffer() {
+            buffer.queue()?;
+        }
+
+        let frame = self.camera.try_dequeue()?;
+        let sequence = frame.data.sequence();
+        let mapping = match frame.get_plane_mapping(0) {
+            Some(mapping) if !frame.data.has_error() => mapping,
+            _ => {
+                warn!("Skipping frame {}: capture error", sequence);
+                self.skipped_frames += 1;
+                return Ok(false);
+            }
+        };
+        let header = match JpegHeader::parse(mapping.as_ref()) {
+            Ok(header) => header,
+            Err(e) => {
+                warn!("Skipping corrupted frame {}: {}", sequence, e);
+                self.skipped_frames += 1;
+                return Ok(false);
+            }
+        };
+
+        let previous = self.header;
+        let resized = previous.map(|h| (h.width, h.height)) != Some((header.width, header.height));
+        if resized {
+            if let Some(previous) = previous {
+                info!(
+                    "Frame size changed from {}x{} to {}x{}, restarting decoder",
+                    previous.width, previous.height, header.width, header.height
+                );
+            }
+            self.stop_decoder()?;
+            self.decoder = Some(self.start_decoder(&header)?);
+        }
+        let decoder = match self.decoder.as_mut() {
+            Some(decoder) => decoder,
+            None => unreachable!("decoder is started with the first frame"),
+        };
+        if resized || previous.map(|h| h.subsampling) != Some(header.subsampling) {
+            if let Some(subsampling) = header.subsampling {
+                set_subsampling_hint(decoder.device(), subsampling);
+            }
+        }
+        self.header = Some(header);
+
+        let timestamp = ioctl::timeval_to_nix(frame.data.timestamp());
+        let bytes_used = mapping.as_ref().len();
+        match decoder.get_buffer()? {
+            GenericQBuffer::Mmap(buffer) => {
+                let mut output = buffer
+                    .get_plane_mapping(0)
+                    .ok_or(MjpegDecoderError::MapError)?;
+                match output.as_mut().get_mut(..bytes_used) {
+                    Some(output) => output.copy_from_slice(mapping.as_ref()),
+                    None => {
+                        warn!("Skipping frame {}: too large for decoder", sequence);
+                        self.skipped_frames += 1;
+                        return Ok(false);
+                    }
+                }
+                drop(output);
+
+                buffer.set_timestamp(timestamp).queue(&[bytes_used])?;
+            }
+            GenericQBuffer::DmaBuf(buffer) => {
+                let index = buffer.index();
+                let dmabuf = self.dmabufs[frame.data.index() as usize].try_clone()?;
+                buffer
+                    .set_timestamp(timestamp)
+                    .queue_with_handles(
+                        GenericBufferHandles::from(vec![DmaBufHandle::from(dmabuf)]),
+                        &[bytes_used],
+                    )
+                    .map_err(|e| e.error)?;
+                // Keep the frame until the decoder is done reading it.
+                self.in_flight.borrow_mut().insert(index, frame);
+            }
+            GenericQBuffer::User(_) => unreachable!("decoder does not use USERPTR buffers"),
+        }
+
+        decoder.kick()?;
+
+        Ok(true)
+    }
+
+    /// Decode the frames queued so far and stop the decoder and the camera.
+    pub fn stop(mut self) -> Result<(), MjpegDecoderError> {
+        self.stop_decoder()
+    }
+
+    /// Open the decoder and set it up for frames with the dimensions of `header`.
+    fn start_decoder(&self, header: &JpegHeader) -> Result<JpegDecoder, MjpegDecoderError> {
+        let (width, height) = (header.width as u32, header.height as u32);
+        let frame_size = self.frame_size;
+
+        let decoder = Decoder::open(&self.decoder_path)?;
+        decoder.check_output_format(PixelFormat::JPEG, width, height)?;
+        let decoder = decoder
+            .set_output_format(|f| {
+                let format: Format = f
+                    .set_pixelformat(PixelFormat::JPEG)
+                    .set_size(width as usize, height as usize)
+                    .set_planes_layout(vec![PlaneLayout {
+                        sizeimage: frame_size,
+                        ..Default::default()
+                    }])
+                    .apply()?;
+
+                ensure!(
+                    format.pixelformat == PixelFormat::JPEG,
+                    "JPEG format not supported by decoder"
+                );
+
+                Ok(())
+            })
+            .map_err(|e| match e.downcast::<DecodeSetupError>() {
+                Ok(e) => MjpegDecoderError::DecodeSetupError(e),
+                Err(e) => MjpegDecoderError::DecoderFormatError(e),
+            })?;
+
+        let memory_type = if self.dmabufs.is_empty() {
+            GenericSupportedMemoryType::Mmap
+        } else {
+            GenericSupportedMemoryType::DmaBuf
+        };
+
+        let in_flight = Rc::clone(&self.in_flight);
+        let input_done_cb: InputDoneCb = Box::new(move |buffer: CompletedInputBuffer<_>| {
+            let index = match buffer {
+                CompletedInputBuffer::Dequeued(buffer) => buffer.data.index() as usize,
+                CompletedInputBuffer::Canceled(buffer) => buffer.index as usize,
+            };
+            // Dropping the camera buffer makes it available for capture again.
+            in_flight.borrow_mut().remove(&index);
+        });
+
+        let event_cb = Arc::clone(&self.event_cb);
+        let event_cb: EventCb =
+            Box::new(move |event: DecoderEvent<MmapProvider>| (*event_cb.lock().unwrap())(event));
+
+        let capture_formats = self.config.capture_formats.clone();
+        let format_changed_cb: FormatChangedCb = Box::new(
+            move |mut f: FormatBuilder, visible_rect: Rect, min_num_buffers: usize| {
+                for &pixelformat in &capture_formats {
+                    f = f.set_pixelformat(pixelformat);
+                    if f.try_apply().is_ok() && f.format().pixelformat == pixelformat {
+                        break;
+                    }
+                }
+                let format: Format = f.apply()?;
+                debug!(
+                    "Decoded format: {:?} (visible rect: {})",
+                    format, visible_rect
+                );
+
+                Ok(FormatChangedReply {
+                    provider: MmapProvider::new(&format),
+                    mem_type: MemoryType::Mmap,
+                    num_buffers: min_num_buffers,
+                })
+            },
+        );
+
+        Ok(decoder
+            .allocate_output_buffers_generic::<GenericBufferHandles>(
+                memory_type,
+                self.config.num_output_buffers,
+            )
+            .map_err(MjpegDecoderError::DecoderBuffersError)?
+            .start(input_done_cb, event_cb, format_changed_cb)?)
+    }
+
+    /// Decode the frames queued into the current decoder, if any, and stop it.
+    fn stop_decoder(&mut self) -> Result<(), MjpegDecoderError> {
+        self.header = None;
+        if let Some(decoder) = self.decoder.take() {
+            decoder.drain(true)?;
+            decoder.stop()?;
+        }
+        // The decoder is done with the camera buffers, whether it signaled it or not.
+        self.in_flight.borrow_mut().clear();
+
+        Ok(())
+    }
+}
+
+/// Tell the decoder of `device` the chroma subsampling of the next images.
+///
+/// Most decoders find it in the images themselves, and either do not have this control or only
+/// use it to report the subsampling, so failures are not errors.
+fn set_subsampling_hint(device: &Device, subsampling: JpegChromaSubsampling) {
+    let mut control = SafeExtControl::<JpegChromaSubsampling>::from_value(subsampling.into());
+    match ioctl::s_ext_ctrls(device, ioctl::CtrlWhich::Current, &mut control) {
+        Ok(()) => debug!("Chroma subsampling set to {:?}", subsampling),
+        Err(e) => debug!("Decoder did not take chroma subsampling hint: {}", e),
+    }
+}
@@ -1,34 +1,51 @@
 mod capture_thread;
+mod frame_drop;
+pub mod pool;
+
+pub use frame_drop::FrameDropPolicy;
 
 use crate::{
     bindings,
+    controls::{
+        codec::{VideoDecDisplayDelay, VideoDecDisplayDelayEnable},
+        set_control,
+    },
     device::{
         poller::{DeviceEvent, PollError, PollEvent, Poller, Waker},
         queue::{
             direction::{Capture, Output},
             handles_provider::HandlesProvider,
             BuffersAllocated, CreateQueueError, FormatBuilder, GetCaptureBufferByIndex,
-            GetFreeBufferError, GetFreeCaptureBuffer, GetFreeOutputBuffer, OutputQueueableProvider,
-            Queue, QueueInit, RequestBuffersError,
+            GetFreeBufferError, GetFreeCaptureBuffer, GetFreeOutputBuffer, GetOutputBufferByIndex,
+            OutputQueueableProvider, Queue, QueueInit, RequestBuffersError,
         },
         AllocatedQueue, Device, DeviceConfig, DeviceOpenError, Stream, TryDequeue,
     },
+    error::AsErrno,
     ioctl::{
-        self, subscribe_event, BufferCapabilities, DqBufError, FormatFlags, StreamOnError,
-        V4l2BufferFromError,
+        self, subscribe_event, BufferCapabilities, DqBufError, FormatFlags, FrmSizeTypes,
+        StreamOnError, V4l2BufferFromError,
     },
     memory::{BufferHandles, PrimitiveBufferHandles},
+    stats::{CodecStats, StatsRecorder},
+    watchdog::{RecoverError, Watchdog},
+    Format, PixelFormat, QueueType,
 };
 
 use capture_thread::CaptureThread;
-use log::{debug, error, info, trace};
+use log::{debug, error, info, trace, warn};
+use nix::errno::Errno;
 use std::{
-    convert::TryFrom,
+    convert::{Infallible, TryFrom},
     io,
     path::Path,
-    sync::{atomic::AtomicUsize, mpsc, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
     task::Wake,
     thread::JoinHandle,
+    time::Duration,
 };
 use thiserror::Error;
 
@@ -42,9 +59,17 @@ pub struct Decoder<S: DecoderState> {
     state: S,
 }
 
+impl<S: DecoderState> Decoder<S> {
+    /// Returns the device this decoder operates on, e.g. to export its CAPTURE buffers.
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+}
+
 pub struct AwaitingOutputFormat {
     output_queue: Queue<Output, QueueInit>,
     capture_queue: Queue<Capture, QueueInit>,
+    stats: Arc<StatsRecorder>,
 }
 impl DecoderState for AwaitingOutputFormat {}
 
@@ -58,15 +83,23 @@ pub enum DecoderOpenError {
     NotAStatefulDecoder,
 }
 
+impl AsErrno for DecoderOpenError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            DecoderOpenError::DeviceOpenError(e) => e.errno(),
+            DecoderOpenError::CreateQueueError(e) => e.errno(),
+            DecoderOpenError::NotAStatefulDecoder => None,
+        }
+    }
+}
+
 impl Decoder<AwaitingOutputFormat> {
     pub fn open(path: &Path) -> Result<Self, DecoderOpenError> {
-        let config = DeviceConfig::new().non_blocking_dqbuf();
-        let device = Arc::new(Device::open(path, config)?);
+        let decoder = Self::open_unchecked(path)?;
+        let output_queue = &decoder.state.output_queue;
+        let capture_queue = &decoder.state.capture_queue;
 
         // Check that the device is indeed a stateful decoder.
-        let capture_queue = Queue::get_capture_mplane_queue(device.clone())?;
-        let output_queue = Queue::get_output_mplane_queue(device.clone())?;
-
         // On a decoder, the OUTPUT formats are compressed, but the CAPTURE ones are not.
         // Return an error if our device does not satisfy these conditions.
         output_queue
@@ -89,35 +122,178 @@ impl Decoder<AwaitingOutputFormat> {
             return Err(DecoderOpenError::NotAStatefulDecoder);
         }
 
+        Ok(decoder)
+    }
+
+    /// Open the device at `path` and obtain its queues, without checking that it is a stateful
+    /// decoder.
+    fn open_unchecked(path: &Path) -> Result<Self, DecoderOpenError> {
+        let config = DeviceConfig::new().non_blocking_dqbuf();
+        let device = Arc::new(Device::open(path, config)?);
+
+        let capture_queue = Queue::get_capture_mplane_queue(device.clone())?;
+        let output_queue = Queue::get_output_mplane_queue(device.clone())?;
+
         Ok(Decoder {
             device,
             state: AwaitingOutputFormat {
                 output_queue,
                 capture_queue,
+                stats: Arc::new(StatsRecorder::new()),
             },
         })
     }
 
+    /// Check that the decoder supports streams using the `pixel_format` codec at a resolution of
+    /// `width`x`height`.
+    ///
+    /// Drivers usually substitute unsupported OUTPUT formats with a supported one instead of
+    /// failing, so calling this before [`Decoder::set_output_format`] allows to report an
+    /// unsupported stream precisely.
+    pub fn check_output_format(
+        &self,
+        pixel_format: PixelFormat,
+        width: u32,
+        height: u32,
+    ) -> Result<(), DecodeSetupError> {
+        if !self
+            .state
+            .output_queue
+            .format_iter()
+            .any(|fmt| fmt.pixelformat == pixel_format)
+        {
+            return Err(DecodeSetupError::UnsupportedCodec(pixel_format));
+        }
+
+        check_resolution(&self.device, pixel_format, width, height)
+    }
+
+    /// Set the format of the OUTPUT queue using `f`.
+    ///
+    /// Failures caused by the decoder not supporting the stream, i.e. the driver rejecting the
+    /// format, substituting another codec for the requested one or not supporting the requested
+    /// resolution, do not make this method fail. They are reported through the event callback
+    /// as a [`DecoderEvent::SetupError`] once the decoder is started, like the setup errors
+    /// detected while decoding.
     pub fn set_output_format<F>(mut self, f: F) -> anyhow::Result<Decoder<AwaitingOutputBuffers>>
     where
         F: FnOnce(FormatBuilder) -> anyhow::Result<()>,
     {
-        let builder = self.state.output_queue.change_format()?;
-        f(builder)?;
+        let mut requested = None;
+        let builder = self
+            .state
+            .output_queue
+            .change_format()?
+            .record_requested(&mut requested);
+        let setup_error = match f(builder) {
+            Ok(()) => None,
+            Err(e) => match e.downcast::<ioctl::SFmtError>() {
+                Ok(e) => Some(DecodeSetupError::DriverError {
+                    errno: e.into(),
+                    stage: DecodeSetupStage::OutputFormat,
+                }),
+                Err(e) => return Err(e),
+            },
+        };
+
+        let format: Format = ioctl::g_fmt(&*self.device, self.state.output_queue.get_type())?;
+        let setup_error = setup_error.or_else(|| {
+            check_requested_format(&self.device, requested.as_ref().unwrap_or(&format), &format)
+                .err()
+        });
 
         Ok(Decoder {
             device: self.device,
             state: AwaitingOutputBuffers {
                 output_queue: self.state.output_queue,
                 capture_queue: self.state.capture_queue,
+                stats: self.state.stats,
+                setup_error,
             },
         })
     }
 }
 
+/// Returns the largest width and height among `frame_sizes`, as returned by
+/// `VIDIOC_ENUM_FRAMESIZES`.
+fn max_frame_size(
+    frame_sizes: impl IntoIterator<Item = bindings::v4l2_frmsizeenum>,
+) -> Option<(u32, u32)> {
+    frame_sizes
+        .into_iter()
+        .filter_map(|frame_size| match frame_size.size()? {
+            FrmSizeTypes::Discrete(size) => Some((size.width, size.height)),
+            FrmSizeTypes::StepWise(size) => Some((size.max_width, size.max_height)),
+        })
+        .reduce(|a, b| (a.0.max(b.0), a.1.max(b.1)))
+}
+
+/// Check that `width`x`height` does not exceed the maximum resolution reported by `device` for the
+/// OUTPUT `pixel_format`. The check passes if the driver does not report its limits.
+fn check_resolution(
+    device: &Device,
+    pixel_format: PixelFormat,
+    width: u32,
+    height: u32,
+) -> Result<(), DecodeSetupError> {
+    let max = max_frame_size(
+        (0..).map_while(|index| ioctl::enum_frame_sizes(device, index, pixel_format).ok()),
+    );
+
+    match max {
+        Some((max_width, max_height)) if width > max_width || height > max_height => {
+            Err(DecodeSetupError::UnsupportedResolution { max })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check the OUTPUT format `requested` by the client against the one `applied` by `device`.
+///
+/// Drivers usually substitute unsupported codecs and clamp unsupported resolutions instead of
+/// failing, so the requested format is what tells whether the stream is supported.
+fn check_requested_format(
+    device: &Device,
+    requested: &Format,
+    applied: &Format,
+) -> Result<(), DecodeSetupError> {
+    if requested.pixelformat != applied.pixelformat {
+        return Err(DecodeSetupError::UnsupportedCodec(requested.pixelformat));
+    }
+
+    check_resolution(
+        device,
+        applied.pixelformat,
+        requested.width.max(applied.width),
+        requested.height.max(applied.height),
+    )
+}
+
+/// Replace `error` with [`DecodeSetupError::UnsupportedResolution`] if the current format of the
+/// OUTPUT queue `output_queue` exceeds the limits of `device`, as this is the most likely cause of
+/// the failure.
+fn diagnose_setup_error(
+    device: &Device,
+    output_queue: QueueType,
+    error: DecodeSetupError,
+) -> DecodeSetupError {
+    let format: Format = match ioctl::g_fmt(device, output_queue) {
+        Ok(format) => format,
+        Err(_) => return error,
+    };
+
+    match check_resolution(device, format.pixelformat, format.width, format.height) {
+        Err(e) => e,
+        Ok(()) => error,
+    }
+}
+
 pub struct AwaitingOutputBuffers {
     output_queue: Queue<Output, QueueInit>,
     capture_queue: Queue<Capture, QueueInit>,
+    stats: Arc<StatsRecorder>,
+    /// Setup error detected while setting the OUTPUT format, reported when the decoder starts.
+    setup_error: Option<DecodeSetupError>,
 }
 impl DecoderState for AwaitingOutputBuffers {}
 
@@ -135,7 +311,13 @@ impl Decoder<AwaitingOutputBuffers> {
                     .output_queue
                     .request_buffers_generic::<OP>(memory_type, num_buffers as u32)?,
                 capture_queue: self.state.capture_queue,
+                stats: self.state.stats,
+                setup_error: self.state.setup_error,
                 poll_wakeups_counter: None,
+                low_latency: false,
+                source_change_timeout: None,
+                frame_drop_policy: None,
+                watchdog_timeout: None,
             },
         })
     }
@@ -151,37 +333,128 @@ impl Decoder<AwaitingOutputBuffers> {
 pub struct ReadyToDecode<OP: BufferHandles> {
     output_queue: Queue<Output, BuffersAllocated<OP>>,
     capture_queue: Queue<Capture, QueueInit>,
+    stats: Arc<StatsRecorder>,
+    setup_error: Option<DecodeSetupError>,
     poll_wakeups_counter: Option<Arc<AtomicUsize>>,
+    low_latency: bool,
+    source_change_timeout: Option<Duration>,
+    frame_drop_policy: Option<FrameDropPolicy>,
+    watchdog_timeout: Option<Duration>,
 }
 impl<OP: BufferHandles> DecoderState for ReadyToDecode<OP> {}
 
 #[derive(Debug, Error)]
 pub enum StartDecoderError {
     #[error("error while creating poller")]
-    CannotCreatePoller(nix::Error),
+    CannotCreatePoller(#[source] nix::Error),
     #[error("cannot subscribe to decoder event")]
     SubscribeEventError(#[from] ioctl::SubscribeEventError),
     #[error("error while enabling event")]
-    CannotEnableEvent(nix::Error),
+    CannotEnableEvent(#[source] nix::Error),
     #[error("error while creating capture thread")]
-    CannotCreateCaptureThread(io::Error),
+    CannotCreateCaptureThread(#[source] io::Error),
     #[error("error while activating capture thread")]
-    CannotStartCaptureThread(io::Error),
+    CannotStartCaptureThread(#[source] io::Error),
     #[error("error while starting the output queue")]
     StreamOnError(#[from] StreamOnError),
 }
 
+impl AsErrno for StartDecoderError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            StartDecoderError::CannotCreatePoller(e) => Some(*e),
+            StartDecoderError::SubscribeEventError(e) => e.errno(),
+            StartDecoderError::CannotEnableEvent(e) => Some(*e),
+            StartDecoderError::CannotCreateCaptureThread(e) => e.errno(),
+            StartDecoderError::CannotStartCaptureThread(e) => e.errno(),
+            StartDecoderError::StreamOnError(e) => e.errno(),
+        }
+    }
+}
+
 impl<OP: BufferHandles> Decoder<ReadyToDecode<OP>> {
     pub fn set_poll_counter(mut self, poll_wakeups_counter: Arc<AtomicUsize>) -> Self {
         self.state.poll_wakeups_counter = Some(poll_wakeups_counter);
         self
     }
 
+    /// Enable or disable the low-latency mode of the decoder.
+    ///
+    /// In low-latency mode, the decoder asks the driver to output frames as soon as they are
+    /// decoded instead of waiting for them to be in display order (using
+    /// `V4L2_CID_MPEG_VIDEO_DEC_DISPLAY_DELAY`), and the format change callback receives the
+    /// minimum number of CAPTURE buffers required by the driver so the client can start streaming
+    /// with as few buffers as possible. Decoded frames are delivered to the client as soon as they
+    /// are dequeued, and the frame drop policy, if any, is ignored.
+    ///
+    /// This reduces the time until the first frame is produced (see
+    /// [`CodecStats::first_frame_latency`]), but the CAPTURE queue is more likely to be starved of
+    /// buffers when the client is slow to return frames, e.g. under jitter. Drivers that do not
+    /// support the display delay controls will still decode, only without this optimization.
+    pub fn set_low_latency(mut self, low_latency: bool) -> Self {
+        self.state.low_latency = low_latency;
+        self
+    }
+
+    /// Emit a [`DecodeSetupError::NoSourceChange`] event if the driver consumes OUTPUT buffers
+    /// but does not report the resolution of the stream within `timeout`.
+    ///
+    /// Drivers often silently ignore streams they cannot decode, so without this the client
+    /// would wait forever for the first frame.
+    pub fn set_source_change_timeout(mut self, timeout: Duration) -> Self {
+        self.state.source_change_timeout = Some(timeout);
+        self
+    }
+
+    /// Drop late decoded frames according to `policy` instead of letting them accumulate when
+    /// the client does not return them fast enough.
+    ///
+    /// Dropped frames are counted in [`CodecStats::frames_dropped`], and the client is notified
+    /// with [`DecoderEvent::FrameDropStarted`] and [`DecoderEvent::FrameDropStopped`] events.
+    /// Frames are always delivered by default.
+    pub fn set_frame_drop_policy(mut self, policy: FrameDropPolicy) -> Self {
+        self.state.frame_drop_policy = Some(policy);
+        self
+    }
+
+    /// Emit a [`DecoderEvent::Stalled`] event if the driver does not make any progress for
+    /// `timeout` while encoded buffers are pending, e.g. because its firmware crashed.
+    ///
+    /// Time during which the driver cannot be expected to make progress, i.e. when no encoded
+    /// buffer is queued, the client holds all the decoded frames or the decoder is paused, is not
+    /// counted. See [`Watchdog`] for details.
+    pub fn set_watchdog(mut self, timeout: Duration) -> Self {
+        self.state.watchdog_timeout = Some(timeout);
+        self
+    }
+
+    /// Disable the display delay of the driver, so frames are output as soon as they are decoded.
+    ///
+    /// Returns `false` if the driver does not support the display delay controls.
+    fn disable_display_delay(&self) -> bool {
+        let res = set_control::<VideoDecDisplayDelayEnable>(
+            &self.device,
+            VideoDecDisplayDelayEnable(true),
+        )
+        .and_then(|()| set_control::<VideoDecDisplayDelay>(&self.device, VideoDecDisplayDelay(0)));
+
+        match res {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(
+                    "Cannot disable display delay, decoding with default latency: {}",
+                    e
+                );
+                false
+            }
+        }
+    }
+
     #[allow(clippy::type_complexity)]
     pub fn start<P, InputDoneCb, DecoderEventCb, FormatChangedCb>(
-        self,
+        mut self,
         input_done_cb: InputDoneCb,
-        decoder_event_cb: DecoderEventCb,
+        mut decoder_event_cb: DecoderEventCb,
         set_capture_format_cb: FormatChangedCb,
     ) -> Result<
         Decoder<Decoding<OP, P, InputDoneCb, DecoderEventCb, FormatChangedCb>>,
@@ -195,6 +468,14 @@ impl<OP: BufferHandles> Decoder<ReadyToDecode<OP>> {
         for<'a> Queue<Capture, BuffersAllocated<P::HandleType>>:
             GetFreeCaptureBuffer<'a, P::HandleType> + GetCaptureBufferByIndex<'a, P::HandleType>,
     {
+        if let Some(error) = self.state.setup_error.take() {
+            decoder_event_cb(DecoderEvent::SetupError(error));
+        }
+
+        if self.state.low_latency && self.disable_display_delay() {
+            debug!("Display delay disabled");
+        }
+
         // We are interested in all resolution change events for the current input (normally 0).
         subscribe_event(
             &*self.device,
@@ -210,6 +491,9 @@ impl<OP: BufferHandles> Decoder<ReadyToDecode<OP>> {
 
         let (command_sender, command_receiver) = mpsc::channel::<DecoderCommand>();
         let (response_sender, response_receiver) = mpsc::channel::<CaptureThreadResponse>();
+        let stats = self.state.stats;
+        // The first frame latency is measured from the moment the decoder starts.
+        stats.restart_clock();
 
         let mut decoder_thread = CaptureThread::new(
             &self.device,
@@ -218,6 +502,13 @@ impl<OP: BufferHandles> Decoder<ReadyToDecode<OP>> {
             set_capture_format_cb,
             command_receiver,
             response_sender,
+            Arc::clone(&stats),
+            self.state.low_latency,
+            self.state.source_change_timeout,
+            self.state.frame_drop_policy,
+            self.state.watchdog_timeout.map(Watchdog::new),
+            self.state.output_queue.buffer_stats(),
+            self.state.output_queue.get_type(),
         )
         .map_err(StartDecoderError::CannotCreateCaptureThread)?;
 
@@ -244,6 +535,8 @@ impl<OP: BufferHandles> Decoder<ReadyToDecode<OP>> {
                 command_waker,
                 command_sender,
                 response_receiver,
+                stats,
+                software_paused: AtomicBool::new(false),
                 handle,
             },
         })
@@ -254,6 +547,9 @@ impl<OP: BufferHandles> Decoder<ReadyToDecode<OP>> {
 enum DecoderCommand {
     Drain(bool),
     Flush,
+    Pause,
+    Resume,
+    Recover,
     Stop,
 }
 
@@ -261,6 +557,30 @@ enum DecoderCommand {
 enum CaptureThreadResponse {
     DrainDone(Result<bool, DrainError>),
     FlushDone(anyhow::Result<()>),
+    PauseDone(Result<PauseStrategy, PauseError>),
+    ResumeDone(Result<(), ResumeError>),
+    RecoverDone(Result<(), RecoverError>),
+}
+
+/// How the decoder has been paused by [`Decoder::pause`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseStrategy {
+    /// The driver supports `V4L2_DEC_CMD_PAUSE` and has been asked to stop decoding.
+    Hardware,
+    /// The driver does not support pausing, so the decoder stops dequeuing decoded frames and
+    /// does not provide new OUTPUT buffers until it is resumed.
+    Software,
+}
+
+impl PauseStrategy {
+    /// Select the strategy to use from the result of probing `V4L2_DEC_CMD_PAUSE` using
+    /// `VIDIOC_TRY_DECODER_CMD`.
+    fn from_probe<CE: std::fmt::Debug>(probe: &ioctl::DecoderCmdResult<(), CE>) -> Self {
+        match probe {
+            Ok(()) => PauseStrategy::Hardware,
+            Err(_) => PauseStrategy::Software,
+        }
+    }
 }
 
 pub struct Decoding<OP, P, InputDoneCb, DecoderEventCb, FormatChangedCb>
@@ -279,6 +599,10 @@ where
     command_sender: mpsc::Sender<DecoderCommand>,
     response_receiver: mpsc::Receiver<CaptureThreadResponse>,
 
+    stats: Arc<StatsRecorder>,
+    // Whether the decoder is paused using `PauseStrategy::Software`.
+    software_paused: AtomicBool,
+
     handle: JoinHandle<CaptureThread<P, DecoderEventCb, FormatChangedCb>>,
 }
 impl<OP, P, InputDoneCb, DecoderEventCb, FormatChangedCb> DecoderState
@@ -298,6 +622,17 @@ pub enum SendCommandError {
     SendError,
 }
 
+impl AsErrno for SendCommandError {
+    fn errno(&self) -> Option<Errno> {
+        None
+    }
+}
+
+/// Returns the first `Errno` found in the chain of `err`, as reported by the capture thread.
+fn capture_thread_errno(err: &anyhow::Error) -> Option<Errno> {
+    err.chain().find_map(|e| e.downcast_ref::<Errno>().copied())
+}
+
 #[derive(Debug, Error)]
 pub enum StopError {
     #[error("error while sending the stop command to the capture thread")]
@@ -308,10 +643,22 @@ pub enum StopError {
     Streamoff(#[from] ioctl::StreamOffError),
 }
 
+impl AsErrno for StopError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            StopError::SendCommand(e) => e.errno(),
+            StopError::Join => None,
+            StopError::Streamoff(e) => e.errno(),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum DrainError {
     #[error("cannot drain now: output format not yet determined")]
     TryAgain,
+    #[error("cannot drain while the decoder is paused")]
+    Paused,
     #[error("error while sending the flush command to the capture thread")]
     SendCommand(#[from] SendCommandError),
     #[error("error while waiting for the decoder thread to drain")]
@@ -320,6 +667,18 @@ pub enum DrainError {
     CaptureThreadError(anyhow::Error),
 }
 
+impl AsErrno for DrainError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            DrainError::TryAgain => None,
+            DrainError::Paused => None,
+            DrainError::SendCommand(e) => e.errno(),
+            DrainError::RecvError(_) => None,
+            DrainError::CaptureThreadError(e) => capture_thread_errno(e),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum FlushError {
     #[error("error while stopping the OUTPUT queue")]
@@ -334,6 +693,74 @@ pub enum FlushError {
     StreamonError(#[from] ioctl::StreamOnError),
 }
 
+impl AsErrno for FlushError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            FlushError::StreamoffError(e) => e.errno(),
+            FlushError::SendCommand(e) => e.errno(),
+            FlushError::RecvError(_) => None,
+            FlushError::CaptureThreadError(e) => capture_thread_errno(e),
+            FlushError::StreamonError(e) => e.errno(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PauseError {
+    #[error("cannot pause now: output format not yet determined")]
+    TryAgain,
+    #[error("cannot pause while a drain is in progress")]
+    DrainInProgress,
+    #[error("decoder is already paused")]
+    AlreadyPaused,
+    #[error("error while sending the pause command to the capture thread")]
+    SendCommand(#[from] SendCommandError),
+    #[error("error while waiting for the decoder thread to pause")]
+    RecvError(#[from] mpsc::RecvError),
+    #[error("error while sending the PAUSE command to the driver")]
+    DecoderCmd(ioctl::DecoderCmdError<Infallible>),
+    #[error("error while pausing on the capture thread")]
+    CaptureThreadError(anyhow::Error),
+}
+
+impl AsErrno for PauseError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            PauseError::TryAgain | PauseError::DrainInProgress | PauseError::AlreadyPaused => None,
+            PauseError::SendCommand(e) => e.errno(),
+            PauseError::RecvError(_) => None,
+            PauseError::DecoderCmd(e) => e.errno(),
+            PauseError::CaptureThreadError(e) => capture_thread_errno(e),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ResumeError {
+    #[error("decoder is not paused")]
+    NotPaused,
+    #[error("error while sending the resume command to the capture thread")]
+    SendCommand(#[from] SendCommandError),
+    #[error("error while waiting for the decoder thread to resume")]
+    RecvError(#[from] mpsc::RecvError),
+    #[error("error while sending the RESUME command to the driver")]
+    DecoderCmd(ioctl::DecoderCmdError<Infallible>),
+    #[error("error while resuming on the capture thread")]
+    CaptureThreadError(anyhow::Error),
+}
+
+impl AsErrno for ResumeError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            ResumeError::NotPaused => None,
+            ResumeError::SendCommand(e) => e.errno(),
+            ResumeError::RecvError(_) => None,
+            ResumeError::DecoderCmd(e) => e.errno(),
+            ResumeError::CaptureThreadError(e) => capture_thread_errno(e),
+        }
+    }
+}
+
 #[allow(type_alias_bounds)]
 type CanceledBuffers<OP: BufferHandles> =
     Vec<<Queue<Output, BuffersAllocated<OP>> as Stream>::Canceled>;
@@ -351,6 +778,19 @@ where
         self.state.output_queue.num_buffers()
     }
 
+    /// Returns a snapshot of the runtime statistics of the decoder.
+    pub fn stats(&self) -> CodecStats {
+        self.state
+            .stats
+            .set_output_queue_depth(self.state.output_queue.num_queued_buffers());
+        self.state.stats.snapshot()
+    }
+
+    /// Reset the runtime statistics of the decoder.
+    pub fn reset_stats(&self) {
+        self.state.stats.reset()
+    }
+
     /// Send a command to the capture thread.
     fn send_command(&self, command: DecoderCommand) -> Result<(), SendCommandError> {
         trace!("Sending command: {:?}", command);
@@ -413,6 +853,9 @@ where
     /// ongoing. They will be processed in order and their frames will come
     /// after the ones still in the pipeline. For a way to cancel all the
     /// pending jobs, see the [`Decoder::flush`] method.
+    ///
+    /// A paused decoder cannot complete a drain, so [`DrainError::Paused`] is
+    /// returned until [`Decoder::resume`] is called.
     pub fn drain(&self, blocking: bool) -> Result<bool, DrainError> {
         debug!("Drain requested");
         self.send_command(DecoderCommand::Drain(blocking))?;
@@ -487,6 +930,75 @@ where
         Ok(())
     }
 
+    /// Pause the decoder.
+    ///
+    /// If the driver supports `V4L2_DEC_CMD_PAUSE` (as probed with `VIDIOC_TRY_DECODER_CMD`), it
+    /// is used to stop decoding. Otherwise the decoder falls back to pausing on its side. In both
+    /// cases decoded frames stop being dequeued, and with the software strategy
+    /// [`Decoder::get_buffer`] also returns [`GetBufferError::Paused`] so no new encoded data is
+    /// queued. Frames decoded while paused are kept by the driver and emitted after
+    /// [`Decoder::resume`] is called, so none is lost.
+    ///
+    /// Pausing is rejected while the initial format is not determined yet, and while a drain is in
+    /// progress. Resolution changes are processed atomically by the capture thread, so a pause
+    /// request received during one takes effect once it is completed.
+    ///
+    /// Returns the strategy that has been used to pause the decoder.
+    pub fn pause(&self) -> Result<PauseStrategy, PauseError> {
+        debug!("Pause requested");
+        self.send_command(DecoderCommand::Pause)?;
+
+        match self.state.response_receiver.recv()? {
+            CaptureThreadResponse::PauseDone(response) => {
+                if let Ok(PauseStrategy::Software) = response {
+                    self.state.software_paused.store(true, Ordering::Release);
+                }
+                response
+            }
+            r => {
+                error!(
+                    "Unexpected capture thread response received while pausing: {:?}",
+                    r
+                );
+                Err(PauseError::CaptureThreadError(anyhow::anyhow!(
+                    "Unexpected response while pausing"
+                )))
+            }
+        }
+    }
+
+    /// Resume a decoder previously paused with [`Decoder::pause`].
+    ///
+    /// Frames decoded while the decoder was paused are emitted after this method returns.
+    pub fn resume(&self) -> Result<(), ResumeError> {
+        debug!("Resume requested");
+        self.send_command(DecoderCommand::Resume)?;
+
+        match self.state.response_receiver.recv()? {
+            CaptureThreadResponse::ResumeDone(response) => {
+                if response.is_ok() {
+                    self.state.software_paused.store(false, Ordering::Release);
+                }
+                response
+            }
+            r => {
+                error!(
+                    "Unexpected capture thread response received while resuming: {:?}",
+                    r
+                );
+                Err(ResumeError::CaptureThreadError(anyhow::anyhow!(
+                    "Unexpected response while resuming"
+                )))
+            }
+        }
+    }
+
+    /// Returns `true` if the decoder has been paused using [`PauseStrategy::Software`], in which
+    /// case no new encoded buffers should be queued.
+    fn is_software_paused(&self) -> bool {
+        self.state.software_paused.load(Ordering::Acquire)
+    }
+
     /// Attempts to dequeue and release output buffers that the driver is done with.
     fn dequeue_output_buffers(&self) -> Result<(), DqBufError<V4l2BufferFromError>> {
         let output_queue = &self.state.output_queue;
@@ -494,6 +1006,7 @@ where
         while output_queue.num_queued_buffers() > 0 {
             match output_queue.try_dequeue() {
                 Ok(buf) => {
+                    self.state.stats.output_dequeued(&buf.data, buf.queued_at());
                     (self.state.input_done_cb)(CompletedInputBuffer::Dequeued(buf));
                 }
                 Err(DqBufError::IoctlError(ioctl::DqBufIoctlError::NotReady)) => break,
@@ -522,6 +1035,64 @@ where
     }
 }
 
+impl<OP, P, InputDoneCb, DecoderEventCb, FormatChangedCb>
+    Decoder<Decoding<OP, P, InputDoneCb, DecoderEventCb, FormatChangedCb>>
+where
+    OP: BufferHandles,
+    P: HandlesProvider,
+    InputDoneCb: InputDoneCallback<OP>,
+    DecoderEventCb: DecoderEventCallback<P>,
+    FormatChangedCb: FormatChangedCallback<P>,
+    for<'a> Queue<Output, BuffersAllocated<OP>>: GetOutputBufferByIndex<'a, OP>,
+{
+    /// Attempt to get the decoder going again after a [`DecoderEvent::Stalled`] event.
+    ///
+    /// Both queues are stopped and restarted, and the encoded buffers that were pending are
+    /// queued again with the same content. Decoding resumes from them, so artifacts may be
+    /// visible until the next key frame. Buffers that cannot be queued again are returned as
+    /// canceled through the input done callback. A non-blocking drain in progress is aborted and
+    /// must be requested again. This must not be called during a blocking drain.
+    ///
+    /// If the decoder already stalled again after a recovery without making any progress, nothing
+    /// is done and [`RecoverError::ReopenRequired`] is returned: the device should then be closed
+    /// and opened again.
+    pub fn recover(&self) -> Result<(), RecoverError> {
+        debug!("Recovery requested");
+        self.send_command(DecoderCommand::Recover)
+            .map_err(|_| RecoverError::CaptureThread)?;
+
+        match self
+            .state
+            .response_receiver
+            .recv()
+            .map_err(|_| RecoverError::CaptureThread)?
+        {
+            CaptureThreadResponse::RecoverDone(response) => response?,
+            r => {
+                error!(
+                    "Unexpected capture thread response received while recovering: {:?}",
+                    r
+                );
+                return Err(RecoverError::CaptureThread);
+            }
+        }
+
+        let canceled_buffers = self.state.output_queue.stream_off()?;
+        if let Err(e) = self.state.output_queue.stream_on() {
+            for buffer in canceled_buffers {
+                (self.state.input_done_cb)(CompletedInputBuffer::Canceled(buffer));
+            }
+            return Err(e.into());
+        }
+        for buffer in self.state.output_queue.requeue_canceled(canceled_buffers) {
+            (self.state.input_done_cb)(CompletedInputBuffer::Canceled(buffer));
+        }
+
+        debug!("Recovery complete");
+        Ok(())
+    }
+}
+
 impl<'a, OP, P, InputDoneCb, DecoderEventCb, FormatChangedCb> OutputQueueableProvider<'a, OP>
     for Decoder<Decoding<OP, P, InputDoneCb, DecoderEventCb, FormatChangedCb>>
 where
@@ -544,6 +1115,19 @@ pub enum GetBufferError {
     PollError(#[from] PollError),
     #[error("error while obtaining buffer")]
     GetFreeBufferError(#[from] GetFreeBufferError),
+    #[error("decoder is paused")]
+    Paused,
+}
+
+impl AsErrno for GetBufferError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GetBufferError::DequeueError(e) => e.errno(),
+            GetBufferError::PollError(e) => e.errno(),
+            GetBufferError::GetFreeBufferError(e) => e.errno(),
+            GetBufferError::Paused => None,
+        }
+    }
 }
 
 /// Let the decoder provide the buffers from the OUTPUT queue.
@@ -565,6 +1149,9 @@ where
     /// are currently queued.
     fn try_get_free_buffer(&'a self) -> Result<Self::Queueable, GetBufferError> {
         self.dequeue_output_buffers()?;
+        if self.is_software_paused() {
+            return Err(GetBufferError::Paused);
+        }
         Ok(self.state.output_queue.try_get_free_buffer()?)
     }
 }
@@ -594,6 +1181,11 @@ where
     pub fn get_buffer(
         &'a mut self,
     ) -> Result<<Self as OutputQueueableProvider<'a, OP>>::Queueable, GetBufferError> {
+        // Waiting for a buffer would block forever if the decoder cannot produce frames.
+        if self.is_software_paused() {
+            return Err(GetBufferError::Paused);
+        }
+
         let output_queue = &self.state.output_queue;
 
         // If all our buffers are queued, wait until we can dequeue some.
@@ -629,3 +1221,101 @@ where
         self.dequeue_output_buffers()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use std::path::Path;
+
+    use super::{max_frame_size, Decoder, PauseStrategy, StartDecoderError};
+    use crate::{
+        bindings,
+        error::{AsErrno, ResultExt},
+        ioctl::{self, DecoderCmdIoctlError, IoctlConvertError},
+    };
+
+    #[test]
+    fn test_open_error_errno() {
+        // `/dev/null` can be opened, but the QUERYCAP ioctl issued right after fails.
+        let err = Decoder::open(Path::new("/dev/null"))
+            .map(|_| ())
+            .context("opening decoder on /dev/null")
+            .unwrap_err();
+
+        assert_eq!(err.errno(), Some(Errno::ENOTTY));
+        assert!(matches!(
+            err.downcast_ref::<ioctl::QueryCapError>(),
+            Some(ioctl::QueryCapError::IoctlError(Errno::ENOTTY))
+        ));
+        assert!(err
+            .to_string()
+            .starts_with("opening decoder on /dev/null: "));
+    }
+
+    #[test]
+    fn test_start_error_errno() {
+        let err = StartDecoderError::from(ioctl::StreamOnError::IoctlError(Errno::ENOMEM));
+        assert_eq!(err.errno(), Some(Errno::ENOMEM));
+
+        let err = StartDecoderError::CannotCreateCaptureThread(Errno::EAGAIN.into());
+        assert_eq!(err.errno(), Some(Errno::EAGAIN));
+    }
+
+    #[test]
+    fn test_pause_strategy() {
+        // The driver accepts the PAUSE command, so we use it.
+        assert_eq!(
+            PauseStrategy::from_probe::<()>(&Ok(())),
+            PauseStrategy::Hardware
+        );
+
+        // The driver rejects it, so we fall back to pausing on our side.
+        assert_eq!(
+            PauseStrategy::from_probe::<()>(&Err(IoctlConvertError::IoctlError(
+                DecoderCmdIoctlError::UnsupportedCommand
+            ))),
+            PauseStrategy::Software
+        );
+        assert_eq!(
+            PauseStrategy::from_probe::<()>(&Err(IoctlConvertError::IoctlError(
+                DecoderCmdIoctlError::from(Errno::ENOTTY)
+            ))),
+            PauseStrategy::Software
+        );
+    }
+
+    #[test]
+    fn test_max_frame_size() {
+        let discrete = |width, height| bindings::v4l2_frmsizeenum {
+            type_: bindings::v4l2_frmsizetypes_V4L2_FRMSIZE_TYPE_DISCRETE,
+            __bindgen_anon_1: bindings::v4l2_frmsizeenum__bindgen_ty_1 {
+                discrete: bindings::v4l2_frmsize_discrete { width, height },
+            },
+            ..Default::default()
+        };
+        let stepwise = bindings::v4l2_frmsizeenum {
+            type_: bindings::v4l2_frmsizetypes_V4L2_FRMSIZE_TYPE_STEPWISE,
+            __bindgen_anon_1: bindings::v4l2_frmsizeenum__bindgen_ty_1 {
+                stepwise: bindings::v4l2_frmsize_stepwise {
+                    min_width: 16,
+                    max_width: 4096,
+                    step_width: 16,
+                    min_height: 16,
+                    max_height: 2304,
+                    step_height: 16,
+                },
+            },
+            ..Default::default()
+        };
+
+        // The driver does not report its limits.
+        assert_eq!(max_frame_size([]), None);
+        // Width and height limits can come from different discrete sizes.
+        assert_eq!(
+            max_frame_size([discrete(1920, 1080), discrete(1080, 1920)]),
+            Some((1920, 1920))
+        );
+        assert_eq!(max_frame_size([stepwise]), Some((4096, 2304)));
+    }
+}
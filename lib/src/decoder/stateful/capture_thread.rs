@@ -1,26 +1,40 @@
 use crate::{
+    bindings,
+    controls::{user::MinBuffersForCapture, SafeExtControl},
     decoder::{
-        stateful::{CaptureThreadResponse, DecoderCommand, DecoderEvent, DrainError},
-        DecoderEventCallback, FormatChangedCallback, FormatChangedReply,
+        stateful::{
+            diagnose_setup_error,
+            frame_drop::{FrameBacklog, FrameDropPolicy},
+            CaptureThreadResponse, DecoderCommand, DecoderEvent, DrainError, PauseError,
+            PauseStrategy, ResumeError,
+        },
+        DecodeSetupError, DecodeSetupStage, DecoderEventCallback, FormatChangedCallback,
+        FormatChangedReply,
     },
     device::{
         poller::{DeviceEvent, PollEvent, Poller, Waker},
         queue::{
-            self, direction::Capture, handles_provider::HandlesProvider, BuffersAllocated,
-            CaptureQueueable, GetCaptureBufferByIndex, GetFreeCaptureBuffer, Queue, QueueInit,
+            self, buffer::BufferStats, direction::Capture, dqbuf::DqBuffer,
+            handles_provider::HandlesProvider, BuffersAllocated, CaptureQueueable,
+            GetCaptureBufferByIndex, GetFreeCaptureBuffer, Queue, QueueInit,
         },
         AllocatedQueue, Device, Stream, TryDequeue,
     },
     ioctl::{self, SelectionTarget},
+    stats::StatsRecorder,
+    watchdog::{RecoverError, Watchdog},
+    Format, QueueType, Rect,
 };
 
 use std::{
     io,
-    sync::{mpsc, Arc},
+    sync::{atomic::Ordering, mpsc, Arc},
     task::Wake,
+    time::{Duration, Instant},
 };
 
 use log::{debug, error, trace, warn};
+use nix::errno::Errno;
 use thiserror::Error;
 
 /// Check if `device` has a dynamic resolution change event pending.
@@ -45,12 +59,29 @@ fn is_drc_event_pending(device: &Device) -> Result<bool, ioctl::DqEventError> {
             ioctl::Event::SrcChangeEvent(changes) => {
                 if changes.contains(ioctl::SrcChanges::RESOLUTION) {
                     debug!("Received resolution change event");
+                    decoder_event!(changes = changes.bits(), "source change");
                     drc_pending = true;
                 }
             }
             ioctl::Event::Eos => {
                 debug!("Received EOS event");
+                decoder_event!("EOS event");
             }
+            ioctl::Event::CtrlEvent(_) | ioctl::Event::FrameSync(_) => (),
+        }
+    }
+}
+
+/// Returns the minimum number of CAPTURE buffers required by `device` to decode the current
+/// stream, or `None` if the driver does not expose this information.
+fn min_buffers_for_capture(device: &Device) -> Option<usize> {
+    let mut control = SafeExtControl::<MinBuffersForCapture>::from_value(0);
+    match ioctl::g_ext_ctrls(device, ioctl::CtrlWhich::Current, &mut control) {
+        Ok(()) if control.value() > 0 => Some(control.value() as usize),
+        Ok(()) => None,
+        Err(e) => {
+            debug!("Cannot obtain minimum number of CAPTURE buffers: {}", e);
+            None
         }
     }
 }
@@ -65,6 +96,8 @@ enum CaptureQueue<P: HandlesProvider> {
         cap_buffer_waker: Arc<Waker>,
         // TODO not super elegant...
         blocking_drain_in_progress: bool,
+        // Whether a drain (blocking or not) has been started and not completed yet.
+        drain_in_progress: bool,
     },
 }
 
@@ -88,6 +121,63 @@ where
     // Sender we use to send status messages after receiving commands from the
     // main thread.
     response_sender: mpsc::Sender<CaptureThreadResponse>,
+    // Statistics shared with the main thread.
+    stats: Arc<StatsRecorder>,
+    // Whether to start the CAPTURE queue with the minimum number of buffers.
+    low_latency: bool,
+    // Set if the decoder is currently paused, along with the way it has been paused.
+    paused: Option<PauseStrategy>,
+    // Time after which we report that the initial resolution has not been received, and the
+    // corresponding timeout.
+    source_change_deadline: Option<(Instant, Duration)>,
+    // Whether the next dequeued CAPTURE buffer will be the first one of the stream.
+    awaiting_first_frame: bool,
+    // Decoded frames not delivered to the client yet, if a frame drop policy is set.
+    backlog: Option<FrameBacklog<DqBuffer<Capture, P::HandleType>>>,
+    // Watchdog reporting stalls of the driver, if enabled.
+    watchdog: Option<Watchdog>,
+    // Buffer counters of the OUTPUT queue, used to tell whether the driver has work pending.
+    output_buffers: Arc<BufferStats>,
+    // Type of the OUTPUT queue, used to diagnose setup errors.
+    output_queue_type: QueueType,
+}
+
+/// Converts a V4L2 buffer timestamp into a duration usable to compare frames.
+fn timestamp_to_duration(timestamp: bindings::timeval) -> Duration {
+    Duration::from_secs(timestamp.tv_sec.max(0) as u64)
+        + Duration::from_micros(timestamp.tv_usec.max(0) as u64)
+}
+
+/// Deliver the frames of `backlog` that the client can take, or all of them if `force` is set,
+/// and notify the client if frames started or stopped being dropped.
+fn deliver_backlog<P, DecoderEventCb>(
+    backlog: &mut FrameBacklog<DqBuffer<Capture, P::HandleType>>,
+    event_cb: &mut DecoderEventCb,
+    force: bool,
+) where
+    P: HandlesProvider,
+    DecoderEventCb: DecoderEventCallback<P>,
+{
+    while let Some(mut frame) = backlog.pop(force) {
+        let held_frames = backlog.held_frames();
+        // Drop callbacks run in reverse order, so this runs before the CAPTURE waker is signaled.
+        frame.add_drop_callback(move |_dqbuf| {
+            held_frames.fetch_sub(1, Ordering::Relaxed);
+        });
+        event_cb(DecoderEvent::FrameDecoded(frame));
+    }
+
+    match backlog.take_dropping_change() {
+        Some(true) => {
+            warn!("Client cannot keep up, dropping decoded frames");
+            event_cb(DecoderEvent::FrameDropStarted)
+        }
+        Some(false) => {
+            debug!("Client caught up, not dropping decoded frames anymore");
+            event_cb(DecoderEvent::FrameDropStopped)
+        }
+        None => (),
+    }
 }
 
 #[derive(Debug, Error)]
@@ -135,6 +225,7 @@ where
     for<'a> Queue<Capture, BuffersAllocated<P::HandleType>>:
         GetFreeCaptureBuffer<'a, P::HandleType> + GetCaptureBufferByIndex<'a, P::HandleType>,
 {
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn new(
         device: &Arc<Device>,
         capture_queue: Queue<Capture, QueueInit>,
@@ -142,6 +233,13 @@ where
         set_capture_format_cb: FormatChangedCb,
         command_receiver: mpsc::Receiver<DecoderCommand>,
         response_sender: mpsc::Sender<CaptureThreadResponse>,
+        stats: Arc<StatsRecorder>,
+        low_latency: bool,
+        source_change_timeout: Option<Duration>,
+        frame_drop_policy: Option<FrameDropPolicy>,
+        watchdog: Option<Watchdog>,
+        output_buffers: Arc<BufferStats>,
+        output_queue_type: QueueType,
     ) -> io::Result<Self> {
         // Start by only listening to V4L2 events in order to catch the initial
         // resolution change, and to the stop waker in case the user had a
@@ -150,6 +248,16 @@ where
         poller.enable_event(DeviceEvent::V4L2Event)?;
         let command_waker = poller.add_waker(COMMAND_WAITING)?;
 
+        // In low-latency mode, frames are passed to the client as soon as they are dequeued
+        // instead of going through the backlog.
+        let frame_drop_policy = match frame_drop_policy {
+            Some(_) if low_latency => {
+                warn!("Frame drop policy ignored in low-latency mode");
+                None
+            }
+            policy => policy,
+        };
+
         let decoder_thread = CaptureThread {
             device: Arc::clone(device),
             capture_queue: CaptureQueue::AwaitingResolution { capture_queue },
@@ -159,6 +267,16 @@ where
             command_waker,
             command_receiver,
             response_sender,
+            stats,
+            low_latency,
+            paused: None,
+            source_change_deadline: source_change_timeout
+                .map(|timeout| (Instant::now() + timeout, timeout)),
+            awaiting_first_frame: false,
+            backlog: frame_drop_policy.map(FrameBacklog::new),
+            watchdog,
+            output_buffers,
+            output_queue_type,
         };
 
         Ok(decoder_thread)
@@ -178,13 +296,20 @@ where
             CaptureQueue::AwaitingResolution { .. } => {
                 Some(CaptureThreadResponse::DrainDone(Err(DrainError::TryAgain)))
             }
+            // Frames are not dequeued while paused, so the drain would never complete.
+            CaptureQueue::Decoding { .. } if self.paused.is_some() => {
+                Some(CaptureThreadResponse::DrainDone(Err(DrainError::Paused)))
+            }
             CaptureQueue::Decoding {
                 blocking_drain_in_progress,
+                drain_in_progress,
                 ..
             } => {
                 // We can receive the LAST buffer, send the STOP command
                 // and exit the loop once the buffer with the LAST tag is received.
                 ioctl::decoder_cmd::<_, ()>(&*self.device, ioctl::DecoderCmd::stop()).unwrap();
+                *drain_in_progress = true;
+                decoder_event!(blocking, "drain started");
                 if blocking {
                     // If we are blocking, we will send the answer when the drain
                     // is completed.
@@ -209,14 +334,20 @@ where
             CaptureQueue::Decoding {
                 capture_queue,
                 blocking_drain_in_progress,
+                drain_in_progress,
                 ..
             } => {
+                // Frames decoded before the flush are not to be delivered anymore.
+                if let Some(backlog) = &mut self.backlog {
+                    backlog.clear();
+                }
                 // Stream the capture queue off and back on, dropping any queued
                 // buffer, and making the decoder ready to work again if it was
                 // halted.
                 capture_queue.stream_off().unwrap();
                 capture_queue.stream_on().unwrap();
                 *blocking_drain_in_progress = false;
+                *drain_in_progress = false;
             }
         }
 
@@ -224,6 +355,129 @@ where
         self.enqueue_capture_buffers()
     }
 
+    fn pause(&mut self) {
+        trace!("Processing pause command");
+        let response = match &self.capture_queue {
+            // Resolution changes are processed synchronously, so if we are awaiting one it can
+            // only be the initial resolution.
+            CaptureQueue::AwaitingResolution { .. } => Err(PauseError::TryAgain),
+            CaptureQueue::Decoding {
+                drain_in_progress: true,
+                ..
+            } => Err(PauseError::DrainInProgress),
+            CaptureQueue::Decoding { .. } if self.paused.is_some() => {
+                Err(PauseError::AlreadyPaused)
+            }
+            CaptureQueue::Decoding { .. } => {
+                let strategy = PauseStrategy::from_probe(&ioctl::try_decoder_cmd::<_, ()>(
+                    &*self.device,
+                    ioctl::DecoderCmd::pause(),
+                ));
+                debug!("Pausing decoder using {:?} strategy", strategy);
+
+                let res = match strategy {
+                    PauseStrategy::Hardware => {
+                        ioctl::decoder_cmd::<_, ()>(&*self.device, ioctl::DecoderCmd::pause())
+                            .map_err(PauseError::DecoderCmd)
+                    }
+                    // Stopping CAPTURE dequeues is taken care of by the main loop.
+                    PauseStrategy::Software => Ok(()),
+                };
+
+                res.map(|()| {
+                    self.paused = Some(strategy);
+                    strategy
+                })
+            }
+        };
+
+        self.send_response(CaptureThreadResponse::PauseDone(response));
+    }
+
+    fn resume(&mut self) {
+        trace!("Processing resume command");
+        let response = match self.paused {
+            None => Err(ResumeError::NotPaused),
+            Some(PauseStrategy::Hardware) => {
+                ioctl::decoder_cmd::<_, ()>(&*self.device, ioctl::DecoderCmd::resume())
+                    .map_err(ResumeError::DecoderCmd)
+            }
+            Some(PauseStrategy::Software) => Ok(()),
+        };
+
+        // Only consider ourselves resumed if the driver did not fail, so the client can retry.
+        if response.is_ok() {
+            self.paused = None;
+        }
+
+        self.send_response(CaptureThreadResponse::ResumeDone(response));
+    }
+
+    fn recover(&mut self) {
+        trace!("Processing recover command");
+        let response = match (&mut self.capture_queue, &mut self.watchdog) {
+            (_, Some(watchdog)) if watchdog.recovery_failed() => {
+                warn!("Decoder stalled again after a recovery");
+                Err(RecoverError::ReopenRequired)
+            }
+            // The CAPTURE queue is not streaming yet, so only the OUTPUT queue needs restarting.
+            (CaptureQueue::AwaitingResolution { .. }, _) => Ok(()),
+            (
+                CaptureQueue::Decoding {
+                    capture_queue,
+                    blocking_drain_in_progress,
+                    drain_in_progress,
+                    ..
+                },
+                watchdog,
+            ) => {
+                // Decoded frames waiting for the client are still valid.
+                if let Some(backlog) = &mut self.backlog {
+                    deliver_backlog::<P, _>(backlog, &mut self.event_cb, true);
+                }
+                // Restarting the CAPTURE queue aborts any drain, and the canceled buffers are
+                // queued again from the provider.
+                let res = capture_queue
+                    .stream_off()
+                    .map_err(RecoverError::from)
+                    .and_then(|_| capture_queue.stream_on().map_err(RecoverError::from));
+                *blocking_drain_in_progress = false;
+                *drain_in_progress = false;
+                if let Some(watchdog) = watchdog {
+                    watchdog.recovery_started();
+                }
+                res
+            }
+        };
+
+        decoder_event!(success = response.is_ok(), "recovery");
+        self.send_response(CaptureThreadResponse::RecoverDone(response));
+        self.enqueue_capture_buffers()
+    }
+
+    /// Report a [`DecoderEvent::Stalled`] event if the driver has not made progress for too long
+    /// while it had encoded buffers to process.
+    fn check_watchdog(&mut self) {
+        let (watchdog, capture_queue) = match (&mut self.watchdog, &self.capture_queue) {
+            (Some(watchdog), CaptureQueue::Decoding { capture_queue, .. }) => {
+                (watchdog, capture_queue)
+            }
+            // The initial resolution is covered by the source change timeout.
+            _ => return,
+        };
+
+        // The driver cannot make progress without CAPTURE buffers, and does not while paused.
+        let can_progress = self.paused.is_none() && capture_queue.num_queued_buffers() > 0;
+        if let Some(stalled) = watchdog.check(self.output_buffers.num_queued(), can_progress) {
+            error!(
+                "Decoder stalled with {} OUTPUT buffers pending",
+                stalled.pending_output
+            );
+            decoder_event!(pending_output = stalled.pending_output, "stalled");
+            (self.event_cb)(DecoderEvent::Stalled(stalled));
+        }
+    }
+
     fn enqueue_capture_buffers(&mut self) {
         trace!("Queueing available CAPTURE buffers");
         let (capture_queue, provider, cap_buffer_waker) = match &mut self.capture_queue {
@@ -266,14 +520,46 @@ where
         }
     }
 
+    /// Report `error` to the client as a [`DecoderEvent::SetupError`].
+    fn setup_error(&mut self, error: DecodeSetupError) {
+        let error = diagnose_setup_error(&self.device, self.output_queue_type, error);
+        error!("Error while setting up decoder: {}", error);
+        (self.event_cb)(DecoderEvent::SetupError(error));
+    }
+
+    /// Report a [`DecodeSetupError::NoSourceChange`] error if the driver has been consuming
+    /// OUTPUT buffers without reporting the initial resolution for too long.
+    fn check_source_change_deadline(&mut self) {
+        let (_, timeout) = match self.source_change_deadline {
+            Some(deadline) if Instant::now() >= deadline.0 => deadline,
+            _ => return,
+        };
+
+        if self.stats.snapshot().frames_in == 0 {
+            // The client has not sent any data yet, so we cannot blame the driver.
+            self.source_change_deadline = Some((Instant::now() + timeout, timeout));
+        } else {
+            // Only report the error once.
+            self.source_change_deadline = None;
+            self.setup_error(DecodeSetupError::NoSourceChange { timeout });
+        }
+    }
+
     fn process_v4l2_event(mut self) -> Self {
         trace!("Processing V4L2 event");
         match self.capture_queue {
-            CaptureQueue::AwaitingResolution { .. } => {
-                if is_drc_event_pending(&self.device).unwrap() {
+            CaptureQueue::AwaitingResolution { .. } => match is_drc_event_pending(&self.device) {
+                Ok(true) => {
+                    self.source_change_deadline = None;
+                    self.awaiting_first_frame = true;
                     self = self.update_capture_format().unwrap()
                 }
-            }
+                Ok(false) => (),
+                Err(e) => self.setup_error(DecodeSetupError::DriverError {
+                    errno: e.into(),
+                    stage: DecodeSetupStage::SourceChange,
+                }),
+            },
             CaptureQueue::Decoding { .. } => unreachable!(),
         }
 
@@ -282,6 +568,7 @@ where
 
     fn update_capture_format(mut self) -> Result<Self, UpdateCaptureError> {
         debug!("Updating CAPTURE format");
+        self.stats.resolution_changed();
         // First reset the capture queue to the `Init` state if needed.
         let mut capture_queue = match self.capture_queue {
             // Initial resolution
@@ -310,6 +597,15 @@ where
                 // Deallocate the queue and return it to the `Init` state. Good
                 // as new!
                 capture_queue.stream_off()?;
+                // Drivers refuse to free buffers that are still in use, e.g. exported to another
+                // device.
+                let num_held = capture_queue.num_buffers() - capture_queue.num_free_buffers();
+                if num_held > 0 {
+                    warn!(
+                        "{} CAPTURE buffers are still held by the client, freeing them may fail",
+                        num_held
+                    );
+                }
                 capture_queue.free_buffers()?.queue
             }
         };
@@ -317,11 +613,26 @@ where
         // Now get the parameters of the new format and build our new CAPTURE
         // queue.
 
-        // TODO use the proper control to get the right value.
-        let min_num_buffers = 4usize;
+        // TODO use the proper control to get the right value outside of low-latency mode too.
+        let min_num_buffers = if self.low_latency {
+            min_buffers_for_capture(&self.device).unwrap_or(4)
+        } else {
+            4usize
+        };
         debug!("Stream requires {} capture buffers", min_num_buffers);
 
-        let visible_rect = capture_queue.get_selection(SelectionTarget::Compose)?;
+        // Some drivers report a compose rectangle larger than the coded size, so clamp it to the
+        // frame.
+        let coded_format: Format = capture_queue.get_format()?;
+        let frame_rect = Rect::new(0, 0, coded_format.width, coded_format.height);
+        let compose_rect = capture_queue.get_selection(SelectionTarget::Compose)?;
+        let visible_rect = compose_rect.intersection(&frame_rect).unwrap_or(frame_rect);
+        if visible_rect != compose_rect {
+            warn!(
+                "Compose rectangle {} exceeds the frame, using {} as visible rectangle",
+                compose_rect, visible_rect
+            );
+        }
         debug!(
             "Visible rectangle: ({}, {}), {}x{}",
             visible_rect.left, visible_rect.top, visible_rect.width, visible_rect.height
@@ -339,11 +650,22 @@ where
         )?;
 
         debug!("Client requires {} capture buffers", num_buffers);
+        // The client may ask for fewer buffers than the driver needs, e.g. in low-latency mode
+        // where it is expected to use the minimum.
+        let num_buffers = std::cmp::max(num_buffers, min_num_buffers);
+        decoder_event!(
+            width = coded_format.width,
+            height = coded_format.height,
+            num_buffers,
+            "resolution change"
+        );
 
         // Allocate the new CAPTURE buffers and get ourselves a new waker for
         // returning buffers.
         let capture_queue =
             capture_queue.request_buffers_generic::<P::HandleType>(mem_type, num_buffers as u32)?;
+        let stats = Arc::clone(&self.stats);
+        capture_queue.set_frame_drop_cb(move |count| stats.frames_lost(count));
         let cap_buffer_waker = self
             .poller
             .add_waker(CAPTURE_READY)
@@ -360,6 +682,7 @@ where
                 provider,
                 cap_buffer_waker,
                 blocking_drain_in_progress: false,
+                drain_in_progress: false,
             },
             ..self
         })
@@ -378,15 +701,21 @@ where
     ///   * If a blocking drain was in progress, complete it.
     fn dequeue_capture_buffer(mut self) -> Self {
         trace!("Dequeueing decoded CAPTURE buffers");
-        let (capture_queue, cap_buffer_waker, blocking_drain_in_progress) =
+        let (capture_queue, cap_buffer_waker, blocking_drain_in_progress, drain_in_progress) =
             match &mut self.capture_queue {
                 CaptureQueue::AwaitingResolution { .. } => unreachable!(),
                 CaptureQueue::Decoding {
                     capture_queue,
                     cap_buffer_waker,
                     blocking_drain_in_progress,
+                    drain_in_progress,
                     ..
-                } => (capture_queue, cap_buffer_waker, blocking_drain_in_progress),
+                } => (
+                    capture_queue,
+                    cap_buffer_waker,
+                    blocking_drain_in_progress,
+                    drain_in_progress,
+                ),
             };
 
         let mut cap_buf = match capture_queue.try_dequeue() {
@@ -400,7 +729,15 @@ where
             }
         };
 
+        self.stats.capture_dequeued(&cap_buf.data);
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.progress();
+        }
         let is_last = cap_buf.data.is_last();
+        // The driver is unlikely to recover if it cannot decode the very first frame, so let the
+        // client know.
+        let first_frame_error =
+            std::mem::take(&mut self.awaiting_first_frame) && cap_buf.data.has_error();
 
         // Add a drop callback to the dequeued buffer so we
         // re-queue it as soon as it is dropped.
@@ -410,8 +747,26 @@ where
             cap_waker.wake();
         });
 
-        // Pass buffers to the client
-        (self.event_cb)(DecoderEvent::FrameDecoded(cap_buf));
+        // Pass buffers to the client, unless it cannot keep up and the frame drop policy says
+        // otherwise. Frames are never dropped during a drain, and all pending frames must be
+        // delivered before the LAST buffer.
+        match &mut self.backlog {
+            Some(backlog) if !is_last && !*drain_in_progress => {
+                let timestamp = timestamp_to_duration(cap_buf.data.timestamp());
+                // Dropping the frames recycles them through their drop callback.
+                let dropped = backlog.push(cap_buf, timestamp);
+                if !dropped.is_empty() {
+                    trace!("Dropping {} late frames", dropped.len());
+                    self.stats.frames_dropped(dropped.len());
+                }
+                deliver_backlog::<P, _>(backlog, &mut self.event_cb, false);
+            }
+            Some(backlog) => {
+                deliver_backlog::<P, _>(backlog, &mut self.event_cb, true);
+                (self.event_cb)(DecoderEvent::FrameDecoded(cap_buf));
+            }
+            None => (self.event_cb)(DecoderEvent::FrameDecoded(cap_buf)),
+        }
 
         if is_last {
             debug!("CAPTURE buffer marked with LAST flag");
@@ -432,6 +787,8 @@ where
                 capture_queue.stream_off().unwrap();
                 capture_queue.stream_on().unwrap();
                 (self.event_cb)(DecoderEvent::EndOfStream);
+                *drain_in_progress = false;
+                decoder_event!(blocking = *blocking_drain_in_progress, "drain complete");
                 if *blocking_drain_in_progress {
                     debug!("Signaling end of blocking drain");
                     *blocking_drain_in_progress = false;
@@ -440,13 +797,23 @@ where
             }
         }
 
+        if first_frame_error {
+            // Error buffers do not carry an error code, so report what DQBUF would.
+            self.setup_error(DecodeSetupError::DriverError {
+                errno: Errno::EIO,
+                stage: DecodeSetupStage::FirstFrame,
+            });
+        }
+
         self
     }
 
     pub(super) fn run(mut self) -> Self {
         'mainloop: loop {
             if let CaptureQueue::Decoding { capture_queue, .. } = &self.capture_queue {
-                match capture_queue.num_queued_buffers() {
+                let num_queued_buffers = capture_queue.num_queued_buffers();
+                self.stats.set_capture_queue_depth(num_queued_buffers);
+                match num_queued_buffers {
                     // If there are no buffers on the CAPTURE queue, poll() will return
                     // immediately with EPOLLERR and we would loop indefinitely.
                     // Prevent this by temporarily disabling polling the CAPTURE queue
@@ -456,6 +823,12 @@ where
                             .disable_event(DeviceEvent::CaptureReady)
                             .unwrap();
                     }
+                    // While paused, leave the decoded frames in the driver until we are resumed.
+                    _ if self.paused.is_some() => {
+                        self.poller
+                            .disable_event(DeviceEvent::CaptureReady)
+                            .unwrap();
+                    }
                     // If device polling was disabled and we have buffers queued, we
                     // can reenable it as poll will now wait for a CAPTURE buffer to
                     // be ready for dequeue.
@@ -465,8 +838,20 @@ where
                 }
             }
 
+            // Wake up in time to check whether the initial resolution is late, or whether the
+            // driver has stalled.
+            let poll_timeout = match (&self.capture_queue, self.source_change_deadline) {
+                (CaptureQueue::AwaitingResolution { .. }, Some((deadline, _))) => {
+                    Some(deadline.saturating_duration_since(Instant::now()))
+                }
+                (CaptureQueue::Decoding { .. }, _) => {
+                    self.watchdog.as_ref().map(Watchdog::next_check)
+                }
+                _ => None,
+            };
+
             trace!("Polling...");
-            let events = match self.poller.poll(None) {
+            let events = match self.poller.poll(poll_timeout) {
                 Ok(events) => events,
                 Err(e) => {
                     error!("Polling failure, exiting capture thread: {}", e);
@@ -479,6 +864,10 @@ where
                     PollEvent::Device(DeviceEvent::CaptureReady) => self.dequeue_capture_buffer(),
                     PollEvent::Waker(CAPTURE_READY) => {
                         self.enqueue_capture_buffers();
+                        // The client may have returned a frame and be ready for another one.
+                        if let Some(backlog) = &mut self.backlog {
+                            deliver_backlog::<P, _>(backlog, &mut self.event_cb, false);
+                        }
                         self
                     }
                     PollEvent::Waker(COMMAND_WAITING) => {
@@ -495,6 +884,9 @@ where
                             match command {
                                 DecoderCommand::Drain(blocking) => self.drain(blocking),
                                 DecoderCommand::Flush => self.flush(),
+                                DecoderCommand::Pause => self.pause(),
+                                DecoderCommand::Resume => self.resume(),
+                                DecoderCommand::Recover => self.recover(),
                                 DecoderCommand::Stop => {
                                     trace!("Processing stop command");
                                     break 'mainloop;
@@ -506,6 +898,16 @@ where
                     _ => panic!("Unexpected event!"),
                 }
             }
+
+            match self.capture_queue {
+                CaptureQueue::AwaitingResolution { .. } => self.check_source_change_deadline(),
+                CaptureQueue::Decoding { .. } => self.check_watchdog(),
+            }
+        }
+
+        // Undelivered frames must be returned before the CAPTURE buffers are freed.
+        if let Some(backlog) = &mut self.backlog {
+            backlog.clear();
         }
 
         // Return the decoder to the awaiting resolution state.
@@ -530,3 +932,286 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::{
+        stateful::{Decoder, Decoding, GetBufferError},
+        CompletedInputBuffer,
+    };
+    use crate::device::queue::{
+        handles_provider::MmapProvider, FormatBuilder, GetFreeOutputBuffer,
+    };
+    use crate::ioctl::backend::mock::{mock_device, MockIoctls};
+    use crate::memory::MmapHandle;
+    use std::sync::atomic::AtomicBool;
+    use std::thread::JoinHandle;
+
+    type InputDoneCb = fn(CompletedInputBuffer<Vec<MmapHandle>>);
+    type EventCb = fn(DecoderEvent<MmapProvider>);
+    type FormatChangedCb =
+        fn(FormatBuilder, Rect, usize) -> anyhow::Result<FormatChangedReply<MmapProvider>>;
+    type TestCaptureThread = CaptureThread<MmapProvider, EventCb, FormatChangedCb>;
+    type TestDecoder =
+        Decoder<Decoding<Vec<MmapHandle>, MmapProvider, InputDoneCb, EventCb, FormatChangedCb>>;
+
+    /// Expects the creation of a queue of type `queue` and the allocation of one MMAP buffer on
+    /// it.
+    fn expect_queue(mock: &MockIoctls, queue: QueueType) {
+        for count in [0, 1] {
+            mock.expect_with(
+                "vidioc_reqbufs",
+                move |reqbufs: &mut bindings::v4l2_requestbuffers| {
+                    assert_eq!(reqbufs.type_, queue as u32);
+                    assert_eq!(reqbufs.count, count);
+                    Ok(0)
+                },
+            );
+        }
+        mock.expect_with("vidioc_querybuf", |buf: &mut bindings::v4l2_buffer| {
+            buf.length = 4096;
+            Ok(0)
+        });
+        mock.expect("vidioc_g_fmt", Ok(0));
+    }
+
+    /// Expects the queue of type `queue` to be streamed off and its buffers freed.
+    fn expect_release_queue(mock: &MockIoctls, queue: QueueType) {
+        mock.expect("vidioc_streamoff", Ok(0)).expect_with(
+            "vidioc_reqbufs",
+            move |reqbufs: &mut bindings::v4l2_requestbuffers| {
+                assert_eq!(reqbufs.type_, queue as u32);
+                assert_eq!(reqbufs.count, 0);
+                Ok(0)
+            },
+        );
+    }
+
+    /// Expects a `VIDIOC_TRY_DECODER_CMD` or `VIDIOC_DECODER_CMD` (as per `name`) of `cmd`,
+    /// returning `result`.
+    fn expect_decoder_cmd(
+        mock: &MockIoctls,
+        name: &'static str,
+        cmd: u32,
+        result: nix::Result<i32>,
+    ) {
+        mock.expect_with(name, move |decoder_cmd: &mut bindings::v4l2_decoder_cmd| {
+            assert_eq!(decoder_cmd.cmd, cmd);
+            result
+        });
+    }
+
+    /// Runs the part of the capture thread main loop processing pause and resume commands, until
+    /// the decoder is stopped.
+    fn run_commands(mut thread: TestCaptureThread) -> JoinHandle<TestCaptureThread> {
+        std::thread::spawn(move || {
+            while let Ok(command) = thread.command_receiver.recv() {
+                match command {
+                    DecoderCommand::Pause => thread.pause(),
+                    DecoderCommand::Resume => thread.resume(),
+                    DecoderCommand::Stop => break,
+                    command => panic!("unexpected command {:?}", command),
+                }
+            }
+
+            thread
+        })
+    }
+
+    /// Returns a decoder of `device` which has received its initial resolution, and whose
+    /// capture thread only processes pause and resume commands.
+    fn decoding_decoder(mock: &MockIoctls, device: Arc<Device>) -> TestDecoder {
+        expect_queue(mock, QueueType::VideoOutput);
+        let output_queue = Queue::get_output_queue(Arc::clone(&device))
+            .unwrap()
+            .request_buffers::<Vec<MmapHandle>>(1)
+            .unwrap();
+        expect_queue(mock, QueueType::VideoCapture);
+        let capture_queue = Queue::get_capture_queue(Arc::clone(&device)).unwrap();
+
+        let (command_sender, command_receiver) = mpsc::channel();
+        let (response_sender, response_receiver) = mpsc::channel();
+        let mut thread: TestCaptureThread = CaptureThread::new(
+            &device,
+            capture_queue,
+            (|_| ()) as EventCb,
+            (|_, _, _| Err(anyhow::anyhow!("unexpected format change"))) as FormatChangedCb,
+            command_receiver,
+            response_sender,
+            Arc::new(StatsRecorder::new()),
+            false,
+            None,
+            None,
+            None,
+            output_queue.buffer_stats(),
+            output_queue.get_type(),
+        )
+        .unwrap();
+        let command_waker = Arc::clone(&thread.command_waker);
+
+        let CaptureQueue::AwaitingResolution { capture_queue } = thread.capture_queue else {
+            unreachable!();
+        };
+        thread.capture_queue = CaptureQueue::Decoding {
+            capture_queue: capture_queue.request_buffers::<Vec<MmapHandle>>(1).unwrap(),
+            provider: MmapProvider::new(&Default::default()),
+            cap_buffer_waker: thread.poller.add_waker(CAPTURE_READY).unwrap(),
+            blocking_drain_in_progress: false,
+            drain_in_progress: false,
+        };
+
+        Decoder {
+            device: Arc::clone(&device),
+            state: Decoding {
+                output_queue,
+                input_done_cb: (|_| ()) as InputDoneCb,
+                output_poller: Poller::new(device).unwrap(),
+                command_waker,
+                command_sender,
+                response_receiver,
+                stats: Arc::new(StatsRecorder::new()),
+                software_paused: AtomicBool::new(false),
+                handle: run_commands(thread),
+            },
+        }
+    }
+
+    /// Stops `decoder` and expects both its queues to be released.
+    fn stop_decoder(mock: &MockIoctls, decoder: TestDecoder) {
+        expect_release_queue(mock, QueueType::VideoCapture);
+        mock.expect("vidioc_streamoff", Ok(0));
+        expect_release_queue(mock, QueueType::VideoOutput);
+        decoder.stop().unwrap();
+    }
+
+    /// Expects a `VIDIOC_DQEVENT` returning an event of type `type_` with `changes` as source
+    /// changes.
+    fn expect_event(mock: &MockIoctls, type_: u32, changes: u32) {
+        mock.expect_with("vidioc_dqevent", move |event: &mut bindings::v4l2_event| {
+            event.type_ = type_;
+            event.u.src_change = bindings::v4l2_event_src_change { changes };
+            Ok(0)
+        });
+    }
+
+    #[test]
+    fn test_drc_event_pending() {
+        let mock = MockIoctls::new();
+        let device = mock_device(&mock);
+
+        // No event pending.
+        mock.expect("vidioc_dqevent", Err(Errno::ENOENT));
+        assert!(!is_drc_event_pending(&device).unwrap());
+
+        // Resolution change, possibly followed by other events.
+        expect_event(
+            &mock,
+            bindings::V4L2_EVENT_SOURCE_CHANGE,
+            bindings::V4L2_EVENT_SRC_CH_RESOLUTION,
+        );
+        expect_event(&mock, bindings::V4L2_EVENT_EOS, 0);
+        mock.expect("vidioc_dqevent", Err(Errno::ENOENT));
+        assert!(is_drc_event_pending(&device).unwrap());
+
+        // The event has been consumed.
+        mock.expect("vidioc_dqevent", Err(Errno::ENOENT));
+        assert!(!is_drc_event_pending(&device).unwrap());
+
+        // Source changes that are not resolution changes do not trigger a DRC.
+        expect_event(&mock, bindings::V4L2_EVENT_SOURCE_CHANGE, 0);
+        mock.expect("vidioc_dqevent", Err(Errno::ENOENT));
+        assert!(!is_drc_event_pending(&device).unwrap());
+
+        // Errors are propagated.
+        mock.expect("vidioc_dqevent", Err(Errno::ENODEV));
+        assert!(matches!(
+            is_drc_event_pending(&device),
+            Err(ioctl::DqEventError::IoctlError(Errno::ENODEV))
+        ));
+
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_pause_hardware() {
+        let mock = MockIoctls::new();
+        let device = Arc::new(mock_device(&mock));
+        let mut decoder = decoding_decoder(&mock, device);
+
+        // The driver supports the PAUSE command, so it is used.
+        expect_decoder_cmd(
+            &mock,
+            "vidioc_try_decoder_cmd",
+            bindings::V4L2_DEC_CMD_PAUSE,
+            Ok(0),
+        );
+        expect_decoder_cmd(
+            &mock,
+            "vidioc_decoder_cmd",
+            bindings::V4L2_DEC_CMD_PAUSE,
+            Ok(0),
+        );
+        assert_eq!(decoder.pause().unwrap(), PauseStrategy::Hardware);
+        assert!(matches!(decoder.pause(), Err(PauseError::AlreadyPaused)));
+        // The driver stops decoding on its own, so encoded data can still be queued.
+        assert!(decoder.get_buffer().is_ok());
+
+        expect_decoder_cmd(
+            &mock,
+            "vidioc_decoder_cmd",
+            bindings::V4L2_DEC_CMD_RESUME,
+            Ok(0),
+        );
+        decoder.resume().unwrap();
+        assert!(matches!(decoder.resume(), Err(ResumeError::NotPaused)));
+
+        // Errors of the driver leave the decoder in its current state.
+        expect_decoder_cmd(
+            &mock,
+            "vidioc_try_decoder_cmd",
+            bindings::V4L2_DEC_CMD_PAUSE,
+            Ok(0),
+        );
+        expect_decoder_cmd(
+            &mock,
+            "vidioc_decoder_cmd",
+            bindings::V4L2_DEC_CMD_PAUSE,
+            Err(Errno::EBUSY),
+        );
+        assert!(matches!(decoder.pause(), Err(PauseError::DecoderCmd(_))));
+        assert!(matches!(decoder.resume(), Err(ResumeError::NotPaused)));
+
+        stop_decoder(&mock, decoder);
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_pause_software() {
+        let mock = MockIoctls::new();
+        let device = Arc::new(mock_device(&mock));
+        let mut decoder = decoding_decoder(&mock, device);
+
+        // The driver does not support the PAUSE command, so we pause on our side and stop
+        // providing OUTPUT buffers.
+        expect_decoder_cmd(
+            &mock,
+            "vidioc_try_decoder_cmd",
+            bindings::V4L2_DEC_CMD_PAUSE,
+            Err(Errno::EINVAL),
+        );
+        assert_eq!(decoder.pause().unwrap(), PauseStrategy::Software);
+        assert!(matches!(decoder.get_buffer(), Err(GetBufferError::Paused)));
+        assert!(matches!(
+            decoder.try_get_free_buffer(),
+            Err(GetBufferError::Paused)
+        ));
+
+        // Resuming does not involve the driver either.
+        decoder.resume().unwrap();
+        assert!(decoder.get_buffer().is_ok());
+
+        stop_decoder(&mock, decoder);
+        mock.assert_done();
+    }
+}
@@ -0,0 +1,193 @@
+//! Dropping of late decoded frames when the client cannot keep up with the decoder.
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Policy deciding when decoded frames are recycled without being delivered to the client.
+///
+/// Decoded frames are delivered as long as the client holds fewer than `max_held_frames` of
+/// them. Past that point, new frames are kept back by the decoder until the client returns one,
+/// and the oldest kept back frames are dropped once there are more than
+/// `max_undelivered_frames` of them, or once their timestamp is older than the one of the latest
+/// decoded frame by more than `max_age`.
+///
+/// Frames are never dropped while a drain is in progress, so all the frames preceding the end of
+/// the stream are delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameDropPolicy {
+    pub max_held_frames: usize,
+    pub max_undelivered_frames: usize,
+    pub max_age: Option<Duration>,
+}
+
+impl FrameDropPolicy {
+    pub fn new(max_held_frames: usize, max_undelivered_frames: usize) -> Self {
+        Self {
+            max_held_frames,
+            max_undelivered_frames,
+            max_age: None,
+        }
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+}
+
+/// Decoded frames waiting to be delivered to the client, along with their timestamps.
+pub(super) struct FrameBacklog<T> {
+    policy: FrameDropPolicy,
+    /// Number of frames delivered to the client and not returned yet.
+    held: Arc<AtomicUsize>,
+    frames: VecDeque<(T, Duration)>,
+    /// Whether frames are currently being dropped.
+    dropping: bool,
+    /// Value of `dropping` last reported to the client.
+    reported_dropping: bool,
+}
+
+impl<T> FrameBacklog<T> {
+    pub(super) fn new(policy: FrameDropPolicy) -> Self {
+        Self {
+            policy,
+            held: Arc::new(AtomicUsize::new(0)),
+            frames: VecDeque::new(),
+            dropping: false,
+            reported_dropping: false,
+        }
+    }
+
+    /// Returns the counter of frames held by the client, which must be decremented when a frame
+    /// returned by [`FrameBacklog::pop`] is released.
+    pub(super) fn held_frames(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.held)
+    }
+
+    #[cfg(test)]
+    pub(super) fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Add a decoded `frame` with `timestamp` to the backlog.
+    ///
+    /// Returns the frames that have been dropped as a result, which the caller recycles by
+    /// dropping them.
+    pub(super) fn push(&mut self, frame: T, timestamp: Duration) -> Vec<T> {
+        self.frames.push_back((frame, timestamp));
+
+        let mut dropped = Vec::new();
+        while let Some((_, oldest)) = self.frames.front() {
+            let too_many = self.frames.len() > self.policy.max_undelivered_frames;
+            let too_old = matches!(self.policy.max_age,
+                Some(max_age) if timestamp.saturating_sub(*oldest) > max_age);
+            if !too_many && !too_old {
+                break;
+            }
+            if let Some((frame, _)) = self.frames.pop_front() {
+                dropped.push(frame);
+            }
+        }
+
+        if !dropped.is_empty() {
+            self.dropping = true;
+        }
+
+        dropped
+    }
+
+    /// Returns the next frame to deliver if the client can take one, or regardless of how many
+    /// frames the client holds if `force` is set.
+    pub(super) fn pop(&mut self, force: bool) -> Option<T> {
+        if !force && self.held.load(Ordering::Relaxed) >= self.policy.max_held_frames {
+            return None;
+        }
+
+        match self.frames.pop_front() {
+            Some((frame, _)) => {
+                self.held.fetch_add(1, Ordering::Relaxed);
+                Some(frame)
+            }
+            None => {
+                // The client has caught up.
+                self.dropping = false;
+                None
+            }
+        }
+    }
+
+    /// Returns `Some(true)` if frames started being dropped, or `Some(false)` if they stopped
+    /// being dropped, since the last call.
+    pub(super) fn take_dropping_change(&mut self) -> Option<bool> {
+        if self.dropping != self.reported_dropping {
+            self.reported_dropping = self.dropping;
+            Some(self.dropping)
+        } else {
+            None
+        }
+    }
+
+    /// Discard all the frames of the backlog without counting them as dropped.
+    pub(super) fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slow_consumer() {
+        let mut backlog = FrameBacklog::new(FrameDropPolicy::new(2, 3));
+        let mut delivered = Vec::new();
+        let mut dropped = 0;
+
+        // The client holds on to every frame it receives.
+        for i in 0..20u64 {
+            dropped += backlog.push(i, Duration::from_millis(i * 33)).len();
+            while let Some(frame) = backlog.pop(false) {
+                delivered.push(frame);
+            }
+            assert!(delivered.len() <= 2);
+            assert!(backlog.len() <= 3);
+        }
+        assert_eq!(delivered, vec![0, 1]);
+        assert_eq!(dropped, 20 - 2 - 3);
+        assert_eq!(backlog.take_dropping_change(), Some(true));
+        assert_eq!(backlog.take_dropping_change(), None);
+
+        // The client releases its frames and catches up with the most recent ones.
+        backlog.held_frames().store(0, Ordering::Relaxed);
+        delivered.clear();
+        while let Some(frame) = backlog.pop(false) {
+            delivered.push(frame);
+            backlog.held_frames().fetch_sub(1, Ordering::Relaxed);
+        }
+        assert_eq!(delivered, vec![17, 18, 19]);
+        assert_eq!(backlog.take_dropping_change(), Some(false));
+    }
+
+    #[test]
+    fn test_max_age() {
+        let mut backlog =
+            FrameBacklog::new(FrameDropPolicy::new(0, 10).max_age(Duration::from_millis(100)));
+
+        let mut dropped = 0;
+        for i in 0..10u64 {
+            dropped += backlog.push(i, Duration::from_millis(i * 40)).len();
+        }
+        // Only frames at most 100ms older than the latest one are kept.
+        assert_eq!(backlog.len(), 3);
+        assert_eq!(dropped, 7);
+
+        // Forced delivery ignores the number of frames held by the client.
+        assert_eq!(backlog.pop(true), Some(7));
+        assert_eq!(backlog.pop(false), None);
+    }
+}
@@ -0,0 +1,566 @@
+//! Management of many stateful decoder sessions running against the same driver.
+//!
+//! Workloads like video surveillance can run dozens of decoding sessions on the same hardware
+//! block. [`DecoderPool`] probes the device once and shares the result across all sessions,
+//! limits the number of sessions that can run concurrently (queueing the extra ones in FIFO
+//! order), optionally serializes operations that are known to be racy on some drivers, and
+//! aggregates the statistics of all its sessions.
+//!
+//! Note that V4L2 buffers belong to the file descriptor they have been allocated on, and thus
+//! cannot be shared between sessions. The memory backing them can however be reused by sessions
+//! decoding to the same format using a [`CaptureBufferPool`].
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, Weak},
+};
+
+use log::debug;
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::{
+    decoder::stateful::{AwaitingOutputFormat, Decoder, DecoderOpenError},
+    device::{
+        poller::Waker,
+        queue::handles_provider::{HandlesProvider, PooledHandles, PooledHandlesProvider},
+    },
+    error::AsErrno,
+    ioctl::{Capability, FmtDesc},
+    memory::BufferHandles,
+    stats::{CodecStats, StatsRecorder},
+    Format,
+};
+
+/// Configuration of a [`DecoderPool`].
+#[derive(Clone, Debug)]
+pub struct DecoderPoolConfig {
+    max_sessions: usize,
+    throttle: bool,
+}
+
+impl DecoderPoolConfig {
+    /// Create a configuration allowing up to `max_sessions` sessions to run concurrently.
+    pub fn new(max_sessions: usize) -> Self {
+        Self {
+            max_sessions: max_sessions.max(1),
+            throttle: false,
+        }
+    }
+
+    /// Serialize the operations passed to [`DecoderSession::throttled`], for drivers that do not
+    /// cope well with many sessions e.g. starting to stream at the same time.
+    pub fn throttle(mut self) -> Self {
+        self.throttle = true;
+        self
+    }
+}
+
+#[derive(Default)]
+struct LimiterState {
+    /// Number of sessions currently running.
+    active: usize,
+    /// Next ticket to hand out to a session waiting for a slot.
+    next_ticket: u64,
+    /// Ticket of the next waiting session allowed to run.
+    now_serving: u64,
+    /// Total number of sessions that have been allowed to run.
+    total: u64,
+    /// Maximum number of sessions that have been running at the same time.
+    peak: usize,
+}
+
+impl LimiterState {
+    fn waiting(&self) -> usize {
+        (self.next_ticket - self.now_serving) as usize
+    }
+}
+
+/// Limits the number of concurrent sessions, serving waiting sessions in FIFO order.
+struct SessionLimiter {
+    max_sessions: usize,
+    state: Mutex<LimiterState>,
+    cond: Condvar,
+}
+
+impl SessionLimiter {
+    fn new(max_sessions: usize) -> Self {
+        Self {
+            max_sessions,
+            state: Default::default(),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Wait until a session slot is available and take it.
+    fn acquire(&self) {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+
+        while state.active >= self.max_sessions || state.now_serving != ticket {
+            state = self.cond.wait(state).unwrap();
+        }
+
+        state.now_serving += 1;
+        self.take_slot(&mut state);
+        // Let the next waiting session check whether it can run as well.
+        self.cond.notify_all();
+    }
+
+    /// Take a session slot if one is available and no other session is waiting.
+    fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.active >= self.max_sessions || state.waiting() > 0 {
+            return false;
+        }
+
+        state.next_ticket += 1;
+        state.now_serving += 1;
+        self.take_slot(&mut state);
+        true
+    }
+
+    fn take_slot(&self, state: &mut LimiterState) {
+        state.active += 1;
+        state.total += 1;
+        state.peak = state.peak.max(state.active);
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.active -= 1;
+        self.cond.notify_all();
+    }
+}
+
+struct PoolInner {
+    path: PathBuf,
+    capability: Capability,
+    output_formats: Vec<FmtDesc>,
+    capture_formats: Vec<FmtDesc>,
+    limiter: SessionLimiter,
+    throttle: Option<Mutex<()>>,
+    sessions_stats: Mutex<Vec<Weak<StatsRecorder>>>,
+}
+
+#[derive(Debug, Error)]
+pub enum PoolError {
+    #[error("error while opening decoder")]
+    DecoderOpenError(#[from] DecoderOpenError),
+    #[error("maximum number of concurrent sessions reached")]
+    NoSessionAvailable,
+}
+
+impl AsErrno for PoolError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            PoolError::DecoderOpenError(e) => e.errno(),
+            PoolError::NoSessionAvailable => None,
+        }
+    }
+}
+
+/// Aggregate statistics of a [`DecoderPool`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Number of sessions currently running.
+    pub active_sessions: usize,
+    /// Number of sessions waiting for a slot.
+    pub waiting_sessions: usize,
+    /// Total number of sessions that have been opened.
+    pub total_sessions: u64,
+    /// Maximum number of sessions that have been running at the same time.
+    pub peak_sessions: usize,
+    /// Statistics of all the sessions that are still alive, see [`CodecStats::accumulate`].
+    pub decoding: CodecStats,
+}
+
+/// Shares device discovery and limits the number of concurrent stateful decoder sessions.
+#[derive(Clone)]
+pub struct DecoderPool {
+    inner: Arc<PoolInner>,
+}
+
+impl DecoderPool {
+    /// Create a new pool for the stateful decoder at `path`.
+    ///
+    /// The device is probed once to make sure it is a stateful decoder and to obtain its
+    /// capabilities and supported formats. Sessions opened from the pool skip these steps.
+    pub fn new(path: &Path, config: DecoderPoolConfig) -> Result<Self, PoolError> {
+        let decoder = Decoder::open(path)?;
+        let capability = decoder.device.caps().clone();
+        let output_formats = decoder.state.output_queue.format_iter().collect();
+        let capture_formats = decoder.state.capture_queue.format_iter().collect();
+        debug!(
+            "Created decoder pool for {} ({}), {} sessions max",
+            path.display(),
+            capability.card,
+            config.max_sessions
+        );
+
+        Ok(Self {
+            inner: Arc::new(PoolInner {
+                path: path.to_path_buf(),
+                capability,
+                output_formats,
+                capture_formats,
+                limiter: SessionLimiter::new(config.max_sessions),
+                throttle: config.throttle.then(Default::default),
+                sessions_stats: Default::default(),
+            }),
+        })
+    }
+
+    /// Returns the capabilities of the device, as probed when the pool was created.
+    pub fn capability(&self) -> &Capability {
+        &self.inner.capability
+    }
+
+    /// Returns the formats supported by the OUTPUT queue of the device.
+    pub fn output_formats(&self) -> &[FmtDesc] {
+        &self.inner.output_formats
+    }
+
+    /// Returns the formats supported by the CAPTURE queue of the device.
+    pub fn capture_formats(&self) -> &[FmtDesc] {
+        &self.inner.capture_formats
+    }
+
+    fn open_decoder(
+        &self,
+    ) -> Result<(Decoder<AwaitingOutputFormat>, DecoderSession), DecoderOpenError> {
+        let session = DecoderSession {
+            pool: Arc::clone(&self.inner),
+        };
+        // `session` releases the slot if opening fails.
+        let decoder = session.throttled(|| Decoder::open_unchecked(&self.inner.path))?;
+        // Include the statistics of the decoder in the ones of the pool once it starts.
+        self.inner
+            .sessions_stats
+            .lock()
+            .unwrap()
+            .push(Arc::downgrade(&decoder.state.stats));
+
+        Ok((decoder, session))
+    }
+
+    /// Open a new decoder session, waiting for a slot to become available if the maximum number of
+    /// concurrent sessions is reached.
+    ///
+    /// The returned [`DecoderSession`] must be kept alive as long as the decoder is in use, as
+    /// dropping it makes its slot available to another session.
+    pub fn open_session(
+        &self,
+    ) -> Result<(Decoder<AwaitingOutputFormat>, DecoderSession), PoolError> {
+        self.inner.limiter.acquire();
+        Ok(self.open_decoder()?)
+    }
+
+    /// Open a new decoder session if a slot is immediately available.
+    pub fn try_open_session(
+        &self,
+    ) -> Result<(Decoder<AwaitingOutputFormat>, DecoderSession), PoolError> {
+        if !self.inner.limiter.try_acquire() {
+            return Err(PoolError::NoSessionAvailable);
+        }
+        Ok(self.open_decoder()?)
+    }
+
+    /// Returns the aggregate statistics of the pool.
+    pub fn stats(&self) -> PoolStats {
+        let (active_sessions, waiting_sessions, total_sessions, peak_sessions) = {
+            let state = self.inner.limiter.state.lock().unwrap();
+            (state.active, state.waiting(), state.total, state.peak)
+        };
+
+        let mut decoding = CodecStats::default();
+        let mut sessions_stats = self.inner.sessions_stats.lock().unwrap();
+        // Forget about the sessions that have been stopped.
+        sessions_stats.retain(|stats| stats.strong_count() > 0);
+        for stats in sessions_stats.iter().filter_map(Weak::upgrade) {
+            decoding.accumulate(&stats.snapshot());
+        }
+
+        PoolStats {
+            active_sessions,
+            waiting_sessions,
+            total_sessions,
+            peak_sessions,
+            decoding,
+        }
+    }
+}
+
+/// A slot in a [`DecoderPool`], which is released when this object is dropped.
+pub struct DecoderSession {
+    pool: Arc<PoolInner>,
+}
+
+impl DecoderSession {
+    /// Run `f`, making sure no other session of the pool runs a throttled operation at the same
+    /// time if the pool has been configured to throttle.
+    ///
+    /// This is typically used around [`Decoder::start`] to avoid many sessions starting to stream
+    /// simultaneously.
+    pub fn throttled<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        let _guard = self.pool.throttle.as_ref().map(|t| t.lock().unwrap());
+        f()
+    }
+}
+
+impl Drop for DecoderSession {
+    fn drop(&mut self) {
+        self.pool.limiter.release();
+    }
+}
+
+/// Keeps the memory backing the CAPTURE buffers of sessions that do not need it anymore, so it can
+/// be reused by sessions decoding to the same format instead of being allocated again.
+///
+/// [`CaptureBufferPool::provider`] returns a [`HandlesProvider`] that takes its handles from the
+/// pool and gives them back when it is dropped, i.e. when the session stops or the CAPTURE format
+/// changes. This is typically used with DMABUF handles.
+pub struct CaptureBufferPool<H: BufferHandles> {
+    buffers: Arc<Mutex<Vec<(Format, H)>>>,
+}
+
+impl<H: BufferHandles> Clone for CaptureBufferPool<H> {
+    fn clone(&self) -> Self {
+        Self {
+            buffers: Arc::clone(&self.buffers),
+        }
+    }
+}
+
+impl<H: BufferHandles> Default for CaptureBufferPool<H> {
+    fn default() -> Self {
+        Self {
+            buffers: Default::default(),
+        }
+    }
+}
+
+impl<H: BufferHandles> CaptureBufferPool<H> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the number of handles currently available in the pool.
+    pub fn num_available(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+
+    /// Returns a provider of `num_buffers` handles for CAPTURE buffers of `format`.
+    ///
+    /// The handles are taken from the pool if previously used with the same format, and the
+    /// missing ones are allocated using `alloc`.
+    pub fn provider<E, F>(
+        &self,
+        format: &Format,
+        num_buffers: usize,
+        mut alloc: F,
+    ) -> Result<PooledCaptureProvider<H>, E>
+    where
+        F: FnMut(&Format) -> Result<H, E>,
+    {
+        let mut handles = Vec::with_capacity(num_buffers);
+        {
+            let mut buffers = self.buffers.lock().unwrap();
+            while handles.len() < num_buffers {
+                match buffers.iter().position(|(f, _)| f == format) {
+                    Some(pos) => handles.push(buffers.swap_remove(pos).1),
+                    None => break,
+                }
+            }
+        }
+        debug!(
+            "Reusing {} pooled CAPTURE buffers out of {}",
+            handles.len(),
+            num_buffers
+        );
+
+        while handles.len() < num_buffers {
+            match alloc(format) {
+                Ok(h) => handles.push(h),
+                Err(e) => {
+                    // Do not lose the handles we took.
+                    self.put_back(format, handles);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(PooledCaptureProvider {
+            provider: PooledHandlesProvider::new(handles),
+            format: format.clone(),
+            pool: self.clone(),
+        })
+    }
+
+    fn put_back(&self, format: &Format, handles: Vec<H>) {
+        self.buffers
+            .lock()
+            .unwrap()
+            .extend(handles.into_iter().map(|h| (format.clone(), h)));
+    }
+}
+
+/// Handles provider returned by [`CaptureBufferPool::provider`].
+///
+/// The handles it holds are returned to the pool when it is dropped. Handles still in use at that
+/// time are freed once they are not used anymore.
+pub struct PooledCaptureProvider<H: BufferHandles> {
+    provider: PooledHandlesProvider<H>,
+    format: Format,
+    pool: CaptureBufferPool<H>,
+}
+
+impl<H: BufferHandles> HandlesProvider for PooledCaptureProvider<H> {
+    type HandleType = PooledHandles<H>;
+
+    fn get_handles(&self, waker: &Arc<Waker>) -> Option<Self::HandleType> {
+        self.provider.get_handles(waker)
+    }
+}
+
+impl<H: BufferHandles> Drop for PooledCaptureProvider<H> {
+    fn drop(&mut self) {
+        self.pool
+            .put_back(&self.format, self.provider.take_available());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{memory::MmapHandle, PixelFormat};
+
+    #[test]
+    fn test_session_limiter() {
+        const MAX_SESSIONS: usize = 3;
+        const NUM_SESSIONS: usize = 8;
+
+        let limiter = Arc::new(SessionLimiter::new(MAX_SESSIONS));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let threads = (0..NUM_SESSIONS)
+            .map(|_| {
+                let limiter = Arc::clone(&limiter);
+                let running = Arc::clone(&running);
+                let max_running = Arc::clone(&max_running);
+                thread::spawn(move || {
+                    limiter.acquire();
+                    let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now_running, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    limiter.release();
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(max_running.load(Ordering::SeqCst) <= MAX_SESSIONS);
+        let state = limiter.state.lock().unwrap();
+        assert_eq!(state.active, 0);
+        assert_eq!(state.waiting(), 0);
+        assert_eq!(state.total, NUM_SESSIONS as u64);
+        assert!(state.peak <= MAX_SESSIONS);
+    }
+
+    #[test]
+    fn test_session_limiter_fifo() {
+        let limiter = Arc::new(SessionLimiter::new(1));
+        limiter.acquire();
+        assert!(!limiter.try_acquire());
+
+        let (order_tx, order_rx) = mpsc::channel();
+        let threads = (0..3)
+            .map(|i| {
+                let thread_limiter = Arc::clone(&limiter);
+                let order_tx = order_tx.clone();
+                // Make sure the threads start waiting in order.
+                while limiter.state.lock().unwrap().waiting() < i {
+                    thread::yield_now();
+                }
+                let handle = thread::spawn(move || {
+                    thread_limiter.acquire();
+                    order_tx.send(i).unwrap();
+                    thread_limiter.release();
+                });
+                while limiter.state.lock().unwrap().waiting() < i + 1 {
+                    thread::yield_now();
+                }
+                handle
+            })
+            .collect::<Vec<_>>();
+
+        // A waiting session has priority over `try_acquire`.
+        limiter.release();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(order_rx.try_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert!(limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_capture_buffer_pool() {
+        let pool = CaptureBufferPool::<Vec<MmapHandle>>::new();
+        let format = Format {
+            width: 640,
+            height: 480,
+            pixelformat: PixelFormat::from(b"NV12"),
+            ..Default::default()
+        };
+        let other_format = Format {
+            width: 1280,
+            height: 720,
+            ..format.clone()
+        };
+        let allocated = AtomicUsize::new(0);
+        let alloc = |_: &Format| -> Result<_, ()> {
+            allocated.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![MmapHandle])
+        };
+
+        let provider = pool.provider(&format, 4, alloc).unwrap();
+        assert_eq!(allocated.load(Ordering::SeqCst), 4);
+        assert_eq!(pool.num_available(), 0);
+        drop(provider);
+        assert_eq!(pool.num_available(), 4);
+
+        // Same format, only the missing buffers are allocated.
+        let provider = pool.provider(&format, 6, alloc).unwrap();
+        assert_eq!(allocated.load(Ordering::SeqCst), 6);
+        assert_eq!(pool.num_available(), 0);
+        drop(provider);
+
+        // Different format, the pooled buffers are not used.
+        let provider = pool.provider(&other_format, 2, alloc).unwrap();
+        assert_eq!(allocated.load(Ordering::SeqCst), 8);
+        assert_eq!(pool.num_available(), 6);
+        drop(provider);
+        assert_eq!(pool.num_available(), 8);
+
+        // Pooled buffers are kept if an allocation fails.
+        assert!(pool
+            .provider(&format, 8, |_: &Format| -> Result<_, ()> { Err(()) })
+            .is_err());
+        assert_eq!(pool.num_available(), 8);
+    }
+}
@@ -18,38 +18,120 @@
 //! a V4L2 structure make sense - if it is relevant, then it will be visible,
 //! and if it is required, then the code won't compile unless it is provided.
 use super::ioctl;
+use super::ioctl::Capabilities;
 use super::ioctl::Capability;
 use super::QueueType;
+use super::{FormatResult, MultiplanarFormatResult};
+use crate::bindings::v4l2_input;
+use crate::controls::{ExtControlTrait, SafeExtControl};
+use crate::error::AsErrno;
+use crate::format::StreamParm;
+use nix::errno::Errno;
+use nix::poll::{PollFd, PollFlags, PollTimeout};
 use std::collections::BTreeSet;
+use std::convert::Infallible;
 use std::fs::File;
 use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, RawFd};
-use std::{path::Path, sync::Mutex};
+use std::time::{Duration, Instant};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 use thiserror::Error;
 
+pub mod format_prober;
+pub mod media;
 pub mod poller;
 pub mod queue;
+pub mod radio;
+pub mod subdev;
 mod traits;
 
 pub use traits::*;
 
-/// Options that can be specified when creating a `Device`.
-#[derive(Default)]
-pub struct DeviceConfig {
-    non_blocking_dqbuf: bool,
+/// Flags used to open the node of a `Device`.
+///
+/// The default opens the node for reading and writing, in blocking mode, and closes it on `exec`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceOpenFlags {
+    /// Open the node with `O_RDWR` if `true`, or `O_RDONLY` otherwise.
+    pub read_write: bool,
+    /// Open the node with `O_NONBLOCK`, making e.g. `DQBUF` return `EAGAIN` instead of blocking
+    /// when no buffer is ready.
+    pub nonblocking: bool,
+    /// Open the node with `O_CLOEXEC`.
+    pub close_on_exec: bool,
+}
+
+impl Default for DeviceOpenFlags {
+    fn default() -> Self {
+        DeviceOpenFlags {
+            read_write: true,
+            nonblocking: false,
+            close_on_exec: true,
+        }
+    }
 }
 
-impl DeviceConfig {
+impl DeviceOpenFlags {
     pub fn new() -> Self {
         Default::default()
     }
 
+    /// Flags for opening the node read-only, e.g. to query its capabilities and formats.
+    pub fn read_only() -> Self {
+        DeviceOpenFlags {
+            read_write: false,
+            ..Default::default()
+        }
+    }
+
+    /// Flags for opening the node for reading and writing in non-blocking mode.
+    pub fn nonblocking() -> Self {
+        DeviceOpenFlags {
+            nonblocking: true,
+            ..Default::default()
+        }
+    }
+
+    /// Flags for opening the node read-only in non-blocking mode, e.g. to poll for its events
+    /// while another process owns its queues.
+    pub fn monitoring() -> Self {
+        DeviceOpenFlags {
+            read_write: false,
+            nonblocking: true,
+            ..Default::default()
+        }
+    }
+
+    /// Returns these flags with `nonblocking` set.
     pub fn non_blocking_dqbuf(self) -> Self {
-        DeviceConfig {
-            non_blocking_dqbuf: true,
+        DeviceOpenFlags {
+            nonblocking: true,
+            ..self
         }
     }
+
+    fn oflags(&self) -> nix::fcntl::OFlag {
+        use nix::fcntl::OFlag;
+
+        let mut flags = if self.read_write {
+            OFlag::O_RDWR
+        } else {
+            OFlag::O_RDONLY
+        };
+        flags.set(OFlag::O_NONBLOCK, self.nonblocking);
+        flags.set(OFlag::O_CLOEXEC, self.close_on_exec);
+
+        flags
+    }
 }
 
+/// Options that can be specified when creating a `Device`.
+///
+/// This is the same type as [`DeviceOpenFlags`], kept under this name for compatibility.
+pub type DeviceConfig = DeviceOpenFlags;
+
 /// An opened V4L2 device. `Queue` objects can be instantiated from it.
 pub struct Device {
     capability: Capability,
@@ -59,14 +141,134 @@ pub struct Device {
 
 #[derive(Debug, Error)]
 pub enum DeviceOpenError {
+    #[error(
+        "permission denied while opening {}: check that the user has access to the device node, \
+        and on Android that the SELinux policy allows the process to use it",
+        .0.display()
+    )]
+    PermissionDenied(PathBuf),
     #[error("error while opening device")]
     OpenError(#[from] nix::Error),
     #[error("error while querying capabilities")]
     QueryCapError(#[from] ioctl::QueryCapError),
 }
 
+impl AsErrno for DeviceOpenError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            DeviceOpenError::PermissionDenied(_) => Some(Errno::EACCES),
+            DeviceOpenError::OpenError(e) => Some(*e),
+            DeviceOpenError::QueryCapError(e) => e.errno(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DeviceError {
+    #[error("device is missing required capabilities {0}")]
+    MissingCapability(Capabilities),
+}
+
+impl From<DeviceError> for Errno {
+    fn from(err: DeviceError) -> Self {
+        match err {
+            DeviceError::MissingCapability(_) => Errno::ENOTSUP,
+        }
+    }
+}
+
+impl AsErrno for DeviceError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            DeviceError::MissingCapability(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SwitchInputError {
+    #[error("no input named {0}")]
+    InputNotFound(String),
+    #[error("error while enumerating inputs: {0}")]
+    EnumInput(ioctl::SelectionError),
+    #[error("error while selecting input: {0}")]
+    SInput(ioctl::SelectionError),
+}
+
+impl From<SwitchInputError> for Errno {
+    fn from(err: SwitchInputError) -> Self {
+        match err {
+            SwitchInputError::InputNotFound(_) => Errno::EINVAL,
+            SwitchInputError::EnumInput(e) => e.into(),
+            SwitchInputError::SInput(e) => e.into(),
+        }
+    }
+}
+
+impl AsErrno for SwitchInputError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            SwitchInputError::InputNotFound(_) => None,
+            SwitchInputError::EnumInput(e) => e.errno(),
+            SwitchInputError::SInput(e) => e.errno(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DrainError {
+    #[error("error while sending the stop command: {0}")]
+    Stop(ioctl::DecoderCmdError<Infallible>),
+    #[error("error while polling the device: {0}")]
+    Poll(Errno),
+    #[error("error while dequeuing a capture buffer: {0}")]
+    Dequeue(ioctl::DqBufError<ioctl::V4l2BufferFromError>),
+    #[error("error while dequeuing an event: {0}")]
+    Event(ioctl::DqEventError),
+    /// The decoder did not signal the end of the drain in time. Contains the buffers that have
+    /// been dequeued so far.
+    #[error("timeout while draining, {} buffers dequeued", .0.len())]
+    Timeout(Vec<ioctl::V4l2Buffer>),
+    /// Polling the device reported `POLLERR` or `POLLHUP`, typically because the `CAPTURE` queue
+    /// is not streaming or has no buffer queued, so the drain cannot complete. Contains the
+    /// buffers that have been dequeued so far.
+    #[error("device error while draining, {} buffers dequeued", .0.len())]
+    DeviceError(Vec<ioctl::V4l2Buffer>),
+}
+
+impl From<DrainError> for Errno {
+    fn from(err: DrainError) -> Self {
+        match err {
+            DrainError::Stop(e) => e.into(),
+            DrainError::Poll(e) => e,
+            DrainError::Dequeue(e) => e.into(),
+            DrainError::Event(e) => e.into(),
+            DrainError::Timeout(_) => Errno::ETIMEDOUT,
+            DrainError::DeviceError(_) => Errno::EIO,
+        }
+    }
+}
+
+impl AsErrno for DrainError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            DrainError::Stop(e) => e.errno(),
+            DrainError::Poll(e) => Some(*e),
+            DrainError::Dequeue(e) => e.errno(),
+            DrainError::Event(e) => e.errno(),
+            DrainError::Timeout(_) => Some(Errno::ETIMEDOUT),
+            DrainError::DeviceError(_) => Some(Errno::EIO),
+        }
+    }
+}
+
+/// Returns the name of `input`.
+fn input_name(input: &v4l2_input) -> String {
+    ioctl::string_from_cstr(&input.name)
+}
+
 impl Device {
-    fn new(fd: File) -> Result<Self, ioctl::QueryCapError> {
+    pub(crate) fn new(fd: File) -> Result<Self, ioctl::QueryCapError> {
         Ok(Device {
             capability: ioctl::querycap(&fd)?,
             fd,
@@ -74,19 +276,26 @@ impl Device {
         })
     }
 
-    pub fn open(path: &Path, config: DeviceConfig) -> Result<Self, DeviceOpenError> {
-        use nix::fcntl::{open, OFlag};
+    /// Opens the node at `path` using `flags`.
+    ///
+    /// This is the same as [`Device::open_with_flags`].
+    pub fn open(path: &Path, flags: DeviceOpenFlags) -> Result<Self, DeviceOpenError> {
+        Self::open_with_flags(path, flags)
+    }
+
+    /// Opens the node at `path` using `flags` and queries its capabilities.
+    pub fn open_with_flags(path: &Path, flags: DeviceOpenFlags) -> Result<Self, DeviceOpenError> {
+        use nix::fcntl::open;
         use nix::sys::stat::Mode;
 
-        let flags = OFlag::O_RDWR
-            | OFlag::O_CLOEXEC
-            | if config.non_blocking_dqbuf {
-                OFlag::O_NONBLOCK
-            } else {
-                OFlag::empty()
-            };
+        let flags = flags.oflags();
 
-        let fd = open(path, flags, Mode::empty())?;
+        // Device nodes are commonly restricted, notably by SELinux on Android, so make that case
+        // explicit.
+        let fd = open(path, flags, Mode::empty()).map_err(|e| match e {
+            Errno::EACCES => DeviceOpenError::PermissionDenied(path.to_path_buf()),
+            e => DeviceOpenError::OpenError(e),
+        })?;
 
         // Safe because we are constructing a file from Fd we just opened.
         Ok(Device::new(unsafe { File::from_raw_fd(fd) })?)
@@ -96,6 +305,262 @@ impl Device {
     pub fn caps(&self) -> &Capability {
         &self.capability
     }
+
+    /// Returns whether the opened node has all the capabilities in `caps`.
+    ///
+    /// The capabilities are queried once when the device is opened, so this does not perform any
+    /// ioctl.
+    pub fn probe_capability(&self, caps: Capabilities) -> bool {
+        self.capability.device_caps().contains(caps)
+    }
+
+    /// Check that the opened node has all the capabilities in `required`, e.g. right after opening
+    /// it.
+    ///
+    /// Returns `DeviceError::MissingCapability` with the capabilities of `required` the node does
+    /// not have otherwise.
+    pub fn check_capabilities(&self, required: Capabilities) -> Result<(), DeviceError> {
+        let missing = required.difference(self.capability.device_caps());
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(DeviceError::MissingCapability(missing))
+        }
+    }
+
+    /// Ask the encoder to stop once the current GOP is complete, instead of immediately.
+    ///
+    /// Returns `EINVAL` if the encoder does not support stopping at the end of a GOP, in which case
+    /// the encoder is not stopped.
+    pub fn stop_encoding_at_gop_end(&self) -> Result<(), Errno> {
+        let command = ioctl::EncoderCmd::stop_at_gop_end();
+        if !ioctl::EncoderCmdCapability::probe(self).supports(command) {
+            return Err(Errno::EINVAL);
+        }
+
+        ioctl::encoder_cmd::<_, ()>(self, command)?;
+        Ok(())
+    }
+
+    /// Subscribe to the `V4L2_EVENT_FRAME_SYNC` events, raised when the device starts receiving
+    /// a frame, which can then be obtained with [`ioctl::dqevent`] as [`ioctl::Event::FrameSync`].
+    ///
+    /// These are mostly used to correlate the frames of a capture node with those of a separate
+    /// metadata node or of external sensors, see [`crate::buffer::FrameSyncCorrelator`]. Returns
+    /// `EINVAL` if the device does not support them.
+    pub fn subscribe_frame_sync(&self) -> Result<(), ioctl::SubscribeEventError> {
+        ioctl::subscribe_event(
+            self,
+            ioctl::EventType::FrameSync,
+            ioctl::SubscribeEventFlags::empty(),
+        )
+    }
+
+    /// Select the video input named `name`, compared case-insensitively, and return its index.
+    ///
+    /// On capture cards with several inputs, this is typically the first thing to do after
+    /// opening the device.
+    pub fn switch_input(&self, name: &str) -> Result<u32, SwitchInputError> {
+        let mut index = 0;
+        let index = loop {
+            let input: v4l2_input = match ioctl::enuminput(self, index) {
+                Ok(input) => input,
+                Err(ioctl::SelectionError::OutOfRange(_)) => {
+                    return Err(SwitchInputError::InputNotFound(name.into()))
+                }
+                Err(e) => return Err(SwitchInputError::EnumInput(e)),
+            };
+
+            if input_name(&input).eq_ignore_ascii_case(name) {
+                break index;
+            }
+            index += 1;
+        };
+
+        ioctl::s_input(self, index).map_err(SwitchInputError::SInput)?;
+        Ok(index as u32)
+    }
+
+    /// Returns the minimum and maximum values of control `T`, in that order, without changing
+    /// the state of the device.
+    ///
+    /// This relies on `V4L2_CTRL_WHICH_MIN_VAL` and `V4L2_CTRL_WHICH_MAX_VAL`, which are only
+    /// supported by recent kernels and for controls with the `V4L2_CTRL_FLAG_HAS_WHICH_MIN_MAX`
+    /// flag. Otherwise the driver returns `EINVAL`.
+    pub fn control_range<T: ExtControlTrait>(
+        &self,
+    ) -> Result<(SafeExtControl<T>, SafeExtControl<T>), ioctl::ExtControlError> {
+        let mut min = SafeExtControl::<T>::default();
+        let mut max = SafeExtControl::<T>::default();
+
+        ioctl::g_ext_ctrls(self, ioctl::CtrlWhich::MinimumValue, &mut min)?;
+        ioctl::g_ext_ctrls(self, ioctl::CtrlWhich::MaximumValue, &mut max)?;
+
+        Ok((min, max))
+    }
+
+    /// Returns the current format of the single-planar `queue`, including its colorimetry.
+    ///
+    /// Returns `EINVAL` if `queue` is not a single-planar video queue supported by the device.
+    pub fn current_format(&self, queue: QueueType) -> Result<FormatResult, Errno> {
+        Ok(ioctl::g_fmt(self, queue)?)
+    }
+
+    /// Returns the current format of the multi-planar `queue`, including its colorimetry.
+    ///
+    /// Returns `EINVAL` if `queue` is not a multi-planar video queue supported by the device.
+    pub fn current_mplane_format(
+        &self,
+        queue: QueueType,
+    ) -> Result<MultiplanarFormatResult, Errno> {
+        Ok(ioctl::g_fmt(self, queue)?)
+    }
+
+    /// Returns the streaming parameters of `queue`, e.g. its frame rate, using `VIDIOC_G_PARM`.
+    pub fn get_stream_params(&self, queue: QueueType) -> Result<StreamParm, Errno> {
+        Ok(ioctl::g_parm(self, queue)?)
+    }
+
+    /// Sets the streaming parameters of the queue `params` apply to using `VIDIOC_S_PARM`, and
+    /// returns the parameters actually selected by the driver.
+    ///
+    /// `params` should be obtained with [`Device::get_stream_params`] and modified, so the
+    /// parameters that are not meant to be changed keep their current value.
+    pub fn set_stream_params(&self, params: StreamParm) -> Result<StreamParm, Errno> {
+        Ok(ioctl::s_parm(self, params)?)
+    }
+
+    /// Drain a stateful decoder: send `V4L2_DEC_CMD_STOP`, then dequeue the decoded frames of the
+    /// capture queue until the last one, and return them in decoding order.
+    ///
+    /// The drain is complete once the decoder has dequeued a buffer with `V4L2_BUF_FLAG_LAST`,
+    /// returned `EPIPE` from `VIDIOC_DQBUF`, or signaled `V4L2_EVENT_EOS` and has no buffer left
+    /// to dequeue. The full shutdown sequence of a decoder is then:
+    ///
+    /// 1. Stop queuing buffers to the `OUTPUT` queue, and call this method.
+    /// 2. Process the returned frames, which are not part of any `Queue` anymore.
+    /// 3. Stream off both queues with `VIDIOC_STREAMOFF`, which returns the remaining buffers of
+    ///    the `OUTPUT` queue and resets the drain, so decoding can resume after streaming on.
+    ///
+    /// Streaming off the `CAPTURE` queue before the drain is complete discards the frames which
+    /// have not been dequeued yet.
+    ///
+    /// The buffers are dequeued directly on the device, so if the `CAPTURE` queue is managed by a
+    /// [`queue::Queue`], its bookkeeping is bypassed and the buffers must be recovered by
+    /// streaming it off. Drivers must signal the end of the drain with `V4L2_EVENT_EOS`, which
+    /// this method subscribes to: the subscription is kept afterwards.
+    ///
+    /// Returns [`DrainError::Timeout`] with the frames dequeued so far if the drain is not
+    /// complete after `timeout`, and [`DrainError::DeviceError`] if the device reports an error
+    /// while polling, e.g. because the `CAPTURE` queue has no buffer queued.
+    ///
+    /// This method is meant for programs that drive the queues of a decoder themselves, e.g.
+    /// using [`queue::Queue`] directly. The [`crate::decoder::stateful::Decoder`] manages its
+    /// `CAPTURE` queue from its own thread and must be drained with its own `drain` method
+    /// instead, as this method would race with that thread for the dequeued buffers.
+    pub fn drain_decoder(&self, timeout: Duration) -> Result<Vec<ioctl::V4l2Buffer>, DrainError> {
+        self.drain_decoder_with(timeout, |timeout| {
+            let mut fds = [PollFd::new(
+                self.as_fd(),
+                PollFlags::POLLIN | PollFlags::POLLPRI,
+            )];
+            let poll_timeout = PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX);
+            match nix::poll::poll(&mut fds, poll_timeout)? {
+                0 => Ok(PollFlags::empty()),
+                _ => Ok(fds[0].revents().unwrap_or(PollFlags::empty())),
+            }
+        })
+    }
+
+    /// Implementation of [`Device::drain_decoder`]. `poll` waits for the device to become ready
+    /// for at most the given duration and returns the reported events, or no event on timeout.
+    fn drain_decoder_with<F>(
+        &self,
+        timeout: Duration,
+        mut poll: F,
+    ) -> Result<Vec<ioctl::V4l2Buffer>, DrainError>
+    where
+        F: FnMut(Duration) -> Result<PollFlags, Errno>,
+    {
+        let deadline = Instant::now() + timeout;
+        let queue = if self
+            .caps()
+            .device_caps()
+            .intersects(Capabilities::VIDEO_M2M_MPLANE | Capabilities::VIDEO_CAPTURE_MPLANE)
+        {
+            QueueType::VideoCaptureMplane
+        } else {
+            QueueType::VideoCapture
+        };
+
+        // The drain can still be detected through the LAST flag if the driver does not support
+        // the event, so a failed subscription is not fatal.
+        let _ = ioctl::subscribe_event(
+            self,
+            ioctl::EventType::Eos,
+            ioctl::SubscribeEventFlags::empty(),
+        );
+        ioctl::decoder_cmd::<_, ()>(self, ioctl::DecoderCmd::stop()).map_err(DrainError::Stop)?;
+
+        let mut buffers = Vec::new();
+        let mut eos = false;
+        loop {
+            // Check the deadline on every iteration, as the device may keep waking us up without
+            // anything to dequeue.
+            let now = Instant::now();
+            if !eos && now >= deadline {
+                return Err(DrainError::Timeout(buffers));
+            }
+
+            // Once the EOS event has been received, only the buffers that are already available
+            // need to be dequeued.
+            let remaining = if eos { Duration::ZERO } else { deadline - now };
+            let revents = match poll(remaining) {
+                Ok(revents) if revents.is_empty() && eos => return Ok(buffers),
+                Ok(revents) if revents.is_empty() => return Err(DrainError::Timeout(buffers)),
+                Ok(revents) => revents,
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(DrainError::Poll(e)),
+            };
+            if revents.intersects(PollFlags::POLLERR | PollFlags::POLLHUP) {
+                return Err(DrainError::DeviceError(buffers));
+            }
+
+            // Only dequeue one event or buffer per wakeup, as the device may be in blocking mode.
+            if revents.contains(PollFlags::POLLPRI) {
+                match ioctl::dqevent(self) {
+                    Ok(ioctl::Event::Eos) => eos = true,
+                    Ok(_) | Err(ioctl::DqEventError::NotReady) => (),
+                    Err(e) => return Err(DrainError::Event(e)),
+                }
+            }
+
+            if revents.contains(PollFlags::POLLIN) {
+                match ioctl::dqbuf::<ioctl::V4l2Buffer>(self, queue) {
+                    Ok(buffer) => {
+                        let last = buffer.is_last();
+                        buffers.push(buffer);
+                        if last {
+                            return Ok(buffers);
+                        }
+                    }
+                    Err(ioctl::DqBufError::IoctlError(ioctl::DqBufIoctlError::Eos)) => {
+                        return Ok(buffers)
+                    }
+                    Err(ioctl::DqBufError::IoctlError(ioctl::DqBufIoctlError::NotReady)) => (),
+                    Err(e) => return Err(DrainError::Dequeue(e)),
+                }
+            }
+        }
+    }
+
+    /// Returns the name of the currently selected video input.
+    pub fn current_input_name(&self) -> Result<String, Errno> {
+        let index = ioctl::g_input(self)?;
+        let input: v4l2_input = ioctl::enuminput(self, index)?;
+
+        Ok(input_name(&input))
+    }
 }
 
 impl AsFd for Device {
@@ -109,3 +574,187 @@ impl AsRawFd for Device {
         self.fd.as_raw_fd()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nix::errno::Errno;
+    use nix::fcntl::OFlag;
+    use nix::poll::PollFlags;
+
+    use super::{Device, DeviceOpenFlags};
+    use crate::bindings;
+    use crate::ioctl::backend::mock::MockIoctls;
+    use crate::ioctl::{BufferFlags, Capabilities};
+    use crate::memory::MemoryType;
+    use crate::QueueType;
+
+    #[test]
+    fn test_device_open_flags() {
+        assert_eq!(
+            DeviceOpenFlags::default().oflags(),
+            OFlag::O_RDWR | OFlag::O_CLOEXEC
+        );
+        assert_eq!(
+            DeviceOpenFlags::new().non_blocking_dqbuf(),
+            DeviceOpenFlags::nonblocking()
+        );
+        assert_eq!(
+            DeviceOpenFlags::nonblocking().oflags(),
+            OFlag::O_RDWR | OFlag::O_CLOEXEC | OFlag::O_NONBLOCK
+        );
+        assert_eq!(
+            DeviceOpenFlags::read_only().oflags(),
+            OFlag::O_RDONLY | OFlag::O_CLOEXEC
+        );
+        assert_eq!(
+            DeviceOpenFlags::monitoring().oflags(),
+            OFlag::O_RDONLY | OFlag::O_CLOEXEC | OFlag::O_NONBLOCK
+        );
+        let flags = DeviceOpenFlags {
+            close_on_exec: false,
+            ..Default::default()
+        };
+        assert_eq!(flags.oflags(), OFlag::O_RDWR);
+    }
+
+    /// Expects a `VIDIOC_DQBUF` on the capture queue returning buffer `index` with `flags`.
+    fn expect_capture_buffer(mock: &MockIoctls, index: u32, flags: BufferFlags) {
+        mock.expect_with("vidioc_dqbuf", move |buf: &mut bindings::v4l2_buffer| {
+            assert_eq!(buf.type_, QueueType::VideoCapture as u32);
+            buf.index = index;
+            buf.memory = MemoryType::Mmap as u32;
+            buf.flags = flags.bits();
+            buf.length = 4096;
+            Ok(0)
+        });
+    }
+
+    #[test]
+    fn test_current_input_name() {
+        let mock = MockIoctls::new();
+        mock.expect("vidioc_querycap", Ok(0));
+        let device = Device::new(mock.file()).unwrap();
+
+        mock.expect_with("vidioc_g_input", |input: &mut std::ffi::c_int| {
+            *input = 1;
+            Ok(0)
+        })
+        .expect_with("vidioc_enuminput", |input: &mut bindings::v4l2_input| {
+            assert_eq!(input.index, 1);
+            input.name[..5].copy_from_slice(b"S-Vid");
+            Ok(0)
+        });
+        assert_eq!(device.current_input_name().unwrap(), "S-Vid");
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_drain_decoder() {
+        let mock = MockIoctls::new();
+        mock.expect_with("vidioc_querycap", |cap: &mut bindings::v4l2_capability| {
+            let caps = Capabilities::VIDEO_M2M | Capabilities::STREAMING;
+            cap.capabilities = (caps | Capabilities::DEVICE_CAPS).bits();
+            cap.device_caps = caps.bits();
+            Ok(0)
+        });
+        let device = Device::new(mock.file()).unwrap();
+
+        // The mock file is always readable, so each iteration dequeues a buffer.
+        mock.expect("vidioc_subscribe_event", Err(Errno::EINVAL))
+            .expect_with(
+                "vidioc_decoder_cmd",
+                |cmd: &mut bindings::v4l2_decoder_cmd| {
+                    assert_eq!(cmd.cmd, bindings::V4L2_DEC_CMD_STOP);
+                    Ok(0)
+                },
+            )
+            .expect("vidioc_dqbuf", Err(Errno::EAGAIN));
+        expect_capture_buffer(&mock, 0, BufferFlags::empty());
+        expect_capture_buffer(&mock, 1, BufferFlags::LAST);
+        let buffers = device.drain_decoder(Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            buffers.iter().map(|b| b.index()).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+
+        // Drivers may also signal the end of the drain with `EPIPE`.
+        mock.expect("vidioc_subscribe_event", Ok(0))
+            .expect("vidioc_decoder_cmd", Ok(0));
+        expect_capture_buffer(&mock, 2, BufferFlags::empty());
+        mock.expect("vidioc_dqbuf", Err(Errno::EPIPE));
+        let buffers = device.drain_decoder(Duration::from_secs(1)).unwrap();
+        assert_eq!(buffers.len(), 1);
+
+        mock.expect("vidioc_subscribe_event", Ok(0))
+            .expect("vidioc_decoder_cmd", Err(Errno::EBUSY));
+        assert!(matches!(
+            device.drain_decoder(Duration::from_secs(1)),
+            Err(super::DrainError::Stop(_))
+        ));
+        mock.assert_done();
+    }
+
+    fn drain_test_device(mock: &MockIoctls) -> Device {
+        mock.expect_with("vidioc_querycap", |cap: &mut bindings::v4l2_capability| {
+            let caps = Capabilities::VIDEO_M2M | Capabilities::STREAMING;
+            cap.capabilities = (caps | Capabilities::DEVICE_CAPS).bits();
+            cap.device_caps = caps.bits();
+            Ok(0)
+        });
+        let device = Device::new(mock.file()).unwrap();
+        mock.expect("vidioc_subscribe_event", Ok(0))
+            .expect("vidioc_decoder_cmd", Ok(0));
+
+        device
+    }
+
+    #[test]
+    fn test_drain_decoder_poll_error() {
+        let mock = MockIoctls::new();
+        let device = drain_test_device(&mock);
+
+        // A device that keeps reporting an error must not be polled forever.
+        expect_capture_buffer(&mock, 0, BufferFlags::empty());
+        let mut polls = 0;
+        let res = device.drain_decoder_with(Duration::from_secs(1), |_| {
+            polls += 1;
+            if polls == 1 {
+                Ok(PollFlags::POLLIN)
+            } else {
+                Ok(PollFlags::POLLIN | PollFlags::POLLERR)
+            }
+        });
+        match res {
+            Err(super::DrainError::DeviceError(buffers)) => assert_eq!(buffers.len(), 1),
+            _ => panic!("unexpected drain result"),
+        }
+        assert_eq!(polls, 2);
+
+        mock.expect("vidioc_subscribe_event", Ok(0))
+            .expect("vidioc_decoder_cmd", Ok(0));
+        assert!(matches!(
+            device.drain_decoder_with(Duration::from_secs(1), |_| Ok(PollFlags::POLLHUP)),
+            Err(super::DrainError::DeviceError(_))
+        ));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_drain_decoder_deadline() {
+        let mock = MockIoctls::new();
+        let device = drain_test_device(&mock);
+
+        // The device keeps waking us up without anything to dequeue: the deadline must still be
+        // honored.
+        let timeout = Duration::from_millis(20);
+        mock.expect("vidioc_dqbuf", Err(Errno::EAGAIN));
+        let res = device.drain_decoder_with(timeout, |_| {
+            std::thread::sleep(timeout);
+            Ok(PollFlags::POLLIN)
+        });
+        assert!(matches!(res, Err(super::DrainError::Timeout(b)) if b.is_empty()));
+        mock.assert_done();
+    }
+}
@@ -0,0 +1,203 @@
+//! Find out which formats a device accepts without changing its state.
+//!
+//! Configuring a pipeline often requires trying several pixel formats until one is supported by
+//! the driver, e.g. to select a codec automatically. [`FormatProber`] uses `VIDIOC_TRY_FMT` for
+//! this purpose, so the current format of the device is left untouched.
+use crate::{
+    device::Device,
+    ioctl::{self, FormatIterator, TryFmtError},
+    Format, PixelFormat, QueueType,
+};
+
+/// Tries formats on a given queue of a device.
+pub struct FormatProber<'a> {
+    device: &'a Device,
+    queue: QueueType,
+}
+
+impl<'a> FormatProber<'a> {
+    /// Create a new prober for formats of `queue` on `device`.
+    pub fn new(device: &'a Device, queue: QueueType) -> Self {
+        FormatProber { device, queue }
+    }
+
+    /// Returns the format the driver would use if `pixelformat` was set with a resolution of
+    /// `width`x`height`, or `None` if the driver does not support `pixelformat`.
+    ///
+    /// The resolution of the returned format may differ from the requested one, as the driver is
+    /// free to adjust it. Errors other than the format being rejected are returned.
+    pub fn probe_format(
+        &self,
+        pixelformat: PixelFormat,
+        width: u32,
+        height: u32,
+    ) -> Result<Option<Format>, TryFmtError> {
+        let format = Format {
+            width,
+            height,
+            pixelformat,
+            ..Default::default()
+        };
+
+        match ioctl::try_fmt::<_, Format>(self.device, (self.queue, &format)) {
+            // Drivers replace unsupported pixel formats with one of theirs instead of failing.
+            Ok(format) => Ok(Some(format).filter(|format| format.pixelformat == pixelformat)),
+            // ... but some of them reject them with `EINVAL`.
+            Err(TryFmtError::InvalidBufferType) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Probes all the pixel formats enumerated by the queue at a resolution of `width`x`height`,
+    /// and returns the ones accepted by the driver along with the format it would use for each.
+    pub fn probe_all_formats(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<(PixelFormat, Format)>, TryFmtError> {
+        let mut formats = Vec::new();
+        for fmtdesc in FormatIterator::new(self.device, self.queue) {
+            if let Some(format) = self.probe_format(fmtdesc.pixelformat, width, height)? {
+                formats.push((fmtdesc.pixelformat, format));
+            }
+        }
+
+        Ok(formats)
+    }
+
+    /// Returns the format for the first pixel format of `preferences` accepted by the driver at a
+    /// resolution of `width`x`height`, or `None` if none of them is supported.
+    pub fn preferred_format(
+        &self,
+        preferences: &[PixelFormat],
+        width: u32,
+        height: u32,
+    ) -> Result<Option<Format>, TryFmtError> {
+        for &pixelformat in preferences {
+            if let Some(format) = self.probe_format(pixelformat, width, height)? {
+                return Ok(Some(format));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::FormatProber;
+    use crate::bindings;
+    use crate::ioctl::backend::mock::{mock_device, MockIoctls};
+    use crate::ioctl::TryFmtError;
+    use crate::{PixelFormat, QueueType};
+
+    /// Expects a `VIDIOC_TRY_FMT` of `pixelformat`, to which the driver answers with
+    /// `driver_format`, or fails with `result`.
+    fn expect_try_fmt(
+        mock: &MockIoctls,
+        pixelformat: &'static [u8; 4],
+        driver_format: &'static [u8; 4],
+        result: nix::Result<i32>,
+    ) {
+        mock.expect_with("vidioc_try_fmt", move |fmt: &mut bindings::v4l2_format| {
+            assert_eq!(fmt.type_, QueueType::VideoCapture as u32);
+            // SAFETY: the format is single-planar.
+            let pix = unsafe { &mut fmt.fmt.pix };
+            assert_eq!(
+                PixelFormat::from(pix.pixelformat),
+                PixelFormat::from(pixelformat)
+            );
+            pix.pixelformat = PixelFormat::from(driver_format).into();
+            // The driver aligns the resolution.
+            pix.width = 640;
+            pix.height = 480;
+            result
+        });
+    }
+
+    #[test]
+    fn test_probe_format() {
+        let mock = MockIoctls::new();
+        let device = mock_device(&mock);
+        let prober = FormatProber::new(&device, QueueType::VideoCapture);
+
+        expect_try_fmt(&mock, b"NV12", b"NV12", Ok(0));
+        let format = prober
+            .probe_format(PixelFormat::from(b"NV12"), 641, 481)
+            .unwrap()
+            .unwrap();
+        assert_eq!(format.pixelformat, PixelFormat::from(b"NV12"));
+        assert_eq!((format.width, format.height), (640, 480));
+
+        // Unsupported formats are either replaced or rejected.
+        expect_try_fmt(&mock, b"NV12", b"YUYV", Ok(0));
+        assert!(prober
+            .probe_format(PixelFormat::from(b"NV12"), 640, 480)
+            .unwrap()
+            .is_none());
+        expect_try_fmt(&mock, b"NV12", b"NV12", Err(Errno::EINVAL));
+        assert!(prober
+            .probe_format(PixelFormat::from(b"NV12"), 640, 480)
+            .unwrap()
+            .is_none());
+
+        // Other errors are reported.
+        expect_try_fmt(&mock, b"NV12", b"NV12", Err(Errno::EIO));
+        assert!(matches!(
+            prober.probe_format(PixelFormat::from(b"NV12"), 640, 480),
+            Err(TryFmtError::IoctlError(Errno::EIO))
+        ));
+
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_preferred_format() {
+        let mock = MockIoctls::new();
+        let device = mock_device(&mock);
+        let prober = FormatProber::new(&device, QueueType::VideoCapture);
+        let preferences = [PixelFormat::from(b"NV12"), PixelFormat::from(b"YUYV")];
+
+        expect_try_fmt(&mock, b"NV12", b"NV12", Err(Errno::EINVAL));
+        expect_try_fmt(&mock, b"YUYV", b"YUYV", Ok(0));
+        let format = prober.preferred_format(&preferences, 640, 480).unwrap();
+        assert_eq!(format.unwrap().pixelformat, PixelFormat::from(b"YUYV"));
+
+        // Errors stop the search.
+        expect_try_fmt(&mock, b"NV12", b"NV12", Err(Errno::EBUSY));
+        assert!(matches!(
+            prober.preferred_format(&preferences, 640, 480),
+            Err(TryFmtError::IoctlError(Errno::EBUSY))
+        ));
+
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_probe_all_formats() {
+        let mock = MockIoctls::new();
+        let device = mock_device(&mock);
+        let prober = FormatProber::new(&device, QueueType::VideoCapture);
+
+        for (index, fourcc) in [b"NV12", b"YUYV"].into_iter().enumerate() {
+            mock.expect_with(
+                "vidioc_enum_fmt",
+                move |fmt: &mut bindings::v4l2_fmtdesc| {
+                    assert_eq!(fmt.index, index as u32);
+                    fmt.pixelformat = PixelFormat::from(fourcc).into();
+                    Ok(0)
+                },
+            );
+            expect_try_fmt(&mock, fourcc, b"YUYV", Ok(0));
+        }
+        mock.expect("vidioc_enum_fmt", Err(Errno::EINVAL));
+
+        let formats = prober.probe_all_formats(640, 480).unwrap();
+        assert_eq!(formats.len(), 1);
+        assert_eq!(formats[0].0, PixelFormat::from(b"YUYV"));
+
+        mock.assert_done();
+    }
+}
@@ -0,0 +1,182 @@
+//! Interface to media controller devices, i.e. the `/dev/media*` nodes describing how the entities
+//! of complex pipelines (sensors, ISPs, video nodes, ...) are connected.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::bindings;
+use crate::error::AsErrno;
+use crate::ioctl::{
+    self, GTopologyError, LinkFlags, MediaEntity, MediaInterface, MediaPad, Topology,
+};
+
+#[derive(Debug, Error)]
+pub enum MediaDeviceError {
+    #[error("error while getting the topology: {0}")]
+    Topology(#[from] GTopologyError),
+    #[error("no entity with ID {0}")]
+    NoSuchEntityId(u32),
+    #[error("no entity named {0:?}")]
+    NoSuchEntityName(String),
+    #[error("entity {0:?} has no device node")]
+    NoDeviceNode(String),
+    #[error("error while reading {0}: {1}")]
+    Sysfs(PathBuf, io::Error),
+    #[error("no device name found in {0}")]
+    NoDeviceName(PathBuf),
+}
+
+impl AsErrno for MediaDeviceError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            MediaDeviceError::Topology(e) => e.errno(),
+            MediaDeviceError::Sysfs(_, e) => e.errno(),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the value of `DEVNAME` in `uevent`, the content of the `uevent` file of a device in
+/// sysfs.
+fn uevent_devname(uevent: &str) -> Option<&str> {
+    uevent
+        .lines()
+        .find_map(|line| line.strip_prefix("DEVNAME="))
+        .filter(|name| !name.is_empty())
+}
+
+/// Returns the path of the device node of `intf`, as reported by sysfs.
+fn interface_device_path(intf: &MediaInterface) -> Result<PathBuf, MediaDeviceError> {
+    let uevent_path = PathBuf::from(format!(
+        "/sys/dev/char/{}:{}/uevent",
+        intf.major, intf.minor
+    ));
+    let uevent = std::fs::read_to_string(&uevent_path)
+        .map_err(|e| MediaDeviceError::Sysfs(uevent_path.clone(), e))?;
+
+    match uevent_devname(&uevent) {
+        Some(name) => Ok(Path::new("/dev").join(name)),
+        None => Err(MediaDeviceError::NoDeviceName(uevent_path)),
+    }
+}
+
+/// An opened media controller device.
+pub struct MediaDevice {
+    fd: File,
+}
+
+impl MediaDevice {
+    pub fn open(path: &Path) -> Result<Self, nix::Error> {
+        use nix::fcntl::{open, OFlag};
+        use nix::sys::stat::Mode;
+
+        let fd = open(path, OFlag::O_RDWR | OFlag::O_CLOEXEC, Mode::empty())?;
+
+        // Safe because we are constructing a file from Fd we just opened.
+        Ok(MediaDevice {
+            fd: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    /// Returns the current topology of the device, i.e. its entities, interfaces, pads and the
+    /// links between them.
+    pub fn get_topology(&self) -> Result<Topology, Errno> {
+        Ok(ioctl::g_topology(self)?)
+    }
+
+    /// Sets the flags of the data link going from pad `source` to pad `sink`, e.g. to enable it
+    /// with [`LinkFlags::ENABLED`].
+    pub fn setup_link(
+        &self,
+        source: &MediaPad,
+        sink: &MediaPad,
+        flags: LinkFlags,
+    ) -> Result<(), Errno> {
+        Ok(ioctl::setup_link(self, source, sink, flags)?)
+    }
+
+    /// Returns the path of the device node of `entity` in `topology`, e.g. `/dev/video0`.
+    pub(crate) fn entity_device_path(
+        topology: &Topology,
+        entity: &MediaEntity,
+    ) -> Result<PathBuf, MediaDeviceError> {
+        let intf = topology
+            .entity_interface(entity)
+            .ok_or_else(|| MediaDeviceError::NoDeviceNode(entity.name.clone()))?;
+
+        interface_device_path(intf)
+    }
+
+    /// Returns the path of the device node of the entity with ID `entity_id`, e.g. `/dev/video0`
+    /// for a video node or `/dev/v4l-subdev0` for a sub-device, so it can be opened.
+    pub fn video_device_for_entity(&self, entity_id: u32) -> Result<PathBuf, MediaDeviceError> {
+        let topology = ioctl::g_topology(self)?;
+        let entity = topology
+            .entity(entity_id)
+            .ok_or(MediaDeviceError::NoSuchEntityId(entity_id))?;
+
+        Self::entity_device_path(&topology, entity)
+    }
+
+    /// Returns the path of the device node of the entity named `entity_name`.
+    pub fn find_video_device(&self, entity_name: &str) -> Result<PathBuf, MediaDeviceError> {
+        let topology = ioctl::g_topology(self)?;
+        let entity = topology
+            .entity_by_name(entity_name)
+            .ok_or_else(|| MediaDeviceError::NoSuchEntityName(entity_name.to_string()))?;
+
+        Self::entity_device_path(&topology, entity)
+    }
+
+    /// Returns all the entities of the device which are exposed as V4L2 video nodes, along with
+    /// the paths of these nodes.
+    pub fn find_all_video_devices(&self) -> Result<Vec<(MediaEntity, PathBuf)>, MediaDeviceError> {
+        let topology = ioctl::g_topology(self)?;
+
+        topology
+            .entities
+            .iter()
+            .filter_map(|entity| {
+                topology
+                    .entity_interface(entity)
+                    .filter(|intf| intf.intf_type == bindings::MEDIA_INTF_T_V4L_VIDEO)
+                    .map(|intf| (entity, intf))
+            })
+            .map(|(entity, intf)| Ok((entity.clone(), interface_device_path(intf)?)))
+            .collect()
+    }
+}
+
+impl AsFd for MediaDevice {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for MediaDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::uevent_devname;
+
+    #[test]
+    fn test_uevent_devname() {
+        let uevent = "MAJOR=81\nMINOR=3\nDEVNAME=video3\n";
+        assert_eq!(uevent_devname(uevent), Some("video3"));
+
+        let uevent = "MAJOR=81\nMINOR=12\nDEVNAME=v4l-subdev2\n";
+        assert_eq!(uevent_devname(uevent), Some("v4l-subdev2"));
+
+        assert_eq!(uevent_devname("MAJOR=81\nMINOR=3\n"), None);
+        assert_eq!(uevent_devname("DEVNAME=\n"), None);
+    }
+}
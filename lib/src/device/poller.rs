@@ -29,6 +29,7 @@ use nix::{
 use thiserror::Error;
 
 use crate::device::Device;
+use crate::error::AsErrno;
 
 #[derive(Debug, PartialEq)]
 pub enum DeviceEvent {
@@ -180,6 +181,17 @@ pub enum PollError {
     V4L2Device,
 }
 
+impl AsErrno for PollError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            PollError::TimeoutTryFromError => None,
+            PollError::EPollWait(e) => Some(*e),
+            PollError::WakerReset(e) => Some(*e),
+            PollError::V4L2Device => None,
+        }
+    }
+}
+
 impl Poller {
     pub fn new(device: Arc<Device>) -> nix::Result<Self> {
         let epoll = Epoll::new(EpollCreateFlags::EPOLL_CLOEXEC)?;
@@ -6,7 +6,8 @@ pub mod handles_provider;
 pub mod qbuf;
 
 use super::{AllocatedQueue, Device, FreeBuffersResult, Stream, TryDequeue};
-use crate::ioctl::{DqBufResult, QueryBufError, V4l2BufferFromError};
+use crate::error::AsErrno;
+use crate::ioctl::{DqBufError, DqBufIoctlError, DqBufResult, QueryBufError, V4l2BufferFromError};
 use crate::{bindings, memory::*};
 use crate::{
     ioctl::{
@@ -15,16 +16,20 @@ use crate::{
     },
     PlaneLayout, Rect,
 };
-use crate::{Format, PixelFormat, QueueType};
+use crate::{Field, Format, PixelFormat, QueueType};
 use buffer::*;
 use direction::*;
 use dqbuf::*;
-use log::debug;
+use log::{debug, error, warn};
 use qbuf::*;
 
+use nix::errno::Errno;
+use nix::poll::{PollFlags, PollTimeout};
+use nix::sys::time::TimeVal;
 use std::convert::{Infallible, TryFrom};
-use std::os::unix::io::{AsRawFd, RawFd};
-use std::sync::{Arc, Weak};
+use std::os::unix::io::{AsFd, AsRawFd, RawFd};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 /// Base values of a queue, that are always value no matter the state the queue
@@ -63,9 +68,11 @@ where
     D: Direction,
     S: QueueState,
 {
+    // Declared first so it is dropped before `inner` releases the queue, as the drop handler of
+    // `BuffersAllocated` may still need to act on it.
+    state: S,
     inner: QueueBase,
     _d: std::marker::PhantomData<D>,
-    state: S,
 }
 
 /// Methods of `Queue` that are available no matter the state.
@@ -122,6 +129,28 @@ where
 
         ioctl::g_selection(&self.inner, selection, target)
     }
+
+    /// Set the `target` selection rectangle of the queue to `rect`. The driver may adjust the
+    /// rectangle, so the rectangle actually applied is returned.
+    pub fn set_selection(
+        &mut self,
+        target: SelectionTarget,
+        rect: Rect,
+    ) -> Result<Rect, ioctl::SSelectionError> {
+        let selection = match self.get_type() {
+            QueueType::VideoCapture | QueueType::VideoCaptureMplane => SelectionType::Capture,
+            QueueType::VideoOutput | QueueType::VideoOutputMplane => SelectionType::Output,
+            _ => return Err(ioctl::SSelectionError::Invalid),
+        };
+
+        ioctl::s_selection(
+            &self.inner,
+            selection,
+            target,
+            rect,
+            ioctl::SelectionFlags::empty(),
+        )
+    }
 }
 
 /// Builder for a V4L2 format. This takes a mutable reference on the queue, so
@@ -129,12 +158,30 @@ where
 pub struct FormatBuilder<'a> {
     queue: &'a mut QueueBase,
     format: Format,
+    requested: Option<&'a mut Option<Format>>,
 }
 
 impl<'a> FormatBuilder<'a> {
     fn new(queue: &'a mut QueueBase) -> Result<Self, GFmtError> {
         let format = ioctl::g_fmt(queue, queue.type_)?;
-        Ok(Self { queue, format })
+        Ok(Self {
+            queue,
+            format,
+            requested: None,
+        })
+    }
+
+    /// Make the builder store the first format it is asked to apply or try into `requested`, so
+    /// the caller can compare it with the format the driver actually applied.
+    pub(crate) fn record_requested(mut self, requested: &'a mut Option<Format>) -> Self {
+        self.requested = Some(requested);
+        self
+    }
+
+    fn save_requested(&mut self) {
+        if let Some(requested) = self.requested.as_mut() {
+            requested.get_or_insert_with(|| self.format.clone());
+        }
     }
 
     /// Get a reference to the format built so far. Useful for checking the
@@ -160,10 +207,26 @@ impl<'a> FormatBuilder<'a> {
         self
     }
 
+    /// Set the field order of the format, e.g. [`Field::Alternate`] to receive each field of an
+    /// interlaced source in its own buffer.
+    pub fn set_field(mut self, field: Field) -> Self {
+        self.format.field = field;
+        self
+    }
+
+    /// Replace the format built so far with `format`. Useful to propagate the
+    /// format of a queue to a queue of another device, e.g. the `CAPTURE` format
+    /// of a decoder to the `OUTPUT` queue of an encoder.
+    pub fn set_from(mut self, format: &Format) -> Self {
+        self.format = format.clone();
+        self
+    }
+
     /// Apply the format built so far. The kernel will adjust the format to fit
     /// the driver's capabilities if needed, and the format actually applied will
     /// be returned.
-    pub fn apply<O: TryFrom<bindings::v4l2_format>>(self) -> Result<O, SFmtError> {
+    pub fn apply<O: TryFrom<bindings::v4l2_format>>(mut self) -> Result<O, SFmtError> {
+        self.save_requested();
         ioctl::s_fmt(self.queue, (self.queue.type_, &self.format))
     }
 
@@ -174,6 +237,7 @@ impl<'a> FormatBuilder<'a> {
     /// Calling `apply()` right after this method is guaranteed to successfully
     /// apply the format without further change.
     pub fn try_apply(&mut self) -> Result<(), TryFmtError> {
+        self.save_requested();
         let new_format = ioctl::try_fmt(self.queue, (self.queue.type_, &self.format))?;
 
         self.format = new_format;
@@ -195,6 +259,15 @@ pub enum CreateQueueError {
     ReqbufsError(#[from] ioctl::ReqbufsError),
 }
 
+impl AsErrno for CreateQueueError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            CreateQueueError::AlreadyBorrowed => None,
+            CreateQueueError::ReqbufsError(e) => e.errno(),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum RequestBuffersError {
     #[error("error while requesting buffers")]
@@ -203,6 +276,15 @@ pub enum RequestBuffersError {
     QueryBufferError(#[from] QueryBufError<Infallible>),
 }
 
+impl AsErrno for RequestBuffersError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            RequestBuffersError::ReqbufsError(e) => e.errno(),
+            RequestBuffersError::QueryBufferError(e) => e.errno(),
+        }
+    }
+}
+
 impl<D: Direction> Queue<D, QueueInit> {
     /// Create a queue for type `queue_type` on `device`. A queue of a specific type
     /// can be requested only once.
@@ -259,6 +341,12 @@ impl<D: Direction> Queue<D, QueueInit> {
             "Requested {} buffers on {} queue, obtained {}",
             count, type_, num_buffers
         );
+        queue_event!(
+            type_,
+            requested = count,
+            num_buffers,
+            "state: Init -> BuffersAllocated"
+        );
 
         // The buffers have been allocated, now let's get their features.
         // We cannot use functional programming here because we need to return
@@ -268,6 +356,11 @@ impl<D: Direction> Queue<D, QueueInit> {
             buffer_features.push(ioctl::querybuf(&self.inner, self.inner.type_, i)?);
         }
 
+        // The format cannot change while buffers are allocated, so keep it to validate the buffers
+        // before queuing them. Queues whose format is not a `Format`, e.g. metadata ones, are not
+        // validated against it.
+        let format = ioctl::g_fmt::<Format>(&self.inner, type_).ok();
+
         let buffer_stats = Arc::new(BufferStats::new());
 
         let buffer_info = buffer_features
@@ -278,13 +371,23 @@ impl<D: Direction> Queue<D, QueueInit> {
             .collect();
 
         Ok(Queue {
-            inner: self.inner,
-            _d: std::marker::PhantomData,
             state: BuffersAllocated {
                 memory_type,
                 buffer_info,
                 buffer_stats,
+                sequence_tracker: Default::default(),
+                frame_drop_cb: Default::default(),
+                format,
+                request_usage: Default::default(),
+                releaser: BuffersReleaser {
+                    device: Arc::clone(&self.inner.device),
+                    queue_type: type_,
+                    memory_type: memory_type.into(),
+                    armed: true,
+                },
             },
+            inner: self.inner,
+            _d: std::marker::PhantomData,
         })
     }
 
@@ -332,25 +435,94 @@ impl Queue<Capture, QueueInit> {
     pub fn get_capture_mplane_queue(device: Arc<Device>) -> Result<Self, CreateQueueError> {
         Queue::<Capture, QueueInit>::create(device, QueueType::VideoCaptureMplane)
     }
+
+    /// Acquires the META_CAPTURE queue from `device`.
+    ///
+    /// This method will fail if the queue has already been obtained and has not
+    /// yet been released.
+    pub fn get_meta_capture_queue(device: Arc<Device>) -> Result<Self, CreateQueueError> {
+        Queue::<Capture, QueueInit>::create(device, QueueType::MetaCapture)
+    }
 }
 
 /// Allocated state for a queue. A queue with its buffers allocated can be
 /// streamed on and off, and buffers can be queued and dequeued.
+///
+/// Dropping a queue in this state stops streaming and frees its buffers, but any error occurring
+/// in the process can only be logged. Use [`Queue::close`] to be notified of these errors.
 pub struct BuffersAllocated<P: BufferHandles> {
     memory_type: P::SupportedMemoryType,
     /// Keep one `Arc` per buffer. This allows us to invalidate this buffer only in case it gets
     /// deallocated alone (V4L2 currently does not allow this, but might in the future).
     buffer_info: Vec<Arc<BufferInfo<P>>>,
     buffer_stats: Arc<BufferStats>,
+    sequence_tracker: Mutex<SequenceTracker>,
+    /// Shared so it can be called without holding the lock of the queue, which lets the callback
+    /// replace itself.
+    frame_drop_cb: Mutex<Option<Arc<Mutex<FrameDropCb>>>>,
+    /// Format of the queue when the buffers were allocated, if it could be obtained.
+    format: Option<Format>,
+    /// Whether the buffers queued since streaming on used requests.
+    request_usage: Mutex<RequestUsage>,
+    releaser: BuffersReleaser,
 }
 impl<P: BufferHandles> QueueState for BuffersAllocated<P> {}
 
+/// Stops streaming and frees the buffers of a queue when dropped, unless the buffers have been
+/// explicitly freed before.
+///
+/// MMAP buffers do not need to be unmapped here, as their mappings only live as long as the
+/// `PlaneMapping`s obtained from them. Buffers that are still mapped or exported at that time
+/// will make the freeing fail, and only be released when the device is closed.
+struct BuffersReleaser {
+    device: Arc<Device>,
+    queue_type: QueueType,
+    memory_type: MemoryType,
+    armed: bool,
+}
+
+impl Drop for BuffersReleaser {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+
+        let queue_type = self.queue_type;
+        if let Err(e) = ioctl::streamoff(&*self.device, queue_type) {
+            error!("Error while streaming off {} queue: {}", queue_type, e);
+        }
+        match ioctl::reqbufs::<()>(&*self.device, queue_type, self.memory_type, 0) {
+            Ok(()) => debug!("Freed all buffers on dropped {} queue", queue_type),
+            Err(e) => error!("Error while freeing buffers of {} queue: {}", queue_type, e),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CloseQueueError {
+    #[error("error while streaming off")]
+    StreamOffError(#[from] StreamOffError),
+    #[error("error while freeing buffers")]
+    ReqbufsError(#[from] ioctl::ReqbufsError),
+}
+
+impl AsErrno for CloseQueueError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            CloseQueueError::StreamOffError(e) => e.errno(),
+            CloseQueueError::ReqbufsError(e) => e.errno(),
+        }
+    }
+}
+
 impl<D: Direction, P: BufferHandles> Queue<D, BuffersAllocated<P>> {
     /// Return all the currently queued buffers as CanceledBuffers. This can
     /// be called after a explicit or implicit streamoff to inform the client
     /// of which buffers have been canceled and return their handles.
+    ///
+    /// The buffers are returned in the order they have been queued.
     fn cancel_queued_buffers(&self) -> Vec<CanceledBuffer<P>> {
-        let canceled_buffers: Vec<_> = self
+        let mut canceled_buffers: Vec<_> = self
             .state
             .buffer_info
             .iter()
@@ -373,18 +545,31 @@ impl<D: Direction, P: BufferHandles> Queue<D, BuffersAllocated<P>> {
                     }
                 })?;
 
-                Some(CanceledBuffer {
-                    index: buffer_info.features.index as u32,
-                    plane_handles,
-                })
+                Some((
+                    buffer_info.queued_at(),
+                    CanceledBuffer {
+                        index: buffer_info.features.index as u32,
+                        plane_handles,
+                    },
+                ))
             })
             .collect();
+        canceled_buffers.sort_by_key(|(queued_at, _)| *queued_at);
+        let canceled_buffers: Vec<_> = canceled_buffers
+            .into_iter()
+            .map(|(_, buffer)| buffer)
+            .collect();
 
         debug!(
             "{} buffers canceled on {} queue",
             canceled_buffers.len(),
             self.get_type()
         );
+        queue_event!(
+            self.get_type(),
+            canceled = canceled_buffers.len(),
+            "buffers canceled"
+        );
 
         assert_eq!(self.state.buffer_stats.num_queued(), 0);
 
@@ -410,6 +595,11 @@ impl<D: Direction, P: BufferHandles> Queue<D, BuffersAllocated<P>> {
 
         Ok(buffer_info)
     }
+
+    /// Returns the counters of free and queued buffers, which can be read from other threads.
+    pub(crate) fn buffer_stats(&self) -> Arc<BufferStats> {
+        Arc::clone(&self.state.buffer_stats)
+    }
 }
 
 impl<'a, D: Direction, P: BufferHandles + 'a> AllocatedQueue<'a, D>
@@ -427,11 +617,15 @@ impl<'a, D: Direction, P: BufferHandles + 'a> AllocatedQueue<'a, D>
         self.state.buffer_stats.num_free()
     }
 
-    fn free_buffers(self) -> Result<FreeBuffersResult<D, Self>, ioctl::ReqbufsError> {
+    fn free_buffers(mut self) -> Result<FreeBuffersResult<D, Self>, ioctl::ReqbufsError> {
+        // Errors are reported to the caller, so do not try again when the state is dropped.
+        self.state.releaser.armed = false;
+
         let type_ = self.inner.type_;
         ioctl::reqbufs::<()>(&self.inner, type_, self.state.memory_type.into(), 0)?;
 
         debug!("Freed all buffers on {} queue", type_);
+        queue_event!(type_, "state: BuffersAllocated -> Init");
 
         // reqbufs also performs an implicit streamoff, so return the cancelled
         // buffers.
@@ -439,15 +633,56 @@ impl<'a, D: Direction, P: BufferHandles + 'a> AllocatedQueue<'a, D>
 
         Ok(FreeBuffersResult {
             queue: Queue {
+                state: QueueInit {},
                 inner: self.inner,
                 _d: std::marker::PhantomData,
-                state: QueueInit {},
             },
             canceled_buffers,
         })
     }
 }
 
+impl<D: Direction, P: BufferHandles> Queue<D, BuffersAllocated<P>> {
+    /// Returns the memory type the buffers of the queue have been allocated with.
+    pub fn memory_type(&self) -> P::SupportedMemoryType {
+        self.state.memory_type
+    }
+
+    /// Stops streaming and frees all the buffers of the queue, returning the first error that
+    /// occurs.
+    ///
+    /// Dropping the queue performs the same steps, but errors are then only logged. Buffers that
+    /// were still queued are canceled and their handles dropped.
+    pub fn close(self) -> Result<(), CloseQueueError> {
+        self.stream_off()?;
+        self.free_buffers()?;
+
+        Ok(())
+    }
+}
+
+impl<D, P> Queue<D, BuffersAllocated<P>>
+where
+    D: Direction,
+    P: PrimitiveBufferHandles,
+    <P::HandleType as PlaneHandle>::Memory: SelfBacked,
+{
+    /// Prepares all the buffers of the queue using `VIDIOC_PREPARE_BUF`, so the driver does not
+    /// need to do it when they are first queued. This is optional but reduces the latency of the
+    /// first frames, and must be done before any buffer is queued.
+    ///
+    /// Preparing a buffer without its memory only works for `MMAP` buffers, so this is only
+    /// available for them. `USERPTR` and `DMABUF` buffers can be prepared one by one with their
+    /// handles using [`ioctl::prepare_buf`]. See [`ioctl::prepare_all_bufs`].
+    pub fn prepare_all(&self) -> Result<(), ioctl::PrepareAllBufsError> {
+        ioctl::prepare_all_bufs(
+            &self.inner,
+            self.inner.type_,
+            self.state.buffer_info.len() as u32,
+        )
+    }
+}
+
 /// Represents a queued buffer which has not been processed due to `streamoff`
 /// being called on a queue.
 pub struct CanceledBuffer<P: BufferHandles> {
@@ -463,13 +698,21 @@ impl<D: Direction, P: BufferHandles> Stream for Queue<D, BuffersAllocated<P>> {
     fn stream_on(&self) -> Result<(), StreamOnError> {
         debug!("{} queue streaming on", self.get_type());
         let type_ = self.inner.type_;
-        ioctl::streamon(&self.inner, type_)
+        ioctl::streamon(&self.inner, type_)?;
+        queue_event!(type_, "streaming on");
+        // The driver starts counting the frames from 0 again.
+        self.state.sequence_tracker.lock().unwrap().reset();
+
+        Ok(())
     }
 
     fn stream_off(&self) -> Result<Vec<Self::Canceled>, StreamOffError> {
         debug!("{} queue streaming off", self.get_type());
         let type_ = self.inner.type_;
         ioctl::streamoff(&self.inner, type_)?;
+        queue_event!(type_, "streaming off");
+        // Buffers can be queued with or without requests again.
+        *self.state.request_usage.lock().unwrap() = RequestUsage::Unknown;
 
         Ok(self.cancel_queued_buffers())
     }
@@ -481,6 +724,28 @@ impl<D: Direction, P: BufferHandles> TryDequeue for Queue<D, BuffersAllocated<P>
     fn try_dequeue(&self) -> DqBufResult<Self::Dequeued, V4l2BufferFromError> {
         let dqbuf: ioctl::V4l2Buffer = ioctl::dqbuf(&self.inner, self.inner.type_)?;
 
+        if self.inner.type_.is_capture() {
+            let dropped = self
+                .state
+                .sequence_tracker
+                .lock()
+                .unwrap()
+                .record(dqbuf.sequence());
+            if dropped > 0 {
+                debug!(
+                    "{} frames dropped before {} buffer {}",
+                    dropped,
+                    self.inner.type_,
+                    dqbuf.sequence()
+                );
+                queue_event!(self.inner.type_, dropped = dropped, "frames dropped");
+                let drop_cb = self.state.frame_drop_cb.lock().unwrap().clone();
+                if let Some(drop_cb) = drop_cb {
+                    (drop_cb.lock().unwrap())(dropped);
+                }
+            }
+        }
+
         let id = dqbuf.index() as usize;
 
         let buffer_info = self
@@ -509,6 +774,198 @@ impl<D: Direction, P: BufferHandles> TryDequeue for Queue<D, BuffersAllocated<P>
     }
 }
 
+#[derive(Debug, Error)]
+pub enum NextKeyframeError {
+    #[error("no buffer queued to receive a keyframe")]
+    NoBufferQueued,
+    #[error("no keyframe dequeued before the timeout expired")]
+    Timeout,
+    #[error("error while polling the device: {0}")]
+    PollError(Errno),
+    #[error("device signaled an error, e.g. the queue is not streaming")]
+    DeviceError,
+    #[error("error while dequeuing buffer: {0}")]
+    DqBufError(#[from] DqBufError<V4l2BufferFromError>),
+}
+
+impl AsErrno for NextKeyframeError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            NextKeyframeError::NoBufferQueued => None,
+            NextKeyframeError::Timeout => None,
+            NextKeyframeError::PollError(e) => Some(*e),
+            NextKeyframeError::DeviceError => None,
+            NextKeyframeError::DqBufError(e) => e.errno(),
+        }
+    }
+}
+
+impl<P: BufferHandles> Queue<Output, BuffersAllocated<P>> {
+    /// Queue the buffers returned by [`Stream::stream_off`] again with the payload they had, e.g.
+    /// to resume processing after restarting a stalled queue.
+    ///
+    /// The number of bytes used in each plane and the timestamp of the buffers are obtained from
+    /// the driver, which keeps them until the buffer is queued again. Buffers are queued in the
+    /// order of `canceled`, which is the order in which they have been queued initially. Returns
+    /// the buffers that could not be queued.
+    pub fn requeue_canceled(&self, canceled: Vec<CanceledBuffer<P>>) -> Vec<CanceledBuffer<P>>
+    where
+        for<'a> Self: GetOutputBufferByIndex<'a, P>,
+    {
+        canceled
+            .into_iter()
+            .filter_map(|buffer| {
+                let index = buffer.index as usize;
+                let (bytes_used, timestamp) = match ioctl::querybuf::<ioctl::V4l2Buffer>(
+                    &self.inner,
+                    self.inner.type_,
+                    index,
+                ) {
+                    Ok(v4l2_buf) => (
+                        v4l2_buf
+                            .planes_iter()
+                            .map(|plane| *plane.bytesused as usize)
+                            .collect::<Vec<_>>(),
+                        ioctl::timeval_to_nix(v4l2_buf.timestamp()),
+                    ),
+                    Err(e) => {
+                        warn!("Cannot query canceled buffer {}: {}", index, e);
+                        return Some(buffer);
+                    }
+                };
+                let qbuf =
+                    match <Self as GetOutputBufferByIndex<'_, P>>::try_get_buffer(self, index) {
+                        Ok(qbuf) => qbuf,
+                        Err(e) => {
+                            warn!("Cannot obtain canceled buffer {}: {}", index, e);
+                            return Some(buffer);
+                        }
+                    };
+
+                match qbuf
+                    .set_timestamp(timestamp)
+                    .queue_with_handles(buffer.plane_handles, &bytes_used)
+                {
+                    Ok(()) => None,
+                    Err(e) => {
+                        warn!("Cannot queue canceled buffer {} again: {}", index, e.error);
+                        Some(CanceledBuffer {
+                            index: buffer.index,
+                            plane_handles: e.plane_handles,
+                        })
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+impl<P: BufferHandles> Queue<Capture, BuffersAllocated<P>> {
+    /// Queue the buffers returned by [`Stream::stream_off`] again with the same handles, e.g. to
+    /// resume processing after restarting a stalled queue. Returns the buffers that could not be
+    /// queued.
+    pub fn requeue_canceled(&self, canceled: Vec<CanceledBuffer<P>>) -> Vec<CanceledBuffer<P>>
+    where
+        for<'a> Self: GetCaptureBufferByIndex<'a, P>,
+    {
+        canceled
+            .into_iter()
+            .filter_map(|buffer| {
+                let index = buffer.index as usize;
+                let qbuf =
+                    match <Self as GetCaptureBufferByIndex<'_, P>>::try_get_buffer(self, index) {
+                        Ok(qbuf) => qbuf,
+                        Err(e) => {
+                            warn!("Cannot obtain canceled buffer {}: {}", index, e);
+                            return Some(buffer);
+                        }
+                    };
+
+                match qbuf.queue_with_handles(buffer.plane_handles) {
+                    Ok(()) => None,
+                    Err(e) => {
+                        warn!("Cannot queue canceled buffer {} again: {}", index, e.error);
+                        Some(CanceledBuffer {
+                            index: buffer.index,
+                            plane_handles: e.plane_handles,
+                        })
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the number of frames dropped by the driver since the queue has been streamed on,
+    /// as revealed by gaps in the sequence numbers of the dequeued buffers, e.g. because no buffer
+    /// was queued in time or the bandwidth of the device was insufficient.
+    ///
+    /// Frames dropped after the last dequeued buffer are only counted once the next buffer is
+    /// dequeued.
+    pub fn dropped_frames(&self) -> u64 {
+        self.state.sequence_tracker.lock().unwrap().dropped()
+    }
+
+    /// Set a callback to be called with the number of frames dropped every time a gap is detected
+    /// in the sequence numbers of the dequeued buffers. See [`Queue::dropped_frames`].
+    ///
+    /// The callback is called while dequeuing the buffer following the gap.
+    pub fn set_frame_drop_cb<F>(&self, drop_cb: F)
+    where
+        F: FnMut(u32) + Send + 'static,
+    {
+        *self.state.frame_drop_cb.lock().unwrap() = Some(Arc::new(Mutex::new(Box::new(drop_cb))));
+    }
+
+    /// Dequeue buffers until one containing a key frame is found, or until
+    /// `timeout` expires. This is useful to seek into the output stream of an
+    /// encoder.
+    ///
+    /// Buffers that do not contain a key frame are dropped, i.e. returned to
+    /// the free list of the queue. It is up to the caller to queue them again.
+    /// [`NextKeyframeError::NoBufferQueued`] is returned if no buffer is, or
+    /// remains, queued to receive a key frame.
+    pub fn next_keyframe(
+        &self,
+        timeout: Duration,
+    ) -> Result<DqBuffer<Capture, P>, NextKeyframeError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            // Polling would fail right away.
+            if self.num_queued_buffers() == 0 {
+                return Err(NextKeyframeError::NoBufferQueued);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let poll_timeout = PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX);
+            match ioctl::backend::poll(self.inner.device.as_fd(), PollFlags::POLLIN, poll_timeout) {
+                Ok(revents) if revents.contains(PollFlags::POLLERR) => {
+                    return Err(NextKeyframeError::DeviceError)
+                }
+                Ok(revents) if revents.is_empty() => return Err(NextKeyframeError::Timeout),
+                Ok(_) => (),
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(NextKeyframeError::PollError(e)),
+            }
+
+            match self.try_dequeue() {
+                Ok(dqbuf) if dqbuf.data.frame_type() == Some(ioctl::FrameType::Key) => {
+                    return Ok(dqbuf)
+                }
+                Ok(dqbuf) => {
+                    debug!(
+                        "Skipping non-keyframe buffer {} ({:?})",
+                        dqbuf.data.index(),
+                        dqbuf.data.frame_type()
+                    );
+                }
+                Err(DqBufError::IoctlError(DqBufIoctlError::NotReady)) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum TryGetBufferError {
     #[error("buffer with provided index {0} does not exist")]
@@ -517,12 +974,24 @@ pub enum TryGetBufferError {
     AlreadyUsed,
 }
 
+impl AsErrno for TryGetBufferError {
+    fn errno(&self) -> Option<Errno> {
+        None
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum GetFreeBufferError {
     #[error("all buffers are currently being used")]
     NoFreeBuffer,
 }
 
+impl AsErrno for GetFreeBufferError {
+    fn errno(&self) -> Option<Errno> {
+        None
+    }
+}
+
 mod private {
     use std::ops::Deref;
 
@@ -663,6 +1132,10 @@ pub trait OutputQueueable<B: BufferHandles> {
     /// `bytes_used` must be a slice with as many slices as there are handles,
     /// describing the amount of useful data in each of them.
     fn queue_with_handles(self, handles: B, bytes_used: &[usize]) -> QueueResult<(), B>;
+
+    /// Set the timestamp of the buffer, which the driver passes to the CAPTURE buffers produced
+    /// from it.
+    fn set_timestamp(self, timestamp: TimeVal) -> Self;
 }
 
 /// Trait for all objects that are capable of providing objects that can be
@@ -806,3 +1279,497 @@ impl<P: BufferHandles> Drop for BufferStateFuse<P> {
         self.trigger();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::IntoRawFd;
+
+    use crate::ioctl::backend::mock::{mock_device, MockIoctls};
+    use crate::ioctl::{BufferFlags, Capabilities, ExpbufFlags};
+    use crate::memory::MmapHandle;
+
+    /// Expects a `VIDIOC_REQBUFS` for `count` MMAP buffers, granting `granted` of them.
+    fn expect_reqbufs(mock: &MockIoctls, count: u32, granted: u32) {
+        mock.expect_with(
+            "vidioc_reqbufs",
+            move |reqbufs: &mut bindings::v4l2_requestbuffers| {
+                assert_eq!(reqbufs.type_, QueueType::VideoCapture as u32);
+                assert_eq!(reqbufs.memory, MemoryType::Mmap as u32);
+                assert_eq!(reqbufs.count, count);
+                reqbufs.count = granted;
+                Ok(0)
+            },
+        );
+    }
+
+    #[test]
+    fn test_queue_state_machine() {
+        const BUFFER_SIZE: u32 = 4096;
+
+        let mock = MockIoctls::new();
+        let device = Arc::new(mock_device(&mock));
+
+        expect_reqbufs(&mock, 0, 0);
+        let queue = Queue::get_capture_queue(Arc::clone(&device)).unwrap();
+        // The same queue cannot be obtained twice.
+        assert!(matches!(
+            Queue::get_capture_queue(Arc::clone(&device)),
+            Err(CreateQueueError::AlreadyBorrowed)
+        ));
+
+        expect_reqbufs(&mock, 2, 2);
+        for i in 0..2 {
+            mock.expect_with("vidioc_querybuf", move |buf: &mut bindings::v4l2_buffer| {
+                assert_eq!(buf.index, i);
+                buf.length = BUFFER_SIZE;
+                buf.m.offset = i * BUFFER_SIZE;
+                Ok(0)
+            });
+        }
+        mock.expect("vidioc_g_fmt", Ok(0));
+        let queue = queue.request_buffers::<Vec<MmapHandle>>(2).unwrap();
+        assert_eq!(queue.num_buffers(), 2);
+        assert_eq!(queue.num_free_buffers(), 2);
+
+        mock.expect("vidioc_streamon", Ok(0));
+        queue.stream_on().unwrap();
+
+        // Invalid buffers are rejected without reaching the driver.
+        assert!(matches!(
+            queue.try_get_free_buffer().unwrap().set_request(3).queue(),
+            Err(ioctl::QBufError::IoctlError(
+                ioctl::QBufIoctlError::Invalid(ioctl::QBufValidationError::RequestsNotSupported)
+            ))
+        ));
+        assert_eq!(queue.num_free_buffers(), 2);
+
+        for i in 0..2 {
+            mock.expect_with("vidioc_qbuf", move |buf: &mut bindings::v4l2_buffer| {
+                assert_eq!(buf.index, i);
+                Ok(0)
+            });
+            queue.try_get_free_buffer().unwrap().queue().unwrap();
+        }
+        assert_eq!(queue.num_queued_buffers(), 2);
+        assert!(matches!(
+            queue.try_get_free_buffer(),
+            Err(GetFreeBufferError::NoFreeBuffer)
+        ));
+
+        mock.expect_with("vidioc_dqbuf", |buf: &mut bindings::v4l2_buffer| {
+            buf.index = 1;
+            buf.type_ = QueueType::VideoCapture as u32;
+            buf.memory = MemoryType::Mmap as u32;
+            buf.length = BUFFER_SIZE;
+            buf.bytesused = 1024;
+            Ok(0)
+        });
+        let dqbuf = queue.try_dequeue().unwrap();
+        assert_eq!(dqbuf.data.index(), 1);
+        assert_eq!(queue.num_queued_buffers(), 1);
+        // Dropping the dequeued buffer makes it available again.
+        drop(dqbuf);
+        assert_eq!(queue.num_free_buffers(), 1);
+
+        // Errors that are hard to trigger on real drivers.
+        mock.expect("vidioc_dqbuf", Err(Errno::EAGAIN))
+            .expect("vidioc_dqbuf", Err(Errno::EPIPE))
+            .expect("vidioc_dqbuf", Err(Errno::EIO))
+            .expect("vidioc_dqbuf", Err(Errno::ENODEV));
+        assert!(matches!(
+            queue.try_dequeue(),
+            Err(DqBufError::IoctlError(DqBufIoctlError::NotReady))
+        ));
+        assert!(matches!(
+            queue.try_dequeue(),
+            Err(DqBufError::IoctlError(DqBufIoctlError::Eos))
+        ));
+        assert!(matches!(
+            queue.try_dequeue(),
+            Err(DqBufError::IoctlError(DqBufIoctlError::HardwareError))
+        ));
+        assert!(matches!(
+            queue.try_dequeue(),
+            Err(DqBufError::IoctlError(DqBufIoctlError::Other(
+                Errno::ENODEV
+            )))
+        ));
+        assert_eq!(queue.num_queued_buffers(), 1);
+
+        // Streaming off returns the buffer that was still queued.
+        mock.expect("vidioc_streamoff", Ok(0));
+        let canceled = queue.stream_off().unwrap();
+        assert_eq!(canceled.len(), 1);
+        assert_eq!(canceled[0].index, 0);
+        assert_eq!(queue.num_free_buffers(), 2);
+
+        expect_reqbufs(&mock, 0, 0);
+        let queue = queue.free_buffers().unwrap().queue;
+        assert_eq!(queue.get_type(), QueueType::VideoCapture);
+
+        mock.assert_done();
+    }
+
+    /// Returns a capture queue of `device` with `count` allocated buffers.
+    fn allocated_queue(
+        mock: &MockIoctls,
+        device: &Arc<Device>,
+        count: u32,
+    ) -> Queue<Capture, BuffersAllocated<Vec<MmapHandle>>> {
+        expect_reqbufs(mock, 0, 0);
+        let queue = Queue::get_capture_queue(Arc::clone(device)).unwrap();
+
+        expect_reqbufs(mock, count, count);
+        for _ in 0..count {
+            mock.expect("vidioc_querybuf", Ok(0));
+        }
+        mock.expect("vidioc_g_fmt", Ok(0));
+        queue.request_buffers::<Vec<MmapHandle>>(count).unwrap()
+    }
+
+    #[test]
+    fn test_queue_drop_frees_buffers() {
+        let mock = MockIoctls::new();
+        let device = Arc::new(mock_device(&mock));
+
+        let queue = allocated_queue(&mock, &device, 2);
+        mock.expect("vidioc_streamon", Ok(0));
+        queue.stream_on().unwrap();
+
+        // Dropping the queue streams off and frees its buffers.
+        mock.expect("vidioc_streamoff", Ok(0));
+        expect_reqbufs(&mock, 0, 0);
+        drop(queue);
+        mock.assert_done();
+
+        // Errors do not prevent the buffers from being freed, and the queue can be obtained
+        // again.
+        let queue = allocated_queue(&mock, &device, 2);
+        mock.expect("vidioc_streamoff", Err(Errno::EIO));
+        expect_reqbufs(&mock, 0, 0);
+        drop(queue);
+        mock.assert_done();
+
+        // Freeing the buffers explicitly does not free them again on drop.
+        let queue = allocated_queue(&mock, &device, 1);
+        expect_reqbufs(&mock, 0, 0);
+        drop(queue.free_buffers().unwrap());
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_queue_prepare_all() {
+        let mock = MockIoctls::new();
+        let device = Arc::new(mock_device(&mock));
+
+        let queue = allocated_queue(&mock, &device, 2);
+        for i in 0..2 {
+            mock.expect_with(
+                "vidioc_prepare_buf",
+                move |buf: &mut bindings::v4l2_buffer| {
+                    assert_eq!(buf.index, i);
+                    assert_eq!(buf.memory, MemoryType::Mmap as u32);
+                    Ok(0)
+                },
+            );
+        }
+        queue.prepare_all().unwrap();
+
+        mock.expect("vidioc_streamoff", Ok(0));
+        expect_reqbufs(&mock, 0, 0);
+        drop(queue);
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_queue_close() {
+        let mock = MockIoctls::new();
+        let device = Arc::new(mock_device(&mock));
+
+        let queue = allocated_queue(&mock, &device, 2);
+        mock.expect("vidioc_streamoff", Ok(0));
+        expect_reqbufs(&mock, 0, 0);
+        queue.close().unwrap();
+        mock.assert_done();
+
+        // Errors are returned, and the freeing is not retried when the queue is dropped.
+        let queue = allocated_queue(&mock, &device, 2);
+        mock.expect("vidioc_streamoff", Ok(0));
+        mock.expect("vidioc_reqbufs", Err(Errno::EBUSY));
+        assert!(matches!(
+            queue.close(),
+            Err(CloseQueueError::ReqbufsError(ReqbufsError::IoctlError(
+                Errno::EBUSY
+            )))
+        ));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_next_keyframe() {
+        const TIMEOUT: Duration = Duration::from_secs(1);
+
+        let mock = MockIoctls::new();
+        let device = Arc::new(mock_device(&mock));
+
+        let queue = allocated_queue(&mock, &device, 2);
+        // Nothing to poll for.
+        assert!(matches!(
+            queue.next_keyframe(TIMEOUT),
+            Err(NextKeyframeError::NoBufferQueued)
+        ));
+
+        mock.expect("vidioc_streamon", Ok(0));
+        queue.stream_on().unwrap();
+        let queue_all = || {
+            while let Ok(buffer) = queue.try_get_free_buffer() {
+                mock.expect("vidioc_qbuf", Ok(0));
+                buffer.queue().unwrap();
+            }
+        };
+        let expect_dqbuf = |index: u32, flags: BufferFlags| {
+            mock.expect_poll(Ok(PollFlags::POLLIN));
+            mock.expect_with("vidioc_dqbuf", move |buf: &mut bindings::v4l2_buffer| {
+                buf.index = index;
+                buf.type_ = QueueType::VideoCapture as u32;
+                buf.memory = MemoryType::Mmap as u32;
+                buf.flags = flags.bits();
+                Ok(0)
+            });
+        };
+
+        // Buffers that are not keyframes are skipped and returned to the free list.
+        queue_all();
+        expect_dqbuf(0, BufferFlags::PFRAME);
+        expect_dqbuf(1, BufferFlags::KEYFRAME);
+        let keyframe = queue.next_keyframe(TIMEOUT).unwrap();
+        assert_eq!(keyframe.data.index(), 1);
+        assert_eq!(queue.num_free_buffers(), 1);
+        drop(keyframe);
+
+        // No keyframe in the queued buffers.
+        queue_all();
+        expect_dqbuf(0, BufferFlags::PFRAME);
+        expect_dqbuf(1, BufferFlags::BFRAME);
+        assert!(matches!(
+            queue.next_keyframe(TIMEOUT),
+            Err(NextKeyframeError::NoBufferQueued)
+        ));
+
+        // The driver does not produce anything in time.
+        queue_all();
+        mock.expect_poll(Ok(PollFlags::empty()));
+        assert!(matches!(
+            queue.next_keyframe(TIMEOUT),
+            Err(NextKeyframeError::Timeout)
+        ));
+
+        // The driver signals an error, e.g. because the queue has been streamed off behind our
+        // back.
+        mock.expect_poll(Ok(PollFlags::POLLERR));
+        assert!(matches!(
+            queue.next_keyframe(TIMEOUT),
+            Err(NextKeyframeError::DeviceError)
+        ));
+
+        // Interrupted polls are retried.
+        mock.expect_poll(Err(Errno::EINTR));
+        expect_dqbuf(0, BufferFlags::KEYFRAME);
+        assert_eq!(queue.next_keyframe(TIMEOUT).unwrap().data.index(), 0);
+
+        mock.expect("vidioc_streamoff", Ok(0));
+        expect_reqbufs(&mock, 0, 0);
+        drop(queue);
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_frame_drop_cb() {
+        let mock = MockIoctls::new();
+        let device = Arc::new(mock_device(&mock));
+
+        let queue = Arc::new(allocated_queue(&mock, &device, 2));
+        mock.expect("vidioc_streamon", Ok(0));
+        queue.stream_on().unwrap();
+        let dequeue = |index: u32, sequence: u32| {
+            while let Ok(buffer) = queue.try_get_free_buffer() {
+                mock.expect("vidioc_qbuf", Ok(0));
+                buffer.queue().unwrap();
+            }
+            mock.expect_with("vidioc_dqbuf", move |buf: &mut bindings::v4l2_buffer| {
+                buf.index = index;
+                buf.type_ = QueueType::VideoCapture as u32;
+                buf.memory = MemoryType::Mmap as u32;
+                buf.sequence = sequence;
+                Ok(0)
+            });
+            drop(queue.try_dequeue().unwrap());
+        };
+
+        // The callback can replace itself without deadlocking.
+        let dropped = Arc::new(Mutex::new(Vec::new()));
+        queue.set_frame_drop_cb({
+            let dropped = Arc::clone(&dropped);
+            let queue = Arc::downgrade(&queue);
+            move |count| {
+                dropped.lock().unwrap().push(count);
+                let dropped = Arc::clone(&dropped);
+                queue
+                    .upgrade()
+                    .unwrap()
+                    .set_frame_drop_cb(move |count| dropped.lock().unwrap().push(count * 10));
+            }
+        });
+        dequeue(0, 0);
+        dequeue(1, 3);
+        dequeue(0, 5);
+        assert_eq!(*dropped.lock().unwrap(), vec![2, 10]);
+        assert_eq!(queue.dropped_frames(), 3);
+
+        mock.expect("vidioc_streamoff", Ok(0));
+        expect_reqbufs(&mock, 0, 0);
+        drop(queue);
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_dqbuf_export() {
+        let mock = MockIoctls::new();
+        let device = Arc::new(mock_device(&mock));
+
+        let queue = allocated_queue(&mock, &device, 2);
+        mock.expect("vidioc_streamon", Ok(0));
+        queue.stream_on().unwrap();
+        let dequeue = |index: u32| {
+            mock.expect("vidioc_qbuf", Ok(0));
+            queue.try_get_free_buffer().unwrap().queue().unwrap();
+            mock.expect_with("vidioc_dqbuf", move |buf: &mut bindings::v4l2_buffer| {
+                buf.index = index;
+                buf.type_ = QueueType::VideoCapture as u32;
+                buf.memory = MemoryType::Mmap as u32;
+                Ok(0)
+            });
+            queue.try_dequeue().unwrap()
+        };
+
+        let dqbuf = dequeue(0);
+        mock.expect_with(
+            "vidioc_expbuf",
+            |expbuf: &mut bindings::v4l2_exportbuffer| {
+                assert_eq!(expbuf.type_, QueueType::VideoCapture as u32);
+                assert_eq!(expbuf.index, 0);
+                assert_eq!(expbuf.plane, 0);
+                assert_eq!(
+                    expbuf.flags,
+                    (ExpbufFlags::CLOEXEC | ExpbufFlags::RDWR).bits()
+                );
+                expbuf.fd = std::fs::File::open("/dev/null").unwrap().into_raw_fd();
+                Ok(0)
+            },
+        );
+        let exported = dqbuf.export(0).unwrap();
+        assert_eq!(exported.buffer().data.index(), 0);
+        // The buffer is only returned to the queue once the exported DMABUF is dropped.
+        assert_eq!(queue.num_free_buffers(), 1);
+        drop(exported);
+        assert_eq!(queue.num_free_buffers(), 2);
+
+        // Failing to export the buffer returns it to the queue.
+        let dqbuf = dequeue(0);
+        mock.expect("vidioc_expbuf", Err(Errno::EINVAL));
+        let err = dqbuf.export(0).unwrap_err();
+        assert!(matches!(err, dqbuf::ExportError::ExpbufError(_)));
+        assert_eq!(err.errno(), Some(Errno::EINVAL));
+        assert_eq!(queue.num_free_buffers(), 2);
+
+        mock.expect("vidioc_streamoff", Ok(0));
+        expect_reqbufs(&mock, 0, 0);
+        drop(queue);
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_requeue_canceled() {
+        const BYTES_USED: u32 = 1000;
+        // Buffers are deliberately not queued in the order of their indices.
+        const ORDER: [u32; 3] = [2, 0, 1];
+
+        let mock = MockIoctls::new();
+        mock.expect_with("vidioc_querycap", |cap: &mut bindings::v4l2_capability| {
+            let caps = Capabilities::VIDEO_OUTPUT | Capabilities::STREAMING;
+            cap.capabilities = (caps | Capabilities::DEVICE_CAPS).bits();
+            cap.device_caps = caps.bits();
+            Ok(0)
+        });
+        let device = Arc::new(Device::new(mock.file()).unwrap());
+
+        mock.expect("vidioc_reqbufs", Ok(0));
+        let queue = Queue::get_output_queue(device).unwrap();
+        mock.expect_with(
+            "vidioc_reqbufs",
+            |reqbufs: &mut bindings::v4l2_requestbuffers| {
+                assert_eq!(reqbufs.type_, QueueType::VideoOutput as u32);
+                Ok(0)
+            },
+        );
+        for _ in 0..ORDER.len() {
+            mock.expect("vidioc_querybuf", Ok(0));
+        }
+        mock.expect("vidioc_g_fmt", Ok(0));
+        let queue = queue
+            .request_buffers::<Vec<MmapHandle>>(ORDER.len() as u32)
+            .unwrap();
+        mock.expect("vidioc_streamon", Ok(0));
+        queue.stream_on().unwrap();
+
+        for index in ORDER {
+            mock.expect_with("vidioc_qbuf", move |buf: &mut bindings::v4l2_buffer| {
+                assert_eq!(buf.index, index);
+                Ok(0)
+            });
+            <Queue<_, _> as GetOutputBufferByIndex<_>>::try_get_buffer(&queue, index as usize)
+                .unwrap()
+                .set_timestamp(TimeVal::new(index as i64, 0))
+                .queue(&[BYTES_USED as usize])
+                .unwrap();
+        }
+
+        // Canceled buffers are returned in the order they have been queued.
+        mock.expect("vidioc_streamoff", Ok(0));
+        let canceled = queue.stream_off().unwrap();
+        assert_eq!(
+            canceled
+                .iter()
+                .map(|buffer| buffer.index)
+                .collect::<Vec<_>>(),
+            ORDER
+        );
+
+        // They are queued again in the same order, with the payload and timestamp reported by
+        // the driver.
+        for index in ORDER {
+            mock.expect_with("vidioc_querybuf", move |buf: &mut bindings::v4l2_buffer| {
+                assert_eq!(buf.index, index);
+                buf.memory = MemoryType::Mmap as u32;
+                buf.length = BYTES_USED;
+                buf.bytesused = BYTES_USED;
+                buf.timestamp.tv_sec = index as _;
+                Ok(0)
+            });
+            mock.expect_with("vidioc_qbuf", move |buf: &mut bindings::v4l2_buffer| {
+                assert_eq!(buf.index, index);
+                assert_eq!(buf.bytesused, BYTES_USED);
+                assert_eq!(buf.timestamp.tv_sec, index as i64);
+                Ok(0)
+            });
+        }
+        assert!(queue.requeue_canceled(canceled).is_empty());
+        assert_eq!(queue.num_queued_buffers(), ORDER.len());
+
+        mock.expect("vidioc_streamoff", Ok(0));
+        mock.expect("vidioc_reqbufs", Ok(0));
+        drop(queue);
+        mock.assert_done();
+    }
+}
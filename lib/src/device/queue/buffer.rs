@@ -1,9 +1,12 @@
 use super::BufferHandles;
 use crate::ioctl;
 
-use std::sync::{
-    atomic::{AtomicUsize, Ordering},
-    Arc, Mutex,
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
 };
 
 /// Represents the current state of an allocated buffer.
@@ -22,7 +25,7 @@ pub(super) enum BufferState<P: BufferHandles> {
 /// Structure that allows a queue and its users to keep track of how many buffers are available for
 /// use and currently queued.
 #[derive(Default)]
-pub(super) struct BufferStats {
+pub(crate) struct BufferStats {
     num_free: AtomicUsize,
     num_queued: AtomicUsize,
 }
@@ -46,6 +49,48 @@ impl BufferStats {
     }
 }
 
+/// Callback receiving the number of frames dropped before a dequeued buffer, see
+/// [`Queue::set_frame_drop_cb`](super::Queue::set_frame_drop_cb).
+pub type FrameDropCb = Box<dyn FnMut(u32) + Send>;
+
+/// Detects the frames dropped by the driver of a CAPTURE queue from the gaps in the sequence
+/// numbers of the dequeued buffers.
+#[derive(Default)]
+pub(super) struct SequenceTracker {
+    last: Option<u32>,
+    dropped: u64,
+}
+
+impl SequenceTracker {
+    /// Records the sequence number of a dequeued buffer and returns the number of frames dropped
+    /// since the previous one.
+    pub fn record(&mut self, sequence: u32) -> u32 {
+        let gap = match self.last.map(|last| sequence.wrapping_sub(last)) {
+            // Buffers older than the last one, e.g. after a wraparound of the sequence numbers
+            // when the stream started, do not bring any information.
+            Some(delta) if delta > u32::MAX / 2 => return 0,
+            // The second field of an interlaced frame has the same sequence number as the first.
+            Some(delta) => delta.saturating_sub(1),
+            None => 0,
+        };
+
+        self.last = Some(sequence);
+        self.dropped += gap as u64;
+
+        gap
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Forget the sequence numbers seen so far, which the driver resets when streaming starts.
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.dropped = 0;
+    }
+}
+
 pub(super) struct BufferInfo<P: BufferHandles> {
     /// Static information about the buffer, obtains from V4L2's `QUERYBUF` ioctl.
     pub(super) features: ioctl::QueryBuffer,
@@ -53,6 +98,8 @@ pub(super) struct BufferInfo<P: BufferHandles> {
     state: Mutex<BufferState<P>>,
     /// Link to the queue's buffer stats, so we can update them as the buffer state changes.
     stats: Arc<BufferStats>,
+    /// Time at which the buffer has last been queued.
+    queued_at: Mutex<Option<Instant>>,
 }
 
 impl<P: BufferHandles> Drop for BufferInfo<P> {
@@ -68,9 +115,20 @@ impl<P: BufferHandles> BufferInfo<P> {
             state: Mutex::new(BufferState::Free),
             features,
             stats: Arc::clone(&stats),
+            queued_at: Mutex::new(None),
         }
     }
 
+    /// Record the time at which the buffer has been queued.
+    pub(super) fn set_queued_at(&self, instant: Instant) {
+        *self.queued_at.lock().unwrap() = Some(instant);
+    }
+
+    /// Returns the time at which the buffer has last been queued.
+    pub(super) fn queued_at(&self) -> Option<Instant> {
+        *self.queued_at.lock().unwrap()
+    }
+
     /// Do something with the buffer's state. The state is provided read-only and thus cannot be
     /// modified.
     pub(super) fn do_with_state<R, F: FnOnce(&BufferState<P>) -> R>(&self, f: F) -> R {
@@ -106,6 +164,29 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_sequence_tracker() {
+        let mut tracker = SequenceTracker::default();
+
+        // The first buffer does not reveal drops, even if its sequence number is not 0.
+        assert_eq!(tracker.record(3), 0);
+        assert_eq!(tracker.record(4), 0);
+        // Both fields of a frame share the same sequence number.
+        assert_eq!(tracker.record(4), 0);
+        assert_eq!(tracker.record(7), 2);
+        // Stale buffers are ignored.
+        assert_eq!(tracker.record(5), 0);
+        assert_eq!(tracker.record(8), 0);
+        assert_eq!(tracker.dropped(), 2);
+
+        // Sequence numbers wrap around.
+        tracker.reset();
+        assert_eq!(tracker.dropped(), 0);
+        assert_eq!(tracker.record(u32::MAX - 1), 0);
+        assert_eq!(tracker.record(1), 2);
+        assert_eq!(tracker.dropped(), 2);
+    }
+
     #[test]
     fn test_buffer_state_update() {
         const NUM_BUFFERS: usize = 5;
@@ -4,15 +4,22 @@ use super::{
     direction::{Capture, Direction},
     BufferStateFuse, BuffersAllocated, Queue,
 };
-use crate::ioctl::{self, PlaneMapping};
+use crate::ioctl::{self, ExpbufFlags, PlaneMapping};
 use crate::{
     device::Device,
-    memory::{BufferHandles, Mappable, PrimitiveBufferHandles},
+    error::AsErrno,
+    format::{FieldPair, FieldPairer},
+    memory::{BufferHandles, DmaBufSource, Mappable, PrimitiveBufferHandles},
 };
+use nix::errno::Errno;
 use std::{
     fmt::Debug,
+    fs::File,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
     sync::{Arc, Weak},
+    time::Instant,
 };
+use thiserror::Error;
 
 pub type DropCallback<D, P> = Box<dyn FnOnce(&mut DqBuffer<D, P>) + Send>;
 
@@ -25,6 +32,8 @@ pub struct DqBuffer<D: Direction, P: BufferHandles> {
     pub data: ioctl::V4l2Buffer,
     /// The backing memory that has been provided for this buffer.
     plane_handles: Option<P>,
+    /// Time at which the buffer has been queued, if known.
+    queued_at: Option<Instant>,
 
     device: Weak<Device>,
     buffer_info: Weak<BufferInfo<P>>,
@@ -52,6 +61,7 @@ impl<D: Direction, P: BufferHandles> DqBuffer<D, P> {
     ) -> Self {
         DqBuffer {
             plane_handles: Some(plane_handles),
+            queued_at: buffer.queued_at(),
             data,
             device: Arc::downgrade(&queue.inner.device),
             buffer_info: Arc::downgrade(buffer),
@@ -69,6 +79,11 @@ impl<D: Direction, P: BufferHandles> DqBuffer<D, P> {
         self.drop_callbacks.push(Box::new(callback));
     }
 
+    /// Returns the time at which this buffer has been queued, if known.
+    pub fn queued_at(&self) -> Option<Instant> {
+        self.queued_at
+    }
+
     /// Return the plane handles of the buffer. This method is guaranteed to
     /// return Some() the first time it is called, and None any subsequent times.
     pub fn take_handles(&mut self) -> Option<P> {
@@ -76,6 +91,107 @@ impl<D: Direction, P: BufferHandles> DqBuffer<D, P> {
     }
 }
 
+impl<D: Direction, P: BufferHandles> FieldPairer<DqBuffer<D, P>> {
+    /// Adds `dqbuf`, dequeued from a queue using [`Field::Alternate`](crate::Field::Alternate),
+    /// using the field and sequence number reported by the driver.
+    ///
+    /// See [`FieldPairer::push`]. The buffer is returned boxed if it does not contain a single
+    /// field.
+    #[allow(clippy::type_complexity)]
+    pub fn push_dqbuf(
+        &mut self,
+        dqbuf: DqBuffer<D, P>,
+    ) -> Result<Option<FieldPair<DqBuffer<D, P>>>, Box<DqBuffer<D, P>>> {
+        let (field, sequence) = (dqbuf.data.field(), dqbuf.data.sequence());
+        self.push(field, sequence, dqbuf).map_err(Box::new)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("device has been closed")]
+    DeviceClosed,
+    #[error("error while exporting buffer")]
+    ExpbufError(#[from] ioctl::ExpbufError),
+}
+
+impl AsErrno for ExportError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            ExportError::DeviceClosed => None,
+            ExportError::ExpbufError(e) => e.errno(),
+        }
+    }
+}
+
+impl<D: Direction, P: BufferHandles> DqBuffer<D, P> {
+    /// Export plane `plane` of this buffer as a DMABUF, e.g. to pass a decoded
+    /// frame to another device without copying it.
+    ///
+    /// The returned [`ExportedDqBuffer`] keeps ownership of this buffer, which
+    /// is only returned to its queue once the exported buffer is dropped, i.e.
+    /// once the consumer of the DMABUF is done with it.
+    pub fn export(self, plane: usize) -> Result<ExportedDqBuffer<D, P>, ExportError> {
+        let device = self.device.upgrade().ok_or(ExportError::DeviceClosed)?;
+        let dmabuf = ioctl::expbuf::<File>(
+            device.as_ref(),
+            self.data.queue(),
+            self.data.index() as usize,
+            plane,
+            ExpbufFlags::CLOEXEC | ExpbufFlags::RDWR,
+        )?;
+
+        Ok(ExportedDqBuffer {
+            dmabuf,
+            buffer: self,
+        })
+    }
+}
+
+/// A dequeued buffer exported as a DMABUF using [`DqBuffer::export`].
+///
+/// It can be used as the backing memory of a `DmaBufHandle` queued to another
+/// device. Dropping it closes the DMABUF and returns the original buffer to
+/// its queue.
+pub struct ExportedDqBuffer<D: Direction, P: BufferHandles> {
+    dmabuf: File,
+    buffer: DqBuffer<D, P>,
+}
+
+impl<D: Direction, P: BufferHandles> ExportedDqBuffer<D, P> {
+    /// Returns the dequeued buffer this DMABUF has been exported from.
+    pub fn buffer(&self) -> &DqBuffer<D, P> {
+        &self.buffer
+    }
+}
+
+impl<D: Direction, P: BufferHandles> Debug for ExportedDqBuffer<D, P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ExportedDqBuffer")
+            .field("dmabuf", &self.dmabuf)
+            .field("buffer", &self.buffer)
+            .finish()
+    }
+}
+
+impl<D: Direction, P: BufferHandles> AsFd for ExportedDqBuffer<D, P> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.dmabuf.as_fd()
+    }
+}
+
+impl<D: Direction, P: BufferHandles> AsRawFd for ExportedDqBuffer<D, P> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.dmabuf.as_raw_fd()
+    }
+}
+
+impl<D: Direction, P: BufferHandles> DmaBufSource for ExportedDqBuffer<D, P> {
+    fn len(&self) -> u64 {
+        DmaBufSource::len(&self.dmabuf)
+    }
+}
+
 impl<P> DqBuffer<Capture, P>
 where
     P: PrimitiveBufferHandles,
@@ -86,14 +202,11 @@ where
         // We can only obtain a mapping if this buffer has not been deleted.
         let buffer_info = self.buffer_info.upgrade()?;
         let plane = buffer_info.features.planes.get(plane_index)?;
-        let plane_data = self.data.planes_iter().nth(plane_index)?;
+        let range = self.data.plane(plane_index)?.data_range();
         // If the buffer info was alive, then the device must also be.
         let device = self.device.upgrade()?;
 
-        let start = *plane_data.data_offset.unwrap_or(&0) as usize;
-        let end = *plane_data.bytesused as usize;
-
-        Some(P::HandleType::map(device.as_ref(), plane)?.restrict(start, end))
+        Some(P::HandleType::map(device.as_ref(), plane)?.restrict(range.start, range.end))
     }
 }
 
@@ -11,6 +11,7 @@ use crate::{
     memory::MmapHandle,
     memory::{BufferHandles, MemoryType, UserPtrHandle},
 };
+use nix::sys::time::TimeVal;
 use std::{fmt::Debug, fs::File, ops::Deref};
 
 /// Supported memory types for `GenericBufferHandles`.
@@ -182,4 +183,12 @@ where
             GenericQBuffer::DmaBuf(d) => d.queue_with_handles(handles, bytes_used),
         }
     }
+
+    fn set_timestamp(self, timestamp: TimeVal) -> Self {
+        match self {
+            GenericQBuffer::Mmap(m) => GenericQBuffer::Mmap(m.set_timestamp(timestamp)),
+            GenericQBuffer::User(u) => GenericQBuffer::User(u.set_timestamp(timestamp)),
+            GenericQBuffer::DmaBuf(d) => GenericQBuffer::DmaBuf(d.set_timestamp(timestamp)),
+        }
+    }
 }
@@ -5,7 +5,7 @@ use std::{
     task::Wake,
 };
 
-use log::error;
+use nix::errno::Errno;
 
 use crate::device::queue::{
     GetCaptureBufferByIndex, GetFreeBufferError, GetFreeCaptureBuffer, TryGetBufferError,
@@ -13,6 +13,7 @@ use crate::device::queue::{
 use crate::{
     bindings,
     device::poller::Waker,
+    error::AsErrno,
     memory::{BufferHandles, MmapHandle, PrimitiveBufferHandles},
     Format,
 };
@@ -27,6 +28,12 @@ pub enum GetSuitableBufferError {
     TryGetIndexed(#[from] TryGetBufferError),
 }
 
+impl AsErrno for GetSuitableBufferError {
+    fn errno(&self) -> Option<Errno> {
+        None
+    }
+}
+
 pub trait HandlesProvider: Send + 'static {
     type HandleType: BufferHandles;
 
@@ -134,6 +141,13 @@ impl<H: BufferHandles> PooledHandlesProvider<H> {
     }
 }
 
+impl<H: BufferHandles> PooledHandlesProvider<H> {
+    /// Remove the handles currently available from the pool and return them.
+    pub(crate) fn take_available(&self) -> Vec<H> {
+        self.d.lock().unwrap().buffers.drain(..).collect()
+    }
+}
+
 impl<H: BufferHandles> HandlesProvider for PooledHandlesProvider<H> {
     type HandleType = PooledHandles<H>;
 
@@ -3,16 +3,20 @@ use crate::device::queue::{
     buffer::BufferInfo, BufferState, BufferStateFuse, BuffersAllocated, Capture, CaptureQueueable,
     Direction, Output, OutputQueueable, Queue,
 };
-use crate::ioctl::{self, QBufIoctlError, QBufResult};
+use crate::error::AsErrno;
+use crate::ioctl::{self, QBufIoctlError, QBufResult, QBufValidationError, QueryBufPlane};
 use crate::memory::*;
+use crate::{Field, Format};
 use std::convert::Infallible;
 use std::ops::Deref;
 use std::{
     fmt::{self, Debug},
     os::fd::RawFd,
     sync::Arc,
+    time::Instant,
 };
 
+use nix::errno::Errno;
 use nix::sys::time::{TimeVal, TimeValLike};
 use thiserror::Error;
 
@@ -31,9 +35,107 @@ impl<B: BufferHandles> Debug for QueueError<B> {
     }
 }
 
+impl<B: BufferHandles> AsErrno for QueueError<B> {
+    fn errno(&self) -> Option<Errno> {
+        self.error.errno()
+    }
+}
+
 #[allow(type_alias_bounds)]
 pub type QueueResult<R, B: BufferHandles> = std::result::Result<R, QueueError<B>>;
 
+/// Whether the buffers queued since the queue has been streamed on used requests. V4L2 does not
+/// allow mixing buffers queued with and without a request until the queue is streamed off.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) enum RequestUsage {
+    #[default]
+    Unknown,
+    WithRequests,
+    WithoutRequests,
+}
+
+/// State of a queue that buffers are validated against before being queued.
+struct QBufConstraints<'a> {
+    is_output: bool,
+    /// Format of the queue, if known.
+    format: Option<&'a Format>,
+    capabilities: ioctl::BufferCapabilities,
+    request_usage: RequestUsage,
+}
+
+impl QBufConstraints<'_> {
+    /// Checks that a buffer made of `planes`, whose lengths as reported by `VIDIOC_QUERYBUF` are
+    /// `buffer_planes`, containing `field` and to be queued with `request` can be accepted by the
+    /// driver.
+    fn validate(
+        &self,
+        planes: &[ioctl::QBufPlane],
+        buffer_planes: &[QueryBufPlane],
+        field: Field,
+        request: Option<RawFd>,
+    ) -> Result<(), QBufValidationError> {
+        if let Some(format) = self.format {
+            if planes.len() != format.plane_fmt.len() {
+                return Err(QBufValidationError::NumPlanes {
+                    got: planes.len(),
+                    expected: format.plane_fmt.len(),
+                });
+            }
+        }
+
+        for (i, plane) in planes.iter().enumerate() {
+            // USERPTR and DMABUF handles set the length of their memory, otherwise the buffer
+            // uses the one allocated by the driver.
+            let length = match plane.0.length {
+                0 => buffer_planes.get(i).map(|p| p.length).unwrap_or(0),
+                length => length,
+            };
+            if length != 0 && plane.0.bytesused > length {
+                return Err(QBufValidationError::BytesUsedExceedsLength {
+                    plane: i,
+                    bytes_used: plane.0.bytesused,
+                    length,
+                });
+            }
+            if self.is_output && plane.0.bytesused == 0 {
+                return Err(QBufValidationError::NoBytesUsed { plane: i });
+            }
+        }
+
+        if let Some(format) = self.format {
+            let valid = match (format.field, field) {
+                // Output buffers of alternate formats must say which field they contain.
+                (Field::Alternate, Field::Any) => !self.is_output,
+                (_, Field::Any) => true,
+                (Field::Alternate, Field::Top | Field::Bottom) => true,
+                (format_field, field) => format_field == field,
+            };
+            if !valid {
+                return Err(QBufValidationError::Field {
+                    field,
+                    format: format.field,
+                });
+            }
+        }
+
+        match (request, self.request_usage) {
+            (Some(_), _)
+                if !self
+                    .capabilities
+                    .contains(ioctl::BufferCapabilities::SUPPORTS_REQUESTS) =>
+            {
+                Err(QBufValidationError::RequestsNotSupported)
+            }
+            (Some(fd), _) if fd < 0 => Err(QBufValidationError::InvalidRequestFd(fd)),
+            (Some(_), RequestUsage::WithoutRequests) => {
+                Err(QBufValidationError::RequestNotExpected)
+            }
+            (None, RequestUsage::WithRequests) => Err(QBufValidationError::RequestRequired),
+            _ => Ok(()),
+        }
+    }
+}
+
 /// A free buffer that has just been obtained from `Queue::get_buffer()` and
 /// which is being prepared to the queued.
 ///
@@ -72,7 +174,10 @@ pub struct QBuffer<
     index: usize,
     num_planes: usize,
     timestamp: TimeVal,
+    field: Field,
     request: Option<RawFd>,
+    in_fence: Option<RawFd>,
+    validate: bool,
     fuse: BufferStateFuse<B>,
     _p: std::marker::PhantomData<P>,
 }
@@ -93,7 +198,10 @@ where
             index: buffer.index,
             num_planes: buffer.planes.len(),
             timestamp: TimeVal::zero(),
+            field: Field::Any,
             request: None,
+            in_fence: None,
+            validate: true,
             fuse,
             _p: std::marker::PhantomData,
         }
@@ -115,11 +223,42 @@ where
         self
     }
 
+    /// Sets the field contained in the buffer. Output buffers of a queue using the
+    /// [`Field::Alternate`] field order must specify whether they contain the top or bottom field,
+    /// otherwise the field order of the format is used.
+    pub fn set_field(mut self, field: Field) -> Self {
+        self.field = field;
+        self
+    }
+
     pub fn set_request(mut self, fd: RawFd) -> Self {
         self.request = Some(fd);
         self
     }
 
+    /// Do not check that the buffer is consistent with the format and state of the queue before
+    /// queuing it, and let the driver reject it instead.
+    ///
+    /// The validation errors tell which member of the buffer is wrong, where drivers usually
+    /// return `EINVAL`, so this is only worth doing when queuing buffers at a very high rate.
+    pub fn skip_validation(mut self) -> Self {
+        self.validate = false;
+        self
+    }
+
+    /// Makes the driver wait for `fd`, a sync file, to be signaled before using the buffer.
+    ///
+    /// Returns [`QBufIoctlError::FencesNotSupported`] if the queue does not support explicit
+    /// synchronization fences, which is always the case on mainline kernels.
+    pub fn set_in_fence(mut self, fd: RawFd) -> Result<Self, QBufIoctlError> {
+        if !self.queue.get_capabilities().supports_fences() {
+            return Err(QBufIoctlError::FencesNotSupported);
+        }
+
+        self.in_fence = Some(fd);
+        Ok(self)
+    }
+
     // R is meant to mean "either P or Q".
     // Caller is responsible for making sure that the number of planes and
     // plane_handles is the same as the number of expected planes for this
@@ -129,14 +268,49 @@ where
         planes: Vec<ioctl::QBufPlane>,
         plane_handles: R,
     ) -> QueueResult<(), R> {
+        let buffer_info = self.queue.state.buffer_info.get(self.index);
+
+        if self.validate {
+            let constraints = QBufConstraints {
+                is_output: self.queue.inner.type_.is_output(),
+                format: self.queue.state.format.as_ref(),
+                capabilities: self.queue.get_capabilities(),
+                request_usage: *self.queue.state.request_usage.lock().unwrap(),
+            };
+            let buffer_planes = buffer_info
+                .map(|info| info.features.planes.as_slice())
+                .unwrap_or_default();
+            if let Err(error) =
+                constraints.validate(&planes, buffer_planes, self.field, self.request)
+            {
+                return Err(QueueError {
+                    error: QBufIoctlError::Invalid(error).into(),
+                    plane_handles,
+                });
+            }
+        }
+
         let mut qbuffer =
             ioctl::QBuffer::<P::HandleType>::new(self.queue.inner.type_, self.index as u32);
         if let Some(request) = self.request {
             qbuffer = qbuffer.set_request(request);
         }
+        if let Some(fence) = self.in_fence {
+            qbuffer = match qbuffer.set_in_fence(fence) {
+                Ok(qbuffer) => qbuffer,
+                Err(error) => {
+                    return Err(QueueError {
+                        error: error.into(),
+                        plane_handles,
+                    })
+                }
+            };
+        }
         qbuffer.planes = planes;
         qbuffer.timestamp = self.timestamp;
+        qbuffer.field = self.field as u32;
 
+        let queued_at = Instant::now();
         match ioctl::qbuf(&self.queue.inner, qbuffer) {
             Ok(()) => (),
             Err(error) => {
@@ -147,9 +321,18 @@ where
             }
         };
 
+        if let Some(buffer_info) = buffer_info {
+            buffer_info.set_queued_at(queued_at);
+        }
+
         // We got this now.
         self.fuse.disarm();
 
+        *self.queue.state.request_usage.lock().unwrap() = match self.request {
+            Some(_) => RequestUsage::WithRequests,
+            None => RequestUsage::WithoutRequests,
+        };
+
         self.queue
             .state
             .buffer_info
@@ -251,6 +434,20 @@ where
 
         self.queue_bound_planes(planes, handles)
     }
+
+    fn set_timestamp(self, timestamp: TimeVal) -> Self {
+        QBuffer::set_timestamp(self, timestamp)
+    }
+}
+
+/// Returns handles for `num_planes` self-backed planes, so the buffer can be queued again with
+/// the handles returned when it is canceled.
+fn self_backed_handles<P>(num_planes: usize) -> P
+where
+    P: PrimitiveBufferHandles + FromIterator<P::HandleType>,
+    P::HandleType: Default,
+{
+    (0..num_planes).map(|_| Default::default()).collect()
 }
 
 /// Shortcut to quickly queue self-backed CAPTURE buffers without specifying
@@ -259,7 +456,8 @@ where
 /// the returned error can be simplified.
 impl<P, B, Q> QBuffer<Capture, P, B, Q>
 where
-    P: PrimitiveBufferHandles + Default,
+    P: PrimitiveBufferHandles + FromIterator<P::HandleType>,
+    P::HandleType: Default,
     <P::HandleType as PlaneHandle>::Memory: SelfBacked,
     B: BufferHandles + From<P>,
     Q: Deref<Target = Queue<Capture, BuffersAllocated<B>>>,
@@ -268,8 +466,9 @@ where
         let planes: Vec<_> = (0..self.num_expected_planes())
             .map(|_| ioctl::QBufPlane::new(0))
             .collect();
+        let handles = self_backed_handles(planes.len());
 
-        self.queue_bound_planes::<P>(planes, Default::default())
+        self.queue_bound_planes::<P>(planes, handles)
             .map_err(|e| e.error)
     }
 }
@@ -281,7 +480,8 @@ where
 impl<P, B, Q> QBuffer<Output, P, B, Q>
 where
     <P::HandleType as PlaneHandle>::Memory: SelfBacked,
-    P: PrimitiveBufferHandles + Default,
+    P: PrimitiveBufferHandles + FromIterator<P::HandleType>,
+    P::HandleType: Default,
     B: BufferHandles + From<P>,
     Q: Deref<Target = Queue<Output, BuffersAllocated<B>>>,
 {
@@ -299,8 +499,194 @@ where
             .iter()
             .map(|size| ioctl::QBufPlane::new(*size))
             .collect();
+        let handles = self_backed_handles(planes.len());
 
-        self.queue_bound_planes::<P>(planes, Default::default())
+        self.queue_bound_planes::<P>(planes, handles)
             .map_err(|e| e.error)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlaneLayout;
+
+    /// Returns a two-planar format using `field`.
+    fn format(field: Field) -> Format {
+        Format {
+            plane_fmt: vec![PlaneLayout::default(); 2],
+            field,
+            ..Default::default()
+        }
+    }
+
+    fn constraints(is_output: bool, format: Option<&Format>) -> QBufConstraints<'_> {
+        QBufConstraints {
+            is_output,
+            format,
+            capabilities: ioctl::BufferCapabilities::SUPPORTS_MMAP,
+            request_usage: RequestUsage::Unknown,
+        }
+    }
+
+    /// Returns planes using `bytes_used`, with the length of their memory set to `length`.
+    fn planes(bytes_used: &[u32], length: u32) -> Vec<ioctl::QBufPlane> {
+        bytes_used
+            .iter()
+            .map(|&bytes_used| {
+                let mut plane = ioctl::QBufPlane::new(bytes_used as usize);
+                plane.0.length = length;
+                plane
+            })
+            .collect()
+    }
+
+    const BUFFER_PLANE: QueryBufPlane = QueryBufPlane {
+        mem_offset: 0,
+        length: 4096,
+    };
+    const BUFFER_PLANES: [QueryBufPlane; 2] = [BUFFER_PLANE; 2];
+
+    #[test]
+    fn test_validate_num_planes() {
+        let format = format(Field::None);
+        let output = constraints(true, Some(&format));
+        assert_eq!(
+            output.validate(&planes(&[16], 0), &BUFFER_PLANES, Field::Any, None),
+            Err(QBufValidationError::NumPlanes {
+                got: 1,
+                expected: 2
+            })
+        );
+        output
+            .validate(&planes(&[16, 16], 0), &BUFFER_PLANES, Field::Any, None)
+            .unwrap();
+        // Without a known format, the number of planes is checked against the buffer only.
+        constraints(true, None)
+            .validate(&planes(&[16], 0), &BUFFER_PLANES, Field::Any, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_bytes_used() {
+        let format = format(Field::None);
+        let output = constraints(true, Some(&format));
+        // The length of the buffer allocated by the driver is used for MMAP planes.
+        assert_eq!(
+            output.validate(&planes(&[16, 4097], 0), &BUFFER_PLANES, Field::Any, None),
+            Err(QBufValidationError::BytesUsedExceedsLength {
+                plane: 1,
+                bytes_used: 4097,
+                length: 4096
+            })
+        );
+        // The length of the memory is used when set by the handle.
+        assert_eq!(
+            output.validate(&planes(&[1024, 16], 512), &BUFFER_PLANES, Field::Any, None),
+            Err(QBufValidationError::BytesUsedExceedsLength {
+                plane: 0,
+                bytes_used: 1024,
+                length: 512
+            })
+        );
+        output
+            .validate(&planes(&[4096, 4096], 0), &BUFFER_PLANES, Field::Any, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_no_bytes_used() {
+        let format = format(Field::None);
+        assert_eq!(
+            constraints(true, Some(&format)).validate(
+                &planes(&[16, 0], 0),
+                &BUFFER_PLANES,
+                Field::Any,
+                None
+            ),
+            Err(QBufValidationError::NoBytesUsed { plane: 1 })
+        );
+        // Capture buffers are filled by the driver.
+        constraints(false, Some(&format))
+            .validate(&planes(&[0, 0], 0), &BUFFER_PLANES, Field::Any, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_field() {
+        let progressive = format(Field::None);
+        let output = constraints(true, Some(&progressive));
+        let planes = planes(&[16, 16], 0);
+        output
+            .validate(&planes, &BUFFER_PLANES, Field::None, None)
+            .unwrap();
+        assert_eq!(
+            output.validate(&planes, &BUFFER_PLANES, Field::Top, None),
+            Err(QBufValidationError::Field {
+                field: Field::Top,
+                format: Field::None
+            })
+        );
+
+        // Output buffers of alternate formats must contain either field.
+        let alternate = format(Field::Alternate);
+        let output = constraints(true, Some(&alternate));
+        output
+            .validate(&planes, &BUFFER_PLANES, Field::Bottom, None)
+            .unwrap();
+        assert_eq!(
+            output.validate(&planes, &BUFFER_PLANES, Field::Any, None),
+            Err(QBufValidationError::Field {
+                field: Field::Any,
+                format: Field::Alternate
+            })
+        );
+        assert_eq!(
+            output
+                .validate(&planes, &BUFFER_PLANES, Field::Interlaced, None)
+                .unwrap_err()
+                .field_name(),
+            "field"
+        );
+        constraints(false, Some(&alternate))
+            .validate(&planes, &BUFFER_PLANES, Field::Any, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_validate_request() {
+        let planes = planes(&[16], 0);
+        let mut output = constraints(true, None);
+        assert_eq!(
+            output.validate(&planes, &BUFFER_PLANES, Field::Any, Some(3)),
+            Err(QBufValidationError::RequestsNotSupported)
+        );
+
+        output.capabilities |= ioctl::BufferCapabilities::SUPPORTS_REQUESTS;
+        output
+            .validate(&planes, &BUFFER_PLANES, Field::Any, Some(3))
+            .unwrap();
+        output
+            .validate(&planes, &BUFFER_PLANES, Field::Any, None)
+            .unwrap();
+        assert_eq!(
+            output.validate(&planes, &BUFFER_PLANES, Field::Any, Some(-1)),
+            Err(QBufValidationError::InvalidRequestFd(-1))
+        );
+
+        // Buffers queued with and without requests cannot be mixed.
+        output.request_usage = RequestUsage::WithRequests;
+        let err = output
+            .validate(&planes, &BUFFER_PLANES, Field::Any, None)
+            .unwrap_err();
+        assert_eq!(err, QBufValidationError::RequestRequired);
+        assert_eq!(err.field_name(), "request_fd");
+        assert_eq!(Errno::from(err), Errno::EBUSY);
+
+        output.request_usage = RequestUsage::WithoutRequests;
+        assert_eq!(
+            output.validate(&planes, &BUFFER_PLANES, Field::Any, Some(3)),
+            Err(QBufValidationError::RequestNotExpected)
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Interface to V4L2 radio devices, i.e. the `/dev/radio*` nodes of AM/FM tuners.
+//!
+//! Radio devices do not have video queues: they are controlled through their tuner, which is set
+//! to the frequency of a station either directly or by a hardware seek. Receivers supporting RDS
+//! deliver the RDS data as a stream of [`RdsBlock`]s, which is obtained with
+//! [`RadioDevice::read_rds`].
+
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use enumn::N;
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::bindings;
+use crate::device::{Device, DeviceOpenError, DeviceOpenFlags};
+use crate::error::AsErrno;
+use crate::ioctl::{self, Capabilities, Capability, HwFreqSeek, HwFreqSeekError, Tuner, TunerType};
+
+/// Size in bytes of a `struct v4l2_rds_data`, i.e. of an RDS block as returned by `read()`.
+const RDS_BLOCK_SIZE: usize = 3;
+
+/// Offset word of an RDS block, which identifies its position within its group.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, N)]
+#[repr(u8)]
+pub enum RdsBlockId {
+    A = bindings::V4L2_RDS_BLOCK_A as u8,
+    B = bindings::V4L2_RDS_BLOCK_B as u8,
+    C = bindings::V4L2_RDS_BLOCK_C as u8,
+    D = bindings::V4L2_RDS_BLOCK_D as u8,
+    /// Block C of a version B group, which carries the program identification code again.
+    CAlt = bindings::V4L2_RDS_BLOCK_C_ALT as u8,
+}
+
+/// Safe variant of `struct v4l2_rds_data`, i.e. one block of RDS data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RdsBlock {
+    /// The 16 information bits of the block.
+    pub data: u16,
+    /// Position of the block within its group, or `None` if the receiver could not determine it.
+    pub id: Option<RdsBlockId>,
+    /// Whether errors have been detected in the block and corrected.
+    pub corrected: bool,
+    /// Whether the block contains errors that could not be corrected, in which case `data` must
+    /// not be used.
+    pub error: bool,
+}
+
+impl RdsBlock {
+    /// Parses the 3 bytes of a `struct v4l2_rds_data`.
+    pub fn from_bytes(bytes: [u8; RDS_BLOCK_SIZE]) -> Self {
+        let [lsb, msb, block] = bytes;
+
+        RdsBlock {
+            data: u16::from_le_bytes([lsb, msb]),
+            id: RdsBlockId::n(block & bindings::V4L2_RDS_BLOCK_MSK as u8),
+            corrected: block & bindings::V4L2_RDS_BLOCK_CORRECTED as u8 != 0,
+            error: block & bindings::V4L2_RDS_BLOCK_ERROR as u8 != 0,
+        }
+    }
+
+    /// Returns whether the block has been received without uncorrectable errors.
+    pub fn is_valid(&self) -> bool {
+        self.id.is_some() && !self.error
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RadioDeviceError {
+    #[error("error while opening radio device: {0}")]
+    Open(#[from] DeviceOpenError),
+    #[error("{} is not a radio device", .0.display())]
+    NotRadio(PathBuf),
+}
+
+impl AsErrno for RadioDeviceError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            RadioDeviceError::Open(e) => e.errno(),
+            RadioDeviceError::NotRadio(_) => None,
+        }
+    }
+}
+
+/// An opened V4L2 radio device.
+///
+/// Radio devices have a single tuner, at index 0, which all the methods operate on.
+pub struct RadioDevice {
+    device: Device,
+}
+
+impl RadioDevice {
+    /// Opens the node at `path` using `flags`, and checks that it is a radio device.
+    pub fn open(path: &Path, flags: DeviceOpenFlags) -> Result<Self, RadioDeviceError> {
+        let device = Device::open_with_flags(path, flags)?;
+        if !device.caps().device_caps().contains(Capabilities::RADIO) {
+            return Err(RadioDeviceError::NotRadio(path.to_path_buf()));
+        }
+
+        Ok(RadioDevice { device })
+    }
+
+    /// Returns the capabilities of the device.
+    pub fn caps(&self) -> &Capability {
+        self.device.caps()
+    }
+
+    /// Returns the state of the tuner, including its frequency range and signal strength.
+    pub fn tuner(&self) -> Result<Tuner, Errno> {
+        Ok(ioctl::g_tuner(self, 0)?)
+    }
+
+    /// Returns the frequency the tuner is set to, in the unit of its frequency range.
+    pub fn frequency(&self) -> Result<u32, Errno> {
+        let frequency: bindings::v4l2_frequency = ioctl::g_frequency(self, 0)?;
+        Ok(frequency.frequency)
+    }
+
+    /// Sets the tuner to `frequency`, in the unit of its frequency range. The driver clamps it to
+    /// that range.
+    pub fn set_frequency(&self, frequency: u32) -> Result<(), Errno> {
+        Ok(ioctl::s_frequency(self, 0, TunerType::Radio, frequency)?)
+    }
+
+    /// Seeks the next station as specified by `seek`, and sets the tuner to its frequency.
+    ///
+    /// This blocks until a station is found, which may take several seconds, even if the device
+    /// has been opened in non-blocking mode.
+    pub fn seek(&self, seek: &HwFreqSeek) -> Result<(), HwFreqSeekError> {
+        let tuner = self.tuner().map_err(HwFreqSeekError::IoctlError)?;
+        ioctl::s_hw_freq_seek_blocking(self, &tuner, seek)
+    }
+
+    /// Reads up to `max_blocks` RDS blocks received by the tuner, blocking until at least one is
+    /// available unless the device has been opened in non-blocking mode, in which case `EAGAIN`
+    /// is returned if there is none.
+    ///
+    /// This requires the tuner to have the `RDS_BLOCK_IO` capability.
+    pub fn read_rds(&self, max_blocks: usize) -> Result<Vec<RdsBlock>, Errno> {
+        let mut buf = vec![0u8; max_blocks * RDS_BLOCK_SIZE];
+        let len = nix::unistd::read(self.as_raw_fd(), &mut buf)?;
+
+        Ok(buf[..len]
+            .chunks_exact(RDS_BLOCK_SIZE)
+            .map(|block| RdsBlock::from_bytes([block[0], block[1], block[2]]))
+            .collect())
+    }
+}
+
+impl AsFd for RadioDevice {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.device.as_fd()
+    }
+}
+
+impl AsRawFd for RadioDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rds_block() {
+        let block = RdsBlock::from_bytes([0x34, 0x12, bindings::V4L2_RDS_BLOCK_B as u8]);
+        assert_eq!(block.data, 0x1234);
+        assert_eq!(block.id, Some(RdsBlockId::B));
+        assert!(!block.corrected && !block.error);
+        assert!(block.is_valid());
+
+        // The deprecated "received block" bits are ignored.
+        let block = RdsBlock::from_bytes([
+            0,
+            0,
+            (bindings::V4L2_RDS_BLOCK_C_ALT | (bindings::V4L2_RDS_BLOCK_C_ALT << 3)) as u8
+                | bindings::V4L2_RDS_BLOCK_CORRECTED as u8,
+        ]);
+        assert_eq!(block.id, Some(RdsBlockId::CAlt));
+        assert!(block.corrected);
+        assert!(block.is_valid());
+
+        let block = RdsBlock::from_bytes([0xff, 0xff, bindings::V4L2_RDS_BLOCK_INVALID as u8]);
+        assert_eq!(block.id, None);
+        assert!(!block.is_valid());
+
+        let block = RdsBlock::from_bytes([
+            0,
+            0,
+            (bindings::V4L2_RDS_BLOCK_A | bindings::V4L2_RDS_BLOCK_ERROR) as u8,
+        ]);
+        assert_eq!(block.id, Some(RdsBlockId::A));
+        assert!(block.error);
+        assert!(!block.is_valid());
+    }
+}
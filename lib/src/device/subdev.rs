@@ -0,0 +1,118 @@
+//! Interface to V4L2 sub-devices, i.e. the `/dev/v4l-subdev*` nodes exposing the individual
+//! entities of a media pipeline, like sensors, scalers or croppers.
+
+use std::fs::File;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, RawFd};
+use std::path::Path;
+
+use nix::errno::Errno;
+
+use crate::ioctl::{self, MbusFormat, SelectionFlags, SelectionTarget, SubDevWhich};
+#[cfg(v4l2r_has_subdev_querycap)]
+use crate::ioctl::{SubDevCapFlags, SubDeviceCapabilities};
+use crate::Rect;
+
+/// An opened V4L2 sub-device.
+pub struct SubDevice {
+    fd: File,
+}
+
+impl SubDevice {
+    pub fn open(path: &Path) -> Result<Self, nix::Error> {
+        use nix::fcntl::{open, OFlag};
+        use nix::sys::stat::Mode;
+
+        let fd = open(path, OFlag::O_RDWR | OFlag::O_CLOEXEC, Mode::empty())?;
+
+        // Safe because we are constructing a file from Fd we just opened.
+        Ok(SubDevice {
+            fd: unsafe { File::from_raw_fd(fd) },
+        })
+    }
+
+    /// Queries the capabilities of the sub-device.
+    ///
+    /// This requires Linux 5.14 or later, and fails with `ENOTTY` on older kernels.
+    #[cfg(v4l2r_has_subdev_querycap)]
+    pub fn query_capabilities(&self) -> Result<SubDeviceCapabilities, Errno> {
+        Ok(ioctl::subdev_querycap(self)?)
+    }
+
+    /// Returns whether the sub-device node has been registered read-only, in which case only the
+    /// ioctls that do not modify its state are allowed. Returns `false` if the capabilities of the
+    /// sub-device cannot be queried.
+    #[cfg(v4l2r_has_subdev_querycap)]
+    pub fn has_ro_subdev(&self) -> bool {
+        self.query_capabilities()
+            .map(|caps| caps.capabilities.contains(SubDevCapFlags::RO_SUBDEV))
+            .unwrap_or(false)
+    }
+
+    /// Returns the format of `pad`.
+    pub fn get_format(&self, pad: u32, which: SubDevWhich) -> Result<MbusFormat, Errno> {
+        Ok(ioctl::subdev_g_fmt(self, pad, which)?)
+    }
+
+    /// Set the format of `pad` to `format`, and return the format actually applied by the driver,
+    /// which may have been adjusted.
+    pub fn set_format(
+        &self,
+        pad: u32,
+        which: SubDevWhich,
+        format: MbusFormat,
+    ) -> Result<MbusFormat, Errno> {
+        Ok(ioctl::subdev_s_fmt(self, pad, which, format)?)
+    }
+
+    /// Returns the `target` selection rectangle of `pad`.
+    pub fn get_selection(
+        &self,
+        pad: u32,
+        which: SubDevWhich,
+        target: SelectionTarget,
+    ) -> Result<Rect, Errno> {
+        Ok(ioctl::subdev_g_selection(self, pad, which, target)?)
+    }
+
+    /// Set the `target` selection rectangle of `pad` to `rect`, and return the rectangle actually
+    /// applied by the driver, which may have been adjusted.
+    pub fn set_selection(
+        &self,
+        pad: u32,
+        which: SubDevWhich,
+        target: SelectionTarget,
+        rect: Rect,
+    ) -> Result<Rect, Errno> {
+        Ok(ioctl::subdev_s_selection(
+            self,
+            pad,
+            which,
+            target,
+            rect,
+            SelectionFlags::empty(),
+        )?)
+    }
+
+    /// Crop the input of `pad` to `rect`, and return the crop rectangle actually applied.
+    pub fn set_crop_region(&self, pad: u32, rect: Rect) -> Result<Rect, Errno> {
+        self.set_selection(pad, SubDevWhich::Active, SelectionTarget::Crop, rect)
+    }
+
+    /// Compose the output of `pad` into `rect`, e.g. to scale it, and return the compose
+    /// rectangle actually applied.
+    pub fn set_compose_region(&self, pad: u32, rect: Rect) -> Result<Rect, Errno> {
+        self.set_selection(pad, SubDevWhich::Active, SelectionTarget::Compose, rect)
+    }
+}
+
+impl AsFd for SubDevice {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for SubDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
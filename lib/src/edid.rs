@@ -0,0 +1,416 @@
+//! Parsing of the EDID data exposed by HDMI receivers and transmitters.
+//!
+//! The raw EDID of a pad is obtained using [`crate::ioctl::g_edid`], and can be
+//! decoded using [`Edid::parse`]. Only the most useful information is
+//! extracted: the identity of the monitor, the resolutions it supports, and its
+//! audio capabilities if it has a CEA-861 extension block.
+
+use thiserror::Error;
+
+use crate::ioctl::EDID_BLOCK_SIZE;
+
+const EDID_HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+/// Offset of the first descriptor of the base block.
+const DESCRIPTORS_OFFSET: usize = 54;
+/// Size of a detailed timing or display descriptor.
+const DESCRIPTOR_SIZE: usize = 18;
+/// Display descriptor containing the name of the monitor.
+const DESCRIPTOR_MONITOR_NAME: u8 = 0xfc;
+
+/// Tag of CEA-861 extension blocks.
+const CEA_EXTENSION_TAG: u8 = 0x02;
+/// Tag of the audio data blocks of a CEA-861 extension block.
+const CEA_AUDIO_DATA_BLOCK: u8 = 0x01;
+/// Flag of a CEA-861 extension block signaling support for basic audio.
+const CEA_BASIC_AUDIO: u8 = 0x40;
+
+/// Resolutions of the established timings, in the order of their bits. The
+/// last one is the only manufacturer timing that is still in use.
+const ESTABLISHED_TIMINGS: [(u32, u32, u32); 17] = [
+    (800, 600, 60),
+    (800, 600, 56),
+    (640, 480, 75),
+    (640, 480, 72),
+    (640, 480, 67),
+    (640, 480, 60),
+    (720, 400, 88),
+    (720, 400, 70),
+    (1280, 1024, 75),
+    (1024, 768, 75),
+    (1024, 768, 70),
+    (1024, 768, 60),
+    (1024, 768, 87),
+    (832, 624, 75),
+    (800, 600, 75),
+    (800, 600, 72),
+    (1152, 870, 75),
+];
+
+/// Sample rates of short audio descriptors, in the order of their bits.
+const AUDIO_SAMPLE_RATES: [u32; 7] = [32000, 44100, 48000, 88200, 96000, 176400, 192000];
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EdidParseError {
+    #[error("EDID size {0} is not a non-zero multiple of 128")]
+    InvalidSize(usize),
+    #[error("invalid EDID header")]
+    InvalidHeader,
+    #[error("invalid checksum for EDID block {0}")]
+    InvalidChecksum(usize),
+}
+
+/// A resolution supported by a monitor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+    /// Refresh rate in Hz.
+    pub refresh_rate: u32,
+}
+
+/// A detailed timing descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetailedTiming {
+    /// Pixel clock in kHz.
+    pub pixel_clock: u32,
+    pub h_active: u32,
+    pub h_blanking: u32,
+    pub v_active: u32,
+    pub v_blanking: u32,
+    pub interlaced: bool,
+}
+
+impl DetailedTiming {
+    fn parse(desc: &[u8]) -> Option<Self> {
+        let pixel_clock = u16::from_le_bytes([desc[0], desc[1]]) as u32 * 10;
+        if pixel_clock == 0 {
+            return None;
+        }
+
+        Some(DetailedTiming {
+            pixel_clock,
+            h_active: desc[2] as u32 | ((desc[4] as u32 & 0xf0) << 4),
+            h_blanking: desc[3] as u32 | ((desc[4] as u32 & 0x0f) << 8),
+            v_active: desc[5] as u32 | ((desc[7] as u32 & 0xf0) << 4),
+            v_blanking: desc[6] as u32 | ((desc[7] as u32 & 0x0f) << 8),
+            interlaced: desc[17] & 0x80 != 0,
+        })
+    }
+
+    /// Returns the refresh rate of this timing in Hz, rounded to the nearest
+    /// integer.
+    pub fn refresh_rate(&self) -> u32 {
+        let total =
+            (self.h_active + self.h_blanking) as u64 * (self.v_active + self.v_blanking) as u64;
+        if total == 0 {
+            return 0;
+        }
+
+        ((self.pixel_clock as u64 * 1000 + total / 2) / total) as u32
+    }
+
+    /// Returns the resolution of this timing.
+    pub fn resolution(&self) -> Resolution {
+        Resolution {
+            width: self.h_active,
+            height: self.v_active,
+            refresh_rate: self.refresh_rate(),
+        }
+    }
+}
+
+/// An audio format supported by a monitor, as described by a short audio
+/// descriptor of its CEA-861 extension block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioDescriptor {
+    /// Audio format code, e.g. 1 for LPCM or 2 for AC-3.
+    pub format: u8,
+    pub max_channels: u8,
+    /// Supported sample rates in Hz.
+    pub sample_rates: Vec<u32>,
+}
+
+/// Information extracted from an EDID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdidInfo {
+    /// Three-letter PNP ID of the manufacturer.
+    pub manufacturer: String,
+    pub product_code: u16,
+    /// EDID version and revision.
+    pub version: (u8, u8),
+    /// Content of the monitor name descriptor, if any.
+    pub monitor_name: Option<String>,
+    /// The first detailed timing, which is the preferred mode of the monitor.
+    pub preferred_timing: Option<DetailedTiming>,
+    /// Resolutions from the established, standard and detailed timings.
+    pub resolutions: Vec<Resolution>,
+    /// Whether the monitor supports basic audio, i.e. 2-channel LPCM.
+    pub basic_audio: bool,
+    pub audio: Vec<AudioDescriptor>,
+}
+
+/// EDID parser.
+pub struct Edid;
+
+impl Edid {
+    /// Parse the EDID blocks of `data`, as returned by
+    /// [`crate::ioctl::g_edid`].
+    pub fn parse(data: &[u8]) -> Result<EdidInfo, EdidParseError> {
+        if data.is_empty() || !data.len().is_multiple_of(EDID_BLOCK_SIZE) {
+            return Err(EdidParseError::InvalidSize(data.len()));
+        }
+        if data[0..8] != EDID_HEADER {
+            return Err(EdidParseError::InvalidHeader);
+        }
+        for (i, block) in data.chunks(EDID_BLOCK_SIZE).enumerate() {
+            if block.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+                return Err(EdidParseError::InvalidChecksum(i));
+            }
+        }
+
+        let base = &data[0..EDID_BLOCK_SIZE];
+        let id = u16::from_be_bytes([base[8], base[9]]);
+        let manufacturer = [10, 5, 0]
+            .iter()
+            .map(|shift| (b'A' - 1 + ((id >> shift) & 0x1f) as u8) as char)
+            .collect();
+        let version = (base[18], base[19]);
+
+        let mut info = EdidInfo {
+            manufacturer,
+            product_code: u16::from_le_bytes([base[10], base[11]]),
+            version,
+            monitor_name: None,
+            preferred_timing: None,
+            resolutions: Vec::new(),
+            basic_audio: false,
+            audio: Vec::new(),
+        };
+
+        // Established timings.
+        let established = u32::from_le_bytes([base[35], base[36], base[37] >> 7, 0]);
+        info.resolutions.extend(
+            ESTABLISHED_TIMINGS
+                .iter()
+                .enumerate()
+                .filter(|(bit, _)| established & (1 << bit) != 0)
+                .map(|(_, &(width, height, refresh_rate))| Resolution {
+                    width,
+                    height,
+                    refresh_rate,
+                }),
+        );
+
+        // Standard timings.
+        for timing in base[38..54].chunks(2) {
+            if timing[0] == 0x00 || timing == [0x01, 0x01] {
+                continue;
+            }
+            let width = (timing[0] as u32 + 31) * 8;
+            let height = match timing[1] >> 6 {
+                // 16:10 since EDID 1.3, 1:1 before.
+                0 if version < (1, 3) => width,
+                0 => width * 10 / 16,
+                1 => width * 3 / 4,
+                2 => width * 4 / 5,
+                _ => width * 9 / 16,
+            };
+            info.resolutions.push(Resolution {
+                width,
+                height,
+                refresh_rate: (timing[1] & 0x3f) as u32 + 60,
+            });
+        }
+
+        // Detailed timings and display descriptors.
+        for desc in base[DESCRIPTORS_OFFSET..DESCRIPTORS_OFFSET + 4 * DESCRIPTOR_SIZE]
+            .chunks(DESCRIPTOR_SIZE)
+        {
+            match DetailedTiming::parse(desc) {
+                Some(timing) => {
+                    if info.preferred_timing.is_none() {
+                        info.preferred_timing = Some(timing);
+                    }
+                    info.resolutions.push(timing.resolution());
+                }
+                None if desc[3] == DESCRIPTOR_MONITOR_NAME => {
+                    info.monitor_name = Some(parse_descriptor_text(&desc[5..]));
+                }
+                None => (),
+            }
+        }
+
+        for block in data[EDID_BLOCK_SIZE..].chunks(EDID_BLOCK_SIZE) {
+            if block[0] == CEA_EXTENSION_TAG {
+                parse_cea_extension(block, &mut info);
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+/// Returns the text of a display descriptor, which is terminated by a line
+/// feed and padded with spaces.
+fn parse_descriptor_text(text: &[u8]) -> String {
+    let text = text.split(|&c| c == b'\n').next().unwrap_or(text);
+
+    String::from_utf8_lossy(text).trim_end().to_string()
+}
+
+/// Parse the audio capabilities and detailed timings of CEA-861 extension
+/// block `block` into `info`.
+fn parse_cea_extension(block: &[u8], info: &mut EdidInfo) {
+    info.basic_audio |= block[3] & CEA_BASIC_AUDIO != 0;
+
+    // Data blocks are located between the header and the detailed timings.
+    let dtd_offset = (block[2] as usize).min(EDID_BLOCK_SIZE - 1);
+    if dtd_offset >= 4 {
+        let mut data_blocks = &block[4..dtd_offset];
+        while let Some((&header, rest)) = data_blocks.split_first() {
+            let len = ((header & 0x1f) as usize).min(rest.len());
+            let (payload, rest) = rest.split_at(len);
+            if header >> 5 == CEA_AUDIO_DATA_BLOCK {
+                info.audio.extend(payload.chunks_exact(3).map(|sad| {
+                    AudioDescriptor {
+                        format: (sad[0] >> 3) & 0x0f,
+                        max_channels: (sad[0] & 0x07) + 1,
+                        sample_rates: AUDIO_SAMPLE_RATES
+                            .iter()
+                            .enumerate()
+                            .filter(|(bit, _)| sad[1] & (1 << bit) != 0)
+                            .map(|(_, &rate)| rate)
+                            .collect(),
+                    }
+                }));
+            }
+            data_blocks = rest;
+        }
+    }
+
+    // The detailed timings of an extension block are all valid resolutions.
+    if dtd_offset > 0 {
+        info.resolutions.extend(
+            block[dtd_offset..EDID_BLOCK_SIZE - 1]
+                .chunks_exact(DESCRIPTOR_SIZE)
+                .map_while(DetailedTiming::parse)
+                .map(|timing| timing.resolution()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fix the checksum of each block of `edid`.
+    fn fix_checksums(edid: &mut [u8]) {
+        for block in edid.chunks_mut(EDID_BLOCK_SIZE) {
+            let sum = block[..EDID_BLOCK_SIZE - 1]
+                .iter()
+                .fold(0u8, |sum, b| sum.wrapping_add(*b));
+            block[EDID_BLOCK_SIZE - 1] = 0u8.wrapping_sub(sum);
+        }
+    }
+
+    /// Returns a two-block EDID for a 1080p60 monitor with LPCM audio.
+    fn test_edid() -> Vec<u8> {
+        let mut edid = vec![0u8; 2 * EDID_BLOCK_SIZE];
+
+        edid[0..8].copy_from_slice(&EDID_HEADER);
+        // "ABC", product code 0x1234.
+        edid[8..12].copy_from_slice(&[0x04, 0x43, 0x34, 0x12]);
+        // EDID 1.3.
+        edid[18..20].copy_from_slice(&[1, 3]);
+        // 640x480@60 and 1024x768@60.
+        edid[35..38].copy_from_slice(&[0x20, 0x08, 0x00]);
+        // 1280x720@60, and unused standard timings.
+        edid[38..40].copy_from_slice(&[0x81, 0xc0]);
+        edid[40..54].fill(0x01);
+        // 1920x1080@60 preferred timing.
+        edid[54..72].copy_from_slice(&[
+            0x02, 0x3a, 0x80, 0x18, 0x71, 0x38, 0x2d, 0x40, 0x58, 0x2c, 0x45, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x1e,
+        ]);
+        // Monitor name.
+        edid[72..77].copy_from_slice(&[0x00, 0x00, 0x00, 0xfc, 0x00]);
+        edid[77..90].copy_from_slice(b"Test Monitor\n");
+        // One extension block.
+        edid[126] = 1;
+
+        // CEA extension with basic audio and an audio data block containing a
+        // 2-channel LPCM descriptor at 32, 44.1 and 48 kHz.
+        let cea = &mut edid[EDID_BLOCK_SIZE..];
+        cea[0..4].copy_from_slice(&[CEA_EXTENSION_TAG, 3, 8, CEA_BASIC_AUDIO]);
+        cea[4..8].copy_from_slice(&[0x23, 0x09, 0x07, 0x07]);
+
+        fix_checksums(&mut edid);
+
+        edid
+    }
+
+    #[test]
+    fn parse_edid() {
+        let info = Edid::parse(&test_edid()).unwrap();
+
+        assert_eq!(info.manufacturer, "ABC");
+        assert_eq!(info.product_code, 0x1234);
+        assert_eq!(info.version, (1, 3));
+        assert_eq!(info.monitor_name.as_deref(), Some("Test Monitor"));
+
+        let preferred = info.preferred_timing.unwrap();
+        assert_eq!(preferred.pixel_clock, 148500);
+        assert_eq!(
+            preferred.resolution(),
+            Resolution {
+                width: 1920,
+                height: 1080,
+                refresh_rate: 60,
+            }
+        );
+        assert!(!preferred.interlaced);
+
+        let resolutions = info
+            .resolutions
+            .iter()
+            .map(|r| (r.width, r.height, r.refresh_rate))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            resolutions,
+            vec![
+                (640, 480, 60),
+                (1024, 768, 60),
+                (1280, 720, 60),
+                (1920, 1080, 60)
+            ]
+        );
+
+        assert!(info.basic_audio);
+        assert_eq!(
+            info.audio,
+            vec![AudioDescriptor {
+                format: 1,
+                max_channels: 2,
+                sample_rates: vec![32000, 44100, 48000],
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_edid() {
+        assert_eq!(Edid::parse(&[]), Err(EdidParseError::InvalidSize(0)));
+        assert_eq!(
+            Edid::parse(&[0u8; 100]),
+            Err(EdidParseError::InvalidSize(100))
+        );
+
+        let mut edid = test_edid();
+        edid[0] = 0xff;
+        assert_eq!(Edid::parse(&edid), Err(EdidParseError::InvalidHeader));
+
+        let mut edid = test_edid();
+        edid[EDID_BLOCK_SIZE + 5] ^= 0xff;
+        assert_eq!(Edid::parse(&edid), Err(EdidParseError::InvalidChecksum(1)));
+    }
+}
@@ -1,37 +1,175 @@
 //! High-level interface for a [V4L2 video
 //! encoder](https://www.kernel.org/doc/html/latest/userspace-api/media/v4l/dev-encoder.html).
 use crate::{
+    bitstream::{h264::NalUnitType, NalIterator},
+    controls::{
+        codec::{VideoBitrate, VideoForceKeyFrame, VideoHeaderMode, VideoPrependSpsPpsToIdr},
+        set_control,
+    },
     device::{
         poller::{DeviceEvent, PollError, PollEvent, Poller, Waker},
         queue::{
+            buffer::BufferStats,
             direction::{Capture, Output},
             dqbuf::DqBuffer,
             handles_provider::HandlesProvider,
             BuffersAllocated, CanceledBuffer, CaptureQueueable, CreateQueueError, FormatBuilder,
             GetCaptureBufferByIndex, GetFreeBufferError, GetFreeCaptureBuffer, GetFreeOutputBuffer,
-            OutputQueueableProvider, Queue, QueueInit, RequestBuffersError,
+            GetOutputBufferByIndex, OutputQueueableProvider, Queue, QueueInit, RequestBuffersError,
         },
         AllocatedQueue, Device, DeviceConfig, DeviceOpenError, Stream, TryDequeue,
     },
+    error::AsErrno,
     ioctl::{
-        self, DqBufError, DqBufIoctlError, EncoderCommand, FormatFlags, GFmtError,
-        V4l2BufferFromError,
+        self, DqBufError, DqBufIoctlError, EncoderCmd, FormatFlags, GFmtError, SFmtError,
+        SelectionTarget, V4l2Buffer, V4l2BufferFromError,
     },
     memory::{BufferHandles, PrimitiveBufferHandles},
-    Format,
+    stats::{CodecStats, StatsRecorder},
+    watchdog::{RecoverError, Stalled, Watchdog},
+    Format, PlaneLayout, Rect,
 };
 
-use log::warn;
+use log::{debug, error, warn};
+use nix::errno::Errno;
 use std::{
     any::Any,
     io,
     path::Path,
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{atomic::AtomicUsize, mpsc, Arc},
     task::Wake,
     thread::JoinHandle,
+    time::Duration,
 };
 use thiserror::Error;
 
+/// Callback receiving the CAPTURE buffer containing the stream headers, see
+/// [`Encoder::set_header_cb`].
+pub type HeaderReadyCb<H> = Box<dyn FnMut(DqBuffer<Capture, H>) + Send>;
+
+/// Callback receiving the new OUTPUT format when the encoder is reconfigured, see
+/// [`Encoder::set_parameters_changed_cb`].
+pub type ParametersChangedCb = Box<dyn FnMut(&Format) + Send>;
+
+/// Error reported while encoding, see [`Encoder::set_error_cb`].
+#[derive(Debug, Error)]
+pub enum EncoderError {
+    #[error(
+        "encoded frame did not fit in the {buffer_size} bytes CAPTURE buffer, \
+        consider using {suggested_size} bytes"
+    )]
+    CaptureOverflow {
+        buffer_size: u32,
+        suggested_size: u32,
+    },
+    /// The driver flagged a CAPTURE buffer as erroneous for a reason other than it being too
+    /// small. Its contents are likely corrupted.
+    #[error("driver reported an error on CAPTURE buffer {index}")]
+    BufferError { index: u32 },
+    /// The driver has not made progress for longer than the timeout set with
+    /// [`Encoder::set_watchdog`], which usually means it has hung. The client can try to get it
+    /// going again with [`Encoder::recover`].
+    #[error("encoder stalled with {} OUTPUT buffers pending", .0.pending_output)]
+    Stalled(Stalled),
+}
+
+impl AsErrno for EncoderError {
+    fn errno(&self) -> Option<Errno> {
+        None
+    }
+}
+
+/// Callback receiving the errors occurring while encoding, see [`Encoder::set_error_cb`].
+pub type EncoderErrorCb = Box<dyn FnMut(EncoderError) + Send>;
+
+/// Policy used to size the CAPTURE buffers receiving the encoded stream, see
+/// [`Encoder::set_capture_buffer_size`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaptureBufferSize {
+    /// Use exactly this size, in bytes.
+    Explicit(u32),
+    /// Reserve this many bits per pixel of the OUTPUT resolution. Typical streams stay well under
+    /// 1 bit per pixel, but high-quality intra-only streams can need several.
+    BitsPerPixel(f32),
+    /// Reserve enough for a frame lasting `max_frame_duration` at `bitrate` bits per second,
+    /// multiplied by `multiplier` to leave room for frames larger than average like key frames.
+    Bitrate {
+        bitrate: u32,
+        max_frame_duration: Duration,
+        multiplier: u32,
+    },
+}
+
+impl CaptureBufferSize {
+    /// Minimum size of a CAPTURE buffer, so the stream headers always fit.
+    pub const MIN_SIZE: u32 = 4096;
+
+    /// Returns the size of the CAPTURE buffers, in bytes, when encoding frames of
+    /// `width`x`height` pixels.
+    pub fn compute(&self, width: u32, height: u32) -> u32 {
+        let size = match *self {
+            CaptureBufferSize::Explicit(size) => return size,
+            CaptureBufferSize::BitsPerPixel(bits_per_pixel) => {
+                width as f64 * height as f64 * bits_per_pixel as f64 / 8.0
+            }
+            CaptureBufferSize::Bitrate {
+                bitrate,
+                max_frame_duration,
+                multiplier,
+            } => bitrate as f64 / 8.0 * max_frame_duration.as_secs_f64() * multiplier as f64,
+        };
+
+        size.ceil().clamp(Self::MIN_SIZE as f64, u32::MAX as f64) as u32
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SetCaptureBufferSizeError {
+    #[error("error while obtaining format: {0}")]
+    GFmt(#[from] GFmtError),
+    #[error("error while setting CAPTURE format: {0}")]
+    SFmt(#[from] SFmtError),
+    #[error(
+        "driver only granted {granted} bytes for CAPTURE buffers out of the {requested} requested"
+    )]
+    SizeRejected { requested: u32, granted: u32 },
+}
+
+impl AsErrno for SetCaptureBufferSizeError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            SetCaptureBufferSizeError::GFmt(e) => e.errno(),
+            SetCaptureBufferSizeError::SFmt(e) => e.errno(),
+            SetCaptureBufferSizeError::SizeRejected { .. } => None,
+        }
+    }
+}
+
+/// Returns the error to report if the encoded data in `buffer` is not valid.
+///
+/// Drivers running out of space fill the buffer completely, and then either flag it with an error
+/// or continue the frame into the next buffer. Other errors flagged by the driver are reported as
+/// such.
+fn check_capture_buffer(buffer: &V4l2Buffer) -> Option<EncoderError> {
+    let plane = buffer.plane(0).unwrap_or_default();
+    let buffer_size = plane.length;
+
+    if buffer_size > 0 && plane.bytesused >= buffer_size {
+        Some(EncoderError::CaptureOverflow {
+            buffer_size,
+            suggested_size: buffer_size
+                .saturating_mul(2)
+                .max(CaptureBufferSize::MIN_SIZE),
+        })
+    } else if buffer.has_error() {
+        Some(EncoderError::BufferError {
+            index: buffer.index(),
+        })
+    } else {
+        None
+    }
+}
+
 /// Trait implemented by all states of the encoder.
 pub trait EncoderState {}
 
@@ -57,6 +195,16 @@ pub enum EncoderOpenError {
     NotAnEncoder,
 }
 
+impl AsErrno for EncoderOpenError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            EncoderOpenError::DeviceOpenError(e) => e.errno(),
+            EncoderOpenError::CreateQueueError(e) => e.errno(),
+            EncoderOpenError::NotAnEncoder => None,
+        }
+    }
+}
+
 impl Encoder<AwaitingCaptureFormat> {
     pub fn open(path: &Path) -> Result<Self, EncoderOpenError> {
         let config = DeviceConfig::new().non_blocking_dqbuf();
@@ -176,6 +324,40 @@ pub struct AwaitingCaptureBuffers<OP: BufferHandles> {
 impl<OP: BufferHandles> EncoderState for AwaitingCaptureBuffers<OP> {}
 
 impl<OP: BufferHandles> Encoder<AwaitingCaptureBuffers<OP>> {
+    /// Set the size of the CAPTURE buffers according to `size` instead of using the driver's
+    /// default, which can be wasteful for low bitrates or too small for high-quality streams.
+    ///
+    /// Returns the size accepted by the driver, which may be larger than requested, or
+    /// [`SetCaptureBufferSizeError::SizeRejected`] if the driver only accepted a smaller size.
+    pub fn set_capture_buffer_size(
+        &mut self,
+        size: CaptureBufferSize,
+    ) -> Result<u32, SetCaptureBufferSizeError> {
+        let output_format: Format = self.state.output_queue.get_format()?;
+        let requested = size.compute(output_format.width, output_format.height);
+
+        let capture_format: Format = self
+            .state
+            .capture_queue
+            .change_format()?
+            .set_planes_layout(vec![PlaneLayout {
+                sizeimage: requested,
+                ..Default::default()
+            }])
+            .apply()?;
+        let granted = capture_format
+            .plane_fmt
+            .first()
+            .map(|plane| plane.sizeimage)
+            .unwrap_or_default();
+
+        if granted < requested {
+            return Err(SetCaptureBufferSizeError::SizeRejected { requested, granted });
+        }
+
+        Ok(granted)
+    }
+
     pub fn allocate_capture_buffers_generic<P: HandlesProvider>(
         self,
         memory_type: <P::HandleType as BufferHandles>::SupportedMemoryType,
@@ -192,6 +374,11 @@ impl<OP: BufferHandles> Encoder<AwaitingCaptureBuffers<OP>> {
                     .request_buffers_generic::<P::HandleType>(memory_type, num_capture as u32)?,
                 capture_memory_provider,
                 poll_wakeups_counter: None,
+                header_mode: None,
+                header_cb: None,
+                parameters_changed_cb: None,
+                error_cb: None,
+                watchdog_timeout: None,
             },
         })
     }
@@ -217,6 +404,11 @@ pub struct ReadyToEncode<OP: BufferHandles, P: HandlesProvider> {
     capture_queue: Queue<Capture, BuffersAllocated<P::HandleType>>,
     capture_memory_provider: P,
     poll_wakeups_counter: Option<Arc<AtomicUsize>>,
+    header_mode: Option<VideoHeaderMode>,
+    header_cb: Option<HeaderReadyCb<P::HandleType>>,
+    parameters_changed_cb: Option<ParametersChangedCb>,
+    error_cb: Option<EncoderErrorCb>,
+    watchdog_timeout: Option<Duration>,
 }
 impl<OP: BufferHandles, P: HandlesProvider> EncoderState for ReadyToEncode<OP, P> {}
 
@@ -230,6 +422,159 @@ where
         self
     }
 
+    /// Select whether the stream headers (e.g. SPS and PPS for H.264) are produced in their own
+    /// CAPTURE buffer or joined with the first encoded frame.
+    ///
+    /// Muxers usually need the headers separately, e.g. to write the `avcC` box of an MP4 file. In
+    /// [`VideoHeaderMode::Separate`] mode, the buffer containing the headers is passed to the
+    /// callback set with [`Encoder::set_header_cb`] before the first frame is produced. Drivers
+    /// that do not support this mode return an error, in which case [`split_h264_headers`] can be
+    /// used on the first frame as a fallback for H.264.
+    pub fn set_header_mode(
+        mut self,
+        mode: VideoHeaderMode,
+    ) -> Result<Self, ioctl::ExtControlError> {
+        set_control::<VideoHeaderMode>(&self.device, mode)?;
+        self.state.header_mode = Some(mode);
+        Ok(self)
+    }
+
+    /// Set the callback receiving the CAPTURE buffer that contains the stream headers when the
+    /// encoder is in [`VideoHeaderMode::Separate`] mode. Without this callback, the headers are
+    /// passed to the output callback like the encoded frames.
+    pub fn set_header_cb<F>(mut self, header_cb: F) -> Self
+    where
+        F: FnMut(DqBuffer<Capture, P::HandleType>) + Send + 'static,
+    {
+        self.state.header_cb = Some(Box::new(header_cb));
+        self
+    }
+
+    /// Set the callback notified with the new OUTPUT format every time the encoder is
+    /// reconfigured with [`Encoder::reconfigure`], whatever its [`VideoHeaderMode`].
+    ///
+    /// The callback is called before any frame of the new format is encoded, so the client knows
+    /// that the stream parameters (e.g. SPS and PPS for H.264) change from the next encoded
+    /// frame, which is a key frame.
+    pub fn set_parameters_changed_cb<F>(mut self, parameters_changed_cb: F) -> Self
+    where
+        F: FnMut(&Format) + Send + 'static,
+    {
+        self.state.parameters_changed_cb = Some(Box::new(parameters_changed_cb));
+        self
+    }
+
+    /// Set the callback receiving the errors that occur while encoding, e.g. encoded frames not
+    /// fitting in the CAPTURE buffers (see [`Encoder::set_capture_buffer_size`]).
+    pub fn set_error_cb<F>(mut self, error_cb: F) -> Self
+    where
+        F: FnMut(EncoderError) + Send + 'static,
+    {
+        self.state.error_cb = Some(Box::new(error_cb));
+        self
+    }
+
+    /// Report an [`EncoderError::Stalled`] error to the error callback if the driver does not
+    /// make any progress for `timeout` while frames are pending, e.g. because its firmware
+    /// crashed.
+    ///
+    /// Time during which the driver cannot be expected to make progress, i.e. when no frame is
+    /// queued or the client holds all the CAPTURE buffers, is not counted. See [`Watchdog`] for
+    /// details.
+    pub fn set_watchdog(mut self, timeout: Duration) -> Self {
+        self.state.watchdog_timeout = Some(timeout);
+        self
+    }
+
+    /// Ask the encoder to repeat the stream headers before every IDR frame, so decoding can start
+    /// from any of them. Only supported by some drivers.
+    pub fn set_prepend_headers_to_idr(self, prepend: bool) -> Result<Self, ioctl::ExtControlError> {
+        set_control::<VideoPrependSpsPpsToIdr>(&self.device, VideoPrependSpsPpsToIdr(prepend))?;
+        Ok(self)
+    }
+
+    /// Change the format of the frames to encode to `format`, e.g. to follow a change of
+    /// resolution of the source.
+    ///
+    /// The buffers of both queues are freed and reallocated with the same count and memory type,
+    /// as the driver will not change the resolution while CAPTURE buffers are allocated, and all
+    /// OUTPUT buffers need to be able to hold frames of the new size. If the driver aligns the
+    /// OUTPUT resolution, the crop rectangle is set so only `format.width`x`format.height` pixels
+    /// are encoded. CAPTURE buffers keep at least their current size.
+    ///
+    /// The capture memory provider must be able to provide buffers large enough for the new
+    /// format. The callback set with [`Encoder::set_parameters_changed_cb`] is called with the
+    /// new format once it is applied.
+    pub fn reconfigure(mut self, format: &Format) -> Result<Self, ReconfigureError> {
+        let output_memory_type = self.state.output_queue.memory_type();
+        let num_output = self.state.output_queue.num_buffers();
+        let capture_memory_type = self.state.capture_queue.memory_type();
+        let num_capture = self.state.capture_queue.num_buffers();
+        let capture_size = self
+            .state
+            .capture_queue
+            .get_format::<Format>()?
+            .plane_fmt
+            .first()
+            .map(|plane| plane.sizeimage)
+            .unwrap_or_default();
+
+        let mut output_queue = self.state.output_queue.free_buffers()?.queue;
+        let mut capture_queue = self.state.capture_queue.free_buffers()?.queue;
+
+        let output_format: Format = output_queue.change_format()?.set_from(format).apply()?;
+        if (output_format.width, output_format.height) != (format.width, format.height) {
+            output_queue.set_selection(
+                SelectionTarget::Crop,
+                Rect::new(0, 0, format.width, format.height),
+            )?;
+        }
+
+        let capture_format: Format = capture_queue.get_format()?;
+        let new_capture_size = capture_format
+            .plane_fmt
+            .first()
+            .map(|plane| plane.sizeimage)
+            .unwrap_or_default();
+        if new_capture_size < capture_size {
+            capture_queue
+                .change_format()?
+                .set_planes_layout(vec![PlaneLayout {
+                    sizeimage: capture_size,
+                    ..Default::default()
+                }])
+                .apply::<Format>()?;
+        }
+
+        encoder_event!(
+            width = output_format.width,
+            height = output_format.height,
+            "reconfigured"
+        );
+        if let Some(parameters_changed_cb) = &mut self.state.parameters_changed_cb {
+            parameters_changed_cb(&output_format);
+        }
+
+        Ok(Encoder {
+            device: self.device,
+            state: ReadyToEncode {
+                output_queue: output_queue
+                    .request_buffers_generic::<OP>(output_memory_type, num_output as u32)?,
+                capture_queue: capture_queue.request_buffers_generic::<P::HandleType>(
+                    capture_memory_type,
+                    num_capture as u32,
+                )?,
+                capture_memory_provider: self.state.capture_memory_provider,
+                poll_wakeups_counter: self.state.poll_wakeups_counter,
+                header_mode: self.state.header_mode,
+                header_cb: self.state.header_cb,
+                parameters_changed_cb: self.state.parameters_changed_cb,
+                error_cb: self.state.error_cb,
+                watchdog_timeout: self.state.watchdog_timeout,
+            },
+        })
+    }
+
     pub fn start<InputDoneCb, OutputReadyCb>(
         self,
         input_done_cb: InputDoneCb,
@@ -245,12 +590,32 @@ where
         let mut output_poller = Poller::new(Arc::clone(&self.device))?;
         output_poller.enable_event(DeviceEvent::OutputReady)?;
 
+        let stats = Arc::new(StatsRecorder::new());
+        self.state.capture_queue.set_frame_drop_cb({
+            let stats = Arc::clone(&stats);
+            move |count| stats.frames_lost(count)
+        });
+
+        // Only single out the headers if the client wants them separately.
+        let header_cb = match self.state.header_mode {
+            Some(VideoHeaderMode::Separate) => self.state.header_cb,
+            _ => None,
+        };
+
+        let (recover_sender, recover_receiver) = mpsc::channel();
         let mut encoder_thread = EncoderThread::new(
             &self.device,
             self.state.capture_queue,
             self.state.capture_memory_provider,
             output_ready_cb,
+            header_cb,
+            self.state.error_cb,
+            Arc::clone(&stats),
+            self.state.watchdog_timeout.map(Watchdog::new),
+            self.state.output_queue.buffer_stats(),
+            recover_sender,
         )?;
+        let recover_waker = Arc::clone(&encoder_thread.recover_waker);
 
         if let Some(counter) = &self.state.poll_wakeups_counter {
             output_poller.set_poll_counter(Arc::clone(counter));
@@ -267,6 +632,11 @@ where
                 output_queue: self.state.output_queue,
                 input_done_cb,
                 output_poller,
+                stats,
+                header_mode: self.state.header_mode,
+                parameters_changed_cb: self.state.parameters_changed_cb,
+                recover_waker,
+                recover_receiver,
                 handle,
             },
         })
@@ -283,6 +653,14 @@ where
     input_done_cb: InputDoneCb,
     output_poller: Poller,
 
+    stats: Arc<StatsRecorder>,
+    header_mode: Option<VideoHeaderMode>,
+    parameters_changed_cb: Option<ParametersChangedCb>,
+
+    // Waker asking the encoder thread to restart the CAPTURE queue, and receiver of its result.
+    recover_waker: Arc<Waker>,
+    recover_receiver: mpsc::Receiver<Result<(), RecoverError>>,
+
     handle: JoinHandle<EncoderThread<P, OutputReadyCb>>,
 }
 impl<OP, P, InputDoneCb, OutputReadyCb> EncoderState for Encoding<OP, P, InputDoneCb, OutputReadyCb>
@@ -312,6 +690,16 @@ pub enum GetBufferError {
     GetFreeBufferError(#[from] GetFreeBufferError),
 }
 
+impl AsErrno for GetBufferError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GetBufferError::DequeueError(e) => e.errno(),
+            GetBufferError::PollError(e) => e.errno(),
+            GetBufferError::GetFreeBufferError(e) => e.errno(),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum EncoderStopError {
     #[error("error while sending STOP command")]
@@ -324,6 +712,49 @@ pub enum EncoderStopError {
     OutputQueueStreamoffError(ioctl::StreamOffError),
 }
 
+impl AsErrno for EncoderStopError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            EncoderStopError::EncoderCmdError(e) => e.errno(),
+            EncoderStopError::ThreadPanickedError(_) => None,
+            EncoderStopError::CaptureQueueStreamoffError(e) => e.errno(),
+            EncoderStopError::OutputQueueStreamoffError(e) => e.errno(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ReconfigureError {
+    #[error("error while stopping the encoder: {0}")]
+    Stop(#[from] EncoderStopError),
+    #[error("error while freeing buffers: {0}")]
+    FreeBuffers(#[from] ioctl::ReqbufsError),
+    #[error("error while obtaining format: {0}")]
+    GFmt(#[from] GFmtError),
+    #[error("error while setting format: {0}")]
+    SFmt(#[from] SFmtError),
+    #[error("error while setting the OUTPUT crop rectangle: {0}")]
+    SSelection(#[from] ioctl::SSelectionError),
+    #[error("error while allocating buffers: {0}")]
+    RequestBuffers(#[from] RequestBuffersError),
+    #[error("error while restarting the encoder: {0}")]
+    Start(#[from] io::Error),
+}
+
+impl AsErrno for ReconfigureError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            ReconfigureError::Stop(e) => e.errno(),
+            ReconfigureError::FreeBuffers(e) => e.errno(),
+            ReconfigureError::GFmt(e) => e.errno(),
+            ReconfigureError::SFmt(e) => e.errno(),
+            ReconfigureError::SSelection(e) => e.errno(),
+            ReconfigureError::RequestBuffers(e) => e.errno(),
+            ReconfigureError::Start(e) => e.errno(),
+        }
+    }
+}
+
 impl<OP, P, InputDoneCb, OutputReadyCb> Encoder<Encoding<OP, P, InputDoneCb, OutputReadyCb>>
 where
     OP: BufferHandles,
@@ -333,7 +764,16 @@ where
 {
     /// Stop the encoder, and returns the encoder ready to be started again.
     pub fn stop(self) -> Result<Encoder<ReadyToEncode<OP, P>>, EncoderStopError> {
-        ioctl::encoder_cmd::<_, ()>(&*self.device, &EncoderCommand::Stop(false))?;
+        self.stop_with_callbacks().map(|(encoder, _, _)| encoder)
+    }
+
+    /// Stop the encoder like [`Encoder::stop`], also returning the callbacks it was started with.
+    #[allow(clippy::type_complexity)]
+    fn stop_with_callbacks(
+        self,
+    ) -> Result<(Encoder<ReadyToEncode<OP, P>>, InputDoneCb, OutputReadyCb), EncoderStopError> {
+        ioctl::encoder_cmd::<_, ()>(&*self.device, EncoderCmd::stop())?;
+        encoder_event!("drain started");
 
         // The encoder thread should receive the LAST buffer and exit on its own.
         let encoding_thread = self
@@ -356,15 +796,54 @@ where
             (self.state.input_done_cb)(CompletedOutputBuffer::Canceled(buffer));
         }
 
-        Ok(Encoder {
+        let encoder = Encoder {
             device: self.device,
             state: ReadyToEncode {
                 output_queue: self.state.output_queue,
                 capture_queue: encoding_thread.capture_queue,
                 capture_memory_provider: encoding_thread.capture_memory_provider,
                 poll_wakeups_counter: None,
+                header_mode: self.state.header_mode,
+                header_cb: encoding_thread.header_cb,
+                parameters_changed_cb: self.state.parameters_changed_cb,
+                error_cb: encoding_thread.error_cb,
+                watchdog_timeout: encoding_thread.watchdog.as_ref().map(Watchdog::timeout),
             },
-        })
+        };
+
+        Ok((
+            encoder,
+            self.state.input_done_cb,
+            encoding_thread.output_ready_cb,
+        ))
+    }
+
+    /// Returns the format of the frames to encode, which changes after [`Encoder::reconfigure`].
+    pub fn get_output_format(&self) -> Result<Format, GFmtError> {
+        self.state.output_queue.get_format()
+    }
+
+    /// Returns a snapshot of the runtime statistics of the encoder.
+    pub fn stats(&self) -> CodecStats {
+        self.state
+            .stats
+            .set_output_queue_depth(self.state.output_queue.num_queued_buffers());
+        self.state.stats.snapshot()
+    }
+
+    /// Reset the runtime statistics of the encoder.
+    pub fn reset_stats(&self) {
+        self.state.stats.reset()
+    }
+
+    /// Ask the encoder to produce a key frame as soon as possible.
+    pub fn force_keyframe(&self) -> Result<(), ioctl::ExtControlError> {
+        set_control::<VideoForceKeyFrame>(&self.device, VideoForceKeyFrame)
+    }
+
+    /// Change the target bitrate of the encoder, in bits per second, while encoding.
+    pub fn set_bitrate(&self, bitrate: u32) -> Result<(), ioctl::ExtControlError> {
+        set_control::<VideoBitrate>(&self.device, VideoBitrate(bitrate as i32))
     }
 
     /// Attempts to dequeue and release output buffers that the driver is done with.
@@ -374,6 +853,7 @@ where
         while output_queue.num_queued_buffers() > 0 {
             match output_queue.try_dequeue() {
                 Ok(buf) => {
+                    self.state.stats.output_dequeued(&buf.data, buf.queued_at());
                     (self.state.input_done_cb)(CompletedOutputBuffer::Dequeued(buf));
                 }
                 Err(DqBufError::IoctlError(DqBufIoctlError::NotReady)) => break,
@@ -402,6 +882,76 @@ where
     }
 }
 
+impl<OP, P, InputDoneCb, OutputReadyCb> Encoder<Encoding<OP, P, InputDoneCb, OutputReadyCb>>
+where
+    OP: BufferHandles,
+    P: HandlesProvider,
+    InputDoneCb: Fn(CompletedOutputBuffer<OP>),
+    OutputReadyCb: FnMut(DqBuffer<Capture, P::HandleType>) + Send + 'static,
+    for<'a> Queue<Capture, BuffersAllocated<P::HandleType>>:
+        GetFreeCaptureBuffer<'a, P::HandleType> + GetCaptureBufferByIndex<'a, P::HandleType>,
+{
+    /// Change the format of the frames to encode to `format` without interrupting the session,
+    /// e.g. when the resolution of the source changes.
+    ///
+    /// The encoder is drained, so all the frames queued so far are encoded and passed to the
+    /// output callback, then reconfigured as with [`Encoder::reconfigure`] on a stopped encoder,
+    /// and finally restarted with the same callbacks. The first frame encoded after this call
+    /// starts a new stream segment: it is a key frame, and in [`VideoHeaderMode::Separate`] mode
+    /// the new stream headers are passed to the header callback before it. In all modes, the
+    /// callback set with [`Encoder::set_parameters_changed_cb`] is called before the encoder is
+    /// restarted.
+    pub fn reconfigure(self, format: &Format) -> Result<Self, ReconfigureError> {
+        let (encoder, input_done_cb, output_ready_cb) = self.stop_with_callbacks()?;
+
+        Ok(encoder
+            .reconfigure(format)?
+            .start(input_done_cb, output_ready_cb)?)
+    }
+}
+
+impl<OP, P, InputDoneCb, OutputReadyCb> Encoder<Encoding<OP, P, InputDoneCb, OutputReadyCb>>
+where
+    OP: BufferHandles,
+    P: HandlesProvider,
+    InputDoneCb: Fn(CompletedOutputBuffer<OP>),
+    OutputReadyCb: FnMut(DqBuffer<Capture, P::HandleType>) + Send,
+    for<'a> Queue<Output, BuffersAllocated<OP>>: GetOutputBufferByIndex<'a, OP>,
+{
+    /// Attempt to get the encoder going again after an [`EncoderError::Stalled`] error.
+    ///
+    /// Both queues are stopped and restarted, and the frames that were pending are queued again
+    /// with the same content. The encoder starts a new stream segment from them, beginning with
+    /// a key frame. Frames that cannot be queued again are returned as canceled through the input
+    /// done callback.
+    ///
+    /// If the encoder already stalled again after a recovery without making any progress, nothing
+    /// is done and [`RecoverError::ReopenRequired`] is returned: the device should then be closed
+    /// and opened again.
+    pub fn recover(&self) -> Result<(), RecoverError> {
+        debug!("Recovery requested");
+        self.state.recover_waker.wake_by_ref();
+        self.state
+            .recover_receiver
+            .recv()
+            .map_err(|_| RecoverError::CaptureThread)??;
+
+        let canceled_buffers = self.state.output_queue.stream_off()?;
+        if let Err(e) = self.state.output_queue.stream_on() {
+            for buffer in canceled_buffers {
+                (self.state.input_done_cb)(CompletedOutputBuffer::Canceled(buffer));
+            }
+            return Err(e.into());
+        }
+        for buffer in self.state.output_queue.requeue_canceled(canceled_buffers) {
+            (self.state.input_done_cb)(CompletedOutputBuffer::Canceled(buffer));
+        }
+
+        debug!("Recovery complete");
+        Ok(())
+    }
+}
+
 impl<'a, OP, P, InputDoneCb, OutputReadyCb> OutputQueueableProvider<'a, OP>
     for Encoder<Encoding<OP, P, InputDoneCb, OutputReadyCb>>
 where
@@ -475,8 +1025,24 @@ where
     poller: Poller,
     waker: Arc<Waker>,
     output_ready_cb: OutputReadyCb,
+    // Callback receiving the headers if they are produced in a separate buffer.
+    header_cb: Option<HeaderReadyCb<P::HandleType>>,
+    error_cb: Option<EncoderErrorCb>,
+    stats: Arc<StatsRecorder>,
+    // Watchdog reporting stalls of the driver, if enabled.
+    watchdog: Option<Watchdog>,
+    // Buffer counters of the OUTPUT queue, used to tell whether the driver has work pending.
+    output_buffers: Arc<BufferStats>,
+    // Waker signaled when the client requests a recovery, and sender of its result.
+    recover_waker: Arc<Waker>,
+    recover_sender: mpsc::Sender<Result<(), RecoverError>>,
 }
 
+/// Waker signaled when a CAPTURE buffer has been released by the client.
+const CAPTURE_READY: u32 = 0;
+/// Waker signaled when the client requests a recovery.
+const RECOVER_REQUESTED: u32 = 1;
+
 impl<P, OutputReadyCb> EncoderThread<P, OutputReadyCb>
 where
     P: HandlesProvider,
@@ -484,16 +1050,24 @@ where
     for<'a> Queue<Capture, BuffersAllocated<P::HandleType>>:
         GetFreeCaptureBuffer<'a, P::HandleType> + GetCaptureBufferByIndex<'a, P::HandleType>,
 {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         device: &Arc<Device>,
         capture_queue: Queue<Capture, BuffersAllocated<P::HandleType>>,
         capture_memory_provider: P,
         output_ready_cb: OutputReadyCb,
+        header_cb: Option<HeaderReadyCb<P::HandleType>>,
+        error_cb: Option<EncoderErrorCb>,
+        stats: Arc<StatsRecorder>,
+        watchdog: Option<Watchdog>,
+        output_buffers: Arc<BufferStats>,
+        recover_sender: mpsc::Sender<Result<(), RecoverError>>,
     ) -> io::Result<Self> {
         let mut poller = Poller::new(Arc::clone(device))?;
 
         poller.enable_event(DeviceEvent::CaptureReady)?;
-        let waker = poller.add_waker(0)?;
+        let waker = poller.add_waker(CAPTURE_READY)?;
+        let recover_waker = poller.add_waker(RECOVER_REQUESTED)?;
 
         Ok(EncoderThread {
             capture_queue,
@@ -501,6 +1075,13 @@ where
             poller,
             waker,
             output_ready_cb,
+            header_cb,
+            error_cb,
+            stats,
+            watchdog,
+            output_buffers,
+            recover_waker,
+            recover_sender,
         })
     }
 
@@ -510,9 +1091,13 @@ where
 
     fn run(mut self) -> Self {
         self.enqueue_capture_buffers();
+        // The encoder produces the headers again every time it is started.
+        let mut awaiting_header = self.header_cb.is_some();
 
         'polling: loop {
-            match self.capture_queue.num_queued_buffers() {
+            let num_queued_buffers = self.capture_queue.num_queued_buffers();
+            self.stats.set_capture_queue_depth(num_queued_buffers);
+            match num_queued_buffers {
                 // If there are no buffers on the CAPTURE queue, poll() will return
                 // immediately with EPOLLERR and we would loop indefinitely.
                 // Prevent this by temporarily disabling polling the device in such
@@ -530,22 +1115,39 @@ where
                 }
             }
 
+            // Wake up in time to check whether the driver has stalled.
+            let poll_timeout = self.watchdog.as_ref().map(Watchdog::next_check);
             // TODO handle errors - this system call can be interrupted and we
             // should leave in this case.
-            for event in self.poller.poll(None).unwrap() {
+            for event in self.poller.poll(poll_timeout).unwrap() {
                 match event {
                     // A CAPTURE buffer has been released by the client.
-                    PollEvent::Waker(0) => {
+                    PollEvent::Waker(CAPTURE_READY) => {
                         // Requeue all available CAPTURE buffers.
                         self.enqueue_capture_buffers();
                     }
+                    PollEvent::Waker(RECOVER_REQUESTED) => {
+                        self.recover();
+                        // The encoder starts a new stream after being restarted.
+                        awaiting_header = self.header_cb.is_some();
+                    }
                     // A CAPTURE buffer is ready to be dequeued.
                     PollEvent::Device(DeviceEvent::CaptureReady) => {
                         // Get the encoded buffer
                         // TODO Manage errors here, including corrupted buffers!
                         if let Ok(mut cap_buf) = self.capture_queue.try_dequeue() {
+                            self.stats.capture_dequeued(&cap_buf.data);
+                            if let Some(watchdog) = &mut self.watchdog {
+                                watchdog.progress();
+                            }
+                            if let Some(error) = check_capture_buffer(&cap_buf.data) {
+                                warn!("{}", error);
+                                if let Some(error_cb) = &mut self.error_cb {
+                                    error_cb(error);
+                                }
+                            }
                             let is_last = cap_buf.data.is_last();
-                            let is_empty = *cap_buf.data.get_first_plane().bytesused == 0;
+                            let is_empty = cap_buf.data.plane_bytesused(0) == Some(0);
 
                             // Add a drop callback to the dequeued buffer so we
                             // re-queue it as soon as it is dropped.
@@ -556,11 +1158,20 @@ where
 
                             // Empty buffers do not need to be passed to the client.
                             if !is_empty {
-                                (self.output_ready_cb)(cap_buf);
+                                match &mut self.header_cb {
+                                    // The first buffer contains the headers.
+                                    Some(header_cb) if awaiting_header => {
+                                        awaiting_header = false;
+                                        encoder_event!("header received");
+                                        header_cb(cap_buf);
+                                    }
+                                    _ => (self.output_ready_cb)(cap_buf),
+                                }
                             }
 
                             // Last buffer of the stream? Time for us to terminate.
                             if is_last {
+                                encoder_event!("drain complete");
                                 break 'polling;
                             }
                         } else {
@@ -571,11 +1182,61 @@ where
                     _ => panic!("Unexpected return from CAPTURE queue poll!"),
                 }
             }
+
+            self.check_watchdog();
         }
 
         self
     }
 
+    /// Report an [`EncoderError::Stalled`] error if the driver has not made progress for too long
+    /// while it had frames to encode.
+    fn check_watchdog(&mut self) {
+        let watchdog = match &mut self.watchdog {
+            Some(watchdog) => watchdog,
+            None => return,
+        };
+
+        // The driver cannot make progress without CAPTURE buffers.
+        let can_progress = self.capture_queue.num_queued_buffers() > 0;
+        if let Some(stalled) = watchdog.check(self.output_buffers.num_queued(), can_progress) {
+            error!(
+                "Encoder stalled with {} OUTPUT buffers pending",
+                stalled.pending_output
+            );
+            encoder_event!(pending_output = stalled.pending_output, "stalled");
+            if let Some(error_cb) = &mut self.error_cb {
+                error_cb(EncoderError::Stalled(stalled));
+            }
+        }
+    }
+
+    /// Restart the CAPTURE queue on behalf of [`Encoder::recover`] and send the result back.
+    fn recover(&mut self) {
+        let response = match &mut self.watchdog {
+            Some(watchdog) if watchdog.recovery_failed() => {
+                warn!("Encoder stalled again after a recovery");
+                Err(RecoverError::ReopenRequired)
+            }
+            watchdog => {
+                // The canceled buffers are queued again from the provider.
+                let res = self
+                    .capture_queue
+                    .stream_off()
+                    .map_err(RecoverError::from)
+                    .and_then(|_| self.capture_queue.stream_on().map_err(RecoverError::from));
+                if let Some(watchdog) = watchdog {
+                    watchdog.recovery_started();
+                }
+                res
+            }
+        };
+
+        encoder_event!(success = response.is_ok(), "recovery");
+        let _ = self.recover_sender.send(response);
+        self.enqueue_capture_buffers();
+    }
+
     fn enqueue_capture_buffers(&mut self) {
         'enqueue: while let Some(handles) = self.capture_memory_provider.get_handles(&self.waker) {
             if let Ok(buffer) = self
@@ -590,3 +1251,130 @@ where
         }
     }
 }
+
+/// Software fallback for encoders that can only produce the stream headers joined with the first
+/// frame (see [`Encoder::set_header_mode`]).
+///
+/// Scans `data`, the content of the first encoded buffer of an Annex-B H.264 stream, for the first
+/// NAL unit that is not a parameter set, and returns the size of the headers that precede it, i.e.
+/// `data[..size]` contains the SPS and PPS and `data[size..]` the first frame. Returns `None` if
+/// `data` does not start with parameter sets.
+///
+/// Prefer [`VideoHeaderMode::Separate`] when the driver supports it, as this relies on parsing the
+/// encoded stream.
+pub fn split_h264_headers(data: &[u8]) -> Option<usize> {
+    let mut has_parameter_sets = false;
+
+    for nal in NalIterator::new(data) {
+        match nal.h264_type() {
+            Some(NalUnitType::Sps | NalUnitType::Pps | NalUnitType::SpsExtension) => {
+                has_parameter_sets = true
+            }
+            // An access unit delimiter can precede the parameter sets.
+            Some(NalUnitType::AccessUnitDelimiter) if !has_parameter_sets => (),
+            // The NAL unit is a subslice of `data`, so its offset is the distance between both.
+            _ => {
+                return has_parameter_sets
+                    .then(|| nal.data().as_ptr() as usize - data.as_ptr() as usize)
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{check_capture_buffer, split_h264_headers, CaptureBufferSize, EncoderError};
+    use crate::{
+        ioctl::{BufferFlags, V4l2Buffer},
+        memory::MemoryType,
+        QueueType,
+    };
+
+    #[test]
+    fn test_capture_buffer_size() {
+        // A 300 kbps 1080p stream needs far less than a raw frame.
+        let size = CaptureBufferSize::Bitrate {
+            bitrate: 300_000,
+            max_frame_duration: Duration::from_millis(250),
+            multiplier: 4,
+        };
+        assert_eq!(size.compute(1920, 1080), 37500);
+        assert!(size.compute(1920, 1080) < 1920 * 1080 * 3 / 2);
+
+        assert_eq!(
+            CaptureBufferSize::BitsPerPixel(0.5).compute(1920, 1080),
+            129600
+        );
+        assert_eq!(CaptureBufferSize::Explicit(1000).compute(1920, 1080), 1000);
+        // Computed sizes are never too small for the headers.
+        assert_eq!(
+            CaptureBufferSize::BitsPerPixel(0.1).compute(16, 16),
+            CaptureBufferSize::MIN_SIZE
+        );
+    }
+
+    #[test]
+    fn test_capture_overflow() {
+        let mut buffer = V4l2Buffer::new(QueueType::VideoCaptureMplane, 3, MemoryType::Mmap);
+        *buffer.get_first_plane_mut().length = 8192;
+        *buffer.get_first_plane_mut().bytesused = 4000;
+        assert!(check_capture_buffer(&buffer).is_none());
+
+        // The driver filled the buffer completely.
+        *buffer.get_first_plane_mut().bytesused = 8192;
+        assert!(matches!(
+            check_capture_buffer(&buffer),
+            Some(EncoderError::CaptureOverflow {
+                buffer_size: 8192,
+                suggested_size: 16384,
+            })
+        ));
+
+        // The driver filled the buffer completely and flagged it as erroneous.
+        buffer.add_flags(BufferFlags::ERROR);
+        assert!(matches!(
+            check_capture_buffer(&buffer),
+            Some(EncoderError::CaptureOverflow { .. })
+        ));
+
+        // Other errors are not overflows.
+        *buffer.get_first_plane_mut().bytesused = 4000;
+        assert!(matches!(
+            check_capture_buffer(&buffer),
+            Some(EncoderError::BufferError { index: 3 })
+        ));
+        *buffer.get_first_plane_mut().bytesused = 0;
+        assert!(matches!(
+            check_capture_buffer(&buffer),
+            Some(EncoderError::BufferError { index: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_split_h264_headers() {
+        let sps = [0, 0, 0, 1, 0x67, 0x42, 0xc0, 0x1e];
+        let pps = [0, 0, 1, 0x68, 0xce, 0x3c, 0x80];
+        let idr = [0, 0, 0, 1, 0x65, 0x88, 0x84];
+        let aud = [0, 0, 0, 1, 0x09, 0xf0];
+
+        let stream = [&sps[..], &pps[..], &idr[..]].concat();
+        assert_eq!(split_h264_headers(&stream), Some(sps.len() + pps.len()));
+
+        let stream = [&aud[..], &sps[..], &pps[..], &idr[..]].concat();
+        assert_eq!(
+            split_h264_headers(&stream),
+            Some(aud.len() + sps.len() + pps.len())
+        );
+
+        // No parameter sets before the frame.
+        assert_eq!(split_h264_headers(&idr), None);
+        // Only parameter sets, e.g. the encoder produced the headers separately.
+        let stream = [&sps[..], &pps[..]].concat();
+        assert_eq!(split_h264_headers(&stream), None);
+        assert_eq!(split_h264_headers(&[]), None);
+    }
+}
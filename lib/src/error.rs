@@ -0,0 +1,246 @@
+//! Crate-wide error handling.
+//!
+//! Each layer of this crate (`ioctl`, `device`, `decoder`, ...) reports failures using its own
+//! error enums, which precisely describe what went wrong at that level and wrap the error of the
+//! layer below as their source. Two things make these errors easier to handle uniformly:
+//!
+//! * The [`AsErrno`] trait, implemented by all the error types of this crate that can result from
+//!   a system call, returns the `Errno` that caused the error, if any, no matter how deeply it is
+//!   nested. This allows e.g. a client to react to `EBUSY` without having to match every
+//!   intermediate error variant.
+//! * The [`Error`] type wraps any error of this crate with a short description of the operation
+//!   that failed (e.g. `"QBUF on OUTPUT queue index 3"`). [`Error`]s can themselves be wrapped
+//!   into other [`Error`]s, building a chain of contexts while preserving the original errno and
+//!   the typed source errors, which can be retrieved using [`Error::downcast_ref`].
+//!
+//! ```
+//! use nix::errno::Errno;
+//! use v4l2r::error::{AsErrno, ResultExt};
+//! use v4l2r::ioctl::StreamOnError;
+//!
+//! fn start() -> v4l2r::error::Result<()> {
+//!     let res: Result<(), StreamOnError> = Err(StreamOnError::IoctlError(Errno::EIO));
+//!     res.context("STREAMON on OUTPUT queue")
+//! }
+//!
+//! let err = start().context("starting decoder").unwrap_err();
+//! assert_eq!(err.errno(), Some(Errno::EIO));
+//! assert_eq!(
+//!     err.to_string(),
+//!     "starting decoder: STREAMON on OUTPUT queue: ioctl error: EIO: I/O error"
+//! );
+//! assert!(err.downcast_ref::<StreamOnError>().is_some());
+//! ```
+//!
+//! # Migrating from `IntoErrno`
+//!
+//! The `ioctl::IntoErrno` trait has been replaced by [`AsErrno`]:
+//!
+//! * `err.into_errno()` becomes `err.errno()`, which borrows the error instead of consuming it
+//!   and returns `Option<Errno>` instead of a raw `i32`. `None` is returned for errors that did
+//!   not originate from a system call, e.g. a failed conversion of an ioctl result. Callers that
+//!   need a code in all cases can keep using the `From<XError> for Errno` implementations, or use
+//!   `err.errno().unwrap_or(Errno::EINVAL)`.
+//! * Higher-level errors (e.g. [`crate::decoder::stateful::DecoderOpenError`]) now also implement
+//!   [`AsErrno`], so the errno of a failed ioctl can be recovered without matching their
+//!   variants.
+//! * Variants of higher-level errors that wrap a `nix::Error` or `io::Error` now expose it through
+//!   [`std::error::Error::source`].
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::fmt::{self, Debug, Display};
+use std::io;
+
+use nix::errno::Errno;
+
+/// Trait for errors that may have been caused by a failed system call.
+pub trait AsErrno {
+    /// Returns the `Errno` at the origin of this error, or `None` if the error was not caused by
+    /// a system call.
+    fn errno(&self) -> Option<Errno>;
+}
+
+impl AsErrno for Errno {
+    fn errno(&self) -> Option<Errno> {
+        Some(*self)
+    }
+}
+
+impl AsErrno for io::Error {
+    fn errno(&self) -> Option<Errno> {
+        self.raw_os_error().map(Errno::from_raw)
+    }
+}
+
+impl AsErrno for Infallible {
+    fn errno(&self) -> Option<Errno> {
+        match *self {}
+    }
+}
+
+impl<E: AsErrno + ?Sized> AsErrno for Box<E> {
+    fn errno(&self) -> Option<Errno> {
+        (**self).errno()
+    }
+}
+
+/// Error of any layer of this crate, along with a short description of the operation that
+/// failed.
+///
+/// The original error is kept as the [`std::error::Error::source`] of this error, and can be
+/// retrieved with [`Error::downcast_ref`].
+pub struct Error {
+    context: Cow<'static, str>,
+    errno: Option<Errno>,
+    source: Box<dyn std::error::Error + Send + Sync + 'static>,
+}
+
+impl Error {
+    /// Wraps `source` into a new error, using `context` to describe the operation that failed.
+    pub fn new<E>(context: impl Into<Cow<'static, str>>, source: E) -> Self
+    where
+        E: AsErrno + std::error::Error + Send + Sync + 'static,
+    {
+        Error {
+            context: context.into(),
+            errno: source.errno(),
+            source: Box::new(source),
+        }
+    }
+
+    /// Returns the description of the operation that failed.
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+
+    /// Returns the first error of type `E` in the chain of sources of this error, if any.
+    pub fn downcast_ref<E: std::error::Error + 'static>(&self) -> Option<&E> {
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(self.source.as_ref());
+        while let Some(err) = source {
+            if let Some(err) = err.downcast_ref::<E>() {
+                return Some(err);
+            }
+            source = err.source();
+        }
+
+        None
+    }
+}
+
+impl AsErrno for Error {
+    fn errno(&self) -> Option<Errno> {
+        self.errno
+    }
+}
+
+impl From<Error> for Errno {
+    fn from(err: Error) -> Self {
+        err.errno.unwrap_or(Errno::EINVAL)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.source)
+    }
+}
+
+impl Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Error")
+            .field("context", &self.context)
+            .field("errno", &self.errno)
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Extension trait for adding context to the errors of this crate.
+pub trait ResultExt<T> {
+    /// Wraps the error, if any, into an [`Error`] described by `context`.
+    fn context(self, context: impl Into<Cow<'static, str>>) -> Result<T>;
+
+    /// Same as [`ResultExt::context`], but only builds the context if an error occurred.
+    fn with_context<C, F>(self, f: F) -> Result<T>
+    where
+        C: Into<Cow<'static, str>>,
+        F: FnOnce() -> C;
+}
+
+impl<T, E> ResultExt<T> for std::result::Result<T, E>
+where
+    E: AsErrno + std::error::Error + Send + Sync + 'static,
+{
+    fn context(self, context: impl Into<Cow<'static, str>>) -> Result<T> {
+        self.map_err(|e| Error::new(context, e))
+    }
+
+    fn with_context<C, F>(self, f: F) -> Result<T>
+    where
+        C: Into<Cow<'static, str>>,
+        F: FnOnce() -> C,
+    {
+        self.map_err(|e| Error::new(f(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::{AsErrno, Error, ResultExt};
+    use crate::ioctl::{DqBufError, DqBufIoctlError, QBufIoctlError, StreamOnError};
+
+    #[test]
+    fn context_chain() {
+        let res: Result<(), _> = Err(StreamOnError::IoctlError(Errno::EIO));
+        let err = res
+            .context("STREAMON on OUTPUT queue")
+            .with_context(|| format!("starting decoder {}", 0))
+            .unwrap_err();
+
+        assert_eq!(err.context(), "starting decoder 0");
+        assert_eq!(err.errno(), Some(Errno::EIO));
+        assert!(matches!(
+            err.downcast_ref::<StreamOnError>(),
+            Some(StreamOnError::IoctlError(Errno::EIO))
+        ));
+        assert_eq!(
+            err.downcast_ref::<Error>().map(Error::context),
+            Some("STREAMON on OUTPUT queue")
+        );
+        assert!(err.downcast_ref::<QBufIoctlError>().is_none());
+    }
+
+    #[test]
+    fn errno_of_ioctl_errors() {
+        // Errors reported by the kernel.
+        assert_eq!(
+            DqBufError::<()>::IoctlError(DqBufIoctlError::Other(Errno::ENODEV)).errno(),
+            Some(Errno::ENODEV)
+        );
+        assert_eq!(
+            DqBufError::<()>::IoctlError(DqBufIoctlError::NotReady).errno(),
+            Some(Errno::EAGAIN)
+        );
+        // Errors not caused by a system call.
+        assert_eq!(DqBufError::ConversionError(()).errno(), None);
+        assert_eq!(QBufIoctlError::NumPlanesMismatch(1, 2).errno(), None);
+        // Without an errno, the context is still preserved but converting to an `Errno` falls
+        // back to `EINVAL`.
+        let err = Error::new(
+            "QBUF on OUTPUT queue index 3",
+            QBufIoctlError::DataOffsetNotSupported,
+        );
+        assert_eq!(err.errno(), None);
+        assert_eq!(Errno::from(err), Errno::EINVAL);
+    }
+}
@@ -0,0 +1,10 @@
+//! Helpers to work with pixel formats.
+
+pub mod drm;
+mod interlace;
+mod layout;
+mod stream_parm;
+
+pub use interlace::*;
+pub use layout::*;
+pub use stream_parm::*;
@@ -0,0 +1,185 @@
+//! Mapping between V4L2 pixel formats and DRM fourccs.
+//!
+//! V4L2 and DRM both describe formats using fourccs, but do not always use the same code for the
+//! same layout. V4L2 names RGB formats after the order of their components in memory, whereas DRM
+//! names them after their order in a little-endian word, so e.g. V4L2's `RGB3` is DRM's `BG24`.
+//! V4L2 also has separate multi-planar variants of formats (e.g. `NM12` for `NV12`) which store
+//! each plane in its own buffer, while DRM describes the planes of a framebuffer separately and
+//! thus only has one code for both.
+//!
+//! The deprecated V4L2 formats `RGB32` (`RGB4`) and `BGR32` (`BGR4`) are not mapped: whether
+//! their fourth byte is alpha or padding is driver-dependent, so e.g. `RGB32` cannot be assumed to
+//! be DRM's `XRGB8888`. V4L2's `XBGR32` (`XR24`) and `ABGR32` (`AR24`) are the unambiguous
+//! equivalents of DRM's `XRGB8888` and `ARGB8888`.
+//!
+//! Only the common raw formats are covered. Compressed formats have no DRM equivalent.
+//!
+//! With the `drm` feature, the conversions are also available as `PixelFormat::to_drm_fourcc`
+//! and `PixelFormat::from_drm_fourcc`.
+
+use crate::PixelFormat;
+
+/// Returns the DRM fourcc made of the 4 characters of `code`, like the `fourcc_code` macro of
+/// `drm_fourcc.h`.
+const fn drm_fourcc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+const fn v4l2_fourcc(code: &[u8; 4]) -> PixelFormat {
+    PixelFormat::from_fourcc(code)
+}
+
+/// V4L2 pixel formats and their DRM equivalent. Multi-planar V4L2 variants come after their
+/// single-planar counterpart, so the latter is found first when looking up a DRM fourcc.
+const FORMATS: [(PixelFormat, u32); 34] = [
+    // 2-plane YUV.
+    (v4l2_fourcc(b"NV12"), drm_fourcc(b"NV12")),
+    (v4l2_fourcc(b"NM12"), drm_fourcc(b"NV12")),
+    (v4l2_fourcc(b"NV21"), drm_fourcc(b"NV21")),
+    (v4l2_fourcc(b"NM21"), drm_fourcc(b"NV21")),
+    (v4l2_fourcc(b"NV16"), drm_fourcc(b"NV16")),
+    (v4l2_fourcc(b"NM16"), drm_fourcc(b"NV16")),
+    (v4l2_fourcc(b"NV61"), drm_fourcc(b"NV61")),
+    (v4l2_fourcc(b"NM61"), drm_fourcc(b"NV61")),
+    (v4l2_fourcc(b"NV24"), drm_fourcc(b"NV24")),
+    (v4l2_fourcc(b"NV42"), drm_fourcc(b"NV42")),
+    (v4l2_fourcc(b"P010"), drm_fourcc(b"P010")),
+    (v4l2_fourcc(b"PM10"), drm_fourcc(b"P010")),
+    // 3-plane YUV.
+    (v4l2_fourcc(b"YU12"), drm_fourcc(b"YU12")),
+    (v4l2_fourcc(b"YM12"), drm_fourcc(b"YU12")),
+    (v4l2_fourcc(b"YV12"), drm_fourcc(b"YV12")),
+    (v4l2_fourcc(b"YM21"), drm_fourcc(b"YV12")),
+    (v4l2_fourcc(b"422P"), drm_fourcc(b"YU16")),
+    (v4l2_fourcc(b"YM16"), drm_fourcc(b"YU16")),
+    (v4l2_fourcc(b"YM24"), drm_fourcc(b"YU24")),
+    // Packed YUV.
+    (v4l2_fourcc(b"YUYV"), drm_fourcc(b"YUYV")),
+    (v4l2_fourcc(b"YVYU"), drm_fourcc(b"YVYU")),
+    (v4l2_fourcc(b"UYVY"), drm_fourcc(b"UYVY")),
+    (v4l2_fourcc(b"VYUY"), drm_fourcc(b"VYUY")),
+    // Greyscale.
+    (v4l2_fourcc(b"GREY"), drm_fourcc(b"R8  ")),
+    (v4l2_fourcc(b"Y16 "), drm_fourcc(b"R16 ")),
+    // RGB.
+    (v4l2_fourcc(b"RGBP"), drm_fourcc(b"RG16")),
+    (v4l2_fourcc(b"RGB3"), drm_fourcc(b"BG24")),
+    (v4l2_fourcc(b"BGR3"), drm_fourcc(b"RG24")),
+    (v4l2_fourcc(b"AR24"), drm_fourcc(b"AR24")),
+    (v4l2_fourcc(b"XR24"), drm_fourcc(b"XR24")),
+    (v4l2_fourcc(b"BA24"), drm_fourcc(b"BA24")),
+    (v4l2_fourcc(b"BX24"), drm_fourcc(b"BX24")),
+    (v4l2_fourcc(b"AB24"), drm_fourcc(b"AB24")),
+    (v4l2_fourcc(b"XB24"), drm_fourcc(b"XB24")),
+];
+
+/// Returns the DRM fourcc describing the same layout as `format`, or `None` if there is no such
+/// format.
+///
+/// Multi-planar variants map to the same DRM fourcc as their single-planar counterpart, e.g. both
+/// `NV12` and `NM12` map to DRM's `NV12`.
+pub fn to_drm_fourcc(format: PixelFormat) -> Option<u32> {
+    FORMATS
+        .iter()
+        .find(|(v4l2, _)| *v4l2 == format)
+        .map(|&(_, drm)| drm)
+}
+
+/// Returns the V4L2 pixel format describing the same layout as the DRM fourcc `fourcc`, or `None`
+/// if there is no such format.
+///
+/// The single-planar variant is returned for formats which also have a multi-planar one.
+pub fn from_drm_fourcc(fourcc: u32) -> Option<PixelFormat> {
+    FORMATS
+        .iter()
+        .find(|(_, drm)| *drm == fourcc)
+        .map(|&(v4l2, _)| v4l2)
+}
+
+#[cfg(feature = "drm")]
+impl PixelFormat {
+    /// Returns the DRM fourcc describing the same layout as this format, or `None` if there is no
+    /// such format. See [`to_drm_fourcc`].
+    pub fn to_drm_fourcc(self) -> Option<u32> {
+        to_drm_fourcc(self)
+    }
+
+    /// Returns the V4L2 pixel format describing the same layout as the DRM fourcc `fourcc`, or
+    /// `None` if there is no such format. See [`from_drm_fourcc`].
+    pub fn from_drm_fourcc(fourcc: u32) -> Option<PixelFormat> {
+        from_drm_fourcc(fourcc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_drm_fourcc, to_drm_fourcc, FORMATS};
+    use crate::PixelFormat;
+
+    #[test]
+    fn test_to_drm_fourcc() {
+        // Values of the DRM_FORMAT_* macros.
+        assert_eq!(to_drm_fourcc(PixelFormat::NV12), Some(0x3231564e));
+        assert_eq!(to_drm_fourcc(PixelFormat::NV12M), Some(0x3231564e));
+        assert_eq!(to_drm_fourcc(PixelFormat::YUV420), Some(0x32315559));
+        assert_eq!(to_drm_fourcc(PixelFormat::YUV420M), Some(0x32315559));
+        assert_eq!(to_drm_fourcc(PixelFormat::YVU420), Some(0x32315659));
+        assert_eq!(to_drm_fourcc(PixelFormat::P010), Some(0x30313050));
+        assert_eq!(to_drm_fourcc(PixelFormat::YUYV), Some(0x56595559));
+        assert_eq!(to_drm_fourcc(PixelFormat::GREY), Some(0x20203852));
+        assert_eq!(to_drm_fourcc(PixelFormat::from(b"422P")), Some(0x36315559));
+        // RGB formats are named differently.
+        assert_eq!(to_drm_fourcc(PixelFormat::RGB24), Some(0x34324742));
+        assert_eq!(to_drm_fourcc(PixelFormat::BGR24), Some(0x34324752));
+        assert_eq!(to_drm_fourcc(PixelFormat::ABGR32), Some(0x34325241));
+        assert_eq!(to_drm_fourcc(PixelFormat::XBGR32), Some(0x34325258));
+        assert_eq!(to_drm_fourcc(PixelFormat::from(b"RGBP")), Some(0x36314752));
+
+        assert_eq!(to_drm_fourcc(PixelFormat::H264), None);
+        assert_eq!(to_drm_fourcc(PixelFormat::MJPEG), None);
+        // The alpha semantic of the deprecated 32-bit RGB formats is ambiguous.
+        assert_eq!(to_drm_fourcc(PixelFormat::from(b"RGB4")), None);
+        assert_eq!(to_drm_fourcc(PixelFormat::from(b"BGR4")), None);
+    }
+
+    #[test]
+    fn test_from_drm_fourcc() {
+        // Single-planar variants are preferred.
+        assert_eq!(from_drm_fourcc(0x3231564e), Some(PixelFormat::NV12));
+        assert_eq!(from_drm_fourcc(0x32315559), Some(PixelFormat::YUV420));
+        assert_eq!(from_drm_fourcc(0x30313050), Some(PixelFormat::P010));
+        assert_eq!(
+            from_drm_fourcc(0x36315559),
+            Some(PixelFormat::from(b"422P"))
+        );
+        assert_eq!(from_drm_fourcc(0x34324742), Some(PixelFormat::RGB24));
+        assert_eq!(from_drm_fourcc(0x34325241), Some(PixelFormat::ABGR32));
+        // DRM_FORMAT_RGB565.
+        assert_eq!(
+            from_drm_fourcc(0x36314752),
+            Some(PixelFormat::from(b"RGBP"))
+        );
+        // DRM_FORMAT_C8 has no equivalent.
+        assert_eq!(from_drm_fourcc(0x20203843), None);
+    }
+
+    #[cfg(feature = "drm")]
+    #[test]
+    fn test_drm_methods() {
+        // DRM_FORMAT_XRGB8888 and DRM_FORMAT_RGB888.
+        assert_eq!(PixelFormat::XBGR32.to_drm_fourcc(), Some(0x34325258));
+        assert_eq!(
+            PixelFormat::from_drm_fourcc(0x34324752),
+            Some(PixelFormat::BGR24)
+        );
+        assert_eq!(PixelFormat::H264.to_drm_fourcc(), None);
+    }
+
+    #[test]
+    fn test_drm_round_trip() {
+        for &(v4l2, drm) in FORMATS.iter() {
+            assert_eq!(to_drm_fourcc(v4l2), Some(drm));
+            assert_eq!(to_drm_fourcc(from_drm_fourcc(drm).unwrap()), Some(drm));
+        }
+    }
+}
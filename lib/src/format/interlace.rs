@@ -0,0 +1,320 @@
+//! Helpers to rebuild frames from the fields of interlaced sources.
+
+use thiserror::Error;
+
+use super::color_plane_layouts;
+use crate::{Field, Format, PixelFormat, PlaneLayout};
+
+/// Returns the format of the frames obtained by weaving two fields of `field_format`, the format
+/// of a queue using [`Field::Alternate`], together.
+///
+/// The frames have twice as many lines as the fields, and keep their strides.
+///
+/// # Examples
+///
+/// ```
+/// # use v4l2r::{format::woven_format, Field, Format, PixelFormat, PlaneLayout};
+/// let field_format = Format {
+///     plane_fmt: PlaneLayout::for_format(PixelFormat::YUYV, 720, 240, 0).unwrap(),
+///     field: Field::Alternate,
+///     ..Format::from((PixelFormat::YUYV, (720, 240)))
+/// };
+/// let frame_format = woven_format(&field_format);
+/// assert_eq!(frame_format.height, 480);
+/// assert_eq!(frame_format.field, Field::Interlaced);
+/// assert_eq!(
+///     frame_format.plane_fmt,
+///     PlaneLayout::for_format(PixelFormat::YUYV, 720, 480, 0).unwrap()
+/// );
+/// ```
+pub fn woven_format(field_format: &Format) -> Format {
+    Format {
+        height: field_format.height * 2,
+        plane_fmt: field_format
+            .plane_fmt
+            .iter()
+            .map(|plane| PlaneLayout {
+                bytesperline: plane.bytesperline,
+                sizeimage: plane.sizeimage * 2,
+            })
+            .collect(),
+        field: Field::Interlaced,
+        ..field_format.clone()
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum WeaveFieldsError {
+    #[error("unsupported format {0} or invalid number of planes")]
+    UnsupportedFormat(PixelFormat),
+    #[error("expected {expected} memory planes, got {actual}")]
+    WrongNumberOfPlanes { expected: usize, actual: usize },
+    #[error("memory plane {0} of a field is too small")]
+    FieldTooSmall(usize),
+    #[error("memory plane {0} of the frame is too small")]
+    FrameTooSmall(usize),
+}
+
+/// Weaves the `top` and `bottom` fields of an interlaced frame into `frame`, the top field
+/// taking the even lines and the bottom field the odd ones.
+///
+/// `field_format` is the format of a queue using [`Field::Alternate`], which buffers contain a
+/// single field each. `frame` must follow the layout returned by [`woven_format`] for it. The
+/// padding bytes at the end of each line of `frame` are left untouched.
+pub fn weave_fields<S: AsRef<[u8]>, F: AsMut<[u8]>>(
+    field_format: &Format,
+    top: &[S],
+    bottom: &[S],
+    frame: &mut [F],
+) -> Result<(), WeaveFieldsError> {
+    let unsupported = || WeaveFieldsError::UnsupportedFormat(field_format.pixelformat);
+    let field_height = field_format.height;
+    let field_layouts = color_plane_layouts(
+        field_format.pixelformat,
+        field_height,
+        &field_format.plane_fmt,
+    )
+    .ok_or_else(unsupported)?;
+    let frame_layouts = color_plane_layouts(
+        field_format.pixelformat,
+        field_height * 2,
+        &field_format.plane_fmt,
+    )
+    .ok_or_else(unsupported)?;
+
+    let expected = field_format.plane_fmt.len();
+    for actual in [top.len(), bottom.len(), frame.len()] {
+        if actual != expected {
+            return Err(WeaveFieldsError::WrongNumberOfPlanes { expected, actual });
+        }
+    }
+
+    let width = field_format.width as usize;
+    for (field_layout, frame_layout) in field_layouts.iter().zip(frame_layouts.iter()) {
+        let line_bytes = frame_layout.line_bytes(width);
+        let frame_plane = frame[frame_layout.memory_plane].as_mut();
+
+        for line in 0..frame_layout.lines(field_height as usize * 2) {
+            let field = if line % 2 == 0 { top } else { bottom };
+            let src_start = field_layout.offset + (line / 2) * field_layout.stride;
+            let src = field[field_layout.memory_plane]
+                .as_ref()
+                .get(src_start..src_start + line_bytes)
+                .ok_or(WeaveFieldsError::FieldTooSmall(field_layout.memory_plane))?;
+            let dst_start = frame_layout.offset + line * frame_layout.stride;
+            frame_plane
+                .get_mut(dst_start..dst_start + line_bytes)
+                .ok_or(WeaveFieldsError::FrameTooSmall(frame_layout.memory_plane))?
+                .copy_from_slice(src);
+        }
+    }
+
+    Ok(())
+}
+
+/// Both fields of an interlaced frame.
+#[derive(Debug)]
+pub struct FieldPair<B> {
+    pub top: B,
+    pub bottom: B,
+}
+
+/// Pairs the buffers of a queue using [`Field::Alternate`] into frames.
+///
+/// Both fields of a frame share the same sequence number. The first field of a frame is kept
+/// until the second one is received, in any order. If a field is lost, the buffer of the other
+/// field of its frame is dropped.
+#[derive(Debug)]
+pub struct FieldPairer<B> {
+    pending: Option<(Field, u32, B)>,
+}
+
+impl<B> Default for FieldPairer<B> {
+    fn default() -> Self {
+        Self { pending: None }
+    }
+}
+
+impl<B> FieldPairer<B> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds `buffer`, which contains `field` of the frame with sequence number `sequence`.
+    ///
+    /// Returns the two fields of the frame once both have been added, or `Err(buffer)` if `field`
+    /// is neither [`Field::Top`] nor [`Field::Bottom`].
+    pub fn push(
+        &mut self,
+        field: Field,
+        sequence: u32,
+        buffer: B,
+    ) -> Result<Option<FieldPair<B>>, B> {
+        if !matches!(field, Field::Top | Field::Bottom) {
+            return Err(buffer);
+        }
+
+        match self.pending.take() {
+            Some((pending_field, pending_sequence, pending))
+                if pending_sequence == sequence && pending_field != field =>
+            {
+                let (top, bottom) = if field == Field::Bottom {
+                    (pending, buffer)
+                } else {
+                    (buffer, pending)
+                };
+                Ok(Some(FieldPair { top, bottom }))
+            }
+            _ => {
+                self.pending = Some((field, sequence, buffer));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Drops the field waiting for its counterpart, if any, e.g. before restarting the stream.
+    pub fn reset(&mut self) {
+        self.pending = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{fill_pattern, frame_checksum, TestPattern};
+
+    /// Returns a `field_height` lines high `GREY` field format with a stride of `stride`.
+    fn grey_fields(width: u32, field_height: u32, stride: u32) -> Format {
+        Format {
+            plane_fmt: vec![PlaneLayout {
+                bytesperline: stride,
+                sizeimage: stride * field_height,
+            }],
+            field: Field::Alternate,
+            ..Format::from((PixelFormat::GREY, (width as usize, field_height as usize)))
+        }
+    }
+
+    #[test]
+    fn test_weave_fields() {
+        let format = grey_fields(3, 2, 4);
+        let top = [[1u8, 1, 1, 0xff, 3, 3, 3, 0xff]];
+        let bottom = [[2u8, 2, 2, 0xff, 4, 4, 4, 0xff]];
+        let mut frame = [vec![
+            0u8;
+            woven_format(&format).plane_fmt[0].sizeimage as usize
+        ]];
+
+        weave_fields(&format, &top, &bottom, &mut frame).unwrap();
+        // Padding is not copied.
+        assert_eq!(frame[0], [1, 1, 1, 0, 2, 2, 2, 0, 3, 3, 3, 0, 4, 4, 4, 0]);
+    }
+
+    #[test]
+    fn test_weave_fields_nv12() {
+        // Each field has two luma lines and one chroma line.
+        let format = Format {
+            plane_fmt: PlaneLayout::for_format(PixelFormat::NV12, 2, 2, 0).unwrap(),
+            field: Field::Alternate,
+            ..Format::from((PixelFormat::NV12, (2, 2)))
+        };
+        let top = [[1u8, 1, 3, 3, 5, 5]];
+        let bottom = [[2u8, 2, 4, 4, 6, 6]];
+        let mut frame = [[0u8; 12]];
+
+        weave_fields(&format, &top, &bottom, &mut frame).unwrap();
+        // The chroma lines of the fields are interleaved as well.
+        assert_eq!(frame[0], [1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6]);
+    }
+
+    #[test]
+    fn test_weave_fields_pattern() {
+        // NV12 frame with padded lines, split into its fields and woven back together.
+        let field_format = Format {
+            plane_fmt: PlaneLayout::for_format(PixelFormat::NV12, 30, 10, 32).unwrap(),
+            field: Field::Alternate,
+            ..Format::from((PixelFormat::NV12, (30, 10)))
+        };
+        let frame_format = woven_format(&field_format);
+        let field_size = field_format.plane_fmt[0].sizeimage as usize;
+        let mut frame = [vec![0u8; field_size * 2]];
+        fill_pattern(&frame_format, TestPattern::Gradient, 0, &mut frame).unwrap();
+
+        let frame_layouts =
+            color_plane_layouts(PixelFormat::NV12, 20, &frame_format.plane_fmt).unwrap();
+        let field_layouts =
+            color_plane_layouts(PixelFormat::NV12, 10, &field_format.plane_fmt).unwrap();
+        let mut fields = [[vec![0u8; field_size]], [vec![0u8; field_size]]];
+        for (frame_layout, field_layout) in frame_layouts.iter().zip(field_layouts.iter()) {
+            for line in 0..frame_layout.lines(20) {
+                let src = frame_layout.offset + line * frame_layout.stride;
+                let dst = field_layout.offset + (line / 2) * field_layout.stride;
+                fields[line % 2][0][dst..dst + field_layout.stride]
+                    .copy_from_slice(&frame[0][src..src + frame_layout.stride]);
+            }
+        }
+
+        let mut woven = [vec![0xffu8; field_size * 2]];
+        weave_fields(&field_format, &fields[0], &fields[1], &mut woven).unwrap();
+        assert_eq!(
+            frame_checksum(&frame_format, &woven),
+            frame_checksum(&frame_format, &frame)
+        );
+    }
+
+    #[test]
+    fn test_weave_fields_errors() {
+        let format = grey_fields(4, 2, 4);
+        let field = [vec![0u8; 8]];
+        let mut frame = [vec![0u8; 16]];
+
+        assert_eq!(
+            weave_fields(&format, &field, &[vec![0u8; 7]], &mut frame),
+            Err(WeaveFieldsError::FieldTooSmall(0))
+        );
+        assert_eq!(
+            weave_fields(&format, &field, &field, &mut [vec![0u8; 15]]),
+            Err(WeaveFieldsError::FrameTooSmall(0))
+        );
+        assert_eq!(
+            weave_fields(&format, &field, &[], &mut frame),
+            Err(WeaveFieldsError::WrongNumberOfPlanes {
+                expected: 1,
+                actual: 0
+            })
+        );
+
+        let compressed = Format {
+            pixelformat: PixelFormat::MJPEG,
+            ..format
+        };
+        assert_eq!(
+            weave_fields(&compressed, &field, &field, &mut frame),
+            Err(WeaveFieldsError::UnsupportedFormat(PixelFormat::MJPEG))
+        );
+    }
+
+    #[test]
+    fn test_field_pairer() {
+        let mut pairer = FieldPairer::new();
+
+        // Bottom field first.
+        assert!(matches!(pairer.push(Field::Bottom, 0, "b0"), Ok(None)));
+        let pair = pairer.push(Field::Top, 0, "t0").unwrap().unwrap();
+        assert_eq!((pair.top, pair.bottom), ("t0", "b0"));
+
+        // The bottom field of frame 1 has been lost.
+        assert!(matches!(pairer.push(Field::Top, 1, "t1"), Ok(None)));
+        assert!(matches!(pairer.push(Field::Top, 2, "t2"), Ok(None)));
+        let pair = pairer.push(Field::Bottom, 2, "b2").unwrap().unwrap();
+        assert_eq!((pair.top, pair.bottom), ("t2", "b2"));
+
+        // Fields of different frames are not paired.
+        assert!(matches!(pairer.push(Field::Top, 3, "t3"), Ok(None)));
+        assert!(matches!(pairer.push(Field::Bottom, 4, "b4"), Ok(None)));
+
+        assert!(matches!(pairer.push(Field::Interlaced, 5, "f5"), Err("f5")));
+        pairer.reset();
+        assert!(matches!(pairer.push(Field::Top, 4, "t4"), Ok(None)));
+    }
+}
@@ -0,0 +1,304 @@
+//! Computation of the memory layout of raw formats.
+
+use crate::{PixelFormat, PlaneLayout};
+
+/// Description of a color plane of a raw format.
+struct ColorPlane {
+    /// Bytes used by each pixel of the plane, after subsampling.
+    bytes_per_pixel: u32,
+    /// Horizontal subsampling factor of the plane.
+    hsub: u32,
+    /// Vertical subsampling factor of the plane.
+    vsub: u32,
+}
+
+const fn plane(bytes_per_pixel: u32, hsub: u32, vsub: u32) -> ColorPlane {
+    ColorPlane {
+        bytes_per_pixel,
+        hsub,
+        vsub,
+    }
+}
+
+const PACKED_8: &[ColorPlane] = &[plane(1, 1, 1)];
+const PACKED_16: &[ColorPlane] = &[plane(2, 1, 1)];
+const PACKED_24: &[ColorPlane] = &[plane(3, 1, 1)];
+const PACKED_32: &[ColorPlane] = &[plane(4, 1, 1)];
+const NV_420: &[ColorPlane] = &[plane(1, 1, 1), plane(2, 2, 2)];
+const NV_422: &[ColorPlane] = &[plane(1, 1, 1), plane(2, 2, 1)];
+const NV_444: &[ColorPlane] = &[plane(1, 1, 1), plane(2, 1, 1)];
+const P010_420: &[ColorPlane] = &[plane(2, 1, 1), plane(4, 2, 2)];
+const YUV_420: &[ColorPlane] = &[plane(1, 1, 1), plane(1, 2, 2), plane(1, 2, 2)];
+const YUV_422: &[ColorPlane] = &[plane(1, 1, 1), plane(1, 2, 1), plane(1, 2, 1)];
+const YUV_444: &[ColorPlane] = &[plane(1, 1, 1), plane(1, 1, 1), plane(1, 1, 1)];
+
+/// Returns the color planes of `format`, and whether each of them is stored in its own memory
+/// plane, or `None` if `format` is not a supported raw format.
+fn color_planes(format: PixelFormat) -> Option<(&'static [ColorPlane], bool)> {
+    let planes = match &format.to_fourcc() {
+        b"GREY" => (PACKED_8, false),
+        b"Y16 " | b"RGBP" | b"YUYV" | b"YVYU" | b"UYVY" | b"VYUY" => (PACKED_16, false),
+        b"RGB3" | b"BGR3" => (PACKED_24, false),
+        b"AR24" | b"XR24" | b"BA24" | b"BX24" | b"AB24" | b"XB24" | b"RA24" | b"RX24" => {
+            (PACKED_32, false)
+        }
+        b"NV12" | b"NV21" => (NV_420, false),
+        b"NM12" | b"NM21" => (NV_420, true),
+        b"NV16" | b"NV61" => (NV_422, false),
+        b"NM16" | b"NM61" => (NV_422, true),
+        b"NV24" | b"NV42" => (NV_444, false),
+        b"P010" => (P010_420, false),
+        b"PM10" => (P010_420, true),
+        b"YU12" | b"YV12" => (YUV_420, false),
+        b"YM12" | b"YM21" => (YUV_420, true),
+        b"422P" => (YUV_422, false),
+        b"YM16" | b"YM61" => (YUV_422, true),
+        b"YM24" | b"YM42" => (YUV_444, true),
+        _ => return None,
+    };
+
+    Some(planes)
+}
+
+impl PlaneLayout {
+    /// Returns the layout of the memory planes of a `width`x`height` frame of `format`, i.e. the
+    /// `plane_fmt` a driver would report for it, or `None` if `format` is not a supported raw
+    /// format.
+    ///
+    /// The stride of the first plane is aligned to `alignment` bytes, which must be a power of
+    /// two, or 0 or 1 for no alignment. The stride of the other planes is derived from it. For
+    /// single-planar formats, the only plane contains all the color planes, one after the other,
+    /// and its `bytesperline` is the stride of the first color plane.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use v4l2r::{PixelFormat, PlaneLayout};
+    /// let planes = PlaneLayout::for_format(PixelFormat::NV12M, 1920, 1080, 64).unwrap();
+    /// assert_eq!(
+    ///     planes,
+    ///     vec![
+    ///         PlaneLayout { bytesperline: 1920, sizeimage: 1920 * 1080 },
+    ///         PlaneLayout { bytesperline: 1920, sizeimage: 1920 * 540 },
+    ///     ]
+    /// );
+    /// ```
+    pub fn for_format(
+        format: PixelFormat,
+        width: u32,
+        height: u32,
+        alignment: u32,
+    ) -> Option<Vec<PlaneLayout>> {
+        let (planes, separate) = color_planes(format)?;
+
+        let first = &planes[0];
+        let stride = (width * first.bytes_per_pixel).next_multiple_of(alignment.max(1));
+        let layouts = planes.iter().map(|plane| {
+            let bytesperline =
+                (stride * plane.bytes_per_pixel).div_ceil(first.bytes_per_pixel * plane.hsub);
+            PlaneLayout {
+                bytesperline,
+                sizeimage: bytesperline * height.div_ceil(plane.vsub),
+            }
+        });
+
+        if separate {
+            Some(layouts.collect())
+        } else {
+            Some(vec![PlaneLayout {
+                bytesperline: stride,
+                sizeimage: layouts.map(|layout| layout.sizeimage).sum(),
+            }])
+        }
+    }
+}
+
+/// Returns the horizontal and vertical subsampling factors of each color plane of `format`, or
+/// `None` if `format` is not a raw format supported by [`PlaneLayout::for_format`].
+///
+/// # Examples
+///
+/// ```
+/// # use v4l2r::{format::plane_subsampling, PixelFormat};
+/// assert_eq!(plane_subsampling(PixelFormat::YUV420), Some(vec![(1, 1), (2, 2), (2, 2)]));
+/// ```
+pub fn plane_subsampling(format: PixelFormat) -> Option<Vec<(u32, u32)>> {
+    let (planes, _) = color_planes(format)?;
+
+    Some(
+        planes
+            .iter()
+            .map(|plane| (plane.hsub, plane.vsub))
+            .collect(),
+    )
+}
+
+/// Location of a color plane of a frame within its memory planes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorPlaneLayout {
+    /// Index of the memory plane containing the color plane.
+    pub memory_plane: usize,
+    /// Offset of the color plane from the start of its memory plane.
+    pub offset: usize,
+    /// Number of bytes between two lines of the color plane.
+    pub stride: usize,
+    /// Bytes used by each pixel of the color plane, after subsampling.
+    pub bytes_per_pixel: usize,
+    /// Horizontal subsampling factor of the color plane.
+    pub hsub: usize,
+    /// Vertical subsampling factor of the color plane.
+    pub vsub: usize,
+}
+
+impl ColorPlaneLayout {
+    /// Returns the number of bytes used by `width` pixels of a line of the color plane.
+    pub fn line_bytes(&self, width: usize) -> usize {
+        width.div_ceil(self.hsub) * self.bytes_per_pixel
+    }
+
+    /// Returns the number of lines of the color plane for a frame `height` pixels high.
+    pub fn lines(&self, height: usize) -> usize {
+        height.div_ceil(self.vsub)
+    }
+}
+
+/// Returns where each color plane of a frame of `format` lies, given the `plane_fmt` reported by
+/// the driver for a frame `height` pixels high, or `None` if `format` is not a raw format
+/// supported by [`PlaneLayout::for_format`] or `plane_fmt` has the wrong number of planes.
+///
+/// This allows stride-aware access to the pixels of single-planar formats, for which the driver
+/// only reports the stride of the first color plane.
+///
+/// # Examples
+///
+/// ```
+/// # use v4l2r::{format::color_plane_layouts, PixelFormat, PlaneLayout};
+/// let plane_fmt = PlaneLayout::for_format(PixelFormat::NV12, 1000, 100, 64).unwrap();
+/// let planes = color_plane_layouts(PixelFormat::NV12, 100, &plane_fmt).unwrap();
+/// assert_eq!(planes[1].offset, 1024 * 100);
+/// assert_eq!(planes[1].stride, 1024);
+/// assert_eq!(planes[1].line_bytes(1000), 1000);
+/// ```
+pub fn color_plane_layouts(
+    format: PixelFormat,
+    height: u32,
+    plane_fmt: &[PlaneLayout],
+) -> Option<Vec<ColorPlaneLayout>> {
+    let (planes, separate) = color_planes(format)?;
+    if plane_fmt.len() != if separate { planes.len() } else { 1 } {
+        return None;
+    }
+
+    let first = &planes[0];
+    let mut offset = 0;
+    let layouts = planes
+        .iter()
+        .enumerate()
+        .map(|(i, plane)| {
+            let (memory_plane, offset, stride) = if separate {
+                (i, 0, plane_fmt[i].bytesperline)
+            } else {
+                let stride = (plane_fmt[0].bytesperline * plane.bytes_per_pixel)
+                    .div_ceil(first.bytes_per_pixel * plane.hsub);
+                let plane_offset = offset;
+                offset += stride * height.div_ceil(plane.vsub);
+                (0, plane_offset, stride)
+            };
+
+            ColorPlaneLayout {
+                memory_plane,
+                offset: offset as usize,
+                stride: stride as usize,
+                bytes_per_pixel: plane.bytes_per_pixel as usize,
+                hsub: plane.hsub as usize,
+                vsub: plane.vsub as usize,
+            }
+        })
+        .collect();
+
+    Some(layouts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{color_plane_layouts, plane_subsampling};
+    use crate::{PixelFormat, PlaneLayout};
+
+    fn layout(format: PixelFormat, width: u32, height: u32, alignment: u32) -> Vec<(u32, u32)> {
+        PlaneLayout::for_format(format, width, height, alignment)
+            .unwrap()
+            .into_iter()
+            .map(|plane| (plane.bytesperline, plane.sizeimage))
+            .collect()
+    }
+
+    #[test]
+    fn test_layouts() {
+        // 10 bits per component stored on 16 bits.
+        assert_eq!(layout(PixelFormat::P010, 1920, 1080, 0), [(3840, 6220800)]);
+        // Odd dimensions round the chroma planes up.
+        assert_eq!(
+            layout(PixelFormat::YUV420M, 15, 9, 1),
+            [(15, 135), (8, 40), (8, 40)]
+        );
+        assert_eq!(
+            PlaneLayout::for_format(PixelFormat::H264, 640, 480, 0),
+            None
+        );
+        assert_eq!(
+            plane_subsampling(PixelFormat::NV16),
+            Some(vec![(1, 1), (2, 1)])
+        );
+        assert_eq!(plane_subsampling(PixelFormat::MJPEG), None);
+    }
+
+    #[test]
+    fn test_color_plane_layouts() {
+        let planes = |format: PixelFormat, width: u32, height: u32, alignment: u32| {
+            let plane_fmt = PlaneLayout::for_format(format, width, height, alignment).unwrap();
+            color_plane_layouts(format, height, &plane_fmt)
+                .unwrap()
+                .into_iter()
+                .map(|plane| (plane.memory_plane, plane.offset, plane.stride))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            planes(PixelFormat::YUV420, 640, 480, 0),
+            [(0, 0, 640), (0, 307200, 320), (0, 384000, 320)]
+        );
+        assert_eq!(
+            planes(PixelFormat::NV12M, 1000, 100, 64),
+            [(0, 0, 1024), (1, 0, 1024)]
+        );
+        assert_eq!(
+            planes(PixelFormat::NV16, 15, 9, 16),
+            [(0, 0, 16), (0, 144, 16)]
+        );
+        assert_eq!(planes(PixelFormat::YUYV, 100, 10, 0), [(0, 0, 200)]);
+
+        let yuyv = color_plane_layouts(PixelFormat::YUYV, 10, &[Default::default()]).unwrap();
+        assert_eq!(yuyv[0].line_bytes(100), 200);
+        assert_eq!(yuyv[0].lines(10), 10);
+        // The number of memory planes must match the format.
+        assert_eq!(
+            color_plane_layouts(PixelFormat::NV12M, 10, &[Default::default()]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_aligned_layouts() {
+        assert_eq!(
+            layout(PixelFormat::NV12M, 1000, 100, 64),
+            [(1024, 102400), (1024, 51200)]
+        );
+        assert_eq!(
+            layout(PixelFormat::YUV420M, 1000, 100, 64),
+            [(1024, 102400), (512, 25600), (512, 25600)]
+        );
+        assert_eq!(layout(PixelFormat::YUYV, 100, 10, 16), [(208, 2080)]);
+        // The chroma planes of single-planar formats follow the aligned luma plane.
+        assert_eq!(layout(PixelFormat::NV12, 1000, 100, 64), [(1024, 153600)]);
+    }
+}
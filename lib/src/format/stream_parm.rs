@@ -0,0 +1,216 @@
+//! Streaming parameters of a queue, i.e. its frame rate and capture or output mode.
+
+use std::fmt;
+
+use bitflags::bitflags;
+
+use crate::{bindings, bindings::v4l2_streamparm, Fraction, QueueDirection, QueueType};
+
+bitflags! {
+    /// Equivalent of the `V4L2_MODE_*` flags of `v4l2_captureparm::capturemode`.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct CaptureMode: u32 {
+        /// High quality imaging mode, typically for still images.
+        const HIGH_QUALITY = bindings::V4L2_MODE_HIGHQUALITY;
+    }
+}
+
+bitflags! {
+    /// Equivalent of the `V4L2_MODE_*` flags of `v4l2_outputparm::outputmode`.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct OutputMode: u32 {
+        /// High quality imaging mode, typically for still images.
+        const HIGH_QUALITY = bindings::V4L2_MODE_HIGHQUALITY;
+    }
+}
+
+/// Streaming parameters of a queue, as exchanged with the `VIDIOC_G_PARM` and `VIDIOC_S_PARM`
+/// ioctls. See [`Device::get_stream_params`](crate::device::Device::get_stream_params).
+///
+/// The capture or output member of `v4l2_streamparm` is used depending on the direction of the
+/// queue.
+///
+/// # Examples
+///
+/// ```
+/// # use v4l2r::{format::StreamParm, Fraction, QueueType};
+/// let mut params = StreamParm::new(QueueType::VideoOutputMplane);
+/// params.set_frame_rate(Fraction::new(30000, 1001));
+/// assert_eq!(params.time_per_frame(), Fraction::INTERVAL_29_97);
+/// assert_eq!(params.frame_rate(), Fraction::new(30000, 1001));
+/// ```
+#[derive(Clone, Copy)]
+pub struct StreamParm(v4l2_streamparm);
+
+impl StreamParm {
+    /// Returns empty parameters for `queue`, e.g. to set its frame rate.
+    pub fn new(queue: QueueType) -> Self {
+        StreamParm(v4l2_streamparm {
+            type_: queue as u32,
+            ..Default::default()
+        })
+    }
+
+    /// Returns the type of the queue these parameters apply to, or `None` if it is unknown.
+    pub fn queue(&self) -> Option<QueueType> {
+        QueueType::n(self.0.type_)
+    }
+
+    fn is_output(&self) -> bool {
+        matches!(
+            self.queue().map(|queue| queue.direction()),
+            Some(QueueDirection::Output)
+        )
+    }
+
+    /// Returns whether the driver supports setting the frame rate, i.e. whether the
+    /// `V4L2_CAP_TIMEPERFRAME` capability is set.
+    pub fn supports_frame_rate(&self) -> bool {
+        // SAFETY: the member of the union that gets used by the driver is determined by the
+        // direction of the queue.
+        let capability = if self.is_output() {
+            unsafe { self.0.parm.output.capability }
+        } else {
+            unsafe { self.0.parm.capture.capability }
+        };
+
+        capability & bindings::V4L2_CAP_TIMEPERFRAME != 0
+    }
+
+    /// Returns the time per frame (i.e. frame interval) of the queue.
+    pub fn time_per_frame(&self) -> Fraction {
+        // SAFETY: the member of the union that gets used by the driver is determined by the
+        // direction of the queue.
+        let interval = if self.is_output() {
+            unsafe { self.0.parm.output.timeperframe }
+        } else {
+            unsafe { self.0.parm.capture.timeperframe }
+        };
+
+        interval.into()
+    }
+
+    /// Returns the frame rate of the queue in frames per second, i.e. the inverse of
+    /// [`StreamParm::time_per_frame`].
+    pub fn frame_rate(&self) -> Fraction {
+        self.time_per_frame().recip()
+    }
+
+    /// Sets the frame rate of the queue to `fps` frames per second. A zero frame rate asks the
+    /// driver to pick its nominal frame interval.
+    pub fn set_frame_rate(&mut self, fps: Fraction) {
+        let interval = fps.recip().into();
+        if self.is_output() {
+            self.0.parm.output.timeperframe = interval;
+        } else {
+            self.0.parm.capture.timeperframe = interval;
+        }
+    }
+
+    /// Returns the capture mode of the queue, which is empty for output queues.
+    pub fn capture_mode(&self) -> CaptureMode {
+        if self.is_output() {
+            return CaptureMode::empty();
+        }
+
+        // SAFETY: this is a capture queue.
+        CaptureMode::from_bits_retain(unsafe { self.0.parm.capture.capturemode })
+    }
+
+    /// Returns the output mode of the queue, which is empty for capture queues.
+    pub fn output_mode(&self) -> OutputMode {
+        if !self.is_output() {
+            return OutputMode::empty();
+        }
+
+        // SAFETY: this is an output queue.
+        OutputMode::from_bits_retain(unsafe { self.0.parm.output.outputmode })
+    }
+
+    /// Returns the number of buffers used by the driver for `read()`, or for `write()` on output
+    /// queues.
+    pub fn read_buffers(&self) -> u32 {
+        // SAFETY: the member of the union that gets used by the driver is determined by the
+        // direction of the queue.
+        if self.is_output() {
+            unsafe { self.0.parm.output.writebuffers }
+        } else {
+            unsafe { self.0.parm.capture.readbuffers }
+        }
+    }
+}
+
+impl From<v4l2_streamparm> for StreamParm {
+    fn from(parm: v4l2_streamparm) -> Self {
+        StreamParm(parm)
+    }
+}
+
+impl From<StreamParm> for v4l2_streamparm {
+    fn from(parm: StreamParm) -> Self {
+        parm.0
+    }
+}
+
+impl fmt::Debug for StreamParm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StreamParm")
+            .field("queue", &self.queue())
+            .field("time_per_frame", &self.time_per_frame())
+            .field("capture_mode", &self.capture_mode())
+            .field("output_mode", &self.output_mode())
+            .field("read_buffers", &self.read_buffers())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_parm_direction() {
+        let mut parm = v4l2_streamparm {
+            type_: QueueType::VideoCapture as u32,
+            ..Default::default()
+        };
+        parm.parm.capture.capability = bindings::V4L2_CAP_TIMEPERFRAME;
+        parm.parm.capture.capturemode = bindings::V4L2_MODE_HIGHQUALITY;
+        parm.parm.capture.timeperframe = Fraction::INTERVAL_25.into();
+        parm.parm.capture.readbuffers = 4;
+
+        let params = StreamParm::from(parm);
+        assert!(params.supports_frame_rate());
+        assert_eq!(params.frame_rate(), Fraction::new(25, 1));
+        assert_eq!(params.capture_mode(), CaptureMode::HIGH_QUALITY);
+        assert_eq!(params.output_mode(), OutputMode::empty());
+        assert_eq!(params.read_buffers(), 4);
+
+        // The same data is interpreted differently for output queues.
+        parm.type_ = QueueType::VideoOutput as u32;
+        parm.parm.output.outputmode = 0;
+        parm.parm.output.writebuffers = 2;
+        let params = StreamParm::from(parm);
+        assert_eq!(params.capture_mode(), CaptureMode::empty());
+        assert_eq!(params.output_mode(), OutputMode::empty());
+        assert_eq!(params.read_buffers(), 2);
+    }
+
+    #[test]
+    fn test_stream_parm_set_frame_rate() {
+        for queue in [QueueType::VideoCaptureMplane, QueueType::VideoOutputMplane] {
+            let mut params = StreamParm::new(queue);
+            assert!(!params.supports_frame_rate());
+            params.set_frame_rate(Fraction::new(60, 1));
+            assert_eq!(params.time_per_frame(), Fraction::INTERVAL_60);
+
+            let parm = v4l2_streamparm::from(params);
+            // SAFETY: the member that has been set depends on the direction of the queue.
+            let interval = match queue.direction() {
+                QueueDirection::Capture => unsafe { parm.parm.capture.timeperframe },
+                QueueDirection::Output => unsafe { parm.parm.output.timeperframe },
+            };
+            assert_eq!(Fraction::from(interval), Fraction::INTERVAL_60);
+        }
+    }
+}
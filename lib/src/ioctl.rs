@@ -43,6 +43,8 @@
 //! error types can be converted to their original error code using their `Into<Errno>`
 //! implementation.
 
+#[macro_use]
+pub(crate) mod backend;
 mod decoder_cmd;
 mod dqbuf;
 mod encoder_cmd;
@@ -52,12 +54,15 @@ mod frameintervals;
 mod framesizes;
 mod g_audio;
 mod g_dv_timings;
+mod g_edid;
 mod g_ext_ctrls;
 mod g_fmt;
 mod g_input;
 mod g_jpegcomp;
 mod g_parm;
 mod g_selection;
+mod g_topology;
+mod hw_freq_seek;
 mod mmap;
 mod qbuf;
 mod querybuf;
@@ -65,7 +70,12 @@ mod querycap;
 mod queryctrl;
 mod reqbufs;
 mod request;
+mod setup_link;
 mod streamon;
+mod subdev_fmt;
+#[cfg(v4l2r_has_subdev_querycap)]
+mod subdev_querycap;
+mod subdev_selection;
 mod subscribe_event;
 
 pub use decoder_cmd::*;
@@ -77,12 +87,15 @@ pub use frameintervals::*;
 pub use framesizes::*;
 pub use g_audio::*;
 pub use g_dv_timings::*;
+pub use g_edid::*;
 pub use g_ext_ctrls::*;
 pub use g_fmt::*;
 pub use g_input::*;
 pub use g_jpegcomp::*;
 pub use g_parm::*;
 pub use g_selection::*;
+pub use g_topology::*;
+pub use hw_freq_seek::*;
 pub use mmap::*;
 pub use qbuf::*;
 pub use querybuf::*;
@@ -90,29 +103,37 @@ pub use querycap::*;
 pub use queryctrl::*;
 pub use reqbufs::*;
 pub use request::*;
+pub use setup_link::*;
 pub use streamon::*;
+pub use subdev_fmt::*;
+#[cfg(v4l2r_has_subdev_querycap)]
+pub use subdev_querycap::*;
+pub use subdev_selection::*;
 pub use subscribe_event::*;
 
 use std::convert::Infallible;
 use std::convert::TryFrom;
-use std::ffi::CStr;
-use std::ffi::FromBytesWithNulError;
 use std::fmt::Debug;
 use std::ops::Deref;
 use std::ops::DerefMut;
+use std::os::unix::io::RawFd;
 
 use bitflags::bitflags;
-use enumn::N;
 use nix::errno::Errno;
+use nix::sys::time::TimeVal;
 use thiserror::Error;
 
 use crate::bindings;
+use crate::error::AsErrno;
 use crate::memory::DmaBuf;
 use crate::memory::Memory;
 use crate::memory::MemoryType;
 use crate::memory::Mmap;
 use crate::memory::UserPtr;
+use crate::timecode::Timecode;
+use crate::timecode::TimecodeError;
 use crate::Colorspace;
+use crate::Field;
 use crate::PixelFormat;
 use crate::Quantization;
 use crate::QueueDirection;
@@ -120,35 +141,28 @@ use crate::QueueType;
 use crate::XferFunc;
 use crate::YCbCrEncoding;
 
-/// Utility function for sub-modules.
-/// Constructs an owned String instance from a slice containing a nul-terminated
-/// C string, after checking that the passed slice indeed contains a nul
-/// character.
-fn string_from_cstr(c_str: &[u8]) -> Result<String, FromBytesWithNulError> {
-    // Make sure that our string contains a nul character.
-    let slice = match c_str.iter().position(|x| *x == b'\0') {
-        // Pass the full slice, `from_bytes_with_nul` will return an error.
+/// Returns the part of `c_str` preceding its first nul character, or all of it if it does not
+/// contain any.
+///
+/// This is how the fixed-size string arrays of V4L2 structures (e.g. the `bus_info` member of
+/// `v4l2_capability`) should be read when they need to be compared byte-for-byte.
+pub fn bytes_until_nul(c_str: &[u8]) -> &[u8] {
+    match c_str.iter().position(|x| *x == b'\0') {
         None => c_str,
-        Some(pos) => &c_str[..pos + 1],
-    };
-
-    Ok(CStr::from_bytes_with_nul(slice)?
-        .to_string_lossy()
-        .into_owned())
-}
-
-/// Extension trait for allowing easy conversion of ioctl errors into their originating error code.
-pub trait IntoErrno {
-    fn into_errno(self) -> i32;
+        Some(pos) => &c_str[..pos],
+    }
 }
 
-impl<T> IntoErrno for T
-where
-    T: Into<Errno>,
-{
-    fn into_errno(self) -> i32 {
-        self.into() as i32
-    }
+/// Constructs an owned String instance from a fixed-size array containing a C string, as found in
+/// many V4L2 structures.
+///
+/// The string ends at the first nul character, or at the end of the array if it is not
+/// nul-terminated. Drivers do not always use UTF-8, so invalid sequences are replaced with
+/// `U+FFFD`. Trailing whitespace is removed.
+pub fn string_from_cstr(c_str: &[u8]) -> String {
+    String::from_utf8_lossy(bytes_until_nul(c_str))
+        .trim_end()
+        .to_string()
 }
 
 /// Error type for a "run ioctl and try to convert to safer type" operation.
@@ -163,15 +177,28 @@ pub enum IoctlConvertError<IE: Debug, CE: Debug> {
     ConversionError(CE),
 }
 
-impl<IE, CE> IntoErrno for IoctlConvertError<IE, CE>
+impl<IE, CE> AsErrno for IoctlConvertError<IE, CE>
 where
-    IE: Debug + Into<Errno>,
+    IE: Debug + AsErrno,
     CE: Debug,
 {
-    fn into_errno(self) -> i32 {
+    fn errno(&self) -> Option<Errno> {
         match self {
-            IoctlConvertError::IoctlError(e) => e.into_errno(),
-            IoctlConvertError::ConversionError(_) => Errno::EINVAL as i32,
+            IoctlConvertError::IoctlError(e) => e.errno(),
+            IoctlConvertError::ConversionError(_) => None,
+        }
+    }
+}
+
+impl<IE, CE> From<IoctlConvertError<IE, CE>> for Errno
+where
+    IE: Debug + Into<Errno>,
+    CE: Debug,
+{
+    fn from(err: IoctlConvertError<IE, CE>) -> Self {
+        match err {
+            IoctlConvertError::IoctlError(e) => e.into(),
+            IoctlConvertError::ConversionError(_) => Errno::EINVAL,
         }
     }
 }
@@ -198,6 +225,54 @@ where
         .and_then(|o| O::try_from(o).map_err(IoctlConvertError::ConversionError))
 }
 
+// The `timestamp` member of `v4l2_buffer` is a userspace `struct timeval`, which on 32-bit targets
+// is 8 or 16 bytes large depending on whether userspace uses a 64-bit `time_t`. The kernel accepts
+// both layouts, telling them apart by the size encoded into the ioctl number, which `nix` derives
+// from the size of the bindings' `v4l2_buffer`. So we must use the layout of the bindings as-is,
+// and make sure it matches what the kernel expects for the current target.
+const _: () = {
+    use std::mem::{offset_of, size_of};
+
+    let timeval_size = size_of::<bindings::timeval>();
+    // (size of `v4l2_buffer`, offset of `timestamp`), as laid out by the kernel.
+    let (buffer_size, timestamp_offset) = if cfg!(target_pointer_width = "64") {
+        (88, 24)
+    } else if timeval_size == 8 {
+        // `v4l2_buffer_time32`.
+        (68, 20)
+    } else if cfg!(target_arch = "x86") {
+        // 64-bit integers are only 4-byte aligned on x86.
+        (76, 20)
+    } else {
+        (80, 24)
+    };
+
+    assert!(timeval_size == 8 || timeval_size == 16);
+    assert!(size_of::<bindings::v4l2_buffer>() == buffer_size);
+    assert!(offset_of!(bindings::v4l2_buffer, timestamp) == timestamp_offset);
+    // `timeval_from_nix` and `timeval_to_nix` are lossless.
+    assert!(size_of::<nix::libc::timeval>() == timeval_size);
+};
+
+/// Converts `timeval` into the `timeval` used by V4L2 structures like `v4l2_buffer`.
+///
+/// Both types are checked at compile time to have the same layout, so the conversion is
+/// lossless.
+pub fn timeval_from_nix(timeval: TimeVal) -> bindings::timeval {
+    bindings::timeval {
+        tv_sec: timeval.tv_sec() as _,
+        tv_usec: timeval.tv_usec() as _,
+    }
+}
+
+/// Converts the `timeval` of a V4L2 structure like `v4l2_buffer` into a `TimeVal`.
+///
+/// Both types are checked at compile time to have the same layout, so the conversion is
+/// lossless.
+pub fn timeval_to_nix(timeval: bindings::timeval) -> TimeVal {
+    TimeVal::new(timeval.tv_sec as _, timeval.tv_usec as _)
+}
+
 /// A fully owned V4L2 buffer obtained from some untrusted place (typically an ioctl), or created
 /// with the purpose of receiving the result of an ioctl.
 ///
@@ -301,24 +376,72 @@ bitflags! {
         const TSTAMP_SRC_EOF = bindings::V4L2_BUF_FLAG_TSTAMP_SRC_EOF;
         const TSTAMP_SRC_SOE = bindings::V4L2_BUF_FLAG_TSTAMP_SRC_SOE;
         const REQUEST_FD = bindings::V4L2_BUF_FLAG_REQUEST_FD;
+        #[cfg(v4l2r_has_fences)]
+        const IN_FENCE = bindings::V4L2_BUF_FLAG_IN_FENCE;
+        #[cfg(v4l2r_has_fences)]
+        const OUT_FENCE = bindings::V4L2_BUF_FLAG_OUT_FENCE;
     }
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, N)]
-#[repr(u32)]
-pub enum BufferField {
-    #[default]
-    Any = bindings::v4l2_field_V4L2_FIELD_ANY,
-    None = bindings::v4l2_field_V4L2_FIELD_NONE,
-    Top = bindings::v4l2_field_V4L2_FIELD_TOP,
-    Interlaced = bindings::v4l2_field_V4L2_FIELD_INTERLACED,
-    SeqTb = bindings::v4l2_field_V4L2_FIELD_SEQ_TB,
-    SeqBt = bindings::v4l2_field_V4L2_FIELD_SEQ_BT,
-    Alternate = bindings::v4l2_field_V4L2_FIELD_ALTERNATE,
-    InterlacedTb = bindings::v4l2_field_V4L2_FIELD_INTERLACED_TB,
-    InterlacedBt = bindings::v4l2_field_V4L2_FIELD_INTERLACED_BT,
+/// Offset of the fence file descriptor in `struct v4l2_buffer`. It shares the storage of
+/// `reserved2` right after `length`, and is accessed through this offset since the name of the
+/// anonymous union holding it depends on the headers.
+#[cfg(v4l2r_has_fences)]
+const FENCE_FD_OFFSET: usize =
+    std::mem::offset_of!(bindings::v4l2_buffer, length) + std::mem::size_of::<u32>();
+
+/// Returns the fence file descriptor of `buffer`.
+#[cfg(v4l2r_has_fences)]
+pub(crate) fn fence_fd(buffer: &bindings::v4l2_buffer) -> RawFd {
+    // SAFETY: `FENCE_FD_OFFSET` is within `v4l2_buffer` and points to a 32-bit integer.
+    unsafe {
+        std::ptr::read_unaligned(
+            (buffer as *const bindings::v4l2_buffer as *const u8).add(FENCE_FD_OFFSET)
+                as *const RawFd,
+        )
+    }
+}
+
+/// Sets the fence file descriptor of `buffer` to `fd`.
+#[cfg(v4l2r_has_fences)]
+pub(crate) fn set_fence_fd(buffer: &mut bindings::v4l2_buffer, fd: RawFd) {
+    // SAFETY: `FENCE_FD_OFFSET` is within `v4l2_buffer` and points to a 32-bit integer.
+    unsafe {
+        std::ptr::write_unaligned(
+            (buffer as *mut bindings::v4l2_buffer as *mut u8).add(FENCE_FD_OFFSET) as *mut RawFd,
+            fd,
+        )
+    }
+}
+
+/// Type of an encoded frame, as reported by the `KEYFRAME`, `PFRAME` and
+/// `BFRAME` buffer flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameType {
+    Key,
+    P,
+    B,
 }
 
+impl BufferFlags {
+    /// Returns the frame type indicated by these flags, or `None` if none of
+    /// the mutually exclusive frame type flags are set.
+    pub fn frame_type(&self) -> Option<FrameType> {
+        if self.contains(BufferFlags::KEYFRAME) {
+            Some(FrameType::Key)
+        } else if self.contains(BufferFlags::PFRAME) {
+            Some(FrameType::P)
+        } else if self.contains(BufferFlags::BFRAME) {
+            Some(FrameType::B)
+        } else {
+            None
+        }
+    }
+}
+
+/// Former name of [`Field`], kept for compatibility.
+pub type BufferField = Field;
+
 #[derive(Debug, Error)]
 pub enum V4l2BufferResizePlanesError {
     #[error("zero planes requested")]
@@ -426,11 +549,13 @@ impl V4l2Buffer {
         self.set_flags(self.flags() - flags);
     }
 
-    pub fn field(&self) -> BufferField {
-        BufferField::n(self.buffer.field).unwrap()
+    /// Returns the field contained in this buffer. For a queue using [`Field::Alternate`], this is
+    /// either [`Field::Top`] or [`Field::Bottom`].
+    pub fn field(&self) -> Field {
+        Field::n(self.buffer.field).unwrap_or_default()
     }
 
-    pub fn set_field(&mut self, field: BufferField) {
+    pub fn set_field(&mut self, field: Field) {
         self.buffer.field = field as u32;
     }
 
@@ -438,10 +563,22 @@ impl V4l2Buffer {
         self.flags().contains(BufferFlags::LAST)
     }
 
+    /// Returns whether the driver flagged the content of this buffer as corrupt, e.g. after a
+    /// transmission or decoding error.
+    ///
+    /// Contrary to [`DqBufIoctlError::HardwareError`], this does not affect the queue: the buffer
+    /// can be queued again as usual once its content is discarded.
     pub fn has_error(&self) -> bool {
         self.flags().contains(BufferFlags::ERROR)
     }
 
+    /// Returns the type of the encoded frame contained in this buffer, if the
+    /// driver reported it. Only meaningful for buffers of an encoder's
+    /// `CAPTURE` queue.
+    pub fn frame_type(&self) -> Option<FrameType> {
+        self.flags().frame_type()
+    }
+
     pub fn timestamp(&self) -> bindings::timeval {
         self.buffer.timestamp
     }
@@ -454,10 +591,54 @@ impl V4l2Buffer {
         self.buffer.sequence
     }
 
+    /// Returns the timecode of this buffer, if it has one.
+    ///
+    /// Returns `None` if the `TIMECODE` flag is not set. Timecodes with invalid fields are
+    /// reported as errors.
+    pub fn timecode(&self) -> Option<Result<Timecode, TimecodeError>> {
+        if self.flags().contains(BufferFlags::TIMECODE) {
+            Some(Timecode::try_from(self.buffer.timecode))
+        } else {
+            None
+        }
+    }
+
+    /// Sets the timecode of this buffer, or clears it if `timecode` is `None`.
+    pub fn set_timecode(&mut self, timecode: Option<Timecode>) {
+        match timecode {
+            Some(timecode) => {
+                self.buffer.timecode = timecode.into();
+                self.add_flags(BufferFlags::TIMECODE);
+            }
+            None => {
+                self.buffer.timecode = Default::default();
+                self.clear_flags(BufferFlags::TIMECODE);
+            }
+        }
+    }
+
     pub fn set_sequence(&mut self, sequence: u32) {
         self.buffer.sequence = sequence;
     }
 
+    /// Returns the out-fence of this buffer, i.e. a sync file signaled once the driver is done
+    /// with it, if one has been requested with [`QBuffer::request_out_fence`] and returned by the
+    /// driver.
+    ///
+    /// The caller takes ownership of the returned file descriptor. Always `None` if fences are not
+    /// supported, see [`crate::has_fence_support`].
+    pub fn out_fence(&self) -> Option<RawFd> {
+        #[cfg(v4l2r_has_fences)]
+        if self.flags().contains(BufferFlags::OUT_FENCE) {
+            let fd = fence_fd(&self.buffer);
+            if fd >= 0 {
+                return Some(fd);
+            }
+        }
+
+        None
+    }
+
     pub fn num_planes(&self) -> usize {
         if self.queue().is_multiplanar() {
             self.buffer.length as usize
@@ -481,8 +662,11 @@ impl V4l2Buffer {
             (_, false) => Err(V4l2BufferResizePlanesError::SinglePlanar),
             (num_planes, true) => {
                 // If we are sizing down, clear the planes we are removing.
-                for plane in &mut self.planes[num_planes..self.buffer.length as usize] {
-                    *plane = Default::default();
+                let num_current = self.buffer.length as usize;
+                if num_planes < num_current {
+                    for plane in &mut self.planes[num_planes..num_current] {
+                        *plane = Default::default();
+                    }
                 }
                 self.buffer.length = num_planes as u32;
                 Ok(())
@@ -590,6 +774,39 @@ impl V4l2Buffer {
             .skip(if multiplanar { 1 } else { 0 })
     }
 
+    /// Returns the size information of each plane of the buffer.
+    ///
+    /// Single-planar buffers have exactly one plane, built from the `bytesused` and `length`
+    /// members of the buffer.
+    pub fn planes(&self) -> impl Iterator<Item = PlaneInfo> + '_ {
+        self.planes_iter().map(|plane| PlaneInfo {
+            bytesused: *plane.bytesused,
+            length: *plane.length,
+            data_offset: plane.data_offset.copied().unwrap_or(0),
+        })
+    }
+
+    /// Returns the size information of plane `plane`, or `None` if the buffer has no such plane.
+    pub fn plane(&self, plane: usize) -> Option<PlaneInfo> {
+        self.planes().nth(plane)
+    }
+
+    /// Returns the number of bytes used by the data of plane `plane`, including its data offset.
+    pub fn plane_bytesused(&self, plane: usize) -> Option<u32> {
+        self.plane(plane).map(|p| p.bytesused)
+    }
+
+    /// Returns the size of the memory backing plane `plane`.
+    pub fn plane_length(&self, plane: usize) -> Option<u32> {
+        self.plane(plane).map(|p| p.length)
+    }
+
+    /// Returns the offset of the data from the start of plane `plane`. Always `0` for
+    /// single-planar buffers.
+    pub fn plane_data_offset(&self, plane: usize) -> Option<u32> {
+        self.plane(plane).map(|p| p.data_offset)
+    }
+
     /// Build a plane iterator including the memory backings for memory type `M`.
     ///
     /// # Safety
@@ -750,6 +967,29 @@ impl<'a> V4l2PlaneMutAccessor<'a> {
     }
 }
 
+/// Size information of a plane of a [`V4l2Buffer`], as returned by [`V4l2Buffer::planes`].
+///
+/// Buffers obtained through [`V4l2Buffer`]'s `TryFrom` implementation, e.g. from `dqbuf`, are
+/// guaranteed to have `bytesused <= length` for all their planes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlaneInfo {
+    /// Number of bytes used in the plane, including `data_offset`.
+    pub bytesused: u32,
+    /// Size of the memory backing the plane.
+    pub length: u32,
+    /// Offset of the data from the start of the plane.
+    pub data_offset: u32,
+}
+
+impl PlaneInfo {
+    /// Returns the range of the plane containing the data, i.e. `data_offset..bytesused`.
+    pub fn data_range(&self) -> std::ops::Range<usize> {
+        let end = self.bytesused as usize;
+
+        (self.data_offset as usize).min(end)..end
+    }
+}
+
 pub struct V4l2PlaneAccessorWithRawBacking<'a, M: Memory> {
     data: V4l2PlaneAccessor<'a>,
     backing: &'a M::RawBacking,
@@ -1044,19 +1284,19 @@ impl TryFrom<bindings::v4l2_format> for V4l2MplaneFormat {
             ));
         }
 
-        let _ = BufferField::n(pix_mp.field)
-            .ok_or(V4l2MplaneFormatFromError::InvalidField(pix_mp.field))?;
-        let _ = Colorspace::n(pix_mp.colorspace).ok_or(
+        let _ =
+            Field::n(pix_mp.field).ok_or(V4l2MplaneFormatFromError::InvalidField(pix_mp.field))?;
+        let _ = Colorspace::known(pix_mp.colorspace).ok_or(
             V4l2MplaneFormatFromError::InvalidColorSpace(pix_mp.colorspace),
         )?;
         let ycbcr_enc = unsafe { pix_mp.__bindgen_anon_1.ycbcr_enc };
-        let _ = YCbCrEncoding::n(ycbcr_enc as u32)
+        let _ = YCbCrEncoding::known(ycbcr_enc as u32)
             .ok_or(V4l2MplaneFormatFromError::InvalidYCbCr(ycbcr_enc));
 
-        let _ = Quantization::n(pix_mp.quantization as u32).ok_or(
+        let _ = Quantization::known(pix_mp.quantization as u32).ok_or(
             V4l2MplaneFormatFromError::InvalidQuantization(pix_mp.quantization),
         )?;
-        let _ = XferFunc::n(pix_mp.xfer_func as u32)
+        let _ = XferFunc::known(pix_mp.xfer_func as u32)
             .ok_or(V4l2MplaneFormatFromError::InvalidXferFunc(pix_mp.xfer_func))?;
 
         Ok(Self(format))
@@ -1067,13 +1307,17 @@ impl TryFrom<bindings::v4l2_format> for V4l2MplaneFormat {
 /// not be validated into its default value.
 impl From<(QueueDirection, bindings::v4l2_pix_format_mplane)> for V4l2MplaneFormat {
     fn from((direction, mut pix_mp): (QueueDirection, bindings::v4l2_pix_format_mplane)) -> Self {
-        pix_mp.field = BufferField::n(pix_mp.field).unwrap_or_default() as u32;
-        pix_mp.colorspace = Colorspace::n(pix_mp.colorspace).unwrap_or_default() as u32;
+        pix_mp.field = Field::n(pix_mp.field).unwrap_or_default() as u32;
+        pix_mp.colorspace = Colorspace::known(pix_mp.colorspace)
+            .unwrap_or_default()
+            .into();
         let ycbcr_enc = unsafe { pix_mp.__bindgen_anon_1.ycbcr_enc };
         pix_mp.__bindgen_anon_1.ycbcr_enc =
-            YCbCrEncoding::n(ycbcr_enc as u32).unwrap_or_default() as u8;
-        pix_mp.quantization = Quantization::n(pix_mp.quantization as u32).unwrap_or_default() as u8;
-        pix_mp.xfer_func = XferFunc::n(pix_mp.xfer_func as u32).unwrap_or_default() as u8;
+            u32::from(YCbCrEncoding::known(ycbcr_enc as u32).unwrap_or_default()) as u8;
+        pix_mp.quantization =
+            u32::from(Quantization::known(pix_mp.quantization as u32).unwrap_or_default()) as u8;
+        pix_mp.xfer_func =
+            u32::from(XferFunc::known(pix_mp.xfer_func as u32).unwrap_or_default()) as u8;
 
         Self(bindings::v4l2_format {
             type_: QueueType::from_dir_and_class(direction, crate::QueueClass::VideoMplane) as u32,
@@ -1098,32 +1342,32 @@ impl V4l2MplaneFormat {
         PixelFormat::from_u32(pix_mp.pixelformat)
     }
 
-    pub fn field(&self) -> BufferField {
+    pub fn field(&self) -> Field {
         let pix_mp: &bindings::v4l2_pix_format_mplane = self.as_ref();
         // Safe because we checked the boundaries at construction time.
-        BufferField::n(pix_mp.field).unwrap()
+        Field::n(pix_mp.field).unwrap()
     }
 
     pub fn colorspace(&self) -> Colorspace {
         let pix_mp: &bindings::v4l2_pix_format_mplane = self.as_ref();
         // Safe because we checked the boundaries at construction time.
-        Colorspace::n(pix_mp.colorspace).unwrap()
+        Colorspace::from(pix_mp.colorspace)
     }
 
     pub fn ycbcr_enc(&self) -> YCbCrEncoding {
         let pix_mp: &bindings::v4l2_pix_format_mplane = self.as_ref();
         // Safe because we checked the boundaries at construction time.
-        YCbCrEncoding::n(unsafe { pix_mp.__bindgen_anon_1.ycbcr_enc as u32 }).unwrap()
+        YCbCrEncoding::from(unsafe { pix_mp.__bindgen_anon_1.ycbcr_enc as u32 })
     }
 
     pub fn quantization(&self) -> Quantization {
         let pix_mp: &bindings::v4l2_pix_format_mplane = self.as_ref();
-        Quantization::n(pix_mp.quantization as u32).unwrap()
+        Quantization::from(pix_mp.quantization as u32)
     }
 
     pub fn xfer_func(&self) -> XferFunc {
         let pix_mp: &bindings::v4l2_pix_format_mplane = self.as_ref();
-        XferFunc::n(pix_mp.xfer_func as u32).unwrap()
+        XferFunc::from(pix_mp.xfer_func as u32)
     }
 
     pub fn planes(&self) -> &[bindings::v4l2_plane_pix_format] {
@@ -1134,37 +1378,48 @@ impl V4l2MplaneFormat {
 
 #[cfg(test)]
 mod tests {
-    use crate::{bindings, QueueType};
+    use crate::{bindings, memory::MemoryType, QueueType};
 
-    use super::UncheckedV4l2Buffer;
+    use super::{BufferFlags, FrameType, UncheckedV4l2Buffer, V4l2Buffer};
 
     #[test]
     fn test_string_from_cstr() {
-        use super::string_from_cstr;
+        use super::{bytes_until_nul, string_from_cstr};
 
         // Nul-terminated slice.
-        assert_eq!(string_from_cstr(b"Hello\0"), Ok(String::from("Hello")));
+        assert_eq!(string_from_cstr(b"Hello\0"), "Hello");
 
         // Slice with nul in the middle and not nul-terminated.
-        assert_eq!(string_from_cstr(b"Hi\0lo"), Ok(String::from("Hi")));
+        assert_eq!(string_from_cstr(b"Hi\0lo"), "Hi");
 
         // Slice with nul in the middle and nul-terminated.
-        assert_eq!(string_from_cstr(b"Hi\0lo\0"), Ok(String::from("Hi")));
+        assert_eq!(string_from_cstr(b"Hi\0lo\0"), "Hi");
 
         // Slice starting with nul.
-        assert_eq!(string_from_cstr(b"\0ello"), Ok(String::from("")));
+        assert_eq!(string_from_cstr(b"\0ello"), "");
 
-        // Slice without nul.
-        match string_from_cstr(b"Hello") {
-            Err(_) => {}
-            Ok(_) => panic!(),
-        };
+        // Slice without nul, i.e. a string filling the whole array.
+        assert_eq!(string_from_cstr(b"Hello"), "Hello");
 
         // Empty slice.
-        match string_from_cstr(b"") {
-            Err(_) => {}
-            Ok(_) => panic!(),
-        };
+        assert_eq!(string_from_cstr(b""), "");
+
+        // Trailing whitespace and padding.
+        assert_eq!(string_from_cstr(b"Card  \n\0\0\0"), "Card");
+        assert_eq!(string_from_cstr(b"  Card\0"), "  Card");
+
+        // Latin-1 and truncated UTF-8 sequences.
+        assert_eq!(string_from_cstr(b"Cam\xe9ra\0"), "Cam\u{fffd}ra");
+        assert_eq!(string_from_cstr(b"Cam\xc3\0\xa9ra"), "Cam\u{fffd}");
+        assert_eq!(string_from_cstr(b"Cam\xc3\xa9ra\0"), "Cam\u{e9}ra");
+
+        // Raw bytes are kept as-is.
+        assert_eq!(
+            bytes_until_nul(b"usb-0000:00:14.0-1 \0\0"),
+            b"usb-0000:00:14.0-1 "
+        );
+        assert_eq!(bytes_until_nul(b"Cam\xe9ra"), b"Cam\xe9ra");
+        assert_eq!(bytes_until_nul(b"\0Cam"), b"");
     }
 
     #[test]
@@ -1188,4 +1443,139 @@ mod tests {
         let v4l2_buf_ref = v4l2_buf.as_mut();
         assert_eq!(unsafe { v4l2_buf_ref.m.planes }, planes_ptr);
     }
+
+    #[test]
+    fn test_planes() {
+        use super::{PlaneInfo, V4l2BufferFromError};
+
+        // Single-planar.
+        let mut buffer = V4l2Buffer::new(QueueType::VideoCapture, 0, MemoryType::Mmap);
+        *buffer.get_first_plane_mut().length = 4096;
+        *buffer.get_first_plane_mut().bytesused = 1000;
+        assert_eq!(buffer.num_planes(), 1);
+        assert_eq!(
+            buffer.planes().collect::<Vec<_>>(),
+            vec![PlaneInfo {
+                bytesused: 1000,
+                length: 4096,
+                data_offset: 0
+            }]
+        );
+        assert_eq!(buffer.plane_bytesused(0), Some(1000));
+        assert_eq!(buffer.plane_length(0), Some(4096));
+        assert_eq!(buffer.plane_data_offset(0), Some(0));
+        assert_eq!(buffer.plane_bytesused(1), None);
+
+        // Multi-planar.
+        let mut buffer = V4l2Buffer::new(QueueType::VideoCaptureMplane, 0, MemoryType::Mmap);
+        buffer.set_num_planes(2).unwrap();
+        for (i, plane) in buffer.planes_iter_mut().enumerate() {
+            *plane.length = 4096 >> i;
+            *plane.bytesused = 1000 >> i;
+            *plane.data_offset.unwrap() = 100 >> i;
+        }
+        assert_eq!(buffer.num_planes(), 2);
+        assert_eq!(buffer.plane_bytesused(1), Some(500));
+        assert_eq!(buffer.plane_length(1), Some(2048));
+        assert_eq!(buffer.plane_data_offset(1), Some(50));
+        assert_eq!(buffer.plane(1).unwrap().data_range(), 50..500);
+        assert_eq!(buffer.plane(2), None);
+
+        // Buffers with planes larger than their backing memory are rejected.
+        let mut v4l2_buf = UncheckedV4l2Buffer::new_for_querybuf(QueueType::VideoCapture, None);
+        v4l2_buf.0.memory = MemoryType::Mmap as u32;
+        v4l2_buf.0.length = 100;
+        v4l2_buf.0.bytesused = 101;
+        assert!(matches!(
+            V4l2Buffer::try_from(v4l2_buf),
+            Err(V4l2BufferFromError::PlaneSizeOverflow(0, 101, 100))
+        ));
+        let mut v4l2_buf =
+            UncheckedV4l2Buffer::new_for_querybuf(QueueType::VideoCaptureMplane, None);
+        v4l2_buf.0.memory = MemoryType::Mmap as u32;
+        v4l2_buf.0.length = 2;
+        let planes = v4l2_buf.1.as_mut().unwrap();
+        planes[0].length = 100;
+        planes[1].length = 100;
+        planes[1].bytesused = 200;
+        assert!(matches!(
+            V4l2Buffer::try_from(v4l2_buf),
+            Err(V4l2BufferFromError::PlaneSizeOverflow(1, 200, 100))
+        ));
+    }
+
+    #[test]
+    fn test_timestamp_round_trip() {
+        use nix::libc::time_t;
+        use nix::sys::time::TimeVal;
+
+        use super::{timeval_from_nix, timeval_to_nix};
+        use crate::ioctl::{QBufPlane, QBuffer};
+        use crate::memory::MmapHandle;
+
+        for (sec, usec) in [
+            (0, 0),
+            (1, 999_999),
+            (time_t::MAX, 999_999),
+            (time_t::MIN, 0),
+            // Not normalized, but must be passed as-is.
+            (-1, 1_500_000),
+        ] {
+            let timestamp = TimeVal::new(sec, usec);
+
+            // Queueing through `QBuffer`...
+            let mut qbuf = QBuffer::<MmapHandle>::new(QueueType::VideoOutput, 0);
+            qbuf.timestamp = timestamp;
+            qbuf.planes.push(QBufPlane::new(0));
+            let mut v4l2_buf = UncheckedV4l2Buffer::from(qbuf);
+            assert_eq!(timeval_to_nix(v4l2_buf.0.timestamp), timestamp);
+
+            // ... and dequeuing, once the driver has copied the timestamp.
+            v4l2_buf.0.memory = MemoryType::Mmap as u32;
+            let buffer = V4l2Buffer::try_from(v4l2_buf).unwrap();
+            assert_eq!(timeval_to_nix(buffer.timestamp()), timestamp);
+
+            // Through the `V4l2Buffer` accessors.
+            let mut buffer = V4l2Buffer::new(QueueType::VideoCaptureMplane, 0, MemoryType::Mmap);
+            buffer.set_timestamp(timeval_from_nix(timestamp));
+            let buffer = V4l2Buffer::try_from(UncheckedV4l2Buffer::from(buffer)).unwrap();
+            assert_eq!(buffer.timestamp(), timeval_from_nix(timestamp));
+            assert_eq!(timeval_to_nix(buffer.timestamp()), timestamp);
+        }
+    }
+
+    #[test]
+    fn test_frame_type() {
+        let mut buffer = V4l2Buffer::new(QueueType::VideoCaptureMplane, 0, MemoryType::Mmap);
+        assert_eq!(buffer.frame_type(), None);
+
+        buffer.set_flags(BufferFlags::KEYFRAME | BufferFlags::DONE);
+        assert_eq!(buffer.frame_type(), Some(FrameType::Key));
+
+        buffer.set_flags(BufferFlags::PFRAME | BufferFlags::LAST);
+        assert_eq!(buffer.frame_type(), Some(FrameType::P));
+
+        buffer.set_flags(BufferFlags::BFRAME);
+        assert_eq!(buffer.frame_type(), Some(FrameType::B));
+
+        buffer.set_flags(BufferFlags::ERROR);
+        assert_eq!(buffer.frame_type(), None);
+    }
+
+    #[test]
+    fn test_timecode() {
+        use crate::timecode::{Timecode, TimecodeType};
+
+        let mut buffer = V4l2Buffer::new(QueueType::VideoOutput, 0, MemoryType::Mmap);
+        assert_eq!(buffer.timecode(), None);
+
+        let timecode = Timecode::new(TimecodeType::Fps30, true, 1, 2, 3, 4).unwrap();
+        buffer.set_timecode(Some(timecode));
+        assert!(buffer.flags().contains(BufferFlags::TIMECODE));
+        assert_eq!(buffer.timecode(), Some(Ok(timecode)));
+
+        buffer.set_timecode(None);
+        assert!(!buffer.flags().contains(BufferFlags::TIMECODE));
+        assert_eq!(buffer.timecode(), None);
+    }
 }
@@ -0,0 +1,391 @@
+//! Indirection over the `ioctl` system call.
+//!
+//! The ioctl functions of this crate are declared using the macros of this module, which mirror
+//! the `nix::ioctl_*` macros but route the call through [`ioctl`]. In regular builds this is a
+//! direct call to the system call, but in unit tests the calls made on the file returned by
+//! `mock::MockIoctls::file` are handled by a scripted mock instead, which allows testing code that
+//! depends on a device without having one. Polling a single file with [`poll`] is handled the
+//! same way.
+use std::ffi::c_void;
+use std::os::unix::io::{BorrowedFd, RawFd};
+
+use nix::errno::Errno;
+use nix::libc::{self, c_int};
+use nix::poll::{PollFd, PollFlags, PollTimeout};
+use nix::sys::ioctl::ioctl_num_type;
+
+/// Same as `nix::ioctl_none!`, but performing the ioctl through [`ioctl`].
+macro_rules! ioctl_none {
+    ($name:ident, $ioty:expr, $nr:expr) => {
+        pub unsafe fn $name(fd: ::nix::libc::c_int) -> ::nix::Result<::nix::libc::c_int> {
+            $crate::ioctl::backend::ioctl(
+                stringify!($name),
+                fd,
+                ::nix::request_code_none!($ioty, $nr) as ::nix::sys::ioctl::ioctl_num_type,
+                ::std::ptr::null_mut(),
+            )
+        }
+    };
+}
+
+/// Same as `nix::ioctl_read!`, but performing the ioctl through [`ioctl`].
+macro_rules! ioctl_read {
+    ($name:ident, $ioty:expr, $nr:expr, $ty:ty) => {
+        pub unsafe fn $name(
+            fd: ::nix::libc::c_int,
+            data: *mut $ty,
+        ) -> ::nix::Result<::nix::libc::c_int> {
+            $crate::ioctl::backend::ioctl(
+                stringify!($name),
+                fd,
+                ::nix::request_code_read!($ioty, $nr, ::std::mem::size_of::<$ty>())
+                    as ::nix::sys::ioctl::ioctl_num_type,
+                data as *mut ::std::ffi::c_void,
+            )
+        }
+    };
+}
+
+/// Same as `nix::ioctl_write_ptr!`, but performing the ioctl through [`ioctl`].
+macro_rules! ioctl_write_ptr {
+    ($name:ident, $ioty:expr, $nr:expr, $ty:ty) => {
+        pub unsafe fn $name(
+            fd: ::nix::libc::c_int,
+            data: *const $ty,
+        ) -> ::nix::Result<::nix::libc::c_int> {
+            $crate::ioctl::backend::ioctl(
+                stringify!($name),
+                fd,
+                ::nix::request_code_write!($ioty, $nr, ::std::mem::size_of::<$ty>())
+                    as ::nix::sys::ioctl::ioctl_num_type,
+                data as *mut ::std::ffi::c_void,
+            )
+        }
+    };
+}
+
+/// Same as `nix::ioctl_readwrite!`, but performing the ioctl through [`ioctl`].
+macro_rules! ioctl_readwrite {
+    ($name:ident, $ioty:expr, $nr:expr, $ty:ty) => {
+        pub unsafe fn $name(
+            fd: ::nix::libc::c_int,
+            data: *mut $ty,
+        ) -> ::nix::Result<::nix::libc::c_int> {
+            $crate::ioctl::backend::ioctl(
+                stringify!($name),
+                fd,
+                ::nix::request_code_readwrite!($ioty, $nr, ::std::mem::size_of::<$ty>())
+                    as ::nix::sys::ioctl::ioctl_num_type,
+                data as *mut ::std::ffi::c_void,
+            )
+        }
+    };
+}
+
+/// Performer of the ioctls of this crate.
+pub(crate) trait IoctlBackend {
+    /// Performs ioctl `request`, declared as `name`, on `fd` with argument `arg`.
+    ///
+    /// # Safety
+    ///
+    /// `arg` must be valid for the argument type expected by `request`.
+    unsafe fn ioctl(
+        &self,
+        name: &'static str,
+        fd: RawFd,
+        request: ioctl_num_type,
+        arg: *mut c_void,
+    ) -> nix::Result<c_int>;
+}
+
+/// Backend performing the actual system call.
+pub(crate) struct SyscallBackend;
+
+impl IoctlBackend for SyscallBackend {
+    #[inline(always)]
+    unsafe fn ioctl(
+        &self,
+        _name: &'static str,
+        fd: RawFd,
+        request: ioctl_num_type,
+        arg: *mut c_void,
+    ) -> nix::Result<c_int> {
+        Errno::result(libc::ioctl(fd, request, arg))
+    }
+}
+
+/// Performs ioctl `request`, declared as `name`, on `fd` with argument `arg`, using the mock
+/// registered for `fd` if any, or the system call otherwise.
+///
+/// # Safety
+///
+/// `arg` must be valid for the argument type expected by `request`.
+#[inline(always)]
+pub(crate) unsafe fn ioctl(
+    name: &'static str,
+    fd: RawFd,
+    request: ioctl_num_type,
+    arg: *mut c_void,
+) -> nix::Result<c_int> {
+    #[cfg(test)]
+    if let Some(mock) = mock::mock_for(fd) {
+        return mock.ioctl(name, fd, request, arg);
+    }
+
+    SyscallBackend.ioctl(name, fd, request, arg)
+}
+
+/// Polls `fd` for `events` for up to `timeout`, using the mock registered for `fd` if any, or the
+/// system call otherwise. Returns the events that occurred, which are empty if `timeout` expired.
+pub(crate) fn poll(
+    fd: BorrowedFd,
+    events: PollFlags,
+    timeout: PollTimeout,
+) -> nix::Result<PollFlags> {
+    #[cfg(test)]
+    if let Some(mock) = mock::mock_for(std::os::unix::io::AsRawFd::as_raw_fd(&fd)) {
+        return mock.poll();
+    }
+
+    let mut fds = [PollFd::new(fd, events)];
+    match nix::poll::poll(&mut fds, timeout)? {
+        0 => Ok(PollFlags::empty()),
+        _ => Ok(fds[0].revents().unwrap_or(PollFlags::empty())),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    //! Scripted mock of the ioctl system call.
+    //!
+    //! A [`MockIoctls`] is backed by a pipe, which unlike regular files can be watched by a
+    //! [`Poller`](crate::device::poller::Poller), and which always has data to read. All the ioctls performed on the read end of that
+    //! pipe (or any duplicate of it, as returned by [`MockIoctls::file`]) are checked against the
+    //! expectations registered on the mock, in order, and return the scripted result instead of
+    //! reaching the kernel.
+    //!
+    //! ```ignore
+    //! let mock = MockIoctls::new();
+    //! mock.expect("vidioc_streamon", Err(Errno::EPIPE));
+    //! let file = mock.file();
+    //! assert!(matches!(
+    //!     streamon(&file, QueueType::VideoCapture),
+    //!     Err(StreamOnError::InvalidPadConfig)
+    //! ));
+    //! mock.assert_done();
+    //! ```
+    use std::collections::VecDeque;
+    use std::ffi::c_void;
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+    use std::sync::{Arc, Mutex, Weak};
+
+    use nix::errno::Errno;
+    use nix::libc::{self, c_int};
+    use nix::poll::PollFlags;
+    use nix::sys::ioctl::ioctl_num_type;
+
+    use super::IoctlBackend;
+    use crate::bindings;
+    use crate::device::Device;
+    use crate::ioctl::Capabilities;
+
+    type Handler = Box<dyn FnOnce(ioctl_num_type, *mut c_void) -> nix::Result<c_int> + Send>;
+
+    struct Expectation {
+        name: &'static str,
+        handler: Handler,
+    }
+
+    /// Identifies a file independently of the descriptor used to access it.
+    type FileId = (u64, u64);
+
+    fn file_id(fd: RawFd) -> Option<FileId> {
+        let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+        // SAFETY: `stat` is a valid buffer for `fstat` to write into.
+        if unsafe { libc::fstat(fd, stat.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        // SAFETY: `fstat` succeeded and thus initialized `stat`.
+        let stat = unsafe { stat.assume_init() };
+
+        Some((stat.st_dev as u64, stat.st_ino as u64))
+    }
+
+    /// Registered mocks. Tests run concurrently, so each mock is looked up using the file the
+    /// ioctl is performed on.
+    static MOCKS: Mutex<Vec<(FileId, Weak<MockIoctls>)>> = Mutex::new(Vec::new());
+
+    /// Returns the mock registered for `fd`, if any.
+    pub(super) fn mock_for(fd: RawFd) -> Option<Arc<MockIoctls>> {
+        let mocks = MOCKS.lock().unwrap();
+        if mocks.is_empty() {
+            return None;
+        }
+        let id = file_id(fd)?;
+
+        mocks
+            .iter()
+            .find(|(mock_id, _)| *mock_id == id)
+            .and_then(|(_, mock)| mock.upgrade())
+    }
+
+    /// Scripted ioctl backend. See the module documentation.
+    pub(crate) struct MockIoctls {
+        file: File,
+        // Write end of the pipe, kept open so the read end does not signal a hang up when polled.
+        _writer: File,
+        id: FileId,
+        expectations: Mutex<VecDeque<Expectation>>,
+    }
+
+    impl MockIoctls {
+        /// Creates a new mock, without any expectation.
+        pub(crate) fn new() -> Arc<Self> {
+            let mut fds = [0; 2];
+            // SAFETY: `fds` is a valid buffer for `pipe2` to write two descriptors into.
+            Errno::result(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) })
+                .expect("failed to create mock file");
+            // SAFETY: both descriptors have just been created and are owned by nobody else.
+            let (file, mut writer) =
+                unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+            // Like a regular file, the mock file is always readable.
+            writer
+                .write_all(&[0])
+                .expect("failed to write to mock file");
+            let id = file_id(file.as_raw_fd()).expect("failed to stat mock file");
+
+            let mock = Arc::new(MockIoctls {
+                file,
+                _writer: writer,
+                id,
+                expectations: Default::default(),
+            });
+            MOCKS.lock().unwrap().push((id, Arc::downgrade(&mock)));
+
+            mock
+        }
+
+        /// Returns a new descriptor to the file whose ioctls are handled by this mock, e.g. to
+        /// build a [`crate::device::Device`].
+        pub(crate) fn file(&self) -> File {
+            self.file
+                .try_clone()
+                .expect("failed to duplicate mock file")
+        }
+
+        fn push(&self, name: &'static str, handler: Handler) -> &Self {
+            self.expectations
+                .lock()
+                .unwrap()
+                .push_back(Expectation { name, handler });
+            self
+        }
+
+        /// Expects ioctl `name` (e.g. `"vidioc_streamon"`) to be called next, and makes it return
+        /// `result` without touching its argument.
+        pub(crate) fn expect(&self, name: &'static str, result: nix::Result<c_int>) -> &Self {
+            self.push(name, Box::new(move |_, _| result))
+        }
+
+        /// Expects ioctl `name` to be called next with an argument of type `T`, and lets
+        /// `handler` inspect and fill the argument and return the result of the call.
+        pub(crate) fn expect_with<T, F>(&self, name: &'static str, handler: F) -> &Self
+        where
+            F: FnOnce(&mut T) -> nix::Result<c_int> + Send + 'static,
+        {
+            self.push(
+                name,
+                Box::new(move |request, arg| {
+                    // The size of the argument is encoded in the 14 bits (`_IOC_SIZEBITS`) starting
+                    // at bit 16 of the request.
+                    let size = (request as u64 >> 16) & 0x3fff;
+                    assert_eq!(
+                        size as usize,
+                        std::mem::size_of::<T>(),
+                        "argument of {} is not a {}",
+                        name,
+                        std::any::type_name::<T>()
+                    );
+                    // SAFETY: the ioctl macros guarantee that `arg` points to the argument type
+                    // of the ioctl, which we just checked has the size of `T`.
+                    handler(unsafe { &mut *(arg as *mut T) })
+                }),
+            )
+        }
+
+        /// Expects the file to be polled next, and makes the poll return `result`, i.e. the events
+        /// that occurred or an error.
+        pub(crate) fn expect_poll(&self, result: nix::Result<PollFlags>) -> &Self {
+            self.push(
+                "poll",
+                Box::new(move |_, _| result.map(|events| events.bits() as c_int)),
+            )
+        }
+
+        /// Handles a poll of the file, see [`MockIoctls::expect_poll`].
+        pub(super) fn poll(&self) -> nix::Result<PollFlags> {
+            self.next_expectation("poll")(0, std::ptr::null_mut())
+                .map(|events| PollFlags::from_bits_truncate(events as _))
+        }
+
+        fn next_expectation(&self, name: &'static str) -> Handler {
+            let expectation = self
+                .expectations
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| panic!("unexpected ioctl {}", name));
+            assert_eq!(
+                expectation.name, name,
+                "expected ioctl {}, got {}",
+                expectation.name, name
+            );
+
+            expectation.handler
+        }
+
+        /// Checks that all the expected ioctls have been performed.
+        pub(crate) fn assert_done(&self) {
+            let expectations = self.expectations.lock().unwrap();
+            assert!(
+                expectations.is_empty(),
+                "expected ioctls not performed: {:?}",
+                expectations.iter().map(|e| e.name).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    /// Returns a single-planar capture device whose ioctls are handled by `mock`.
+    pub(crate) fn mock_device(mock: &MockIoctls) -> Device {
+        mock.expect_with("vidioc_querycap", |cap: &mut bindings::v4l2_capability| {
+            let caps = Capabilities::VIDEO_CAPTURE | Capabilities::STREAMING;
+            cap.capabilities = (caps | Capabilities::DEVICE_CAPS).bits();
+            cap.device_caps = caps.bits();
+            Ok(0)
+        });
+
+        Device::new(mock.file()).unwrap()
+    }
+
+    impl IoctlBackend for MockIoctls {
+        unsafe fn ioctl(
+            &self,
+            name: &'static str,
+            _fd: RawFd,
+            request: ioctl_num_type,
+            arg: *mut c_void,
+        ) -> nix::Result<c_int> {
+            self.next_expectation(name)(request, arg)
+        }
+    }
+
+    impl Drop for MockIoctls {
+        fn drop(&mut self) {
+            if let Ok(mut mocks) = MOCKS.lock() {
+                mocks.retain(|(id, _)| *id != self.id);
+            }
+        }
+    }
+}
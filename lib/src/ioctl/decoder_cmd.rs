@@ -9,6 +9,7 @@ use thiserror::Error;
 
 use crate::bindings;
 use crate::bindings::v4l2_decoder_cmd;
+use crate::error::AsErrno;
 use crate::ioctl::ioctl_and_convert;
 use crate::ioctl::IoctlConvertError;
 use crate::ioctl::IoctlConvertResult;
@@ -198,6 +199,16 @@ impl From<DecoderCmdIoctlError> for Errno {
     }
 }
 
+impl AsErrno for DecoderCmdIoctlError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            DecoderCmdIoctlError::DrainInProgress => Some(Errno::EBUSY),
+            DecoderCmdIoctlError::UnsupportedCommand => Some(Errno::EINVAL),
+            DecoderCmdIoctlError::Other(e) => Some(*e),
+        }
+    }
+}
+
 impl From<Errno> for DecoderCmdIoctlError {
     fn from(error: Errno) -> Self {
         match error {
@@ -211,8 +222,8 @@ impl From<Errno> for DecoderCmdIoctlError {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_decoder_cmd;
-    nix::ioctl_readwrite!(vidioc_decoder_cmd, b'V', 96, v4l2_decoder_cmd);
-    nix::ioctl_readwrite!(vidioc_try_decoder_cmd, b'V', 97, v4l2_decoder_cmd);
+    ioctl_readwrite!(vidioc_decoder_cmd, b'V', 96, v4l2_decoder_cmd);
+    ioctl_readwrite!(vidioc_try_decoder_cmd, b'V', 97, v4l2_decoder_cmd);
 }
 
 pub type DecoderCmdError<CE> = IoctlConvertError<DecoderCmdIoctlError, CE>;
@@ -225,13 +236,17 @@ where
     O: TryFrom<v4l2_decoder_cmd>,
     O::Error: std::fmt::Debug,
 {
-    let mut dec_cmd = command.into();
+    let mut dec_cmd: v4l2_decoder_cmd = command.into();
+    let _span = ioctl_span!(
+        "VIDIOC_DECODER_CMD",
+        cmd = dec_cmd.cmd,
+        flags = dec_cmd.flags
+    );
+
+    let res = unsafe { ioctl::vidioc_decoder_cmd(fd.as_raw_fd(), &mut dec_cmd) };
+    record_errno!(_span, res);
 
-    ioctl_and_convert(
-        unsafe { ioctl::vidioc_decoder_cmd(fd.as_raw_fd(), &mut dec_cmd) }
-            .map(|_| dec_cmd)
-            .map_err(Into::into),
-    )
+    ioctl_and_convert(res.map(|_| dec_cmd).map_err(Into::into))
 }
 
 /// Safe wrapper around the `VIDIOC_TRY_DECODER_CMD` ioctl.
@@ -241,13 +256,17 @@ where
     O: TryFrom<v4l2_decoder_cmd>,
     O::Error: std::fmt::Debug,
 {
-    let mut dec_cmd = command.into();
+    let mut dec_cmd: v4l2_decoder_cmd = command.into();
+    let _span = ioctl_span!(
+        "VIDIOC_TRY_DECODER_CMD",
+        cmd = dec_cmd.cmd,
+        flags = dec_cmd.flags
+    );
+
+    let res = unsafe { ioctl::vidioc_try_decoder_cmd(fd.as_raw_fd(), &mut dec_cmd) };
+    record_errno!(_span, res);
 
-    ioctl_and_convert(
-        unsafe { ioctl::vidioc_try_decoder_cmd(fd.as_raw_fd(), &mut dec_cmd) }
-            .map(|_| dec_cmd)
-            .map_err(Into::into),
-    )
+    ioctl_and_convert(res.map(|_| dec_cmd).map_err(Into::into))
 }
 
 #[cfg(test)]
@@ -1,3 +1,4 @@
+use crate::error::AsErrno;
 use crate::ioctl::ioctl_and_convert;
 use crate::ioctl::IoctlConvertError;
 use crate::ioctl::IoctlConvertResult;
@@ -14,7 +15,7 @@ use thiserror::Error;
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_buffer;
-    nix::ioctl_readwrite!(vidioc_dqbuf, b'V', 17, v4l2_buffer);
+    ioctl_readwrite!(vidioc_dqbuf, b'V', 17, v4l2_buffer);
 }
 
 #[derive(Debug, Error)]
@@ -23,6 +24,14 @@ pub enum DqBufIoctlError {
     Eos,
     #[error("no buffer ready for dequeue")]
     NotReady,
+    /// The driver reported an unrecoverable error (`EIO`) and no buffer has been dequeued. The
+    /// queue refuses to dequeue buffers until it is streamed off, which returns all its buffers,
+    /// and streamed on again.
+    ///
+    /// Errors affecting a single buffer are reported by dequeuing it normally with
+    /// `V4L2_BUF_FLAG_ERROR` set instead, see [`V4l2Buffer::has_error`](super::V4l2Buffer::has_error).
+    #[error("hardware error, the queue must be restarted")]
+    HardwareError,
     #[error("unexpected ioctl error: {0}")]
     Other(Errno),
 }
@@ -32,6 +41,7 @@ impl From<Errno> for DqBufIoctlError {
         match error {
             Errno::EAGAIN => Self::NotReady,
             Errno::EPIPE => Self::Eos,
+            Errno::EIO => Self::HardwareError,
             error => Self::Other(error),
         }
     }
@@ -42,11 +52,23 @@ impl From<DqBufIoctlError> for Errno {
         match err {
             DqBufIoctlError::Eos => Errno::EPIPE,
             DqBufIoctlError::NotReady => Errno::EAGAIN,
+            DqBufIoctlError::HardwareError => Errno::EIO,
             DqBufIoctlError::Other(e) => e,
         }
     }
 }
 
+impl AsErrno for DqBufIoctlError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            DqBufIoctlError::Eos => Some(Errno::EPIPE),
+            DqBufIoctlError::NotReady => Some(Errno::EAGAIN),
+            DqBufIoctlError::HardwareError => Some(Errno::EIO),
+            DqBufIoctlError::Other(e) => Some(*e),
+        }
+    }
+}
+
 pub type DqBufError<CE> = IoctlConvertError<DqBufIoctlError, CE>;
 pub type DqBufResult<O, CE> = IoctlConvertResult<O, DqBufIoctlError, CE>;
 
@@ -57,10 +79,21 @@ where
     O::Error: std::fmt::Debug,
 {
     let mut v4l2_buf = UncheckedV4l2Buffer::new_for_querybuf(queue, None);
+    let _span = ioctl_span!(
+        "VIDIOC_DQBUF",
+        queue = ?queue,
+        index = ::tracing::field::Empty,
+        flags = ::tracing::field::Empty,
+        bytesused = ::tracing::field::Empty
+    );
+
+    let res = unsafe { ioctl::vidioc_dqbuf(fd.as_raw_fd(), v4l2_buf.as_mut()) };
+    record_errno!(_span, res);
+    if res.is_ok() {
+        span_record!(_span, "index", v4l2_buf.0.index);
+        span_record!(_span, "flags", v4l2_buf.0.flags);
+        span_record!(_span, "bytesused", crate::trace::bytes_used(&v4l2_buf));
+    }
 
-    ioctl_and_convert(
-        unsafe { ioctl::vidioc_dqbuf(fd.as_raw_fd(), v4l2_buf.as_mut()) }
-            .map(|_| v4l2_buf)
-            .map_err(Into::into),
-    )
+    ioctl_and_convert(res.map(|_| v4l2_buf).map_err(Into::into))
 }
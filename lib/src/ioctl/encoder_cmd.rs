@@ -1,21 +1,25 @@
+//! Safe wrappers for the `VIDIOC_G_ENC_INDEX` and `VIDIOC_(TRY_)ENCODER_CMD` ioctls.
+
 use std::convert::{Infallible, TryFrom};
 use std::os::unix::io::AsRawFd;
 
+use bitflags::bitflags;
 use nix::errno::Errno;
 use thiserror::Error;
 
 use crate::bindings;
 use crate::bindings::v4l2_enc_idx;
 use crate::bindings::v4l2_encoder_cmd;
+use crate::error::AsErrno;
 
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_enc_idx;
     use crate::bindings::v4l2_encoder_cmd;
 
-    nix::ioctl_read!(vidioc_g_enc_index, b'V', 76, v4l2_enc_idx);
-    nix::ioctl_readwrite!(vidioc_encoder_cmd, b'V', 77, v4l2_encoder_cmd);
-    nix::ioctl_readwrite!(vidioc_try_encoder_cmd, b'V', 78, v4l2_encoder_cmd);
+    ioctl_read!(vidioc_g_enc_index, b'V', 76, v4l2_enc_idx);
+    ioctl_readwrite!(vidioc_encoder_cmd, b'V', 77, v4l2_encoder_cmd);
+    ioctl_readwrite!(vidioc_try_encoder_cmd, b'V', 78, v4l2_encoder_cmd);
 }
 
 #[derive(Debug, Error)]
@@ -32,6 +36,14 @@ impl From<GEncIndexError> for Errno {
     }
 }
 
+impl AsErrno for GEncIndexError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GEncIndexError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_G_ENC_INDEX` ioctl.
 pub fn g_enc_index<O: From<v4l2_enc_idx>>(fd: &impl AsRawFd) -> Result<O, GEncIndexError> {
     let mut enc_idx: v4l2_enc_idx = Default::default();
@@ -42,6 +54,58 @@ pub fn g_enc_index<O: From<v4l2_enc_idx>>(fd: &impl AsRawFd) -> Result<O, GEncIn
     }
 }
 
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct EncoderStopFlags: u32 {
+        const AT_GOP_END = bindings::V4L2_ENC_CMD_STOP_AT_GOP_END;
+    }
+}
+
+/// Safe variant of `struct v4l2_encoder_cmd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderCmd {
+    Start,
+    Stop { flags: EncoderStopFlags },
+    Pause,
+    Resume,
+}
+
+impl EncoderCmd {
+    /// Returns a STOP command that stops the encoder immediately.
+    pub fn stop() -> Self {
+        EncoderCmd::Stop {
+            flags: EncoderStopFlags::empty(),
+        }
+    }
+
+    /// Returns a STOP command that stops the encoder at the end of the current GOP.
+    pub fn stop_at_gop_end() -> Self {
+        EncoderCmd::Stop {
+            flags: EncoderStopFlags::AT_GOP_END,
+        }
+    }
+}
+
+impl From<EncoderCmd> for v4l2_encoder_cmd {
+    fn from(command: EncoderCmd) -> Self {
+        let (cmd, flags) = match command {
+            EncoderCmd::Start => (bindings::V4L2_ENC_CMD_START, 0),
+            EncoderCmd::Stop { flags } => (bindings::V4L2_ENC_CMD_STOP, flags.bits()),
+            EncoderCmd::Pause => (bindings::V4L2_ENC_CMD_PAUSE, 0),
+            EncoderCmd::Resume => (bindings::V4L2_ENC_CMD_RESUME, 0),
+        };
+
+        v4l2_encoder_cmd {
+            cmd,
+            flags,
+            ..Default::default()
+        }
+    }
+}
+
+/// Previous representation of encoder commands, where the `Stop` member indicates whether the
+/// encoder should stop at the end of the current GOP.
+#[deprecated(note = "use `EncoderCmd` instead")]
 #[derive(Debug, Clone, Copy)]
 pub enum EncoderCommand {
     Start,
@@ -50,6 +114,51 @@ pub enum EncoderCommand {
     Resume,
 }
 
+#[allow(deprecated)]
+impl From<&EncoderCommand> for EncoderCmd {
+    fn from(command: &EncoderCommand) -> Self {
+        match command {
+            EncoderCommand::Start => EncoderCmd::Start,
+            EncoderCommand::Stop(true) => EncoderCmd::stop_at_gop_end(),
+            EncoderCommand::Stop(false) => EncoderCmd::stop(),
+            EncoderCommand::Pause => EncoderCmd::Pause,
+            EncoderCommand::Resume => EncoderCmd::Resume,
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl From<&EncoderCommand> for v4l2_encoder_cmd {
+    fn from(command: &EncoderCommand) -> Self {
+        EncoderCmd::from(command).into()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BuildEncoderCmdError {
+    #[error("invalid command code {0}")]
+    InvalidCommandCode(u32),
+    #[error("invalid stop command flags 0x{0:x}")]
+    InvalidStopFlags(u32),
+}
+
+impl TryFrom<v4l2_encoder_cmd> for EncoderCmd {
+    type Error = BuildEncoderCmdError;
+
+    fn try_from(cmd: v4l2_encoder_cmd) -> Result<Self, Self::Error> {
+        Ok(match cmd.cmd {
+            bindings::V4L2_ENC_CMD_START => EncoderCmd::Start,
+            bindings::V4L2_ENC_CMD_STOP => EncoderCmd::Stop {
+                flags: EncoderStopFlags::from_bits(cmd.flags)
+                    .ok_or(BuildEncoderCmdError::InvalidStopFlags(cmd.flags))?,
+            },
+            bindings::V4L2_ENC_CMD_PAUSE => EncoderCmd::Pause,
+            bindings::V4L2_ENC_CMD_RESUME => EncoderCmd::Resume,
+            code => return Err(BuildEncoderCmdError::InvalidCommandCode(code)),
+        })
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum EncoderCmdError {
     #[error("error while converting from v4l2_encoder_cmd")]
@@ -73,6 +182,17 @@ impl From<EncoderCmdError> for Errno {
     }
 }
 
+impl AsErrno for EncoderCmdError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            EncoderCmdError::FromV4L2CommandConversionError => None,
+            EncoderCmdError::DrainInProgress => Some(Errno::EBUSY),
+            EncoderCmdError::UnsupportedCommand => Some(Errno::EINVAL),
+            EncoderCmdError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 impl From<Errno> for EncoderCmdError {
     fn from(error: Errno) -> Self {
         match error {
@@ -83,24 +203,6 @@ impl From<Errno> for EncoderCmdError {
     }
 }
 
-impl From<&EncoderCommand> for v4l2_encoder_cmd {
-    fn from(command: &EncoderCommand) -> Self {
-        v4l2_encoder_cmd {
-            cmd: match command {
-                EncoderCommand::Start => bindings::V4L2_ENC_CMD_START,
-                EncoderCommand::Stop(_) => bindings::V4L2_ENC_CMD_STOP,
-                EncoderCommand::Pause => bindings::V4L2_ENC_CMD_PAUSE,
-                EncoderCommand::Resume => bindings::V4L2_ENC_CMD_RESUME,
-            },
-            flags: match &command {
-                EncoderCommand::Stop(at_gop) if *at_gop => bindings::V4L2_ENC_CMD_STOP_AT_GOP_END,
-                _ => 0,
-            },
-            ..Default::default()
-        }
-    }
-}
-
 impl TryFrom<v4l2_encoder_cmd> for () {
     type Error = Infallible;
 
@@ -114,9 +216,16 @@ pub fn encoder_cmd<I: Into<v4l2_encoder_cmd>, O: TryFrom<v4l2_encoder_cmd>>(
     fd: &impl AsRawFd,
     command: I,
 ) -> Result<O, EncoderCmdError> {
-    let mut enc_cmd = command.into();
+    let mut enc_cmd: v4l2_encoder_cmd = command.into();
+    let _span = ioctl_span!(
+        "VIDIOC_ENCODER_CMD",
+        cmd = enc_cmd.cmd,
+        flags = enc_cmd.flags
+    );
+    let res = unsafe { ioctl::vidioc_encoder_cmd(fd.as_raw_fd(), &mut enc_cmd) };
+    record_errno!(_span, res);
 
-    match unsafe { ioctl::vidioc_encoder_cmd(fd.as_raw_fd(), &mut enc_cmd) } {
+    match res {
         Ok(_) => Ok(
             O::try_from(enc_cmd).map_err(|_| EncoderCmdError::FromV4L2CommandConversionError)?
         ),
@@ -129,12 +238,108 @@ pub fn try_encoder_cmd<I: Into<v4l2_encoder_cmd>, O: TryFrom<v4l2_encoder_cmd>>(
     fd: &impl AsRawFd,
     command: I,
 ) -> Result<O, EncoderCmdError> {
-    let mut enc_cmd = command.into();
+    let mut enc_cmd: v4l2_encoder_cmd = command.into();
+    let _span = ioctl_span!(
+        "VIDIOC_TRY_ENCODER_CMD",
+        cmd = enc_cmd.cmd,
+        flags = enc_cmd.flags
+    );
+    let res = unsafe { ioctl::vidioc_try_encoder_cmd(fd.as_raw_fd(), &mut enc_cmd) };
+    record_errno!(_span, res);
 
-    match unsafe { ioctl::vidioc_try_encoder_cmd(fd.as_raw_fd(), &mut enc_cmd) } {
+    match res {
         Ok(_) => Ok(
             O::try_from(enc_cmd).map_err(|_| EncoderCmdError::FromV4L2CommandConversionError)?
         ),
         Err(e) => Err(e.into()),
     }
 }
+
+/// Encoder commands supported by a device, as probed using `VIDIOC_TRY_ENCODER_CMD`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncoderCmdCapability {
+    pub start: bool,
+    pub stop: bool,
+    pub stop_at_gop_end: bool,
+    pub pause: bool,
+    pub resume: bool,
+}
+
+impl EncoderCmdCapability {
+    /// Probe the commands supported by the encoder `fd`. This does not change the state of the
+    /// encoder.
+    pub fn probe(fd: &impl AsRawFd) -> Self {
+        // Drivers clear the flags they do not support, so check that they are preserved.
+        let supported = |command: EncoderCmd| matches!(try_encoder_cmd::<_, EncoderCmd>(fd, command), Ok(c) if c == command);
+
+        EncoderCmdCapability {
+            start: supported(EncoderCmd::Start),
+            stop: supported(EncoderCmd::stop()),
+            stop_at_gop_end: supported(EncoderCmd::stop_at_gop_end()),
+            pause: supported(EncoderCmd::Pause),
+            resume: supported(EncoderCmd::Resume),
+        }
+    }
+
+    /// Returns whether `command` is supported by the encoder.
+    pub fn supports(&self, command: EncoderCmd) -> bool {
+        match command {
+            EncoderCmd::Start => self.start,
+            EncoderCmd::Stop { flags } if flags.contains(EncoderStopFlags::AT_GOP_END) => {
+                self.stop_at_gop_end
+            }
+            EncoderCmd::Stop { .. } => self.stop,
+            EncoderCmd::Pause => self.pause,
+            EncoderCmd::Resume => self.resume,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::{EncoderCmd, EncoderStopFlags};
+    use crate::bindings::{self, v4l2_encoder_cmd};
+
+    #[test]
+    fn test_encoder_cmd_conversion() {
+        for command in [
+            EncoderCmd::Start,
+            EncoderCmd::stop(),
+            EncoderCmd::stop_at_gop_end(),
+            EncoderCmd::Pause,
+            EncoderCmd::Resume,
+        ] {
+            let v4l2_cmd = v4l2_encoder_cmd::from(command);
+            assert_eq!(EncoderCmd::try_from(v4l2_cmd).unwrap(), command);
+        }
+
+        let v4l2_cmd = v4l2_encoder_cmd::from(EncoderCmd::stop_at_gop_end());
+        assert_eq!(v4l2_cmd.cmd, bindings::V4L2_ENC_CMD_STOP);
+        assert_eq!(v4l2_cmd.flags, EncoderStopFlags::AT_GOP_END.bits());
+
+        assert!(EncoderCmd::try_from(v4l2_encoder_cmd {
+            cmd: 42,
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_legacy_encoder_command_conversion() {
+        use super::EncoderCommand;
+
+        for (legacy, command) in [
+            (EncoderCommand::Start, EncoderCmd::Start),
+            (EncoderCommand::Stop(false), EncoderCmd::stop()),
+            (EncoderCommand::Stop(true), EncoderCmd::stop_at_gop_end()),
+            (EncoderCommand::Pause, EncoderCmd::Pause),
+            (EncoderCommand::Resume, EncoderCmd::Resume),
+        ] {
+            let v4l2_cmd = v4l2_encoder_cmd::from(&legacy);
+            assert_eq!(EncoderCmd::try_from(v4l2_cmd).unwrap(), command);
+        }
+    }
+}
@@ -1,8 +1,9 @@
 //! Safe wrapper for the `VIDIOC_ENUM_FMT` ioctl.
-use super::string_from_cstr;
+use super::{querycap, string_from_cstr, Capabilities, Capability};
 use crate::bindings;
 use crate::bindings::v4l2_fmtdesc;
-use crate::{PixelFormat, QueueType};
+use crate::error::AsErrno;
+use crate::{PixelFormat, QueueDirection, QueueType};
 use bitflags::bitflags;
 use log::error;
 use nix::errno::Errno;
@@ -17,6 +18,26 @@ bitflags! {
     pub struct FormatFlags: u32 {
         const COMPRESSED = bindings::V4L2_FMT_FLAG_COMPRESSED;
         const EMULATED = bindings::V4L2_FMT_FLAG_EMULATED;
+        /// The colorspace of a CAPTURE format can be set by the application.
+        const CSC_COLORSPACE = bindings::V4L2_FMT_FLAG_CSC_COLORSPACE;
+        /// The transfer function of a CAPTURE format can be set by the application.
+        const CSC_XFER_FUNC = bindings::V4L2_FMT_FLAG_CSC_XFER_FUNC;
+        /// The Y'CbCr (or HSV) encoding of a CAPTURE format can be set by the application.
+        const CSC_YCBCR_ENC = bindings::V4L2_FMT_FLAG_CSC_YCBCR_ENC;
+        /// The quantization of a CAPTURE format can be set by the application.
+        const CSC_QUANTIZATION = bindings::V4L2_FMT_FLAG_CSC_QUANTIZATION;
+    }
+}
+
+bitflags! {
+    /// Parts of the colorimetry of a format that the driver can convert to, i.e. that can be
+    /// requested using `VIDIOC_S_FMT` with the `V4L2_PIX_FMT_FLAG_SET_CSC` flag.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct ColorspaceSet: u32 {
+        const COLORSPACE = FormatFlags::CSC_COLORSPACE.bits();
+        const XFER_FUNC = FormatFlags::CSC_XFER_FUNC.bits();
+        const YCBCR_ENC = FormatFlags::CSC_YCBCR_ENC.bits();
+        const QUANTIZATION = FormatFlags::CSC_QUANTIZATION.bits();
     }
 }
 /// Quickly get the Fourcc code of a format.
@@ -27,13 +48,37 @@ impl From<v4l2_fmtdesc> for PixelFormat {
 }
 
 /// Safe variant of the `v4l2_fmtdesc` struct, to be used with `enum_fmt`.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct FmtDesc {
     pub flags: FormatFlags,
     pub description: String,
     pub pixelformat: PixelFormat,
 }
 
+impl FmtDesc {
+    /// Returns whether this is a compressed format, e.g. a codec bitstream.
+    pub fn is_compressed(&self) -> bool {
+        self.flags.contains(FormatFlags::COMPRESSED)
+    }
+
+    /// Returns whether this format is not natively supported by the hardware but emulated in
+    /// software, in which case a native format should be preferred if possible.
+    pub fn is_emulated(&self) -> bool {
+        self.flags.contains(FormatFlags::EMULATED)
+    }
+
+    /// Returns whether the driver can perform some colorspace conversion for this format, in
+    /// which case the application does not need to do it in software.
+    pub fn supports_csc(&self) -> bool {
+        !self.csc_colorspaces().is_empty()
+    }
+
+    /// Returns the parts of the colorimetry that can be set using `VIDIOC_S_FMT` for this format.
+    pub fn csc_colorspaces(&self) -> ColorspaceSet {
+        ColorspaceSet::from_bits_truncate(self.flags.bits())
+    }
+}
+
 impl fmt::Display for FmtDesc {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -54,7 +99,7 @@ impl From<v4l2_fmtdesc> for FmtDesc {
     fn from(fmtdesc: v4l2_fmtdesc) -> Self {
         FmtDesc {
             flags: FormatFlags::from_bits_truncate(fmtdesc.flags),
-            description: string_from_cstr(&fmtdesc.description).unwrap_or_else(|_| "".into()),
+            description: string_from_cstr(&fmtdesc.description),
             pixelformat: fmtdesc.pixelformat.into(),
         }
     }
@@ -63,7 +108,7 @@ impl From<v4l2_fmtdesc> for FmtDesc {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_fmtdesc;
-    nix::ioctl_readwrite!(vidioc_enum_fmt, b'V', 2, v4l2_fmtdesc);
+    ioctl_readwrite!(vidioc_enum_fmt, b'V', 2, v4l2_fmtdesc);
 }
 
 #[derive(Debug, Error)]
@@ -80,6 +125,14 @@ impl From<EnumFmtError> for Errno {
     }
 }
 
+impl AsErrno for EnumFmtError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            EnumFmtError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_ENUM_FMT` ioctl.
 pub fn enum_fmt<T: From<v4l2_fmtdesc>>(
     fd: &impl AsRawFd,
@@ -115,6 +168,73 @@ impl<'a, F: AsRawFd> FormatIterator<'a, F> {
             index: 0,
         }
     }
+
+    /// Create a new iterator listing the formats of the video OUTPUT queue of `fd`, e.g. the
+    /// compressed formats accepted by a decoder or the raw formats accepted by an encoder.
+    ///
+    /// The multi-planar queue is used if the device supports it, the single-planar one otherwise.
+    pub fn for_output(fd: &'a F) -> Self {
+        Self::for_video_queue(fd, QueueType::VideoOutput)
+    }
+
+    /// Create a new iterator listing the formats of the video CAPTURE queue of `fd`.
+    ///
+    /// The multi-planar queue is used if the device supports it, the single-planar one otherwise.
+    pub fn for_capture(fd: &'a F) -> Self {
+        Self::for_video_queue(fd, QueueType::VideoCapture)
+    }
+
+    /// Create a new iterator listing the formats of the single-planar video queue `queue`, or of
+    /// its multi-planar equivalent if the device supports it.
+    ///
+    /// Devices exposing both kinds of queues for the same direction use the multi-planar one,
+    /// unless it does not list any format.
+    fn for_video_queue(fd: &'a F, queue: QueueType) -> Self {
+        let caps = match querycap::<Capability>(fd) {
+            Ok(caps) => caps.device_caps(),
+            Err(e) => {
+                error!("Cannot query device capabilities: {}", e);
+                Capabilities::empty()
+            }
+        };
+        let direction = queue.direction();
+
+        let queue = match queue.mplane_equivalent() {
+            Some(mplane) if Self::supports_mplane(caps, direction) => {
+                if !Self::supports_splane(caps, direction)
+                    || enum_fmt::<FmtDesc>(fd, mplane, 0).is_ok()
+                {
+                    mplane
+                } else {
+                    queue
+                }
+            }
+            _ => queue,
+        };
+        Self::new(fd, queue)
+    }
+
+    /// Returns whether `caps` include multi-planar video queues for `direction`.
+    fn supports_mplane(caps: Capabilities, direction: QueueDirection) -> bool {
+        caps.intersects(
+            Capabilities::VIDEO_M2M_MPLANE
+                | match direction {
+                    QueueDirection::Capture => Capabilities::VIDEO_CAPTURE_MPLANE,
+                    QueueDirection::Output => Capabilities::VIDEO_OUTPUT_MPLANE,
+                },
+        )
+    }
+
+    /// Returns whether `caps` include single-planar video queues for `direction`.
+    fn supports_splane(caps: Capabilities, direction: QueueDirection) -> bool {
+        caps.intersects(
+            Capabilities::VIDEO_M2M
+                | match direction {
+                    QueueDirection::Capture => Capabilities::VIDEO_CAPTURE,
+                    QueueDirection::Output => Capabilities::VIDEO_OUTPUT,
+                },
+        )
+    }
 }
 
 impl<'a, F: AsRawFd> Iterator for FormatIterator<'a, F> {
@@ -135,3 +255,119 @@ impl<'a, F: AsRawFd> Iterator for FormatIterator<'a, F> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::{ColorspaceSet, FmtDesc, FormatFlags, FormatIterator};
+    use crate::bindings;
+    use crate::ioctl::backend::mock::MockIoctls;
+    use crate::ioctl::Capabilities;
+    use crate::QueueType;
+
+    /// Expects a `VIDIOC_QUERYCAP` reporting `caps` as the device capabilities.
+    fn expect_querycap(mock: &MockIoctls, caps: Capabilities) {
+        mock.expect_with(
+            "vidioc_querycap",
+            move |cap: &mut bindings::v4l2_capability| {
+                cap.capabilities = (caps | Capabilities::DEVICE_CAPS).bits();
+                cap.device_caps = caps.bits();
+                Ok(0)
+            },
+        );
+    }
+
+    /// Expects a `VIDIOC_ENUM_FMT` of the first format of `queue`, returning `result`.
+    fn expect_first_format(mock: &MockIoctls, queue: QueueType, result: nix::Result<i32>) {
+        mock.expect_with(
+            "vidioc_enum_fmt",
+            move |fmt: &mut bindings::v4l2_fmtdesc| {
+                assert_eq!(fmt.type_, queue as u32);
+                assert_eq!(fmt.index, 0);
+                result
+            },
+        );
+    }
+
+    #[test]
+    fn test_video_queue_type() {
+        let mock = MockIoctls::new();
+        let file = mock.file();
+
+        // Single-planar only.
+        expect_querycap(&mock, Capabilities::VIDEO_CAPTURE);
+        assert_eq!(
+            FormatIterator::for_capture(&file).queue,
+            QueueType::VideoCapture
+        );
+
+        // Multi-planar only.
+        expect_querycap(&mock, Capabilities::VIDEO_M2M_MPLANE);
+        assert_eq!(
+            FormatIterator::for_output(&file).queue,
+            QueueType::VideoOutputMplane
+        );
+
+        // The direction matters: a multi-planar OUTPUT queue does not make the CAPTURE one
+        // multi-planar.
+        expect_querycap(
+            &mock,
+            Capabilities::VIDEO_CAPTURE | Capabilities::VIDEO_OUTPUT_MPLANE,
+        );
+        assert_eq!(
+            FormatIterator::for_capture(&file).queue,
+            QueueType::VideoCapture
+        );
+        expect_querycap(
+            &mock,
+            Capabilities::VIDEO_CAPTURE | Capabilities::VIDEO_OUTPUT_MPLANE,
+        );
+        assert_eq!(
+            FormatIterator::for_output(&file).queue,
+            QueueType::VideoOutputMplane
+        );
+
+        // Both kinds of queues: the multi-planar one is preferred if it lists formats.
+        let both = Capabilities::VIDEO_CAPTURE | Capabilities::VIDEO_CAPTURE_MPLANE;
+        expect_querycap(&mock, both);
+        expect_first_format(&mock, QueueType::VideoCaptureMplane, Ok(0));
+        assert_eq!(
+            FormatIterator::for_capture(&file).queue,
+            QueueType::VideoCaptureMplane
+        );
+        expect_querycap(&mock, both);
+        expect_first_format(&mock, QueueType::VideoCaptureMplane, Err(Errno::EINVAL));
+        assert_eq!(
+            FormatIterator::for_capture(&file).queue,
+            QueueType::VideoCapture
+        );
+
+        // Devices that cannot be queried use the single-planar queue.
+        mock.expect("vidioc_querycap", Err(Errno::ENOTTY));
+        assert_eq!(
+            FormatIterator::for_capture(&file).queue,
+            QueueType::VideoCapture
+        );
+
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_csc_colorspaces() {
+        let mut desc = FmtDesc {
+            flags: FormatFlags::EMULATED,
+            description: String::new(),
+            pixelformat: b"NV12".into(),
+        };
+        assert!(!desc.supports_csc());
+        assert_eq!(desc.csc_colorspaces(), ColorspaceSet::empty());
+
+        desc.flags |= FormatFlags::CSC_COLORSPACE | FormatFlags::CSC_QUANTIZATION;
+        assert!(desc.supports_csc());
+        assert_eq!(
+            desc.csc_colorspaces(),
+            ColorspaceSet::COLORSPACE | ColorspaceSet::QUANTIZATION
+        );
+    }
+}
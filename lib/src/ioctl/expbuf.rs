@@ -6,6 +6,7 @@ use std::os::unix::io::{AsRawFd, FromRawFd};
 use thiserror::Error;
 
 use crate::bindings::v4l2_exportbuffer;
+use crate::error::AsErrno;
 use crate::QueueType;
 
 bitflags! {
@@ -22,7 +23,7 @@ bitflags! {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_exportbuffer;
-    nix::ioctl_readwrite!(vidioc_expbuf, b'V', 16, v4l2_exportbuffer);
+    ioctl_readwrite!(vidioc_expbuf, b'V', 16, v4l2_exportbuffer);
 }
 
 #[derive(Debug, Error)]
@@ -39,6 +40,14 @@ impl From<ExpbufError> for Errno {
     }
 }
 
+impl AsErrno for ExpbufError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            ExpbufError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_EXPBUF` ioctl.
 pub fn expbuf<R: FromRawFd>(
     fd: &impl AsRawFd,
@@ -4,12 +4,14 @@ use thiserror::Error;
 
 use crate::bindings;
 use crate::bindings::v4l2_frmivalenum;
+use crate::error::AsErrno;
+use crate::Fraction;
 use crate::PixelFormat;
 
 /// A wrapper for the 'v4l2_frmivalenum' union member types
 #[derive(Debug)]
 pub enum FrmIvalTypes<'a> {
-    Discrete(&'a bindings::v4l2_fract),
+    Discrete(Fraction),
     StepWise(&'a bindings::v4l2_frmival_stepwise),
 }
 
@@ -20,11 +22,9 @@ impl v4l2_frmivalenum {
         match self.type_ {
             // SAFETY: the member of the union that gets used by the driver
             // is determined by the type
-            bindings::v4l2_frmivaltypes_V4L2_FRMIVAL_TYPE_DISCRETE => {
-                Some(FrmIvalTypes::Discrete(unsafe {
-                    &self.__bindgen_anon_1.discrete
-                }))
-            }
+            bindings::v4l2_frmivaltypes_V4L2_FRMIVAL_TYPE_DISCRETE => Some(FrmIvalTypes::Discrete(
+                unsafe { self.__bindgen_anon_1.discrete }.into(),
+            )),
 
             // SAFETY: the member of the union that gets used by the driver
             // is determined by the type
@@ -43,7 +43,7 @@ impl v4l2_frmivalenum {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_frmivalenum;
-    nix::ioctl_readwrite!(vidioc_enum_frameintervals, b'V', 75, v4l2_frmivalenum);
+    ioctl_readwrite!(vidioc_enum_frameintervals, b'V', 75, v4l2_frmivalenum);
 }
 
 #[derive(Debug, Error)]
@@ -59,6 +59,14 @@ impl From<FrameIntervalsError> for Errno {
         }
     }
 }
+
+impl AsErrno for FrameIntervalsError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            FrameIntervalsError::IoctlError(e) => Some(*e),
+        }
+    }
+}
 /// Safe wrapper around the `VIDIOC_ENUM_FRAMEINTERVALS` ioctl.
 pub fn enum_frame_intervals<O: From<v4l2_frmivalenum>>(
     fd: &impl AsRawFd,
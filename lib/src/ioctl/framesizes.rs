@@ -4,6 +4,7 @@ use thiserror::Error;
 
 use crate::bindings;
 use crate::bindings::v4l2_frmsizeenum;
+use crate::error::AsErrno;
 use crate::PixelFormat;
 
 /// A wrapper for the 'v4l2_frmsizeenum' union member types
@@ -43,7 +44,7 @@ impl v4l2_frmsizeenum {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_frmsizeenum;
-    nix::ioctl_readwrite!(vidioc_enum_framesizes, b'V', 74, v4l2_frmsizeenum);
+    ioctl_readwrite!(vidioc_enum_framesizes, b'V', 74, v4l2_frmsizeenum);
 }
 
 #[derive(Debug, Error)]
@@ -60,6 +61,14 @@ impl From<FrameSizeError> for Errno {
     }
 }
 
+impl AsErrno for FrameSizeError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            FrameSizeError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_ENUM_FRAMESIZES` ioctl.
 pub fn enum_frame_sizes<O: From<v4l2_frmsizeenum>>(
     fd: &impl AsRawFd,
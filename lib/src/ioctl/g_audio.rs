@@ -5,6 +5,7 @@ use enumn::N;
 use nix::errno::Errno;
 use thiserror::Error;
 
+use super::string_from_cstr;
 use crate::bindings;
 use crate::bindings::v4l2_audio;
 use crate::bindings::v4l2_audioout;
@@ -12,6 +13,7 @@ use crate::bindings::v4l2_frequency;
 use crate::bindings::v4l2_frequency_band;
 use crate::bindings::v4l2_modulator;
 use crate::bindings::v4l2_tuner;
+use crate::error::AsErrno;
 
 bitflags! {
     #[derive(Clone, Copy, Debug)]
@@ -66,7 +68,37 @@ bitflags! {
     }
 }
 
-#[derive(Debug, N)]
+impl TunerCapFlags {
+    /// Converts `hz` to the unit in which a tuner with these capabilities expresses frequencies,
+    /// i.e. 62.5 kHz, or 62.5 Hz if they contain `LOW`, or 1 Hz if they contain `ONE_HZ`. The
+    /// result is rounded down.
+    pub fn hz_to_units(self, hz: u32) -> u32 {
+        if self.contains(Self::ONE_HZ) {
+            hz
+        } else if self.contains(Self::LOW) {
+            (hz as u64 * 2 / 125) as u32
+        } else {
+            (hz as u64 * 2 / 125_000) as u32
+        }
+    }
+
+    /// Converts `units`, a frequency expressed in the unit of a tuner with these capabilities, to
+    /// Hz.
+    pub fn units_to_hz(self, units: u32) -> u64 {
+        if self.contains(Self::ONE_HZ) {
+            units as u64
+        } else if self.contains(Self::LOW) {
+            units as u64 * 125 / 2
+        } else {
+            units as u64 * 125_000 / 2
+        }
+    }
+}
+
+/// Capabilities of a modulator, which use the same flags as tuners.
+pub type ModulatorCapability = TunerCapFlags;
+
+#[derive(Clone, Copy, Debug, N)]
 #[repr(u32)]
 pub enum TunerMode {
     Mono = bindings::V4L2_TUNER_MODE_MONO,
@@ -76,6 +108,73 @@ pub enum TunerMode {
     Lang1Lang2 = bindings::V4L2_TUNER_MODE_LANG1_LANG2,
 }
 
+/// Safe variant of `struct v4l2_modulator`.
+#[derive(Clone, Debug)]
+pub struct Modulator {
+    pub index: u32,
+    pub name: String,
+    pub capability: ModulatorCapability,
+    /// Lowest tunable frequency, in units of 62.5 kHz, or 62.5 Hz if `capability` contains
+    /// `LOW`, or 1 Hz if it contains `ONE_HZ`.
+    pub rangelow: u32,
+    /// Highest tunable frequency, in the same unit as `rangelow`.
+    pub rangehigh: u32,
+    pub txsubchans: TunerTransmissionFlags,
+    pub type_: Option<TunerType>,
+}
+
+impl From<v4l2_modulator> for Modulator {
+    fn from(modulator: v4l2_modulator) -> Self {
+        Modulator {
+            index: modulator.index,
+            name: string_from_cstr(&modulator.name),
+            capability: ModulatorCapability::from_bits_truncate(modulator.capability),
+            rangelow: modulator.rangelow,
+            rangehigh: modulator.rangehigh,
+            txsubchans: TunerTransmissionFlags::from_bits_truncate(modulator.txsubchans),
+            type_: TunerType::n(modulator.type_),
+        }
+    }
+}
+
+/// Safe variant of `struct v4l2_tuner`.
+#[derive(Clone, Debug)]
+pub struct Tuner {
+    pub index: u32,
+    pub name: String,
+    pub type_: Option<TunerType>,
+    pub capability: TunerCapFlags,
+    /// Lowest tunable frequency, in units of 62.5 kHz, or 62.5 Hz if `capability` contains
+    /// `LOW`, or 1 Hz if it contains `ONE_HZ`.
+    pub rangelow: u32,
+    /// Highest tunable frequency, in the same unit as `rangelow`.
+    pub rangehigh: u32,
+    pub rxsubchans: TunerTransmissionFlags,
+    pub audmode: Option<TunerMode>,
+    /// Strength of the received signal, from 0 to 65535, if known.
+    pub signal: u32,
+    /// Automatic frequency control: negative if the received frequency is too low, positive if it
+    /// is too high.
+    pub afc: i32,
+}
+
+impl From<v4l2_tuner> for Tuner {
+    fn from(tuner: v4l2_tuner) -> Self {
+        Tuner {
+            index: tuner.index,
+            name: string_from_cstr(&tuner.name),
+            type_: TunerType::n(tuner.type_),
+            capability: TunerCapFlags::from_bits_truncate(tuner.capability),
+            rangelow: tuner.rangelow,
+            rangehigh: tuner.rangehigh,
+            rxsubchans: TunerTransmissionFlags::from_bits_truncate(tuner.rxsubchans),
+            audmode: TunerMode::n(tuner.audmode),
+            signal: tuner.signal as u32,
+            afc: tuner.afc,
+        }
+    }
+}
+
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_audio;
@@ -85,25 +184,25 @@ mod ioctl {
     use crate::bindings::v4l2_modulator;
     use crate::bindings::v4l2_tuner;
 
-    nix::ioctl_readwrite!(vidioc_g_tuner, b'V', 29, v4l2_tuner);
-    nix::ioctl_write_ptr!(vidioc_s_tuner, b'V', 30, v4l2_tuner);
+    ioctl_readwrite!(vidioc_g_tuner, b'V', 29, v4l2_tuner);
+    ioctl_write_ptr!(vidioc_s_tuner, b'V', 30, v4l2_tuner);
 
-    nix::ioctl_read!(vidioc_g_audio, b'V', 33, v4l2_audio);
-    nix::ioctl_write_ptr!(vidioc_s_audio, b'V', 34, v4l2_audio);
+    ioctl_read!(vidioc_g_audio, b'V', 33, v4l2_audio);
+    ioctl_write_ptr!(vidioc_s_audio, b'V', 34, v4l2_audio);
 
-    nix::ioctl_read!(vidioc_g_audout, b'V', 49, v4l2_audioout);
-    nix::ioctl_write_ptr!(vidioc_s_audout, b'V', 50, v4l2_audioout);
+    ioctl_read!(vidioc_g_audout, b'V', 49, v4l2_audioout);
+    ioctl_write_ptr!(vidioc_s_audout, b'V', 50, v4l2_audioout);
 
-    nix::ioctl_readwrite!(vidioc_g_modulator, b'V', 54, v4l2_modulator);
-    nix::ioctl_write_ptr!(vidioc_s_modulator, b'V', 55, v4l2_modulator);
+    ioctl_readwrite!(vidioc_g_modulator, b'V', 54, v4l2_modulator);
+    ioctl_write_ptr!(vidioc_s_modulator, b'V', 55, v4l2_modulator);
 
-    nix::ioctl_readwrite!(vidioc_g_frequency, b'V', 56, v4l2_frequency);
-    nix::ioctl_write_ptr!(vidioc_s_frequency, b'V', 57, v4l2_frequency);
+    ioctl_readwrite!(vidioc_g_frequency, b'V', 56, v4l2_frequency);
+    ioctl_write_ptr!(vidioc_s_frequency, b'V', 57, v4l2_frequency);
 
-    nix::ioctl_readwrite!(vidioc_enumaudio, b'V', 65, v4l2_audio);
-    nix::ioctl_readwrite!(vidioc_enumaudout, b'V', 66, v4l2_audioout);
+    ioctl_readwrite!(vidioc_enumaudio, b'V', 65, v4l2_audio);
+    ioctl_readwrite!(vidioc_enumaudout, b'V', 66, v4l2_audioout);
 
-    nix::ioctl_readwrite!(vidioc_enum_freq_bands, b'V', 101, v4l2_frequency_band);
+    ioctl_readwrite!(vidioc_enum_freq_bands, b'V', 101, v4l2_frequency_band);
 }
 
 #[derive(Debug, Error)]
@@ -123,6 +222,15 @@ impl From<GAudioError> for Errno {
     }
 }
 
+impl AsErrno for GAudioError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GAudioError::Invalid => Some(Errno::EINVAL),
+            GAudioError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_G_TUNER` ioctl.
 pub fn g_tuner<O: From<v4l2_tuner>>(fd: &impl AsRawFd, index: u32) -> Result<O, GAudioError> {
     let mut tuner = v4l2_tuner {
@@ -208,6 +316,9 @@ pub fn s_audout(fd: &impl AsRawFd, index: u32) -> Result<(), GAudioError> {
 }
 
 /// Safe wrapper around the `VIDIOC_G_MODULATOR` ioctl.
+///
+/// Modulators are found on TV and radio output devices. Their analog input counterpart is the
+/// tuner, see [`g_tuner`].
 pub fn g_modulator<O: From<v4l2_modulator>>(
     fd: &impl AsRawFd,
     index: u32,
@@ -243,6 +354,15 @@ pub fn s_modulator(
     }
 }
 
+/// Returns an iterator over all the modulators of `fd`, using the `VIDIOC_G_MODULATOR` ioctl.
+pub fn enum_modulators<'a, O, F>(fd: &'a F) -> impl Iterator<Item = O> + 'a
+where
+    O: From<v4l2_modulator> + 'a,
+    F: AsRawFd,
+{
+    (0..).map_while(move |index| g_modulator(fd, index).ok())
+}
+
 /// Safe wrapper around the `VIDIOC_G_FREQUENCY` ioctl.
 pub fn g_frequency<O: From<v4l2_frequency>>(
     fd: &impl AsRawFd,
@@ -325,6 +445,15 @@ impl From<EnumFreqBandsError> for Errno {
         }
     }
 }
+
+impl AsErrno for EnumFreqBandsError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            EnumFreqBandsError::Invalid => Some(Errno::EINVAL),
+            EnumFreqBandsError::IoctlError(e) => Some(*e),
+        }
+    }
+}
 /// Safe wrapper around the `VIDIOC_ENUM_FREQ_BANDS` ioctl.
 pub fn enum_freq_bands<O: From<v4l2_frequency_band>>(
     fd: &impl AsRawFd,
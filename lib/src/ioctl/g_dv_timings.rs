@@ -8,6 +8,7 @@ use crate::bindings;
 use crate::bindings::v4l2_dv_timings;
 use crate::bindings::v4l2_dv_timings_cap;
 use crate::bindings::v4l2_enum_dv_timings;
+use crate::error::AsErrno;
 
 #[doc(hidden)]
 mod ioctl {
@@ -15,11 +16,11 @@ mod ioctl {
     use crate::bindings::v4l2_dv_timings_cap;
     use crate::bindings::v4l2_enum_dv_timings;
 
-    nix::ioctl_readwrite!(vidioc_s_dv_timings, b'V', 87, v4l2_dv_timings);
-    nix::ioctl_readwrite!(vidioc_g_dv_timings, b'V', 88, v4l2_dv_timings);
-    nix::ioctl_readwrite!(vidioc_enum_dv_timings, b'V', 98, v4l2_enum_dv_timings);
-    nix::ioctl_read!(vidioc_query_dv_timings, b'V', 99, v4l2_dv_timings);
-    nix::ioctl_readwrite!(vidioc_dv_timings_cap, b'V', 100, v4l2_dv_timings_cap);
+    ioctl_readwrite!(vidioc_s_dv_timings, b'V', 87, v4l2_dv_timings);
+    ioctl_readwrite!(vidioc_g_dv_timings, b'V', 88, v4l2_dv_timings);
+    ioctl_readwrite!(vidioc_enum_dv_timings, b'V', 98, v4l2_enum_dv_timings);
+    ioctl_read!(vidioc_query_dv_timings, b'V', 99, v4l2_dv_timings);
+    ioctl_readwrite!(vidioc_dv_timings_cap, b'V', 100, v4l2_dv_timings_cap);
 }
 
 #[derive(Debug, N)]
@@ -51,6 +52,17 @@ impl From<GDvTimingsError> for Errno {
     }
 }
 
+impl AsErrno for GDvTimingsError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GDvTimingsError::Invalid => Some(Errno::EINVAL),
+            GDvTimingsError::Unsupported => Some(Errno::ENODATA),
+            GDvTimingsError::Busy => Some(Errno::EBUSY),
+            GDvTimingsError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_S_DV_TIMINGS` ioctl.
 pub fn s_dv_timings<I: Into<v4l2_dv_timings>, O: From<v4l2_dv_timings>>(
     fd: &impl AsRawFd,
@@ -102,6 +114,16 @@ impl From<EnumDvTimingsError> for Errno {
     }
 }
 
+impl AsErrno for EnumDvTimingsError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            EnumDvTimingsError::Invalid => Some(Errno::EINVAL),
+            EnumDvTimingsError::Unsupported => Some(Errno::ENODATA),
+            EnumDvTimingsError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_ENUM_DV_TIMINGS` ioctl.
 pub fn enum_dv_timings<O: From<v4l2_dv_timings>>(
     fd: &impl AsRawFd,
@@ -143,6 +165,17 @@ impl From<QueryDvTimingsError> for Errno {
     }
 }
 
+impl AsErrno for QueryDvTimingsError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            QueryDvTimingsError::Unsupported => Some(Errno::ENODATA),
+            QueryDvTimingsError::NoLink => Some(Errno::ENOLINK),
+            QueryDvTimingsError::UnstableSignal => Some(Errno::ENOLCK),
+            QueryDvTimingsError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_QUERY_DV_TIMINGS` ioctl.
 pub fn query_dv_timings<O: From<v4l2_dv_timings>>(
     fd: &impl AsRawFd,
@@ -174,6 +207,14 @@ impl From<DvTimingsCapError> for Errno {
     }
 }
 
+impl AsErrno for DvTimingsCapError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            DvTimingsCapError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_DV_TIMINGS_CAP` ioctl.
 pub fn dv_timings_cap<O: From<v4l2_dv_timings_cap>>(
     fd: &impl AsRawFd,
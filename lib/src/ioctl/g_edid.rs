@@ -0,0 +1,63 @@
+use std::os::unix::io::AsRawFd;
+
+use nix::errno::Errno;
+
+use crate::bindings::v4l2_edid;
+
+#[doc(hidden)]
+mod ioctl {
+    use crate::bindings::v4l2_edid;
+
+    ioctl_readwrite!(vidioc_g_edid, b'V', 40, v4l2_edid);
+    ioctl_readwrite!(vidioc_s_edid, b'V', 41, v4l2_edid);
+}
+
+/// Size of an EDID block in bytes.
+pub const EDID_BLOCK_SIZE: usize = 128;
+
+/// Safe wrapper around the `VIDIOC_G_EDID` ioctl.
+///
+/// Returns all the EDID blocks of `pad`, or an empty vector if it has no EDID.
+pub fn g_edid(fd: &impl AsRawFd, pad: u32) -> Result<Vec<u8>, Errno> {
+    // Passing 0 blocks makes the driver return the number of available blocks.
+    let mut edid = v4l2_edid {
+        pad,
+        ..Default::default()
+    };
+    unsafe { ioctl::vidioc_g_edid(fd.as_raw_fd(), &mut edid) }?;
+
+    let mut data = vec![0u8; edid.blocks as usize * EDID_BLOCK_SIZE];
+    if data.is_empty() {
+        return Ok(data);
+    }
+
+    edid.start_block = 0;
+    edid.edid = data.as_mut_ptr();
+    unsafe { ioctl::vidioc_g_edid(fd.as_raw_fd(), &mut edid) }?;
+    // The EDID may have shrunk between the two calls.
+    data.truncate(edid.blocks as usize * EDID_BLOCK_SIZE);
+
+    Ok(data)
+}
+
+/// Safe wrapper around the `VIDIOC_S_EDID` ioctl.
+///
+/// `edid` must contain a whole number of EDID blocks. Passing an empty slice
+/// disables the EDID of `pad`.
+pub fn s_edid(fd: &impl AsRawFd, pad: u32, edid: &[u8]) -> Result<(), Errno> {
+    if !edid.len().is_multiple_of(EDID_BLOCK_SIZE) {
+        return Err(Errno::EINVAL);
+    }
+
+    let mut v4l2_edid = v4l2_edid {
+        pad,
+        blocks: (edid.len() / EDID_BLOCK_SIZE) as u32,
+        // The driver only reads from this buffer.
+        edid: edid.as_ptr() as *mut u8,
+        ..Default::default()
+    };
+
+    unsafe { ioctl::vidioc_s_edid(fd.as_raw_fd(), &mut v4l2_edid) }?;
+
+    Ok(())
+}
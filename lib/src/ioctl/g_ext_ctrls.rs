@@ -11,6 +11,7 @@ use crate::bindings::v4l2_ext_controls;
 use crate::bindings::v4l2_querymenu;
 use crate::controls::codec::FwhtFlags;
 use crate::controls::AsV4l2ControlSlice;
+use crate::error::AsErrno;
 use crate::Colorspace;
 use crate::Quantization;
 use crate::XferFunc;
@@ -80,13 +81,13 @@ impl TryFrom<v4l2_ctrl_fwht_params> for ValidControl<v4l2_ctrl_fwht_params> {
         // Validate all the input data.
         let _ = FwhtFlags::from_bits(value.flags)
             .ok_or(FwhtParamsCtrlError::InvalidFlags(value.flags))?;
-        let _ = Colorspace::n(value.colorspace)
+        let _ = Colorspace::known(value.colorspace)
             .ok_or(FwhtParamsCtrlError::InvalidColorspace(value.colorspace))?;
-        let _ = XferFunc::n(value.xfer_func)
+        let _ = XferFunc::known(value.xfer_func)
             .ok_or(FwhtParamsCtrlError::InvalidXferFunc(value.xfer_func))?;
-        let _ = YCbCrEncoding::n(value.ycbcr_enc)
+        let _ = YCbCrEncoding::known(value.ycbcr_enc)
             .ok_or(FwhtParamsCtrlError::InvalidYCbCrEncoding(value.ycbcr_enc))?;
-        let _ = Quantization::n(value.quantization)
+        let _ = Quantization::known(value.quantization)
             .ok_or(FwhtParamsCtrlError::InvalidQuantization(value.quantization))?;
 
         Ok(ValidControl(value))
@@ -99,19 +100,19 @@ impl ValidControl<v4l2_ctrl_fwht_params> {
     }
 
     pub fn colorspace(&self) -> Colorspace {
-        Colorspace::n(self.0.colorspace).unwrap()
+        Colorspace::from(self.0.colorspace)
     }
 
     pub fn xfer_func(&self) -> XferFunc {
-        XferFunc::n(self.0.xfer_func).unwrap()
+        XferFunc::from(self.0.xfer_func)
     }
 
     pub fn ycbcr_enc(&self) -> YCbCrEncoding {
-        YCbCrEncoding::n(self.0.ycbcr_enc).unwrap()
+        YCbCrEncoding::from(self.0.ycbcr_enc)
     }
 
     pub fn quantization(&self) -> Quantization {
-        Quantization::n(self.0.quantization).unwrap()
+        Quantization::from(self.0.quantization)
     }
 }
 
@@ -120,12 +121,12 @@ mod ioctl {
     use crate::bindings::v4l2_control;
     use crate::bindings::v4l2_ext_controls;
     use crate::bindings::v4l2_querymenu;
-    nix::ioctl_readwrite!(vidioc_g_ctrl, b'V', 27, v4l2_control);
-    nix::ioctl_readwrite!(vidioc_s_ctrl, b'V', 28, v4l2_control);
-    nix::ioctl_readwrite!(vidioc_g_ext_ctrls, b'V', 71, v4l2_ext_controls);
-    nix::ioctl_readwrite!(vidioc_s_ext_ctrls, b'V', 72, v4l2_ext_controls);
-    nix::ioctl_readwrite!(vidioc_try_ext_ctrls, b'V', 73, v4l2_ext_controls);
-    nix::ioctl_readwrite!(vidioc_querymenu, b'V', 37, v4l2_querymenu);
+    ioctl_readwrite!(vidioc_g_ctrl, b'V', 27, v4l2_control);
+    ioctl_readwrite!(vidioc_s_ctrl, b'V', 28, v4l2_control);
+    ioctl_readwrite!(vidioc_g_ext_ctrls, b'V', 71, v4l2_ext_controls);
+    ioctl_readwrite!(vidioc_s_ext_ctrls, b'V', 72, v4l2_ext_controls);
+    ioctl_readwrite!(vidioc_try_ext_ctrls, b'V', 73, v4l2_ext_controls);
+    ioctl_readwrite!(vidioc_querymenu, b'V', 37, v4l2_querymenu);
 }
 
 #[derive(Debug, Error)]
@@ -145,6 +146,15 @@ impl From<GCtrlError> for Errno {
     }
 }
 
+impl AsErrno for GCtrlError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GCtrlError::Invalid => Some(Errno::EINVAL),
+            GCtrlError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_G_CTRL` ioctl.
 pub fn g_ctrl(fd: &impl AsRawFd, id: u32) -> Result<i32, GCtrlError> {
     let mut ctrl = v4l2_control {
@@ -184,6 +194,14 @@ impl From<ExtControlErrorType> for Errno {
     }
 }
 
+impl AsErrno for ExtControlErrorType {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            ExtControlErrorType::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub struct ExtControlError {
     pub error_idx: u32,
@@ -202,14 +220,33 @@ impl From<ExtControlError> for Errno {
     }
 }
 
+impl AsErrno for ExtControlError {
+    fn errno(&self) -> Option<Errno> {
+        self.error.errno()
+    }
+}
+
+// Not defined by older kernel headers, so we define them here to make them available regardless
+// of the headers the bindings are generated from. Kernels that do not support them reject them
+// with `EINVAL`.
+const V4L2_CTRL_WHICH_MIN_VAL: u32 = 0x0f02_0000;
+const V4L2_CTRL_WHICH_MAX_VAL: u32 = 0x0f03_0000;
+
 /// Encapsulates the `ctrl_class` and `which` enum of `v4l2_ext_controls`.
 ///
-/// Note that `Default` is an invalid value for `S_EXT_CTRLS` and `TRY_EXT_CTRLS`.
+/// Note that `Default`, `MinimumValue` and `MaximumValue` are invalid values for `S_EXT_CTRLS` and
+/// `TRY_EXT_CTRLS`.
 pub enum CtrlWhich {
     Current,
     Default,
     Request(RawFd),
     Class(u32),
+    /// Minimum value the control can take. Only supported by recent kernels, and for controls
+    /// with the `V4L2_CTRL_FLAG_HAS_WHICH_MIN_MAX` flag.
+    MinimumValue,
+    /// Maximum value the control can take. Only supported by recent kernels, and for controls
+    /// with the `V4L2_CTRL_FLAG_HAS_WHICH_MIN_MAX` flag.
+    MaximumValue,
 }
 
 use bindings::v4l2_ext_controls__bindgen_ty_1 as v4l2_class_or_which;
@@ -227,6 +264,12 @@ impl CtrlWhich {
                 which: bindings::V4L2_CTRL_WHICH_REQUEST_VAL,
             },
             CtrlWhich::Class(class) => v4l2_class_or_which { ctrl_class: *class },
+            CtrlWhich::MinimumValue => v4l2_class_or_which {
+                which: V4L2_CTRL_WHICH_MIN_VAL,
+            },
+            CtrlWhich::MaximumValue => v4l2_class_or_which {
+                which: V4L2_CTRL_WHICH_MAX_VAL,
+            },
         }
     }
 }
@@ -240,6 +283,8 @@ impl TryFrom<&v4l2_ext_controls> for CtrlWhich {
             bindings::V4L2_CTRL_WHICH_CUR_VAL => Ok(CtrlWhich::Current),
             bindings::V4L2_CTRL_WHICH_DEF_VAL => Ok(CtrlWhich::Default),
             bindings::V4L2_CTRL_WHICH_REQUEST_VAL => Ok(CtrlWhich::Request(ctrls.request_fd)),
+            V4L2_CTRL_WHICH_MIN_VAL => Ok(CtrlWhich::MinimumValue),
+            V4L2_CTRL_WHICH_MAX_VAL => Ok(CtrlWhich::MaximumValue),
             bindings::V4L2_CTRL_CLASS_USER
             | bindings::V4L2_CTRL_CLASS_CODEC
             | bindings::V4L2_CTRL_CLASS_CAMERA
@@ -280,8 +325,12 @@ pub fn g_ext_ctrls<I: AsV4l2ControlSlice>(
         ..Default::default()
     };
 
+    let _span = ioctl_span!("VIDIOC_G_EXT_CTRLS", count = v4l2_controls.count);
     // SAFETY: the 'controls' argument is properly set up above
-    match unsafe { ioctl::vidioc_g_ext_ctrls(fd.as_raw_fd(), &mut v4l2_controls) } {
+    let res = unsafe { ioctl::vidioc_g_ext_ctrls(fd.as_raw_fd(), &mut v4l2_controls) };
+    record_errno!(_span, res);
+
+    match res {
         Ok(_) => Ok(()),
         Err(e) => Err(ExtControlError {
             error_idx: v4l2_controls.error_idx,
@@ -309,8 +358,12 @@ pub fn s_ext_ctrls<I: AsV4l2ControlSlice>(
         ..Default::default()
     };
 
+    let _span = ioctl_span!("VIDIOC_S_EXT_CTRLS", count = v4l2_controls.count);
     // SAFETY: the 'controls' argument is properly set up above
-    match unsafe { ioctl::vidioc_s_ext_ctrls(fd.as_raw_fd(), &mut v4l2_controls) } {
+    let res = unsafe { ioctl::vidioc_s_ext_ctrls(fd.as_raw_fd(), &mut v4l2_controls) };
+    record_errno!(_span, res);
+
+    match res {
         Ok(_) => Ok(()),
         Err(e) => Err(ExtControlError {
             error_idx: v4l2_controls.error_idx,
@@ -338,8 +391,12 @@ pub fn try_ext_ctrls<I: AsV4l2ControlSlice>(
         ..Default::default()
     };
 
+    let _span = ioctl_span!("VIDIOC_TRY_EXT_CTRLS", count = v4l2_controls.count);
     // SAFETY: the 'controls' argument is properly set up above
-    match unsafe { ioctl::vidioc_try_ext_ctrls(fd.as_raw_fd(), &mut v4l2_controls) } {
+    let res = unsafe { ioctl::vidioc_try_ext_ctrls(fd.as_raw_fd(), &mut v4l2_controls) };
+    record_errno!(_span, res);
+
+    match res {
         Ok(_) => Ok(()),
         Err(e) => Err(ExtControlError {
             error_idx: v4l2_controls.error_idx,
@@ -365,6 +422,15 @@ impl From<QueryMenuError> for Errno {
     }
 }
 
+impl AsErrno for QueryMenuError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            QueryMenuError::InvalidIdOrIndex => Some(Errno::EINVAL),
+            QueryMenuError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_QUERYMENU`
 pub fn querymenu<O: From<v4l2_querymenu>>(
     fd: &impl AsRawFd,
@@ -383,3 +449,33 @@ pub fn querymenu<O: From<v4l2_querymenu>>(
         Err(e) => Err(QueryMenuError::IoctlError(e)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::{v4l2_ext_controls, CtrlWhich};
+
+    #[test]
+    fn test_ctrl_which_min_max() {
+        let min = v4l2_ext_controls {
+            __bindgen_anon_1: CtrlWhich::MinimumValue.binding_value(),
+            ..Default::default()
+        };
+        assert_eq!(unsafe { min.__bindgen_anon_1.which }, 0x0f02_0000);
+        assert!(matches!(
+            CtrlWhich::try_from(&min),
+            Ok(CtrlWhich::MinimumValue)
+        ));
+
+        let max = v4l2_ext_controls {
+            __bindgen_anon_1: CtrlWhich::MaximumValue.binding_value(),
+            ..Default::default()
+        };
+        assert_eq!(unsafe { max.__bindgen_anon_1.which }, 0x0f03_0000);
+        assert!(matches!(
+            CtrlWhich::try_from(&max),
+            Ok(CtrlWhich::MaximumValue)
+        ));
+    }
+}
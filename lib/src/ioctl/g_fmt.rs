@@ -7,6 +7,7 @@ use thiserror::Error;
 
 use crate::bindings;
 use crate::bindings::v4l2_format;
+use crate::error::AsErrno;
 use crate::Format;
 use crate::FormatConversionError;
 use crate::PlaneLayout;
@@ -32,6 +33,13 @@ impl TryFrom<(QueueType, &Format)> for v4l2_format {
                                 pixelformat: format.pixelformat.into(),
                                 num_planes: format.plane_fmt.len() as u8,
                                 plane_fmt: Default::default(),
+                                colorspace: format.colorspace.into(),
+                                __bindgen_anon_1: bindings::v4l2_pix_format_mplane__bindgen_ty_1 {
+                                    ycbcr_enc: u32::from(format.ycbcr_enc) as u8,
+                                },
+                                quantization: u32::from(format.quantization) as u8,
+                                xfer_func: u32::from(format.xfer_func) as u8,
+                                field: format.field as u32,
                                 ..Default::default()
                             };
 
@@ -45,6 +53,24 @@ impl TryFrom<(QueueType, &Format)> for v4l2_format {
                         },
                     }
                 }
+                QueueType::MetaCapture | QueueType::MetaOutput => {
+                    bindings::v4l2_format__bindgen_ty_1 {
+                        meta: {
+                            if format.plane_fmt.len() > 1 {
+                                return Err(Self::Error::TooManyPlanes(format.plane_fmt.len()));
+                            }
+
+                            bindings::v4l2_meta_format {
+                                dataformat: format.pixelformat.into(),
+                                buffersize: format
+                                    .plane_fmt
+                                    .first()
+                                    .map(|plane| plane.sizeimage)
+                                    .unwrap_or_default(),
+                            }
+                        },
+                    }
+                }
                 _ => bindings::v4l2_format__bindgen_ty_1 {
                     pix: {
                         if format.plane_fmt.len() > 1 {
@@ -66,6 +92,15 @@ impl TryFrom<(QueueType, &Format)> for v4l2_format {
                             pixelformat: format.pixelformat.into(),
                             bytesperline,
                             sizeimage,
+                            colorspace: format.colorspace.into(),
+                            // Required for the driver to consider the fields below.
+                            priv_: bindings::V4L2_PIX_FMT_PRIV_MAGIC,
+                            __bindgen_anon_1: bindings::v4l2_pix_format__bindgen_ty_1 {
+                                ycbcr_enc: format.ycbcr_enc.into(),
+                            },
+                            quantization: format.quantization.into(),
+                            xfer_func: format.xfer_func.into(),
+                            field: format.field as u32,
                             ..Default::default()
                         }
                     },
@@ -88,9 +123,9 @@ impl From<&PlaneLayout> for bindings::v4l2_plane_pix_format {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_format;
-    nix::ioctl_readwrite!(vidioc_g_fmt, b'V', 4, v4l2_format);
-    nix::ioctl_readwrite!(vidioc_s_fmt, b'V', 5, v4l2_format);
-    nix::ioctl_readwrite!(vidioc_try_fmt, b'V', 64, v4l2_format);
+    ioctl_readwrite!(vidioc_g_fmt, b'V', 4, v4l2_format);
+    ioctl_readwrite!(vidioc_s_fmt, b'V', 5, v4l2_format);
+    ioctl_readwrite!(vidioc_try_fmt, b'V', 64, v4l2_format);
 }
 
 #[derive(Debug, Error)]
@@ -113,6 +148,16 @@ impl From<GFmtError> for Errno {
     }
 }
 
+impl AsErrno for GFmtError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GFmtError::FromV4L2FormatConversionError => None,
+            GFmtError::InvalidBufferType => Some(Errno::EINVAL),
+            GFmtError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_G_FMT` ioctl.
 pub fn g_fmt<O: TryFrom<v4l2_format>>(fd: &impl AsRawFd, queue: QueueType) -> Result<O, GFmtError> {
     let mut fmt = v4l2_format {
@@ -155,6 +200,18 @@ impl From<SFmtError> for Errno {
     }
 }
 
+impl AsErrno for SFmtError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            SFmtError::FromV4L2FormatConversionError => None,
+            SFmtError::ToV4L2FormatConversionError => None,
+            SFmtError::InvalidBufferType => Some(Errno::EINVAL),
+            SFmtError::DeviceBusy => Some(Errno::EBUSY),
+            SFmtError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_S_FMT` ioctl.
 pub fn s_fmt<I: TryInto<v4l2_format>, O: TryFrom<v4l2_format>>(
     fd: &mut impl AsRawFd,
@@ -164,7 +221,11 @@ pub fn s_fmt<I: TryInto<v4l2_format>, O: TryFrom<v4l2_format>>(
         .try_into()
         .map_err(|_| SFmtError::ToV4L2FormatConversionError)?;
 
-    match unsafe { ioctl::vidioc_s_fmt(fd.as_raw_fd(), &mut fmt) } {
+    let _span = ioctl_span!("VIDIOC_S_FMT", queue = ?crate::trace::RawQueueType(fmt.type_));
+    let res = unsafe { ioctl::vidioc_s_fmt(fd.as_raw_fd(), &mut fmt) };
+    record_errno!(_span, res);
+
+    match res {
         Ok(_) => Ok(fmt
             .try_into()
             .map_err(|_| SFmtError::FromV4L2FormatConversionError)?),
@@ -197,6 +258,17 @@ impl From<TryFmtError> for Errno {
     }
 }
 
+impl AsErrno for TryFmtError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            TryFmtError::FromV4L2FormatConversionError => None,
+            TryFmtError::ToV4L2FormatConversionError => None,
+            TryFmtError::InvalidBufferType => Some(Errno::EINVAL),
+            TryFmtError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_TRY_FMT` ioctl.
 pub fn try_fmt<I: TryInto<v4l2_format>, O: TryFrom<v4l2_format>>(
     fd: &impl AsRawFd,
@@ -218,6 +290,7 @@ pub fn try_fmt<I: TryInto<v4l2_format>, O: TryFrom<v4l2_format>>(
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{Colorspace, Field, Quantization, XferFunc, YCbCrEncoding};
     use std::convert::TryInto;
 
     #[test]
@@ -242,6 +315,12 @@ mod test {
                     bytesperline: 160,
                 },
             ],
+            colorspace: Colorspace::Bt2020,
+            xfer_func: XferFunc::Smpte2084,
+            // Unknown values must be preserved.
+            ycbcr_enc: YCbCrEncoding::Unknown(42),
+            quantization: Quantization::FullRange,
+            field: Field::SeqTb,
         };
         let v4l2_format = v4l2_format {
             ..(QueueType::VideoCaptureMplane, &mplane).try_into().unwrap()
@@ -262,6 +341,11 @@ mod test {
                 sizeimage: 307200,
                 bytesperline: 640,
             }],
+            colorspace: Colorspace::Rec709,
+            xfer_func: XferFunc::F709,
+            ycbcr_enc: YCbCrEncoding::E709,
+            quantization: Quantization::LimRange,
+            field: Field::Alternate,
         };
         // Conversion to/from single-planar format.
         let v4l2_format = v4l2_format {
@@ -291,10 +375,38 @@ mod test {
                     bytesperline: 160,
                 },
             ],
+            ..Default::default()
         };
         assert_eq!(
             TryInto::<v4l2_format>::try_into((QueueType::VideoCapture, &mplane)).err(),
             Some(FormatConversionError::TooManyPlanes(3))
         );
     }
+
+    #[test]
+    // Convert from Format to metadata v4l2_format and back.
+    fn meta_to_v4l2_format() {
+        let meta = Format {
+            pixelformat: b"SENS".into(),
+            plane_fmt: vec![PlaneLayout {
+                sizeimage: 4096,
+                bytesperline: 0,
+            }],
+            ..Default::default()
+        };
+        let v4l2_format: v4l2_format = (QueueType::MetaCapture, &meta).try_into().unwrap();
+        assert_eq!(v4l2_format.type_, QueueType::MetaCapture as u32);
+        let meta2: Format = v4l2_format.try_into().unwrap();
+        assert_eq!(meta, meta2);
+
+        // Metadata buffers have a single plane.
+        let two_planes = Format {
+            plane_fmt: vec![Default::default(); 2],
+            ..meta
+        };
+        assert_eq!(
+            TryInto::<v4l2_format>::try_into((QueueType::MetaCapture, &two_planes)).err(),
+            Some(FormatConversionError::TooManyPlanes(2))
+        );
+    }
 }
@@ -6,6 +6,7 @@ use thiserror::Error;
 
 use crate::bindings::v4l2_input;
 use crate::bindings::v4l2_output;
+use crate::error::AsErrno;
 
 #[doc(hidden)]
 mod ioctl {
@@ -14,13 +15,13 @@ mod ioctl {
     use crate::bindings::v4l2_input;
     use crate::bindings::v4l2_output;
 
-    nix::ioctl_readwrite!(vidioc_enuminput, b'V', 26, v4l2_input);
-    nix::ioctl_read!(vidioc_g_input, b'V', 38, c_int);
-    nix::ioctl_readwrite!(vidioc_s_input, b'V', 39, c_int);
+    ioctl_readwrite!(vidioc_enuminput, b'V', 26, v4l2_input);
+    ioctl_read!(vidioc_g_input, b'V', 38, c_int);
+    ioctl_readwrite!(vidioc_s_input, b'V', 39, c_int);
 
-    nix::ioctl_read!(vidioc_g_output, b'V', 46, c_int);
-    nix::ioctl_readwrite!(vidioc_s_output, b'V', 47, c_int);
-    nix::ioctl_readwrite!(vidioc_enumoutput, b'V', 48, v4l2_output);
+    ioctl_read!(vidioc_g_output, b'V', 46, c_int);
+    ioctl_readwrite!(vidioc_s_output, b'V', 47, c_int);
+    ioctl_readwrite!(vidioc_enumoutput, b'V', 48, v4l2_output);
 }
 
 #[derive(Debug, Error)]
@@ -40,6 +41,15 @@ impl From<SelectionError> for Errno {
     }
 }
 
+impl AsErrno for SelectionError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            SelectionError::OutOfRange(_) => Some(Errno::EINVAL),
+            SelectionError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_ENUMINPUT` ioctl.
 pub fn enuminput<R: From<v4l2_input>>(
     fd: &impl AsRawFd,
@@ -61,7 +71,9 @@ pub fn enuminput<R: From<v4l2_input>>(
 pub fn g_input(fd: &impl AsRawFd) -> Result<usize, Errno> {
     let mut input: c_int = 0;
 
-    unsafe { ioctl::vidioc_g_input(fd.as_raw_fd(), &mut input) }.map(|r| r as usize)
+    unsafe { ioctl::vidioc_g_input(fd.as_raw_fd(), &mut input) }?;
+
+    Ok(input as usize)
 }
 
 /// Safe wrapper around the `VIDIOC_S_INPUT` ioctl.
@@ -98,7 +110,9 @@ pub fn enumoutput<R: From<v4l2_output>>(
 pub fn g_output(fd: &impl AsRawFd) -> Result<usize, Errno> {
     let mut output: c_int = 0;
 
-    unsafe { ioctl::vidioc_g_output(fd.as_raw_fd(), &mut output) }.map(|r| r as usize)
+    unsafe { ioctl::vidioc_g_output(fd.as_raw_fd(), &mut output) }?;
+
+    Ok(output as usize)
 }
 
 /// Safe wrapper around the `VIDIOC_S_OUTPUT` ioctl.
@@ -111,3 +125,29 @@ pub fn s_output(fd: &impl AsRawFd, index: usize) -> Result<(), SelectionError> {
         Err(e) => Err(SelectionError::IoctlError(e)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioctl::backend::mock::MockIoctls;
+
+    #[test]
+    fn test_g_input_output() {
+        let mock = MockIoctls::new();
+        mock.expect_with("vidioc_g_input", |input: &mut c_int| {
+            *input = 2;
+            Ok(0)
+        })
+        .expect_with("vidioc_g_output", |output: &mut c_int| {
+            *output = 1;
+            Ok(0)
+        })
+        .expect("vidioc_g_input", Err(Errno::ENOTTY));
+        let file = mock.file();
+
+        assert_eq!(g_input(&file), Ok(2));
+        assert_eq!(g_output(&file), Ok(1));
+        assert_eq!(g_input(&file), Err(Errno::ENOTTY));
+        mock.assert_done();
+    }
+}
@@ -4,13 +4,14 @@ use nix::errno::Errno;
 use thiserror::Error;
 
 use crate::bindings::v4l2_jpegcompression;
+use crate::error::AsErrno;
 
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_jpegcompression;
 
-    nix::ioctl_read!(vidioc_g_jpegcomp, b'V', 61, v4l2_jpegcompression);
-    nix::ioctl_write_ptr!(vidioc_s_jpegcomp, b'V', 62, v4l2_jpegcompression);
+    ioctl_read!(vidioc_g_jpegcomp, b'V', 61, v4l2_jpegcompression);
+    ioctl_write_ptr!(vidioc_s_jpegcomp, b'V', 62, v4l2_jpegcompression);
 }
 
 #[derive(Debug, Error)]
@@ -27,6 +28,14 @@ impl From<GJpegCompError> for Errno {
     }
 }
 
+impl AsErrno for GJpegCompError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GJpegCompError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_G_JPEGCOMP` ioctl.
 pub fn g_jpegcomp<O: From<v4l2_jpegcompression>>(fd: &impl AsRawFd) -> Result<O, GJpegCompError> {
     let mut jpegcomp: v4l2_jpegcompression = Default::default();
@@ -6,6 +6,9 @@ use thiserror::Error;
 use crate::bindings::v4l2_standard;
 use crate::bindings::v4l2_std_id;
 use crate::bindings::v4l2_streamparm;
+use crate::error::AsErrno;
+use crate::Fraction;
+use crate::QueueDirection;
 use crate::QueueType;
 
 #[doc(hidden)]
@@ -14,12 +17,12 @@ mod ioctl {
     use crate::bindings::v4l2_std_id;
     use crate::bindings::v4l2_streamparm;
 
-    nix::ioctl_readwrite!(vidioc_g_parm, b'V', 21, v4l2_streamparm);
-    nix::ioctl_readwrite!(vidioc_s_parm, b'V', 22, v4l2_streamparm);
-    nix::ioctl_read!(vidioc_g_std, b'V', 23, v4l2_std_id);
-    nix::ioctl_write_ptr!(vidioc_s_std, b'V', 24, v4l2_std_id);
-    nix::ioctl_readwrite!(vidioc_enumstd, b'V', 25, v4l2_standard);
-    nix::ioctl_read!(vidioc_querystd, b'V', 63, v4l2_std_id);
+    ioctl_readwrite!(vidioc_g_parm, b'V', 21, v4l2_streamparm);
+    ioctl_readwrite!(vidioc_s_parm, b'V', 22, v4l2_streamparm);
+    ioctl_read!(vidioc_g_std, b'V', 23, v4l2_std_id);
+    ioctl_write_ptr!(vidioc_s_std, b'V', 24, v4l2_std_id);
+    ioctl_readwrite!(vidioc_enumstd, b'V', 25, v4l2_standard);
+    ioctl_read!(vidioc_querystd, b'V', 63, v4l2_std_id);
 }
 
 #[derive(Debug, Error)]
@@ -36,6 +39,14 @@ impl From<GParmError> for Errno {
     }
 }
 
+impl AsErrno for GParmError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GParmError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_G_PARM` ioctl.
 pub fn g_parm<O: From<v4l2_streamparm>>(
     fd: &impl AsRawFd,
@@ -65,6 +76,50 @@ pub fn s_parm<I: Into<v4l2_streamparm>, O: From<v4l2_streamparm>>(
     }
 }
 
+/// Returns the time per frame (i.e. frame interval) of `queue` using the
+/// `VIDIOC_G_PARM` ioctl.
+pub fn g_time_per_frame(fd: &impl AsRawFd, queue: QueueType) -> Result<Fraction, GParmError> {
+    let parm: v4l2_streamparm = g_parm(fd, queue)?;
+
+    // SAFETY: the member of the union that gets used by the driver is
+    // determined by the direction of the queue.
+    let interval = match queue.direction() {
+        QueueDirection::Capture => unsafe { parm.parm.capture.timeperframe },
+        QueueDirection::Output => unsafe { parm.parm.output.timeperframe },
+    };
+
+    Ok(interval.into())
+}
+
+/// Sets the time per frame (i.e. frame interval) of `queue` using the
+/// `VIDIOC_S_PARM` ioctl, and returns the interval actually selected by the
+/// driver.
+pub fn s_time_per_frame(
+    fd: &impl AsRawFd,
+    queue: QueueType,
+    interval: Fraction,
+) -> Result<Fraction, GParmError> {
+    let mut parm: v4l2_streamparm = g_parm(fd, queue)?;
+
+    // The member of the union that gets used by the driver is determined by
+    // the direction of the queue.
+    match queue.direction() {
+        QueueDirection::Capture => parm.parm.capture.timeperframe = interval.into(),
+        QueueDirection::Output => parm.parm.output.timeperframe = interval.into(),
+    }
+
+    let parm: v4l2_streamparm = s_parm(fd, parm)?;
+
+    // SAFETY: the member of the union that gets used by the driver is
+    // determined by the direction of the queue.
+    let interval = match queue.direction() {
+        QueueDirection::Capture => unsafe { parm.parm.capture.timeperframe },
+        QueueDirection::Output => unsafe { parm.parm.output.timeperframe },
+    };
+
+    Ok(interval.into())
+}
+
 /// Safe wrapper around the `VIDIOC_G_STD` ioctl.
 pub fn g_std<O: From<v4l2_std_id>>(fd: &impl AsRawFd) -> Result<O, GParmError> {
     let mut std_id: v4l2_std_id = 0;
@@ -92,6 +147,15 @@ impl From<SStdError> for Errno {
     }
 }
 
+impl AsErrno for SStdError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            SStdError::Unsupported => Some(Errno::EINVAL),
+            SStdError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_S_STD` ioctl.
 pub fn s_std<I: Into<v4l2_std_id>>(fd: &impl AsRawFd, std_id: I) -> Result<(), SStdError> {
     let std_id = std_id.into();
@@ -123,6 +187,16 @@ impl From<EnumStdError> for Errno {
     }
 }
 
+impl AsErrno for EnumStdError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            EnumStdError::OutOfBounds => Some(Errno::EINVAL),
+            EnumStdError::Unsupported => Some(Errno::ENODATA),
+            EnumStdError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_ENUMSTD` ioctl.
 pub fn enumstd<O: From<v4l2_standard>>(fd: &impl AsRawFd, index: u32) -> Result<O, EnumStdError> {
     let mut standard = v4l2_standard {
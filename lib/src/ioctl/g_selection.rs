@@ -8,6 +8,7 @@ use thiserror::Error;
 use crate::bindings;
 use crate::bindings::v4l2_rect;
 use crate::bindings::v4l2_selection;
+use crate::error::AsErrno;
 
 #[derive(Debug, N, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
@@ -41,8 +42,8 @@ bitflags! {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_selection;
-    nix::ioctl_readwrite!(vidioc_g_selection, b'V', 94, v4l2_selection);
-    nix::ioctl_readwrite!(vidioc_s_selection, b'V', 95, v4l2_selection);
+    ioctl_readwrite!(vidioc_g_selection, b'V', 94, v4l2_selection);
+    ioctl_readwrite!(vidioc_s_selection, b'V', 95, v4l2_selection);
 }
 
 #[derive(Debug, Error)]
@@ -62,6 +63,15 @@ impl From<GSelectionError> for Errno {
     }
 }
 
+impl AsErrno for GSelectionError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GSelectionError::Invalid => Some(Errno::EINVAL),
+            GSelectionError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_G_SELECTION` ioctl.
 pub fn g_selection<R: From<v4l2_rect>>(
     fd: &impl AsRawFd,
@@ -104,6 +114,17 @@ impl From<SSelectionError> for Errno {
     }
 }
 
+impl AsErrno for SSelectionError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            SSelectionError::Invalid => Some(Errno::EINVAL),
+            SSelectionError::InvalidRange => Some(Errno::ERANGE),
+            SSelectionError::Busy => Some(Errno::EBUSY),
+            SSelectionError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_S_SELECTION` ioctl.
 pub fn s_selection<RI: Into<v4l2_rect>, RO: From<v4l2_rect>>(
     fd: &impl AsRawFd,
@@ -0,0 +1,722 @@
+//! Safe wrapper for the `MEDIA_IOC_G_TOPOLOGY` ioctl of media controller devices.
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+use std::os::unix::io::AsRawFd;
+
+use bitflags::bitflags;
+use enumn::N;
+use nix::errno::Errno;
+use thiserror::Error;
+
+use super::string_from_cstr;
+use crate::bindings;
+use crate::bindings::media_device_info;
+use crate::bindings::media_v2_entity;
+use crate::bindings::media_v2_interface;
+use crate::bindings::media_v2_link;
+use crate::bindings::media_v2_pad;
+use crate::bindings::media_v2_topology;
+use crate::error::AsErrno;
+
+/// Main function of a media entity, i.e. one of the `MEDIA_ENT_F_*` values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, N)]
+#[repr(u32)]
+pub enum EntityFunction {
+    Unknown = bindings::MEDIA_ENT_F_UNKNOWN,
+    V4l2SubdevUnknown = bindings::MEDIA_ENT_F_V4L2_SUBDEV_UNKNOWN,
+    DtvDemod = bindings::MEDIA_ENT_F_DTV_DEMOD,
+    TsDemux = bindings::MEDIA_ENT_F_TS_DEMUX,
+    DtvCa = bindings::MEDIA_ENT_F_DTV_CA,
+    DtvNetDecap = bindings::MEDIA_ENT_F_DTV_NET_DECAP,
+    IoV4l = bindings::MEDIA_ENT_F_IO_V4L,
+    IoDtv = bindings::MEDIA_ENT_F_IO_DTV,
+    IoVbi = bindings::MEDIA_ENT_F_IO_VBI,
+    IoSwradio = bindings::MEDIA_ENT_F_IO_SWRADIO,
+    CamSensor = bindings::MEDIA_ENT_F_CAM_SENSOR,
+    Flash = bindings::MEDIA_ENT_F_FLASH,
+    Lens = bindings::MEDIA_ENT_F_LENS,
+    AtvDecoder = bindings::MEDIA_ENT_F_ATV_DECODER,
+    Tuner = bindings::MEDIA_ENT_F_TUNER,
+    IfVidDecoder = bindings::MEDIA_ENT_F_IF_VID_DECODER,
+    IfAudDecoder = bindings::MEDIA_ENT_F_IF_AUD_DECODER,
+    AudioCapture = bindings::MEDIA_ENT_F_AUDIO_CAPTURE,
+    AudioPlayback = bindings::MEDIA_ENT_F_AUDIO_PLAYBACK,
+    AudioMixer = bindings::MEDIA_ENT_F_AUDIO_MIXER,
+    ProcVideoComposer = bindings::MEDIA_ENT_F_PROC_VIDEO_COMPOSER,
+    ProcVideoPixelFormatter = bindings::MEDIA_ENT_F_PROC_VIDEO_PIXEL_FORMATTER,
+    ProcVideoPixelEncConv = bindings::MEDIA_ENT_F_PROC_VIDEO_PIXEL_ENC_CONV,
+    ProcVideoLut = bindings::MEDIA_ENT_F_PROC_VIDEO_LUT,
+    ProcVideoScaler = bindings::MEDIA_ENT_F_PROC_VIDEO_SCALER,
+    ProcVideoStatistics = bindings::MEDIA_ENT_F_PROC_VIDEO_STATISTICS,
+    ProcVideoEncoder = bindings::MEDIA_ENT_F_PROC_VIDEO_ENCODER,
+    ProcVideoDecoder = bindings::MEDIA_ENT_F_PROC_VIDEO_DECODER,
+    ProcVideoIsp = bindings::MEDIA_ENT_F_PROC_VIDEO_ISP,
+    VidMux = bindings::MEDIA_ENT_F_VID_MUX,
+    VidIfBridge = bindings::MEDIA_ENT_F_VID_IF_BRIDGE,
+    DvDecoder = bindings::MEDIA_ENT_F_DV_DECODER,
+    DvEncoder = bindings::MEDIA_ENT_F_DV_ENCODER,
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct EntityFlags: u32 {
+        /// Default entity for its type, e.g. the default camera of the system.
+        const DEFAULT = bindings::MEDIA_ENT_FL_DEFAULT;
+        /// The entity is a connector.
+        const CONNECTOR = bindings::MEDIA_ENT_FL_CONNECTOR;
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct PadFlags: u32 {
+        const SINK = bindings::MEDIA_PAD_FL_SINK;
+        const SOURCE = bindings::MEDIA_PAD_FL_SOURCE;
+        /// The pad must be connected by an enabled link for the entity to stream.
+        const MUST_CONNECT = bindings::MEDIA_PAD_FL_MUST_CONNECT;
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct LinkFlags: u32 {
+        const ENABLED = bindings::MEDIA_LNK_FL_ENABLED;
+        /// The link cannot be disabled, i.e. it is always enabled.
+        const IMMUTABLE = bindings::MEDIA_LNK_FL_IMMUTABLE;
+        /// The link can be enabled or disabled while streaming.
+        const DYNAMIC = bindings::MEDIA_LNK_FL_DYNAMIC;
+    }
+}
+
+/// Type of a media link, stored in the `MEDIA_LNK_FL_LINK_TYPE` bits of its flags.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, N)]
+#[repr(u32)]
+pub enum LinkType {
+    /// Link between a source pad and a sink pad, through which data flows.
+    Data = bindings::MEDIA_LNK_FL_DATA_LINK,
+    /// Link between an interface and an entity.
+    Interface = bindings::MEDIA_LNK_FL_INTERFACE_LINK,
+    /// Link between two entities that work together, e.g. a sensor and its lens.
+    Ancillary = bindings::MEDIA_LNK_FL_ANCILLARY_LINK,
+}
+
+/// Safe variant of the `media_v2_entity` struct.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MediaEntity {
+    pub id: u32,
+    pub name: String,
+    /// Main function of the entity, one of the `MEDIA_ENT_F_*` values. Use
+    /// [`MediaEntity::entity_function`] to get it as an [`EntityFunction`].
+    pub function: u32,
+    pub flags: EntityFlags,
+}
+
+impl MediaEntity {
+    /// Returns the main function of the entity, or `None` if it is unknown to this crate.
+    pub fn entity_function(&self) -> Option<EntityFunction> {
+        EntityFunction::n(self.function)
+    }
+}
+
+impl From<media_v2_entity> for MediaEntity {
+    fn from(entity: media_v2_entity) -> Self {
+        let name = entity.name.map(|c| c as u8);
+        MediaEntity {
+            id: entity.id,
+            name: string_from_cstr(&name),
+            function: entity.function,
+            flags: EntityFlags::from_bits_truncate(entity.flags),
+        }
+    }
+}
+
+/// Safe variant of the `media_v2_interface` struct.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MediaInterface {
+    pub id: u32,
+    /// Type of the interface, one of the `MEDIA_INTF_T_*` values.
+    pub intf_type: u32,
+    pub flags: u32,
+    /// Major number of the device node of the interface.
+    pub major: u32,
+    /// Minor number of the device node of the interface.
+    pub minor: u32,
+}
+
+impl From<media_v2_interface> for MediaInterface {
+    fn from(intf: media_v2_interface) -> Self {
+        // SAFETY: all the interface types defined so far are device nodes.
+        let devnode = unsafe { intf.__bindgen_anon_1.devnode };
+        MediaInterface {
+            id: intf.id,
+            intf_type: intf.intf_type,
+            flags: intf.flags,
+            major: devnode.major,
+            minor: devnode.minor,
+        }
+    }
+}
+
+/// Safe variant of the `media_v2_pad` struct.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MediaPad {
+    pub id: u32,
+    /// ID of the entity this pad belongs to.
+    pub entity_id: u32,
+    pub flags: PadFlags,
+    /// Index of the pad within its entity, as used by the sub-device API.
+    pub index: u32,
+}
+
+/// Equivalent of the `MEDIA_V2_PAD_HAS_INDEX` macro: returns whether the `index` field of
+/// `media_v2_pad` is filled by media devices of API version `media_version`.
+fn media_v2_pad_has_index(media_version: u32) -> bool {
+    media_version >= (4 << 16) | (19 << 8)
+}
+
+/// Converts `pads`, as returned by a media device of API version `media_version`.
+///
+/// Older devices do not report the index of pads, in which case it is deduced from their position
+/// among the pads of their entity, in the order they have been returned.
+fn pads_from_raw(pads: Vec<media_v2_pad>, media_version: u32) -> Vec<MediaPad> {
+    let has_index = media_v2_pad_has_index(media_version);
+    let mut entity_pads: HashMap<u32, u32> = HashMap::new();
+
+    pads.into_iter()
+        .map(|pad| {
+            let position = entity_pads.entry(pad.entity_id).or_default();
+            let index = if has_index { pad.index } else { *position };
+            *position += 1;
+
+            MediaPad {
+                id: pad.id,
+                entity_id: pad.entity_id,
+                flags: PadFlags::from_bits_truncate(pad.flags),
+                index,
+            }
+        })
+        .collect()
+}
+
+/// Safe variant of the `media_v2_link` struct.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MediaLink {
+    pub id: u32,
+    /// ID of the source of the link, i.e. a pad for data links, or an interface for interface
+    /// links.
+    pub source_id: u32,
+    /// ID of the sink of the link, i.e. a pad for data links, or an entity for interface links.
+    pub sink_id: u32,
+    /// Flags of the link. The bits not covered by [`LinkFlags`] are retained, and include the
+    /// type of the link returned by [`MediaLink::link_type`].
+    pub flags: LinkFlags,
+}
+
+impl MediaLink {
+    /// Returns the type of the link, or `None` if it is unknown to this crate.
+    pub fn link_type(&self) -> Option<LinkType> {
+        LinkType::n(self.flags.bits() & bindings::MEDIA_LNK_FL_LINK_TYPE)
+    }
+}
+
+impl From<media_v2_link> for MediaLink {
+    fn from(link: media_v2_link) -> Self {
+        MediaLink {
+            id: link.id,
+            source_id: link.source_id,
+            sink_id: link.sink_id,
+            flags: LinkFlags::from_bits_retain(link.flags),
+        }
+    }
+}
+
+/// Graph of the entities of a media device, their pads, and the links between them, as returned
+/// by `MEDIA_IOC_G_TOPOLOGY`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Topology {
+    /// Version of the topology, which is incremented each time it changes.
+    pub version: u64,
+    pub entities: Vec<MediaEntity>,
+    pub interfaces: Vec<MediaInterface>,
+    pub pads: Vec<MediaPad>,
+    pub links: Vec<MediaLink>,
+}
+
+impl Topology {
+    /// Returns the entity with ID `id`, if any.
+    pub fn entity(&self, id: u32) -> Option<&MediaEntity> {
+        self.entities.iter().find(|entity| entity.id == id)
+    }
+
+    /// Returns the pad with ID `id`, if any.
+    pub fn pad(&self, id: u32) -> Option<&MediaPad> {
+        self.pads.iter().find(|pad| pad.id == id)
+    }
+
+    /// Returns the pad of the entity with ID `entity_id` which index is `index`, if any.
+    pub fn entity_pad(&self, entity_id: u32, index: u32) -> Option<&MediaPad> {
+        self.pads
+            .iter()
+            .find(|pad| pad.entity_id == entity_id && pad.index == index)
+    }
+
+    /// Returns the data link going from pad `source` to pad `sink`, if any.
+    pub fn data_link(&self, source: &MediaPad, sink: &MediaPad) -> Option<&MediaLink> {
+        self.links.iter().find(|link| {
+            link.link_type() == Some(LinkType::Data)
+                && link.source_id == source.id
+                && link.sink_id == sink.id
+        })
+    }
+
+    /// Returns the entity named `name`, if any.
+    pub fn entity_by_name(&self, name: &str) -> Option<&MediaEntity> {
+        self.entities.iter().find(|entity| entity.name == name)
+    }
+
+    /// Returns all the entities which main function is `func`.
+    pub fn entities_by_function(&self, func: EntityFunction) -> Vec<&MediaEntity> {
+        self.entities
+            .iter()
+            .filter(|entity| entity.function == func as u32)
+            .collect()
+    }
+
+    /// Returns the pads of `entity`.
+    pub fn entity_pads<'a>(
+        &'a self,
+        entity: &'a MediaEntity,
+    ) -> impl Iterator<Item = &'a MediaPad> {
+        self.pads.iter().filter(|pad| pad.entity_id == entity.id)
+    }
+
+    /// Returns the interface through which `entity` can be controlled from userspace, e.g. the
+    /// `/dev/video*` node of a video entity, if it has one.
+    pub fn entity_interface(&self, entity: &MediaEntity) -> Option<&MediaInterface> {
+        self.links
+            .iter()
+            .filter(|link| {
+                link.link_type() == Some(LinkType::Interface) && link.sink_id == entity.id
+            })
+            .find_map(|link| {
+                self.interfaces
+                    .iter()
+                    .find(|intf| intf.id == link.source_id)
+            })
+    }
+
+    /// Returns the shortest chain of data links going from `source` to `sink`, or `None` if there
+    /// is no such chain. If `source` and `sink` are the same entity, the chain is empty.
+    ///
+    /// All data links are considered, whether they are enabled or not, so the returned links may
+    /// need to be enabled before the pipeline can stream.
+    pub fn find_pipeline(
+        &self,
+        source: &MediaEntity,
+        sink: &MediaEntity,
+    ) -> Option<Vec<MediaLink>> {
+        let pad_entities: HashMap<u32, u32> = self
+            .pads
+            .iter()
+            .map(|pad| (pad.id, pad.entity_id))
+            .collect();
+        // Data links leaving each entity, along with the entity they lead to.
+        let mut edges: HashMap<u32, Vec<(&MediaLink, u32)>> = HashMap::new();
+        for link in &self.links {
+            if link.link_type() != Some(LinkType::Data) {
+                continue;
+            }
+            if let (Some(&from), Some(&to)) = (
+                pad_entities.get(&link.source_id),
+                pad_entities.get(&link.sink_id),
+            ) {
+                edges.entry(from).or_default().push((link, to));
+            }
+        }
+
+        // Link through which each visited entity has been reached first.
+        let mut reached_by: HashMap<u32, Option<(&MediaLink, u32)>> = HashMap::new();
+        let mut queue = VecDeque::new();
+        reached_by.insert(source.id, None);
+        queue.push_back(source.id);
+
+        while let Some(entity) = queue.pop_front() {
+            if entity == sink.id {
+                let mut pipeline = Vec::new();
+                let mut current = entity;
+                while let Some((link, from)) = reached_by[&current] {
+                    pipeline.push(link.clone());
+                    current = from;
+                }
+                pipeline.reverse();
+                return Some(pipeline);
+            }
+
+            for &(link, to) in edges.get(&entity).into_iter().flatten() {
+                if let Entry::Vacant(entry) = reached_by.entry(to) {
+                    entry.insert(Some((link, entity)));
+                    queue.push_back(to);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[doc(hidden)]
+mod ioctl {
+    use crate::bindings::media_device_info;
+    use crate::bindings::media_v2_topology;
+    ioctl_readwrite!(media_ioc_device_info, b'|', 0x00, media_device_info);
+    ioctl_readwrite!(media_ioc_g_topology, b'|', 0x04, media_v2_topology);
+}
+
+#[derive(Debug, Error)]
+pub enum GTopologyError {
+    #[error("MEDIA_IOC_G_TOPOLOGY is not supported (not a media device?)")]
+    Unsupported,
+    #[error("ioctl error: {0}")]
+    IoctlError(Errno),
+}
+
+impl From<GTopologyError> for Errno {
+    fn from(err: GTopologyError) -> Self {
+        match err {
+            GTopologyError::Unsupported => Errno::ENOTTY,
+            GTopologyError::IoctlError(e) => e,
+        }
+    }
+}
+
+impl AsErrno for GTopologyError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            GTopologyError::Unsupported => Some(Errno::ENOTTY),
+            GTopologyError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
+/// Returns the version of the media controller API implemented by `fd`.
+fn media_version(fd: &impl AsRawFd) -> Result<u32, GTopologyError> {
+    let mut info: media_device_info = Default::default();
+    match unsafe { ioctl::media_ioc_device_info(fd.as_raw_fd(), &mut info) } {
+        Ok(_) => Ok(info.media_version),
+        Err(Errno::ENOTTY) => Err(GTopologyError::Unsupported),
+        Err(e) => Err(GTopologyError::IoctlError(e)),
+    }
+}
+
+fn media_ioc_g_topology(
+    fd: &impl AsRawFd,
+    topology: &mut media_v2_topology,
+) -> Result<(), GTopologyError> {
+    match unsafe { ioctl::media_ioc_g_topology(fd.as_raw_fd(), topology) } {
+        Ok(_) => Ok(()),
+        Err(Errno::ENOTTY) => Err(GTopologyError::Unsupported),
+        Err(e) => Err(GTopologyError::IoctlError(e)),
+    }
+}
+
+/// Safe wrapper around the `MEDIA_IOC_G_TOPOLOGY` ioctl.
+///
+/// The ioctl is called once to get the number of elements of the topology, and a second time to
+/// retrieve them. If the topology changes in between, the operation is retried.
+pub fn g_topology(fd: &impl AsRawFd) -> Result<Topology, GTopologyError> {
+    let media_version = media_version(fd)?;
+
+    loop {
+        let mut topology: media_v2_topology = Default::default();
+        media_ioc_g_topology(fd, &mut topology)?;
+        let version = topology.topology_version;
+
+        let mut entities = vec![media_v2_entity::default(); topology.num_entities as usize];
+        let mut interfaces = vec![media_v2_interface::default(); topology.num_interfaces as usize];
+        let mut pads = vec![media_v2_pad::default(); topology.num_pads as usize];
+        let mut links = vec![media_v2_link::default(); topology.num_links as usize];
+        topology.ptr_entities = entities.as_mut_ptr() as u64;
+        topology.ptr_interfaces = interfaces.as_mut_ptr() as u64;
+        topology.ptr_pads = pads.as_mut_ptr() as u64;
+        topology.ptr_links = links.as_mut_ptr() as u64;
+
+        match media_ioc_g_topology(fd, &mut topology) {
+            // Elements have been added since the first call.
+            Err(GTopologyError::IoctlError(Errno::ENOSPC)) => continue,
+            Err(e) => return Err(e),
+            Ok(()) if topology.topology_version != version => continue,
+            Ok(()) => (),
+        }
+
+        entities.truncate(topology.num_entities as usize);
+        interfaces.truncate(topology.num_interfaces as usize);
+        pads.truncate(topology.num_pads as usize);
+        links.truncate(topology.num_links as usize);
+
+        return Ok(Topology {
+            version,
+            entities: entities.into_iter().map(MediaEntity::from).collect(),
+            interfaces: interfaces.into_iter().map(MediaInterface::from).collect(),
+            pads: pads_from_raw(pads, media_version),
+            links: links.into_iter().map(MediaLink::from).collect(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioctl::backend::mock::MockIoctls;
+
+    fn entity(id: u32, name: &str, function: EntityFunction) -> MediaEntity {
+        MediaEntity {
+            id,
+            name: name.to_string(),
+            function: function as u32,
+            flags: EntityFlags::empty(),
+        }
+    }
+
+    fn pad(id: u32, entity_id: u32, flags: PadFlags) -> MediaPad {
+        MediaPad {
+            id,
+            entity_id,
+            flags,
+            index: 0,
+        }
+    }
+
+    fn link(id: u32, source_id: u32, sink_id: u32) -> MediaLink {
+        MediaLink {
+            id,
+            source_id,
+            sink_id,
+            flags: LinkFlags::ENABLED,
+        }
+    }
+
+    /// Sensor -> CSI receiver -> ISP -> video node, with a second ISP output for statistics and
+    /// an interface link to the video node.
+    fn camera_topology() -> Topology {
+        Topology {
+            version: 1,
+            entities: vec![
+                entity(1, "sensor", EntityFunction::CamSensor),
+                entity(2, "csi", EntityFunction::VidIfBridge),
+                entity(3, "isp", EntityFunction::ProcVideoIsp),
+                entity(4, "isp-capture", EntityFunction::IoV4l),
+                entity(5, "isp-stats", EntityFunction::IoV4l),
+            ],
+            interfaces: vec![MediaInterface {
+                id: 200,
+                intf_type: bindings::MEDIA_INTF_T_V4L_VIDEO,
+                flags: 0,
+                major: 81,
+                minor: 3,
+            }],
+            pads: vec![
+                pad(10, 1, PadFlags::SOURCE),
+                pad(20, 2, PadFlags::SINK),
+                pad(21, 2, PadFlags::SOURCE),
+                pad(30, 3, PadFlags::SINK),
+                pad(31, 3, PadFlags::SOURCE),
+                pad(32, 3, PadFlags::SOURCE),
+                pad(40, 4, PadFlags::SINK),
+                pad(50, 5, PadFlags::SINK),
+            ],
+            links: vec![
+                link(100, 10, 20),
+                link(101, 21, 30),
+                link(102, 32, 50),
+                link(103, 31, 40),
+                MediaLink {
+                    id: 104,
+                    source_id: 200,
+                    sink_id: 4,
+                    flags: LinkFlags::ENABLED
+                        | LinkFlags::from_bits_retain(bindings::MEDIA_LNK_FL_INTERFACE_LINK),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_entities_by_function() {
+        let topology = camera_topology();
+
+        let video_nodes = topology.entities_by_function(EntityFunction::IoV4l);
+        assert_eq!(
+            video_nodes
+                .iter()
+                .map(|e| e.name.as_str())
+                .collect::<Vec<_>>(),
+            ["isp-capture", "isp-stats"]
+        );
+        assert!(topology
+            .entities_by_function(EntityFunction::Lens)
+            .is_empty());
+        assert_eq!(
+            topology.entity(3).unwrap().entity_function(),
+            Some(EntityFunction::ProcVideoIsp)
+        );
+        assert_eq!(topology.entity_pads(topology.entity(3).unwrap()).count(), 3);
+        assert_eq!(topology.links[4].link_type(), Some(LinkType::Interface));
+    }
+
+    #[test]
+    fn test_entity_interface() {
+        let topology = camera_topology();
+
+        let capture = topology.entity_by_name("isp-capture").unwrap();
+        assert_eq!(capture.id, 4);
+        let intf = topology.entity_interface(capture).unwrap();
+        assert_eq!((intf.id, intf.major, intf.minor), (200, 81, 3));
+
+        // Data links do not lead to interfaces.
+        let isp = topology.entity_by_name("isp").unwrap();
+        assert_eq!(topology.entity_interface(isp), None);
+        assert_eq!(topology.entity_by_name("unknown"), None);
+    }
+
+    #[test]
+    fn test_data_link() {
+        let topology = camera_topology();
+
+        let csi_sink = topology.entity_pad(2, 0).unwrap();
+        assert_eq!(csi_sink.id, 20);
+        let source = topology.pad(21).unwrap();
+        let sink = topology.entity_pad(3, 0).unwrap();
+        assert_eq!(topology.data_link(source, sink).map(|l| l.id), Some(101));
+        // Links only go one way.
+        assert_eq!(topology.data_link(sink, source), None);
+        assert_eq!(topology.entity_pad(2, 1), None);
+    }
+
+    #[test]
+    fn test_find_pipeline() {
+        let topology = camera_topology();
+        let sensor = topology.entity(1).unwrap();
+        let capture = topology.entity(4).unwrap();
+        let stats = topology.entity(5).unwrap();
+
+        let pipeline = topology.find_pipeline(sensor, capture).unwrap();
+        assert_eq!(
+            pipeline.iter().map(|l| l.id).collect::<Vec<_>>(),
+            [100, 101, 103]
+        );
+        let pipeline = topology.find_pipeline(sensor, stats).unwrap();
+        assert_eq!(
+            pipeline.iter().map(|l| l.id).collect::<Vec<_>>(),
+            [100, 101, 102]
+        );
+        assert_eq!(topology.find_pipeline(sensor, sensor), Some(vec![]));
+        // Links only go one way.
+        assert_eq!(topology.find_pipeline(capture, sensor), None);
+    }
+
+    #[test]
+    fn test_pads_from_raw() {
+        let raw_pad = |id, entity_id, index| media_v2_pad {
+            id,
+            entity_id,
+            index,
+            ..Default::default()
+        };
+        let pads = vec![raw_pad(10, 1, 1), raw_pad(11, 1, 0), raw_pad(20, 2, 0)];
+        let indices = |pads: Vec<MediaPad>| {
+            pads.iter()
+                .map(|pad| (pad.id, pad.index))
+                .collect::<Vec<_>>()
+        };
+
+        // The index reported by the device is used if it is valid...
+        assert_eq!(
+            indices(pads_from_raw(pads.clone(), (4 << 16) | (19 << 8))),
+            [(10, 1), (11, 0), (20, 0)]
+        );
+        // ... otherwise the position of the pad within its entity is used.
+        assert_eq!(
+            indices(pads_from_raw(pads, (4 << 16) | (18 << 8))),
+            [(10, 0), (11, 1), (20, 0)]
+        );
+    }
+
+    #[test]
+    fn test_g_topology() {
+        let mock = MockIoctls::new();
+        mock.expect_with("media_ioc_device_info", |info: &mut media_device_info| {
+            info.media_version = (6 << 16) | (1 << 8);
+            Ok(0)
+        })
+        .expect_with("media_ioc_g_topology", |t: &mut media_v2_topology| {
+            t.topology_version = 7;
+            t.num_entities = 2;
+            t.num_pads = 2;
+            t.num_links = 1;
+            Ok(0)
+        })
+        .expect_with("media_ioc_g_topology", |t: &mut media_v2_topology| {
+            // Fields of packed structures cannot be borrowed, so copy the value.
+            assert_eq!({ t.num_entities }, 2);
+            t.topology_version = 7;
+            // SAFETY: the pointers are set to arrays of the announced sizes by `g_topology`.
+            unsafe {
+                let entities = t.ptr_entities as *mut media_v2_entity;
+                for (i, name) in [b"sensor", b"video0"].iter().enumerate() {
+                    let entity = &mut *entities.add(i);
+                    entity.id = i as u32 + 1;
+                    for (dst, src) in entity.name.iter_mut().zip(name.iter()) {
+                        *dst = *src as _;
+                    }
+                }
+                (*entities).function = bindings::MEDIA_ENT_F_CAM_SENSOR;
+                (*entities.add(1)).function = bindings::MEDIA_ENT_F_IO_V4L;
+
+                let pads = t.ptr_pads as *mut media_v2_pad;
+                *pads = media_v2_pad {
+                    id: 3,
+                    entity_id: 1,
+                    flags: bindings::MEDIA_PAD_FL_SOURCE,
+                    ..Default::default()
+                };
+                *pads.add(1) = media_v2_pad {
+                    id: 4,
+                    entity_id: 2,
+                    flags: bindings::MEDIA_PAD_FL_SINK,
+                    ..Default::default()
+                };
+
+                *(t.ptr_links as *mut media_v2_link) = media_v2_link {
+                    id: 5,
+                    source_id: 3,
+                    sink_id: 4,
+                    flags: bindings::MEDIA_LNK_FL_ENABLED | bindings::MEDIA_LNK_FL_IMMUTABLE,
+                    ..Default::default()
+                };
+            }
+            Ok(0)
+        })
+        .expect("media_ioc_device_info", Err(Errno::ENOTTY));
+        let file = mock.file();
+
+        let topology = g_topology(&file).unwrap();
+        assert_eq!(topology.version, 7);
+        assert_eq!(topology.entities.len(), 2);
+        assert_eq!(topology.entities[0].name, "sensor");
+        assert_eq!(
+            topology.entities[1].entity_function(),
+            Some(EntityFunction::IoV4l)
+        );
+        assert!(topology.interfaces.is_empty());
+        assert_eq!(topology.pads[1].flags, PadFlags::SINK);
+        assert_eq!(topology.links[0].link_type(), Some(LinkType::Data));
+        assert!(topology.links[0].flags.contains(LinkFlags::IMMUTABLE));
+        assert_eq!(
+            topology
+                .find_pipeline(&topology.entities[0], &topology.entities[1])
+                .map(|links| links.len()),
+            Some(1)
+        );
+
+        assert!(matches!(
+            g_topology(&file),
+            Err(GTopologyError::Unsupported)
+        ));
+        mock.assert_done();
+    }
+}
@@ -0,0 +1,255 @@
+//! Safe wrapper for the `VIDIOC_S_HW_FREQ_SEEK` ioctl.
+//!
+//! A hardware seek blocks until the tuner has found a station, which can take several seconds.
+//! On a file descriptor opened in non-blocking mode, drivers fail immediately with `EAGAIN`
+//! instead, which older drivers also return when the seek reached the end of the band without
+//! finding a station, so both cases are reported as [`HwFreqSeekError::EndReached`]. Use
+//! [`s_hw_freq_seek_blocking`] to seek from a non-blocking file descriptor.
+use std::os::unix::io::AsRawFd;
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use thiserror::Error;
+
+use crate::bindings::v4l2_hw_freq_seek;
+use crate::error::AsErrno;
+use crate::ioctl::Tuner;
+
+#[doc(hidden)]
+mod ioctl {
+    use crate::bindings::v4l2_hw_freq_seek;
+    ioctl_write_ptr!(vidioc_s_hw_freq_seek, b'V', 82, v4l2_hw_freq_seek);
+}
+
+/// Direction of a hardware seek.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeekDirection {
+    Down,
+    Up,
+}
+
+/// Parameters of a hardware seek.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HwFreqSeek {
+    pub direction: SeekDirection,
+    /// Continue from the other end of the band once one of its ends is reached. Requires the
+    /// `HWSEEK_WRAP` tuner capability.
+    pub wrap_around: bool,
+    /// Resolution of the seek in Hz, or 0 for the default of the driver, which rounds it to the
+    /// closest resolution it supports.
+    pub spacing: u32,
+    /// Lowest and highest frequencies to seek, in Hz, or `None` to seek the whole band. Requires
+    /// the `HWSEEK_PROG_LIM` tuner capability.
+    pub band: Option<(u32, u32)>,
+}
+
+impl HwFreqSeek {
+    /// Seek the next station in `direction`, over the whole band, with the default spacing and
+    /// without wrapping around.
+    pub fn new(direction: SeekDirection) -> Self {
+        HwFreqSeek {
+            direction,
+            wrap_around: false,
+            spacing: 0,
+            band: None,
+        }
+    }
+
+    /// Returns the `v4l2_hw_freq_seek` performing this seek on `tuner`.
+    fn to_v4l2(self, tuner: &Tuner) -> v4l2_hw_freq_seek {
+        let capability = tuner.capability;
+        let (rangelow, rangehigh) = self
+            .band
+            .map(|(low, high)| (capability.hz_to_units(low), capability.hz_to_units(high)))
+            .unwrap_or((0, 0));
+
+        v4l2_hw_freq_seek {
+            tuner: tuner.index,
+            type_: tuner.type_.map(|t| t as u32).unwrap_or_default(),
+            seek_upward: (self.direction == SeekDirection::Up) as u32,
+            wrap_around: self.wrap_around as u32,
+            spacing: self.spacing,
+            rangelow,
+            rangehigh,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum HwFreqSeekError {
+    #[error("invalid tuner index or seek parameters")]
+    Invalid,
+    #[error("another seek is already in progress")]
+    Busy,
+    #[error("no station found")]
+    NoStation,
+    #[error("end of the band reached without finding a station, or non-blocking fd")]
+    EndReached,
+    #[error("error while switching the fd to blocking mode: {0}")]
+    BlockingMode(Errno),
+    #[error("ioctl error: {0}")]
+    IoctlError(Errno),
+}
+
+impl From<HwFreqSeekError> for Errno {
+    fn from(err: HwFreqSeekError) -> Self {
+        match err {
+            HwFreqSeekError::Invalid => Errno::EINVAL,
+            HwFreqSeekError::Busy => Errno::EBUSY,
+            HwFreqSeekError::NoStation => Errno::ENODATA,
+            HwFreqSeekError::EndReached => Errno::EAGAIN,
+            HwFreqSeekError::BlockingMode(e) => e,
+            HwFreqSeekError::IoctlError(e) => e,
+        }
+    }
+}
+
+impl AsErrno for HwFreqSeekError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            HwFreqSeekError::Invalid => Some(Errno::EINVAL),
+            HwFreqSeekError::Busy => Some(Errno::EBUSY),
+            HwFreqSeekError::NoStation => Some(Errno::ENODATA),
+            HwFreqSeekError::EndReached => Some(Errno::EAGAIN),
+            HwFreqSeekError::BlockingMode(e) => Some(*e),
+            HwFreqSeekError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
+/// Safe wrapper around the `VIDIOC_S_HW_FREQ_SEEK` ioctl.
+///
+/// Seeks a station from the current frequency of `tuner`, as returned by `VIDIOC_G_TUNER`, and
+/// returns once one has been found. The frequencies of `seek` are converted to the unit of the
+/// tuner.
+///
+/// If `fd` is in non-blocking mode, this fails with [`HwFreqSeekError::EndReached`] without
+/// seeking. See [`s_hw_freq_seek_blocking`].
+pub fn s_hw_freq_seek(
+    fd: &impl AsRawFd,
+    tuner: &Tuner,
+    seek: &HwFreqSeek,
+) -> Result<(), HwFreqSeekError> {
+    let seek = seek.to_v4l2(tuner);
+
+    match unsafe { ioctl::vidioc_s_hw_freq_seek(fd.as_raw_fd(), &seek) } {
+        Ok(_) => Ok(()),
+        Err(Errno::EINVAL) => Err(HwFreqSeekError::Invalid),
+        Err(Errno::EBUSY) => Err(HwFreqSeekError::Busy),
+        Err(Errno::ENODATA) => Err(HwFreqSeekError::NoStation),
+        Err(Errno::EAGAIN) => Err(HwFreqSeekError::EndReached),
+        Err(e) => Err(HwFreqSeekError::IoctlError(e)),
+    }
+}
+
+/// Same as [`s_hw_freq_seek`], but switches `fd` to blocking mode for the duration of the seek if
+/// it is in non-blocking mode, so [`HwFreqSeekError::EndReached`] can only mean that no station
+/// has been found.
+///
+/// The blocking mode is a property of the open file, so other file descriptors sharing it, e.g.
+/// obtained with `dup()`, are also blocking until this returns.
+pub fn s_hw_freq_seek_blocking(
+    fd: &impl AsRawFd,
+    tuner: &Tuner,
+    seek: &HwFreqSeek,
+) -> Result<(), HwFreqSeekError> {
+    let raw_fd = fd.as_raw_fd();
+    let flags = OFlag::from_bits_retain(
+        fcntl(raw_fd, FcntlArg::F_GETFL).map_err(HwFreqSeekError::BlockingMode)?,
+    );
+    if !flags.contains(OFlag::O_NONBLOCK) {
+        return s_hw_freq_seek(fd, tuner, seek);
+    }
+
+    fcntl(raw_fd, FcntlArg::F_SETFL(flags - OFlag::O_NONBLOCK))
+        .map_err(HwFreqSeekError::BlockingMode)?;
+    let res = s_hw_freq_seek(fd, tuner, seek);
+    fcntl(raw_fd, FcntlArg::F_SETFL(flags)).map_err(HwFreqSeekError::BlockingMode)?;
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioctl::backend::mock::MockIoctls;
+    use crate::ioctl::{TunerCapFlags, TunerTransmissionFlags, TunerType};
+
+    fn fm_tuner(capability: TunerCapFlags) -> Tuner {
+        Tuner {
+            index: 0,
+            name: "FM".to_string(),
+            type_: Some(TunerType::Radio),
+            capability,
+            rangelow: capability.hz_to_units(64_000_000),
+            rangehigh: capability.hz_to_units(108_000_000),
+            rxsubchans: TunerTransmissionFlags::STEREO,
+            audmode: None,
+            signal: 0,
+            afc: 0,
+        }
+    }
+
+    #[test]
+    fn test_tuner_units() {
+        let caps = TunerCapFlags::empty();
+        assert_eq!(caps.hz_to_units(100_000_000), 1600);
+        assert_eq!(caps.units_to_hz(1600), 100_000_000);
+
+        let caps = TunerCapFlags::LOW;
+        assert_eq!(caps.hz_to_units(100_000_000), 1_600_000);
+        assert_eq!(caps.units_to_hz(1_600_000), 100_000_000);
+
+        let caps = TunerCapFlags::LOW | TunerCapFlags::ONE_HZ;
+        assert_eq!(caps.hz_to_units(100_000_000), 100_000_000);
+        assert_eq!(caps.units_to_hz(100_000_000), 100_000_000);
+    }
+
+    #[test]
+    fn test_s_hw_freq_seek() {
+        let tuner = fm_tuner(TunerCapFlags::LOW | TunerCapFlags::HWSEEK_PROG_LIM);
+
+        let mock = MockIoctls::new();
+        mock.expect_with("vidioc_s_hw_freq_seek", |seek: &mut v4l2_hw_freq_seek| {
+            assert_eq!(seek.tuner, 0);
+            assert_eq!(seek.type_, TunerType::Radio as u32);
+            assert_eq!((seek.seek_upward, seek.wrap_around), (1, 1));
+            assert_eq!(seek.spacing, 100_000);
+            // 87.5 and 88 MHz in units of 62.5 Hz.
+            assert_eq!((seek.rangelow, seek.rangehigh), (1_400_000, 1_408_000));
+            Ok(0)
+        })
+        .expect_with("vidioc_s_hw_freq_seek", |seek: &mut v4l2_hw_freq_seek| {
+            assert_eq!((seek.seek_upward, seek.wrap_around), (0, 0));
+            assert_eq!((seek.spacing, seek.rangelow, seek.rangehigh), (0, 0, 0));
+            Err(Errno::ENODATA)
+        })
+        .expect("vidioc_s_hw_freq_seek", Err(Errno::EAGAIN))
+        .expect("vidioc_s_hw_freq_seek", Err(Errno::EBUSY));
+        let file = mock.file();
+
+        let seek = HwFreqSeek {
+            direction: SeekDirection::Up,
+            wrap_around: true,
+            spacing: 100_000,
+            band: Some((87_500_000, 88_000_000)),
+        };
+        s_hw_freq_seek(&file, &tuner, &seek).unwrap();
+
+        let seek = HwFreqSeek::new(SeekDirection::Down);
+        assert!(matches!(
+            s_hw_freq_seek(&file, &tuner, &seek),
+            Err(HwFreqSeekError::NoStation)
+        ));
+        assert!(matches!(
+            s_hw_freq_seek(&file, &tuner, &seek),
+            Err(HwFreqSeekError::EndReached)
+        ));
+        assert!(matches!(
+            s_hw_freq_seek(&file, &tuner, &seek),
+            Err(HwFreqSeekError::Busy)
+        ));
+        mock.assert_done();
+    }
+}
@@ -11,6 +11,8 @@ use log::error;
 use nix::{errno::Errno, libc::off_t, sys::mman};
 use thiserror::Error;
 
+use crate::error::AsErrno;
+
 pub struct PlaneMapping {
     // A mapping remains valid until we munmap it, that is, until the
     // PlaneMapping object is deleted. Hence the static lifetime.
@@ -92,6 +94,15 @@ impl From<MmapError> for Errno {
     }
 }
 
+impl AsErrno for MmapError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            MmapError::ZeroLength => None,
+            MmapError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 // TODO should be unsafe because the mapping can be used after a buffer is queued?
 // Or not, since this cannot cause a crash...
 pub fn mmap(fd: &impl AsFd, mem_offset: u32, length: u32) -> Result<PlaneMapping, MmapError> {
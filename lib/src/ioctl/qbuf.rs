@@ -9,13 +9,17 @@ use std::os::unix::prelude::RawFd;
 use thiserror::Error;
 
 use crate::bindings;
+use crate::error::AsErrno;
 use crate::ioctl::ioctl_and_convert;
+use crate::ioctl::timeval_from_nix;
 use crate::ioctl::BufferFlags;
 use crate::ioctl::IoctlConvertError;
 use crate::ioctl::IoctlConvertResult;
 use crate::ioctl::UncheckedV4l2Buffer;
 use crate::memory::Memory;
+use crate::memory::MemoryType;
 use crate::memory::PlaneHandle;
+use crate::Field;
 use crate::QueueType;
 
 #[derive(Debug, Error)]
@@ -24,6 +28,10 @@ pub enum QBufIoctlError {
     NumPlanesMismatch(usize, usize),
     #[error("data offset specified while using the single-planar API")]
     DataOffsetNotSupported,
+    #[error("explicit synchronization fences are not supported")]
+    FencesNotSupported,
+    #[error("invalid buffer: {0}")]
+    Invalid(#[from] QBufValidationError),
     #[error("unexpected ioctl error: {0}")]
     Other(Errno),
 }
@@ -39,11 +47,80 @@ impl From<QBufIoctlError> for Errno {
         match err {
             QBufIoctlError::NumPlanesMismatch(_, _) => Errno::EINVAL,
             QBufIoctlError::DataOffsetNotSupported => Errno::EINVAL,
+            QBufIoctlError::FencesNotSupported => Errno::ENOTSUP,
+            QBufIoctlError::Invalid(e) => e.into(),
             QBufIoctlError::Other(e) => e,
         }
     }
 }
 
+impl AsErrno for QBufIoctlError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            QBufIoctlError::NumPlanesMismatch(..) => None,
+            QBufIoctlError::DataOffsetNotSupported => None,
+            QBufIoctlError::FencesNotSupported => None,
+            QBufIoctlError::Invalid(_) => None,
+            QBufIoctlError::Other(e) => Some(*e),
+        }
+    }
+}
+
+/// Inconsistency detected in a buffer before queuing it, for which the driver would otherwise
+/// return an error without indication of what is wrong.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum QBufValidationError {
+    #[error("{got} planes specified, but the format of the queue has {expected}")]
+    NumPlanes { got: usize, expected: usize },
+    #[error("bytesused of plane {plane} ({bytes_used}) is larger than its length ({length})")]
+    BytesUsedExceedsLength {
+        plane: usize,
+        bytes_used: u32,
+        length: u32,
+    },
+    #[error("bytesused of plane {plane} is 0 on an output queue")]
+    NoBytesUsed { plane: usize },
+    #[error("field {field:?} is invalid for the {format:?} field order of the queue")]
+    Field { field: Field, format: Field },
+    #[error("the queue does not support requests")]
+    RequestsNotSupported,
+    #[error("invalid request file descriptor {0}")]
+    InvalidRequestFd(RawFd),
+    #[error("buffers have been queued with a request since streaming on, but none is set")]
+    RequestRequired,
+    #[error("buffers have been queued without a request since streaming on, but one is set")]
+    RequestNotExpected,
+}
+
+impl QBufValidationError {
+    /// Returns the name of the `struct v4l2_buffer` member that failed validation.
+    pub fn field_name(&self) -> &'static str {
+        match self {
+            QBufValidationError::NumPlanes { .. } => "length",
+            QBufValidationError::BytesUsedExceedsLength { .. }
+            | QBufValidationError::NoBytesUsed { .. } => "bytesused",
+            QBufValidationError::Field { .. } => "field",
+            QBufValidationError::RequestsNotSupported
+            | QBufValidationError::InvalidRequestFd(_)
+            | QBufValidationError::RequestRequired
+            | QBufValidationError::RequestNotExpected => "request_fd",
+        }
+    }
+}
+
+/// Returns the error the driver would have returned for the same buffer.
+impl From<QBufValidationError> for Errno {
+    fn from(err: QBufValidationError) -> Self {
+        match err {
+            QBufValidationError::RequestsNotSupported => Errno::EBADR,
+            QBufValidationError::RequestRequired | QBufValidationError::RequestNotExpected => {
+                Errno::EBUSY
+            }
+            _ => Errno::EINVAL,
+        }
+    }
+}
+
 /// Representation of a single plane of a V4L2 buffer.
 pub struct QBufPlane(pub bindings::v4l2_plane);
 
@@ -88,6 +165,8 @@ pub struct QBuffer<H: PlaneHandle> {
     pub timestamp: TimeVal,
     pub planes: Vec<QBufPlane>,
     pub request: Option<RawFd>,
+    pub in_fence: Option<RawFd>,
+    pub out_fence: bool,
     pub _h: std::marker::PhantomData<H>,
 }
 
@@ -102,6 +181,8 @@ impl<H: PlaneHandle> QBuffer<H> {
             timestamp: TimeVal::zero(),
             planes: Vec::new(),
             request: None,
+            in_fence: None,
+            out_fence: false,
             _h: std::marker::PhantomData,
         }
     }
@@ -118,6 +199,35 @@ impl<H: PlaneHandle> QBuffer<H> {
         self.flags |= BufferFlags::REQUEST_FD;
         self
     }
+
+    /// Makes the driver wait for `fd`, a sync file, to be signaled before using the buffer.
+    ///
+    /// Returns [`QBufIoctlError::FencesNotSupported`] if the kernel headers this crate has been
+    /// built against do not support fences. Whether the driver supports them can be checked with
+    /// [`BufferCapabilities::supports_fences`](crate::ioctl::BufferCapabilities::supports_fences).
+    pub fn set_in_fence(mut self, fd: RawFd) -> Result<Self, QBufIoctlError> {
+        if !crate::has_fence_support() {
+            return Err(QBufIoctlError::FencesNotSupported);
+        }
+
+        self.in_fence = Some(fd);
+        Ok(self)
+    }
+
+    /// Asks the driver for an out-fence signaled once it is done with the buffer. It can be
+    /// retrieved using [`V4l2Buffer::out_fence`](crate::ioctl::V4l2Buffer::out_fence) on the
+    /// buffer returned by [`qbuf`] or [`dqbuf`](crate::ioctl::dqbuf).
+    ///
+    /// Returns [`QBufIoctlError::FencesNotSupported`] if the kernel headers this crate has been
+    /// built against do not support fences.
+    pub fn request_out_fence(mut self) -> Result<Self, QBufIoctlError> {
+        if !crate::has_fence_support() {
+            return Err(QBufIoctlError::FencesNotSupported);
+        }
+
+        self.out_fence = true;
+        Ok(self)
+    }
 }
 
 impl<H: PlaneHandle> From<QBuffer<H>> for UncheckedV4l2Buffer {
@@ -129,11 +239,20 @@ impl<H: PlaneHandle> From<QBuffer<H>> for UncheckedV4l2Buffer {
         v4l2_buf.0.flags = qbuf.flags.bits();
         v4l2_buf.0.field = qbuf.field;
         v4l2_buf.0.sequence = qbuf.sequence;
-        v4l2_buf.0.timestamp.tv_sec = qbuf.timestamp.tv_sec();
-        v4l2_buf.0.timestamp.tv_usec = qbuf.timestamp.tv_usec();
+        v4l2_buf.0.timestamp = timeval_from_nix(qbuf.timestamp);
         if let Some(request) = &qbuf.request {
             v4l2_buf.0.__bindgen_anon_1.request_fd = *request;
         }
+        #[cfg(v4l2r_has_fences)]
+        {
+            if let Some(fence) = qbuf.in_fence {
+                v4l2_buf.0.flags |= bindings::V4L2_BUF_FLAG_IN_FENCE;
+                crate::ioctl::set_fence_fd(&mut v4l2_buf.0, fence);
+            }
+            if qbuf.out_fence {
+                v4l2_buf.0.flags |= bindings::V4L2_BUF_FLAG_OUT_FENCE;
+            }
+        }
         if let Some(planes) = &mut v4l2_buf.1 {
             for (dst_plane, src_plane) in planes.iter_mut().zip(qbuf.planes.into_iter()) {
                 *dst_plane = src_plane.0;
@@ -153,10 +272,8 @@ impl<H: PlaneHandle> From<QBuffer<H>> for UncheckedV4l2Buffer {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_buffer;
-    nix::ioctl_readwrite!(vidioc_querybuf, b'V', 9, v4l2_buffer);
-    nix::ioctl_readwrite!(vidioc_qbuf, b'V', 15, v4l2_buffer);
-    nix::ioctl_readwrite!(vidioc_dqbuf, b'V', 17, v4l2_buffer);
-    nix::ioctl_readwrite!(vidioc_prepare_buf, b'V', 93, v4l2_buffer);
+    ioctl_readwrite!(vidioc_qbuf, b'V', 15, v4l2_buffer);
+    ioctl_readwrite!(vidioc_prepare_buf, b'V', 93, v4l2_buffer);
 }
 
 pub type QBufError<CE> = IoctlConvertError<QBufIoctlError, CE>;
@@ -184,12 +301,18 @@ where
     O::Error: std::fmt::Debug,
 {
     let mut v4l2_buf: UncheckedV4l2Buffer = buffer.into();
+    let _span = ioctl_span!(
+        "VIDIOC_QBUF",
+        queue = ?crate::trace::RawQueueType(v4l2_buf.0.type_),
+        index = v4l2_buf.0.index,
+        flags = v4l2_buf.0.flags,
+        bytesused = crate::trace::bytes_used(&v4l2_buf)
+    );
+
+    let res = unsafe { ioctl::vidioc_qbuf(fd.as_raw_fd(), v4l2_buf.as_mut()) };
+    record_errno!(_span, res);
 
-    ioctl_and_convert(
-        unsafe { ioctl::vidioc_qbuf(fd.as_raw_fd(), v4l2_buf.as_mut()) }
-            .map(|_| v4l2_buf)
-            .map_err(Into::into),
-    )
+    ioctl_and_convert(res.map(|_| v4l2_buf).map_err(Into::into))
 }
 
 /// Safe wrapper around the `VIDIOC_PREPARE_BUF` ioctl.
@@ -200,10 +323,162 @@ where
     O::Error: std::fmt::Debug,
 {
     let mut v4l2_buf: UncheckedV4l2Buffer = buffer.into();
+    let _span = ioctl_span!(
+        "VIDIOC_PREPARE_BUF",
+        queue = ?crate::trace::RawQueueType(v4l2_buf.0.type_),
+        index = v4l2_buf.0.index,
+        flags = v4l2_buf.0.flags,
+        bytesused = crate::trace::bytes_used(&v4l2_buf)
+    );
+
+    let res = unsafe { ioctl::vidioc_prepare_buf(fd.as_raw_fd(), v4l2_buf.as_mut()) };
+    record_errno!(_span, res);
+
+    ioctl_and_convert(res.map(|_| v4l2_buf).map_err(Into::into))
+}
+
+#[derive(Debug, Error)]
+#[error("failed to prepare buffer {index}: {error}")]
+pub struct PrepareAllBufsError {
+    /// Index of the first buffer that could not be prepared.
+    pub index: u32,
+    pub error: Errno,
+}
 
-    ioctl_and_convert(
-        unsafe { ioctl::vidioc_prepare_buf(fd.as_raw_fd(), v4l2_buf.as_mut()) }
-            .map(|_| v4l2_buf)
-            .map_err(Into::into),
-    )
+impl AsErrno for PrepareAllBufsError {
+    fn errno(&self) -> Option<Errno> {
+        Some(self.error)
+    }
+}
+
+/// Calls `VIDIOC_PREPARE_BUF` on the `MMAP` buffers `0..count` of `queue`.
+///
+/// Preparing buffers is optional, but lets the driver perform the work needed to queue them (e.g.
+/// cache maintenance) before streaming starts instead of when they are first queued, which reduces
+/// the latency of the first frames.
+///
+/// No backing memory is passed to the driver, so this only works for `MMAP` buffers. `USERPTR`
+/// and `DMABUF` buffers must be prepared one by one using [`prepare_buf`] with their handles.
+///
+/// All the buffers are attempted even if some fail, and the first failure is returned.
+pub fn prepare_all_bufs(
+    fd: &impl AsRawFd,
+    queue: QueueType,
+    count: u32,
+) -> Result<(), PrepareAllBufsError> {
+    let mut first_error = None;
+
+    for index in 0..count {
+        let mut v4l2_buf = UncheckedV4l2Buffer::new_for_querybuf(queue, Some(index));
+        v4l2_buf.0.memory = MemoryType::Mmap as u32;
+        let _span = ioctl_span!("VIDIOC_PREPARE_BUF", queue = ?queue, index = index);
+
+        let res = unsafe { ioctl::vidioc_prepare_buf(fd.as_raw_fd(), v4l2_buf.as_mut()) };
+        record_errno!(_span, res);
+
+        if let Err(error) = res {
+            first_error.get_or_insert(PrepareAllBufsError { index, error });
+        }
+    }
+
+    match first_error {
+        None => Ok(()),
+        Some(error) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::{prepare_all_bufs, QBuffer};
+    use crate::bindings::v4l2_buffer;
+    use crate::ioctl::backend::mock::MockIoctls;
+    use crate::ioctl::{BufferFlags, V4l2Buffer};
+    use crate::memory::{MemoryType, MmapHandle};
+    use crate::QueueType;
+
+    #[test]
+    fn prepare_all_bufs_reports_first_error() {
+        let mock = MockIoctls::new();
+        for expected_index in 0..4 {
+            mock.expect_with("vidioc_prepare_buf", move |buf: &mut v4l2_buffer| {
+                assert_eq!(buf.index, expected_index);
+                assert_eq!(buf.type_, QueueType::VideoCaptureMplane as u32);
+                assert_eq!(buf.memory, MemoryType::Mmap as u32);
+                match expected_index {
+                    1 => Err(Errno::EINVAL),
+                    2 => Err(Errno::ENOMEM),
+                    _ => Ok(0),
+                }
+            });
+        }
+        let file = mock.file();
+
+        // Buffers following a failed one are still prepared.
+        let err = prepare_all_bufs(&file, QueueType::VideoCaptureMplane, 4).unwrap_err();
+        assert_eq!((err.index, err.error), (1, Errno::EINVAL));
+        mock.assert_done();
+
+        assert!(prepare_all_bufs(&file, QueueType::VideoCapture, 0).is_ok());
+    }
+
+    #[cfg(not(v4l2r_has_fences))]
+    #[test]
+    fn fences_unsupported() {
+        use super::QBufIoctlError;
+        use crate::error::AsErrno;
+        use crate::ioctl::{BufferCapabilities, UncheckedV4l2Buffer};
+
+        assert!(!crate::has_fence_support());
+        assert!(!BufferCapabilities::all().supports_fences());
+
+        let err = QBuffer::<MmapHandle>::new(QueueType::VideoOutputMplane, 0)
+            .set_in_fence(3)
+            .unwrap_err();
+        assert!(matches!(err, QBufIoctlError::FencesNotSupported));
+        assert_eq!(err.errno(), None);
+        assert_eq!(Errno::from(err), Errno::ENOTSUP);
+
+        let err = QBuffer::<MmapHandle>::new(QueueType::VideoCaptureMplane, 0)
+            .request_out_fence()
+            .unwrap_err();
+        assert!(matches!(err, QBufIoctlError::FencesNotSupported));
+
+        // Whatever the driver leaves in the fence slot, no out-fence is ever reported.
+        let mut v4l2_buf = UncheckedV4l2Buffer::new_for_querybuf(QueueType::VideoCapture, Some(0));
+        v4l2_buf.0.memory = MemoryType::Mmap as u32;
+        v4l2_buf.0.flags = BufferFlags::all().bits();
+        v4l2_buf.0.reserved2 = 3;
+        let buffer = V4l2Buffer::try_from(v4l2_buf).unwrap();
+        assert_eq!(buffer.out_fence(), None);
+    }
+
+    #[cfg(v4l2r_has_fences)]
+    #[test]
+    fn fences_passed_to_qbuf() {
+        use super::qbuf;
+        use crate::ioctl::{fence_fd, set_fence_fd, QBufPlane};
+
+        let mock = MockIoctls::new();
+        mock.expect_with("vidioc_qbuf", |buf: &mut v4l2_buffer| {
+            let flags = BufferFlags::from_bits_retain(buf.flags);
+            assert!(flags.contains(BufferFlags::IN_FENCE | BufferFlags::OUT_FENCE));
+            assert_eq!(fence_fd(buf), 3);
+            // The driver returns the out-fence in place of the in-fence.
+            set_fence_fd(buf, 4);
+            Ok(0)
+        });
+        let file = mock.file();
+
+        let mut qbuffer = QBuffer::<MmapHandle>::new(QueueType::VideoOutput, 0)
+            .set_in_fence(3)
+            .unwrap()
+            .request_out_fence()
+            .unwrap();
+        qbuffer.planes.push(QBufPlane::new(0));
+        let buffer: V4l2Buffer = qbuf(&file, qbuffer).unwrap();
+        assert_eq!(buffer.out_fence(), Some(4));
+        mock.assert_done();
+    }
 }
@@ -5,6 +5,10 @@ use std::os::unix::io::AsRawFd;
 use nix::errno::Errno;
 use thiserror::Error;
 
+use crate::bindings;
+use crate::bindings::v4l2_buffer;
+use crate::bindings::v4l2_plane;
+use crate::error::AsErrno;
 use crate::ioctl::ioctl_and_convert;
 use crate::ioctl::BufferFlags;
 use crate::ioctl::IoctlConvertError;
@@ -65,7 +69,7 @@ impl TryFrom<UncheckedV4l2Buffer> for QueryBuffer {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_buffer;
-    nix::ioctl_readwrite!(vidioc_querybuf, b'V', 9, v4l2_buffer);
+    ioctl_readwrite!(vidioc_querybuf, b'V', 9, v4l2_buffer);
 }
 
 #[derive(Debug, Error)]
@@ -94,6 +98,15 @@ impl From<QueryBufIoctlError> for Errno {
     }
 }
 
+impl AsErrno for QueryBufIoctlError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            QueryBufIoctlError::InvalidInput => Some(Errno::EINVAL),
+            QueryBufIoctlError::Other(e) => Some(*e),
+        }
+    }
+}
+
 pub type QueryBufError<CE> = IoctlConvertError<QueryBufIoctlError, CE>;
 pub type QueryBufResult<O, CE> = IoctlConvertResult<O, QueryBufIoctlError, CE>;
 
@@ -111,3 +124,140 @@ where
             .map_err(Into::into),
     )
 }
+
+/// A multi-planar `v4l2_buffer` along with the array of planes its `m.planes` member points to.
+///
+/// Keeping both together guarantees that the planes pointer of the buffer remains valid for as
+/// long as the buffer can be accessed.
+pub struct MplaneBuf {
+    buffer: v4l2_buffer,
+    planes: Vec<v4l2_plane>,
+}
+
+impl MplaneBuf {
+    /// Returns a new buffer for `queue` and `index`, with room for `num_planes` planes.
+    ///
+    /// `num_planes` must be between 1 and `VIDEO_MAX_PLANES`, otherwise
+    /// [`QueryBufIoctlError::InvalidInput`] is returned.
+    pub fn new(queue: QueueType, index: u32, num_planes: u8) -> Result<Self, QueryBufIoctlError> {
+        let num_planes = num_planes as u32;
+        if num_planes == 0 || num_planes > bindings::VIDEO_MAX_PLANES {
+            return Err(QueryBufIoctlError::InvalidInput);
+        }
+        let mut planes: Vec<v4l2_plane> = vec![Default::default(); num_planes as usize];
+        let mut buffer = v4l2_buffer {
+            index,
+            type_: queue as u32,
+            length: num_planes,
+            ..Default::default()
+        };
+        buffer.m.planes = planes.as_mut_ptr();
+
+        Ok(MplaneBuf { buffer, planes })
+    }
+
+    /// Returns the buffer. Its `m.planes` member points to [`MplaneBuf::planes`].
+    pub fn buffer(&self) -> &v4l2_buffer {
+        &self.buffer
+    }
+
+    /// Returns the planes of the buffer.
+    pub fn planes(&self) -> &[v4l2_plane] {
+        &self.planes
+    }
+
+    /// Splits the buffer from its planes.
+    ///
+    /// The `m.planes` member of the returned buffer points into the returned `Vec`. It remains
+    /// valid as long as the `Vec` is neither dropped nor resized, which is up to the caller to
+    /// guarantee before passing the buffer to the kernel or dereferencing the pointer.
+    pub fn into_parts(self) -> (v4l2_buffer, Vec<v4l2_plane>) {
+        (self.buffer, self.planes)
+    }
+}
+
+impl AsMut<v4l2_buffer> for MplaneBuf {
+    fn as_mut(&mut self) -> &mut v4l2_buffer {
+        self.buffer.m.planes = self.planes.as_mut_ptr();
+        &mut self.buffer
+    }
+}
+
+/// Safe wrapper around the `VIDIOC_QUERYBUF` ioctl for multi-planar queues, which returns the
+/// raw buffer along with its `num_planes` planes.
+///
+/// On success, the planes are truncated to the number of planes reported by the driver.
+pub fn querybuf_mplane(
+    fd: &impl AsRawFd,
+    queue: QueueType,
+    index: usize,
+    num_planes: u8,
+) -> Result<MplaneBuf, QueryBufIoctlError> {
+    if !queue.is_multiplanar() {
+        return Err(QueryBufIoctlError::InvalidInput);
+    }
+
+    let mut buf = MplaneBuf::new(queue, index as u32, num_planes)?;
+    unsafe { ioctl::vidioc_querybuf(fd.as_raw_fd(), buf.as_mut()) }?;
+    let length = (buf.buffer.length as usize).min(buf.planes.len());
+    buf.planes.truncate(length);
+
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::{querybuf_mplane, MplaneBuf, QueryBufIoctlError};
+    use crate::bindings;
+    use crate::ioctl::backend::mock::MockIoctls;
+    use crate::QueueType;
+
+    #[test]
+    fn mplane_buf() {
+        let buf = MplaneBuf::new(QueueType::VideoCaptureMplane, 2, 3).unwrap();
+        assert_eq!(buf.buffer().index, 2);
+        assert_eq!(buf.buffer().type_, QueueType::VideoCaptureMplane as u32);
+        assert_eq!(buf.buffer().length, 3);
+        assert_eq!(buf.planes().len(), 3);
+        assert_eq!(
+            unsafe { buf.buffer().m.planes } as *const _,
+            buf.planes().as_ptr()
+        );
+
+        // The pointer remains valid after splitting the buffer from its planes.
+        let (buffer, planes) = buf.into_parts();
+        assert_eq!(unsafe { buffer.m.planes } as *const _, planes.as_ptr());
+        assert_eq!(planes.len(), 3);
+
+        // The number of planes is not adjusted silently.
+        for num_planes in [0, bindings::VIDEO_MAX_PLANES as u8 + 1] {
+            assert!(matches!(
+                MplaneBuf::new(QueueType::VideoCaptureMplane, 0, num_planes),
+                Err(QueryBufIoctlError::InvalidInput)
+            ));
+        }
+    }
+
+    #[test]
+    fn querybuf_mplane_errors() {
+        let mock = MockIoctls::new();
+        mock.expect("vidioc_querybuf", Err(Errno::ENOTTY));
+        let null = mock.file();
+
+        assert!(matches!(
+            querybuf_mplane(&null, QueueType::VideoCapture, 0, 1),
+            Err(QueryBufIoctlError::InvalidInput)
+        ));
+        assert!(matches!(
+            querybuf_mplane(&null, QueueType::VideoCaptureMplane, 0, 0),
+            Err(QueryBufIoctlError::InvalidInput)
+        ));
+        assert!(matches!(
+            querybuf_mplane(&null, QueueType::VideoCaptureMplane, 0, 2),
+            Err(QueryBufIoctlError::Other(Errno::ENOTTY))
+        ));
+        mock.assert_done();
+    }
+}
@@ -1,7 +1,8 @@
 //! Safe wrapper for the `VIDIOC_QUERYCAP` ioctl.
-use super::string_from_cstr;
+use super::{bytes_until_nul, string_from_cstr};
 use crate::bindings;
 use crate::bindings::v4l2_capability;
+use crate::error::AsErrno;
 use bitflags::bitflags;
 use nix::errno::Errno;
 use std::fmt;
@@ -65,7 +66,11 @@ impl From<v4l2_capability> for Capabilities {
 }
 
 /// Safe variant of the `v4l2_capability` struct, to be used with `querycap`.
-#[derive(Debug)]
+///
+/// The raw bytes of the driver strings are kept alongside their lossy `String` conversions, so
+/// this struct has private fields and cannot be built with a struct literal. Use
+/// [`Capability::new`], or convert a `v4l2_capability`, instead.
+#[derive(Clone, Debug)]
 pub struct Capability {
     pub driver: String,
     pub card: String,
@@ -73,14 +78,71 @@ pub struct Capability {
     pub version: u32,
     pub capabilities: Capabilities,
     pub device_caps: Option<Capabilities>,
+    raw_driver: [u8; 16],
+    raw_card: [u8; 32],
+    raw_bus_info: [u8; 32],
+}
+
+/// Returns `string` as a nul-terminated array, truncated if needed.
+fn cstr_array<const N: usize>(string: &str) -> [u8; N] {
+    let mut array = [0u8; N];
+    let len = string.len().min(N - 1);
+    array[..len].copy_from_slice(&string.as_bytes()[..len]);
+    array
 }
 
 impl Capability {
+    /// Create a capability from its members, e.g. to emulate a device. The strings are truncated
+    /// to the size of the corresponding `v4l2_capability` members.
+    pub fn new(
+        driver: &str,
+        card: &str,
+        bus_info: &str,
+        version: u32,
+        capabilities: Capabilities,
+        device_caps: Option<Capabilities>,
+    ) -> Self {
+        let raw_driver = cstr_array(driver);
+        let raw_card = cstr_array(card);
+        let raw_bus_info = cstr_array(bus_info);
+
+        Capability {
+            driver: string_from_cstr(&raw_driver),
+            card: string_from_cstr(&raw_card),
+            bus_info: string_from_cstr(&raw_bus_info),
+            version,
+            capabilities,
+            device_caps,
+            raw_driver,
+            raw_card,
+            raw_bus_info,
+        }
+    }
+
     /// Returns the set of capabilities of the hardware as a whole.
     pub fn capabilities(&self) -> Capabilities {
         self.capabilities
     }
 
+    /// Returns the bytes of the `driver` member, as reported by the driver and without the
+    /// terminating nul character.
+    pub fn driver_bytes(&self) -> &[u8] {
+        bytes_until_nul(&self.raw_driver)
+    }
+
+    /// Returns the bytes of the `card` member, as reported by the driver and without the
+    /// terminating nul character.
+    pub fn card_bytes(&self) -> &[u8] {
+        bytes_until_nul(&self.raw_card)
+    }
+
+    /// Returns the bytes of the `bus_info` member, as reported by the driver and without the
+    /// terminating nul character. Use this rather than [`Capability::bus_info`] when an exact
+    /// match is needed, e.g. to identify a device.
+    pub fn bus_info_bytes(&self) -> &[u8] {
+        bytes_until_nul(&self.raw_bus_info)
+    }
+
     /// Returns the capabilities that apply to the currently opened V4L2 node.
     pub fn device_caps(&self) -> Capabilities {
         self.device_caps
@@ -91,9 +153,9 @@ impl Capability {
 impl From<v4l2_capability> for Capability {
     fn from(qcap: v4l2_capability) -> Self {
         Capability {
-            driver: string_from_cstr(&qcap.driver).unwrap_or_else(|_| "".into()),
-            card: string_from_cstr(&qcap.card).unwrap_or_else(|_| "".into()),
-            bus_info: string_from_cstr(&qcap.bus_info).unwrap_or_else(|_| "".into()),
+            driver: string_from_cstr(&qcap.driver),
+            card: string_from_cstr(&qcap.card),
+            bus_info: string_from_cstr(&qcap.bus_info),
             version: qcap.version,
             capabilities: Capabilities::from_bits_truncate(qcap.capabilities),
             device_caps: if qcap.capabilities & bindings::V4L2_CAP_DEVICE_CAPS != 0 {
@@ -101,6 +163,9 @@ impl From<v4l2_capability> for Capability {
             } else {
                 None
             },
+            raw_driver: qcap.driver,
+            raw_card: qcap.card,
+            raw_bus_info: qcap.bus_info,
         }
     }
 }
@@ -108,7 +173,7 @@ impl From<v4l2_capability> for Capability {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_capability;
-    nix::ioctl_read!(vidioc_querycap, b'V', 0, v4l2_capability);
+    ioctl_read!(vidioc_querycap, b'V', 0, v4l2_capability);
 }
 
 #[derive(Debug, Error)]
@@ -125,6 +190,14 @@ impl From<QueryCapError> for Errno {
     }
 }
 
+impl AsErrno for QueryCapError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            QueryCapError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_QUERYCAP` ioctl.
 pub fn querycap<T: From<v4l2_capability>>(fd: &impl AsRawFd) -> Result<T, QueryCapError> {
     let mut qcap: v4l2_capability = Default::default();
@@ -134,3 +207,50 @@ pub fn querycap<T: From<v4l2_capability>>(fd: &impl AsRawFd) -> Result<T, QueryC
         Err(e) => Err(QueryCapError::IoctlError(e)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Capability;
+    use crate::bindings::v4l2_capability;
+
+    #[test]
+    fn capability_strings() {
+        let mut qcap = v4l2_capability::default();
+        qcap.driver.copy_from_slice(b"0123456789abcdef");
+        qcap.card[..9].copy_from_slice(b"Cam\xe9ra  \0");
+        qcap.bus_info[..12].copy_from_slice(b"usb-1\0junk\0\0");
+
+        let cap = Capability::from(qcap);
+        // Not nul-terminated.
+        assert_eq!(cap.driver, "0123456789abcdef");
+        assert_eq!(cap.driver_bytes(), b"0123456789abcdef");
+        // Latin-1 and trailing whitespace.
+        assert_eq!(cap.card, "Cam\u{fffd}ra");
+        assert_eq!(cap.card_bytes(), b"Cam\xe9ra  ");
+        // Embedded nul.
+        assert_eq!(cap.bus_info, "usb-1");
+        assert_eq!(cap.bus_info_bytes(), b"usb-1");
+    }
+
+    #[test]
+    fn capability_new() {
+        use super::Capabilities;
+
+        let caps = Capabilities::VIDEO_CAPTURE | Capabilities::STREAMING;
+        let cap = Capability::new(
+            "vivid",
+            "a card name that is longer than 32 bytes",
+            "platform:vivid-000",
+            0x60000,
+            caps | Capabilities::DEVICE_CAPS,
+            Some(caps),
+        );
+        assert_eq!(cap.driver, "vivid");
+        assert_eq!(cap.driver_bytes(), b"vivid");
+        // Truncated so the terminating nul fits.
+        assert_eq!(cap.card, "a card name that is longer than");
+        assert_eq!(cap.card_bytes().len(), 31);
+        assert_eq!(cap.bus_info_bytes(), b"platform:vivid-000");
+        assert_eq!(cap.device_caps().bits(), caps.bits());
+    }
+}
@@ -2,12 +2,14 @@
 use std::os::unix::io::AsRawFd;
 
 use bitflags::bitflags;
+use log::error;
 use nix::errno::Errno;
 use thiserror::Error;
 
 use crate::bindings;
 use crate::bindings::v4l2_query_ext_ctrl;
 use crate::bindings::v4l2_queryctrl;
+use crate::error::AsErrno;
 
 /// Index of a control that has been validated, i.e. which ID is within the range of
 /// `V4L2_CTRL_ID_MASK`.
@@ -39,6 +41,26 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Flags of a control, as returned in the `flags` member of `v4l2_queryctrl` and
+    /// `v4l2_query_ext_ctrl`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct ControlFlags: u32 {
+        const DISABLED = bindings::V4L2_CTRL_FLAG_DISABLED;
+        const GRABBED = bindings::V4L2_CTRL_FLAG_GRABBED;
+        const READ_ONLY = bindings::V4L2_CTRL_FLAG_READ_ONLY;
+        const UPDATE = bindings::V4L2_CTRL_FLAG_UPDATE;
+        const INACTIVE = bindings::V4L2_CTRL_FLAG_INACTIVE;
+        const SLIDER = bindings::V4L2_CTRL_FLAG_SLIDER;
+        const WRITE_ONLY = bindings::V4L2_CTRL_FLAG_WRITE_ONLY;
+        const VOLATILE = bindings::V4L2_CTRL_FLAG_VOLATILE;
+        const HAS_PAYLOAD = bindings::V4L2_CTRL_FLAG_HAS_PAYLOAD;
+        const EXECUTE_ON_WRITE = bindings::V4L2_CTRL_FLAG_EXECUTE_ON_WRITE;
+        const MODIFY_LAYOUT = bindings::V4L2_CTRL_FLAG_MODIFY_LAYOUT;
+        const DYNAMIC_ARRAY = bindings::V4L2_CTRL_FLAG_DYNAMIC_ARRAY;
+    }
+}
+
 /// Decompose a u32 between its control ID and query flags parts.
 pub fn parse_ctrl_id_and_flags(ctrl: u32) -> (CtrlId, QueryCtrlFlags) {
     (
@@ -50,10 +72,10 @@ pub fn parse_ctrl_id_and_flags(ctrl: u32) -> (CtrlId, QueryCtrlFlags) {
 #[doc(hidden)]
 mod ioctl {
     use crate::bindings::v4l2_queryctrl;
-    nix::ioctl_readwrite!(vidioc_queryctrl, b'V', 36, v4l2_queryctrl);
+    ioctl_readwrite!(vidioc_queryctrl, b'V', 36, v4l2_queryctrl);
 
     use crate::bindings::v4l2_query_ext_ctrl;
-    nix::ioctl_readwrite!(vidioc_query_ext_ctrl, b'V', 103, v4l2_query_ext_ctrl);
+    ioctl_readwrite!(vidioc_query_ext_ctrl, b'V', 103, v4l2_query_ext_ctrl);
 }
 
 #[derive(Debug, Error)]
@@ -70,6 +92,14 @@ impl From<QueryCtrlError> for Errno {
     }
 }
 
+impl AsErrno for QueryCtrlError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            QueryCtrlError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_QUERYCTRL` ioctl.
 pub fn queryctrl<T: From<v4l2_queryctrl>>(
     fd: &impl AsRawFd,
@@ -104,9 +134,90 @@ pub fn query_ext_ctrl<T: From<v4l2_query_ext_ctrl>>(
     }
 }
 
+/// Iterator over all the controls of a device, including compound ones, in ID order.
+///
+/// Each item is the `v4l2_query_ext_ctrl` returned by the driver, which includes the control
+/// classes (controls of type `V4L2_CTRL_TYPE_CTRL_CLASS`) grouping the following controls.
+pub struct ControlIterator<'a, F: AsRawFd> {
+    fd: &'a F,
+    last_id: u32,
+    done: bool,
+}
+
+impl<'a, F: AsRawFd> ControlIterator<'a, F> {
+    pub fn new(fd: &'a F) -> Self {
+        ControlIterator {
+            fd,
+            last_id: 0,
+            done: false,
+        }
+    }
+}
+
+impl<'a, F: AsRawFd> Iterator for ControlIterator<'a, F> {
+    type Item = v4l2_query_ext_ctrl;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match query_ext_ctrl::<v4l2_query_ext_ctrl>(
+            self.fd,
+            CtrlId(self.last_id),
+            QueryCtrlFlags::NEXT | QueryCtrlFlags::COMPOUND,
+        ) {
+            Ok(qctrl) => {
+                self.last_id = qctrl.id;
+                Some(qctrl)
+            }
+            // EINVAL means we have reached the last control.
+            Err(QueryCtrlError::IoctlError(Errno::EINVAL)) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                error!("Unexpected return value for VIDIOC_QUERY_EXT_CTRL: {}", e);
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ioctl::backend::mock::MockIoctls;
+
+    #[test]
+    fn test_control_iterator() {
+        let mock = MockIoctls::new();
+        for id in [bindings::V4L2_CID_USER_CLASS, bindings::V4L2_CID_BRIGHTNESS] {
+            mock.expect_with(
+                "vidioc_query_ext_ctrl",
+                move |q: &mut v4l2_query_ext_ctrl| {
+                    // The next control is queried after the one returned by the previous call.
+                    assert!(q.id & bindings::V4L2_CTRL_FLAG_NEXT_CTRL != 0);
+                    assert!(q.id & bindings::V4L2_CTRL_FLAG_NEXT_COMPOUND != 0);
+                    assert!(q.id & bindings::V4L2_CTRL_ID_MASK < id);
+                    q.id = id;
+                    Ok(0)
+                },
+            );
+        }
+        mock.expect("vidioc_query_ext_ctrl", Err(Errno::EINVAL));
+        let file = mock.file();
+
+        let ids = ControlIterator::new(&file)
+            .map(|qctrl| qctrl.id)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            [bindings::V4L2_CID_USER_CLASS, bindings::V4L2_CID_BRIGHTNESS]
+        );
+        mock.assert_done();
+    }
 
     #[test]
     fn test_ctrlid() {
@@ -3,6 +3,7 @@ use crate::bindings;
 use crate::bindings::v4l2_create_buffers;
 use crate::bindings::v4l2_format;
 use crate::bindings::v4l2_requestbuffers;
+use crate::error::AsErrno;
 use crate::memory::MemoryType;
 use crate::QueueType;
 use bitflags::bitflags;
@@ -21,6 +22,25 @@ bitflags! {
         const SUPPORTS_REQUESTS = bindings::V4L2_BUF_CAP_SUPPORTS_REQUESTS;
         const SUPPORTS_ORPHANED_BUFS = bindings::V4L2_BUF_CAP_SUPPORTS_ORPHANED_BUFS;
         //const SUPPORTS_M2M_HOLD_CAPTURE_BUF = bindings::V4L2_BUF_CAP_SUPPORTS_M2M_HOLD_CAPTURE_BUF;
+        #[cfg(v4l2r_has_fences)]
+        const SUPPORTS_FENCES = bindings::V4L2_BUF_CAP_SUPPORTS_FENCES;
+    }
+}
+
+impl BufferCapabilities {
+    /// Returns whether the queue accepts explicit synchronization fences along its buffers.
+    ///
+    /// Always `false` if the kernel headers this crate has been built against do not support
+    /// fences, see [`crate::has_fence_support`].
+    pub fn supports_fences(&self) -> bool {
+        #[cfg(v4l2r_has_fences)]
+        {
+            self.contains(BufferCapabilities::SUPPORTS_FENCES)
+        }
+        #[cfg(not(v4l2r_has_fences))]
+        {
+            false
+        }
     }
 }
 
@@ -43,6 +63,34 @@ impl From<v4l2_requestbuffers> for BufferCapabilities {
     }
 }
 
+/// Number of buffers actually granted by the driver, which may differ from the number of
+/// buffers that has been requested.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BufferCount(pub u32);
+
+impl BufferCount {
+    /// Minimum number of buffers required to stream without stalling, i.e. one buffer owned by
+    /// the driver and one owned by the client.
+    pub const MIN_STREAMING: u32 = 2;
+
+    /// Returns `true` if there are enough buffers to double-buffer while streaming.
+    pub fn is_sufficient_for_streaming(&self) -> bool {
+        self.0 >= Self::MIN_STREAMING
+    }
+}
+
+impl From<v4l2_requestbuffers> for BufferCount {
+    fn from(reqbufs: v4l2_requestbuffers) -> Self {
+        BufferCount(reqbufs.count)
+    }
+}
+
+impl From<BufferCount> for u32 {
+    fn from(count: BufferCount) -> Self {
+        count.0
+    }
+}
+
 /// Full result of the `reqbufs` ioctl.
 pub struct RequestBuffers {
     pub count: u32,
@@ -63,14 +111,16 @@ mod ioctl {
     use crate::bindings::v4l2_create_buffers;
     use crate::bindings::v4l2_requestbuffers;
 
-    nix::ioctl_readwrite!(vidioc_reqbufs, b'V', 8, v4l2_requestbuffers);
-    nix::ioctl_readwrite!(vidioc_create_bufs, b'V', 92, v4l2_create_buffers);
+    ioctl_readwrite!(vidioc_reqbufs, b'V', 8, v4l2_requestbuffers);
+    ioctl_readwrite!(vidioc_create_bufs, b'V', 92, v4l2_create_buffers);
 }
 
 #[derive(Debug, Error)]
 pub enum ReqbufsError {
     #[error("invalid buffer ({0}) or memory type ({1:?}) requested")]
     InvalidBufferType(QueueType, MemoryType),
+    #[error("driver granted {granted} buffers while at least {requested} were requested")]
+    InsufficientBuffers { requested: u32, granted: u32 },
     #[error("ioctl error: {0}")]
     IoctlError(nix::Error),
 }
@@ -79,11 +129,22 @@ impl From<ReqbufsError> for Errno {
     fn from(err: ReqbufsError) -> Self {
         match err {
             ReqbufsError::InvalidBufferType(_, _) => Errno::EINVAL,
+            ReqbufsError::InsufficientBuffers { .. } => Errno::ENOMEM,
             ReqbufsError::IoctlError(e) => e,
         }
     }
 }
 
+impl AsErrno for ReqbufsError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            ReqbufsError::InvalidBufferType(..) => Some(Errno::EINVAL),
+            ReqbufsError::InsufficientBuffers { .. } => None,
+            ReqbufsError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_REQBUFS` ioctl.
 pub fn reqbufs<O: From<v4l2_requestbuffers>>(
     fd: &impl AsRawFd,
@@ -98,13 +159,65 @@ pub fn reqbufs<O: From<v4l2_requestbuffers>>(
         ..Default::default()
     };
 
-    match unsafe { ioctl::vidioc_reqbufs(fd.as_raw_fd(), &mut reqbufs) } {
+    let _span = ioctl_span!(
+        "VIDIOC_REQBUFS",
+        queue = ?queue,
+        memory = ?memory,
+        count,
+        granted = ::tracing::field::Empty
+    );
+    let res = unsafe { ioctl::vidioc_reqbufs(fd.as_raw_fd(), &mut reqbufs) };
+    record_errno!(_span, res);
+    if res.is_ok() {
+        span_record!(_span, "granted", reqbufs.count);
+    }
+
+    match res {
         Ok(_) => Ok(O::from(reqbufs)),
         Err(Errno::EINVAL) => Err(ReqbufsError::InvalidBufferType(queue, memory)),
         Err(e) => Err(ReqbufsError::IoctlError(e)),
     }
 }
 
+/// Number of times [`reqbufs_at_least`] will retry with a larger count if the driver grants fewer
+/// buffers than requested.
+const REQBUFS_AT_LEAST_RETRIES: u32 = 3;
+
+/// Allocate at least `min_count` buffers using `VIDIOC_REQBUFS`.
+///
+/// If the driver grants fewer buffers than `min_count`, the request is retried with an increased
+/// count a few times before giving up with [`ReqbufsError::InsufficientBuffers`]. This prevents
+/// silently starting a pipeline with fewer buffers than it needs.
+pub fn reqbufs_at_least(
+    fd: &impl AsRawFd,
+    queue: QueueType,
+    memory: MemoryType,
+    min_count: u32,
+) -> Result<BufferCount, ReqbufsError> {
+    let mut count = min_count;
+    let mut granted: BufferCount = reqbufs(fd, queue, memory, count)?;
+
+    for _ in 0..REQBUFS_AT_LEAST_RETRIES {
+        if granted.0 >= min_count {
+            return Ok(granted);
+        }
+        count += 1;
+        granted = reqbufs(fd, queue, memory, count)?;
+    }
+
+    if granted.0 >= min_count {
+        Ok(granted)
+    } else {
+        // Do not leave the caller with buffers it has no use for. Failing to free them is not
+        // worth reporting over the lack of buffers itself.
+        let _ = reqbufs::<BufferCount>(fd, queue, memory, 0);
+        Err(ReqbufsError::InsufficientBuffers {
+            requested: min_count,
+            granted: granted.0,
+        })
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum CreateBufsError {
     #[error("no memory available to allocate MMAP buffers")]
@@ -125,6 +238,16 @@ impl From<CreateBufsError> for Errno {
     }
 }
 
+impl AsErrno for CreateBufsError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            CreateBufsError::NoMem => Some(Errno::ENOMEM),
+            CreateBufsError::Invalid => Some(Errno::EINVAL),
+            CreateBufsError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_CREATE_BUFS` ioctl.
 pub fn create_bufs<F: Into<v4l2_format>, O: From<v4l2_create_buffers>>(
     fd: &impl AsRawFd,
@@ -146,3 +269,78 @@ pub fn create_bufs<F: Into<v4l2_format>, O: From<v4l2_create_buffers>>(
         Err(e) => Err(CreateBufsError::IoctlError(e)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioctl::backend::mock::MockIoctls;
+
+    /// Expects a `VIDIOC_REQBUFS` for `count` buffers, granting `granted` of them.
+    fn expect_reqbufs(mock: &MockIoctls, count: u32, granted: u32) {
+        mock.expect_with(
+            "vidioc_reqbufs",
+            move |reqbufs: &mut v4l2_requestbuffers| {
+                assert_eq!(reqbufs.count, count);
+                reqbufs.count = granted;
+                Ok(0)
+            },
+        );
+    }
+
+    #[test]
+    fn test_buffer_count() {
+        assert!(!BufferCount(1).is_sufficient_for_streaming());
+        assert!(BufferCount(BufferCount::MIN_STREAMING).is_sufficient_for_streaming());
+        assert_eq!(u32::from(BufferCount(4)), 4);
+    }
+
+    #[test]
+    fn test_reqbufs_at_least() {
+        let mock = MockIoctls::new();
+        let file = mock.file();
+
+        // The driver grants the exact count.
+        expect_reqbufs(&mock, 4, 4);
+        assert_eq!(
+            reqbufs_at_least(&file, QueueType::VideoCapture, MemoryType::Mmap, 4).unwrap(),
+            BufferCount(4)
+        );
+
+        // The driver clamps the count, and grants enough buffers on the second try.
+        expect_reqbufs(&mock, 4, 3);
+        expect_reqbufs(&mock, 5, 4);
+        assert_eq!(
+            reqbufs_at_least(&file, QueueType::VideoCapture, MemoryType::Mmap, 4).unwrap(),
+            BufferCount(4)
+        );
+
+        // More buffers than requested are fine.
+        expect_reqbufs(&mock, 2, 3);
+        assert_eq!(
+            reqbufs_at_least(&file, QueueType::VideoCapture, MemoryType::Mmap, 2).unwrap(),
+            BufferCount(3)
+        );
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_reqbufs_at_least_insufficient() {
+        let mock = MockIoctls::new();
+        let file = mock.file();
+
+        // The driver never grants enough buffers, so the granted ones are freed.
+        expect_reqbufs(&mock, 8, 6);
+        for count in 9..=11 {
+            expect_reqbufs(&mock, count, 6);
+        }
+        expect_reqbufs(&mock, 0, 0);
+        assert!(matches!(
+            reqbufs_at_least(&file, QueueType::VideoCapture, MemoryType::Mmap, 8),
+            Err(ReqbufsError::InsufficientBuffers {
+                requested: 8,
+                granted: 6
+            })
+        ));
+        mock.assert_done();
+    }
+}
@@ -1,3 +1,4 @@
+use nix::errno::Errno;
 use nix::libc::c_int;
 use nix::poll::{PollFd, PollTimeout};
 use std::convert::TryFrom;
@@ -6,14 +7,16 @@ use std::os::unix::io::{AsFd, AsRawFd, RawFd};
 use std::os::unix::prelude::FromRawFd;
 use thiserror::Error;
 
+use crate::error::AsErrno;
+
 pub use nix::poll::PollFlags;
 
 #[doc(hidden)]
 mod ioctl {
     use nix::libc::c_int;
-    nix::ioctl_read!(media_ioc_request_alloc, b'|', 5, c_int);
-    nix::ioctl_none!(media_request_ioc_queue, b'|', 0x80);
-    nix::ioctl_none!(media_request_ioc_reinit, b'|', 0x81);
+    ioctl_read!(media_ioc_request_alloc, b'|', 5, c_int);
+    ioctl_none!(media_request_ioc_queue, b'|', 0x80);
+    ioctl_none!(media_request_ioc_reinit, b'|', 0x81);
 }
 
 #[derive(Debug, Error)]
@@ -26,6 +29,16 @@ pub enum RequestError {
     InvalidDuration,
 }
 
+impl AsErrno for RequestError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            RequestError::IoctlError(e) => Some(*e),
+            RequestError::UnknownPollFlagReturned => None,
+            RequestError::InvalidDuration => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Request {
     fd: File,
@@ -0,0 +1,119 @@
+//! Safe wrapper for the `MEDIA_IOC_SETUP_LINK` ioctl of media controller devices.
+use std::os::unix::io::AsRawFd;
+
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::bindings::media_link_desc;
+use crate::bindings::media_pad_desc;
+use crate::error::AsErrno;
+use crate::ioctl::{LinkFlags, MediaPad};
+
+#[doc(hidden)]
+mod ioctl {
+    use crate::bindings::media_link_desc;
+    ioctl_readwrite!(media_ioc_setup_link, b'|', 0x03, media_link_desc);
+}
+
+#[derive(Debug, Error)]
+pub enum SetupLinkError {
+    #[error("the link does not exist or is immutable")]
+    Invalid,
+    #[error("the link is in use by a streaming pipeline")]
+    Busy,
+    #[error("ioctl error: {0}")]
+    IoctlError(Errno),
+}
+
+impl From<SetupLinkError> for Errno {
+    fn from(err: SetupLinkError) -> Self {
+        match err {
+            SetupLinkError::Invalid => Errno::EINVAL,
+            SetupLinkError::Busy => Errno::EBUSY,
+            SetupLinkError::IoctlError(e) => e,
+        }
+    }
+}
+
+impl AsErrno for SetupLinkError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            SetupLinkError::Invalid => Some(Errno::EINVAL),
+            SetupLinkError::Busy => Some(Errno::EBUSY),
+            SetupLinkError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
+fn pad_desc(pad: &MediaPad) -> media_pad_desc {
+    media_pad_desc {
+        entity: pad.entity_id,
+        index: pad.index as u16,
+        ..Default::default()
+    }
+}
+
+/// Safe wrapper around the `MEDIA_IOC_SETUP_LINK` ioctl.
+///
+/// Sets the flags of the data link going from pad `source` to pad `sink`. Only
+/// [`LinkFlags::ENABLED`] can be changed, and only if the link is not immutable.
+pub fn setup_link(
+    fd: &impl AsRawFd,
+    source: &MediaPad,
+    sink: &MediaPad,
+    flags: LinkFlags,
+) -> Result<(), SetupLinkError> {
+    let mut link = media_link_desc {
+        source: pad_desc(source),
+        sink: pad_desc(sink),
+        flags: flags.bits(),
+        ..Default::default()
+    };
+
+    match unsafe { ioctl::media_ioc_setup_link(fd.as_raw_fd(), &mut link) } {
+        Ok(_) => Ok(()),
+        Err(Errno::EINVAL) => Err(SetupLinkError::Invalid),
+        Err(Errno::EBUSY) => Err(SetupLinkError::Busy),
+        Err(e) => Err(SetupLinkError::IoctlError(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioctl::backend::mock::MockIoctls;
+    use crate::ioctl::PadFlags;
+
+    #[test]
+    fn test_setup_link() {
+        let source = MediaPad {
+            id: 21,
+            entity_id: 2,
+            flags: PadFlags::SOURCE,
+            index: 1,
+        };
+        let sink = MediaPad {
+            id: 30,
+            entity_id: 3,
+            flags: PadFlags::SINK,
+            index: 0,
+        };
+
+        let mock = MockIoctls::new();
+        mock.expect_with("media_ioc_setup_link", |link: &mut media_link_desc| {
+            assert_eq!((link.source.entity, link.source.index), (2, 1));
+            assert_eq!((link.sink.entity, link.sink.index), (3, 0));
+            assert_eq!(link.flags, LinkFlags::ENABLED.bits());
+            Ok(0)
+        })
+        .expect("media_ioc_setup_link", Err(Errno::EBUSY));
+        let file = mock.file();
+
+        setup_link(&file, &source, &sink, LinkFlags::ENABLED).unwrap();
+        assert!(matches!(
+            setup_link(&file, &source, &sink, LinkFlags::empty()),
+            Err(SetupLinkError::Busy)
+        ));
+        mock.assert_done();
+    }
+}
@@ -1,4 +1,5 @@
 //! Safe wrapper for the `VIDIOC_STREAM(ON|OFF)` ioctls.
+use crate::error::AsErrno;
 use crate::QueueType;
 use nix::errno::Errno;
 use std::os::unix::io::AsRawFd;
@@ -6,14 +7,18 @@ use thiserror::Error;
 
 #[doc(hidden)]
 mod ioctl {
-    nix::ioctl_write_ptr!(vidioc_streamon, b'V', 18, u32);
-    nix::ioctl_write_ptr!(vidioc_streamoff, b'V', 19, u32);
+    ioctl_write_ptr!(vidioc_streamon, b'V', 18, u32);
+    ioctl_write_ptr!(vidioc_streamoff, b'V', 19, u32);
 }
 
 #[derive(Debug, Error)]
 pub enum StreamOnError {
     #[error("queue type ({0}) not supported, or no buffers allocated or enqueued")]
     InvalidQueue(QueueType),
+    #[error("queue is owned by another file handle")]
+    DeviceBusy,
+    #[error("insufficient resources to start streaming")]
+    InsufficientResources,
     #[error("invalid pad configuration")]
     InvalidPadConfig,
     #[error("invalid pipeline link configuration")]
@@ -26,6 +31,8 @@ impl From<StreamOnError> for Errno {
     fn from(err: StreamOnError) -> Self {
         match err {
             StreamOnError::InvalidQueue(_) => Errno::EINVAL,
+            StreamOnError::DeviceBusy => Errno::EBUSY,
+            StreamOnError::InsufficientResources => Errno::ENOBUFS,
             StreamOnError::InvalidPadConfig => Errno::EPIPE,
             StreamOnError::InvalidPipelineConfig => Errno::ENOLINK,
             StreamOnError::IoctlError(e) => e,
@@ -33,11 +40,30 @@ impl From<StreamOnError> for Errno {
     }
 }
 
+impl AsErrno for StreamOnError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            StreamOnError::InvalidQueue(_) => Some(Errno::EINVAL),
+            StreamOnError::DeviceBusy => Some(Errno::EBUSY),
+            StreamOnError::InsufficientResources => Some(Errno::ENOBUFS),
+            StreamOnError::InvalidPadConfig => Some(Errno::EPIPE),
+            StreamOnError::InvalidPipelineConfig => Some(Errno::ENOLINK),
+            StreamOnError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_STREAMON` ioctl.
 pub fn streamon(fd: &impl AsRawFd, queue: QueueType) -> Result<(), StreamOnError> {
-    match unsafe { ioctl::vidioc_streamon(fd.as_raw_fd(), &(queue as u32)) } {
+    let _span = ioctl_span!("VIDIOC_STREAMON", queue = ?queue);
+    let res = unsafe { ioctl::vidioc_streamon(fd.as_raw_fd(), &(queue as u32)) };
+    record_errno!(_span, res);
+
+    match res {
         Ok(_) => Ok(()),
         Err(Errno::EINVAL) => Err(StreamOnError::InvalidQueue(queue)),
+        Err(Errno::EBUSY) => Err(StreamOnError::DeviceBusy),
+        Err(Errno::ENOBUFS) => Err(StreamOnError::InsufficientResources),
         Err(Errno::EPIPE) => Err(StreamOnError::InvalidPadConfig),
         Err(Errno::ENOLINK) => Err(StreamOnError::InvalidPipelineConfig),
         Err(e) => Err(StreamOnError::IoctlError(e)),
@@ -48,6 +74,8 @@ pub fn streamon(fd: &impl AsRawFd, queue: QueueType) -> Result<(), StreamOnError
 pub enum StreamOffError {
     #[error("queue type not supported")]
     InvalidQueue,
+    #[error("queue is owned by another file handle")]
+    DeviceBusy,
     #[error("ioctl error: {0}")]
     IoctlError(Errno),
 }
@@ -56,16 +84,142 @@ impl From<StreamOffError> for Errno {
     fn from(err: StreamOffError) -> Self {
         match err {
             StreamOffError::InvalidQueue => Errno::EINVAL,
+            StreamOffError::DeviceBusy => Errno::EBUSY,
             StreamOffError::IoctlError(e) => e,
         }
     }
 }
 
+impl AsErrno for StreamOffError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            StreamOffError::InvalidQueue => Some(Errno::EINVAL),
+            StreamOffError::DeviceBusy => Some(Errno::EBUSY),
+            StreamOffError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_STREAMOFF` ioctl.
 pub fn streamoff(fd: &impl AsRawFd, queue: QueueType) -> Result<(), StreamOffError> {
-    match unsafe { ioctl::vidioc_streamoff(fd.as_raw_fd(), &(queue as u32)) } {
+    let _span = ioctl_span!("VIDIOC_STREAMOFF", queue = ?queue);
+    let res = unsafe { ioctl::vidioc_streamoff(fd.as_raw_fd(), &(queue as u32)) };
+    record_errno!(_span, res);
+
+    match res {
         Ok(_) => Ok(()),
         Err(Errno::EINVAL) => Err(StreamOffError::InvalidQueue),
+        Err(Errno::EBUSY) => Err(StreamOffError::DeviceBusy),
         Err(e) => Err(StreamOffError::IoctlError(e)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::{streamoff, streamon, StreamOffError, StreamOnError};
+    use crate::error::AsErrno;
+    use crate::ioctl::backend::mock::MockIoctls;
+    use crate::QueueType;
+
+    #[test]
+    fn test_streamon_error_errno() {
+        for (err, errno) in [
+            (
+                StreamOnError::InvalidQueue(QueueType::VideoCapture),
+                Errno::EINVAL,
+            ),
+            (StreamOnError::DeviceBusy, Errno::EBUSY),
+            (StreamOnError::InsufficientResources, Errno::ENOBUFS),
+            (StreamOnError::InvalidPadConfig, Errno::EPIPE),
+            (StreamOnError::InvalidPipelineConfig, Errno::ENOLINK),
+            (StreamOnError::IoctlError(Errno::EIO), Errno::EIO),
+        ] {
+            assert_eq!(err.errno(), Some(errno));
+            assert_eq!(Errno::from(err), errno);
+        }
+
+        for (err, errno) in [
+            (StreamOffError::InvalidQueue, Errno::EINVAL),
+            (StreamOffError::DeviceBusy, Errno::EBUSY),
+            (StreamOffError::IoctlError(Errno::EIO), Errno::EIO),
+        ] {
+            assert_eq!(err.errno(), Some(errno));
+            assert_eq!(Errno::from(err), errno);
+        }
+    }
+
+    #[test]
+    fn test_stream_not_a_device() {
+        let mock = MockIoctls::new();
+        mock.expect("vidioc_streamon", Err(Errno::ENOTTY))
+            .expect("vidioc_streamoff", Err(Errno::ENOTTY));
+        let file = mock.file();
+
+        assert!(matches!(
+            streamon(&file, QueueType::VideoCapture),
+            Err(StreamOnError::IoctlError(Errno::ENOTTY))
+        ));
+        assert!(matches!(
+            streamoff(&file, QueueType::VideoCapture),
+            Err(StreamOffError::IoctlError(Errno::ENOTTY))
+        ));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let mock = MockIoctls::new();
+        mock.expect_with("vidioc_streamon", |queue: &mut u32| {
+            assert_eq!(*queue, QueueType::VideoCaptureMplane as u32);
+            Ok(0)
+        });
+        for errno in [
+            Errno::EINVAL,
+            Errno::EBUSY,
+            Errno::ENOBUFS,
+            Errno::EPIPE,
+            Errno::ENOLINK,
+            Errno::ENODEV,
+        ] {
+            mock.expect("vidioc_streamon", Err(errno));
+        }
+        mock.expect("vidioc_streamoff", Err(Errno::EBUSY))
+            .expect("vidioc_streamoff", Ok(0));
+        let file = mock.file();
+        let queue = QueueType::VideoCaptureMplane;
+
+        assert!(streamon(&file, queue).is_ok());
+        assert!(matches!(
+            streamon(&file, queue),
+            Err(StreamOnError::InvalidQueue(QueueType::VideoCaptureMplane))
+        ));
+        assert!(matches!(
+            streamon(&file, queue),
+            Err(StreamOnError::DeviceBusy)
+        ));
+        assert!(matches!(
+            streamon(&file, queue),
+            Err(StreamOnError::InsufficientResources)
+        ));
+        assert!(matches!(
+            streamon(&file, queue),
+            Err(StreamOnError::InvalidPadConfig)
+        ));
+        assert!(matches!(
+            streamon(&file, queue),
+            Err(StreamOnError::InvalidPipelineConfig)
+        ));
+        assert!(matches!(
+            streamon(&file, queue),
+            Err(StreamOnError::IoctlError(Errno::ENODEV))
+        ));
+        assert!(matches!(
+            streamoff(&file, queue),
+            Err(StreamOffError::DeviceBusy)
+        ));
+        assert!(streamoff(&file, queue).is_ok());
+        mock.assert_done();
+    }
+}
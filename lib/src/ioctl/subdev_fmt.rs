@@ -0,0 +1,203 @@
+//! Safe wrappers for the `VIDIOC_SUBDEV_G_FMT` and `VIDIOC_SUBDEV_S_FMT` ioctls.
+use std::os::unix::io::AsRawFd;
+
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::bindings::v4l2_mbus_framefmt;
+use crate::bindings::v4l2_subdev_format;
+use crate::error::AsErrno;
+use crate::ioctl::SubDevWhich;
+
+/// A media bus code, i.e. one of the `MEDIA_BUS_FMT_*` values describing how the data flowing
+/// between two pads is laid out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MediaBusCode(u32);
+
+impl From<u32> for MediaBusCode {
+    fn from(code: u32) -> Self {
+        MediaBusCode(code)
+    }
+}
+
+impl From<MediaBusCode> for u32 {
+    fn from(code: MediaBusCode) -> Self {
+        code.0
+    }
+}
+
+/// Safe variant of the `v4l2_mbus_framefmt` struct, i.e. the format of the data flowing through a
+/// pad of a sub-device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MbusFormat {
+    pub width: u32,
+    pub height: u32,
+    /// Media bus code of the format.
+    pub code: MediaBusCode,
+    /// One of the `V4L2_FIELD_*` values.
+    pub field: u32,
+    /// One of the `V4L2_COLORSPACE_*` values.
+    pub colorspace: u32,
+    /// One of the `V4L2_QUANTIZATION_*` values.
+    pub quantization: u32,
+    /// One of the `V4L2_XFER_FUNC_*` values.
+    pub xfer_func: u32,
+}
+
+impl From<v4l2_mbus_framefmt> for MbusFormat {
+    fn from(fmt: v4l2_mbus_framefmt) -> Self {
+        MbusFormat {
+            width: fmt.width,
+            height: fmt.height,
+            code: fmt.code.into(),
+            field: fmt.field,
+            colorspace: fmt.colorspace,
+            quantization: fmt.quantization as u32,
+            xfer_func: fmt.xfer_func as u32,
+        }
+    }
+}
+
+impl From<MbusFormat> for v4l2_mbus_framefmt {
+    fn from(fmt: MbusFormat) -> Self {
+        v4l2_mbus_framefmt {
+            width: fmt.width,
+            height: fmt.height,
+            code: fmt.code.into(),
+            field: fmt.field,
+            colorspace: fmt.colorspace,
+            quantization: fmt.quantization as _,
+            xfer_func: fmt.xfer_func as _,
+            ..Default::default()
+        }
+    }
+}
+
+#[doc(hidden)]
+mod ioctl {
+    use crate::bindings::v4l2_subdev_format;
+    ioctl_readwrite!(vidioc_subdev_g_fmt, b'V', 4, v4l2_subdev_format);
+    ioctl_readwrite!(vidioc_subdev_s_fmt, b'V', 5, v4l2_subdev_format);
+}
+
+#[derive(Debug, Error)]
+pub enum SubDevFmtError {
+    #[error("invalid pad or format")]
+    Invalid,
+    #[error("the pad is busy, e.g. because the sub-device is streaming")]
+    Busy,
+    #[error("ioctl error: {0}")]
+    IoctlError(Errno),
+}
+
+impl From<SubDevFmtError> for Errno {
+    fn from(err: SubDevFmtError) -> Self {
+        match err {
+            SubDevFmtError::Invalid => Errno::EINVAL,
+            SubDevFmtError::Busy => Errno::EBUSY,
+            SubDevFmtError::IoctlError(e) => e,
+        }
+    }
+}
+
+impl AsErrno for SubDevFmtError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            SubDevFmtError::Invalid => Some(Errno::EINVAL),
+            SubDevFmtError::Busy => Some(Errno::EBUSY),
+            SubDevFmtError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
+impl From<Errno> for SubDevFmtError {
+    fn from(errno: Errno) -> Self {
+        match errno {
+            Errno::EINVAL => SubDevFmtError::Invalid,
+            Errno::EBUSY => SubDevFmtError::Busy,
+            e => SubDevFmtError::IoctlError(e),
+        }
+    }
+}
+
+/// Safe wrapper around the `VIDIOC_SUBDEV_G_FMT` ioctl.
+pub fn subdev_g_fmt<O: From<v4l2_mbus_framefmt>>(
+    fd: &impl AsRawFd,
+    pad: u32,
+    which: SubDevWhich,
+) -> Result<O, SubDevFmtError> {
+    let mut fmt = v4l2_subdev_format {
+        which: which as u32,
+        pad,
+        ..Default::default()
+    };
+
+    match unsafe { ioctl::vidioc_subdev_g_fmt(fd.as_raw_fd(), &mut fmt) } {
+        Ok(_) => Ok(O::from(fmt.format)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Safe wrapper around the `VIDIOC_SUBDEV_S_FMT` ioctl.
+///
+/// Returns the format actually applied by the driver, which may have been adjusted.
+pub fn subdev_s_fmt<I: Into<v4l2_mbus_framefmt>, O: From<v4l2_mbus_framefmt>>(
+    fd: &impl AsRawFd,
+    pad: u32,
+    which: SubDevWhich,
+    format: I,
+) -> Result<O, SubDevFmtError> {
+    let mut fmt = v4l2_subdev_format {
+        which: which as u32,
+        pad,
+        format: format.into(),
+        ..Default::default()
+    };
+
+    match unsafe { ioctl::vidioc_subdev_s_fmt(fd.as_raw_fd(), &mut fmt) } {
+        Ok(_) => Ok(O::from(fmt.format)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bindings;
+    use crate::ioctl::backend::mock::MockIoctls;
+
+    #[test]
+    fn test_subdev_fmt() {
+        let mock = MockIoctls::new();
+        mock.expect_with("vidioc_subdev_s_fmt", |fmt: &mut v4l2_subdev_format| {
+            assert_eq!(fmt.pad, 1);
+            assert_eq!(fmt.which, SubDevWhich::Active as u32);
+            assert_eq!(fmt.format.code, bindings::MEDIA_BUS_FMT_RGB888_1X24);
+            // The driver aligns the width.
+            fmt.format.width = 640;
+            Ok(0)
+        })
+        .expect("vidioc_subdev_s_fmt", Err(Errno::EBUSY))
+        .expect("vidioc_subdev_g_fmt", Err(Errno::EINVAL));
+        let file = mock.file();
+
+        let requested = MbusFormat {
+            width: 641,
+            height: 480,
+            code: bindings::MEDIA_BUS_FMT_RGB888_1X24.into(),
+            ..Default::default()
+        };
+        let format: MbusFormat = subdev_s_fmt(&file, 1, SubDevWhich::Active, requested).unwrap();
+        assert_eq!((format.width, format.height), (640, 480));
+
+        assert!(matches!(
+            subdev_s_fmt::<_, MbusFormat>(&file, 1, SubDevWhich::Active, requested),
+            Err(SubDevFmtError::Busy)
+        ));
+        assert!(matches!(
+            subdev_g_fmt::<MbusFormat>(&file, 7, SubDevWhich::Active),
+            Err(SubDevFmtError::Invalid)
+        ));
+        mock.assert_done();
+    }
+}
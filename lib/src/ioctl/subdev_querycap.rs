@@ -0,0 +1,117 @@
+//! Safe wrapper for the `VIDIOC_SUBDEV_QUERYCAP` ioctl.
+use std::os::unix::io::AsRawFd;
+
+use bitflags::bitflags;
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::bindings;
+use crate::bindings::v4l2_subdev_capability;
+use crate::error::AsErrno;
+
+bitflags! {
+    /// Capabilities of a sub-device, as reported by `VIDIOC_SUBDEV_QUERYCAP`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct SubDevCapFlags: u32 {
+        /// The sub-device node has been registered read-only, i.e. only the ioctls that do not
+        /// modify its state are allowed.
+        const RO_SUBDEV = bindings::V4L2_SUBDEV_CAP_RO_SUBDEV;
+    }
+}
+
+/// Safe variant of the `v4l2_subdev_capability` struct, to be used with `subdev_querycap`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SubDeviceCapabilities {
+    /// Version of the sub-device API, as a (major, minor, patch) triplet.
+    pub version: (u8, u8, u8),
+    pub capabilities: SubDevCapFlags,
+}
+
+impl From<v4l2_subdev_capability> for SubDeviceCapabilities {
+    fn from(caps: v4l2_subdev_capability) -> Self {
+        SubDeviceCapabilities {
+            version: (
+                (caps.version >> 16) as u8,
+                (caps.version >> 8) as u8,
+                caps.version as u8,
+            ),
+            capabilities: SubDevCapFlags::from_bits_truncate(caps.capabilities),
+        }
+    }
+}
+
+#[doc(hidden)]
+mod ioctl {
+    use crate::bindings::v4l2_subdev_capability;
+    ioctl_read!(vidioc_subdev_querycap, b'V', 0, v4l2_subdev_capability);
+}
+
+#[derive(Debug, Error)]
+pub enum SubDevQueryCapError {
+    #[error("VIDIOC_SUBDEV_QUERYCAP is not supported (kernel older than 5.14?)")]
+    Unsupported,
+    #[error("ioctl error: {0}")]
+    IoctlError(Errno),
+}
+
+impl From<SubDevQueryCapError> for Errno {
+    fn from(err: SubDevQueryCapError) -> Self {
+        match err {
+            SubDevQueryCapError::Unsupported => Errno::ENOTTY,
+            SubDevQueryCapError::IoctlError(e) => e,
+        }
+    }
+}
+
+impl AsErrno for SubDevQueryCapError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            SubDevQueryCapError::Unsupported => Some(Errno::ENOTTY),
+            SubDevQueryCapError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
+/// Safe wrapper around the `VIDIOC_SUBDEV_QUERYCAP` ioctl.
+pub fn subdev_querycap<T: From<v4l2_subdev_capability>>(
+    fd: &impl AsRawFd,
+) -> Result<T, SubDevQueryCapError> {
+    let mut caps: v4l2_subdev_capability = Default::default();
+
+    match unsafe { ioctl::vidioc_subdev_querycap(fd.as_raw_fd(), &mut caps) } {
+        Ok(_) => Ok(T::from(caps)),
+        Err(Errno::ENOTTY) => Err(SubDevQueryCapError::Unsupported),
+        Err(e) => Err(SubDevQueryCapError::IoctlError(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioctl::backend::mock::MockIoctls;
+
+    #[test]
+    fn test_subdev_querycap() {
+        let mock = MockIoctls::new();
+        mock.expect_with(
+            "vidioc_subdev_querycap",
+            |caps: &mut v4l2_subdev_capability| {
+                caps.version = (6 << 16) | (12 << 8) | 3;
+                caps.capabilities = bindings::V4L2_SUBDEV_CAP_RO_SUBDEV | 0x8000_0000;
+                Ok(0)
+            },
+        )
+        .expect("vidioc_subdev_querycap", Err(Errno::ENOTTY));
+        let file = mock.file();
+
+        let caps: SubDeviceCapabilities = subdev_querycap(&file).unwrap();
+        assert_eq!(caps.version, (6, 12, 3));
+        assert_eq!(caps.capabilities, SubDevCapFlags::RO_SUBDEV);
+
+        assert!(matches!(
+            subdev_querycap::<SubDeviceCapabilities>(&file),
+            Err(SubDevQueryCapError::Unsupported)
+        ));
+        mock.assert_done();
+    }
+}
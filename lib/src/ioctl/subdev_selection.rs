@@ -0,0 +1,77 @@
+//! Safe wrappers for the `VIDIOC_SUBDEV_G_SELECTION` and `VIDIOC_SUBDEV_S_SELECTION` ioctls.
+use std::os::unix::io::AsRawFd;
+
+use enumn::N;
+use nix::errno::Errno;
+
+use crate::bindings;
+use crate::bindings::v4l2_rect;
+use crate::bindings::v4l2_subdev_selection;
+use crate::ioctl::GSelectionError;
+use crate::ioctl::SSelectionError;
+use crate::ioctl::SelectionFlags;
+use crate::ioctl::SelectionTarget;
+
+/// Whether a sub-device ioctl applies to the configuration of the hardware, or to the try
+/// configuration of the file handle used for negotiation.
+#[derive(Debug, N, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SubDevWhich {
+    Try = bindings::v4l2_subdev_format_whence_V4L2_SUBDEV_FORMAT_TRY,
+    Active = bindings::v4l2_subdev_format_whence_V4L2_SUBDEV_FORMAT_ACTIVE,
+}
+
+#[doc(hidden)]
+mod ioctl {
+    use crate::bindings::v4l2_subdev_selection;
+    ioctl_readwrite!(vidioc_subdev_g_selection, b'V', 61, v4l2_subdev_selection);
+    ioctl_readwrite!(vidioc_subdev_s_selection, b'V', 62, v4l2_subdev_selection);
+}
+
+/// Safe wrapper around the `VIDIOC_SUBDEV_G_SELECTION` ioctl.
+pub fn subdev_g_selection<R: From<v4l2_rect>>(
+    fd: &impl AsRawFd,
+    pad: u32,
+    which: SubDevWhich,
+    target: SelectionTarget,
+) -> Result<R, GSelectionError> {
+    let mut sel = v4l2_subdev_selection {
+        which: which as u32,
+        pad,
+        target: target as u32,
+        ..Default::default()
+    };
+
+    match unsafe { ioctl::vidioc_subdev_g_selection(fd.as_raw_fd(), &mut sel) } {
+        Ok(_) => Ok(R::from(sel.r)),
+        Err(Errno::EINVAL) => Err(GSelectionError::Invalid),
+        Err(e) => Err(GSelectionError::IoctlError(e)),
+    }
+}
+
+/// Safe wrapper around the `VIDIOC_SUBDEV_S_SELECTION` ioctl.
+pub fn subdev_s_selection<RI: Into<v4l2_rect>, RO: From<v4l2_rect>>(
+    fd: &impl AsRawFd,
+    pad: u32,
+    which: SubDevWhich,
+    target: SelectionTarget,
+    rect: RI,
+    flags: SelectionFlags,
+) -> Result<RO, SSelectionError> {
+    let mut sel = v4l2_subdev_selection {
+        which: which as u32,
+        pad,
+        target: target as u32,
+        flags: flags.bits(),
+        r: rect.into(),
+        ..Default::default()
+    };
+
+    match unsafe { ioctl::vidioc_subdev_s_selection(fd.as_raw_fd(), &mut sel) } {
+        Ok(_) => Ok(RO::from(sel.r)),
+        Err(Errno::EINVAL) => Err(SSelectionError::Invalid),
+        Err(Errno::ERANGE) => Err(SSelectionError::InvalidRange),
+        Err(Errno::EBUSY) => Err(SSelectionError::Busy),
+        Err(e) => Err(SSelectionError::IoctlError(e)),
+    }
+}
@@ -4,6 +4,7 @@
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::os::unix::io::AsRawFd;
+use std::time::Duration;
 
 use bitflags::bitflags;
 use nix::errno::Errno;
@@ -12,6 +13,7 @@ use thiserror::Error;
 use crate::bindings;
 use crate::bindings::v4l2_event;
 use crate::bindings::v4l2_event_subscription;
+use crate::error::AsErrno;
 
 bitflags! {
     #[derive(Clone, Copy, Debug)]
@@ -63,9 +65,39 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct CtrlChanges: u32 {
+        const VALUE = bindings::V4L2_EVENT_CTRL_CH_VALUE;
+        const FLAGS = bindings::V4L2_EVENT_CTRL_CH_FLAGS;
+        const RANGE = bindings::V4L2_EVENT_CTRL_CH_RANGE;
+    }
+}
+
+/// Payload of a `V4L2_EVENT_CTRL` event.
+#[derive(Debug)]
+pub struct CtrlEvent {
+    /// ID of the control that changed.
+    pub id: u32,
+    pub changes: CtrlChanges,
+    /// New value of the control, if it is not a pointer control.
+    pub value: i64,
+}
+
+/// Payload of a `V4L2_EVENT_FRAME_SYNC` event, sent when the device starts receiving a frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameSyncEvent {
+    /// Sequence number of the frame, matching the `sequence` of the buffer it will be written to.
+    pub frame_sequence: u32,
+    /// Time at which the event has been raised, in the `CLOCK_MONOTONIC` time base.
+    pub timestamp: Duration,
+}
+
 #[derive(Debug)]
 pub enum Event {
     SrcChangeEvent(SrcChanges),
+    CtrlEvent(CtrlEvent),
+    FrameSync(FrameSyncEvent),
     Eos,
 }
 
@@ -76,8 +108,25 @@ impl TryFrom<v4l2_event> for Event {
         Ok(match value.type_ {
             bindings::V4L2_EVENT_VSYNC => todo!(),
             bindings::V4L2_EVENT_EOS => Event::Eos,
-            bindings::V4L2_EVENT_CTRL => todo!(),
-            bindings::V4L2_EVENT_FRAME_SYNC => todo!(),
+            bindings::V4L2_EVENT_CTRL => {
+                let ctrl = unsafe { value.u.ctrl };
+                Event::CtrlEvent(CtrlEvent {
+                    id: value.id,
+                    changes: CtrlChanges::from_bits_truncate(ctrl.changes),
+                    value: if ctrl.type_ == bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER64 {
+                        unsafe { ctrl.__bindgen_anon_1.value64 }
+                    } else {
+                        unsafe { ctrl.__bindgen_anon_1.value as i64 }
+                    },
+                })
+            }
+            bindings::V4L2_EVENT_FRAME_SYNC => Event::FrameSync(FrameSyncEvent {
+                frame_sequence: unsafe { value.u.frame_sync.frame_sequence },
+                timestamp: Duration::new(
+                    value.timestamp.tv_sec as u64,
+                    value.timestamp.tv_nsec as u32,
+                ),
+            }),
             bindings::V4L2_EVENT_SOURCE_CHANGE => {
                 let changes = unsafe { value.u.src_change.changes };
                 Event::SrcChangeEvent(
@@ -118,9 +167,9 @@ fn build_v4l2_event_subscription(
 mod ioctl {
     use crate::bindings::{v4l2_event, v4l2_event_subscription};
 
-    nix::ioctl_read!(vidioc_dqevent, b'V', 89, v4l2_event);
-    nix::ioctl_write_ptr!(vidioc_subscribe_event, b'V', 90, v4l2_event_subscription);
-    nix::ioctl_write_ptr!(vidioc_unsubscribe_event, b'V', 91, v4l2_event_subscription);
+    ioctl_read!(vidioc_dqevent, b'V', 89, v4l2_event);
+    ioctl_write_ptr!(vidioc_subscribe_event, b'V', 90, v4l2_event_subscription);
+    ioctl_write_ptr!(vidioc_unsubscribe_event, b'V', 91, v4l2_event_subscription);
 }
 
 #[derive(Debug, Error)]
@@ -137,6 +186,14 @@ impl From<SubscribeEventError> for Errno {
     }
 }
 
+impl AsErrno for SubscribeEventError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            SubscribeEventError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 /// Safe wrapper around the `VIDIOC_SUBSCRIBE_EVENT` ioctl.
 pub fn subscribe_event(
     fd: &impl AsRawFd,
@@ -197,10 +254,32 @@ impl From<DqEventError> for Errno {
     }
 }
 
+impl AsErrno for DqEventError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            DqEventError::NotReady => Some(Errno::ENOENT),
+            DqEventError::EventConversionError => None,
+            DqEventError::IoctlError(e) => Some(*e),
+        }
+    }
+}
+
 pub fn dqevent<O: TryFrom<v4l2_event>>(fd: &impl AsRawFd) -> Result<O, DqEventError> {
     let mut event: v4l2_event = Default::default();
 
-    match unsafe { ioctl::vidioc_dqevent(fd.as_raw_fd(), &mut event) } {
+    let _span = ioctl_span!(
+        "VIDIOC_DQEVENT",
+        type_ = ::tracing::field::Empty,
+        pending = ::tracing::field::Empty
+    );
+    let res = unsafe { ioctl::vidioc_dqevent(fd.as_raw_fd(), &mut event) };
+    record_errno!(_span, res);
+    if res.is_ok() {
+        span_record!(_span, "type_", event.type_);
+        span_record!(_span, "pending", event.pending);
+    }
+
+    match res {
         Ok(_) => Ok(event
             .try_into()
             .map_err(|_| DqEventError::EventConversionError)?),
@@ -208,3 +287,66 @@ pub fn dqevent<O: TryFrom<v4l2_event>>(fd: &impl AsRawFd) -> Result<O, DqEventEr
         Err(e) => Err(DqEventError::IoctlError(e)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bindings::{v4l2_event_ctrl, v4l2_event_ctrl__bindgen_ty_1, v4l2_event_frame_sync};
+
+    #[test]
+    fn test_ctrl_event() {
+        let mut event = v4l2_event {
+            type_: bindings::V4L2_EVENT_CTRL,
+            id: bindings::V4L2_CID_BRIGHTNESS,
+            ..Default::default()
+        };
+        event.u.ctrl = v4l2_event_ctrl {
+            changes: bindings::V4L2_EVENT_CTRL_CH_VALUE | bindings::V4L2_EVENT_CTRL_CH_RANGE,
+            type_: bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER,
+            __bindgen_anon_1: v4l2_event_ctrl__bindgen_ty_1 { value: -42 },
+            ..Default::default()
+        };
+
+        match Event::try_from(event).unwrap() {
+            Event::CtrlEvent(ctrl) => {
+                assert_eq!(ctrl.id, bindings::V4L2_CID_BRIGHTNESS);
+                assert_eq!(ctrl.changes, CtrlChanges::VALUE | CtrlChanges::RANGE);
+                assert_eq!(ctrl.value, -42);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+
+        event.u.ctrl = v4l2_event_ctrl {
+            changes: bindings::V4L2_EVENT_CTRL_CH_VALUE,
+            type_: bindings::v4l2_ctrl_type_V4L2_CTRL_TYPE_INTEGER64,
+            __bindgen_anon_1: v4l2_event_ctrl__bindgen_ty_1 { value64: 1 << 40 },
+            ..Default::default()
+        };
+        assert!(matches!(
+            Event::try_from(event),
+            Ok(Event::CtrlEvent(CtrlEvent { value, .. })) if value == 1 << 40
+        ));
+    }
+
+    #[test]
+    fn test_frame_sync_event() {
+        let mut event = v4l2_event {
+            type_: bindings::V4L2_EVENT_FRAME_SYNC,
+            ..Default::default()
+        };
+        event.u.frame_sync = v4l2_event_frame_sync { frame_sequence: 42 };
+        event.timestamp.tv_sec = 3;
+        event.timestamp.tv_nsec = 500;
+
+        match Event::try_from(event).unwrap() {
+            Event::FrameSync(frame_sync) => assert_eq!(
+                frame_sync,
+                FrameSyncEvent {
+                    frame_sequence: 42,
+                    timestamp: Duration::new(3, 500),
+                }
+            ),
+            e => panic!("unexpected event {:?}", e),
+        }
+    }
+}
@@ -17,23 +17,101 @@
 //! to provide safe, specialized APIs that support various V4L2 usage scenarios
 //! (camera, decoder/encoder, etc).
 //!
+//! # Tracing
+//!
+//! If the `tracing` feature is enabled, this crate emits spans and events using the
+//! [`tracing`](https://docs.rs/tracing) crate. Without it, the instrumentation compiles to
+//! nothing. Everything is emitted at the `DEBUG` level using the following targets:
+//!
+//! * `v4l2r::ioctl`: one span per call to the buffer, streaming, format, event, command and
+//!   extended controls ioctls, named after the ioctl (e.g. `VIDIOC_DQBUF`). Buffer ioctls record
+//!   the `queue`, `index`, `flags` and `bytesused` of the buffer, and all spans record the `errno`
+//!   of a failed call.
+//! * `v4l2r::queue`: state transitions of [`device::queue::Queue`] (buffers allocation and
+//!   release, streaming on and off), inside a `queue` span.
+//! * `v4l2r::decoder` and `v4l2r::encoder`: source change, resolution change and drain events of
+//!   the stateful decoder and encoder.
+//!
+//! The `queue` field is the `Debug` representation of the [`QueueType`], so the output can be
+//! restricted to a single queue with a span filter, e.g. using `tracing-subscriber`'s `EnvFilter`:
+//!
+//! ```text
+//! RUST_LOG='v4l2r[{queue=VideoCaptureMplane}]=debug'
+//! ```
+//!
+//! Enabling span close events in the subscriber gives the duration of each ioctl. See the
+//! `trace_queues` example.
+//!
+#[macro_use]
+mod trace;
+
 #[doc(hidden)]
 pub mod bindings;
+pub mod bitstream;
+pub mod buffer;
+pub mod camera;
 pub mod controls;
+pub mod converter;
 pub mod decoder;
 pub mod device;
+pub mod edid;
 pub mod encoder;
+pub mod error;
+pub mod format;
 pub mod ioctl;
 pub mod memory;
+pub mod stats;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod timecode;
+pub mod watchdog;
 
 // This can be needed to match nix errors that we expose.
 pub use nix;
 
+/// Returns whether the AV1 stateless controls (`controls::codec::Av1*`) are available.
+///
+/// These controls are only defined if the kernel headers this crate has been built against
+/// support them, which is detected at build time.
+pub const fn has_av1_support() -> bool {
+    cfg!(v4l2r_has_av1)
+}
+
+/// Returns whether the HEVC stateless controls (`controls::codec::HevcSps`, `HevcPps`,
+/// `HevcSliceParams`, `HevcDecodeParams` and `HevcScalingMatrix`) are available.
+///
+/// These controls are only defined if the kernel headers this crate has been built against
+/// support them, which is detected at build time.
+pub const fn has_hevc_support() -> bool {
+    cfg!(v4l2r_has_hevc)
+}
+
+/// Returns whether the VP9 stateless frame control (`controls::codec::Vp9Frame`) is available.
+///
+/// This control is only defined if the kernel headers this crate has been built against support
+/// it, which is detected at build time.
+pub const fn has_vp9_support() -> bool {
+    cfg!(v4l2r_has_vp9)
+}
+
+/// Returns whether explicit synchronization fences can be passed along buffers (see
+/// [`ioctl::QBuffer::set_in_fence`]).
+///
+/// Fences are not supported by mainline Linux, and are only available if the kernel headers this
+/// crate has been built against define them, which is detected at build time. Drivers must also
+/// support them, which is reported by [`ioctl::BufferCapabilities::supports_fences`].
+pub const fn has_fence_support() -> bool {
+    cfg!(v4l2r_has_fences)
+}
+
 use std::convert::TryFrom;
 use std::fmt;
-use std::fmt::{Debug, Display};
+use std::fmt::{Debug, Display, Write};
+use std::str::FromStr;
 
 use enumn::N;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 // The goal of this library is to provide two layers of abstraction:
@@ -61,6 +139,7 @@ pub enum QueueClass {
 
 /// Types of queues currently supported by this library.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, N)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(u32)]
 pub enum QueueType {
     VideoCapture = bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE,
@@ -108,6 +187,36 @@ impl QueueType {
         )
     }
 
+    /// Returns the multiplanar counterpart of a singleplanar video queue type, or `None` if the
+    /// queue type is already multiplanar or not a video type.
+    pub fn mplane_equivalent(&self) -> Option<QueueType> {
+        match self {
+            QueueType::VideoCapture => Some(QueueType::VideoCaptureMplane),
+            QueueType::VideoOutput => Some(QueueType::VideoOutputMplane),
+            _ => None,
+        }
+    }
+
+    /// Returns the singleplanar counterpart of a multiplanar video queue type, or `None` if the
+    /// queue type is not multiplanar.
+    pub fn splane_equivalent(&self) -> Option<QueueType> {
+        match self {
+            QueueType::VideoCaptureMplane => Some(QueueType::VideoCapture),
+            QueueType::VideoOutputMplane => Some(QueueType::VideoOutput),
+            _ => None,
+        }
+    }
+
+    /// Returns whether this is a CAPTURE queue type.
+    pub fn is_capture(&self) -> bool {
+        self.direction() == QueueDirection::Capture
+    }
+
+    /// Returns whether this is an OUTPUT queue type.
+    pub fn is_output(&self) -> bool {
+        self.direction() == QueueDirection::Output
+    }
+
     /// Returns the direction of the queue type (Output or Capture).
     pub fn direction(&self) -> QueueDirection {
         match self {
@@ -153,7 +262,42 @@ impl Display for QueueType {
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct PixelFormat(u32);
 
+/// Bit set in the fourcc of formats which are the big-endian variant of
+/// another format, as done by the `v4l2_fourcc_be` macro.
+const FOURCC_BE_FLAG: u32 = 1 << 31;
+
 impl PixelFormat {
+    pub const NV12: PixelFormat = PixelFormat::from_fourcc(b"NV12");
+    pub const NV21: PixelFormat = PixelFormat::from_fourcc(b"NV21");
+    pub const NV16: PixelFormat = PixelFormat::from_fourcc(b"NV16");
+    pub const NV12M: PixelFormat = PixelFormat::from_fourcc(b"NM12");
+    pub const YUV420: PixelFormat = PixelFormat::from_fourcc(b"YU12");
+    pub const YVU420: PixelFormat = PixelFormat::from_fourcc(b"YV12");
+    pub const YUV420M: PixelFormat = PixelFormat::from_fourcc(b"YM12");
+    pub const YUYV: PixelFormat = PixelFormat::from_fourcc(b"YUYV");
+    pub const UYVY: PixelFormat = PixelFormat::from_fourcc(b"UYVY");
+    pub const P010: PixelFormat = PixelFormat::from_fourcc(b"P010");
+    pub const GREY: PixelFormat = PixelFormat::from_fourcc(b"GREY");
+    pub const RGB24: PixelFormat = PixelFormat::from_fourcc(b"RGB3");
+    pub const BGR24: PixelFormat = PixelFormat::from_fourcc(b"BGR3");
+    pub const ABGR32: PixelFormat = PixelFormat::from_fourcc(b"AR24");
+    pub const XBGR32: PixelFormat = PixelFormat::from_fourcc(b"XR24");
+    pub const MJPEG: PixelFormat = PixelFormat::from_fourcc(b"MJPG");
+    pub const JPEG: PixelFormat = PixelFormat::from_fourcc(b"JPEG");
+    pub const MPEG2: PixelFormat = PixelFormat::from_fourcc(b"MPG2");
+    pub const H264: PixelFormat = PixelFormat::from_fourcc(b"H264");
+    pub const HEVC: PixelFormat = PixelFormat::from_fourcc(b"HEVC");
+    pub const VP8: PixelFormat = PixelFormat::from_fourcc(b"VP80");
+    pub const VP9: PixelFormat = PixelFormat::from_fourcc(b"VP90");
+    pub const FWHT: PixelFormat = PixelFormat::from_fourcc(b"FWHT");
+    pub const MPEG2_SLICE: PixelFormat = PixelFormat::from_fourcc(b"MG2S");
+    pub const H264_SLICE: PixelFormat = PixelFormat::from_fourcc(b"S264");
+    pub const HEVC_SLICE: PixelFormat = PixelFormat::from_fourcc(b"S265");
+    pub const VP8_FRAME: PixelFormat = PixelFormat::from_fourcc(b"VP8F");
+    pub const VP9_FRAME: PixelFormat = PixelFormat::from_fourcc(b"VP9F");
+    pub const AV1_FRAME: PixelFormat = PixelFormat::from_fourcc(b"AV1F");
+    pub const FWHT_STATELESS: PixelFormat = PixelFormat::from_fourcc(b"SFWH");
+
     pub const fn from_u32(v: u32) -> Self {
         Self(v)
     }
@@ -169,6 +313,43 @@ impl PixelFormat {
     pub const fn to_fourcc(self) -> [u8; 4] {
         self.0.to_le_bytes()
     }
+
+    /// Returns whether this is the big-endian variant of another format.
+    pub const fn is_big_endian(self) -> bool {
+        self.0 & FOURCC_BE_FLAG != 0
+    }
+
+    /// Returns whether this is a known RGB or HSV format. Such formats use full
+    /// range quantization by default.
+    pub fn is_rgb_or_hsv(self) -> bool {
+        matches!(
+            &self.to_fourcc(),
+            b"RGB1"
+                | b"R444"
+                | b"AR12"
+                | b"XR12"
+                | b"RGBO"
+                | b"AR15"
+                | b"XR15"
+                | b"RGBP"
+                | b"RGBQ"
+                | b"RGBR"
+                | b"BGR3"
+                | b"RGB3"
+                | b"BGR4"
+                | b"RGB4"
+                | b"AR24"
+                | b"XR24"
+                | b"BA24"
+                | b"BX24"
+                | b"AB24"
+                | b"XB24"
+                | b"RA24"
+                | b"RX24"
+                | b"HSV3"
+                | b"HSV4"
+        )
+    }
 }
 
 /// Converts a Fourcc in 32-bit integer format (like the ones passed in V4L2
@@ -259,6 +440,10 @@ impl fmt::Debug for PixelFormat {
 
 /// Produces a displayable form of this PixelFormat.
 ///
+/// Non-printable characters and backslashes are escaped as `\xNN`, and
+/// big-endian formats get a `-BE` suffix. The result can be parsed back using
+/// [`FromStr`].
+///
 /// # Examples
 ///
 /// ```
@@ -267,21 +452,112 @@ impl fmt::Debug for PixelFormat {
 /// let nv12 = u32::from_le(0x3231564e);
 /// let f = PixelFormat::from(nv12);
 /// assert_eq!(f.to_string(), "NV12");
+/// // Big-endian variant of a format.
+/// let rgbr = PixelFormat::from(u32::from(PixelFormat::from(b"RGBR")) | (1 << 31));
+/// assert_eq!(rgbr.to_string(), "RGBR-BE");
 /// ```
 impl fmt::Display for PixelFormat {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let fourcc = self
-            .0
-            .to_le_bytes()
-            .iter()
-            .map(|&x| x as char)
-            .collect::<String>();
-        f.write_str(fourcc.as_str())
+        for c in (self.0 & !FOURCC_BE_FLAG).to_le_bytes() {
+            if c == b' ' || (c.is_ascii_graphic() && c != b'\\') {
+                f.write_char(c as char)?;
+            } else {
+                write!(f, "\\x{:02x}", c)?;
+            }
+        }
+        if self.is_big_endian() {
+            f.write_str("-BE")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PixelFormatParseError {
+    #[error("fourcc must be 4 characters long, got {0}")]
+    InvalidLength(usize),
+    #[error("invalid character {0:?} in fourcc")]
+    InvalidCharacter(char),
+    #[error("invalid escape sequence {0:?} in fourcc")]
+    InvalidEscape(String),
+}
+
+/// Parse the 4 characters of a fourcc, which may be escaped as `\xNN`.
+fn parse_fourcc(s: &str) -> Result<[u8; 4], PixelFormatParseError> {
+    let mut fourcc = Vec::with_capacity(4);
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => {
+                let escape = chars.by_ref().take(3).collect::<String>();
+                escape
+                    .strip_prefix('x')
+                    .filter(|hex| hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| PixelFormatParseError::InvalidEscape(format!("\\{}", escape)))?
+            }
+            c if c == ' ' || c.is_ascii_graphic() => c as u8,
+            c => return Err(PixelFormatParseError::InvalidCharacter(c)),
+        };
+        fourcc.push(c);
+    }
+
+    fourcc
+        .try_into()
+        .map_err(|fourcc: Vec<u8>| PixelFormatParseError::InvalidLength(fourcc.len()))
+}
+
+/// Parses a pixel format from its [`Display`] representation. Fourccs are
+/// case-sensitive.
+///
+/// # Examples
+///
+/// ```
+/// # use v4l2r::PixelFormat;
+/// let f: PixelFormat = "NV12".parse().unwrap();
+/// assert_eq!(f, PixelFormat::NV12);
+/// assert!("nv12".parse::<PixelFormat>().unwrap() != PixelFormat::NV12);
+/// assert!("NV1".parse::<PixelFormat>().is_err());
+/// ```
+impl FromStr for PixelFormat {
+    type Err = PixelFormatParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // A fourcc can legitimately end with "-BE", so only consider it a
+        // suffix if what precedes it is a valid fourcc.
+        if let Some(fourcc) = s.strip_suffix("-BE") {
+            if let Ok(fourcc) = parse_fourcc(fourcc) {
+                return Ok(Self(u32::from_le_bytes(fourcc) | FOURCC_BE_FLAG));
+            }
+        }
+
+        parse_fourcc(s).map(|fourcc| Self::from_fourcc(&fourcc))
+    }
+}
+
+/// Serializes this PixelFormat as its [`Display`] representation.
+#[cfg(feature = "serde")]
+impl Serialize for PixelFormat {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserializes a PixelFormat from its [`Display`] representation.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for PixelFormat {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
     }
 }
 
 /// Description of a single plane in a format.
-#[derive(Debug, PartialEq, Clone, Default)]
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct PlaneLayout {
     /// Useful size of the plane ; the backing memory must be at least that large.
     pub sizeimage: u32,
@@ -293,7 +569,33 @@ pub struct PlaneLayout {
 /// and multi-planar formats. When the single-planar API is used, only
 /// one plane shall be used - attempts to have more will be rejected by the
 /// ioctl wrappers.
+///
+/// With the `serde` feature, formats can be serialized, e.g. to persist a
+/// negotiated configuration.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "serde")]
+/// # {
+/// # use v4l2r::{Format, PlaneLayout};
+/// let format = Format {
+///     plane_fmt: vec![PlaneLayout {
+///         sizeimage: 460800,
+///         bytesperline: 640,
+///     }],
+///     ..Format::from((b"NV12", (640, 480)))
+/// };
+///
+/// let saved = serde_json::to_string(&format).unwrap();
+/// assert!(saved.contains(r#""pixelformat":"NV12""#));
+///
+/// let restored: Format = serde_json::from_str(&saved).unwrap();
+/// assert_eq!(restored, format);
+/// # }
+/// ```
 #[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Format {
     /// Width of the image in pixels.
     pub width: u32,
@@ -304,6 +606,71 @@ pub struct Format {
     /// Individual layout of each plane in this format. The exact number of planes
     /// is defined by `pixelformat`.
     pub plane_fmt: Vec<PlaneLayout>,
+    /// Colorspace of the image.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub colorspace: Colorspace,
+    /// Transfer function of the image.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub xfer_func: XferFunc,
+    /// Y'CbCr encoding of the image. For HSV formats this holds the HSV
+    /// encoding instead, which can be obtained with [`Format::hsv_enc`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ycbcr_enc: YCbCrEncoding,
+    /// Quantization range of the image.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub quantization: Quantization,
+    /// Field order of the image.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub field: Field,
+}
+
+impl Format {
+    /// Returns the HSV encoding of the image, which shares its storage with
+    /// `ycbcr_enc`. Only meaningful for HSV formats.
+    pub fn hsv_enc(&self) -> HsvEncoding {
+        HsvEncoding::from(u32::from(self.ycbcr_enc))
+    }
+
+    /// Returns the transfer function of the image, resolving
+    /// [`XferFunc::Default`] according to the colorspace.
+    pub fn resolved_xfer_func(&self) -> XferFunc {
+        self.xfer_func.resolve(self.colorspace)
+    }
+
+    /// Returns the Y'CbCr encoding of the image, resolving
+    /// [`YCbCrEncoding::Default`] according to the colorspace.
+    pub fn resolved_ycbcr_enc(&self) -> YCbCrEncoding {
+        self.ycbcr_enc.resolve(self.colorspace)
+    }
+
+    /// Returns the quantization of the image, resolving
+    /// [`Quantization::Default`] according to the colorspace and pixel format.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use v4l2r::{Colorspace, Format, PixelFormat, Quantization};
+    /// let mut format = Format::from((PixelFormat::NV12, (1280, 720)));
+    /// format.colorspace = Colorspace::Rec709;
+    /// assert_eq!(format.resolved_quantization(), Quantization::LimRange);
+    ///
+    /// format.pixelformat = PixelFormat::XBGR32;
+    /// assert_eq!(format.resolved_quantization(), Quantization::FullRange);
+    /// ```
+    pub fn resolved_quantization(&self) -> Quantization {
+        self.quantization
+            .resolve(self.pixelformat.is_rgb_or_hsv(), self.colorspace)
+    }
+
+    /// Returns the colorimetry of the image.
+    pub fn color_metadata(&self) -> ColorMetadata {
+        ColorMetadata {
+            colorspace: self.colorspace,
+            xfer_func: self.xfer_func,
+            ycbcr_enc: self.ycbcr_enc,
+            quantization: self.quantization,
+        }
+    }
 }
 
 #[derive(Debug, Error, PartialEq)]
@@ -322,6 +689,7 @@ impl TryFrom<bindings::v4l2_format> for Format {
             bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE
             | bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_OUTPUT => {
                 let pix = unsafe { &fmt.fmt.pix };
+                let color = ColorMetadata::from(pix);
                 Ok(Format {
                     width: pix.width,
                     height: pix.height,
@@ -330,6 +698,11 @@ impl TryFrom<bindings::v4l2_format> for Format {
                         bytesperline: pix.bytesperline,
                         sizeimage: pix.sizeimage,
                     }],
+                    colorspace: color.colorspace,
+                    xfer_func: color.xfer_func,
+                    ycbcr_enc: color.ycbcr_enc,
+                    quantization: color.quantization,
+                    field: Field::n(pix.field).unwrap_or_default(),
                 })
             }
             bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE_MPLANE
@@ -350,11 +723,29 @@ impl TryFrom<bindings::v4l2_format> for Format {
                     });
                 }
 
+                let color = ColorMetadata::from(pix_mp);
                 Ok(Format {
                     width: pix_mp.width,
                     height: pix_mp.height,
                     pixelformat: PixelFormat::from(pix_mp.pixelformat),
                     plane_fmt,
+                    colorspace: color.colorspace,
+                    xfer_func: color.xfer_func,
+                    ycbcr_enc: color.ycbcr_enc,
+                    quantization: color.quantization,
+                    field: Field::n(pix_mp.field).unwrap_or_default(),
+                })
+            }
+            bindings::v4l2_buf_type_V4L2_BUF_TYPE_META_CAPTURE
+            | bindings::v4l2_buf_type_V4L2_BUF_TYPE_META_OUTPUT => {
+                let meta = unsafe { fmt.fmt.meta };
+                Ok(Format {
+                    pixelformat: PixelFormat::from(meta.dataformat),
+                    plane_fmt: vec![PlaneLayout {
+                        bytesperline: 0,
+                        sizeimage: meta.buffersize,
+                    }],
+                    ..Default::default()
                 })
             }
             t => Err(Self::Error::InvalidBufferType(t)),
@@ -385,8 +776,93 @@ impl<T: Into<PixelFormat>> From<(T, (usize, usize))> for Format {
     }
 }
 
+/// Format of a single-planar queue as returned by `VIDIOC_G_FMT`, see
+/// [`Device::current_format`](device::Device::current_format).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatResult {
+    pub pixel_format: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+    pub bytesperline: u32,
+    pub sizeimage: u32,
+    pub field: Field,
+    pub color_metadata: ColorMetadata,
+}
+
+impl TryFrom<bindings::v4l2_format> for FormatResult {
+    type Error = FormatConversionError;
+
+    fn try_from(fmt: bindings::v4l2_format) -> std::result::Result<Self, Self::Error> {
+        match fmt.type_ {
+            bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE
+            | bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_OUTPUT => {
+                let pix = unsafe { &fmt.fmt.pix };
+                Ok(FormatResult {
+                    pixel_format: PixelFormat::from(pix.pixelformat),
+                    width: pix.width,
+                    height: pix.height,
+                    bytesperline: pix.bytesperline,
+                    sizeimage: pix.sizeimage,
+                    field: Field::n(pix.field).unwrap_or_default(),
+                    color_metadata: ColorMetadata::from(pix),
+                })
+            }
+            t => Err(FormatConversionError::InvalidBufferType(t)),
+        }
+    }
+}
+
+/// Format of a multi-planar queue as returned by `VIDIOC_G_FMT`, see
+/// [`Device::current_mplane_format`](device::Device::current_mplane_format).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiplanarFormatResult {
+    pub pixel_format: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+    /// Layout of each memory plane of the format.
+    pub planes: Vec<PlaneLayout>,
+    pub field: Field,
+    pub color_metadata: ColorMetadata,
+}
+
+impl TryFrom<bindings::v4l2_format> for MultiplanarFormatResult {
+    type Error = FormatConversionError;
+
+    fn try_from(fmt: bindings::v4l2_format) -> std::result::Result<Self, Self::Error> {
+        match fmt.type_ {
+            bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE_MPLANE
+            | bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_OUTPUT_MPLANE => {
+                let pix_mp = unsafe { &fmt.fmt.pix_mp };
+                let planes = pix_mp
+                    .plane_fmt
+                    .get(..pix_mp.num_planes as usize)
+                    .ok_or(FormatConversionError::TooManyPlanes(
+                        pix_mp.num_planes as usize,
+                    ))?
+                    .iter()
+                    .map(|plane| PlaneLayout {
+                        sizeimage: plane.sizeimage,
+                        bytesperline: plane.bytesperline,
+                    })
+                    .collect();
+
+                Ok(MultiplanarFormatResult {
+                    pixel_format: PixelFormat::from(pix_mp.pixelformat),
+                    width: pix_mp.width,
+                    height: pix_mp.height,
+                    planes,
+                    field: Field::n(pix_mp.field).unwrap_or_default(),
+                    color_metadata: ColorMetadata::from(pix_mp),
+                })
+            }
+            t => Err(FormatConversionError::InvalidBufferType(t)),
+        }
+    }
+}
+
 /// A more elegant representation for `v4l2_rect`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Rect {
     pub left: i32,
     pub top: i32,
@@ -403,6 +879,133 @@ impl Rect {
             height,
         }
     }
+
+    /// Returns whether this rectangle has no area.
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Returns the horizontal coordinate right after this rectangle.
+    fn right(&self) -> i64 {
+        self.left as i64 + self.width as i64
+    }
+
+    /// Returns the vertical coordinate right after this rectangle.
+    fn bottom(&self) -> i64 {
+        self.top as i64 + self.height as i64
+    }
+
+    /// Returns whether `other` lies entirely within this rectangle.
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.left >= self.left
+            && other.top >= self.top
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    /// Returns the area covered by both this rectangle and `other`, or `None` if they do not
+    /// overlap.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use v4l2r::Rect;
+    /// let a = Rect::new(0, 0, 640, 480);
+    /// assert_eq!(a.intersection(&Rect::new(320, -10, 640, 100)), Some(Rect::new(320, 0, 320, 90)));
+    /// // Adjacent rectangles do not overlap.
+    /// assert_eq!(a.intersection(&Rect::new(640, 0, 10, 10)), None);
+    /// ```
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let left = self.left.max(other.left);
+        let top = self.top.max(other.top);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        if right <= left as i64 || bottom <= top as i64 {
+            return None;
+        }
+
+        Some(Rect::new(
+            left,
+            top,
+            (right - left as i64) as u32,
+            (bottom - top as i64) as u32,
+        ))
+    }
+
+    /// Returns a rectangle of the same size as this one, centered within `bounds`.
+    ///
+    /// If the margins cannot be equal, the extra pixel goes to the right or bottom margin. If
+    /// this rectangle is larger than `bounds`, it overflows it equally on both sides.
+    pub fn centered_in(&self, bounds: &Rect) -> Rect {
+        let left = bounds.left as i64 + (bounds.width as i64 - self.width as i64).div_euclid(2);
+        let top = bounds.top as i64 + (bounds.height as i64 - self.height as i64).div_euclid(2);
+
+        Rect::new(left as i32, top as i32, self.width, self.height)
+    }
+
+    /// Returns the largest rectangle with the aspect ratio of this one that fits within `bounds`,
+    /// centered within it, e.g. to letterbox a frame into a display area.
+    ///
+    /// The size of the result is rounded down, so it never exceeds `bounds`. An empty rectangle
+    /// remains empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use v4l2r::Rect;
+    /// let frame = Rect::new(0, 0, 1920, 1080);
+    /// let display = Rect::new(0, 0, 1024, 768);
+    /// assert_eq!(frame.scaled_to_fit(&display), Rect::new(0, 96, 1024, 576));
+    /// ```
+    pub fn scaled_to_fit(&self, bounds: &Rect) -> Rect {
+        let (width, height) = if self.is_empty() {
+            (0, 0)
+        } else if self.width as u64 * bounds.height as u64
+            >= bounds.width as u64 * self.height as u64
+        {
+            // Limited by the width of `bounds`.
+            let height = bounds.width as u64 * self.height as u64 / self.width as u64;
+            (bounds.width, height as u32)
+        } else {
+            // Limited by the height of `bounds`.
+            let width = bounds.height as u64 * self.width as u64 / self.height as u64;
+            (width as u32, bounds.height)
+        };
+
+        Rect::new(0, 0, width, height).centered_in(bounds)
+    }
+
+    /// Returns the largest rectangle within this one which coordinates and size are multiples
+    /// of `alignment`, e.g. 2 for formats with subsampled chroma. An `alignment` of 0 is
+    /// considered to be 1.
+    ///
+    /// The result is zero-sized if no such rectangle exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use v4l2r::Rect;
+    /// let rect = Rect::new(1, -3, 101, 50);
+    /// assert_eq!(rect.aligned(2), Rect::new(2, -2, 100, 48));
+    /// ```
+    pub fn aligned(&self, alignment: u32) -> Rect {
+        let alignment = alignment.max(1) as i64;
+        let round_up = |v: i64| (v + alignment - 1).div_euclid(alignment) * alignment;
+        let round_down = |v: i64| v.div_euclid(alignment) * alignment;
+
+        let left = round_up(self.left as i64);
+        let top = round_up(self.top as i64);
+        let right = round_down(self.right());
+        let bottom = round_down(self.bottom());
+
+        Rect::new(
+            left as i32,
+            top as i32,
+            (right - left).max(0) as u32,
+            (bottom - top).max(0) as u32,
+        )
+    }
 }
 
 impl From<bindings::v4l2_rect> for Rect {
@@ -443,63 +1046,1026 @@ impl Display for Rect {
     }
 }
 
-/// Equivalent of `enum v4l2_colorspace`.
-#[repr(u32)]
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, N)]
-pub enum Colorspace {
-    #[default]
-    Default = bindings::v4l2_colorspace_V4L2_COLORSPACE_DEFAULT,
-    Smpte170M = bindings::v4l2_colorspace_V4L2_COLORSPACE_SMPTE170M,
-    Smpte240M = bindings::v4l2_colorspace_V4L2_COLORSPACE_SMPTE240M,
-    Rec709 = bindings::v4l2_colorspace_V4L2_COLORSPACE_REC709,
-    Bt878 = bindings::v4l2_colorspace_V4L2_COLORSPACE_BT878,
-    SystemM470 = bindings::v4l2_colorspace_V4L2_COLORSPACE_470_SYSTEM_M,
-    SystemBG470 = bindings::v4l2_colorspace_V4L2_COLORSPACE_470_SYSTEM_BG,
-    Jpeg = bindings::v4l2_colorspace_V4L2_COLORSPACE_JPEG,
-    Srgb = bindings::v4l2_colorspace_V4L2_COLORSPACE_SRGB,
-    OpRgb = bindings::v4l2_colorspace_V4L2_COLORSPACE_OPRGB,
-    Bt2020 = bindings::v4l2_colorspace_V4L2_COLORSPACE_BT2020,
-    Raw = bindings::v4l2_colorspace_V4L2_COLORSPACE_RAW,
-    DciP3 = bindings::v4l2_colorspace_V4L2_COLORSPACE_DCI_P3,
-}
-
-/// Equivalent of `enum v4l2_xfer_func`.
-#[repr(u32)]
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, N)]
-pub enum XferFunc {
-    #[default]
-    Default = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_DEFAULT,
-    F709 = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_709,
-    Srgb = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_SRGB,
-    OpRgb = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_OPRGB,
-    Smpte240M = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_SMPTE240M,
-    None = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_NONE,
-    DciP3 = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_DCI_P3,
-    Smpte2084 = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_SMPTE2084,
+/// A more elegant representation for `v4l2_fract`, used e.g. for frame
+/// intervals.
+///
+/// Fractions are compared by value, so e.g. `2/60` is equal to `1/30`. This
+/// allows frame intervals returned by drivers to be compared exactly against
+/// broadcast rates like [`Fraction::INTERVAL_29_97`], which floating point
+/// values cannot represent.
+///
+/// # Examples
+///
+/// ```
+/// # use v4l2r::Fraction;
+/// let interval = Fraction::from((2002, 60000));
+/// assert_eq!(interval, Fraction::INTERVAL_29_97);
+/// assert_eq!(interval.simplified(), Fraction::new(1001, 30000));
+/// assert!(interval > Fraction::INTERVAL_30);
+/// ```
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Fraction {
+    pub numerator: u32,
+    pub denominator: u32,
 }
 
-/// Equivalent of `enum v4l2_ycbcr_encoding`.
-#[repr(u32)]
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, N)]
-pub enum YCbCrEncoding {
-    #[default]
-    Default = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_DEFAULT,
-    E601 = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_601,
-    E709 = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_709,
-    Xv601 = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_XV601,
-    Xv709 = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_XV709,
-    Sycc = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_SYCC,
-    Bt2020 = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_BT2020,
-    Bt2020ConstLum = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_BT2020_CONST_LUM,
-    Smpte240M = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_SMPTE240M,
-}
-
-/// Equivalent of `enum v4l2_quantization`.
+/// Returns the greatest common divisor of `a` and `b`, or 0 if both are 0.
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+
+    a
+}
+
+impl Fraction {
+    /// Frame interval of 23.976 (24000/1001) frames per second.
+    pub const INTERVAL_23_976: Fraction = Fraction::new(1001, 24000);
+    /// Frame interval of 24 frames per second.
+    pub const INTERVAL_24: Fraction = Fraction::new(1, 24);
+    /// Frame interval of 25 frames per second.
+    pub const INTERVAL_25: Fraction = Fraction::new(1, 25);
+    /// Frame interval of 29.97 (30000/1001) frames per second.
+    pub const INTERVAL_29_97: Fraction = Fraction::new(1001, 30000);
+    /// Frame interval of 30 frames per second.
+    pub const INTERVAL_30: Fraction = Fraction::new(1, 30);
+    /// Frame interval of 50 frames per second.
+    pub const INTERVAL_50: Fraction = Fraction::new(1, 50);
+    /// Frame interval of 59.94 (60000/1001) frames per second.
+    pub const INTERVAL_59_94: Fraction = Fraction::new(1001, 60000);
+    /// Frame interval of 60 frames per second.
+    pub const INTERVAL_60: Fraction = Fraction::new(1, 60);
+
+    /// Create a new fraction without checking its denominator. Fractions with
+    /// a zero denominator are still valid values, e.g. to tell the driver to
+    /// pick a frame interval.
+    pub const fn new(numerator: u32, denominator: u32) -> Fraction {
+        Fraction {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// Create a new fraction, or return `None` if `denominator` is zero.
+    pub const fn checked_new(numerator: u32, denominator: u32) -> Option<Fraction> {
+        if denominator == 0 {
+            None
+        } else {
+            Some(Fraction::new(numerator, denominator))
+        }
+    }
+
+    /// Returns this fraction reduced to its lowest terms.
+    pub fn simplified(&self) -> Fraction {
+        match gcd(self.numerator as u64, self.denominator as u64) as u32 {
+            0 => *self,
+            divisor => Fraction::new(self.numerator / divisor, self.denominator / divisor),
+        }
+    }
+
+    /// Returns the inverse of this fraction, e.g. to convert a frame interval
+    /// into a frame rate.
+    pub fn recip(&self) -> Fraction {
+        Fraction::new(self.denominator, self.numerator)
+    }
+
+    /// Returns the product of this fraction and `other` in its lowest terms,
+    /// or `None` if it cannot be represented.
+    pub fn checked_mul(&self, other: Fraction) -> Option<Fraction> {
+        let numerator = self.numerator as u64 * other.numerator as u64;
+        let denominator = self.denominator as u64 * other.denominator as u64;
+        let divisor = gcd(numerator, denominator).max(1);
+
+        Some(Fraction::new(
+            u32::try_from(numerator / divisor).ok()?,
+            u32::try_from(denominator / divisor).ok()?,
+        ))
+    }
+
+    /// Returns the number of frames per second when this fraction is a frame
+    /// interval, i.e. `denominator / numerator`. The result is approximate and
+    /// only meant for display.
+    pub fn fps(&self) -> f64 {
+        self.denominator as f64 / self.numerator as f64
+    }
+}
+
+impl PartialEq for Fraction {
+    fn eq(&self, other: &Self) -> bool {
+        let a = self.simplified();
+        let b = other.simplified();
+
+        a.numerator == b.numerator && a.denominator == b.denominator
+    }
+}
+
+impl Eq for Fraction {}
+
+/// Fractions are ordered by value. Fractions with a zero denominator cannot be
+/// ordered, but compare equal to the fractions they are equal to.
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        if self.denominator == 0 || other.denominator == 0 {
+            return (self == other).then_some(std::cmp::Ordering::Equal);
+        }
+
+        let a = self.numerator as u64 * other.denominator as u64;
+        let b = other.numerator as u64 * self.denominator as u64;
+        Some(a.cmp(&b))
+    }
+}
+
+impl From<(u32, u32)> for Fraction {
+    fn from((numerator, denominator): (u32, u32)) -> Self {
+        Fraction::new(numerator, denominator)
+    }
+}
+
+impl From<bindings::v4l2_fract> for Fraction {
+    fn from(fract: bindings::v4l2_fract) -> Self {
+        Fraction {
+            numerator: fract.numerator,
+            denominator: fract.denominator,
+        }
+    }
+}
+
+impl From<Fraction> for bindings::v4l2_fract {
+    fn from(fraction: Fraction) -> Self {
+        bindings::v4l2_fract {
+            numerator: fraction.numerator,
+            denominator: fraction.denominator,
+        }
+    }
+}
+
+impl Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+/// Declares a Rust equivalent of a V4L2 colorimetry enum. Values unknown to
+/// this crate are preserved in an `Unknown` variant instead of being rejected,
+/// so they can be passed back to the driver unchanged.
+macro_rules! colorimetry_enum {
+    (
+        $(#[$attr:meta])*
+        pub enum $name:ident {
+            $($(#[$vattr:meta])* $variant:ident = $value:path,)*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+        pub enum $name {
+            $($(#[$vattr])* $variant,)*
+            /// Value not known to this crate.
+            Unknown(u32),
+        }
+
+        impl $name {
+            /// Returns the variant matching `value`, or `None` if it is unknown.
+            pub fn known(value: u32) -> Option<Self> {
+                match Self::from(value) {
+                    $name::Unknown(_) => None,
+                    known => Some(known),
+                }
+            }
+        }
+
+        impl From<u32> for $name {
+            fn from(value: u32) -> Self {
+                match value {
+                    $($value => $name::$variant,)*
+                    value => $name::Unknown(value),
+                }
+            }
+        }
+
+        impl From<$name> for u32 {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => $value,)*
+                    $name::Unknown(value) => value,
+                }
+            }
+        }
+    };
+}
+
+colorimetry_enum! {
+    /// Equivalent of `enum v4l2_colorspace`.
+    #[derive(Default)]
+    pub enum Colorspace {
+        #[default]
+        Default = bindings::v4l2_colorspace_V4L2_COLORSPACE_DEFAULT,
+        Smpte170M = bindings::v4l2_colorspace_V4L2_COLORSPACE_SMPTE170M,
+        Smpte240M = bindings::v4l2_colorspace_V4L2_COLORSPACE_SMPTE240M,
+        Rec709 = bindings::v4l2_colorspace_V4L2_COLORSPACE_REC709,
+        Bt878 = bindings::v4l2_colorspace_V4L2_COLORSPACE_BT878,
+        SystemM470 = bindings::v4l2_colorspace_V4L2_COLORSPACE_470_SYSTEM_M,
+        SystemBG470 = bindings::v4l2_colorspace_V4L2_COLORSPACE_470_SYSTEM_BG,
+        Jpeg = bindings::v4l2_colorspace_V4L2_COLORSPACE_JPEG,
+        Srgb = bindings::v4l2_colorspace_V4L2_COLORSPACE_SRGB,
+        OpRgb = bindings::v4l2_colorspace_V4L2_COLORSPACE_OPRGB,
+        Bt2020 = bindings::v4l2_colorspace_V4L2_COLORSPACE_BT2020,
+        Raw = bindings::v4l2_colorspace_V4L2_COLORSPACE_RAW,
+        DciP3 = bindings::v4l2_colorspace_V4L2_COLORSPACE_DCI_P3,
+    }
+}
+
+colorimetry_enum! {
+    /// Equivalent of `enum v4l2_xfer_func`.
+    #[derive(Default)]
+    pub enum XferFunc {
+        #[default]
+        Default = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_DEFAULT,
+        F709 = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_709,
+        Srgb = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_SRGB,
+        OpRgb = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_OPRGB,
+        Smpte240M = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_SMPTE240M,
+        None = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_NONE,
+        DciP3 = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_DCI_P3,
+        Smpte2084 = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_SMPTE2084,
+    }
+}
+
+colorimetry_enum! {
+    /// Equivalent of `enum v4l2_ycbcr_encoding`.
+    #[derive(Default)]
+    pub enum YCbCrEncoding {
+        #[default]
+        Default = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_DEFAULT,
+        E601 = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_601,
+        E709 = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_709,
+        Xv601 = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_XV601,
+        Xv709 = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_XV709,
+        Sycc = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_SYCC,
+        Bt2020 = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_BT2020,
+        Bt2020ConstLum = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_BT2020_CONST_LUM,
+        Smpte240M = bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_SMPTE240M,
+    }
+}
+
+colorimetry_enum! {
+    /// Equivalent of `enum v4l2_hsv_encoding`. It shares its storage with
+    /// [`YCbCrEncoding`] and is used instead of it for HSV formats.
+    pub enum HsvEncoding {
+        Hsv180 = bindings::v4l2_hsv_encoding_V4L2_HSV_ENC_180,
+        Hsv256 = bindings::v4l2_hsv_encoding_V4L2_HSV_ENC_256,
+    }
+}
+
+colorimetry_enum! {
+    /// Equivalent of `enum v4l2_quantization`.
+    #[derive(Default)]
+    pub enum Quantization {
+        #[default]
+        Default = bindings::v4l2_quantization_V4L2_QUANTIZATION_DEFAULT,
+        FullRange = bindings::v4l2_quantization_V4L2_QUANTIZATION_FULL_RANGE,
+        LimRange = bindings::v4l2_quantization_V4L2_QUANTIZATION_LIM_RANGE,
+    }
+}
+
+impl XferFunc {
+    /// Returns the default transfer function of `colorspace`. Equivalent of
+    /// the `V4L2_MAP_XFER_FUNC_DEFAULT` macro.
+    pub fn default_for(colorspace: Colorspace) -> XferFunc {
+        match colorspace {
+            Colorspace::OpRgb => XferFunc::OpRgb,
+            Colorspace::Smpte240M => XferFunc::Smpte240M,
+            Colorspace::DciP3 => XferFunc::DciP3,
+            Colorspace::Raw => XferFunc::None,
+            Colorspace::Srgb | Colorspace::Jpeg => XferFunc::Srgb,
+            _ => XferFunc::F709,
+        }
+    }
+
+    /// Returns this transfer function, or the default one of `colorspace` if
+    /// it is [`XferFunc::Default`].
+    pub fn resolve(self, colorspace: Colorspace) -> XferFunc {
+        match self {
+            XferFunc::Default => XferFunc::default_for(colorspace),
+            xfer_func => xfer_func,
+        }
+    }
+}
+
+impl YCbCrEncoding {
+    /// Returns the default Y'CbCr encoding of `colorspace`. Equivalent of the
+    /// `V4L2_MAP_YCBCR_ENC_DEFAULT` macro.
+    pub fn default_for(colorspace: Colorspace) -> YCbCrEncoding {
+        match colorspace {
+            Colorspace::Rec709 | Colorspace::DciP3 => YCbCrEncoding::E709,
+            Colorspace::Bt2020 => YCbCrEncoding::Bt2020,
+            Colorspace::Smpte240M => YCbCrEncoding::Smpte240M,
+            _ => YCbCrEncoding::E601,
+        }
+    }
+
+    /// Returns this encoding, or the default one of `colorspace` if it is
+    /// [`YCbCrEncoding::Default`].
+    pub fn resolve(self, colorspace: Colorspace) -> YCbCrEncoding {
+        match self {
+            YCbCrEncoding::Default => YCbCrEncoding::default_for(colorspace),
+            ycbcr_enc => ycbcr_enc,
+        }
+    }
+}
+
+impl Quantization {
+    /// Returns the default quantization of a format using `colorspace`.
+    /// Equivalent of the `V4L2_MAP_QUANTIZATION_DEFAULT` macro.
+    ///
+    /// RGB and HSV formats, as well as JPEG, use full range. Everything else
+    /// uses limited range. Like the kernel macro, the Y'CbCr encoding does not
+    /// influence the result.
+    pub fn default_for(is_rgb_or_hsv: bool, colorspace: Colorspace) -> Quantization {
+        if is_rgb_or_hsv || colorspace == Colorspace::Jpeg {
+            Quantization::FullRange
+        } else {
+            Quantization::LimRange
+        }
+    }
+
+    /// Returns this quantization, or the default one if it is
+    /// [`Quantization::Default`].
+    pub fn resolve(self, is_rgb_or_hsv: bool, colorspace: Colorspace) -> Quantization {
+        match self {
+            Quantization::Default => Quantization::default_for(is_rgb_or_hsv, colorspace),
+            quantization => quantization,
+        }
+    }
+}
+
+/// Colorimetry of an image, i.e. the colorspace-related fields of a format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ColorMetadata {
+    pub colorspace: Colorspace,
+    pub xfer_func: XferFunc,
+    /// Y'CbCr encoding, or HSV encoding for HSV formats.
+    pub ycbcr_enc: YCbCrEncoding,
+    pub quantization: Quantization,
+}
+
+impl From<&bindings::v4l2_pix_format> for ColorMetadata {
+    fn from(pix: &bindings::v4l2_pix_format) -> Self {
+        // The extended colorimetry fields are only valid if `priv_` is set to the magic value.
+        let (ycbcr_enc, quantization, xfer_func) = if pix.priv_ == bindings::V4L2_PIX_FMT_PRIV_MAGIC
+        {
+            (
+                unsafe { pix.__bindgen_anon_1.ycbcr_enc },
+                pix.quantization,
+                pix.xfer_func,
+            )
+        } else {
+            Default::default()
+        };
+
+        ColorMetadata {
+            colorspace: pix.colorspace.into(),
+            xfer_func: xfer_func.into(),
+            ycbcr_enc: ycbcr_enc.into(),
+            quantization: quantization.into(),
+        }
+    }
+}
+
+impl From<&bindings::v4l2_pix_format_mplane> for ColorMetadata {
+    fn from(pix_mp: &bindings::v4l2_pix_format_mplane) -> Self {
+        ColorMetadata {
+            colorspace: pix_mp.colorspace.into(),
+            xfer_func: (pix_mp.xfer_func as u32).into(),
+            ycbcr_enc: (unsafe { pix_mp.__bindgen_anon_1.ycbcr_enc } as u32).into(),
+            quantization: (pix_mp.quantization as u32).into(),
+        }
+    }
+}
+
+/// Equivalent of `enum v4l2_field`: how the fields of an interlaced image are stored in a
+/// format, or which fields are contained in a buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, N)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(u32)]
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, N)]
-pub enum Quantization {
+pub enum Field {
+    /// Let the driver choose. Only valid when setting a format or queueing a buffer.
     #[default]
-    Default = bindings::v4l2_quantization_V4L2_QUANTIZATION_DEFAULT,
-    FullRange = bindings::v4l2_quantization_V4L2_QUANTIZATION_FULL_RANGE,
-    LimRange = bindings::v4l2_quantization_V4L2_QUANTIZATION_LIM_RANGE,
+    Any = bindings::v4l2_field_V4L2_FIELD_ANY,
+    /// Progressive image.
+    None = bindings::v4l2_field_V4L2_FIELD_NONE,
+    /// Top field only.
+    Top = bindings::v4l2_field_V4L2_FIELD_TOP,
+    /// Bottom field only.
+    Bottom = bindings::v4l2_field_V4L2_FIELD_BOTTOM,
+    /// Both fields interleaved line by line, the first one in time depending on the standard.
+    Interlaced = bindings::v4l2_field_V4L2_FIELD_INTERLACED,
+    /// Both fields one after the other, top field first.
+    SeqTb = bindings::v4l2_field_V4L2_FIELD_SEQ_TB,
+    /// Both fields one after the other, bottom field first.
+    SeqBt = bindings::v4l2_field_V4L2_FIELD_SEQ_BT,
+    /// One field per buffer, alternating between top and bottom. The field contained in each
+    /// buffer is reported in its `field` member, and the height of the format is the height of
+    /// a field.
+    Alternate = bindings::v4l2_field_V4L2_FIELD_ALTERNATE,
+    /// Both fields interleaved line by line, top field first in time.
+    InterlacedTb = bindings::v4l2_field_V4L2_FIELD_INTERLACED_TB,
+    /// Both fields interleaved line by line, bottom field first in time.
+    InterlacedBt = bindings::v4l2_field_V4L2_FIELD_INTERLACED_BT,
+}
+
+impl Field {
+    /// Returns whether an image using this field order contains the top field. Equivalent of
+    /// the `V4L2_FIELD_HAS_TOP` macro.
+    pub fn has_top(self) -> bool {
+        !matches!(
+            self,
+            Field::Any | Field::None | Field::Bottom | Field::Alternate
+        )
+    }
+
+    /// Returns whether an image using this field order contains the bottom field. Equivalent of
+    /// the `V4L2_FIELD_HAS_BOTTOM` macro.
+    pub fn has_bottom(self) -> bool {
+        !matches!(
+            self,
+            Field::Any | Field::None | Field::Top | Field::Alternate
+        )
+    }
+
+    /// Returns whether an image using this field order contains both fields. Equivalent of the
+    /// `V4L2_FIELD_HAS_BOTH` macro.
+    pub fn has_both(self) -> bool {
+        self.has_top() && self.has_bottom()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns `count` pseudo-random rectangles, generated using a fixed seed.
+    fn random_rects(count: usize) -> Vec<Rect> {
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut next = move |max: u32| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % max as u64) as u32
+        };
+
+        (0..count)
+            .map(|_| {
+                Rect::new(
+                    next(200) as i32 - 100,
+                    next(200) as i32 - 100,
+                    next(150),
+                    next(150),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn format_result_color_metadata() {
+        let mut fmt = bindings::v4l2_format {
+            type_: bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE,
+            ..Default::default()
+        };
+        let pix = unsafe { &mut fmt.fmt.pix };
+        pix.width = 640;
+        pix.height = 480;
+        pix.pixelformat = PixelFormat::from(b"YUYV").into();
+        pix.bytesperline = 1280;
+        pix.sizeimage = 614400;
+        pix.field = bindings::v4l2_field_V4L2_FIELD_NONE;
+        pix.colorspace = bindings::v4l2_colorspace_V4L2_COLORSPACE_REC709;
+        pix.xfer_func = bindings::v4l2_xfer_func_V4L2_XFER_FUNC_709;
+        pix.quantization = bindings::v4l2_quantization_V4L2_QUANTIZATION_LIM_RANGE;
+
+        // The extended fields are ignored without the magic value.
+        let result = FormatResult::try_from(fmt).unwrap();
+        assert_eq!(result.pixel_format, PixelFormat::from(b"YUYV"));
+        assert_eq!((result.width, result.height), (640, 480));
+        assert_eq!((result.bytesperline, result.sizeimage), (1280, 614400));
+        assert_eq!(result.field, Field::None);
+        assert_eq!(
+            result.color_metadata,
+            ColorMetadata {
+                colorspace: Colorspace::Rec709,
+                ..Default::default()
+            }
+        );
+
+        fmt.fmt.pix.priv_ = bindings::V4L2_PIX_FMT_PRIV_MAGIC;
+        let result = FormatResult::try_from(fmt).unwrap();
+        assert_eq!(result.color_metadata.xfer_func, XferFunc::F709);
+        assert_eq!(result.color_metadata.quantization, Quantization::LimRange);
+        assert_eq!(
+            result.color_metadata,
+            Format::try_from(fmt).unwrap().color_metadata()
+        );
+        assert_eq!(
+            MultiplanarFormatResult::try_from(fmt),
+            Err(FormatConversionError::InvalidBufferType(fmt.type_))
+        );
+
+        let mut fmt = bindings::v4l2_format {
+            type_: bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_OUTPUT_MPLANE,
+            ..Default::default()
+        };
+        let pix_mp = unsafe { &mut fmt.fmt.pix_mp };
+        pix_mp.pixelformat = PixelFormat::from(b"NM12").into();
+        pix_mp.num_planes = 2;
+        pix_mp.plane_fmt[0].bytesperline = 640;
+        pix_mp.plane_fmt[0].sizeimage = 307200;
+        pix_mp.plane_fmt[1].bytesperline = 640;
+        pix_mp.plane_fmt[1].sizeimage = 153600;
+        pix_mp.colorspace = bindings::v4l2_colorspace_V4L2_COLORSPACE_BT2020;
+        pix_mp.__bindgen_anon_1 = bindings::v4l2_pix_format_mplane__bindgen_ty_1 {
+            ycbcr_enc: bindings::v4l2_ycbcr_encoding_V4L2_YCBCR_ENC_BT2020 as u8,
+        };
+
+        let result = MultiplanarFormatResult::try_from(fmt).unwrap();
+        assert_eq!(result.planes.len(), 2);
+        assert_eq!(result.planes[1].sizeimage, 153600);
+        assert_eq!(result.color_metadata.colorspace, Colorspace::Bt2020);
+        assert_eq!(result.color_metadata.ycbcr_enc, YCbCrEncoding::Bt2020);
+        assert!(FormatResult::try_from(fmt).is_err());
+
+        fmt.fmt.pix_mp.num_planes = 9;
+        assert_eq!(
+            MultiplanarFormatResult::try_from(fmt),
+            Err(FormatConversionError::TooManyPlanes(9))
+        );
+    }
+
+    #[test]
+    fn colorimetry_unknown_values() {
+        assert_eq!(Colorspace::from(9), Colorspace::OpRgb);
+        assert_eq!(Colorspace::from(0xff), Colorspace::Unknown(0xff));
+        assert_eq!(u32::from(Colorspace::Unknown(0xff)), 0xff);
+        assert_eq!(Colorspace::known(0xff), None);
+        assert_eq!(Colorspace::try_from(12).unwrap(), Colorspace::DciP3);
+        assert_eq!(XferFunc::from(8), XferFunc::Unknown(8));
+        assert_eq!(YCbCrEncoding::from(8), YCbCrEncoding::Smpte240M);
+        assert_eq!(Quantization::known(2), Some(Quantization::LimRange));
+        assert_eq!(HsvEncoding::from(128), HsvEncoding::Hsv180);
+        assert_eq!(HsvEncoding::from(0), HsvEncoding::Unknown(0));
+
+        // HSV encodings are stored in the Y'CbCr encoding field.
+        let format = Format {
+            ycbcr_enc: YCbCrEncoding::from(129),
+            ..Format::from((b"HSV3", (640, 480)))
+        };
+        assert_eq!(format.ycbcr_enc, YCbCrEncoding::Unknown(129));
+        assert_eq!(format.hsv_enc(), HsvEncoding::Hsv256);
+    }
+
+    #[test]
+    fn colorimetry_defaults() {
+        use Colorspace as Cs;
+        use Quantization as Q;
+        use XferFunc as Xf;
+        use YCbCrEncoding as Enc;
+
+        // Expected results of the `V4L2_MAP_XFER_FUNC_DEFAULT`,
+        // `V4L2_MAP_YCBCR_ENC_DEFAULT` and `V4L2_MAP_QUANTIZATION_DEFAULT` (for
+        // Y'CbCr formats) macros for each colorspace.
+        let table = [
+            (Cs::Default, Xf::F709, Enc::E601, Q::LimRange),
+            (Cs::Smpte170M, Xf::F709, Enc::E601, Q::LimRange),
+            (Cs::Smpte240M, Xf::Smpte240M, Enc::Smpte240M, Q::LimRange),
+            (Cs::Rec709, Xf::F709, Enc::E709, Q::LimRange),
+            (Cs::Bt878, Xf::F709, Enc::E601, Q::LimRange),
+            (Cs::SystemM470, Xf::F709, Enc::E601, Q::LimRange),
+            (Cs::SystemBG470, Xf::F709, Enc::E601, Q::LimRange),
+            (Cs::Jpeg, Xf::Srgb, Enc::E601, Q::FullRange),
+            (Cs::Srgb, Xf::Srgb, Enc::E601, Q::LimRange),
+            (Cs::OpRgb, Xf::OpRgb, Enc::E601, Q::LimRange),
+            (Cs::Bt2020, Xf::F709, Enc::Bt2020, Q::LimRange),
+            (Cs::Raw, Xf::None, Enc::E601, Q::LimRange),
+            (Cs::DciP3, Xf::DciP3, Enc::E709, Q::LimRange),
+            (Cs::Unknown(42), Xf::F709, Enc::E601, Q::LimRange),
+        ];
+
+        for (colorspace, xfer_func, ycbcr_enc, quantization) in table {
+            assert_eq!(Xf::default_for(colorspace), xfer_func, "{colorspace:?}");
+            assert_eq!(Enc::default_for(colorspace), ycbcr_enc, "{colorspace:?}");
+            assert_eq!(
+                Q::default_for(false, colorspace),
+                quantization,
+                "{colorspace:?}"
+            );
+            // RGB and HSV formats always use full range.
+            assert_eq!(
+                Q::default_for(true, colorspace),
+                Q::FullRange,
+                "{colorspace:?}"
+            );
+
+            let yuv = Format {
+                colorspace,
+                ..Format::from((PixelFormat::NV12, (640, 480)))
+            };
+            assert_eq!(yuv.resolved_xfer_func(), xfer_func);
+            assert_eq!(yuv.resolved_ycbcr_enc(), ycbcr_enc);
+            assert_eq!(yuv.resolved_quantization(), quantization);
+            let rgb = Format {
+                pixelformat: PixelFormat::XBGR32,
+                ..yuv.clone()
+            };
+            assert_eq!(rgb.resolved_quantization(), Q::FullRange);
+            let hsv = Format {
+                pixelformat: PixelFormat::from(b"HSV4"),
+                ..yuv
+            };
+            assert_eq!(hsv.resolved_quantization(), Q::FullRange);
+        }
+    }
+
+    #[test]
+    fn colorimetry_explicit_values() {
+        // Values that are not `Default` are never overridden.
+        let format = Format {
+            colorspace: Colorspace::Bt2020,
+            xfer_func: XferFunc::Smpte2084,
+            ycbcr_enc: YCbCrEncoding::Bt2020ConstLum,
+            quantization: Quantization::FullRange,
+            ..Format::from((PixelFormat::P010, (3840, 2160)))
+        };
+        assert_eq!(format.resolved_xfer_func(), XferFunc::Smpte2084);
+        assert_eq!(format.resolved_ycbcr_enc(), YCbCrEncoding::Bt2020ConstLum);
+        assert_eq!(format.resolved_quantization(), Quantization::FullRange);
+
+        // Unknown values are not resolved either.
+        assert_eq!(
+            XferFunc::Unknown(42).resolve(Colorspace::Srgb),
+            XferFunc::Unknown(42)
+        );
+        assert_eq!(
+            Quantization::LimRange.resolve(true, Colorspace::Jpeg),
+            Quantization::LimRange
+        );
+    }
+
+    #[test]
+    fn fraction_ntsc() {
+        assert_eq!(Fraction::from((1001, 30000)), Fraction::INTERVAL_29_97);
+        assert_eq!(Fraction::from((2002, 60000)), Fraction::INTERVAL_29_97);
+        assert_eq!(Fraction::INTERVAL_59_94.recip(), Fraction::new(60000, 1001));
+        assert_ne!(Fraction::INTERVAL_29_97, Fraction::INTERVAL_30);
+        assert_ne!(Fraction::INTERVAL_23_976, Fraction::INTERVAL_24);
+        assert!(Fraction::INTERVAL_29_97 > Fraction::INTERVAL_30);
+        assert!(Fraction::INTERVAL_59_94 < Fraction::INTERVAL_29_97);
+        // Two fields make a frame.
+        assert_eq!(
+            Fraction::INTERVAL_59_94.checked_mul(Fraction::new(2, 1)),
+            Some(Fraction::INTERVAL_29_97)
+        );
+
+        // The floating point rates are only approximate.
+        assert!((Fraction::INTERVAL_29_97.fps() - 29.97).abs() < 0.001);
+        assert!((Fraction::INTERVAL_23_976.fps() - 23.976).abs() < 0.001);
+        assert!((Fraction::INTERVAL_59_94.fps() - 59.94).abs() < 0.001);
+        assert_eq!(Fraction::INTERVAL_25.fps(), 25.0);
+    }
+
+    #[test]
+    fn fraction_zero_denominator() {
+        use std::cmp::Ordering;
+
+        // `PartialEq` and `PartialOrd` must agree.
+        let infinite = Fraction::new(1, 0);
+        assert_eq!(infinite, Fraction::new(2, 0));
+        assert_eq!(
+            infinite.partial_cmp(&Fraction::new(2, 0)),
+            Some(Ordering::Equal)
+        );
+        let undefined = Fraction::new(0, 0);
+        assert_eq!(undefined, undefined);
+        assert_eq!(undefined.partial_cmp(&undefined), Some(Ordering::Equal));
+
+        assert_ne!(infinite, undefined);
+        assert_eq!(infinite.partial_cmp(&undefined), None);
+        assert_eq!(undefined.partial_cmp(&Fraction::new(0, 1)), None);
+        assert_eq!(infinite.partial_cmp(&Fraction::INTERVAL_30), None);
+    }
+
+    #[test]
+    fn field_has_top_bottom() {
+        assert!(Field::Interlaced.has_both());
+        assert!(Field::SeqBt.has_both());
+        assert!(Field::Top.has_top() && !Field::Top.has_bottom());
+        assert!(Field::Bottom.has_bottom() && !Field::Bottom.has_top());
+        // Alternate buffers contain a single field, which is given by the buffer.
+        assert!(!Field::Alternate.has_top() && !Field::Alternate.has_bottom());
+        assert!(!Field::None.has_top() && !Field::Any.has_bottom());
+
+        assert_eq!(
+            Field::n(bindings::v4l2_field_V4L2_FIELD_BOTTOM),
+            Some(Field::Bottom)
+        );
+    }
+
+    #[test]
+    fn fraction_arithmetic() {
+        assert_eq!(
+            Fraction::new(30000, 1001).simplified(),
+            Fraction::new(30000, 1001)
+        );
+        let simplified = Fraction::new(120, 3600).simplified();
+        assert_eq!((simplified.numerator, simplified.denominator), (1, 30));
+        let zero = Fraction::new(0, 25).simplified();
+        assert_eq!((zero.numerator, zero.denominator), (0, 1));
+        let undefined = Fraction::new(0, 0).simplified();
+        assert_eq!((undefined.numerator, undefined.denominator), (0, 0));
+
+        assert_eq!(Fraction::checked_new(1, 0), None);
+        assert_eq!(Fraction::checked_new(1, 30), Some(Fraction::INTERVAL_30));
+        assert_eq!(
+            Fraction::new(1, 0).partial_cmp(&Fraction::INTERVAL_30),
+            None
+        );
+        assert_eq!(
+            Fraction::new(u32::MAX, 1).checked_mul(Fraction::new(2, 1)),
+            None
+        );
+        assert_eq!(
+            Fraction::new(u32::MAX, 2).checked_mul(Fraction::new(2, 3)),
+            Some(Fraction::new(u32::MAX, 3))
+        );
+        assert_eq!(
+            Fraction::from(bindings::v4l2_fract {
+                numerator: 1,
+                denominator: 30
+            }),
+            Fraction::INTERVAL_30
+        );
+    }
+
+    #[test]
+    fn rect_intersection() {
+        let rects = random_rects(64);
+        for a in &rects {
+            if !a.is_empty() {
+                assert_eq!(a.intersection(a), Some(*a));
+            }
+            for b in &rects {
+                let intersection = a.intersection(b);
+                assert_eq!(intersection, b.intersection(a));
+                match intersection {
+                    Some(i) => {
+                        assert!(!i.is_empty());
+                        assert!(a.contains(&i) && b.contains(&i), "{} {} {}", a, b, i);
+                    }
+                    // Rectangles that do not overlap have no pixel in common.
+                    None => assert!(
+                        a.is_empty()
+                            || b.is_empty()
+                            || a.right() <= b.left as i64
+                            || b.right() <= a.left as i64
+                            || a.bottom() <= b.top as i64
+                            || b.bottom() <= a.top as i64,
+                        "{} {}",
+                        a,
+                        b
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rect_contains() {
+        let bounds = Rect::new(0, 0, 10, 10);
+        assert!(bounds.contains(&bounds));
+        assert!(bounds.contains(&Rect::new(9, 9, 1, 1)));
+        assert!(!bounds.contains(&Rect::new(9, 9, 2, 1)));
+        assert!(!bounds.contains(&Rect::new(-1, 0, 1, 1)));
+    }
+
+    #[test]
+    fn rect_centered_in() {
+        let rects = random_rects(64);
+        for rect in &rects {
+            for bounds in &rects {
+                let centered = rect.centered_in(bounds);
+                assert_eq!((centered.width, centered.height), (rect.width, rect.height));
+                let left_margin = centered.left as i64 - bounds.left as i64;
+                let right_margin = bounds.right() - centered.right();
+                assert!(right_margin - left_margin == 0 || right_margin - left_margin == 1);
+                let top_margin = centered.top as i64 - bounds.top as i64;
+                let bottom_margin = bounds.bottom() - centered.bottom();
+                assert!(bottom_margin - top_margin == 0 || bottom_margin - top_margin == 1);
+                if rect.width <= bounds.width && rect.height <= bounds.height {
+                    assert!(bounds.contains(&centered));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rect_scaled_to_fit() {
+        let rects = random_rects(64);
+        for rect in rects.iter().filter(|r| !r.is_empty()) {
+            for bounds in &rects {
+                let scaled = rect.scaled_to_fit(bounds);
+                assert!(bounds.contains(&scaled), "{} {} {}", rect, bounds, scaled);
+                // One side fills `bounds`.
+                assert!(scaled.width == bounds.width || scaled.height == bounds.height);
+                // The aspect ratio is preserved, up to the rounding down of one side.
+                let (w, h) = (scaled.width as u64, scaled.height as u64);
+                let (rw, rh) = (rect.width as u64, rect.height as u64);
+                let width_limited = h * rw <= w * rh && w * rh < (h + 1) * rw;
+                let height_limited = w * rh <= h * rw && h * rw < (w + 1) * rh;
+                assert!(width_limited || height_limited, "{} {}", rect, scaled);
+            }
+        }
+        assert!(Rect::new(0, 0, 0, 10)
+            .scaled_to_fit(&Rect::new(0, 0, 100, 100))
+            .is_empty());
+    }
+
+    #[test]
+    fn rect_aligned() {
+        for rect in random_rects(256) {
+            for alignment in [0, 1, 2, 4, 16] {
+                let aligned = rect.aligned(alignment);
+                let alignment = alignment.max(1);
+                assert_eq!(aligned.left.rem_euclid(alignment as i32), 0);
+                assert_eq!(aligned.top.rem_euclid(alignment as i32), 0);
+                assert_eq!(aligned.width % alignment, 0);
+                assert_eq!(aligned.height % alignment, 0);
+                if !aligned.is_empty() {
+                    assert!(rect.contains(&aligned), "{} {}", rect, aligned);
+                }
+                // At most one alignment unit is lost on each side.
+                assert!(aligned.width + 2 * alignment > rect.width);
+                assert!(aligned.height + 2 * alignment > rect.height);
+            }
+        }
+        assert_eq!(Rect::new(1, 1, 2, 2).aligned(2), Rect::new(2, 2, 0, 0));
+    }
+
+    #[test]
+    fn pixel_format_display() {
+        assert_eq!(PixelFormat::NV12.to_string(), "NV12");
+        assert_eq!(PixelFormat::from(b"Y16 ").to_string(), "Y16 ");
+        assert_eq!(
+            PixelFormat::from(&[b'A', 0x00, 0xe9, b'\\']).to_string(),
+            "A\\x00\\xe9\\x5c"
+        );
+        assert_eq!(format!("{:?}", PixelFormat::H264), "0x34363248 (H264)");
+    }
+
+    #[test]
+    fn pixel_format_from_str() {
+        assert_eq!("NV12".parse(), Ok(PixelFormat::NV12));
+        assert_eq!("MJPG".parse(), Ok(PixelFormat::MJPEG));
+        assert_eq!("Y16 ".parse(), Ok(PixelFormat::from(b"Y16 ")));
+        assert_ne!("nv12".parse(), Ok(PixelFormat::NV12));
+        assert_eq!("A\\x42CD".parse(), Ok(PixelFormat::from(b"ABCD")));
+
+        assert_eq!(
+            "NV1".parse::<PixelFormat>(),
+            Err(PixelFormatParseError::InvalidLength(3))
+        );
+        assert_eq!(
+            "NV122".parse::<PixelFormat>(),
+            Err(PixelFormatParseError::InvalidLength(5))
+        );
+        assert_eq!(
+            "".parse::<PixelFormat>(),
+            Err(PixelFormatParseError::InvalidLength(0))
+        );
+        assert_eq!(
+            "NV1\u{e9}".parse::<PixelFormat>(),
+            Err(PixelFormatParseError::InvalidCharacter('\u{e9}'))
+        );
+        assert_eq!(
+            "NV1\\y0".parse::<PixelFormat>(),
+            Err(PixelFormatParseError::InvalidEscape("\\y0".into()))
+        );
+        assert_eq!(
+            "NV1\\x".parse::<PixelFormat>(),
+            Err(PixelFormatParseError::InvalidEscape("\\x".into()))
+        );
+    }
+
+    #[test]
+    fn pixel_format_big_endian() {
+        let le = PixelFormat::from(b"AR15");
+        let be = PixelFormat::from(u32::from(le) | (1 << 31));
+        assert!(!le.is_big_endian());
+        assert!(be.is_big_endian());
+        assert_eq!(be.to_string(), "AR15-BE");
+        assert_eq!("AR15-BE".parse(), Ok(be));
+        // "-BE" is only a suffix if it follows a complete fourcc.
+        assert_eq!("A-BE".parse(), Ok(PixelFormat::from(b"A-BE")));
+        assert!(!PixelFormat::from(b"A-BE").is_big_endian());
+    }
+
+    #[test]
+    fn pixel_format_round_trip() {
+        for fourcc in [
+            PixelFormat::NV12,
+            PixelFormat::from(b"Y16 "),
+            PixelFormat::from(&[0xe9, b'A', 0x7f, b'\\']),
+            PixelFormat::from(&[0x00, 0x01, 0x80, 0xff]),
+            PixelFormat::from(u32::MAX),
+            PixelFormat::from(0),
+        ] {
+            assert_eq!(fourcc.to_string().parse(), Ok(fourcc), "{:?}", fourcc);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod serde_tests {
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use super::*;
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: T) {
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(serde_json::from_str::<T>(&json).unwrap(), value);
+    }
+
+    #[test]
+    fn pixel_format_serde() {
+        let nv12 = PixelFormat::from(b"NV12");
+        assert_eq!(serde_json::to_string(&nv12).unwrap(), r#""NV12""#);
+        round_trip(nv12);
+        round_trip(PixelFormat::from(b"Y16 "));
+        round_trip(PixelFormat::from_u32(0));
+
+        let err = serde_json::from_str::<PixelFormat>(r#""NV1""#).unwrap_err();
+        assert!(err.to_string().contains("4 characters long, got 3"));
+        assert!(serde_json::from_str::<PixelFormat>(r#""NV122""#).is_err());
+        assert!(serde_json::from_str::<PixelFormat>(r#""NV1\u0100""#).is_err());
+        assert!(serde_json::from_str::<PixelFormat>("842094158").is_err());
+    }
+
+    #[test]
+    fn format_serde() {
+        round_trip(Format::default());
+        round_trip(Format {
+            width: 1920,
+            height: 1080,
+            pixelformat: PixelFormat::from(b"NM12"),
+            plane_fmt: vec![
+                PlaneLayout {
+                    sizeimage: 2088960,
+                    bytesperline: 1920,
+                },
+                PlaneLayout {
+                    sizeimage: 1044480,
+                    bytesperline: 1920,
+                },
+            ],
+            colorspace: Colorspace::Rec709,
+            xfer_func: XferFunc::F709,
+            ycbcr_enc: YCbCrEncoding::Unknown(42),
+            quantization: Quantization::LimRange,
+            field: Field::InterlacedBt,
+        });
+
+        // Formats saved without colorimetry information can still be loaded.
+        let format: Format = serde_json::from_str(
+            r#"{"width":640,"height":480,"pixelformat":"NV12","plane_fmt":[]}"#,
+        )
+        .unwrap();
+        assert_eq!(format, Format::from((PixelFormat::NV12, (640, 480))));
+    }
+
+    #[test]
+    fn rect_serde() {
+        round_trip(Rect::new(-16, 8, 640, 480));
+    }
+
+    #[test]
+    fn fraction_serde() {
+        round_trip(Fraction::new(1, 30));
+    }
+
+    #[test]
+    fn enums_serde() {
+        round_trip(QueueType::VideoCaptureMplane);
+        round_trip(Colorspace::Rec709);
+        round_trip(XferFunc::Smpte2084);
+        round_trip(YCbCrEncoding::Bt2020ConstLum);
+        round_trip(Quantization::LimRange);
+
+        assert_eq!(
+            serde_json::to_string(&Quantization::FullRange).unwrap(),
+            r#""FullRange""#
+        );
+        let err = serde_json::from_str::<Colorspace>(r#""Rec2020""#).unwrap_err();
+        assert!(err.to_string().contains("unknown variant `Rec2020`"));
+        assert!(serde_json::from_str::<QueueType>(r#""VideoCapturePlane""#).is_err());
+    }
 }
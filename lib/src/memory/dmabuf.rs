@@ -1,9 +1,11 @@
 //! Operations specific to DMABuf-type buffers.
-use log::warn;
+use log::{debug, error, warn};
+
+use nix::errno::Errno;
 
 use super::*;
-use crate::{bindings, ioctl};
-use std::os::fd::RawFd;
+use crate::{bindings, ioctl, QueueType};
+use std::os::fd::{BorrowedFd, OwnedFd, RawFd};
 use std::os::unix::io::{AsFd, AsRawFd};
 
 pub struct DmaBuf;
@@ -89,3 +91,162 @@ impl<T: DmaBufSource> DmaBufHandle<T> {
         ioctl::mmap(&self.0, 0, len as u32)
     }
 }
+
+/// Minimal pool of DMABUF buffers working directly on a device file descriptor.
+///
+/// This models the plain DMABUF workflow: buffers are allocated with `VIDIOC_REQBUFS`, then each
+/// buffer is queued with a dmabuf FD, and the same FD is returned when the buffer is dequeued.
+/// Only the first plane of each buffer is used, so this is suitable for single-plane formats.
+///
+/// Dropping the pool streams the queue off and frees its buffers.
+pub struct DmaBufPool<D: AsFd> {
+    device: D,
+    queue: QueueType,
+    /// Dmabuf currently bound to each buffer index, if the buffer is queued.
+    queued: Vec<Option<OwnedFd>>,
+}
+
+impl<D: AsFd> DmaBufPool<D> {
+    /// Request `count` DMABUF buffers on `queue` of `device`.
+    ///
+    /// The driver may grant a different number of buffers than `count`, which is returned by
+    /// [`DmaBufPool::count`]. It is an error for it to grant none.
+    pub fn new(device: D, queue: QueueType, count: u32) -> Result<Self, Errno> {
+        let count: ioctl::BufferCount =
+            ioctl::reqbufs(&device.as_fd(), queue, MemoryType::DmaBuf, count)?;
+        if count.0 == 0 {
+            return Err(Errno::ENOMEM);
+        }
+
+        Ok(DmaBufPool {
+            device,
+            queue,
+            queued: std::iter::repeat_with(|| None)
+                .take(count.0 as usize)
+                .collect(),
+        })
+    }
+
+    /// Returns the number of buffers allocated by the driver.
+    pub fn count(&self) -> u32 {
+        self.queued.len() as u32
+    }
+
+    /// Queue buffer `index` backed by `dmabuf_fd`.
+    ///
+    /// `dmabuf_fd` is duplicated and kept until the buffer is dequeued. Queuing a buffer that
+    /// does not exist or is already queued returns `EINVAL`.
+    pub fn queue(&mut self, index: u32, dmabuf_fd: BorrowedFd<'_>) -> Result<(), Errno> {
+        match self.queued.get(index as usize) {
+            Some(None) => (),
+            Some(Some(_)) | None => return Err(Errno::EINVAL),
+        }
+
+        let dmabuf = dmabuf_fd
+            .try_clone_to_owned()
+            .map_err(|e| Errno::from_raw(e.raw_os_error().unwrap_or(Errno::EIO as i32)))?;
+
+        let mut plane = ioctl::QBufPlane::new(0);
+        plane.0.m.fd = dmabuf.as_raw_fd();
+        let mut qbuf = ioctl::QBuffer::<DmaBufHandle<std::fs::File>>::new(self.queue, index);
+        qbuf.planes.push(plane);
+        ioctl::qbuf::<_, ()>(&self.device.as_fd(), qbuf).map_err(Errno::from)?;
+
+        self.queued[index as usize] = Some(dmabuf);
+
+        Ok(())
+    }
+
+    /// Dequeue the next processed buffer, returning its index and the dmabuf it was queued with.
+    pub fn dequeue(&mut self) -> Result<(u32, OwnedFd), Errno> {
+        let buffer: ioctl::V4l2Buffer =
+            ioctl::dqbuf(&self.device.as_fd(), self.queue).map_err(Errno::from)?;
+        let index = buffer.index();
+
+        match self.queued.get_mut(index as usize).and_then(Option::take) {
+            Some(dmabuf) => Ok((index, dmabuf)),
+            None => {
+                warn!("Dequeued buffer {} was not queued by this pool", index);
+                Err(Errno::EINVAL)
+            }
+        }
+    }
+}
+
+impl<D: AsFd> Drop for DmaBufPool<D> {
+    /// Stop the queue and free its buffers, so the dmabufs still queued can be released.
+    fn drop(&mut self) {
+        let device = self.device.as_fd();
+        if let Err(e) = ioctl::streamoff(&device, self.queue) {
+            error!("Error while streaming off {} queue: {}", self.queue, e);
+        }
+        match ioctl::reqbufs::<()>(&device, self.queue, MemoryType::DmaBuf, 0) {
+            Ok(()) => debug!("Freed all DMABUF buffers of {} queue", self.queue),
+            Err(e) => error!("Error while freeing buffers of {} queue: {}", self.queue, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::DmaBufPool;
+    use crate::bindings;
+    use crate::ioctl::backend::mock::MockIoctls;
+    use crate::memory::MemoryType;
+    use crate::QueueType;
+
+    /// Expects a `VIDIOC_REQBUFS` for `count` DMABUF buffers, granting `granted` of them.
+    fn expect_reqbufs(mock: &MockIoctls, count: u32, granted: u32) {
+        mock.expect_with(
+            "vidioc_reqbufs",
+            move |reqbufs: &mut bindings::v4l2_requestbuffers| {
+                assert_eq!(reqbufs.type_, QueueType::VideoCapture as u32);
+                assert_eq!(reqbufs.memory, MemoryType::DmaBuf as u32);
+                assert_eq!(reqbufs.count, count);
+                reqbufs.count = granted;
+                Ok(0)
+            },
+        );
+    }
+
+    #[test]
+    fn test_dmabuf_pool_frees_buffers() {
+        let mock = MockIoctls::new();
+
+        expect_reqbufs(&mock, 4, 2);
+        let pool = DmaBufPool::new(mock.file(), QueueType::VideoCapture, 4).unwrap();
+        assert_eq!(pool.count(), 2);
+        mock.assert_done();
+
+        mock.expect("vidioc_streamoff", Ok(0));
+        expect_reqbufs(&mock, 0, 0);
+        drop(pool);
+        mock.assert_done();
+
+        // Queued buffers are freed as well, even if streaming off fails.
+        expect_reqbufs(&mock, 1, 1);
+        let mut pool = DmaBufPool::new(mock.file(), QueueType::VideoCapture, 1).unwrap();
+        mock.expect("vidioc_qbuf", Ok(0));
+        let dmabuf = mock.file();
+        pool.queue(0, std::os::fd::AsFd::as_fd(&dmabuf)).unwrap();
+        mock.expect("vidioc_streamoff", Err(Errno::EIO));
+        expect_reqbufs(&mock, 0, 0);
+        drop(pool);
+        mock.assert_done();
+    }
+
+    #[test]
+    fn test_dmabuf_pool_no_buffers() {
+        let mock = MockIoctls::new();
+
+        // No pool is created, so nothing is freed.
+        expect_reqbufs(&mock, 4, 0);
+        assert!(matches!(
+            DmaBufPool::new(mock.file(), QueueType::VideoCapture, 4),
+            Err(Errno::ENOMEM)
+        ));
+        mock.assert_done();
+    }
+}
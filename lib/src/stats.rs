@@ -0,0 +1,411 @@
+//! Runtime statistics for the high-level [`decoder`](crate::decoder) and
+//! [`encoder`](crate::encoder) interfaces.
+//!
+//! Counters are maintained using atomics so updating them from the hot path is cheap. Latency is
+//! measured from the time an OUTPUT buffer is queued to the time the CAPTURE buffer carrying the
+//! same timestamp is dequeued, so it only makes sense if the client sets distinct timestamps on
+//! the buffers it queues.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use nix::libc::{suseconds_t, time_t};
+
+use crate::{bindings, ioctl::V4l2Buffer};
+
+/// Maximum number of latency samples kept to compute the average and p95 latencies.
+const MAX_LATENCY_SAMPLES: usize = 512;
+/// Maximum number of unmatched timestamps we keep track of before discarding the oldest ones.
+const MAX_PENDING_TIMESTAMPS: usize = 64;
+
+/// Snapshot of the statistics of a decoder or encoder session.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CodecStats {
+    /// Number of OUTPUT buffers processed by the driver.
+    pub frames_in: u64,
+    /// Number of CAPTURE buffers produced by the driver.
+    pub frames_out: u64,
+    /// Number of bytes consumed from the OUTPUT buffers.
+    pub bytes_in: u64,
+    /// Number of bytes produced into the CAPTURE buffers.
+    pub bytes_out: u64,
+    /// Number of buffers (OUTPUT or CAPTURE) dequeued with the `ERROR` flag set.
+    pub error_buffers: u64,
+    /// Number of buffers currently queued on the OUTPUT queue.
+    pub output_queue_depth: usize,
+    /// Number of buffers currently queued on the CAPTURE queue.
+    pub capture_queue_depth: usize,
+    /// Average latency between queueing an OUTPUT buffer and dequeueing its CAPTURE buffer, over
+    /// the last samples.
+    pub average_latency: Option<Duration>,
+    /// 95th percentile of the same latency.
+    pub p95_latency: Option<Duration>,
+    /// Number of frames whose latency has been measured, i.e. whose CAPTURE buffer has been
+    /// matched with the OUTPUT buffer it was produced from.
+    pub latency_samples: u64,
+    /// Number of resolution changes that took place, including the initial resolution of a
+    /// decoded stream.
+    pub resolution_changes: u64,
+    /// Time elapsed between the start of the session and the first CAPTURE frame being dequeued.
+    pub first_frame_latency: Option<Duration>,
+    /// Number of decoded frames recycled without being delivered to the client because it could
+    /// not keep up, see [`FrameDropPolicy`](crate::decoder::stateful::FrameDropPolicy).
+    pub frames_dropped: u64,
+    /// Number of frames the driver skipped, as revealed by gaps in the sequence numbers of the
+    /// CAPTURE buffers, see [`Queue::dropped_frames`](crate::device::queue::Queue::dropped_frames).
+    pub frames_lost: u64,
+}
+
+impl CodecStats {
+    /// Add the statistics of `other` to `self`, e.g. to aggregate the statistics of several
+    /// sessions. Counters and queue depths are summed, while latencies keep the worst value.
+    pub fn accumulate(&mut self, other: &CodecStats) {
+        self.frames_in += other.frames_in;
+        self.frames_out += other.frames_out;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.error_buffers += other.error_buffers;
+        self.output_queue_depth += other.output_queue_depth;
+        self.capture_queue_depth += other.capture_queue_depth;
+        self.average_latency = self.average_latency.max(other.average_latency);
+        self.p95_latency = self.p95_latency.max(other.p95_latency);
+        self.latency_samples += other.latency_samples;
+        self.resolution_changes += other.resolution_changes;
+        self.first_frame_latency = self.first_frame_latency.max(other.first_frame_latency);
+        self.frames_dropped += other.frames_dropped;
+        self.frames_lost += other.frames_lost;
+    }
+}
+
+/// Seconds and microseconds of a buffer timestamp, used to match CAPTURE buffers with the OUTPUT
+/// buffer they have been decoded from.
+type TimestampKey = (time_t, suseconds_t);
+
+#[derive(Default)]
+struct LatencyTracker {
+    /// OUTPUT buffers that have been processed, but whose CAPTURE buffer has not been seen yet.
+    queued: BTreeMap<TimestampKey, Instant>,
+    /// CAPTURE buffers dequeued before we learned the queue time of their OUTPUT buffer.
+    dequeued: BTreeMap<TimestampKey, Instant>,
+    samples: VecDeque<Duration>,
+    /// Total number of samples taken, including the ones that have been discarded from `samples`.
+    num_samples: u64,
+}
+
+impl LatencyTracker {
+    fn add_sample(&mut self, latency: Duration) {
+        if self.samples.len() == MAX_LATENCY_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+        self.num_samples += 1;
+    }
+
+    /// Inserts `instant` for `key` into `map`, making sure the map does not grow unbounded if
+    /// some timestamps never get matched.
+    fn insert_pending(
+        map: &mut BTreeMap<TimestampKey, Instant>,
+        key: TimestampKey,
+        instant: Instant,
+    ) {
+        if map.len() >= MAX_PENDING_TIMESTAMPS {
+            if let Some(oldest) = map.iter().min_by_key(|(_, i)| **i).map(|(k, _)| *k) {
+                map.remove(&oldest);
+            }
+        }
+        map.insert(key, instant);
+    }
+
+    fn output_done(&mut self, key: TimestampKey, queued_at: Instant) {
+        match self.dequeued.remove(&key) {
+            Some(dequeued_at) => self.add_sample(dequeued_at.saturating_duration_since(queued_at)),
+            None => Self::insert_pending(&mut self.queued, key, queued_at),
+        }
+    }
+
+    fn capture_done(&mut self, key: TimestampKey, dequeued_at: Instant) {
+        match self.queued.remove(&key) {
+            Some(queued_at) => self.add_sample(dequeued_at.saturating_duration_since(queued_at)),
+            None => Self::insert_pending(&mut self.dequeued, key, dequeued_at),
+        }
+    }
+
+    /// Returns the average and 95th percentile latencies of the current samples.
+    fn latencies(&self) -> (Option<Duration>, Option<Duration>) {
+        if self.samples.is_empty() {
+            return (None, None);
+        }
+
+        let total: Duration = self.samples.iter().sum();
+        let average = total / self.samples.len() as u32;
+
+        let mut sorted = self.samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        let p95_index = (sorted.len() * 95).div_ceil(100).saturating_sub(1);
+
+        (Some(average), Some(sorted[p95_index]))
+    }
+}
+
+fn timestamp_key(timestamp: bindings::timeval) -> TimestampKey {
+    (timestamp.tv_sec, timestamp.tv_usec)
+}
+
+fn bytes_used(buffer: &V4l2Buffer) -> u64 {
+    buffer.planes_iter().map(|p| *p.bytesused as u64).sum()
+}
+
+/// Lock-free counters shared between a decoder or encoder and its CAPTURE thread.
+///
+/// Only the latency tracking requires taking a lock, which is held for a very short time.
+pub(crate) struct StatsRecorder {
+    /// Time at which the session has started.
+    started_at: Mutex<Instant>,
+    /// Nanoseconds between `started_at` and the first CAPTURE frame, or 0 if no frame has been
+    /// dequeued yet.
+    first_frame_latency: AtomicU64,
+    frames_in: AtomicU64,
+    frames_out: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    error_buffers: AtomicU64,
+    resolution_changes: AtomicU64,
+    frames_dropped: AtomicU64,
+    frames_lost: AtomicU64,
+    output_queue_depth: AtomicUsize,
+    capture_queue_depth: AtomicUsize,
+    latency: Mutex<LatencyTracker>,
+}
+
+impl StatsRecorder {
+    pub(crate) fn new() -> Self {
+        Self {
+            started_at: Mutex::new(Instant::now()),
+            first_frame_latency: AtomicU64::new(0),
+            frames_in: AtomicU64::new(0),
+            frames_out: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            error_buffers: AtomicU64::new(0),
+            resolution_changes: AtomicU64::new(0),
+            frames_dropped: AtomicU64::new(0),
+            frames_lost: AtomicU64::new(0),
+            output_queue_depth: AtomicUsize::new(0),
+            capture_queue_depth: AtomicUsize::new(0),
+            latency: Default::default(),
+        }
+    }
+
+    /// Record an OUTPUT buffer that has been dequeued. `queued_at` is the time at which the
+    /// buffer was queued, if known.
+    pub(crate) fn output_dequeued(&self, buffer: &V4l2Buffer, queued_at: Option<Instant>) {
+        self.frames_in.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in
+            .fetch_add(bytes_used(buffer), Ordering::Relaxed);
+        if buffer.has_error() {
+            self.error_buffers.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let Some(queued_at) = queued_at {
+            self.latency
+                .lock()
+                .unwrap()
+                .output_done(timestamp_key(buffer.timestamp()), queued_at);
+        }
+    }
+
+    /// Record a CAPTURE buffer that has just been dequeued.
+    ///
+    /// Empty buffers carrying the `LAST` flag only signal the end of a drain sequence and are not
+    /// counted as frames.
+    pub(crate) fn capture_dequeued(&self, buffer: &V4l2Buffer) {
+        let now = Instant::now();
+        let bytes_used = bytes_used(buffer);
+
+        if bytes_used == 0 && buffer.is_last() {
+            return;
+        }
+
+        if self.frames_out.fetch_add(1, Ordering::Relaxed) == 0 {
+            // Make sure we never store 0, as it means "no frame yet".
+            let started_at = *self.started_at.lock().unwrap();
+            let latency = now.saturating_duration_since(started_at).as_nanos() as u64;
+            let _ = self.first_frame_latency.compare_exchange(
+                0,
+                latency.max(1),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+        self.bytes_out.fetch_add(bytes_used, Ordering::Relaxed);
+        if buffer.has_error() {
+            self.error_buffers.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.latency
+            .lock()
+            .unwrap()
+            .capture_done(timestamp_key(buffer.timestamp()), now);
+    }
+
+    pub(crate) fn resolution_changed(&self) {
+        self.resolution_changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn frames_dropped(&self, count: usize) {
+        self.frames_dropped
+            .fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn frames_lost(&self, count: u32) {
+        self.frames_lost.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_output_queue_depth(&self, depth: usize) {
+        self.output_queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_capture_queue_depth(&self, depth: usize) {
+        self.capture_queue_depth.store(depth, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the current statistics.
+    pub(crate) fn snapshot(&self) -> CodecStats {
+        let latency = self.latency.lock().unwrap();
+        let (average_latency, p95_latency) = latency.latencies();
+        let latency_samples = latency.num_samples;
+        drop(latency);
+
+        CodecStats {
+            frames_in: self.frames_in.load(Ordering::Relaxed),
+            frames_out: self.frames_out.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            error_buffers: self.error_buffers.load(Ordering::Relaxed),
+            output_queue_depth: self.output_queue_depth.load(Ordering::Relaxed),
+            capture_queue_depth: self.capture_queue_depth.load(Ordering::Relaxed),
+            average_latency,
+            p95_latency,
+            latency_samples,
+            resolution_changes: self.resolution_changes.load(Ordering::Relaxed),
+            first_frame_latency: match self.first_frame_latency.load(Ordering::Relaxed) {
+                0 => None,
+                latency => Some(Duration::from_nanos(latency)),
+            },
+            frames_dropped: self.frames_dropped.load(Ordering::Relaxed),
+            frames_lost: self.frames_lost.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Make the session start now, e.g. if the recorder has been created before the session
+    /// actually starts.
+    pub(crate) fn restart_clock(&self) {
+        *self.started_at.lock().unwrap() = Instant::now();
+    }
+
+    /// Reset all the counters. Queue depths are left untouched as they reflect the current state
+    /// of the queues, and so is the first frame latency since it only happens once per session.
+    pub(crate) fn reset(&self) {
+        self.frames_in.store(0, Ordering::Relaxed);
+        self.frames_out.store(0, Ordering::Relaxed);
+        self.bytes_in.store(0, Ordering::Relaxed);
+        self.bytes_out.store(0, Ordering::Relaxed);
+        self.error_buffers.store(0, Ordering::Relaxed);
+        self.resolution_changes.store(0, Ordering::Relaxed);
+        self.frames_dropped.store(0, Ordering::Relaxed);
+        self.frames_lost.store(0, Ordering::Relaxed);
+        *self.latency.lock().unwrap() = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ioctl::BufferFlags, QueueType};
+
+    fn buffer(queue: QueueType, index: u32, sec: i64, bytesused: u32) -> V4l2Buffer {
+        let mut buffer = V4l2Buffer::new(queue, index, crate::memory::MemoryType::Mmap);
+        buffer.set_timestamp(bindings::timeval {
+            tv_sec: sec as _,
+            tv_usec: 0,
+        });
+        *buffer.get_first_plane_mut().bytesused = bytesused;
+        buffer
+    }
+
+    #[test]
+    fn test_stats_consistency() {
+        let stats = StatsRecorder::new();
+        let start = Instant::now();
+
+        for i in 0..10 {
+            let out = buffer(QueueType::VideoOutputMplane, i, i as i64, 1000);
+            let mut cap = buffer(QueueType::VideoCaptureMplane, i, i as i64, 100);
+            if i == 3 {
+                cap.add_flags(BufferFlags::ERROR);
+            }
+            // Alternate the order in which OUTPUT and CAPTURE buffers are seen.
+            if i % 2 == 0 {
+                stats.output_dequeued(&out, Some(start));
+                stats.capture_dequeued(&cap);
+            } else {
+                stats.capture_dequeued(&cap);
+                stats.output_dequeued(&out, Some(start));
+            }
+        }
+        stats.resolution_changed();
+        stats.frames_lost(2);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.frames_in, 10);
+        assert_eq!(snapshot.frames_out, 10);
+        assert_eq!(snapshot.bytes_in, 10000);
+        assert_eq!(snapshot.bytes_out, 1000);
+        assert_eq!(snapshot.error_buffers, 1);
+        assert_eq!(snapshot.frames_lost, 2);
+        assert_eq!(snapshot.resolution_changes, 1);
+        assert_eq!(snapshot.latency_samples, 10);
+        let average = snapshot.average_latency.unwrap();
+        let p95 = snapshot.p95_latency.unwrap();
+        assert!(p95 >= average);
+        assert!(p95 <= start.elapsed());
+
+        // All timestamps have been matched.
+        let latency = stats.latency.lock().unwrap();
+        assert!(latency.queued.is_empty());
+        assert!(latency.dequeued.is_empty());
+        assert_eq!(latency.samples.len(), 10);
+        drop(latency);
+
+        let first_frame_latency = snapshot.first_frame_latency.unwrap();
+        assert!(first_frame_latency <= stats.started_at.lock().unwrap().elapsed());
+
+        stats.reset();
+        assert_eq!(
+            stats.snapshot(),
+            CodecStats {
+                first_frame_latency: Some(first_frame_latency),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_pending_timestamps_bounded() {
+        let stats = StatsRecorder::new();
+
+        for i in 0..(MAX_PENDING_TIMESTAMPS * 2) {
+            let out = buffer(QueueType::VideoOutputMplane, 0, i as i64, 0);
+            stats.output_dequeued(&out, Some(Instant::now()));
+        }
+
+        let latency = stats.latency.lock().unwrap();
+        assert_eq!(latency.queued.len(), MAX_PENDING_TIMESTAMPS);
+        assert!(latency.samples.is_empty());
+    }
+}
@@ -0,0 +1,421 @@
+//! Helpers to check the content of frames in tests, available with the `test-utils` feature.
+//!
+//! All the functions of this module only consider the visible part of a frame, as described by
+//! its [`Format`]: the padding at the end of each line and of each plane is ignored since its
+//! content is undefined. This makes it possible to compare frames using different strides, and
+//! to catch errors in the handling of strides and plane offsets.
+//!
+//! # Examples
+//!
+//! ```
+//! # use v4l2r::{test_utils::*, Format, PixelFormat, PlaneLayout};
+//! let format = |alignment| Format {
+//!     plane_fmt: PlaneLayout::for_format(PixelFormat::NV12, 100, 50, alignment).unwrap(),
+//!     ..Format::from((PixelFormat::NV12, (100, 50)))
+//! };
+//! let (packed, padded) = (format(0), format(64));
+//! let mut a = [vec![0u8; packed.plane_fmt[0].sizeimage as usize]];
+//! let mut b = [vec![0xffu8; padded.plane_fmt[0].sizeimage as usize]];
+//!
+//! fill_pattern(&packed, TestPattern::Gradient, 0, &mut a).unwrap();
+//! fill_pattern(&padded, TestPattern::Gradient, 0, &mut b).unwrap();
+//! assert_eq!(frame_checksum(&packed, &a), frame_checksum(&padded, &b));
+//! assert_eq!(psnr(&packed, &a, &padded, &b), Ok(f64::INFINITY));
+//! ```
+use std::hash::Hasher;
+
+use thiserror::Error;
+
+use crate::{
+    device::queue::{direction::Capture, dqbuf::DqBuffer},
+    format::{color_plane_layouts, ColorPlaneLayout},
+    memory::{Mappable, PrimitiveBufferHandles},
+    Format, PixelFormat,
+};
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum FrameError {
+    #[error("unsupported format {0} or invalid number of planes")]
+    UnsupportedFormat(PixelFormat),
+    #[error("expected {expected} memory planes, got {actual}")]
+    WrongNumberOfPlanes { expected: usize, actual: usize },
+    #[error("memory plane {0} is too small for the format")]
+    PlaneTooSmall(usize),
+    #[error("cannot map memory plane {0}")]
+    MappingFailed(usize),
+    #[error("the frames have different pixel formats or sizes")]
+    FormatMismatch,
+}
+
+/// Returns the layouts of the color planes of `format`, checking that `num_planes` memory planes
+/// are provided.
+fn layouts(format: &Format, num_planes: usize) -> Result<Vec<ColorPlaneLayout>, FrameError> {
+    let layouts = color_plane_layouts(format.pixelformat, format.height, &format.plane_fmt)
+        .ok_or(FrameError::UnsupportedFormat(format.pixelformat))?;
+    let expected = format.plane_fmt.len();
+    if num_planes != expected {
+        return Err(FrameError::WrongNumberOfPlanes {
+            expected,
+            actual: num_planes,
+        });
+    }
+
+    Ok(layouts)
+}
+
+/// Returns the visible part of each line of each color plane of the frame of `format` stored in
+/// `planes`.
+fn visible_lines<'a, S: AsRef<[u8]>>(
+    format: &Format,
+    planes: &'a [S],
+) -> Result<Vec<Vec<&'a [u8]>>, FrameError> {
+    let width = format.width as usize;
+    let height = format.height as usize;
+
+    layouts(format, planes.len())?
+        .iter()
+        .map(|layout| {
+            let plane = planes[layout.memory_plane].as_ref();
+            let line_bytes = layout.line_bytes(width);
+            (0..layout.lines(height))
+                .map(|line| {
+                    let start = layout.offset + line * layout.stride;
+                    plane
+                        .get(start..start + line_bytes)
+                        .ok_or(FrameError::PlaneTooSmall(layout.memory_plane))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// 64-bit FNV-1a hasher.
+///
+/// Contrary to `DefaultHasher`, its output is specified and does not change between Rust
+/// releases, so checksums can be stored as reference values.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    fn new() -> Self {
+        Fnv1a(Self::OFFSET_BASIS)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        // Do not depend on the endianness of the host.
+        self.write(&i.to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Returns a checksum of the visible part of each color plane of the frame of `format` stored in
+/// `planes`, in the order of the color planes.
+///
+/// Checksums are computed with FNV-1a, and are stable across platforms and Rust releases.
+pub fn plane_checksums<S: AsRef<[u8]>>(
+    format: &Format,
+    planes: &[S],
+) -> Result<Vec<u64>, FrameError> {
+    Ok(visible_lines(format, planes)?
+        .into_iter()
+        .map(|lines| {
+            let mut hasher = Fnv1a::new();
+            for line in lines {
+                hasher.write(line);
+            }
+            hasher.finish()
+        })
+        .collect())
+}
+
+/// Returns a checksum of the visible part of the frame of `format` stored in `planes`.
+pub fn frame_checksum<S: AsRef<[u8]>>(format: &Format, planes: &[S]) -> Result<u64, FrameError> {
+    let mut hasher = Fnv1a::new();
+    for checksum in plane_checksums(format, planes)? {
+        hasher.write_u64(checksum);
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Returns the checksums of the color planes of `dqbuf`, a frame of `format`, like
+/// [`plane_checksums`].
+pub fn dqbuf_checksums<P>(
+    format: &Format,
+    dqbuf: &DqBuffer<Capture, P>,
+) -> Result<Vec<u64>, FrameError>
+where
+    P: PrimitiveBufferHandles,
+    P::HandleType: Mappable,
+{
+    let mappings = (0..format.plane_fmt.len())
+        .map(|i| {
+            dqbuf
+                .get_plane_mapping(i)
+                .ok_or(FrameError::MappingFailed(i))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    plane_checksums(format, &mappings)
+}
+
+/// Returns the peak signal-to-noise ratio between the visible parts of frames `a` and `b`, in
+/// dB, or infinity if they are identical.
+///
+/// Both frames must have the same pixel format and size, but can use different strides. Samples
+/// are compared byte by byte with a peak value of 255, so the result is only meaningful for
+/// 8-bit formats. This is useful to compare frames that went through a lossy codec, for which a
+/// PSNR over 30 dB usually denotes a correct result.
+pub fn psnr<S: AsRef<[u8]>, T: AsRef<[u8]>>(
+    a_format: &Format,
+    a: &[S],
+    b_format: &Format,
+    b: &[T],
+) -> Result<f64, FrameError> {
+    if (a_format.pixelformat, a_format.width, a_format.height)
+        != (b_format.pixelformat, b_format.width, b_format.height)
+    {
+        return Err(FrameError::FormatMismatch);
+    }
+
+    let a = visible_lines(a_format, a)?;
+    let b = visible_lines(b_format, b)?;
+    let (mut squared_error, mut samples) = (0u64, 0u64);
+    for (a_line, b_line) in a.iter().flatten().zip(b.iter().flatten()) {
+        for (&x, &y) in a_line.iter().zip(b_line.iter()) {
+            squared_error += (x.abs_diff(y) as u64).pow(2);
+        }
+        samples += a_line.len() as u64;
+    }
+
+    if squared_error == 0 {
+        return Ok(f64::INFINITY);
+    }
+    let mse = squared_error as f64 / samples as f64;
+    Ok(10.0 * (255.0 * 255.0 / mse).log10())
+}
+
+/// Deterministic content generated by [`fill_pattern`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPattern {
+    /// Smooth diagonal gradient, shifting with every frame. It compresses well, making it
+    /// suitable for testing lossy encoders.
+    Gradient,
+    /// Eight vertical bars of the 75% color bars, in RGB order. Each byte of a pixel takes a
+    /// component of its bar in turn, so the colors are only accurate for 24 bits RGB formats.
+    ColorBars,
+    /// Single color, which components are taken by the bytes of each pixel in turn like for
+    /// [`TestPattern::ColorBars`].
+    Solid([u8; 3]),
+}
+
+/// RGB components of the 75% color bars, from left to right.
+const COLOR_BARS: [[u8; 3]; 8] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+    [0, 0, 0],
+];
+
+/// Fills the visible part of the frame of `format` stored in `planes` with frame number
+/// `frame_number` of `pattern`. The padding bytes are left untouched.
+pub fn fill_pattern<F: AsMut<[u8]>>(
+    format: &Format,
+    pattern: TestPattern,
+    frame_number: u32,
+    planes: &mut [F],
+) -> Result<(), FrameError> {
+    let width = format.width as usize;
+    let height = format.height as usize;
+
+    for layout in layouts(format, planes.len())? {
+        let plane = planes[layout.memory_plane].as_mut();
+        let line_bytes = layout.line_bytes(width);
+        let plane_width = width.div_ceil(layout.hsub);
+
+        for line in 0..layout.lines(height) {
+            let start = layout.offset + line * layout.stride;
+            let line_data = plane
+                .get_mut(start..start + line_bytes)
+                .ok_or(FrameError::PlaneTooSmall(layout.memory_plane))?;
+
+            for (x, pixel) in line_data.chunks_mut(layout.bytes_per_pixel).enumerate() {
+                for (component, byte) in pixel.iter_mut().enumerate() {
+                    *byte = match pattern {
+                        TestPattern::Gradient => {
+                            // Triangle wave, to avoid sharp edges when wrapping around.
+                            let phase = (x + line) / 2 + component * 85 + frame_number as usize * 4;
+                            let phase = phase % 512;
+                            if phase < 256 {
+                                phase as u8
+                            } else {
+                                (511 - phase) as u8
+                            }
+                        }
+                        TestPattern::ColorBars => {
+                            COLOR_BARS[x * COLOR_BARS.len() / plane_width][component % 3]
+                        }
+                        TestPattern::Solid(color) => color[component % 3],
+                    };
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PlaneLayout;
+
+    /// Returns a `width`x`height` RGB24 format with a stride of `stride`.
+    fn rgb_format(width: u32, height: u32, stride: u32) -> Format {
+        Format {
+            plane_fmt: vec![PlaneLayout {
+                bytesperline: stride,
+                sizeimage: stride * height,
+            }],
+            ..Format::from((PixelFormat::RGB24, (width as usize, height as usize)))
+        }
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        // Reference values of the FNV-1a specification.
+        assert_eq!(Fnv1a::new().finish(), 0xcbf29ce484222325);
+        let mut hasher = Fnv1a::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63dc4c8601ec8c);
+        let mut hasher = Fnv1a::new();
+        hasher.write(b"foobar");
+        assert_eq!(hasher.finish(), 0x85944171f73967e8);
+
+        // Checksums do not depend on how the data is split.
+        let mut hasher = Fnv1a::new();
+        hasher.write(b"foo");
+        hasher.write(b"bar");
+        assert_eq!(hasher.finish(), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn test_checksums_ignore_padding() {
+        let format = rgb_format(2, 2, 8);
+        let a = [[1u8, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0]];
+        let b = [[1u8, 2, 3, 4, 5, 6, 9, 9, 7, 8, 9, 10, 11, 12, 9, 9]];
+        assert_eq!(frame_checksum(&format, &a), frame_checksum(&format, &b));
+
+        // Same content with a different stride.
+        let packed = rgb_format(2, 2, 6);
+        let c = [[1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]];
+        assert_eq!(frame_checksum(&format, &a), frame_checksum(&packed, &c));
+
+        let d = [[1u8, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 13, 0, 0]];
+        assert_ne!(frame_checksum(&format, &a), frame_checksum(&format, &d));
+        assert_eq!(
+            frame_checksum(&format, &[[0u8; 13]]),
+            Err(FrameError::PlaneTooSmall(0))
+        );
+        assert_eq!(
+            frame_checksum::<[u8; 16]>(&format, &[]),
+            Err(FrameError::WrongNumberOfPlanes {
+                expected: 1,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_multiplanar_checksums() {
+        let format = Format {
+            plane_fmt: PlaneLayout::for_format(PixelFormat::NV12M, 4, 4, 0).unwrap(),
+            ..Format::from((PixelFormat::NV12M, (4, 4)))
+        };
+        let mut planes = [vec![0u8; 16], vec![0u8; 8]];
+        let checksums = plane_checksums(&format, &planes).unwrap();
+        assert_eq!(checksums.len(), 2);
+
+        // Only the checksum of the modified plane changes.
+        planes[1][7] = 1;
+        let modified = plane_checksums(&format, &planes).unwrap();
+        assert_eq!(modified[0], checksums[0]);
+        assert_ne!(modified[1], checksums[1]);
+    }
+
+    #[test]
+    fn test_psnr() {
+        let format = rgb_format(4, 4, 16);
+        let padded = rgb_format(4, 4, 20);
+        let mut a = [vec![0u8; 64]];
+        let mut b = [vec![0u8; 80]];
+        fill_pattern(&format, TestPattern::Gradient, 3, &mut a).unwrap();
+        fill_pattern(&padded, TestPattern::Gradient, 3, &mut b).unwrap();
+        assert_eq!(psnr(&format, &a, &padded, &b), Ok(f64::INFINITY));
+
+        // A difference of 1 on every sample gives a PSNR of 20 * log10(255).
+        for byte in b[0].iter_mut() {
+            *byte ^= 1;
+        }
+        let value = psnr(&format, &a, &padded, &b).unwrap();
+        assert!((value - 48.13).abs() < 0.01, "{}", value);
+
+        assert_eq!(
+            psnr(&format, &a, &rgb_format(4, 3, 16), &a),
+            Err(FrameError::FormatMismatch)
+        );
+    }
+
+    #[test]
+    fn test_fill_pattern() {
+        let format = rgb_format(8, 2, 32);
+        let mut frame = [vec![0xaau8; 64]];
+        fill_pattern(&format, TestPattern::ColorBars, 0, &mut frame).unwrap();
+        // One pixel per bar.
+        assert_eq!(frame[0][..6], [191, 191, 191, 191, 191, 0]);
+        assert_eq!(frame[0][21..24], [0, 0, 0]);
+        // Padding is left untouched.
+        assert_eq!(frame[0][24..32], [0xaa; 8]);
+        assert_eq!(frame[0][32..56], frame[0][..24]);
+
+        fill_pattern(&format, TestPattern::Solid([1, 2, 3]), 0, &mut frame).unwrap();
+        assert_eq!(frame[0][..24], [1, 2, 3].repeat(8));
+        assert_eq!(frame[0][24..32], [0xaa; 8]);
+
+        // Each frame of the gradient is different.
+        let mut next = [vec![0u8; 64]];
+        fill_pattern(&format, TestPattern::Gradient, 0, &mut frame).unwrap();
+        fill_pattern(&format, TestPattern::Gradient, 1, &mut next).unwrap();
+        assert_ne!(
+            frame_checksum(&format, &frame),
+            frame_checksum(&format, &next)
+        );
+
+        assert_eq!(
+            fill_pattern(
+                &Format::from((PixelFormat::MJPEG, (8, 2))),
+                TestPattern::Gradient,
+                0,
+                &mut frame
+            ),
+            Err(FrameError::UnsupportedFormat(PixelFormat::MJPEG))
+        );
+    }
+}
@@ -0,0 +1,499 @@
+//! SMPTE 12M timecodes, as carried by `struct v4l2_timecode`.
+//!
+//! A [`Timecode`] can be obtained from a dequeued buffer using
+//! [`crate::ioctl::V4l2Buffer::timecode`], and set on a buffer to queue using
+//! [`crate::ioctl::V4l2Buffer::set_timecode`]. Timecodes can be converted to and
+//! from a number of frames since midnight, taking drop-frame counting into
+//! account for the 29.97 and 59.94 frames per second rates.
+
+use std::fmt::{self, Display};
+
+use bitflags::bitflags;
+use enumn::N;
+use thiserror::Error;
+
+use crate::bindings;
+
+/// Number of hours after which timecodes wrap around.
+const HOURS_PER_DAY: u32 = 24;
+
+/// Equivalent of the `V4L2_TC_TYPE_*` constants, i.e. the nominal frame rate of
+/// a timecode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, N)]
+#[repr(u32)]
+pub enum TimecodeType {
+    Fps24 = bindings::V4L2_TC_TYPE_24FPS,
+    Fps25 = bindings::V4L2_TC_TYPE_25FPS,
+    Fps30 = bindings::V4L2_TC_TYPE_30FPS,
+    Fps50 = bindings::V4L2_TC_TYPE_50FPS,
+    Fps60 = bindings::V4L2_TC_TYPE_60FPS,
+}
+
+impl TimecodeType {
+    /// Returns the nominal number of frames per second, i.e. the number of
+    /// frame values in a second of timecode.
+    pub fn fps(self) -> u32 {
+        match self {
+            TimecodeType::Fps24 => 24,
+            TimecodeType::Fps25 => 25,
+            TimecodeType::Fps30 => 30,
+            TimecodeType::Fps50 => 50,
+            TimecodeType::Fps60 => 60,
+        }
+    }
+
+    /// Returns the number of frame numbers skipped at the start of each minute
+    /// not multiple of ten in drop-frame mode, or `None` if drop-frame is not
+    /// supported with this type.
+    fn dropped_frames(self) -> Option<u32> {
+        match self {
+            TimecodeType::Fps30 => Some(2),
+            TimecodeType::Fps60 => Some(4),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    /// `flags` member of `struct v4l2_timecode`.
+    pub struct TimecodeFlags: u32 {
+        const DROPFRAME = bindings::V4L2_TC_FLAG_DROPFRAME;
+        const COLORFRAME = bindings::V4L2_TC_FLAG_COLORFRAME;
+        const USERBITS_8BITCHARS = bindings::V4L2_TC_USERBITS_8BITCHARS;
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TimecodeError {
+    #[error("invalid timecode type {0}")]
+    InvalidType(u32),
+    #[error("drop-frame is not supported at {0} frames per second")]
+    DropFrameNotSupported(u32),
+    #[error("invalid time {0:02}:{1:02}:{2:02}")]
+    InvalidTime(u8, u8, u8),
+    #[error("frame {0} is out of range at {1} frames per second")]
+    InvalidFrame(u8, u32),
+    #[error("frame {0} does not exist in drop-frame timecode")]
+    DroppedFrame(u8),
+    #[error("frame count {0} exceeds 24 hours")]
+    FrameCountTooLarge(u32),
+}
+
+/// A validated SMPTE timecode.
+///
+/// # Examples
+///
+/// ```
+/// # use v4l2r::timecode::{Timecode, TimecodeType};
+/// // The last frame of the first minute of 29.97 drop-frame timecode...
+/// let tc = Timecode::new(TimecodeType::Fps30, true, 0, 0, 59, 29).unwrap();
+/// assert_eq!(tc.to_frame_count(), 1799);
+/// // ... is followed by frame 2, since frames 0 and 1 are dropped.
+/// assert_eq!(tc.next().to_string(), "00:01:00;02");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timecode {
+    type_: TimecodeType,
+    flags: TimecodeFlags,
+    hours: u8,
+    minutes: u8,
+    seconds: u8,
+    frames: u8,
+    /// User bits of the timecode, not interpreted by this type.
+    pub userbits: [u8; 4],
+}
+
+impl Timecode {
+    /// Create a new timecode, validating all its fields.
+    pub fn new(
+        type_: TimecodeType,
+        drop_frame: bool,
+        hours: u8,
+        minutes: u8,
+        seconds: u8,
+        frames: u8,
+    ) -> Result<Self, TimecodeError> {
+        let flags = if drop_frame {
+            TimecodeFlags::DROPFRAME
+        } else {
+            TimecodeFlags::empty()
+        };
+        let timecode = Timecode {
+            type_,
+            flags,
+            hours,
+            minutes,
+            seconds,
+            frames,
+            userbits: Default::default(),
+        };
+        timecode.validate()?;
+
+        Ok(timecode)
+    }
+
+    /// Check that the fields of this timecode describe a valid frame.
+    fn validate(&self) -> Result<(), TimecodeError> {
+        let fps = self.type_.fps();
+
+        if self.hours as u32 >= HOURS_PER_DAY || self.minutes >= 60 || self.seconds >= 60 {
+            return Err(TimecodeError::InvalidTime(
+                self.hours,
+                self.minutes,
+                self.seconds,
+            ));
+        }
+        if self.frames as u32 >= fps {
+            return Err(TimecodeError::InvalidFrame(self.frames, fps));
+        }
+        if self.is_drop_frame() {
+            let dropped = self
+                .type_
+                .dropped_frames()
+                .ok_or(TimecodeError::DropFrameNotSupported(fps))?;
+            if self.seconds == 0
+                && !self.minutes.is_multiple_of(10)
+                && (self.frames as u32) < dropped
+            {
+                return Err(TimecodeError::DroppedFrame(self.frames));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create the timecode of the frame `count` frames after midnight.
+    pub fn from_frame_count(
+        type_: TimecodeType,
+        drop_frame: bool,
+        count: u32,
+    ) -> Result<Self, TimecodeError> {
+        let fps = type_.fps();
+        let frames_per_day = Self::frames_per_day(type_, drop_frame)?;
+        if count >= frames_per_day {
+            return Err(TimecodeError::FrameCountTooLarge(count));
+        }
+
+        // Turn the frame count into the frame number it would have if no
+        // frame numbers were dropped.
+        let nominal = match drop_frame.then(|| type_.dropped_frames()).flatten() {
+            Some(dropped) => {
+                let frames_per_minute = 60 * fps - dropped;
+                let frames_per_10_minutes = 600 * fps - 9 * dropped;
+                let tens = count / frames_per_10_minutes;
+                let remainder = count % frames_per_10_minutes;
+                // The first minute of each 10 minutes period does not drop any
+                // frame, so the dropped frames of the period only start
+                // counting after its first `dropped` frames.
+                count
+                    + 9 * dropped * tens
+                    + dropped * (remainder.saturating_sub(dropped) / frames_per_minute)
+            }
+            None => count,
+        };
+
+        let seconds = nominal / fps;
+        Timecode::new(
+            type_,
+            drop_frame,
+            (seconds / 3600) as u8,
+            (seconds / 60 % 60) as u8,
+            (seconds % 60) as u8,
+            (nominal % fps) as u8,
+        )
+    }
+
+    /// Returns the number of distinct frames in a day of timecode.
+    fn frames_per_day(type_: TimecodeType, drop_frame: bool) -> Result<u32, TimecodeError> {
+        let fps = type_.fps();
+        let nominal = HOURS_PER_DAY * 3600 * fps;
+
+        if drop_frame {
+            let dropped = type_
+                .dropped_frames()
+                .ok_or(TimecodeError::DropFrameNotSupported(fps))?;
+            Ok(nominal - HOURS_PER_DAY * 54 * dropped)
+        } else {
+            Ok(nominal)
+        }
+    }
+
+    /// Returns the number of frames elapsed since midnight.
+    pub fn to_frame_count(&self) -> u32 {
+        let fps = self.type_.fps();
+        let total_minutes = self.hours as u32 * 60 + self.minutes as u32;
+        let nominal = (total_minutes * 60 + self.seconds as u32) * fps + self.frames as u32;
+
+        match self.drop_frame_count() {
+            Some(dropped) => nominal - dropped * (total_minutes - total_minutes / 10),
+            None => nominal,
+        }
+    }
+
+    /// Returns the number of frame numbers dropped every minute, if this is a
+    /// drop-frame timecode.
+    fn drop_frame_count(&self) -> Option<u32> {
+        if self.is_drop_frame() {
+            self.type_.dropped_frames()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the timecode `frames` frames after (or before, if negative)
+    /// this one, wrapping around at midnight.
+    pub fn add_frames(&self, frames: i64) -> Timecode {
+        // Cannot fail since the type and drop-frame mode have been validated.
+        let frames_per_day = Self::frames_per_day(self.type_, self.is_drop_frame()).unwrap() as i64;
+        let count = (self.to_frame_count() as i64 + frames).rem_euclid(frames_per_day);
+
+        Timecode {
+            flags: self.flags,
+            userbits: self.userbits,
+            ..Timecode::from_frame_count(self.type_, self.is_drop_frame(), count as u32).unwrap()
+        }
+    }
+
+    /// Returns the timecode of the next frame, wrapping around at midnight.
+    pub fn next(&self) -> Timecode {
+        self.add_frames(1)
+    }
+
+    pub fn type_(&self) -> TimecodeType {
+        self.type_
+    }
+
+    pub fn flags(&self) -> TimecodeFlags {
+        self.flags
+    }
+
+    /// Returns whether this timecode uses drop-frame counting.
+    pub fn is_drop_frame(&self) -> bool {
+        self.flags.contains(TimecodeFlags::DROPFRAME)
+    }
+
+    pub fn hours(&self) -> u8 {
+        self.hours
+    }
+
+    pub fn minutes(&self) -> u8 {
+        self.minutes
+    }
+
+    pub fn seconds(&self) -> u8 {
+        self.seconds
+    }
+
+    pub fn frames(&self) -> u8 {
+        self.frames
+    }
+}
+
+/// Formats the timecode as `HH:MM:SS:FF`, or `HH:MM:SS;FF` for drop-frame
+/// timecodes.
+impl Display for Timecode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.is_drop_frame() { ';' } else { ':' };
+
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{}{:02}",
+            self.hours, self.minutes, self.seconds, separator, self.frames
+        )
+    }
+}
+
+impl TryFrom<bindings::v4l2_timecode> for Timecode {
+    type Error = TimecodeError;
+
+    fn try_from(tc: bindings::v4l2_timecode) -> Result<Self, Self::Error> {
+        let timecode = Timecode {
+            type_: TimecodeType::n(tc.type_).ok_or(TimecodeError::InvalidType(tc.type_))?,
+            flags: TimecodeFlags::from_bits_retain(tc.flags),
+            hours: tc.hours,
+            minutes: tc.minutes,
+            seconds: tc.seconds,
+            frames: tc.frames,
+            userbits: tc.userbits,
+        };
+        timecode.validate()?;
+
+        Ok(timecode)
+    }
+}
+
+impl From<Timecode> for bindings::v4l2_timecode {
+    fn from(tc: Timecode) -> Self {
+        bindings::v4l2_timecode {
+            type_: tc.type_ as u32,
+            flags: tc.flags.bits(),
+            frames: tc.frames,
+            seconds: tc.seconds,
+            minutes: tc.minutes,
+            hours: tc.hours,
+            userbits: tc.userbits,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tc(type_: TimecodeType, drop_frame: bool, h: u8, m: u8, s: u8, f: u8) -> Timecode {
+        Timecode::new(type_, drop_frame, h, m, s, f).unwrap()
+    }
+
+    #[test]
+    fn validation() {
+        use TimecodeType::*;
+
+        assert_eq!(
+            Timecode::new(Fps30, true, 0, 1, 0, 0),
+            Err(TimecodeError::DroppedFrame(0))
+        );
+        assert_eq!(
+            Timecode::new(Fps30, true, 0, 1, 0, 1),
+            Err(TimecodeError::DroppedFrame(1))
+        );
+        assert!(Timecode::new(Fps30, true, 0, 1, 0, 2).is_ok());
+        assert!(Timecode::new(Fps30, false, 0, 1, 0, 0).is_ok());
+        assert!(Timecode::new(Fps30, true, 0, 10, 0, 0).is_ok());
+        assert!(Timecode::new(Fps30, true, 0, 1, 1, 0).is_ok());
+        assert_eq!(
+            Timecode::new(Fps60, true, 0, 1, 0, 3),
+            Err(TimecodeError::DroppedFrame(3))
+        );
+        assert!(Timecode::new(Fps60, true, 0, 1, 0, 4).is_ok());
+        assert_eq!(
+            Timecode::new(Fps25, true, 0, 0, 0, 0),
+            Err(TimecodeError::DropFrameNotSupported(25))
+        );
+        assert_eq!(
+            Timecode::new(Fps25, false, 0, 0, 0, 25),
+            Err(TimecodeError::InvalidFrame(25, 25))
+        );
+        assert!(Timecode::new(Fps24, false, 23, 59, 59, 23).is_ok());
+        assert_eq!(
+            Timecode::new(Fps24, false, 24, 0, 0, 0),
+            Err(TimecodeError::InvalidTime(24, 0, 0))
+        );
+        assert_eq!(
+            Timecode::new(Fps50, false, 0, 60, 0, 0),
+            Err(TimecodeError::InvalidTime(0, 60, 0))
+        );
+        assert_eq!(
+            Timecode::from_frame_count(Fps30, true, 2589408),
+            Err(TimecodeError::FrameCountTooLarge(2589408))
+        );
+    }
+
+    #[test]
+    fn drop_frame_boundaries() {
+        use TimecodeType::*;
+
+        // (timecode, frame count, next timecode)
+        let ntsc = [
+            (tc(Fps30, true, 0, 0, 0, 0), 0, "00:00:00;01"),
+            (tc(Fps30, true, 0, 0, 59, 29), 1799, "00:01:00;02"),
+            (tc(Fps30, true, 0, 1, 0, 2), 1800, "00:01:00;03"),
+            (tc(Fps30, true, 0, 1, 59, 29), 3597, "00:02:00;02"),
+            (tc(Fps30, true, 0, 9, 59, 29), 17981, "00:10:00;00"),
+            (tc(Fps30, true, 0, 10, 0, 0), 17982, "00:10:00;01"),
+            (tc(Fps30, true, 0, 10, 0, 1), 17983, "00:10:00;02"),
+            (tc(Fps30, true, 0, 10, 59, 29), 19781, "00:11:00;02"),
+            (tc(Fps30, true, 0, 11, 0, 2), 19782, "00:11:00;03"),
+            (tc(Fps30, true, 0, 59, 59, 29), 107891, "01:00:00;00"),
+            (tc(Fps30, true, 1, 0, 0, 0), 107892, "01:00:00;01"),
+            (tc(Fps30, true, 23, 59, 59, 29), 2589407, "00:00:00;00"),
+        ];
+        let ntsc_60 = [
+            (tc(Fps60, true, 0, 0, 59, 59), 3599, "00:01:00;04"),
+            (tc(Fps60, true, 0, 1, 0, 4), 3600, "00:01:00;05"),
+            (tc(Fps60, true, 0, 9, 59, 59), 35963, "00:10:00;00"),
+            (tc(Fps60, true, 0, 10, 0, 0), 35964, "00:10:00;01"),
+            (tc(Fps60, true, 23, 59, 59, 59), 5178815, "00:00:00;00"),
+        ];
+        let non_drop = [
+            (tc(Fps30, false, 0, 0, 59, 29), 1799, "00:01:00:00"),
+            (tc(Fps25, false, 0, 9, 59, 24), 14999, "00:10:00:00"),
+            (tc(Fps24, false, 23, 59, 59, 23), 2073599, "00:00:00:00"),
+        ];
+
+        for (timecode, count, next) in ntsc.into_iter().chain(ntsc_60).chain(non_drop) {
+            assert_eq!(timecode.to_frame_count(), count, "{timecode}");
+            assert_eq!(
+                Timecode::from_frame_count(timecode.type_(), timecode.is_drop_frame(), count),
+                Ok(timecode)
+            );
+            assert_eq!(timecode.next().to_string(), next, "{timecode}");
+            assert_eq!(timecode.next().add_frames(-1), timecode);
+        }
+    }
+
+    #[test]
+    fn drop_frame_exhaustive() {
+        // Go through every frame of a day, checking that frame counts and
+        // timecodes are consistent and that exactly the right frame numbers are
+        // dropped.
+        for type_ in [TimecodeType::Fps30, TimecodeType::Fps60] {
+            let dropped = type_.dropped_frames().unwrap() as u8;
+            let mut timecode = tc(type_, true, 0, 0, 0, 0);
+            let frames_per_day = Timecode::frames_per_day(type_, true).unwrap();
+
+            for count in 0..frames_per_day {
+                assert_eq!(timecode.to_frame_count(), count);
+                assert_eq!(Timecode::from_frame_count(type_, true, count), Ok(timecode));
+
+                let next = timecode.next();
+                let expected_frames = if timecode.frames as u32 + 1 < type_.fps() {
+                    timecode.frames + 1
+                } else if next.seconds == 0 && !next.minutes.is_multiple_of(10) {
+                    dropped
+                } else {
+                    0
+                };
+                assert_eq!(next.frames, expected_frames, "after {timecode}");
+                timecode = next;
+            }
+
+            // We wrapped around at midnight.
+            assert_eq!(timecode, tc(type_, true, 0, 0, 0, 0));
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            tc(TimecodeType::Fps30, true, 1, 2, 3, 4).to_string(),
+            "01:02:03;04"
+        );
+        assert_eq!(
+            tc(TimecodeType::Fps25, false, 10, 20, 30, 24).to_string(),
+            "10:20:30:24"
+        );
+    }
+
+    #[test]
+    fn v4l2_timecode_conversion() {
+        let mut timecode = tc(TimecodeType::Fps60, true, 12, 34, 56, 7);
+        timecode.userbits = [1, 2, 3, 4];
+        let raw = bindings::v4l2_timecode::from(timecode);
+        assert_eq!(raw.type_, bindings::V4L2_TC_TYPE_60FPS);
+        assert_eq!(raw.flags, bindings::V4L2_TC_FLAG_DROPFRAME);
+        assert_eq!(Timecode::try_from(raw), Ok(timecode));
+
+        // Flags are preserved when incrementing.
+        let raw = bindings::v4l2_timecode {
+            flags: bindings::V4L2_TC_FLAG_COLORFRAME,
+            ..raw
+        };
+        let timecode = Timecode::try_from(raw).unwrap();
+        assert_eq!(timecode.next().flags(), TimecodeFlags::COLORFRAME);
+        assert_eq!(timecode.next().userbits, [1, 2, 3, 4]);
+
+        let raw = bindings::v4l2_timecode { type_: 0, ..raw };
+        assert_eq!(Timecode::try_from(raw), Err(TimecodeError::InvalidType(0)));
+    }
+}
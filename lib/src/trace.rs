@@ -0,0 +1,246 @@
+//! Optional instrumentation using the `tracing` crate.
+//!
+//! All the macros of this module expand to nothing unless the `tracing` feature is enabled, so
+//! the fields passed to them are not even evaluated in that case. See the crate documentation for
+//! the list of targets and fields that are emitted.
+
+/// Creates and enters a span for the ioctl `$name`, with an `errno` field that is recorded by
+/// [`record_errno!`] if the ioctl fails. The span is closed when the returned guard is dropped.
+#[cfg(feature = "tracing")]
+macro_rules! ioctl_span {
+    ($name:literal $(, $($fields:tt)+)?) => {
+        ::tracing::debug_span!(
+            target: "v4l2r::ioctl",
+            $name,
+            errno = ::tracing::field::Empty
+            $(, $($fields)+)?
+        )
+        .entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! ioctl_span {
+    ($($args:tt)*) => {
+        $crate::trace::NoSpan
+    };
+}
+
+/// Placeholder for the ioctl spans when the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
+
+/// Records the errno of `$res`, a `nix::Result`, into `$span` if it is an error.
+#[cfg(feature = "tracing")]
+macro_rules! record_errno {
+    ($span:expr, $res:expr) => {
+        if let Err(e) = &$res {
+            $span.record("errno", ::tracing::field::debug(e));
+        }
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! record_errno {
+    ($($args:tt)*) => {};
+}
+
+/// Records `$value` into the (previously declared) field `$field` of `$span`.
+#[cfg(feature = "tracing")]
+macro_rules! span_record {
+    ($span:expr, $field:literal, $value:expr) => {
+        $span.record($field, $value);
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span_record {
+    ($($args:tt)*) => {};
+}
+
+/// Emits a debug event for a state transition of `$queue`.
+///
+/// The event is emitted inside a `queue` span carrying the queue type, so that span filters such
+/// as `v4l2r[{queue=VideoCaptureMplane}]` also apply to it.
+#[cfg(feature = "tracing")]
+macro_rules! queue_event {
+    ($queue:expr, $($args:tt)+) => {
+        ::tracing::debug_span!(target: "v4l2r::queue", "queue", queue = ?$queue)
+            .in_scope(|| ::tracing::debug!(target: "v4l2r::queue", $($args)+))
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! queue_event {
+    ($($args:tt)*) => {};
+}
+
+/// Emits a debug event with target `v4l2r::decoder`.
+#[cfg(feature = "tracing")]
+macro_rules! decoder_event {
+    ($($args:tt)+) => {
+        ::tracing::debug!(target: "v4l2r::decoder", $($args)+)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! decoder_event {
+    ($($args:tt)*) => {};
+}
+
+/// Emits a debug event with target `v4l2r::encoder`.
+#[cfg(feature = "tracing")]
+macro_rules! encoder_event {
+    ($($args:tt)+) => {
+        ::tracing::debug!(target: "v4l2r::encoder", $($args)+)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! encoder_event {
+    ($($args:tt)*) => {};
+}
+
+/// Returns the total number of bytes used by all the planes of `buffer`.
+#[cfg(feature = "tracing")]
+pub(crate) fn bytes_used(buffer: &crate::ioctl::UncheckedV4l2Buffer) -> u64 {
+    match &buffer.1 {
+        Some(planes) => planes
+            .iter()
+            .take(buffer.0.length as usize)
+            .map(|p| p.bytesused as u64)
+            .sum(),
+        None => buffer.0.bytesused as u64,
+    }
+}
+
+/// Formats a raw `v4l2_buf_type` like the corresponding [`crate::QueueType`], so fields recorded
+/// from raw buffers can be filtered the same way as those recorded from a `QueueType`.
+#[cfg(feature = "tracing")]
+pub(crate) struct RawQueueType(pub u32);
+
+#[cfg(feature = "tracing")]
+impl std::fmt::Debug for RawQueueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match crate::QueueType::n(self.0) {
+            Some(queue) => std::fmt::Debug::fmt(&queue, f),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use nix::errno::Errno;
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    use super::*;
+    use crate::bindings;
+    use crate::ioctl::backend::mock::MockIoctls;
+    use crate::ioctl::UncheckedV4l2Buffer;
+    use crate::QueueType;
+
+    /// Writer collecting the output of a subscriber so it can be inspected.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Output {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs `f` with a subscriber recording all the debug spans and events, and returns what it
+    /// has recorded.
+    fn capture<F: FnOnce()>(f: F) -> String {
+        let output = Output::default();
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, f);
+        output.contents()
+    }
+
+    #[test]
+    fn test_bytes_used() {
+        let mut buffer = UncheckedV4l2Buffer::new_for_querybuf(QueueType::VideoCapture, Some(0));
+        buffer.0.bytesused = 1234;
+        assert_eq!(bytes_used(&buffer), 1234);
+
+        let mut buffer =
+            UncheckedV4l2Buffer::new_for_querybuf(QueueType::VideoCaptureMplane, Some(0));
+        buffer.0.length = 2;
+        let planes = buffer.1.as_mut().unwrap();
+        planes[0].bytesused = u32::MAX;
+        planes[1].bytesused = 16;
+        // Planes past the announced number are ignored.
+        planes[2].bytesused = 4096;
+        assert_eq!(bytes_used(&buffer), u32::MAX as u64 + 16);
+    }
+
+    #[test]
+    fn test_raw_queue_type() {
+        assert_eq!(
+            format!(
+                "{:?}",
+                RawQueueType(bindings::v4l2_buf_type_V4L2_BUF_TYPE_VIDEO_CAPTURE_MPLANE)
+            ),
+            format!("{:?}", QueueType::VideoCaptureMplane)
+        );
+        assert_eq!(format!("{:?}", RawQueueType(0x1234)), "4660");
+    }
+
+    #[test]
+    fn test_ioctl_span() {
+        let mock = MockIoctls::new();
+        mock.expect("vidioc_streamon", Ok(0))
+            .expect("vidioc_streamoff", Err(Errno::EBUSY));
+        let file = mock.file();
+
+        let output = capture(|| {
+            crate::ioctl::streamon(&file, QueueType::VideoOutputMplane).unwrap();
+            crate::ioctl::streamoff(&file, QueueType::VideoOutputMplane).unwrap_err();
+        });
+        mock.assert_done();
+
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2, "unexpected output: {output}");
+        assert!(lines[0].contains("v4l2r::ioctl"));
+        assert!(lines[0].contains("VIDIOC_STREAMON{queue=VideoOutputMplane}"));
+        assert!(!lines[0].contains("errno"));
+        assert!(lines[1].contains("VIDIOC_STREAMOFF{queue=VideoOutputMplane errno=EBUSY}"));
+    }
+
+    #[test]
+    fn test_queue_event() {
+        let output = capture(|| {
+            queue_event!(QueueType::VideoCapture, dropped = 3, "frames dropped");
+            decoder_event!("drain completed");
+        });
+
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3, "unexpected output: {output}");
+        assert!(lines[0].contains("queue{queue=VideoCapture}"));
+        assert!(lines[0].contains("v4l2r::queue: frames dropped dropped=3"));
+        assert!(lines[2].contains("v4l2r::decoder: drain completed"));
+    }
+}
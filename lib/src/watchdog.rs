@@ -0,0 +1,312 @@
+//! Detection of stalled codecs, e.g. hardware decoders or encoders whose firmware crashed.
+//!
+//! The only symptom of such a hang is usually that no buffer is ever dequeued again. A
+//! [`Watchdog`] keeps track of the time elapsed since the driver last made progress while it had
+//! work to do, and reports a [`Stalled`] event once this exceeds its timeout. The high-level
+//! [`decoder`](crate::decoder) and [`encoder`](crate::encoder) interfaces can run one on their
+//! CAPTURE thread, and clients driving the queues themselves can use it directly along with
+//! [`restart_queues`] to attempt a recovery.
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use nix::errno::Errno;
+use thiserror::Error;
+
+use crate::{
+    device::{
+        queue::{
+            direction::{Capture, Output},
+            BuffersAllocated, CanceledBuffer, GetCaptureBufferByIndex, GetOutputBufferByIndex,
+            Queue,
+        },
+        AllocatedQueue, Stream,
+    },
+    error::AsErrno,
+    ioctl,
+    memory::BufferHandles,
+};
+
+/// Reported when the driver has not made any progress for longer than the watchdog timeout while
+/// OUTPUT buffers were pending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stalled {
+    /// Number of OUTPUT buffers queued when the stall has been detected.
+    pub pending_output: usize,
+    /// Time of the last progress of the driver, or of the moment it was given work to do if it
+    /// was idle before.
+    pub last_progress: Instant,
+}
+
+/// Detects when a driver stops making progress while it has work to do.
+///
+/// The driver makes progress when a CAPTURE buffer is dequeued, which must be signaled using
+/// [`Watchdog::progress`], or when the number of pending OUTPUT buffers decreases. The latter
+/// prevents false positives with codecs that consume several OUTPUT buffers before producing a
+/// frame.
+///
+/// The driver is not expected to make progress when no OUTPUT buffer is pending, e.g. when the
+/// client is not feeding any input or once a drain has consumed all the input, nor when it cannot
+/// do so, e.g. when all the CAPTURE buffers are held by the client. Time spent in these states is
+/// not counted.
+///
+/// Since the watchdog is only updated when [`Watchdog::check`] is called, stalls are detected
+/// with a delay of up to [`Watchdog::next_check`].
+#[derive(Debug)]
+pub struct Watchdog {
+    timeout: Duration,
+    /// Time of the last progress, or `None` if the driver had nothing to do at the last check.
+    last_progress: Option<Instant>,
+    /// Number of OUTPUT buffers pending at the last check.
+    pending_output: usize,
+    /// Whether the current stall has already been reported.
+    reported: bool,
+    /// Whether a recovery has been attempted and the driver did not make progress since.
+    recovering: bool,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            last_progress: None,
+            pending_output: 0,
+            reported: false,
+            recovering: false,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Record that the driver made progress, i.e. a CAPTURE buffer has been dequeued.
+    pub fn progress(&mut self) {
+        self.progress_at(Instant::now())
+    }
+
+    fn progress_at(&mut self, now: Instant) {
+        self.last_progress = Some(now);
+        self.reported = false;
+        self.recovering = false;
+    }
+
+    /// Update the watchdog with the number of OUTPUT buffers currently queued, and whether the
+    /// driver is able to make progress at all.
+    ///
+    /// Returns the stall if the driver has not made progress for longer than the timeout. Each
+    /// stall is only reported once.
+    pub fn check(&mut self, pending_output: usize, can_progress: bool) -> Option<Stalled> {
+        self.check_at(pending_output, can_progress, Instant::now())
+    }
+
+    fn check_at(
+        &mut self,
+        pending_output: usize,
+        can_progress: bool,
+        now: Instant,
+    ) -> Option<Stalled> {
+        let previous_pending = std::mem::replace(&mut self.pending_output, pending_output);
+
+        if pending_output == 0 || !can_progress {
+            self.last_progress = None;
+            self.reported = false;
+            return None;
+        }
+
+        // OUTPUT buffers have been consumed.
+        if pending_output < previous_pending {
+            self.progress_at(now);
+        }
+
+        let last_progress = *self.last_progress.get_or_insert(now);
+        if self.reported || now.saturating_duration_since(last_progress) < self.timeout {
+            return None;
+        }
+
+        self.reported = true;
+        Some(Stalled {
+            pending_output,
+            last_progress,
+        })
+    }
+
+    /// Returns the time after which [`Watchdog::check`] should be called again.
+    ///
+    /// While the driver is idle, this is a fraction of the timeout so work becoming pending is
+    /// noticed soon enough.
+    pub fn next_check(&self) -> Duration {
+        self.next_check_at(Instant::now())
+    }
+
+    fn next_check_at(&self, now: Instant) -> Duration {
+        match self.last_progress {
+            Some(last_progress) if !self.reported => {
+                (last_progress + self.timeout).saturating_duration_since(now)
+            }
+            _ => self.timeout / 4,
+        }
+    }
+
+    /// Returns `true` if a recovery has been attempted and the driver has not made any progress
+    /// since, meaning it is unlikely to recover without reopening the device.
+    pub fn recovery_failed(&self) -> bool {
+        self.recovering
+    }
+
+    /// Record that a recovery is being attempted. The timeout starts over, so the driver gets
+    /// another chance to make progress.
+    pub fn recovery_started(&mut self) {
+        self.last_progress = None;
+        self.reported = false;
+        self.recovering = true;
+    }
+
+    /// Check `output_queue` and `capture_queue` for a stall, for clients driving the queues
+    /// themselves. [`Watchdog::progress`] must be called every time a CAPTURE buffer is dequeued.
+    pub fn check_queues<OP: BufferHandles, CP: BufferHandles>(
+        &mut self,
+        output_queue: &Queue<Output, BuffersAllocated<OP>>,
+        capture_queue: &Queue<Capture, BuffersAllocated<CP>>,
+    ) -> Option<Stalled> {
+        self.check(
+            output_queue.num_queued_buffers(),
+            capture_queue.num_queued_buffers() > 0,
+        )
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RecoverError {
+    #[error("the driver stalled again after a recovery, the device needs to be reopened")]
+    ReopenRequired,
+    #[error("error while stopping a queue: {0}")]
+    StreamOff(#[from] ioctl::StreamOffError),
+    #[error("error while starting a queue: {0}")]
+    StreamOn(#[from] ioctl::StreamOnError),
+    #[error("error while communicating with the CAPTURE thread")]
+    CaptureThread,
+}
+
+impl AsErrno for RecoverError {
+    fn errno(&self) -> Option<Errno> {
+        match self {
+            RecoverError::ReopenRequired => None,
+            RecoverError::StreamOff(e) => e.errno(),
+            RecoverError::StreamOn(e) => e.errno(),
+            RecoverError::CaptureThread => None,
+        }
+    }
+}
+
+/// Attempt to get the driver of `output_queue` and `capture_queue` going again after `watchdog`
+/// reported a stall, by stopping and restarting both queues and queueing the buffers that were
+/// pending again.
+///
+/// Returns the OUTPUT and CAPTURE buffers that could not be queued again, or
+/// [`RecoverError::ReopenRequired`] if a previous recovery did not help.
+#[allow(clippy::type_complexity)]
+pub fn restart_queues<OP, CP>(
+    watchdog: &mut Watchdog,
+    output_queue: &Queue<Output, BuffersAllocated<OP>>,
+    capture_queue: &Queue<Capture, BuffersAllocated<CP>>,
+) -> Result<(Vec<CanceledBuffer<OP>>, Vec<CanceledBuffer<CP>>), RecoverError>
+where
+    OP: BufferHandles,
+    CP: BufferHandles,
+    for<'a> Queue<Output, BuffersAllocated<OP>>: GetOutputBufferByIndex<'a, OP>,
+    for<'a> Queue<Capture, BuffersAllocated<CP>>: GetCaptureBufferByIndex<'a, CP>,
+{
+    if watchdog.recovery_failed() {
+        warn!("Driver stalled again after a recovery");
+        return Err(RecoverError::ReopenRequired);
+    }
+
+    debug!("Restarting stalled queues");
+    let canceled_capture = capture_queue.stream_off()?;
+    let canceled_output = output_queue.stream_off()?;
+    watchdog.recovery_started();
+    capture_queue.stream_on()?;
+    output_queue.stream_on()?;
+
+    Ok((
+        output_queue.requeue_canceled(canceled_output),
+        capture_queue.requeue_canceled(canceled_capture),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn test_watchdog_stall() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut watchdog = Watchdog::new(TIMEOUT);
+
+        // The timer starts when work becomes pending.
+        assert_eq!(watchdog.check_at(2, true, at(0)), None);
+        assert_eq!(watchdog.next_check_at(at(40)), Duration::from_millis(60));
+        assert_eq!(watchdog.check_at(2, true, at(99)), None);
+        assert_eq!(
+            watchdog.check_at(2, true, at(100)),
+            Some(Stalled {
+                pending_output: 2,
+                last_progress: at(0),
+            })
+        );
+        // Stalls are only reported once.
+        assert_eq!(watchdog.check_at(2, true, at(300)), None);
+
+        // Progress restarts the timer.
+        watchdog.progress_at(at(300));
+        assert_eq!(watchdog.check_at(2, true, at(350)), None);
+        // So do consumed OUTPUT buffers.
+        assert_eq!(watchdog.check_at(1, true, at(390)), None);
+        assert_eq!(watchdog.check_at(1, true, at(480)), None);
+        assert!(watchdog.check_at(1, true, at(490)).is_some());
+    }
+
+    #[test]
+    fn test_watchdog_idle() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut watchdog = Watchdog::new(TIMEOUT);
+
+        // No input is being fed.
+        assert_eq!(watchdog.check_at(0, true, at(0)), None);
+        assert_eq!(watchdog.next_check_at(at(0)), TIMEOUT / 4);
+        assert_eq!(watchdog.check_at(0, true, at(500)), None);
+        // Input fed after a long idle period is not late yet.
+        assert_eq!(watchdog.check_at(1, true, at(510)), None);
+        assert_eq!(watchdog.check_at(1, true, at(600)), None);
+
+        // The client holds all the CAPTURE buffers.
+        assert_eq!(watchdog.check_at(1, false, at(700)), None);
+        assert_eq!(watchdog.check_at(1, true, at(750)), None);
+        assert!(watchdog.check_at(1, true, at(850)).is_some());
+    }
+
+    #[test]
+    fn test_watchdog_recovery() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut watchdog = Watchdog::new(TIMEOUT);
+
+        assert!(watchdog.check_at(1, true, at(0)).is_none());
+        assert!(watchdog.check_at(1, true, at(100)).is_some());
+        assert!(!watchdog.recovery_failed());
+
+        // The driver gets another full timeout after a recovery.
+        watchdog.recovery_started();
+        assert!(watchdog.check_at(1, true, at(150)).is_none());
+        assert!(watchdog.check_at(1, true, at(250)).is_some());
+        assert!(watchdog.recovery_failed());
+
+        // Progress means the recovery succeeded.
+        watchdog.progress_at(at(300));
+        assert!(!watchdog.recovery_failed());
+    }
+}
@@ -0,0 +1,224 @@
+//! Harness for the integration tests running against the `vivid`, `vicodec`, `vim2m` and `vimc`
+//! virtual drivers.
+//!
+//! The nodes to test are discovered by scanning the capabilities of all the `/dev/video*` and
+//! `/dev/radio*` nodes and the topology of all the `/dev/media*` nodes, so the tests do not depend
+//! on the order in which the modules have been loaded. A test which node is not available is
+//! skipped with a message instead of failing, so these tests pass on machines without the modules.
+//! Load them with `modprobe vivid vicodec vim2m vimc` to actually run them.
+#![allow(dead_code)]
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+use v4l2r::device::media::MediaDevice;
+use v4l2r::device::queue::direction::{Capture, Output};
+use v4l2r::device::queue::{Queue, QueueInit};
+use v4l2r::device::{Device, DeviceConfig};
+use v4l2r::ioctl::{Capabilities, EntityFunction, FormatIterator};
+use v4l2r::{PixelFormat, QueueType};
+
+/// Role a test needs a node for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// `vivid` video capture node.
+    Capture,
+    /// `vivid` metadata capture node.
+    Metadata,
+    /// `vicodec` stateful FWHT decoder.
+    Decoder,
+    /// `vicodec` FWHT encoder.
+    Encoder,
+    /// `vim2m` memory-to-memory converter.
+    Converter,
+    /// `vimc` media device.
+    Camera,
+    /// `vivid` radio receiver node.
+    Radio,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Role::Capture => "vivid video capture node",
+            Role::Metadata => "vivid metadata capture node",
+            Role::Decoder => "vicodec stateful decoder node",
+            Role::Encoder => "vicodec encoder node",
+            Role::Converter => "vim2m converter node",
+            Role::Camera => "vimc media device",
+            Role::Radio => "vivid radio receiver node",
+        })
+    }
+}
+
+/// Nodes found for each role.
+#[derive(Debug, Default)]
+struct TestNodes {
+    capture: Option<PathBuf>,
+    metadata: Option<PathBuf>,
+    decoder: Option<PathBuf>,
+    encoder: Option<PathBuf>,
+    converter: Option<PathBuf>,
+    camera: Option<PathBuf>,
+    radio: Option<PathBuf>,
+}
+
+impl TestNodes {
+    fn slot(&mut self, role: Role) -> &mut Option<PathBuf> {
+        match role {
+            Role::Capture => &mut self.capture,
+            Role::Metadata => &mut self.metadata,
+            Role::Decoder => &mut self.decoder,
+            Role::Encoder => &mut self.encoder,
+            Role::Converter => &mut self.converter,
+            Role::Camera => &mut self.camera,
+            Role::Radio => &mut self.radio,
+        }
+    }
+}
+
+/// Returns the role of the `vicodec` node `device`, which device capabilities are `caps`.
+fn vicodec_role(device: &Device, caps: Capabilities) -> Option<Role> {
+    let (output, capture) = if caps.contains(Capabilities::VIDEO_M2M_MPLANE) {
+        (QueueType::VideoOutputMplane, QueueType::VideoCaptureMplane)
+    } else if caps.contains(Capabilities::VIDEO_M2M) {
+        (QueueType::VideoOutput, QueueType::VideoCapture)
+    } else {
+        return None;
+    };
+    let has_fwht = |queue| {
+        FormatIterator::new(device, queue).any(|fmt| fmt.pixelformat == PixelFormat::from(b"FWHT"))
+    };
+
+    // The stateless decoder takes `SFWH` and is thus not matched.
+    if has_fwht(output) {
+        Some(Role::Decoder)
+    } else if has_fwht(capture) {
+        Some(Role::Encoder)
+    } else {
+        None
+    }
+}
+
+/// Returns whether the media device at `path` is exposed by `vimc`, which sensors are named
+/// `Sensor A` and `Sensor B`.
+fn is_vimc(path: &Path) -> bool {
+    let Ok(topology) = MediaDevice::open(path).and_then(|media| media.get_topology()) else {
+        return false;
+    };
+
+    topology
+        .entity_by_name("Sensor A")
+        .is_some_and(|entity| entity.entity_function() == Some(EntityFunction::CamSensor))
+}
+
+/// Returns the role `path` can be used for, if any.
+fn node_role(path: &Path) -> Option<Role> {
+    if path.to_string_lossy().starts_with("/dev/media") {
+        return is_vimc(path).then_some(Role::Camera);
+    }
+
+    let device = Device::open(path, DeviceConfig::new()).ok()?;
+    let caps = device.caps().device_caps();
+
+    match device.caps().driver.as_str() {
+        "vivid" if caps.contains(Capabilities::RADIO | Capabilities::TUNER) => Some(Role::Radio),
+        "vivid"
+            if caps
+                .intersects(Capabilities::VIDEO_CAPTURE | Capabilities::VIDEO_CAPTURE_MPLANE) =>
+        {
+            Some(Role::Capture)
+        }
+        "vivid" if caps.contains(Capabilities::META_CAPTURE) => Some(Role::Metadata),
+        "vicodec" => vicodec_role(&device, caps),
+        "vim2m" => Some(Role::Converter),
+        _ => None,
+    }
+}
+
+/// Scans `/dev` for the nodes to test. The first node found for each role is used.
+fn scan() -> TestNodes {
+    let mut nodes = TestNodes::default();
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir("/dev") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| {
+                        ["video", "media", "radio"]
+                            .iter()
+                            .any(|prefix| name.starts_with(prefix))
+                    })
+                    .unwrap_or(false)
+            })
+            .collect(),
+        Err(_) => return nodes,
+    };
+    paths.sort();
+
+    for path in paths {
+        if let Some(role) = node_role(&path) {
+            nodes.slot(role).get_or_insert(path);
+        }
+    }
+
+    nodes
+}
+
+/// Returns the path of the node to use for `role`, if one has been found.
+pub fn node(role: Role) -> Option<PathBuf> {
+    static NODES: OnceLock<Mutex<TestNodes>> = OnceLock::new();
+
+    NODES
+        .get_or_init(|| Mutex::new(scan()))
+        .lock()
+        .unwrap()
+        .slot(role)
+        .clone()
+}
+
+/// Opens the node at `path`.
+pub fn open(path: &Path) -> Arc<Device> {
+    Arc::new(Device::open(path, DeviceConfig::new()).expect("failed to open device"))
+}
+
+/// Serializes the tests of a test binary, since the nodes can only stream from one file handle
+/// at a time.
+pub fn lock() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    // A failed test poisons the lock, which is not a reason to fail the other ones.
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns the path of the node to use for `role`, or skips the calling test if there is none.
+macro_rules! require_node {
+    ($role:expr) => {
+        match crate::common::node($role) {
+            Some(path) => path,
+            None => {
+                eprintln!("skipping: no {} found (is the module loaded?)", $role);
+                return;
+            }
+        }
+    };
+}
+
+/// Returns the CAPTURE queue of `device`, using the multi-planar API if the single-planar one is
+/// not supported.
+pub fn capture_queue(device: &Arc<Device>) -> Queue<Capture, QueueInit> {
+    Queue::get_capture_queue(Arc::clone(device))
+        .or_else(|_| Queue::get_capture_mplane_queue(Arc::clone(device)))
+        .expect("failed to obtain CAPTURE queue")
+}
+
+/// Returns the OUTPUT queue of `device`, using the multi-planar API if the single-planar one is
+/// not supported.
+pub fn output_queue(device: &Arc<Device>) -> Queue<Output, QueueInit> {
+    Queue::get_output_queue(Arc::clone(device))
+        .or_else(|_| Queue::get_output_mplane_queue(Arc::clone(device)))
+        .expect("failed to obtain OUTPUT queue")
+}
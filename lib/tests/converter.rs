@@ -0,0 +1,155 @@
+//! End-to-end tests of the converter against the `vim2m` virtual memory-to-memory driver.
+//!
+//! These tests are skipped if `vim2m` is not loaded. See the `common` module for details.
+#[macro_use]
+mod common;
+
+use v4l2r::converter::{Converter, ConverterConfig, ConverterOpenError};
+use v4l2r::device::queue::direction::Capture;
+use v4l2r::device::queue::dqbuf::ExportedDqBuffer;
+use v4l2r::memory::{DmaBufHandle, MmapHandle};
+use v4l2r::test_utils::{fill_pattern, frame_checksum, TestPattern};
+use v4l2r::{Format, PixelFormat, PlaneLayout};
+
+use common::Role;
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 480;
+/// Color of the source frames of the scaling test, in RGB order.
+const COLOR: [u8; 3] = [200, 100, 50];
+
+/// Returns a frame of `format` filled with `pattern`.
+fn pattern_frame(format: &Format, pattern: TestPattern) -> Vec<u8> {
+    let mut frame = vec![0u8; format.plane_fmt[0].sizeimage as usize];
+    fill_pattern(format, pattern, 0, std::slice::from_mut(&mut frame))
+        .expect("failed to generate frame");
+    frame
+}
+
+/// Returns the checksum of `frame`, a frame of `format`.
+fn checksum<S: AsRef<[u8]>>(format: &Format, frame: S) -> u64 {
+    frame_checksum(format, &[frame]).expect("failed to checksum frame")
+}
+
+#[test]
+fn converter_copy() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Converter);
+
+    let config = ConverterConfig::new(b"RGB3", WIDTH, HEIGHT, b"BGR3", WIDTH, HEIGHT);
+    let converter =
+        Converter::<Vec<MmapHandle>>::open(&path, &config).expect("failed to open converter");
+    assert_eq!(
+        converter.src_format().pixelformat,
+        PixelFormat::from(b"RGB3")
+    );
+    assert_eq!(
+        converter.dst_format().pixelformat,
+        PixelFormat::from(b"BGR3")
+    );
+
+    let frame = pattern_frame(converter.src_format(), TestPattern::ColorBars);
+    // The expected result is the source frame with the components of each pixel swapped.
+    let expected_format = Format {
+        plane_fmt: PlaneLayout::for_format(PixelFormat::from(b"BGR3"), WIDTH, HEIGHT, 0).unwrap(),
+        ..converter.dst_format().clone()
+    };
+    let mut expected = pattern_frame(&expected_format, TestPattern::ColorBars);
+    for pixel in expected.chunks_mut(3) {
+        pixel.reverse();
+    }
+    let expected = checksum(&expected_format, &expected);
+
+    // Convert several frames to check that the buffers are recycled.
+    for _ in 0..4 {
+        let converted = converter
+            .convert(&[frame.as_slice()])
+            .expect("failed to convert frame");
+        let mapping = converted
+            .get_plane_mapping(0)
+            .expect("failed to map converted frame");
+        assert_eq!(checksum(converter.dst_format(), &mapping), expected);
+    }
+
+    // A frame larger than the source buffers is rejected.
+    let too_large = vec![0u8; converter.src_format().plane_fmt[0].sizeimage as usize + 1];
+    assert!(converter.convert(&[too_large.as_slice()]).is_err());
+}
+
+#[test]
+fn converter_scale() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Converter);
+
+    let config = ConverterConfig::new(b"RGB3", WIDTH, HEIGHT, b"RGB3", WIDTH / 2, HEIGHT / 2);
+    let converter = match Converter::<Vec<MmapHandle>>::open(&path, &config) {
+        Ok(converter) => converter,
+        // Older versions of vim2m cannot scale.
+        Err(ConverterOpenError::UnsupportedFormat { .. }) => {
+            eprintln!("skipping: converter does not support scaling");
+            return;
+        }
+        Err(e) => panic!("failed to open converter: {}", e),
+    };
+
+    let frame = pattern_frame(converter.src_format(), TestPattern::Solid(COLOR));
+    let converted = converter
+        .convert(&[frame.as_slice()])
+        .expect("failed to convert frame");
+    assert_eq!(
+        converted.data.plane_bytesused(0),
+        Some(WIDTH / 2 * HEIGHT / 2 * 3)
+    );
+    let mapping = converted
+        .get_plane_mapping(0)
+        .expect("failed to map converted frame");
+    let dst_format = converter.dst_format();
+    assert_eq!(
+        checksum(dst_format, &mapping),
+        checksum(
+            dst_format,
+            pattern_frame(dst_format, TestPattern::Solid(COLOR))
+        )
+    );
+}
+
+/// Chains two converters, the frames converted by the first one being passed to the second one as
+/// DMABUFs.
+#[test]
+fn converter_dmabuf_chain() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Converter);
+
+    let to_bgr = Converter::<Vec<MmapHandle>>::open(
+        &path,
+        &ConverterConfig::new(b"RGB3", WIDTH, HEIGHT, b"BGR3", WIDTH, HEIGHT),
+    )
+    .expect("failed to open first converter");
+    let to_rgb = Converter::<Vec<DmaBufHandle<ExportedDqBuffer<Capture, Vec<MmapHandle>>>>>::open(
+        &path,
+        &ConverterConfig::new(b"BGR3", WIDTH, HEIGHT, b"RGB3", WIDTH, HEIGHT),
+    )
+    .expect("failed to open second converter");
+
+    let frame = pattern_frame(to_bgr.src_format(), TestPattern::ColorBars);
+    let expected = checksum(to_bgr.src_format(), &frame);
+    for _ in 0..4 {
+        let bgr = to_bgr
+            .convert(&[frame.as_slice()])
+            .expect("failed to convert frame");
+        let bytes_used = bgr.data.plane_bytesused(0).unwrap() as usize;
+        let bgr = bgr.export(0).expect("failed to export converted frame");
+
+        let (rgb, planes) = to_rgb
+            .convert_dmabuf(vec![DmaBufHandle::from(bgr)], &[bytes_used])
+            .expect("failed to convert DMABUF frame");
+        // The source frame is given back once converted.
+        assert_eq!(planes.len(), 1);
+        drop(planes);
+
+        let mapping = rgb
+            .get_plane_mapping(0)
+            .expect("failed to map converted frame");
+        assert_eq!(checksum(to_rgb.dst_format(), &mapping), expected);
+    }
+}
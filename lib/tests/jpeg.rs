@@ -0,0 +1,109 @@
+//! End-to-end tests of the MJPEG decoding pipeline.
+//!
+//! These tests need a stateful JPEG decoder and a capture node producing MJPEG frames, e.g. a UVC
+//! camera. `vivid` cannot produce MJPEG, so they are skipped unless such nodes are found.
+#[allow(unused_macros)]
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use v4l2r::decoder::jpeg::{find_jpeg_decoders, MjpegDecoder, MjpegDecoderConfig};
+use v4l2r::decoder::DecoderEvent;
+use v4l2r::device::{Device, DeviceConfig};
+use v4l2r::ioctl::{Capabilities, FormatIterator};
+use v4l2r::{PixelFormat, QueueType};
+
+/// Number of frames to decode in each test.
+const NUM_FRAMES: usize = 8;
+
+/// Returns whether `path` is a capture node that can produce MJPEG frames.
+fn is_mjpeg_camera(path: &Path) -> bool {
+    let device = match Device::open(path, DeviceConfig::new()) {
+        Ok(device) => device,
+        Err(_) => return false,
+    };
+    let caps = device.caps().device_caps();
+    let queue = if caps.contains(Capabilities::VIDEO_CAPTURE) {
+        QueueType::VideoCapture
+    } else if caps.contains(Capabilities::VIDEO_CAPTURE_MPLANE) {
+        QueueType::VideoCaptureMplane
+    } else {
+        return false;
+    };
+
+    FormatIterator::new(&device, queue).any(|fmt| fmt.pixelformat == PixelFormat::MJPEG)
+}
+
+/// Returns the first capture node producing MJPEG frames.
+fn find_mjpeg_camera() -> Option<PathBuf> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir("/dev")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with("video"))
+                .unwrap_or(false)
+        })
+        .collect();
+    paths.sort();
+
+    paths.into_iter().find(|path| is_mjpeg_camera(path))
+}
+
+/// Captures and decodes `NUM_FRAMES` frames, and checks that each valid frame has been decoded.
+fn decode_camera(config: MjpegDecoderConfig) {
+    let _lock = common::lock();
+    let decoder = match find_jpeg_decoders().into_iter().next() {
+        Some(decoder) => decoder,
+        None => {
+            eprintln!("skipping: no JPEG decoder found");
+            return;
+        }
+    };
+    let camera = match find_mjpeg_camera() {
+        Some(camera) => camera,
+        None => {
+            eprintln!("skipping: no MJPEG capture node found");
+            return;
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let mut pipeline = MjpegDecoder::new(&camera, &decoder, config, move |event| {
+        if let DecoderEvent::FrameDecoded(dqbuf) = event {
+            let bytes_used = dqbuf.data.plane_bytesused(0).unwrap_or(0);
+            // The buffer with the LAST flag may be empty.
+            if bytes_used > 0 {
+                let _ = sender.send(dqbuf.data.has_error());
+            }
+        }
+    })
+    .expect("failed to create MJPEG decoder");
+
+    let mut queued = 0;
+    for _ in 0..NUM_FRAMES {
+        if pipeline.decode_frame().expect("failed to decode frame") {
+            queued += 1;
+        }
+    }
+    assert_eq!(queued + pipeline.skipped_frames(), NUM_FRAMES);
+    assert!(pipeline.frame_dimensions().is_some() || queued == 0);
+    pipeline.stop().expect("failed to stop MJPEG decoder");
+
+    let decoded: Vec<bool> = receiver.try_iter().collect();
+    assert_eq!(decoded.len(), queued);
+    assert!(decoded.iter().all(|&error| !error));
+}
+
+#[test]
+fn mjpeg_decode_copy() {
+    decode_camera(MjpegDecoderConfig::new(640, 480));
+}
+
+#[test]
+fn mjpeg_decode_zero_copy() {
+    decode_camera(MjpegDecoderConfig::new(640, 480).zero_copy());
+}
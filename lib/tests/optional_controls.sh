@@ -0,0 +1,41 @@
+#!/bin/sh
+# Checks that the crate builds and passes its tests against both old and new kernel headers, and
+# that the optional controls are detected accordingly.
+#
+# Usage: optional_controls.sh <old kernel source directory> <new kernel source directory>
+#
+# The old kernel must predate the HEVC and AV1 stateless controls, which were added to the uapi
+# headers in Linux 6.0 and 6.5 respectively, but support the VP9 ones (e.g. Linux 5.18), and the
+# new kernel must support all of them (e.g. Linux 6.12). The uapi headers of each kernel are
+# installed into a temporary directory and the bindings are generated from them.
+# This requires libclang.
+set -eu
+
+if [ $# -ne 2 ]; then
+    echo "usage: $0 <old kernel source directory> <new kernel source directory>" >&2
+    exit 1
+fi
+
+manifest="$(dirname "$(realpath "$0")")/../Cargo.toml"
+
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+# Runs the tests of the crate against the headers of kernel source `$2`, expecting the optional
+# controls listed in `$3` to be available. `$1` is used to name the temporary directories.
+run_tests() {
+    name=$1
+    kernel_src=$(realpath "$2")
+    headers="$tmp/headers/$name"
+
+    echo "Testing against the headers of Linux $(make -s -C "$kernel_src" kernelversion)..."
+    make -s -C "$kernel_src" O="$tmp/build/$name" INSTALL_HDR_PATH="$headers" headers_install
+
+    V4L2R_VIDEODEV2_H_PATH="$headers/include/linux" \
+        BINDGEN_EXTRA_CLANG_ARGS="-I$headers/include" \
+        V4L2R_EXPECT_OPTIONAL_CONTROLS="$3" \
+        cargo test --quiet --lib --manifest-path "$manifest" --target-dir "$tmp/target/$name"
+}
+
+run_tests old "$1" "vp9"
+run_tests new "$2" "av1,hevc,vp9"
@@ -0,0 +1,92 @@
+//! End-to-end tests of radio devices against the radio receiver of the `vivid` virtual driver.
+//!
+//! These tests are skipped if `vivid` is not loaded. See the `common` module for details.
+#[macro_use]
+mod common;
+
+use v4l2r::device::radio::{RadioDevice, RadioDeviceError};
+use v4l2r::device::DeviceConfig;
+use v4l2r::ioctl::{Capabilities, HwFreqSeek, SeekDirection, TunerCapFlags, TunerType};
+
+use common::Role;
+
+/// Number of times to read RDS data before giving up on receiving a valid block.
+const RDS_READS: usize = 8;
+
+fn open(path: &std::path::Path) -> RadioDevice {
+    RadioDevice::open(path, DeviceConfig::new()).expect("failed to open radio device")
+}
+
+#[test]
+fn radio_tuner() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Radio);
+    let radio = open(&path);
+
+    let tuner = radio.tuner().expect("failed to get tuner");
+    assert!(matches!(tuner.type_, Some(TunerType::Radio)));
+    assert!(tuner.rangelow < tuner.rangehigh);
+
+    radio
+        .set_frequency(tuner.rangelow)
+        .expect("failed to set frequency");
+    assert_eq!(
+        radio.frequency().expect("failed to get frequency"),
+        tuner.rangelow
+    );
+
+    // Out-of-range frequencies are clamped.
+    radio
+        .set_frequency(tuner.rangehigh + 1)
+        .expect("failed to set frequency");
+    assert_eq!(
+        radio.frequency().expect("failed to get frequency"),
+        tuner.rangehigh
+    );
+}
+
+#[test]
+fn radio_seek_rds() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Radio);
+    let radio = open(&path);
+
+    let tuner = radio.tuner().expect("failed to get tuner");
+    radio
+        .set_frequency(tuner.rangelow)
+        .expect("failed to set frequency");
+    if radio
+        .caps()
+        .device_caps()
+        .contains(Capabilities::HW_FREQ_SEEK)
+    {
+        let mut seek = HwFreqSeek::new(SeekDirection::Up);
+        seek.wrap_around = tuner.capability.contains(TunerCapFlags::HWSEEK_WRAP);
+        radio.seek(&seek).expect("failed to seek");
+        let frequency = radio.frequency().expect("failed to get frequency");
+        assert!((tuner.rangelow..=tuner.rangehigh).contains(&frequency));
+    }
+
+    if !tuner.capability.contains(TunerCapFlags::RDS_BLOCK_IO) {
+        eprintln!("skipping RDS capture: the tuner does not support RDS block I/O");
+        return;
+    }
+    let mut valid = 0;
+    for _ in 0..RDS_READS {
+        let blocks = radio.read_rds(16).expect("failed to read RDS data");
+        assert!(!blocks.is_empty());
+        valid += blocks.iter().filter(|block| block.is_valid()).count();
+    }
+    assert!(valid > 0, "no valid RDS block received");
+}
+
+#[test]
+fn radio_open_video_node() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Capture);
+
+    assert!(matches!(
+        RadioDevice::open(&path, DeviceConfig::new()),
+        Err(RadioDeviceError::NotRadio(p)) if p == path
+    ));
+}
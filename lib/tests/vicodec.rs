@@ -0,0 +1,1217 @@
+//! End-to-end tests against the `vicodec` virtual codec driver.
+//!
+//! These tests are skipped if `vicodec` (and for some, `vivid`) is not loaded. See the `common`
+//! module for details.
+#[macro_use]
+mod common;
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use std::time::{Duration, Instant};
+
+use nix::sys::time::{TimeVal, TimeValLike};
+
+use v4l2r::decoder::stateful::pool::{
+    CaptureBufferPool, DecoderPool, DecoderPoolConfig, PooledCaptureProvider,
+};
+use v4l2r::decoder::stateful::{Decoder, DrainError};
+use v4l2r::decoder::{DecodeSetupError, DecoderEvent, FormatChangedReply};
+use v4l2r::device::queue::direction::{Capture, Output};
+use v4l2r::device::queue::dqbuf::DqBuffer;
+use v4l2r::device::queue::handles_provider::MmapProvider;
+use v4l2r::device::queue::*;
+use v4l2r::device::{AllocatedQueue, Stream, TryDequeue};
+use v4l2r::encoder::{
+    CaptureBufferSize, CompletedOutputBuffer, Encoder, EncoderError, SetCaptureBufferSizeError,
+};
+use v4l2r::ioctl::{self, Event, EventType, ExpbufFlags, SrcChanges, SubscribeEventFlags};
+use v4l2r::memory::{DmaBufHandle, MemoryType, MmapHandle};
+use v4l2r::test_utils::{fill_pattern, frame_checksum, psnr, FrameError, TestPattern};
+use v4l2r::{Format, PixelFormat, PlaneLayout, Rect};
+
+use common::Role;
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 480;
+/// Number of frames to encode and decode.
+const NUM_FRAMES: usize = 8;
+/// Every FWHT frame starts with this magic.
+const FWHT_MAGIC: &[u8] = b"OOOO";
+/// Minimum PSNR between a source frame and its decoded version, in dB. FWHT is lossy, but
+/// errors in the handling of strides or offsets result in much lower values.
+const MIN_PSNR: f64 = 30.0;
+
+type MmapOutputQueue = Queue<Output, BuffersAllocated<Vec<MmapHandle>>>;
+
+/// Fills `plane`, the single plane of a frame of `format`, with frame `frame_number` of the
+/// source pattern, and returns the number of bytes used.
+fn fill_source_frame<P: AsMut<[u8]>>(format: &Format, frame_number: usize, plane: &mut P) -> usize {
+    fill_pattern(
+        format,
+        TestPattern::Gradient,
+        frame_number as u32,
+        std::slice::from_mut(plane),
+    )
+    .expect("failed to generate frame");
+
+    format.plane_fmt[0].sizeimage as usize
+}
+
+/// Checks that `decoded`, frames of `format`, are close to the source frames they have been
+/// encoded from, and returns their checksums.
+fn check_decoded(format: &Format, decoded: &[Vec<u8>]) -> Vec<u64> {
+    let source_format = Format {
+        plane_fmt: PlaneLayout::for_format(format.pixelformat, format.width, format.height, 0)
+            .expect("unsupported decoded format"),
+        ..format.clone()
+    };
+    let mut source = vec![0u8; source_format.plane_fmt[0].sizeimage as usize];
+
+    decoded
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            fill_source_frame(&source_format, i, &mut source);
+            let psnr = psnr(&source_format, &[&source], format, &[frame])
+                .expect("failed to compare frames");
+            assert!(psnr >= MIN_PSNR, "frame {}: PSNR of {:.2} dB", i, psnr);
+
+            frame_checksum(format, &[frame]).expect("failed to checksum frame")
+        })
+        .collect()
+}
+
+/// Encodes `NUM_FRAMES` generated RGB frames using the encoder at `path`, and returns the encoded
+/// frames.
+fn encode(path: &Path) -> Vec<Vec<u8>> {
+    let device = common::open(path);
+    let mut output_queue = common::output_queue(&device);
+    let mut capture_queue = common::capture_queue(&device);
+
+    let _: Format = capture_queue
+        .change_format()
+        .expect("failed to get CAPTURE format")
+        .set_pixelformat(b"FWHT")
+        .apply()
+        .expect("failed to set CAPTURE format");
+    let output_format: Format = output_queue
+        .change_format()
+        .expect("failed to get OUTPUT format")
+        .set_size(WIDTH as usize, HEIGHT as usize)
+        .set_pixelformat(b"RGB3")
+        .apply()
+        .expect("failed to set OUTPUT format");
+    assert_eq!(output_format.pixelformat, PixelFormat::from(b"RGB3"));
+
+    let output_queue = output_queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate OUTPUT buffers");
+    let capture_queue = capture_queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate CAPTURE buffers");
+    output_queue
+        .stream_on()
+        .expect("failed to stream OUTPUT on");
+    capture_queue
+        .stream_on()
+        .expect("failed to stream CAPTURE on");
+
+    let mut encoded = Vec::new();
+    for i in 0..NUM_FRAMES {
+        capture_queue
+            .try_get_free_buffer()
+            .expect("no free CAPTURE buffer")
+            .queue()
+            .expect("failed to queue CAPTURE buffer");
+
+        let buffer = output_queue
+            .try_get_free_buffer()
+            .expect("no free OUTPUT buffer");
+        let mut mapping = buffer
+            .get_plane_mapping(0)
+            .expect("failed to map OUTPUT buffer");
+        let bytes_used = fill_source_frame(&output_format, i, &mut mapping);
+        buffer
+            .queue(&[bytes_used])
+            .expect("failed to queue OUTPUT buffer");
+
+        drop(
+            output_queue
+                .try_dequeue()
+                .expect("failed to dequeue OUTPUT buffer"),
+        );
+        let dqbuf = capture_queue
+            .try_dequeue()
+            .expect("failed to dequeue CAPTURE buffer");
+        encoded.push(encoded_frame(&dqbuf).expect("failed to read encoded frame"));
+    }
+
+    encoded
+}
+
+/// Returns a copy of the bytes used in the single plane of `dqbuf`, an encoded frame.
+///
+/// Fails if the driver reports more bytes used than the buffer can hold.
+fn encoded_frame(dqbuf: &DqBuffer<Capture, Vec<MmapHandle>>) -> Result<Vec<u8>, FrameError> {
+    let bytes_used = dqbuf.data.plane_bytesused(0).unwrap_or(0) as usize;
+    let mapping = dqbuf
+        .get_plane_mapping(0)
+        .ok_or(FrameError::MappingFailed(0))?;
+
+    mapping
+        .as_ref()
+        .get(..bytes_used)
+        .map(<[u8]>::to_vec)
+        .ok_or(FrameError::PlaneTooSmall(0))
+}
+
+/// Copies `frame` into a free buffer of `queue` and queues it.
+fn queue_encoded_frame(queue: &MmapOutputQueue, frame: &[u8]) {
+    let buffer = queue.try_get_free_buffer().expect("no free OUTPUT buffer");
+    let mut mapping = buffer
+        .get_plane_mapping(0)
+        .expect("failed to map OUTPUT buffer");
+    mapping.as_mut()[..frame.len()].copy_from_slice(frame);
+    buffer
+        .queue(&[frame.len()])
+        .expect("failed to queue OUTPUT buffer");
+}
+
+/// Decodes `encoded`, a `width`x`height` stream, using the stateful decoder at `path`, and returns
+/// the CAPTURE format along with the decoded frames.
+fn decode(path: &Path, width: u32, height: u32, encoded: &[Vec<u8>]) -> (Format, Vec<Vec<u8>>) {
+    let device = common::open(path);
+    ioctl::subscribe_event(
+        &*device,
+        EventType::SourceChange(0),
+        SubscribeEventFlags::empty(),
+    )
+    .expect("failed to subscribe to source change events");
+
+    let mut output_queue = common::output_queue(&device);
+    let mut capture_queue = common::capture_queue(&device);
+
+    let _: Format = output_queue
+        .change_format()
+        .expect("failed to get OUTPUT format")
+        .set_size(width as usize, height as usize)
+        .set_pixelformat(b"FWHT")
+        .apply()
+        .expect("failed to set OUTPUT format");
+    let output_queue = output_queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate OUTPUT buffers");
+    output_queue
+        .stream_on()
+        .expect("failed to stream OUTPUT on");
+
+    // Queue the first frame and wait for the decoder to report the format of the stream.
+    let mut frames = encoded.iter();
+    queue_encoded_frame(&output_queue, frames.next().expect("no frame to decode"));
+    loop {
+        match ioctl::dqevent::<Event>(&*device).expect("failed to dequeue event") {
+            Event::SrcChangeEvent(changes) if changes.contains(SrcChanges::RESOLUTION) => break,
+            _ => (),
+        }
+    }
+
+    let format: Format = capture_queue
+        .change_format()
+        .expect("failed to get CAPTURE format")
+        .set_pixelformat(b"RGB3")
+        .apply()
+        .expect("failed to set CAPTURE format");
+    assert_eq!((format.width, format.height), (width, height));
+    assert_eq!(format.pixelformat, PixelFormat::from(b"RGB3"));
+
+    let capture_queue = capture_queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate CAPTURE buffers");
+    while let Ok(buffer) = capture_queue.try_get_free_buffer() {
+        buffer.queue().expect("failed to queue CAPTURE buffer");
+    }
+    capture_queue
+        .stream_on()
+        .expect("failed to stream CAPTURE on");
+
+    let mut decoded = Vec::new();
+    loop {
+        let dqbuf = capture_queue
+            .try_dequeue()
+            .expect("failed to dequeue CAPTURE buffer");
+        assert!(!dqbuf.data.has_error());
+        let mapping = dqbuf
+            .get_plane_mapping(0)
+            .expect("failed to map CAPTURE buffer");
+        decoded.push(mapping.as_ref().to_vec());
+        drop(mapping);
+        drop(dqbuf);
+
+        if decoded.len() == encoded.len() {
+            break;
+        }
+
+        capture_queue
+            .try_get_free_buffer()
+            .expect("no free CAPTURE buffer")
+            .queue()
+            .expect("failed to queue CAPTURE buffer");
+        if let Some(frame) = frames.next() {
+            // Recycle the OUTPUT buffer of a frame that has been decoded.
+            if output_queue.num_free_buffers() == 0 {
+                drop(
+                    output_queue
+                        .try_dequeue()
+                        .expect("failed to dequeue OUTPUT buffer"),
+                );
+            }
+            queue_encoded_frame(&output_queue, frame);
+        }
+    }
+
+    (format, decoded)
+}
+
+/// Calls `f` until it returns `true`, failing the test if that takes too long.
+fn wait_until<F: FnMut() -> bool>(what: &str, mut f: F) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !f() {
+        assert!(
+            Instant::now() < deadline,
+            "timeout while waiting for {}",
+            what
+        );
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn encode_decode_round_trip() {
+    let _lock = common::lock();
+    let encoder = require_node!(Role::Encoder);
+    let decoder = require_node!(Role::Decoder);
+
+    let encoded = encode(&encoder);
+    assert_eq!(encoded.len(), NUM_FRAMES);
+    for frame in &encoded {
+        assert!(frame.starts_with(FWHT_MAGIC));
+    }
+
+    // FWHT is lossy, so the decoded frames are only close to the source ones. Also check that
+    // decoding is deterministic, and that every frame has been decoded in order since the content
+    // of the source frames changes every frame.
+    let (format, decoded) = decode(&decoder, WIDTH, HEIGHT, &encoded);
+    let checksums = check_decoded(&format, &decoded);
+    assert_eq!(checksums.len(), NUM_FRAMES);
+    assert!(checksums.windows(2).all(|w| w[0] != w[1]));
+    let (format, decoded) = decode(&decoder, WIDTH, HEIGHT, &encoded);
+    assert_eq!(check_decoded(&format, &decoded), checksums);
+}
+
+#[test]
+fn encoder_reconfigure() {
+    let _lock = common::lock();
+    let encoder = require_node!(Role::Encoder);
+    let decoder = require_node!(Role::Decoder);
+    const SIZES: [(u32, u32); 2] = [(WIDTH, HEIGHT), (1280, 720)];
+
+    // The high-level encoder only supports multi-planar queues.
+    let encoder = match Encoder::open(&encoder) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            eprintln!("skipping: cannot open encoder: {}", e);
+            return;
+        }
+    };
+    let encoder = encoder
+        .set_capture_format(|f| {
+            let _: Format = f.set_pixelformat(b"FWHT").apply()?;
+            Ok(())
+        })
+        .expect("failed to set CAPTURE format")
+        .set_output_format(|f| {
+            let _: Format = f
+                .set_size(WIDTH as usize, HEIGHT as usize)
+                .set_pixelformat(b"RGB3")
+                .apply()?;
+            Ok(())
+        })
+        .expect("failed to set OUTPUT format");
+    let capture_format = encoder
+        .get_capture_format()
+        .expect("failed to get CAPTURE format");
+    let encoder = encoder
+        .allocate_output_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate OUTPUT buffers");
+    // The parameter change is notified even though the headers are not produced separately.
+    let parameter_changes = Arc::new(Mutex::new(Vec::new()));
+    let encoder = encoder
+        .allocate_capture_buffers(2, MmapProvider::new(&capture_format))
+        .expect("failed to allocate CAPTURE buffers")
+        .set_parameters_changed_cb({
+            let parameter_changes = Arc::clone(&parameter_changes);
+            move |format: &Format| {
+                parameter_changes
+                    .lock()
+                    .unwrap()
+                    .push((format.width, format.height))
+            }
+        });
+
+    let encoded = Arc::new(Mutex::new(Vec::new()));
+    let output_ready_cb = {
+        let encoded = Arc::clone(&encoded);
+        move |dqbuf: DqBuffer<Capture, Vec<MmapHandle>>| {
+            let bytes_used = dqbuf.data.plane_bytesused(0).unwrap_or(0) as usize;
+            // Skip the empty buffer signaling the end of the drain.
+            if bytes_used == 0 {
+                return;
+            }
+            encoded
+                .lock()
+                .unwrap()
+                .push(encoded_frame(&dqbuf).expect("failed to read encoded frame"));
+        }
+    };
+    let mut encoder = encoder
+        .start(
+            |_: CompletedOutputBuffer<Vec<MmapHandle>>| (),
+            output_ready_cb,
+        )
+        .expect("failed to start encoder");
+
+    let mut segments = Vec::new();
+    for (i, &(width, height)) in SIZES.iter().enumerate() {
+        if i > 0 {
+            let format = Format {
+                width,
+                height,
+                plane_fmt: Vec::new(),
+                ..encoder
+                    .get_output_format()
+                    .expect("failed to get OUTPUT format")
+            };
+            encoder = encoder
+                .reconfigure(&format)
+                .expect("failed to reconfigure encoder");
+            // All the frames of the previous segment have been encoded while draining.
+            segments.push(std::mem::take(&mut *encoded.lock().unwrap()));
+            assert_eq!(*parameter_changes.lock().unwrap(), vec![(width, height)]);
+        }
+
+        let format = encoder
+            .get_output_format()
+            .expect("failed to get OUTPUT format");
+        assert_eq!((format.width, format.height), (width, height));
+        for i in 0..NUM_FRAMES {
+            let buffer = encoder.get_buffer().expect("failed to get OUTPUT buffer");
+            let mut mapping = buffer
+                .get_plane_mapping(0)
+                .expect("failed to map OUTPUT buffer");
+            let bytes_used = fill_source_frame(&format, i, &mut mapping);
+            buffer
+                .queue(&[bytes_used])
+                .expect("failed to queue OUTPUT buffer");
+        }
+    }
+    encoder.stop().expect("failed to stop encoder");
+    segments.push(std::mem::take(&mut *encoded.lock().unwrap()));
+
+    // Each segment is a stream of its own, which can be decoded from its first frame.
+    for (segment, &(width, height)) in segments.iter().zip(SIZES.iter()) {
+        assert_eq!(segment.len(), NUM_FRAMES);
+        assert!(segment.iter().all(|frame| frame.starts_with(FWHT_MAGIC)));
+
+        let (format, decoded) = decode(&decoder, width, height, segment);
+        let checksums = check_decoded(&format, &decoded);
+        assert_eq!(checksums.len(), NUM_FRAMES);
+        assert!(checksums.windows(2).all(|w| w[0] != w[1]));
+    }
+}
+
+#[test]
+fn codec_stats() {
+    let _lock = common::lock();
+    let encoder = require_node!(Role::Encoder);
+    let decoder = require_node!(Role::Decoder);
+    let frame_timestamp = |i: usize| TimeVal::microseconds(1000 * (i as i64 + 1));
+
+    let encoder = match Encoder::open(&encoder) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            eprintln!("skipping: cannot open encoder: {}", e);
+            return;
+        }
+    };
+    let encoder = encoder
+        .set_capture_format(|f| {
+            let _: Format = f.set_pixelformat(b"FWHT").apply()?;
+            Ok(())
+        })
+        .expect("failed to set CAPTURE format")
+        .set_output_format(|f| {
+            let _: Format = f
+                .set_size(WIDTH as usize, HEIGHT as usize)
+                .set_pixelformat(b"RGB3")
+                .apply()?;
+            Ok(())
+        })
+        .expect("failed to set OUTPUT format");
+    let capture_format = encoder
+        .get_capture_format()
+        .expect("failed to get CAPTURE format");
+    let encoder = encoder
+        .allocate_output_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate OUTPUT buffers")
+        .allocate_capture_buffers(2, MmapProvider::new(&capture_format))
+        .expect("failed to allocate CAPTURE buffers");
+
+    let encoded = Arc::new(Mutex::new(Vec::new()));
+    let output_ready_cb = {
+        let encoded = Arc::clone(&encoded);
+        move |dqbuf: DqBuffer<Capture, Vec<MmapHandle>>| {
+            let bytes_used = dqbuf.data.plane_bytesused(0).unwrap_or(0) as usize;
+            if bytes_used == 0 {
+                return;
+            }
+            encoded
+                .lock()
+                .unwrap()
+                .push(encoded_frame(&dqbuf).expect("failed to read encoded frame"));
+        }
+    };
+    let mut encoder = encoder
+        .start(
+            |_: CompletedOutputBuffer<Vec<MmapHandle>>| (),
+            output_ready_cb,
+        )
+        .expect("failed to start encoder");
+
+    let format = encoder
+        .get_output_format()
+        .expect("failed to get OUTPUT format");
+    for i in 0..NUM_FRAMES {
+        let buffer = encoder.get_buffer().expect("failed to get OUTPUT buffer");
+        let mut mapping = buffer
+            .get_plane_mapping(0)
+            .expect("failed to map OUTPUT buffer");
+        let bytes_used = fill_source_frame(&format, i, &mut mapping);
+        buffer
+            .set_timestamp(frame_timestamp(i))
+            .queue(&[bytes_used])
+            .expect("failed to queue OUTPUT buffer");
+    }
+    wait_until("the encoded frames", || {
+        encoded.lock().unwrap().len() == NUM_FRAMES
+    });
+    // OUTPUT buffers are only accounted for once they have been dequeued.
+    wait_until("the OUTPUT buffers of the encoder", || {
+        let _ = encoder.try_get_free_buffer();
+        encoder.stats().frames_in == NUM_FRAMES as u64
+    });
+    let stats = encoder.stats();
+    assert_eq!(stats.frames_in, NUM_FRAMES as u64);
+    assert_eq!(stats.frames_out, NUM_FRAMES as u64);
+    assert_eq!(stats.latency_samples, NUM_FRAMES as u64);
+    assert_eq!(stats.error_buffers, 0);
+    assert!(stats.average_latency.is_some());
+    assert!(stats.first_frame_latency.is_some());
+    encoder.stop().expect("failed to stop encoder");
+
+    let encoded = std::mem::take(&mut *encoded.lock().unwrap());
+    let decoded = Arc::new(AtomicUsize::new(0));
+    let event_cb = {
+        let decoded = Arc::clone(&decoded);
+        move |event: DecoderEvent<MmapProvider>| {
+            if let DecoderEvent::FrameDecoded(dqbuf) = event {
+                if dqbuf.data.plane_bytesused(0).unwrap_or(0) > 0 {
+                    decoded.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+    };
+    let set_capture_format_cb = |f: FormatBuilder,
+                                 _: Rect,
+                                 min_num_buffers: usize|
+     -> anyhow::Result<FormatChangedReply<MmapProvider>> {
+        let format: Format = f.set_pixelformat(b"RGB3").apply()?;
+        Ok(FormatChangedReply {
+            provider: MmapProvider::new(&format),
+            mem_type: MemoryType::Mmap,
+            num_buffers: min_num_buffers,
+        })
+    };
+    let decoder = match Decoder::open(&decoder) {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            eprintln!("skipping: cannot open decoder: {}", e);
+            return;
+        }
+    };
+    let mut decoder = decoder
+        .set_output_format(|f| {
+            let _: Format = f
+                .set_size(WIDTH as usize, HEIGHT as usize)
+                .set_pixelformat(b"FWHT")
+                .apply()?;
+            Ok(())
+        })
+        .expect("failed to set OUTPUT format")
+        .allocate_output_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate OUTPUT buffers")
+        .start(|_| (), event_cb, set_capture_format_cb)
+        .expect("failed to start decoder");
+
+    for (i, frame) in encoded.iter().enumerate() {
+        let buffer = decoder.get_buffer().expect("failed to get OUTPUT buffer");
+        let mut mapping = buffer
+            .get_plane_mapping(0)
+            .expect("failed to map OUTPUT buffer");
+        mapping.as_mut()[..frame.len()].copy_from_slice(frame);
+        drop(mapping);
+        buffer
+            .set_timestamp(frame_timestamp(i))
+            .queue(&[frame.len()])
+            .expect("failed to queue OUTPUT buffer");
+    }
+    decoder.drain(true).expect("failed to drain decoder");
+    assert_eq!(decoded.load(Ordering::SeqCst), NUM_FRAMES);
+    wait_until("the OUTPUT buffers of the decoder", || {
+        let _ = decoder.try_get_free_buffer();
+        decoder.stats().frames_in == NUM_FRAMES as u64
+    });
+    let stats = decoder.stats();
+    assert_eq!(stats.frames_in, NUM_FRAMES as u64);
+    assert_eq!(stats.frames_out, NUM_FRAMES as u64);
+    assert_eq!(stats.latency_samples, NUM_FRAMES as u64);
+    assert_eq!(stats.resolution_changes, 1);
+    assert_eq!(stats.error_buffers, 0);
+    decoder.stop().expect("failed to stop decoder");
+}
+
+#[test]
+fn decoder_setup_errors() {
+    let _lock = common::lock();
+    let decoder = require_node!(Role::Decoder);
+    const ABSURD_SIZE: u32 = 1 << 16;
+
+    // Starts a decoder after setting its OUTPUT format with `f`, and returns the setup errors it
+    // reported.
+    let setup_errors = |f: &dyn Fn(FormatBuilder) -> FormatBuilder| {
+        let decoder = match Decoder::open(&decoder) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                eprintln!("skipping: cannot open decoder: {}", e);
+                return None;
+            }
+        };
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let event_cb = {
+            let errors = Arc::clone(&errors);
+            move |event: DecoderEvent<MmapProvider>| {
+                if let DecoderEvent::SetupError(e) = event {
+                    errors.lock().unwrap().push(e);
+                }
+            }
+        };
+        let decoder = decoder
+            .set_output_format(|builder| {
+                let _: Format = f(builder).apply()?;
+                Ok(())
+            })
+            .expect("setup errors must not be returned by set_output_format")
+            .allocate_output_buffers::<Vec<MmapHandle>>(2)
+            .expect("failed to allocate OUTPUT buffers")
+            .start(
+                |_| (),
+                event_cb,
+                |f: FormatBuilder,
+                 _: Rect,
+                 min_num_buffers: usize|
+                 -> anyhow::Result<FormatChangedReply<MmapProvider>> {
+                    Ok(FormatChangedReply {
+                        provider: MmapProvider::new(f.format()),
+                        mem_type: MemoryType::Mmap,
+                        num_buffers: min_num_buffers,
+                    })
+                },
+            )
+            .expect("failed to start decoder");
+        decoder.stop().expect("failed to stop decoder");
+        let errors = std::mem::take(&mut *errors.lock().unwrap());
+        Some(errors)
+    };
+
+    // vicodec replaces unknown codecs with FWHT.
+    let Some(errors) = setup_errors(&|f| {
+        f.set_size(WIDTH as usize, HEIGHT as usize)
+            .set_pixelformat(b"XXXX")
+    }) else {
+        return;
+    };
+    assert!(
+        matches!(
+            errors.as_slice(),
+            [DecodeSetupError::UnsupportedCodec(pixel_format)]
+                if *pixel_format == PixelFormat::from(b"XXXX")
+        ),
+        "unexpected setup errors: {:?}",
+        errors
+    );
+
+    // vicodec clamps the resolution to the limits it reports.
+    let errors = setup_errors(&|f| {
+        f.set_size(ABSURD_SIZE as usize, ABSURD_SIZE as usize)
+            .set_pixelformat(b"FWHT")
+    })
+    .unwrap();
+    match errors.as_slice() {
+        [DecodeSetupError::UnsupportedResolution {
+            max: Some((max_width, max_height)),
+        }] => assert!(*max_width < ABSURD_SIZE && *max_height < ABSURD_SIZE),
+        _ => panic!("unexpected setup errors: {:?}", errors),
+    }
+
+    // Supported streams do not report any error.
+    let errors = setup_errors(&|f| {
+        f.set_size(WIDTH as usize, HEIGHT as usize)
+            .set_pixelformat(b"FWHT")
+    })
+    .unwrap();
+    assert!(errors.is_empty(), "unexpected setup errors: {:?}", errors);
+}
+
+#[test]
+fn decoder_drain_while_paused() {
+    let _lock = common::lock();
+    let encoder = require_node!(Role::Encoder);
+    let decoder = require_node!(Role::Decoder);
+
+    let encoded = encode(&encoder);
+    let decoder = match Decoder::open(&decoder) {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            eprintln!("skipping: cannot open decoder: {}", e);
+            return;
+        }
+    };
+    let decoded = Arc::new(AtomicUsize::new(0));
+    let event_cb = {
+        let decoded = Arc::clone(&decoded);
+        move |event: DecoderEvent<MmapProvider>| {
+            if let DecoderEvent::FrameDecoded(dqbuf) = event {
+                if dqbuf.data.plane_bytesused(0).unwrap_or(0) > 0 {
+                    decoded.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+    };
+    let mut decoder = decoder
+        .set_output_format(|f| {
+            let _: Format = f
+                .set_size(WIDTH as usize, HEIGHT as usize)
+                .set_pixelformat(b"FWHT")
+                .apply()?;
+            Ok(())
+        })
+        .expect("failed to set OUTPUT format")
+        .allocate_output_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate OUTPUT buffers")
+        .start(
+            |_| (),
+            event_cb,
+            |f: FormatBuilder,
+             _: Rect,
+             min_num_buffers: usize|
+             -> anyhow::Result<FormatChangedReply<MmapProvider>> {
+                Ok(FormatChangedReply {
+                    provider: MmapProvider::new(f.format()),
+                    mem_type: MemoryType::Mmap,
+                    num_buffers: min_num_buffers,
+                })
+            },
+        )
+        .expect("failed to start decoder");
+
+    for frame in &encoded {
+        let buffer = decoder.get_buffer().expect("failed to get OUTPUT buffer");
+        let mut mapping = buffer
+            .get_plane_mapping(0)
+            .expect("failed to map OUTPUT buffer");
+        mapping.as_mut()[..frame.len()].copy_from_slice(frame);
+        drop(mapping);
+        buffer
+            .queue(&[frame.len()])
+            .expect("failed to queue OUTPUT buffer");
+    }
+    // The decoder can only be paused once the format of the stream is known.
+    wait_until("the first decoded frame", || {
+        decoded.load(Ordering::SeqCst) > 0
+    });
+
+    decoder.pause().expect("failed to pause decoder");
+    // A paused decoder cannot complete a drain, so it must not wait for it.
+    assert!(matches!(decoder.drain(true), Err(DrainError::Paused)));
+    assert!(matches!(decoder.drain(false), Err(DrainError::Paused)));
+
+    // Once resumed, the drain completes and no frame has been lost.
+    decoder.resume().expect("failed to resume decoder");
+    assert!(decoder.drain(true).expect("failed to drain decoder"));
+    assert_eq!(decoded.load(Ordering::SeqCst), NUM_FRAMES);
+    decoder.stop().expect("failed to stop decoder");
+}
+
+#[test]
+fn decoder_pool_sessions() {
+    const NUM_SESSIONS: usize = 8;
+
+    let _lock = common::lock();
+    let encoder = require_node!(Role::Encoder);
+    let decoder = require_node!(Role::Decoder);
+
+    let encoded = Arc::new(encode(&encoder));
+    let pool = match DecoderPool::new(&decoder, DecoderPoolConfig::new(NUM_SESSIONS).throttle()) {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("skipping: cannot create decoder pool: {}", e);
+            return;
+        }
+    };
+    let buffer_pool = CaptureBufferPool::<Vec<MmapHandle>>::new();
+    // All the sessions are checked while running, then stopped together.
+    let decoded_barrier = Arc::new(Barrier::new(NUM_SESSIONS + 1));
+    let stop_barrier = Arc::new(Barrier::new(NUM_SESSIONS + 1));
+
+    let sessions = (0..NUM_SESSIONS)
+        .map(|_| {
+            let pool = pool.clone();
+            let buffer_pool = buffer_pool.clone();
+            let encoded = Arc::clone(&encoded);
+            let decoded_barrier = Arc::clone(&decoded_barrier);
+            let stop_barrier = Arc::clone(&stop_barrier);
+            std::thread::spawn(move || {
+                let decoded = Arc::new(AtomicUsize::new(0));
+                let event_cb = {
+                    let decoded = Arc::clone(&decoded);
+                    move |event: DecoderEvent<PooledCaptureProvider<Vec<MmapHandle>>>| {
+                        if let DecoderEvent::FrameDecoded(dqbuf) = event {
+                            if dqbuf.data.plane_bytesused(0).unwrap_or(0) > 0 {
+                                decoded.fetch_add(1, Ordering::SeqCst);
+                            }
+                        }
+                    }
+                };
+                let set_capture_format_cb = move |f: FormatBuilder,
+                                                  _: Rect,
+                                                  min_num_buffers: usize|
+                      -> anyhow::Result<
+                    FormatChangedReply<PooledCaptureProvider<Vec<MmapHandle>>>,
+                > {
+                    let format = f.format().clone();
+                    let provider = buffer_pool.provider(&format, min_num_buffers, |format| {
+                        Ok::<_, anyhow::Error>(vec![MmapHandle; format.plane_fmt.len()])
+                    })?;
+                    Ok(FormatChangedReply {
+                        provider,
+                        mem_type: MemoryType::Mmap,
+                        num_buffers: min_num_buffers,
+                    })
+                };
+
+                let (decoder, session) = pool.open_session().expect("failed to open session");
+                let decoder = decoder
+                    .set_output_format(|f| {
+                        let _: Format = f
+                            .set_size(WIDTH as usize, HEIGHT as usize)
+                            .set_pixelformat(b"FWHT")
+                            .apply()?;
+                        Ok(())
+                    })
+                    .expect("failed to set OUTPUT format")
+                    .allocate_output_buffers::<Vec<MmapHandle>>(2)
+                    .expect("failed to allocate OUTPUT buffers");
+                let mut decoder = session
+                    .throttled(|| decoder.start(|_| (), event_cb, set_capture_format_cb))
+                    .expect("failed to start decoder");
+
+                for frame in encoded.iter() {
+                    let buffer = decoder.get_buffer().expect("failed to get OUTPUT buffer");
+                    let mut mapping = buffer
+                        .get_plane_mapping(0)
+                        .expect("failed to map OUTPUT buffer");
+                    mapping.as_mut()[..frame.len()].copy_from_slice(frame);
+                    drop(mapping);
+                    buffer
+                        .queue(&[frame.len()])
+                        .expect("failed to queue OUTPUT buffer");
+                }
+                decoder.drain(true).expect("failed to drain decoder");
+                assert_eq!(decoded.load(Ordering::SeqCst), NUM_FRAMES);
+
+                decoded_barrier.wait();
+                stop_barrier.wait();
+                decoder.stop().expect("failed to stop decoder");
+                drop(session);
+            })
+        })
+        .collect::<Vec<_>>();
+
+    decoded_barrier.wait();
+    let stats = pool.stats();
+    assert_eq!(stats.active_sessions, NUM_SESSIONS);
+    assert_eq!(stats.waiting_sessions, 0);
+    // Sessions are tracked without any action from the client.
+    assert_eq!(
+        stats.decoding.frames_out,
+        (NUM_SESSIONS * NUM_FRAMES) as u64
+    );
+    stop_barrier.wait();
+
+    for session in sessions {
+        session.join().expect("decoding session failed");
+    }
+    let stats = pool.stats();
+    assert_eq!(stats.active_sessions, 0);
+    assert_eq!(stats.total_sessions, NUM_SESSIONS as u64);
+    assert!(stats.peak_sessions <= NUM_SESSIONS);
+    // The CAPTURE buffers of the stopped sessions are kept for the next ones.
+    assert!(buffer_pool.num_available() > 0);
+}
+
+#[test]
+fn encoder_watchdog() {
+    let _lock = common::lock();
+    let encoder = require_node!(Role::Encoder);
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    let encoder = match Encoder::open(&encoder) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            eprintln!("skipping: cannot open encoder: {}", e);
+            return;
+        }
+    };
+    let encoder = encoder
+        .set_capture_format(|f| {
+            let _: Format = f.set_pixelformat(b"FWHT").apply()?;
+            Ok(())
+        })
+        .expect("failed to set CAPTURE format")
+        .set_output_format(|f| {
+            let _: Format = f
+                .set_size(WIDTH as usize, HEIGHT as usize)
+                .set_pixelformat(b"RGB3")
+                .apply()?;
+            Ok(())
+        })
+        .expect("failed to set OUTPUT format");
+    let capture_format = encoder
+        .get_capture_format()
+        .expect("failed to get CAPTURE format");
+    let encoder = encoder
+        .allocate_output_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate OUTPUT buffers");
+
+    let stalls = Arc::new(AtomicUsize::new(0));
+    let encoder = encoder
+        .allocate_capture_buffers(2, MmapProvider::new(&capture_format))
+        .expect("failed to allocate CAPTURE buffers")
+        .set_watchdog(TIMEOUT)
+        .set_error_cb({
+            let stalls = Arc::clone(&stalls);
+            move |error| {
+                if let EncoderError::Stalled(_) = error {
+                    stalls.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+
+    let encoded = Arc::new(Mutex::new(Vec::new()));
+    let output_ready_cb = {
+        let encoded = Arc::clone(&encoded);
+        move |dqbuf: DqBuffer<Capture, Vec<MmapHandle>>| {
+            let bytes_used = dqbuf.data.plane_bytesused(0).unwrap_or(0) as usize;
+            if bytes_used == 0 {
+                return;
+            }
+            encoded
+                .lock()
+                .unwrap()
+                .push(encoded_frame(&dqbuf).expect("failed to read encoded frame"));
+        }
+    };
+    let mut encoder = encoder
+        .start(
+            |_: CompletedOutputBuffer<Vec<MmapHandle>>| (),
+            output_ready_cb,
+        )
+        .expect("failed to start encoder");
+
+    let format = encoder
+        .get_output_format()
+        .expect("failed to get OUTPUT format");
+    for round in 0..2 {
+        if round == 0 {
+            // Idle periods must not be reported as stalls.
+            std::thread::sleep(TIMEOUT * 3);
+        } else {
+            // Restarting a working encoder is harmless, and encoding goes on afterwards.
+            encoder.recover().expect("failed to recover encoder");
+        }
+
+        for i in 0..NUM_FRAMES {
+            let buffer = encoder.get_buffer().expect("failed to get OUTPUT buffer");
+            let mut mapping = buffer
+                .get_plane_mapping(0)
+                .expect("failed to map OUTPUT buffer");
+            let bytes_used = fill_source_frame(&format, round * NUM_FRAMES + i, &mut mapping);
+            buffer
+                .queue(&[bytes_used])
+                .expect("failed to queue OUTPUT buffer");
+        }
+        // Neither must a working driver.
+        std::thread::sleep(TIMEOUT * 3);
+        assert_eq!(stalls.load(Ordering::SeqCst), 0);
+    }
+    encoder.stop().expect("failed to stop encoder");
+    assert_eq!(stalls.load(Ordering::SeqCst), 0);
+
+    let encoded = encoded.lock().unwrap();
+    assert_eq!(encoded.len(), NUM_FRAMES * 2);
+    assert!(encoded.iter().all(|frame| frame.starts_with(FWHT_MAGIC)));
+}
+
+#[test]
+fn encoder_capture_buffer_errors() {
+    let _lock = common::lock();
+    let encoder = require_node!(Role::Encoder);
+
+    let encoder = match Encoder::open(&encoder) {
+        Ok(encoder) => encoder,
+        Err(e) => {
+            eprintln!("skipping: cannot open encoder: {}", e);
+            return;
+        }
+    };
+    let encoder = encoder
+        .set_capture_format(|f| {
+            let _: Format = f.set_pixelformat(b"FWHT").apply()?;
+            Ok(())
+        })
+        .expect("failed to set CAPTURE format")
+        .set_output_format(|f| {
+            let _: Format = f
+                .set_size(WIDTH as usize, HEIGHT as usize)
+                .set_pixelformat(b"RGB3")
+                .apply()?;
+            Ok(())
+        })
+        .expect("failed to set OUTPUT format");
+    // Only used to know the number of planes.
+    let capture_format = encoder
+        .get_capture_format()
+        .expect("failed to get CAPTURE format");
+    let mut encoder = encoder
+        .allocate_output_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate OUTPUT buffers");
+    // Ask for the smallest buffers possible, the driver may grant larger ones.
+    match encoder.set_capture_buffer_size(CaptureBufferSize::Explicit(CaptureBufferSize::MIN_SIZE))
+    {
+        Ok(_) | Err(SetCaptureBufferSizeError::SizeRejected { .. }) => (),
+        Err(e) => panic!("failed to set CAPTURE buffer size: {}", e),
+    }
+
+    let overflows = Arc::new(AtomicUsize::new(0));
+    let buffer_errors = Arc::new(AtomicUsize::new(0));
+    let encoder = encoder
+        .allocate_capture_buffers(2, MmapProvider::new(&capture_format))
+        .expect("failed to allocate CAPTURE buffers")
+        .set_error_cb({
+            let overflows = Arc::clone(&overflows);
+            let buffer_errors = Arc::clone(&buffer_errors);
+            move |error| match error {
+                EncoderError::CaptureOverflow { .. } => {
+                    overflows.fetch_add(1, Ordering::SeqCst);
+                }
+                EncoderError::BufferError { .. } => {
+                    buffer_errors.fetch_add(1, Ordering::SeqCst);
+                }
+                _ => (),
+            }
+        });
+
+    // Count the buffers the driver filled completely, which are the only ones that overflowed.
+    let full_buffers = Arc::new(AtomicUsize::new(0));
+    let encoded = Arc::new(AtomicUsize::new(0));
+    let output_ready_cb = {
+        let full_buffers = Arc::clone(&full_buffers);
+        let encoded = Arc::clone(&encoded);
+        move |dqbuf: DqBuffer<Capture, Vec<MmapHandle>>| {
+            let plane = dqbuf.data.plane(0).unwrap_or_default();
+            if plane.bytesused == 0 {
+                return;
+            }
+            if plane.bytesused >= plane.length {
+                full_buffers.fetch_add(1, Ordering::SeqCst);
+            }
+            encoded.fetch_add(1, Ordering::SeqCst);
+        }
+    };
+    let mut encoder = encoder
+        .start(
+            |_: CompletedOutputBuffer<Vec<MmapHandle>>| (),
+            output_ready_cb,
+        )
+        .expect("failed to start encoder");
+
+    let format = encoder
+        .get_output_format()
+        .expect("failed to get OUTPUT format");
+    for i in 0..NUM_FRAMES {
+        let buffer = encoder.get_buffer().expect("failed to get OUTPUT buffer");
+        let mut mapping = buffer
+            .get_plane_mapping(0)
+            .expect("failed to map OUTPUT buffer");
+        let bytes_used = fill_source_frame(&format, i, &mut mapping);
+        buffer
+            .queue(&[bytes_used])
+            .expect("failed to queue OUTPUT buffer");
+    }
+    wait_until("the encoded frames", || {
+        encoded.load(Ordering::SeqCst) >= NUM_FRAMES
+    });
+    encoder.stop().expect("failed to stop encoder");
+
+    // Frames that fit must not be reported as overflows, nor as errors.
+    assert_eq!(
+        overflows.load(Ordering::SeqCst),
+        full_buffers.load(Ordering::SeqCst)
+    );
+    assert_eq!(buffer_errors.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn dmabuf_sharing() {
+    let _lock = common::lock();
+    let capture = require_node!(Role::Capture);
+    let encoder = require_node!(Role::Encoder);
+
+    // Capture RGB frames from vivid.
+    let capture_device = common::open(&capture);
+    let mut capture_queue = common::capture_queue(&capture_device);
+    let capture_format: Format = capture_queue
+        .change_format()
+        .expect("failed to get capture format")
+        .set_size(WIDTH as usize, HEIGHT as usize)
+        .set_pixelformat(b"RGB3")
+        .apply()
+        .expect("failed to set capture format");
+    if capture_format.pixelformat != PixelFormat::from(b"RGB3")
+        || (capture_format.width, capture_format.height) != (WIDTH, HEIGHT)
+    {
+        eprintln!(
+            "skipping: vivid does not support the format of the encoder: {:?}",
+            capture_format
+        );
+        return;
+    }
+
+    // And encode them using vicodec, without copying them.
+    let encoder_device = common::open(&encoder);
+    let mut output_queue = common::output_queue(&encoder_device);
+    let mut encoded_queue = common::capture_queue(&encoder_device);
+    let _: Format = encoded_queue
+        .change_format()
+        .expect("failed to get encoder CAPTURE format")
+        .set_pixelformat(b"FWHT")
+        .apply()
+        .expect("failed to set encoder CAPTURE format");
+    let output_format: Format = output_queue
+        .change_format()
+        .expect("failed to get encoder OUTPUT format")
+        .set_size(WIDTH as usize, HEIGHT as usize)
+        .set_pixelformat(b"RGB3")
+        .apply()
+        .expect("failed to set encoder OUTPUT format");
+    assert_eq!(
+        output_format.plane_fmt[0].bytesperline,
+        capture_format.plane_fmt[0].bytesperline
+    );
+
+    let capture_queue = capture_queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate capture buffers");
+    let dmabufs: Vec<File> = (0..capture_queue.num_buffers())
+        .map(|index| {
+            ioctl::expbuf(
+                &*capture_device,
+                capture_queue.get_type(),
+                index,
+                0,
+                ExpbufFlags::CLOEXEC | ExpbufFlags::RDWR,
+            )
+            .expect("failed to export capture buffer")
+        })
+        .collect();
+
+    let output_queue = output_queue
+        .request_buffers::<Vec<DmaBufHandle<File>>>(2)
+        .expect("failed to allocate encoder OUTPUT buffers");
+    let encoded_queue = encoded_queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate encoder CAPTURE buffers");
+
+    capture_queue.stream_on().expect("failed to stream on");
+    output_queue
+        .stream_on()
+        .expect("failed to stream encoder OUTPUT on");
+    encoded_queue
+        .stream_on()
+        .expect("failed to stream encoder CAPTURE on");
+
+    for _ in 0..NUM_FRAMES {
+        while let Ok(buffer) = capture_queue.try_get_free_buffer() {
+            buffer.queue().expect("failed to queue capture buffer");
+        }
+        // Keep the captured frame dequeued while the encoder reads it.
+        let frame = capture_queue
+            .try_dequeue()
+            .expect("failed to dequeue capture buffer");
+        let bytes_used = frame.data.plane_bytesused(0).unwrap_or(0) as usize;
+        let dmabuf = dmabufs[frame.data.index() as usize]
+            .try_clone()
+            .expect("failed to duplicate DMABUF");
+
+        encoded_queue
+            .try_get_free_buffer()
+            .expect("no free encoder CAPTURE buffer")
+            .queue()
+            .expect("failed to queue encoder CAPTURE buffer");
+        output_queue
+            .try_get_free_buffer()
+            .expect("no free encoder OUTPUT buffer")
+            .queue_with_handles(vec![DmaBufHandle::from(dmabuf)], &[bytes_used])
+            .expect("failed to queue encoder OUTPUT buffer");
+
+        drop(
+            output_queue
+                .try_dequeue()
+                .expect("failed to dequeue encoder OUTPUT buffer"),
+        );
+        let encoded = encoded_queue
+            .try_dequeue()
+            .expect("failed to dequeue encoder CAPTURE buffer");
+        let encoded_size = encoded.data.plane_bytesused(0).unwrap_or(0) as usize;
+        let mapping = encoded
+            .get_plane_mapping(0)
+            .expect("failed to map encoder CAPTURE buffer");
+        assert!(encoded_size > FWHT_MAGIC.len());
+        assert!(mapping.as_ref().starts_with(FWHT_MAGIC));
+    }
+
+    encoded_queue.stream_off().expect("failed to stream off");
+    output_queue.stream_off().expect("failed to stream off");
+    capture_queue.stream_off().expect("failed to stream off");
+}
@@ -0,0 +1,179 @@
+//! End-to-end tests of the camera pipeline helper against the `vimc` virtual media device.
+//!
+//! These tests are skipped if `vimc` is not loaded. See the `common` module for details.
+#[macro_use]
+mod common;
+
+use std::sync::mpsc;
+
+use v4l2r::bindings;
+use v4l2r::camera::{
+    Camera, CameraConfig, CameraError, CameraFrame, FrameMatcher, PipelineBuilder, PipelineError,
+};
+use v4l2r::device::media::MediaDevice;
+use v4l2r::device::queue::GetFreeCaptureBuffer;
+use v4l2r::device::{Stream, TryDequeue};
+use v4l2r::ioctl::{EntityFunction, LinkFlags, SubDevWhich};
+use v4l2r::memory::MmapHandle;
+use v4l2r::PixelFormat;
+
+use common::Role;
+
+/// Number of frames to capture in streaming tests.
+const NUM_FRAMES: usize = 4;
+
+#[test]
+fn camera_capture() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Camera);
+    let media = MediaDevice::open(&path).expect("failed to open media device");
+
+    let camera = Camera::open(&media, CameraConfig::new(b"RGB3", 640, 480))
+        .expect("failed to set up camera");
+    assert_eq!(
+        camera.sensor.entity_function(),
+        Some(EntityFunction::CamSensor)
+    );
+    assert_eq!(camera.format.pixelformat, PixelFormat::from(b"RGB3"));
+    assert_eq!((camera.format.width, camera.format.height), (640, 480));
+    // vimc has no metadata node.
+    assert!(camera.metadata_queue.is_none());
+    assert!(camera.metadata_format.is_none());
+
+    // The links of the pipeline have been enabled.
+    let topology = media.get_topology().expect("failed to get topology");
+    for link in &camera.pipeline {
+        let link = topology.links.iter().find(|l| l.id == link.id).unwrap();
+        assert!(link.flags.contains(LinkFlags::ENABLED));
+    }
+
+    let queue = camera
+        .capture_queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate buffers");
+    queue.stream_on().expect("failed to stream on");
+
+    // Without metadata, frames are delivered as they come.
+    let (sender, receiver) = mpsc::channel();
+    let mut matcher = FrameMatcher::new(0, move |frame: CameraFrame<_, ()>| {
+        sender.send(frame).unwrap()
+    });
+    let mut sequences = Vec::new();
+    for _ in 0..NUM_FRAMES {
+        while let Ok(buffer) = queue.try_get_free_buffer() {
+            buffer.queue().expect("failed to queue buffer");
+        }
+
+        let dqbuf = queue.try_dequeue().expect("failed to dequeue buffer");
+        assert!(!dqbuf.data.has_error());
+        assert!(dqbuf.data.plane_bytesused(0).unwrap_or(0) > 0);
+        matcher.push_frame(dqbuf.data.sequence(), dqbuf);
+
+        // Dropping the delivered frames returns their buffers to the queue.
+        for frame in receiver.try_iter() {
+            assert!(frame.metadata.is_none());
+            sequences.push(frame.sequence);
+        }
+    }
+
+    assert_eq!(sequences.len(), NUM_FRAMES);
+    assert!(sequences.windows(2).all(|w| w[0] < w[1]));
+
+    queue.stream_off().expect("failed to stream off");
+}
+
+#[test]
+fn camera_unsupported_format() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Camera);
+    let media = MediaDevice::open(&path).expect("failed to open media device");
+
+    match Camera::open(&media, CameraConfig::new(b"FWHT", 640, 480)) {
+        Err(CameraError::NoVideoNode(format)) => assert_eq!(format, PixelFormat::from(b"FWHT")),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("vimc cannot capture FWHT frames"),
+    }
+}
+
+#[test]
+fn pipeline_builder() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Camera);
+    let open = || MediaDevice::open(&path).expect("failed to open media device");
+
+    let media = open();
+    let topology = media.get_topology().expect("failed to get topology");
+    let id = |name| {
+        topology
+            .entity_by_name(name)
+            .unwrap_or_else(|| panic!("vimc has no {:?} entity", name))
+            .id
+    };
+    let (sensor, debayer, scaler) = (id("Sensor A"), id("Debayer A"), id("Scaler"));
+    let raw_capture = media
+        .find_video_device("Raw Capture 0")
+        .expect("failed to find raw capture node");
+    let code = bindings::MEDIA_BUS_FMT_SBGGR8_1X8.into();
+
+    let pipeline = PipelineBuilder::new(media)
+        .enable_link(debayer, 1, scaler, 0)
+        .set_subdev_format(sensor, 0, 640, 480, code)
+        .set_video_format(&raw_capture, PixelFormat::from(b"BA81"), 640, 480)
+        .build()
+        .expect("failed to build pipeline");
+
+    let topology = pipeline
+        .media
+        .get_topology()
+        .expect("failed to get topology");
+    let debayer_pad = topology.entity_pad(debayer, 1).unwrap();
+    let scaler_pad = topology.entity_pad(scaler, 0).unwrap();
+    let link = topology.data_link(debayer_pad, scaler_pad).unwrap();
+    assert!(link.flags.contains(LinkFlags::ENABLED));
+
+    let format = pipeline
+        .subdev(sensor)
+        .expect("sensor sub-device not opened")
+        .get_format(0, SubDevWhich::Active)
+        .expect("failed to get sensor format");
+    assert_eq!((format.code, format.width, format.height), (code, 640, 480));
+
+    let video = pipeline
+        .video_device(&raw_capture)
+        .expect("raw capture node not configured");
+    assert_eq!(video.format.pixelformat, PixelFormat::from(b"BA81"));
+    assert_eq!((video.format.width, video.format.height), (640, 480));
+    drop(pipeline);
+
+    // The sensor only produces frames of even width.
+    match PipelineBuilder::new(open())
+        .set_subdev_format(sensor, 0, 641, 480, code)
+        .build()
+    {
+        Err(PipelineError::FormatMismatch {
+            pad: 0,
+            requested,
+            got,
+            ..
+        }) => {
+            assert_eq!(requested.width, 641);
+            assert_eq!(got.width, 640);
+        }
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("vimc sensors cannot produce frames of odd width"),
+    }
+
+    // The sensor is not linked to the scaler.
+    match PipelineBuilder::new(open())
+        .enable_link(sensor, 0, scaler, 0)
+        .build()
+    {
+        Err(PipelineError::NoSuchLink {
+            from_pad: 0,
+            to_pad: 0,
+            ..
+        }) => (),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("vimc has no link from its sensor to its scaler"),
+    }
+}
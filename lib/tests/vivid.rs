@@ -0,0 +1,482 @@
+//! End-to-end tests against the `vivid` virtual capture driver.
+//!
+//! These tests are skipped if `vivid` is not loaded. See the `common` module for details.
+#[macro_use]
+mod common;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use v4l2r::bindings;
+use v4l2r::bindings::v4l2_ext_control;
+use v4l2r::buffer::{FrameSyncCorrelator, FrameSyncMatch};
+use v4l2r::controls::image_process::TestPattern;
+use v4l2r::controls::user::{Brightness, Contrast};
+use v4l2r::controls::{AsV4l2ControlSlice, SafeExtControl};
+use v4l2r::device::queue::*;
+use v4l2r::device::{AllocatedQueue, Stream, TryDequeue};
+use v4l2r::format::{weave_fields, woven_format, FieldPairer};
+use v4l2r::ioctl::{
+    self, CtrlChanges, CtrlWhich, DqEventError, Event, EventType, FormatIterator,
+    SubscribeEventError, SubscribeEventFlags,
+};
+use v4l2r::memory::{MmapHandle, UserPtrHandle};
+use v4l2r::{Field, Format, PixelFormat, PlaneLayout, QueueType};
+
+use common::Role;
+
+/// Number of frames to capture in streaming tests.
+const NUM_FRAMES: usize = 4;
+
+#[test]
+fn mmap_capture() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Capture);
+    let device = common::open(&path);
+
+    // Use a fixed pattern, so the result does not depend on what previous tests did.
+    let mut pattern = SafeExtControl::<TestPattern>::from_value(0);
+    ioctl::s_ext_ctrls(&*device, CtrlWhich::Current, &mut pattern)
+        .expect("failed to set test pattern");
+
+    let queue = common::capture_queue(&device);
+    let queue = queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate buffers");
+    queue.stream_on().expect("failed to stream on");
+
+    let mut last_sequence = None;
+    for _ in 0..NUM_FRAMES {
+        while let Ok(buffer) = queue.try_get_free_buffer() {
+            buffer.queue().expect("failed to queue buffer");
+        }
+
+        let dqbuf = queue.try_dequeue().expect("failed to dequeue buffer");
+        assert!(!dqbuf.data.has_error());
+        assert!(dqbuf.data.plane_bytesused(0).unwrap_or(0) > 0);
+        let mapping = dqbuf.get_plane_mapping(0).expect("failed to map buffer");
+        // The color bars cannot be all black.
+        assert!(mapping.as_ref().iter().any(|&b| b != 0));
+
+        let sequence = dqbuf.data.sequence();
+        if let Some(last_sequence) = last_sequence {
+            assert!(sequence > last_sequence);
+        }
+        last_sequence = Some(sequence);
+    }
+
+    queue.stream_off().expect("failed to stream off");
+    assert_eq!(queue.num_free_buffers(), queue.num_buffers());
+}
+
+#[test]
+fn prepared_capture() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Capture);
+    let device = common::open(&path);
+
+    let queue = common::capture_queue(&device);
+    let queue = queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate buffers");
+    queue.prepare_all().expect("failed to prepare buffers");
+    for index in 0..queue.num_buffers() {
+        let buffer: ioctl::QueryBuffer =
+            ioctl::querybuf(&*device, queue.get_type(), index).expect("failed to query buffer");
+        assert!(buffer.flags.contains(ioctl::BufferFlags::PREPARED));
+    }
+
+    // Prepared buffers are queued as usual.
+    queue.stream_on().expect("failed to stream on");
+    while let Ok(buffer) = queue.try_get_free_buffer() {
+        buffer.queue().expect("failed to queue buffer");
+    }
+    let dqbuf = queue.try_dequeue().expect("failed to dequeue buffer");
+    assert!(!dqbuf.data.has_error());
+    drop(dqbuf);
+
+    queue.stream_off().expect("failed to stream off");
+}
+
+#[test]
+fn frame_sync_correlation() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Capture);
+    let device = common::open(&path);
+
+    // vivid does not raise frame-sync events, but its sequence numbers still allow to check the
+    // detection of dropped frames.
+    let has_frame_sync = match device.subscribe_frame_sync() {
+        Ok(()) => true,
+        Err(SubscribeEventError::IoctlError(nix::errno::Errno::EINVAL)) => false,
+        Err(e) => panic!("failed to subscribe to frame-sync events: {}", e),
+    };
+
+    let queue = common::capture_queue(&device);
+    let queue = queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate buffers");
+    queue.stream_on().expect("failed to stream on");
+
+    let mut correlator = FrameSyncCorrelator::new(2);
+    let mut matches = Vec::new();
+    let mut sequences = Vec::new();
+    for i in 0..NUM_FRAMES * 2 {
+        while let Ok(buffer) = queue.try_get_free_buffer() {
+            buffer.queue().expect("failed to queue buffer");
+        }
+        // Starve the driver of buffers for a while, so it drops some frames.
+        if i == NUM_FRAMES {
+            std::thread::sleep(Duration::from_millis(200));
+        }
+
+        let dqbuf = queue.try_dequeue().expect("failed to dequeue buffer");
+        let sequence = dqbuf.data.sequence();
+        sequences.push(sequence);
+        correlator.push_buffer(sequence, sequence);
+        loop {
+            match ioctl::dqevent::<Event>(&*device) {
+                Ok(Event::FrameSync(event)) => correlator.push_event(event),
+                Ok(e) => panic!("unexpected event {:?}", e),
+                Err(DqEventError::NotReady) => break,
+                Err(e) => panic!("failed to dequeue event: {}", e),
+            }
+        }
+        matches.extend(std::iter::from_fn(|| correlator.pop()));
+    }
+    queue.stream_off().expect("failed to stream off");
+    matches.extend(correlator.flush());
+
+    // Every frame between the first and last buffers is accounted for, in order. Frame-sync
+    // events can also report frames started before the first buffer or after the last one.
+    let (first_sequence, last_sequence) = (sequences[0], sequences[sequences.len() - 1]);
+    let mut expected = first_sequence;
+    for m in matches.iter().filter(|m| {
+        !matches!(m, FrameSyncMatch::Dropped { first, .. }
+            if *first < first_sequence || *first > last_sequence)
+    }) {
+        match m {
+            FrameSyncMatch::Matched { buffer, .. } | FrameSyncMatch::NoEvent { buffer, .. } => {
+                assert_eq!(*buffer, expected);
+                expected += 1;
+            }
+            FrameSyncMatch::Dropped { first, count } => {
+                assert_eq!(*first, expected);
+                assert!(!sequences.contains(first));
+                expected += count;
+            }
+        }
+    }
+    assert_eq!(expected, last_sequence + 1);
+    if !has_frame_sync {
+        assert!(!matches
+            .iter()
+            .any(|m| matches!(m, FrameSyncMatch::Matched { .. })));
+    }
+}
+
+#[test]
+fn dropped_frames() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Capture);
+    let device = common::open(&path);
+
+    // vivid can drop a percentage of its frames on purpose.
+    let perc_dropped = match ioctl::ControlIterator::new(&*device).find(|qctrl| {
+        ioctl::string_from_cstr(&qctrl.name.map(|c| c as u8)) == "Percentage of Dropped Buffers"
+    }) {
+        Some(qctrl) => qctrl.id,
+        None => {
+            eprintln!("skipping: vivid has no control to drop buffers");
+            return;
+        }
+    };
+    ioctl::s_ctrl(&*device, perc_dropped, 50).expect("failed to set dropped buffers control");
+
+    let queue = common::capture_queue(&device);
+    let queue = queue
+        .request_buffers::<Vec<MmapHandle>>(2)
+        .expect("failed to allocate buffers");
+    let reported = Arc::new(AtomicU64::new(0));
+    queue.set_frame_drop_cb({
+        let reported = Arc::clone(&reported);
+        move |count| {
+            reported.fetch_add(count as u64, Ordering::Relaxed);
+        }
+    });
+    queue.stream_on().expect("failed to stream on");
+
+    let mut sequences = Vec::new();
+    for _ in 0..NUM_FRAMES * 4 {
+        while let Ok(buffer) = queue.try_get_free_buffer() {
+            buffer.queue().expect("failed to queue buffer");
+        }
+        let dqbuf = queue.try_dequeue().expect("failed to dequeue buffer");
+        sequences.push(dqbuf.data.sequence());
+    }
+    queue.stream_off().expect("failed to stream off");
+    ioctl::s_ctrl(&*device, perc_dropped, 0).expect("failed to restore dropped buffers control");
+
+    // Every frame missing from the sequence numbers has been counted.
+    let span = (sequences[sequences.len() - 1] - sequences[0] + 1) as u64;
+    assert_eq!(queue.dropped_frames(), span - sequences.len() as u64);
+    assert!(queue.dropped_frames() > 0);
+    assert_eq!(reported.load(Ordering::Relaxed), queue.dropped_frames());
+
+    // The counter starts over with the stream.
+    queue.stream_on().expect("failed to stream on");
+    assert_eq!(queue.dropped_frames(), 0);
+    queue.stream_off().expect("failed to stream off");
+}
+
+#[test]
+fn userptr_capture() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Capture);
+    let device = common::open(&path);
+
+    let queue = common::capture_queue(&device);
+    let format: Format = queue.get_format().expect("failed to get format");
+    let size = format.plane_fmt[0].sizeimage as usize;
+    let queue = queue
+        .request_buffers::<Vec<UserPtrHandle<Vec<u8>>>>(2)
+        .expect("failed to allocate buffers");
+    queue.stream_on().expect("failed to stream on");
+
+    for _ in 0..NUM_FRAMES {
+        while let Ok(buffer) = queue.try_get_free_buffer() {
+            buffer
+                .queue_with_handles(vec![UserPtrHandle::from(vec![0u8; size])])
+                .expect("failed to queue buffer");
+        }
+
+        let mut dqbuf = queue.try_dequeue().expect("failed to dequeue buffer");
+        assert!(!dqbuf.data.has_error());
+        let handles = dqbuf.take_handles().expect("no handles in dequeued buffer");
+        // The driver has written into our memory.
+        assert!(handles[0].0.iter().any(|&b| b != 0));
+    }
+
+    queue.stream_off().expect("failed to stream off");
+}
+
+/// Controls of the picture, which are set and read in a single batch.
+#[derive(Default)]
+#[repr(C)]
+struct PictureControls {
+    brightness: SafeExtControl<Brightness>,
+    contrast: SafeExtControl<Contrast>,
+}
+
+impl AsV4l2ControlSlice for &mut PictureControls {
+    fn as_v4l2_control_slice(&mut self) -> &mut [v4l2_ext_control] {
+        let ptr = (*self) as *mut PictureControls as *mut v4l2_ext_control;
+        unsafe { std::slice::from_raw_parts_mut(ptr, 2) }
+    }
+}
+
+#[test]
+fn control_batch() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Capture);
+    let device = common::open(&path);
+
+    let mut defaults = PictureControls::default();
+    ioctl::g_ext_ctrls(&*device, CtrlWhich::Default, &mut defaults)
+        .expect("failed to get default values");
+
+    let mut controls = PictureControls {
+        brightness: SafeExtControl::from_value(100),
+        contrast: SafeExtControl::from_value(150),
+    };
+    ioctl::try_ext_ctrls(&*device, CtrlWhich::Current, &mut controls)
+        .expect("failed to try controls");
+    ioctl::s_ext_ctrls(&*device, CtrlWhich::Current, &mut controls)
+        .expect("failed to set controls");
+
+    let mut current = PictureControls::default();
+    ioctl::g_ext_ctrls(&*device, CtrlWhich::Current, &mut current).expect("failed to get controls");
+    assert_eq!(current.brightness.value(), 100);
+    assert_eq!(current.contrast.value(), 150);
+
+    ioctl::s_ext_ctrls(&*device, CtrlWhich::Current, &mut defaults)
+        .expect("failed to restore default values");
+}
+
+#[test]
+fn ctrl_events() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Capture);
+    let device = common::open(&path);
+
+    // Also receive the events caused by our own changes.
+    ioctl::subscribe_event(
+        &*device,
+        EventType::Ctrl(bindings::V4L2_CID_BRIGHTNESS),
+        SubscribeEventFlags::SEND_INITIAL | SubscribeEventFlags::ALLOW_FEEDBACK,
+    )
+    .expect("failed to subscribe to control events");
+
+    let initial = match ioctl::dqevent::<Event>(&*device).expect("no initial event") {
+        Event::CtrlEvent(ctrl) => ctrl,
+        e => panic!("unexpected event {:?}", e),
+    };
+    assert_eq!(initial.id, bindings::V4L2_CID_BRIGHTNESS);
+
+    let value = if initial.value == 42 { 43 } else { 42 };
+    let mut brightness = SafeExtControl::<Brightness>::from_value(value);
+    ioctl::s_ext_ctrls(&*device, CtrlWhich::Current, &mut brightness)
+        .expect("failed to set brightness");
+
+    match ioctl::dqevent::<Event>(&*device).expect("no event after change") {
+        Event::CtrlEvent(ctrl) => {
+            assert_eq!(ctrl.id, bindings::V4L2_CID_BRIGHTNESS);
+            assert!(ctrl.changes.contains(CtrlChanges::VALUE));
+            assert_eq!(ctrl.value, value as i64);
+        }
+        e => panic!("unexpected event {:?}", e),
+    }
+
+    ioctl::unsubscribe_all_events(&*device).expect("failed to unsubscribe");
+    brightness.set_value(initial.value as i32);
+    ioctl::s_ext_ctrls(&*device, CtrlWhich::Current, &mut brightness)
+        .expect("failed to restore brightness");
+}
+
+#[test]
+fn metadata_formats() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Metadata);
+    let device = common::open(&path);
+
+    let formats: Vec<_> = FormatIterator::new(&*device, QueueType::MetaCapture).collect();
+    assert!(!formats.is_empty());
+}
+
+/// Checks the layouts computed by `PlaneLayout::for_format` against the ones reported by vivid.
+#[test]
+fn plane_layouts() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Capture);
+    let device = common::open(&path);
+    let queue = common::capture_queue(&device);
+
+    let mut num_checked = 0;
+    for fmtdesc in queue.format_iter() {
+        for (width, height) in [(640, 480), (640, 360), (1280, 720)] {
+            let expected = match PlaneLayout::for_format(fmtdesc.pixelformat, width, height, 0) {
+                Some(expected) => expected,
+                None => continue,
+            };
+            let format = queue
+                .try_format(Format {
+                    width,
+                    height,
+                    pixelformat: fmtdesc.pixelformat,
+                    ..Default::default()
+                })
+                .expect("failed to try format");
+            // Only compare the formats the driver accepted as is.
+            if format.pixelformat != fmtdesc.pixelformat
+                || (format.width, format.height) != (width, height)
+            {
+                continue;
+            }
+
+            assert_eq!(
+                format.plane_fmt, expected,
+                "layout of {} at {}x{}",
+                fmtdesc.pixelformat, width, height
+            );
+            num_checked += 1;
+        }
+    }
+    assert!(num_checked > 0);
+}
+
+/// Captures an interlaced input one field per buffer, and weaves the fields back into frames.
+#[test]
+fn field_alternate_capture() {
+    let _lock = common::lock();
+    let path = require_node!(Role::Capture);
+    let device = common::open(&path);
+
+    // The webcam input of vivid is progressive, but its S-Video input is interlaced.
+    if device.switch_input("S-Video 2").is_err() {
+        eprintln!("skipping: vivid has no S-Video input");
+        return;
+    }
+
+    let mut queue = common::capture_queue(&device);
+    let format: Format = queue
+        .change_format()
+        .expect("failed to get format")
+        .set_pixelformat(PixelFormat::YUYV)
+        .set_field(Field::Alternate)
+        .apply()
+        .expect("failed to set format");
+    assert_eq!(format.field, Field::Alternate);
+    let frame_format = woven_format(&format);
+    let stride = format.plane_fmt[0].bytesperline as usize;
+    let line_bytes = format.width as usize * 2;
+
+    let queue = queue
+        .request_buffers::<Vec<MmapHandle>>(4)
+        .expect("failed to allocate buffers");
+    queue.stream_on().expect("failed to stream on");
+
+    let mut pairer = FieldPairer::new();
+    let mut frames = 0;
+    while frames < NUM_FRAMES {
+        while let Ok(buffer) = queue.try_get_free_buffer() {
+            buffer.queue().expect("failed to queue buffer");
+        }
+
+        let dqbuf = queue.try_dequeue().expect("failed to dequeue buffer");
+        assert!(
+            matches!(dqbuf.data.field(), Field::Top | Field::Bottom),
+            "unexpected field {:?}",
+            dqbuf.data.field()
+        );
+        let pair = match pairer
+            .push_dqbuf(dqbuf)
+            .expect("buffer does not contain a single field")
+        {
+            Some(pair) => pair,
+            None => continue,
+        };
+
+        let top = pair.top.get_plane_mapping(0).expect("failed to map buffer");
+        let bottom = pair
+            .bottom
+            .get_plane_mapping(0)
+            .expect("failed to map buffer");
+        let mut frame = vec![0u8; frame_format.plane_fmt[0].sizeimage as usize];
+        weave_fields(
+            &format,
+            &[top.as_ref()],
+            &[bottom.as_ref()],
+            &mut [frame.as_mut_slice()],
+        )
+        .expect("failed to weave fields");
+
+        // The lines of the frame alternate between the two fields.
+        assert_eq!(frame[..line_bytes], top.as_ref()[..line_bytes]);
+        assert_eq!(
+            frame[stride..stride + line_bytes],
+            bottom.as_ref()[..line_bytes]
+        );
+        assert_eq!(
+            frame[2 * stride..2 * stride + line_bytes],
+            top.as_ref()[stride..stride + line_bytes]
+        );
+        frames += 1;
+    }
+
+    drop(pairer);
+    queue.stream_off().expect("failed to stream off");
+    device
+        .switch_input("Webcam 0")
+        .expect("failed to restore input");
+}
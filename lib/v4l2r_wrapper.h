@@ -1,4 +1,6 @@
 #include <linux/videodev2.h>
+#include <linux/v4l2-subdev.h>
+#include <linux/media.h>
 
 #define MARK_FIX_753(name) const unsigned long int Fix753_##name = name;
 #include "fix753.h"
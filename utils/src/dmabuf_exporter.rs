@@ -57,6 +57,7 @@ mod tests {
                     bytesperline: BYTES_PER_LINE,
                 },
             ],
+            ..Default::default()
         };
 
         let dmabufs = export_dmabufs(&format, 4).unwrap();
@@ -67,4 +68,28 @@ mod tests {
             assert!(buf[1].0.len() >= SIZE_IMAGE_UV as u64);
         }
     }
+
+    #[test]
+    fn test_export_dmabufs_computed_layout() {
+        const WIDTH: u32 = 640;
+        const HEIGHT: u32 = 480;
+
+        let plane_fmt = PlaneLayout::for_format(PixelFormat::NV12M, WIDTH, HEIGHT, 64).unwrap();
+        let format = Format {
+            width: WIDTH,
+            height: HEIGHT,
+            pixelformat: PixelFormat::NV12M,
+            plane_fmt: plane_fmt.clone(),
+            ..Default::default()
+        };
+
+        let dmabufs = export_dmabufs(&format, 2).unwrap();
+        assert_eq!(dmabufs.len(), 2);
+        for buf in dmabufs {
+            assert_eq!(buf.len(), plane_fmt.len());
+            for (dmabuf, plane) in buf.iter().zip(plane_fmt.iter()) {
+                assert!(dmabuf.0.len() >= plane.sizeimage as u64);
+            }
+        }
+    }
 }
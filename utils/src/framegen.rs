@@ -1,8 +1,12 @@
 use thiserror::Error;
+use v4l2r::{Format, PixelFormat, PlaneLayout};
+
 #[derive(Debug, Error)]
 pub enum NewFrameGeneratorError {
     #[error("invalid stride")]
     InvalidStride,
+    #[error("unsupported format {0}, only RGB3 can be generated")]
+    UnsupportedFormat(PixelFormat),
 }
 
 #[derive(Debug, Error)]
@@ -11,6 +15,7 @@ pub enum GenerateFrameError {
     BufferTooSmall,
 }
 
+/// Generates a moving RGB3 pattern.
 pub struct FrameGenerator {
     width: usize,
     height: usize,
@@ -20,7 +25,11 @@ pub struct FrameGenerator {
 
 impl FrameGenerator {
     pub fn new(width: usize, height: usize, stride: usize) -> Result<Self, NewFrameGeneratorError> {
-        if stride < width * 3 {
+        let min_stride =
+            PlaneLayout::for_format(PixelFormat::RGB24, width as u32, height as u32, 0)
+                .map(|planes| planes[0].bytesperline as usize)
+                .unwrap_or_default();
+        if stride < min_stride {
             return Err(NewFrameGeneratorError::InvalidStride);
         }
 
@@ -32,6 +41,26 @@ impl FrameGenerator {
         })
     }
 
+    /// Create a generator for frames of `format`, using the stride of its first plane.
+    pub fn for_format(format: &Format) -> Result<Self, NewFrameGeneratorError> {
+        if format.pixelformat != PixelFormat::RGB24 {
+            return Err(NewFrameGeneratorError::UnsupportedFormat(
+                format.pixelformat,
+            ));
+        }
+        let stride = format
+            .plane_fmt
+            .first()
+            .ok_or(NewFrameGeneratorError::InvalidStride)?
+            .bytesperline;
+
+        Self::new(
+            format.width as usize,
+            format.height as usize,
+            stride as usize,
+        )
+    }
+
     pub fn frame_size(&self) -> usize {
         self.stride * self.height
     }